@@ -0,0 +1,37 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Textured billboards and impostors. Compiled with OIT when the scene pass
+// has the order-independent transparency targets, which billboards are
+// accumulated into like transparent entities.
+
+#include "oit.glsl"
+
+layout(constant_id = 0) const uint TEXTURE_CAPACITY = 16;
+layout(set = 1, binding = 1) uniform sampler2D textures[TEXTURE_CAPACITY];
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragColor;
+layout(location = 2) flat in uint fragTexture;
+layout(location = 3) in float fragViewDepth;
+
+layout(location = 0) out vec4 outColor;
+#ifdef OIT
+layout(location = 1) out vec4 outAccum;
+layout(location = 2) out float outReveal;
+#endif
+
+void main() {
+	outColor = texture(textures[fragTexture], fragTexCoord) * fragColor;
+	// Cut out rather than blended away, so empty texels cost no blending.
+	if (outColor.a < 1.0 / 255.0) {
+		discard;
+	}
+
+#ifdef OIT
+	float alpha = outColor.a;
+	float weight = oitWeight(alpha, fragViewDepth);
+	outAccum = vec4(outColor.rgb * alpha, alpha) * weight;
+	outReveal = alpha;
+#endif
+}
@@ -0,0 +1,40 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "scene.glsl"
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec2 inCorner;
+layout(location = 2) in vec2 inSize;
+layout(location = 3) in vec2 inTexCoord;
+layout(location = 4) in vec4 inColor;
+// The world axis cylindrical billboards turn about, or zero for spherical
+// ones.
+layout(location = 5) in vec3 inAxis;
+layout(location = 6) in uint inTexture;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+layout(location = 2) flat out uint fragTexture;
+layout(location = 3) out float fragViewDepth;
+
+void main() {
+	vec4 viewPosition;
+	if (inAxis == vec3(0.0)) {
+		// Offset in view space so the quad always faces the camera.
+		viewPosition = ubo.view * vec4(inPosition, 1.0);
+		viewPosition.xy += inCorner * inSize;
+	} else {
+		// Kept upright along the axis, and turned to the camera's right
+		// about it.
+		vec3 cameraRight = vec3(ubo.view[0][0], ubo.view[1][0], ubo.view[2][0]);
+		vec3 right = normalize(cameraRight - inAxis * dot(cameraRight, inAxis));
+		vec3 position = inPosition + right * inCorner.x * inSize.x + inAxis * inCorner.y * inSize.y;
+		viewPosition = ubo.view * vec4(position, 1.0);
+	}
+	gl_Position = ubo.proj[0] * viewPosition;
+	fragTexCoord = inTexCoord;
+	fragColor = inColor;
+	fragTexture = inTexture;
+	fragViewDepth = -viewPosition.z;
+}
@@ -1,2 +1,85 @@
+# Code shared between shaders lives in headers under include/, which
+# shaders `#include` by name.
+glslc() { command glslc -Iinclude "$@"; }
+
+# HLSL is compiled with DXC, for shaders ported from HLSL libraries. DXC
+# maps each register to the binding of the same number in the set of its
+# space, whatever its class, so `register(b0, space0)` is the scene uniform
+# buffer and a texture and sampler at the same register combine into one
+# binding with `[[vk::combinedImageSampler]]`, as the renderer's layouts
+# expect. No class is shifted, since every set numbers its bindings from 0.
+# Matrices keep DXC's column-major packing, which is how the renderer
+# writes them, so `mul(m, v)` is GLSL's `m * v`. Entry points are named
+# `main` in the SPIR-V, which pipelines are created with.
+dxc() {
+    command dxc -spirv -fspv-target-env=vulkan1.0 -fspv-entrypoint-name=main -E main -Iinclude "$@"
+}
+
 glslc shader.vert -o vert.spv
 glslc shader.frag -o frag.spv
+glslc -DINDIRECT shader.vert -o vert_indirect.spv
+glslc -DINDIRECT shader.frag -o frag_indirect.spv
+glslc -DMULTIVIEW shader.vert -o vert_multiview.spv
+glslc -DMULTIVIEW shader.frag -o frag_multiview.spv
+glslc -DOIT shader.frag -o frag_oit.spv
+glslc -DINDIRECT -DOIT shader.frag -o frag_indirect_oit.spv
+glslc --target-env=vulkan1.2 meshlet.task -o meshlet_task.spv
+glslc --target-env=vulkan1.2 meshlet.mesh -o meshlet_mesh.spv
+glslc --target-env=vulkan1.2 shadow.rgen -o shadow_rgen.spv
+glslc --target-env=vulkan1.2 shadow.rmiss -o shadow_rmiss.spv
+glslc --target-env=vulkan1.2 shadow.rchit -o shadow_rchit.spv
+glslc --target-env=vulkan1.2 ao.comp -o ao_comp.spv
+dxc -T vs_6_0 debug.vert.hlsl -Fo debug_vert.spv
+dxc -T ps_6_0 debug.frag.hlsl -Fo debug_frag.spv
+glslc grid.vert -o grid_vert.spv
+glslc grid.frag -o grid_frag.spv
+glslc sprite.vert -o sprite_vert.spv
+glslc sprite.frag -o sprite_frag.spv
+glslc particle.vert -o particle_vert.spv
+glslc particle.frag -o particle_frag.spv
+glslc gpu_particle.vert -o gpu_particle_vert.spv
+glslc gpu_particles.comp -o gpu_particles_comp.spv
+glslc cull.comp -o cull_comp.spv
+glslc reduce.comp -o reduce_comp.spv
+glslc --target-env=vulkan1.1 reduce_subgroup.comp -o reduce_subgroup_comp.spv
+glslc scan.comp -o scan_comp.spv
+glslc --target-env=vulkan1.1 scan_subgroup.comp -o scan_subgroup_comp.spv
+glslc scan_add.comp -o scan_add_comp.spv
+glslc mipmap.comp -o mipmap_comp.spv
+glslc metering.comp -o metering_comp.spv
+glslc -DHDR metering.comp -o metering_hdr_comp.spv
+glslc -DHDR -DMULTISAMPLED metering.comp -o metering_hdr_ms_comp.spv
+glslc histogram.comp -o histogram_comp.spv
+glslc -DMULTISAMPLED histogram.comp -o histogram_ms_comp.spv
+glslc exposure_adapt.comp -o exposure_adapt_comp.spv
+glslc tonemap.vert -o tonemap_vert.spv
+glslc tonemap.frag -o tonemap_frag.spv
+glslc tonemap_ms.frag -o tonemap_ms_frag.spv
+glslc tonemap_sampled.frag -o tonemap_sampled_frag.spv
+glslc tonemap_ms_sampled.frag -o tonemap_ms_sampled_frag.spv
+glslc focus_blur.frag -o focus_blur_frag.spv
+glslc oit_composite.frag -o oit_composite_frag.spv
+glslc -DMULTISAMPLED oit_composite.frag -o oit_composite_ms_frag.spv
+glslc -DSAMPLED oit_composite.frag -o oit_composite_sampled_frag.spv
+glslc -DMULTISAMPLED -DSAMPLED oit_composite.frag -o oit_composite_ms_sampled_frag.spv
+glslc decal.vert -o decal_vert.spv
+glslc decal.frag -o decal_frag.spv
+glslc -DMULTISAMPLED decal.frag -o decal_ms_frag.spv
+glslc -DSAMPLED decal.frag -o decal_sampled_frag.spv
+glslc -DMULTISAMPLED -DSAMPLED decal.frag -o decal_ms_sampled_frag.spv
+glslc stereo_mirror.frag -o stereo_mirror_frag.spv
+glslc xr_eyes.frag -o xr_eyes_frag.spv
+glslc self_test_color.frag -o self_test_color_frag.spv
+glslc self_test_sample.frag -o self_test_sample_frag.spv
+glslc billboard.vert -o billboard_vert.spv
+glslc billboard.frag -o billboard_frag.spv
+glslc -DOIT billboard.frag -o billboard_frag_oit.spv
+glslc impostor.vert -o impostor_vert.spv
+glslc impostor.frag -o impostor_frag.spv
+
+# A define the compiler ignores leaves a variant identical to its base
+# shader, so no two outputs may be the same.
+cksum *.spv | awk '
+    { key = $1 " " $2; if (key in first) { print first[key] " and " $3 " are identical"; failed = 1 } else first[key] = $3 }
+    END { exit failed }
+' >&2 || exit 1
@@ -0,0 +1,3 @@
+float4 main([[vk::location(0)]] float3 color : COLOR) : SV_Target0 {
+	return float4(color, 1.0);
+}
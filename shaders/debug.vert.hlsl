@@ -0,0 +1,20 @@
+// Debug lines in world space, each vertex colored.
+
+#include "scene.hlsli"
+
+struct VertexInput {
+	[[vk::location(0)]] float3 position : POSITION;
+	[[vk::location(1)]] float3 color : COLOR;
+};
+
+struct VertexOutput {
+	float4 position : SV_Position;
+	[[vk::location(0)]] float3 color : COLOR;
+};
+
+VertexOutput main(VertexInput input) {
+	VertexOutput output;
+	output.position = mul(ubo.proj[0], mul(ubo.view, float4(input.position, 1.0)));
+	output.color = input.color;
+	return output;
+}
@@ -0,0 +1,49 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Projects a texture onto the scene inside a decal's box, found from the
+// scene depth. Compiled with MULTISAMPLED when the depth is multisampled,
+// and with SAMPLED for dynamic rendering, which samples it instead of
+// reading it as an input attachment.
+#if defined(SAMPLED) && defined(MULTISAMPLED)
+layout(set = 2, binding = 0) uniform sampler2DMS sceneDepth;
+#define LOAD_DEPTH() texelFetch(sceneDepth, ivec2(gl_FragCoord.xy), 0).r
+#elif defined(SAMPLED)
+layout(set = 2, binding = 0) uniform sampler2D sceneDepth;
+#define LOAD_DEPTH() texelFetch(sceneDepth, ivec2(gl_FragCoord.xy), 0).r
+#elif defined(MULTISAMPLED)
+layout(input_attachment_index = 0, set = 2, binding = 0) uniform subpassInputMS sceneDepth;
+#define LOAD_DEPTH() subpassLoad(sceneDepth, 0).r
+#else
+layout(input_attachment_index = 0, set = 2, binding = 0) uniform subpassInput sceneDepth;
+#define LOAD_DEPTH() subpassLoad(sceneDepth).r
+#endif
+
+#include "textures.glsl"
+
+layout(push_constant) uniform PushConstants {
+	mat4 model;
+	vec4 color;
+	uint textureIndex;
+} pcs;
+
+layout(location = 0) in vec4 clipPosition;
+layout(location = 1) flat in mat4 clipToDecal;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+	// Shaded once per pixel from the first sample's depth.
+	vec4 surface = clipToDecal * vec4(clipPosition.xy / clipPosition.w, LOAD_DEPTH(), 1.0);
+	vec3 position = surface.xyz / surface.w;
+
+	// Outside the box, or the background at infinity.
+	if (!all(lessThanEqual(abs(position), vec3(0.5)))) {
+		discard;
+	}
+
+	// Projected down the box's Y axis. Multiplied into the scene color, which
+	// tints the surface's albedo under its own lighting.
+	vec4 texel = sampleTexture(pcs.textureIndex, position.xz + 0.5) * pcs.color;
+	outColor = vec4(mix(vec3(1.0), texel.rgb, texel.a), 1.0);
+}
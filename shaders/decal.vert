@@ -0,0 +1,32 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "scene.glsl"
+
+layout(push_constant) uniform PushConstants {
+	mat4 model;
+	vec4 color;
+	uint textureIndex;
+} pcs;
+
+layout(location = 0) out vec4 clipPosition;
+layout(location = 1) flat out mat4 clipToDecal;
+
+// The corners of the unit cube, by bits: X, Y, Z.
+const uint cubeIndices[36] = uint[](
+	0, 4, 6, 0, 6, 2,
+	1, 7, 5, 1, 3, 7,
+	0, 1, 5, 0, 5, 4,
+	2, 7, 3, 2, 6, 7,
+	0, 2, 3, 0, 3, 1,
+	4, 5, 7, 4, 7, 6
+);
+
+void main() {
+	uint corner = cubeIndices[gl_VertexIndex];
+	vec3 position = vec3(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1) - 0.5;
+	mat4 decalToClip = ubo.proj[0] * ubo.view * pcs.model;
+	clipPosition = decalToClip * vec4(position, 1.0);
+	clipToDecal = inverse(decalToClip);
+	gl_Position = clipPosition;
+}
@@ -0,0 +1,27 @@
+#version 450
+
+layout(binding = 0) uniform sampler2D viewport;
+
+layout(push_constant) uniform PushConstants {
+	vec2 inverseExtent;
+	float lod;
+	float dim;
+	float amount;
+} pcs;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+	vec2 uv = gl_FragCoord.xy * pcs.inverseExtent;
+
+	// Mip levels are box filtered, so a single lookup shows their blocks.
+	// Four bilinear taps half a texel apart turn it into a tent filter. The
+	// mip chain starts at half resolution.
+	vec2 offset = 0.5 * exp2(pcs.lod + 1.0) * pcs.inverseExtent;
+	vec3 color = textureLod(viewport, uv + vec2(-offset.x, -offset.y), pcs.lod).rgb;
+	color += textureLod(viewport, uv + vec2(offset.x, -offset.y), pcs.lod).rgb;
+	color += textureLod(viewport, uv + vec2(-offset.x, offset.y), pcs.lod).rgb;
+	color += textureLod(viewport, uv + vec2(offset.x, offset.y), pcs.lod).rgb;
+
+	outColor = vec4(color * 0.25 * (1.0 - pcs.dim), pcs.amount);
+}
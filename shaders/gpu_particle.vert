@@ -0,0 +1,35 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "scene.glsl"
+
+layout(push_constant) uniform PushConstants {
+	vec4 startColor;
+	vec4 endColor;
+	float startSize;
+	float endSize;
+} pcs;
+
+// Per instance, straight from the simulation's particle buffer.
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in float inAge;
+layout(location = 2) in float inLifetime;
+
+layout(location = 0) out vec2 fragCorner;
+layout(location = 1) out vec4 fragColor;
+
+const vec2 corners[6] = vec2[](
+	vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+	vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+	float t = clamp(inAge / inLifetime, 0.0, 1.0);
+	vec2 corner = corners[gl_VertexIndex];
+
+	vec4 viewPosition = ubo.view * vec4(inPosition, 1.0);
+	viewPosition.xy += corner * mix(pcs.startSize, pcs.endSize, t);
+	gl_Position = ubo.proj[0] * viewPosition;
+	fragCorner = corner;
+	fragColor = mix(pcs.startColor, pcs.endColor, t);
+}
@@ -0,0 +1,55 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "scene.glsl"
+
+layout(push_constant) uniform PushConstants {
+	layout(offset = 64) uint upAxis;
+	float metersPerUnit;
+} pcs;
+
+layout(location = 0) in vec3 nearPoint;
+layout(location = 1) in vec3 farPoint;
+
+layout(location = 0) out vec4 outColor;
+
+// Coordinates on the ground plane, in meters.
+vec2 ground(vec3 position) {
+    vec2 plane = pcs.upAxis == 1 ? position.xz : position.xy;
+    return plane * pcs.metersPerUnit;
+}
+
+vec4 grid(vec2 plane, float scale) {
+    vec2 coord = plane * scale;
+    vec2 derivative = fwidth(coord);
+    vec2 lines = abs(fract(coord - 0.5) - 0.5) / derivative;
+    float line = min(lines.x, lines.y);
+    vec4 color = vec4(0.3, 0.3, 0.3, 1.0 - min(line, 1.0));
+
+    vec2 axis = min(derivative, 1.0) / scale;
+    if (abs(plane.x) < axis.x) {
+        color.rgb = vec3(0.2, 0.9, 0.2);
+    }
+    if (abs(plane.y) < axis.y) {
+        color.rgb = vec3(0.9, 0.2, 0.2);
+    }
+
+    return color;
+}
+
+void main() {
+    // Intersect the view ray with the ground plane through the origin.
+    float t = -nearPoint[pcs.upAxis] / (farPoint[pcs.upAxis] - nearPoint[pcs.upAxis]);
+    if (t <= 0.0 || t >= 1.0) {
+        discard;
+    }
+
+    vec3 position = nearPoint + t * (farPoint - nearPoint);
+    vec4 clip = ubo.proj[0] * ubo.view * vec4(position, 1.0);
+    gl_FragDepth = clip.z / clip.w;
+
+    float fade = 1.0 - smoothstep(0.25, 1.0, t);
+    vec2 plane = ground(position);
+    outColor = max(grid(plane, 1.0), grid(plane, 0.1));
+    outColor.a *= fade;
+}
@@ -0,0 +1,32 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "scene.glsl"
+
+layout(location = 0) out vec3 nearPoint;
+layout(location = 1) out vec3 farPoint;
+
+vec2 positions[6] = vec2[](
+	vec2(1.0, 1.0), vec2(-1.0, -1.0), vec2(-1.0, 1.0),
+	vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(1.0, -1.0)
+);
+
+vec3 toWorld(vec3 viewPoint) {
+	vec4 point = inverse(ubo.view) * vec4(viewPoint, 1.0);
+	return point.xyz / point.w;
+}
+
+void main() {
+	vec2 position = positions[gl_VertexIndex];
+	// The view space ray through this corner, one unit deep. Built from the
+	// projection's X and Y alone, since its depth may be reversed or reach
+	// infinity.
+	vec3 ray = vec3(
+		(position.x + ubo.proj[0][2][0]) / ubo.proj[0][0][0],
+		(position.y + ubo.proj[0][2][1]) / ubo.proj[0][1][1],
+		-1.0
+	);
+	nearPoint = toWorld(ray * ubo.nearPlane);
+	farPoint = toWorld(ray * ubo.farPlane);
+	gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,24 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// The model's albedo under a fixed sky-like light, so its shape still reads
+// once it is flattened onto a billboard.
+
+#include "textures.glsl"
+
+// Where `FragmentPushConstants` puts the texture index.
+layout(push_constant) uniform PushConstants {
+	layout(offset = 72) uint textureIndex;
+	layout(offset = 80) vec4 up;
+} pcs;
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec3 fragNormal;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+	vec3 albedo = sampleTexture(pcs.textureIndex, fragTexCoord).rgb;
+	float sky = 0.5 + 0.5 * dot(normalize(fragNormal), pcs.up.xyz);
+	outColor = vec4(albedo * mix(0.4, 1.0, sky), 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+// Draws the model into one view of an impostor, see `impostor.rs`.
+
+layout(push_constant) uniform PushConstants {
+	mat4 viewProj;
+} pcs;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec3 inNormal;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec3 fragNormal;
+
+void main() {
+	gl_Position = pcs.viewProj * vec4(inPosition, 1.0);
+	fragTexCoord = inTexCoord;
+	fragNormal = inNormal;
+}
@@ -0,0 +1,45 @@
+// Color math shared by the exposure and tonemap passes.
+#ifndef COLOR_GLSL
+#define COLOR_GLSL
+
+// Of Rec. 709 primaries, which the scene is lit in.
+float relativeLuminance(vec3 color) {
+	return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 color) {
+	const float a = 2.51;
+	const float b = 0.03;
+	const float c = 2.43;
+	const float d = 0.59;
+	const float e = 0.14;
+	return clamp((color * (a * color + b)) / (color * (c * color + d) + e), 0.0, 1.0);
+}
+
+vec3 encodeSrgb(vec3 color) {
+	return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, greaterThan(color, vec3(0.0031308)));
+}
+
+vec3 decodeSrgb(vec3 color) {
+	return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), greaterThan(color, vec3(0.04045)));
+}
+
+// Rec. 709 primaries to Rec. 2020.
+const mat3 REC709_TO_REC2020 = mat3(
+	0.6274, 0.0691, 0.0164,
+	0.3293, 0.9195, 0.0880,
+	0.0433, 0.0114, 0.8956);
+
+// SMPTE ST 2084 from absolute nits.
+vec3 encodePq(vec3 nits) {
+	const float m1 = 0.1593017578125;
+	const float m2 = 78.84375;
+	const float c1 = 0.8359375;
+	const float c2 = 18.8515625;
+	const float c3 = 18.6875;
+	vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+	return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+#endif
@@ -0,0 +1,27 @@
+// Diffuse lighting by the sun and the light probes.
+#ifndef LIGHTING_GLSL
+#define LIGHTING_GLSL
+
+#include "scene.glsl"
+
+#define PI 3.14159265359
+
+// Irradiance from light probe coefficients per color channel, as (L00,
+// L1-1, L10, L11), around the normal `n`.
+vec3 probeIrradiance(vec4 r, vec4 g, vec4 b, vec3 n) {
+	vec4 basis = vec4(1.0, n.y, n.z, n.x);
+	return vec3(dot(r, basis), dot(g, basis), dot(b, basis));
+}
+
+// Irradiance from the sun on a surface facing `n`, which sees `visibility`
+// of it.
+vec3 sunIrradiance(vec3 n, float visibility) {
+	return ubo.sunIlluminance.rgb * max(dot(n, ubo.sunDirection.xyz), 0.0) * visibility;
+}
+
+// Light a Lambertian surface reflects.
+vec3 lambert(vec3 albedo, vec3 irradiance) {
+	return albedo * irradiance / PI;
+}
+
+#endif
@@ -0,0 +1,12 @@
+// Weighted blended order-independent transparency.
+#ifndef OIT_GLSL
+#define OIT_GLSL
+
+// McGuire and Bavoil's depth weight, which favors nearer surfaces without
+// ordering them.
+float oitWeight(float alpha, float viewDepth) {
+	float z = viewDepth;
+	return alpha * clamp(10.0 / (1e-5 + pow(z / 5.0, 2.0) + pow(z / 200.0, 6.0)), 1e-2, 3e3);
+}
+
+#endif
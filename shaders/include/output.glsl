@@ -0,0 +1,31 @@
+// Encoding for the swapchain, for passes that write to it.
+#ifndef OUTPUT_GLSL
+#define OUTPUT_GLSL
+
+#include "color.glsl"
+
+// The first of the constants below, for passes that number theirs from
+// elsewhere.
+#ifndef OUTPUT_CONSTANT_ID
+#define OUTPUT_CONSTANT_ID 1
+#endif
+
+layout(constant_id = OUTPUT_CONSTANT_ID) const bool ENCODE_SRGB = false;
+// How the swapchain encodes color, see `SwapchainColorSpace`: 0 for sRGB,
+// 1 for the PQ curve over Rec. 2020 primaries, 2 for linear scRGB.
+layout(constant_id = OUTPUT_CONSTANT_ID + 1) const int OUTPUT = 0;
+// Nits that 1.0 is shown at on HDR outputs.
+layout(constant_id = OUTPUT_CONSTANT_ID + 2) const float PAPER_WHITE = 203.0;
+
+vec3 encodeOutput(vec3 color) {
+	if (OUTPUT == 1) {
+		return encodePq(REC709_TO_REC2020 * color * PAPER_WHITE);
+	}
+	if (OUTPUT == 2) {
+		// scRGB places 1.0 at 80 nits.
+		return color * (PAPER_WHITE / 80.0);
+	}
+	return ENCODE_SRGB ? encodeSrgb(color) : color;
+}
+
+#endif
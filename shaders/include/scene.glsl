@@ -0,0 +1,25 @@
+// The scene's uniform buffer at set 0 binding 0, as `UniformBufferObject`
+// lays it out.
+#ifndef SCENE_GLSL
+#define SCENE_GLSL
+
+layout(binding = 0) uniform UniformBufferObject {
+	mat4 view;
+	// Indexed by camera layer: the world, then the viewmodel.
+	mat4 proj[2];
+	float exposure;
+	float nearPlane;
+	float farPlane;
+	// Towards the sun, with w set when the shadow mask was traced this
+	// frame.
+	vec4 sunDirection;
+	vec4 sunIlluminance;
+	// Set when ambient occlusion was traced this frame.
+	float occlusionTraced;
+	// Indexed by view: the left eye, then the right. Only set for the
+	// stereo pass.
+	mat4 eyeView[2];
+	mat4 eyeProj[2];
+} ubo;
+
+#endif
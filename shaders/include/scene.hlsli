@@ -0,0 +1,27 @@
+// The scene's uniform buffer at set 0 binding 0, as `scene.glsl` declares
+// it for GLSL.
+#ifndef SCENE_HLSLI
+#define SCENE_HLSLI
+
+struct SceneUniforms {
+	float4x4 view;
+	// Indexed by camera layer: the world, then the viewmodel.
+	float4x4 proj[2];
+	float exposure;
+	float nearPlane;
+	float farPlane;
+	// Towards the sun, with w set when the shadow mask was traced this
+	// frame.
+	float4 sunDirection;
+	float4 sunIlluminance;
+	// Set when ambient occlusion was traced this frame.
+	float occlusionTraced;
+	// Indexed by view: the left eye, then the right. Only set for the
+	// stereo pass.
+	float4x4 eyeView[2];
+	float4x4 eyeProj[2];
+};
+
+ConstantBuffer<SceneUniforms> ubo : register(b0, space0);
+
+#endif
@@ -0,0 +1,27 @@
+// The texture table at set 1, which entities, decals and impostors pick
+// from with a texture index: the low half is the slot, the high half the
+// atlas region.
+#ifndef TEXTURES_GLSL
+#define TEXTURES_GLSL
+
+// Atlas regions as offset and scale in their page. Region 0 is the whole
+// texture.
+layout(set = 1, binding = 0) readonly buffer AtlasRegions {
+	vec4 rects[];
+} atlasRegions;
+
+// Every texture the scene samples. Slot 0 is the scene texture.
+layout(constant_id = 0) const uint TEXTURE_CAPACITY = 16;
+layout(set = 1, binding = 1) uniform sampler2D textures[TEXTURE_CAPACITY];
+
+// `texCoord` in the atlas page of `textureIndex`.
+vec2 atlasTexCoord(uint textureIndex, vec2 texCoord) {
+	vec4 region = atlasRegions.rects[textureIndex >> 16];
+	return region.xy + texCoord * region.zw;
+}
+
+vec4 sampleTexture(uint textureIndex, vec2 texCoord) {
+	return texture(textures[textureIndex & 0xFFFFu], atlasTexCoord(textureIndex, texCoord));
+}
+
+#endif
@@ -0,0 +1,47 @@
+// Everything the tonemap passes share but how they read the HDR color at
+// binding 0.
+#ifndef TONEMAP_GLSL
+#define TONEMAP_GLSL
+
+#include "output.glsl"
+
+// Grades the tonemapped color, sRGB encoded. The identity when grading is
+// off.
+layout(binding = 1) uniform sampler3D colorLut;
+// Adapted by `exposure_adapt.comp` when the exposure is metered with a
+// histogram.
+layout(binding = 2) readonly buffer Exposure {
+	float exposure;
+	float luminance;
+} adapted;
+
+layout(push_constant) uniform PushConstants {
+	bool passthrough;
+	// Scales the color by the adapted exposure, which the scene left out.
+	bool adaptedExposure;
+	// Map encoded color to the LUT's texture coordinates.
+	vec4 lutScale;
+	vec4 lutOffset;
+} pcs;
+
+vec3 tonemap(vec3 color) {
+	if (pcs.passthrough) {
+		return clamp(color, 0.0, 1.0);
+	}
+	if (pcs.adaptedExposure) {
+		color *= adapted.exposure;
+	}
+	return aces(color);
+}
+
+// LUTs are authored for display encoded color.
+vec3 grade(vec3 color) {
+	if (pcs.passthrough) {
+		return color;
+	}
+
+	vec3 coordinates = encodeSrgb(color) * pcs.lutScale.rgb + pcs.lutOffset.rgb;
+	return decodeSrgb(clamp(texture(colorLut, coordinates).rgb, 0.0, 1.0));
+}
+
+#endif
@@ -0,0 +1,54 @@
+#version 450
+
+// Blends the order-independent transparency targets over the scene color.
+// Compiled with MULTISAMPLED when they are multisampled, and with SAMPLED
+// for dynamic rendering, which samples them instead of reading them as
+// input attachments.
+#if defined(SAMPLED) && defined(MULTISAMPLED)
+layout(binding = 0) uniform sampler2DMS accumTarget;
+layout(binding = 1) uniform sampler2DMS revealTarget;
+#define LOAD(target, i) texelFetch(target, ivec2(gl_FragCoord.xy), i)
+#elif defined(SAMPLED)
+layout(binding = 0) uniform sampler2D accumTarget;
+layout(binding = 1) uniform sampler2D revealTarget;
+#define LOAD(target, i) texelFetch(target, ivec2(gl_FragCoord.xy), 0)
+#elif defined(MULTISAMPLED)
+layout(input_attachment_index = 0, binding = 0) uniform subpassInputMS accumTarget;
+layout(input_attachment_index = 1, binding = 1) uniform subpassInputMS revealTarget;
+#define LOAD(target, i) subpassLoad(target, i)
+#else
+layout(input_attachment_index = 0, binding = 0) uniform subpassInput accumTarget;
+layout(input_attachment_index = 1, binding = 1) uniform subpassInput revealTarget;
+#define LOAD(target, i) subpassLoad(target)
+#endif
+
+layout(constant_id = 0) const int SAMPLES = 1;
+
+layout(location = 0) out vec4 outColor;
+
+// Shaded once per pixel, so the targets' samples are averaged and the result
+// blended over each of the scene's samples.
+void main() {
+	vec4 accum = vec4(0.0);
+	float reveal = 0.0;
+	for (int i = 0; i < SAMPLES; i++) {
+		accum += LOAD(accumTarget, i);
+		reveal += LOAD(revealTarget, i).r;
+	}
+	accum /= float(SAMPLES);
+	reveal /= float(SAMPLES);
+
+	// Nothing transparent covers the pixel.
+	if (reveal >= 1.0) {
+		discard;
+	}
+
+	// Many bright, near surfaces can overflow the half floats.
+	if (isinf(max(max(abs(accum.r), abs(accum.g)), abs(accum.b)))) {
+		accum.rgb = vec3(accum.a);
+	}
+
+	// Blended with the scene by the revealage: ONE_MINUS_SRC_ALPHA and
+	// SRC_ALPHA.
+	outColor = vec4(accum.rgb / max(accum.a, 1e-5), reveal);
+}
@@ -0,0 +1,21 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "scene.glsl"
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec2 inCorner;
+layout(location = 2) in float inSize;
+layout(location = 3) in vec4 inColor;
+
+layout(location = 0) out vec2 fragCorner;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+	// Offset in view space so the quad always faces the camera.
+	vec4 viewPosition = ubo.view * vec4(inPosition, 1.0);
+	viewPosition.xy += inCorner * inSize;
+	gl_Position = ubo.proj[0] * viewPosition;
+	fragCorner = inCorner;
+	fragColor = inColor;
+}
@@ -0,0 +1,11 @@
+#version 450
+
+layout(binding = 0) uniform sampler2D tex;
+
+layout(location = 0) out vec4 outColor;
+
+// The target and texture are the same size, so each pixel samples the
+// texel under it.
+void main() {
+	outColor = texture(tex, gl_FragCoord.xy / vec2(textureSize(tex, 0)));
+}
@@ -1,17 +1,178 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
-layout(binding = 1) uniform sampler2D texSampler;
+#include "lighting.glsl"
+#include "oit.glsl"
+#include "scene.glsl"
+#include "textures.glsl"
+
+#define DEBUG_VIEW_NONE 0
+#define DEBUG_VIEW_DEPTH 1
+#define DEBUG_VIEW_NORMALS 2
+#define DEBUG_VIEW_UVS 3
+#define DEBUG_VIEW_MIP_LEVEL 4
+#define DEBUG_VIEW_OVERDRAW 5
+
+#define SHADING_LIT 0
+#define SHADING_UNLIT 1
+
+// A material texture index for no texture.
+#define NO_TEXTURE 0xFFFFFFFFu
+
+// Traced sun visibility, blocker distance and view depth per pixel.
+layout(binding = 2) uniform sampler2D shadowMask;
+// Traced ambient occlusion, view depth and frames accumulated per pixel.
+layout(binding = 3) uniform sampler2D ambientOcclusion;
+
+// Each material instance's parameters, picked per draw. Block 0 is for
+// entities without a material.
+struct MaterialBlock {
+	vec4 baseColor;
+	vec4 emissive;
+	// Texture indices replacing the entity's and multiplying `emissive`.
+	uint texture;
+	uint emissiveTexture;
+};
+
+layout(binding = 4) readonly buffer Materials {
+	MaterialBlock blocks[];
+} materials;
+
+// How the material shades, see `Shading`.
+layout(constant_id = 2) const uint SHADING = SHADING_LIT;
 
 layout(push_constant) uniform PushConstants {
 	layout(offset = 64) float opacity;
+	uint debugView;
+	uint textureIndex;
+	uint material;
+	// Light probe irradiance per color channel, as (L00, L1-1, L10, L11).
+	vec4 ambientR;
+	vec4 ambientG;
+	vec4 ambientB;
 } pcs;
 
 layout(location = 0) in vec3 fragColor;
 layout(location = 1) in vec2 fragTexCoord;
+layout(location = 2) in vec3 fragWorldPosition;
+layout(location = 3) in float fragViewDepth;
+#ifdef INDIRECT
+layout(location = 7) flat in float fragOpacity;
+layout(location = 8) flat in uint fragTextureIndex;
+layout(location = 9) flat in vec4 fragAmbient[3];
+
+#define OPACITY fragOpacity
+#define TEXTURE_INDEX fragTextureIndex
+#define AMBIENT_R fragAmbient[0]
+#define AMBIENT_G fragAmbient[1]
+#define AMBIENT_B fragAmbient[2]
+// Entities with a material are never culled on the GPU.
+#define MATERIAL 0u
+#else
+#define OPACITY pcs.opacity
+#define TEXTURE_INDEX pcs.textureIndex
+#define AMBIENT_R pcs.ambientR
+#define AMBIENT_G pcs.ambientG
+#define AMBIENT_B pcs.ambientB
+#define MATERIAL pcs.material
+#endif
 
 layout(location = 0) out vec4 outColor;
+#ifdef OIT
+// Compiled with OIT when the scene pass has the order-independent
+// transparency targets. Transparent entities are accumulated into them, and
+// everything else resets them where it covers, so that transparent surfaces
+// it is drawn over do not show through.
+layout(constant_id = 1) const bool OIT_ACCUMULATE = false;
 
-void main() {
-    outColor = vec4(texture(texSampler, fragTexCoord).rgb, pcs.opacity);
+layout(location = 1) out vec4 outAccum;
+layout(location = 2) out float outReveal;
+#endif
+
+const vec3 mipColors[6] = vec3[](
+    vec3(0.0, 0.0, 1.0),
+    vec3(0.0, 1.0, 1.0),
+    vec3(0.0, 1.0, 0.0),
+    vec3(1.0, 1.0, 0.0),
+    vec3(1.0, 0.5, 0.0),
+    vec3(1.0, 0.0, 0.0)
+);
+
+// Whether a traced pass saw this surface at `viewDepth`, which it does not
+// for viewmodels and overlays, or behind transparent entities.
+bool tracedHere(float viewDepth) {
+    return abs(viewDepth - fragViewDepth) < 0.02 * fragViewDepth;
 }
 
+void main() {
+    MaterialBlock material = materials.blocks[MATERIAL];
+    uint textureIndex = material.texture == NO_TEXTURE ? TEXTURE_INDEX : material.texture;
+
+    switch (pcs.debugView) {
+    case DEBUG_VIEW_DEPTH:
+        float depth = (fragViewDepth - ubo.nearPlane) / (ubo.farPlane - ubo.nearPlane);
+        outColor = vec4(vec3(clamp(depth, 0.0, 1.0)), 1.0);
+        break;
+    case DEBUG_VIEW_NORMALS:
+        vec3 normal = normalize(cross(dFdx(fragWorldPosition), dFdy(fragWorldPosition)));
+        outColor = vec4(normal * 0.5 + 0.5, 1.0);
+        break;
+    case DEBUG_VIEW_UVS:
+        outColor = vec4(fract(fragTexCoord), 0.0, 1.0);
+        break;
+    case DEBUG_VIEW_MIP_LEVEL:
+        float lod = textureQueryLod(textures[textureIndex & 0xFFFFu], atlasTexCoord(textureIndex, fragTexCoord)).x;
+        int level = clamp(int(lod), 0, 5);
+        outColor = vec4(mix(mipColors[level], mipColors[min(level + 1, 5)], fract(lod)), 1.0);
+        break;
+    case DEBUG_VIEW_OVERDRAW:
+        // Drawn with additive blending, so each layer adds up.
+        outColor = vec4(0.1, 0.04, 0.02, 1.0);
+        break;
+    default:
+        vec3 albedo = sampleTexture(textureIndex, fragTexCoord).rgb * material.baseColor.rgb;
+        vec3 emissive = material.emissive.rgb;
+        if (material.emissiveTexture != NO_TEXTURE) {
+            emissive *= sampleTexture(material.emissiveTexture, fragTexCoord).rgb;
+        }
+        float alpha = OPACITY * material.baseColor.a;
+        if (SHADING == SHADING_UNLIT) {
+            outColor = vec4((albedo + emissive) * ubo.exposure, alpha);
+            break;
+        }
+
+        vec3 n = normalize(cross(dFdx(fragWorldPosition), dFdy(fragWorldPosition)));
+        vec3 irradiance = probeIrradiance(AMBIENT_R, AMBIENT_G, AMBIENT_B, n);
+        float visibility = 1.0;
+#ifndef MULTIVIEW
+        // Traced from the mono camera, so of no use to the eyes.
+        if (ubo.sunDirection.w > 0.0) {
+            vec4 shadow = texelFetch(shadowMask, ivec2(gl_FragCoord.xy), 0);
+            if (tracedHere(shadow.z)) {
+                visibility = shadow.x;
+            }
+        }
+        if (ubo.occlusionTraced > 0.0) {
+            vec4 occlusion = texelFetch(ambientOcclusion, ivec2(gl_FragCoord.xy), 0);
+            if (tracedHere(occlusion.y)) {
+                irradiance *= occlusion.x;
+            }
+        }
+#endif
+        irradiance = max(irradiance, 0.0) + sunIrradiance(n, visibility);
+        outColor = vec4((lambert(albedo, irradiance) + emissive) * ubo.exposure, alpha);
+        break;
+    }
+
+#ifdef OIT
+    if (OIT_ACCUMULATE) {
+        float alpha = outColor.a;
+        float weight = oitWeight(alpha, fragViewDepth);
+        outAccum = vec4(outColor.rgb * alpha, alpha) * weight;
+        outReveal = alpha;
+    } else {
+        outAccum = vec4(0.0);
+        outReveal = 1.0;
+    }
+#endif
+}
@@ -1,24 +1,114 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
 
-layout(binding = 0) uniform UniformBufferObject {
-	mat4 view;
-	mat4 proj;
-} ubo;
+// Compiled with MULTIVIEW for the stereo pass, which draws each eye as a
+// view of the same draw.
+#ifdef MULTIVIEW
+#extension GL_EXT_multiview : require
+#endif
+
+#include "scene.glsl"
+
+// Skinned entities' joint matrices, back to back. Each takes vertices from
+// the bind pose to the entity's pose, before the model matrix.
+layout(binding = 1) readonly buffer JointMatrices {
+	mat4 joints[];
+} jointMatrices;
 
 layout(push_constant) uniform PushConstants {
 	mat4 model;
 } pcs;
 
+#ifdef INDIRECT
+// Compiled with INDIRECT for GPU-culled draws, which read what is otherwise
+// pushed from the object the culling pass wrote for their instance.
+struct Object {
+	mat4 model;
+	vec4 boundingSphere;
+	uint instance;
+	uint textureIndex;
+	float opacity;
+	uint lod;
+	vec4 ambient[3];
+};
+
+layout(set = 2, binding = 0) readonly buffer Objects {
+	Object objects[];
+} objects;
+
+layout(set = 2, binding = 1) readonly buffer DrawObjects {
+	uint indices[];
+} drawObjects;
+#endif
+
 layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec3 inColor;
 layout(location = 2) in vec2 inTexCoord;
+layout(location = 3) in vec3 inNormal;
+// xyz along increasing U, w the bitangent sign.
+layout(location = 4) in vec4 inTangent;
+layout(location = 5) in vec2 inTexCoord1;
+layout(location = 6) in uvec4 inJoints;
+layout(location = 7) in vec4 inWeights;
 
 layout(location = 0) out vec3 fragColor;
 layout(location = 1) out vec2 fragTexCoord;
+layout(location = 2) out vec3 fragWorldPosition;
+layout(location = 3) out float fragViewDepth;
+layout(location = 4) out vec3 fragNormal;
+layout(location = 5) out vec4 fragTangent;
+layout(location = 6) out vec2 fragTexCoord1;
+#ifdef INDIRECT
+layout(location = 7) flat out float fragOpacity;
+layout(location = 8) flat out uint fragTextureIndex;
+layout(location = 9) flat out vec4 fragAmbient[3];
+#endif
+
+// The depth pre-pass and the color pass that tests against it with EQUAL are
+// different pipelines, which only agree on depth exactly with this.
+invariant gl_Position;
 
 void main() {
-	gl_Position = ubo.proj * ubo.view * pcs.model * vec4(inPosition, 1.0);
+#ifdef INDIRECT
+	Object object = objects.objects[drawObjects.indices[gl_InstanceIndex]];
+	uint instance = object.instance;
+	mat4 model = object.model;
+	fragOpacity = object.opacity;
+	fragTextureIndex = object.textureIndex;
+	fragAmbient = object.ambient;
+#else
+	uint instance = uint(gl_InstanceIndex);
+	mat4 model = pcs.model;
+#endif
+
+	// Draws pass their camera layer in the low bit of the first instance
+	// and where their joint matrices start in the rest.
+	uint layer = instance & 1u;
+	uint jointBase = instance >> 1;
+
+	if (inWeights != vec4(0.0)) {
+		model *= inWeights.x * jointMatrices.joints[jointBase + inJoints.x]
+			+ inWeights.y * jointMatrices.joints[jointBase + inJoints.y]
+			+ inWeights.z * jointMatrices.joints[jointBase + inJoints.z]
+			+ inWeights.w * jointMatrices.joints[jointBase + inJoints.w];
+	}
+
+	vec4 worldPosition = model * vec4(inPosition, 1.0);
+#ifdef MULTIVIEW
+	vec4 viewPosition = ubo.eyeView[gl_ViewIndex] * worldPosition;
+	gl_Position = ubo.eyeProj[gl_ViewIndex] * viewPosition;
+#else
+	vec4 viewPosition = ubo.view * worldPosition;
+	gl_Position = ubo.proj[layer] * viewPosition;
+#endif
 	fragColor = inColor;
 	fragTexCoord = inTexCoord;
+	fragWorldPosition = worldPosition.xyz;
+	fragViewDepth = -viewPosition.z;
+	// Assumes uniform scale, which the model matrix can transform normals
+	// with. A non-uniform one would need its inverse transpose.
+	mat3 normalMatrix = mat3(model);
+	fragNormal = normalize(normalMatrix * inNormal);
+	fragTangent = vec4(normalize(normalMatrix * inTangent.xyz), inTangent.w);
+	fragTexCoord1 = inTexCoord1;
 }
-
@@ -0,0 +1,28 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Sprites are drawn straight into the swapchain image, which only encodes
+// sRGB on write for `_SRGB` formats, so they encode it like the tonemap
+// pass. Their constants are numbered from 0.
+#define OUTPUT_CONSTANT_ID 0
+#include "output.glsl"
+
+layout(binding = 0) uniform sampler2D atlas;
+
+layout(location = 0) in vec2 fragTexCoord;
+layout(location = 1) in vec4 fragColor;
+layout(location = 2) flat in uint fragMode;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec4 texel = texture(atlas, fragTexCoord);
+    if (fragMode == 1) {
+        // Glyphs are coverage masks stored in the red channel.
+        outColor = vec4(fragColor.rgb, fragColor.a * texel.r);
+    } else {
+        outColor = texel * fragColor;
+    }
+
+    outColor.rgb = encodeOutput(clamp(outColor.rgb, 0.0, 1.0));
+}
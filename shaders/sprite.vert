@@ -0,0 +1,32 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+	vec2 screenSize;
+} pcs;
+
+// The top-left corner, and the top and left edges, in pixels.
+layout(location = 0) in vec2 inOrigin;
+layout(location = 1) in vec2 inAxisX;
+layout(location = 2) in vec2 inAxisY;
+layout(location = 3) in vec2 inUvMin;
+layout(location = 4) in vec2 inUvMax;
+layout(location = 5) in vec4 inColor;
+layout(location = 6) in uint inMode;
+
+layout(location = 0) out vec2 fragTexCoord;
+layout(location = 1) out vec4 fragColor;
+layout(location = 2) flat out uint fragMode;
+
+vec2 corners[6] = vec2[](
+	vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0),
+	vec2(0.0, 1.0), vec2(1.0, 0.0), vec2(1.0, 1.0)
+);
+
+void main() {
+	vec2 corner = corners[gl_VertexIndex];
+	vec2 pixel = inOrigin + corner.x * inAxisX + corner.y * inAxisY;
+	gl_Position = vec4(pixel / pcs.screenSize * 2.0 - 1.0, 0.0, 1.0);
+	fragTexCoord = mix(inUvMin, inUvMax, corner);
+	fragColor = inColor;
+	fragMode = inMode;
+}
@@ -0,0 +1,18 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Both eyes of the stereo target side by side, tonemapped like the scene.
+layout(binding = 0) uniform sampler2DArray eyes;
+
+#include "output.glsl"
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+	ivec2 size = textureSize(eyes, 0).xy;
+	ivec2 pixel = ivec2(gl_FragCoord.xy);
+	int eye = min(pixel.x / size.x, 1);
+	pixel.x -= eye * size.x;
+	vec3 color = aces(texelFetch(eyes, ivec3(min(pixel, size - 1), eye), 0).rgb);
+	outColor = vec4(encodeOutput(color), 1.0);
+}
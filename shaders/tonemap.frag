@@ -0,0 +1,14 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+layout(input_attachment_index = 0, binding = 0) uniform subpassInput hdrColor;
+
+#include "tonemap.glsl"
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+	vec3 color = tonemap(subpassLoad(hdrColor).rgb);
+	color = grade(color);
+	outColor = vec4(encodeOutput(color), 1.0);
+}
@@ -0,0 +1,22 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+layout(input_attachment_index = 0, binding = 0) uniform subpassInputMS hdrColor;
+
+layout(constant_id = 0) const int SAMPLES = 1;
+
+#include "tonemap.glsl"
+
+layout(location = 0) out vec4 outColor;
+
+// Resolves after tonemapping so that bright edges against dark backgrounds
+// average in display space rather than being dominated by the HDR value.
+void main() {
+	vec3 color = vec3(0.0);
+	for (int i = 0; i < SAMPLES; i++) {
+		color += tonemap(subpassLoad(hdrColor, i).rgb);
+	}
+	color /= float(SAMPLES);
+	color = grade(color);
+	outColor = vec4(encodeOutput(color), 1.0);
+}
@@ -0,0 +1,14 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+layout(binding = 0) uniform sampler2D hdrColor;
+
+#include "tonemap.glsl"
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+	vec3 color = tonemap(texelFetch(hdrColor, ivec2(gl_FragCoord.xy), 0).rgb);
+	color = grade(color);
+	outColor = vec4(encodeOutput(color), 1.0);
+}
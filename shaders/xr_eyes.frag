@@ -0,0 +1,18 @@
+#version 450
+#extension GL_EXT_multiview : require
+#extension GL_GOOGLE_include_directive : require
+
+#include "color.glsl"
+
+// Each eye of the stereo target into its layer of the OpenXR swapchain,
+// tonemapped like the scene. Both are the same size.
+layout(binding = 0) uniform sampler2DArray eyes;
+
+layout(constant_id = 1) const bool ENCODE_SRGB = false;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+	vec3 color = aces(texelFetch(eyes, ivec3(gl_FragCoord.xy, gl_ViewIndex), 0).rgb);
+	outColor = vec4(ENCODE_SRGB ? encodeSrgb(color) : color, 1.0);
+}
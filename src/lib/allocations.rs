@@ -0,0 +1,114 @@
+use std::{collections::BTreeMap, fmt, panic::Location, sync::Mutex};
+
+use vulkanalia::{prelude::v1_0::*, vk::Handle};
+
+/// Every live allocation of every device, by device and memory handle.
+/// Global so that memory can be freed wherever the device is at hand.
+static ALLOCATIONS: Mutex<BTreeMap<(usize, u64), Allocation>> = Mutex::new(BTreeMap::new());
+
+/// What a device memory allocation backs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AllocationKind {
+    Buffer,
+    Image,
+}
+
+/// A live device memory allocation, see `App::allocations`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    /// Where the buffer or image was created, which tags it.
+    pub location: &'static Location<'static>,
+    pub kind: AllocationKind,
+    pub size: u64,
+}
+
+/// The live allocations of a device, largest first. Prints as a table with
+/// one line per place allocating, for finding what holds memory or was
+/// never freed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AllocationReport {
+    pub allocations: Vec<Allocation>,
+}
+
+impl AllocationReport {
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.allocations.iter().map(|a| a.size).sum()
+    }
+}
+
+impl fmt::Display for AllocationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut places: Vec<(&Location, AllocationKind, usize, u64)> = vec![];
+        for allocation in &self.allocations {
+            match places.iter_mut().find(|(l, k, ..)| *l == allocation.location && *k == allocation.kind) {
+                Some((.., count, size)) => {
+                    *count += 1;
+                    *size += allocation.size;
+                }
+                None => places.push((allocation.location, allocation.kind, 1, allocation.size)),
+            }
+        }
+        places.sort_by_key(|(.., size)| u64::MAX - size);
+
+        writeln!(f, "{} allocations, {} KiB:", self.allocations.len(), self.total_size() >> 10)?;
+        for (location, kind, count, size) in places {
+            writeln!(f, "  {:>10} KiB  {:>4} x {:<6?}  {}", size >> 10, count, kind, location)?;
+        }
+        Ok(())
+    }
+}
+
+fn key(device: &Device, memory: vk::DeviceMemory) -> (usize, u64) {
+    (device.handle().as_raw(), memory.as_raw())
+}
+
+/// Allocates memory for a buffer or image and tracks it until
+/// `free_memory`, tagged with the caller.
+#[track_caller]
+pub(crate) unsafe fn allocate_memory(
+    device: &Device,
+    info: &vk::MemoryAllocateInfo,
+    kind: AllocationKind,
+) -> VkResult<vk::DeviceMemory> {
+    let location = Location::caller();
+    let memory = device.allocate_memory(info, None)?;
+    let allocation = Allocation { location, kind, size: info.allocation_size };
+    ALLOCATIONS.lock().unwrap().insert(key(device, memory), allocation);
+    Ok(memory)
+}
+
+/// Frees memory from `allocate_memory`. Null handles are ignored, as
+/// Vulkan does.
+pub(crate) unsafe fn free_memory(device: &Device, memory: vk::DeviceMemory) {
+    if memory.is_null() {
+        return;
+    }
+    ALLOCATIONS.lock().unwrap().remove(&key(device, memory));
+    device.free_memory(memory, None);
+}
+
+/// The live allocations of `device`.
+pub(crate) fn allocation_report(device: &Device) -> AllocationReport {
+    let handle = device.handle().as_raw();
+    let mut allocations = ALLOCATIONS
+        .lock()
+        .unwrap()
+        .range((handle, 0)..=(handle, u64::MAX))
+        .map(|(_, a)| *a)
+        .collect::<Vec<_>>();
+    allocations.sort_by_key(|a| u64::MAX - a.size);
+    AllocationReport { allocations }
+}
+
+/// Forgets the allocations of `device`, which is about to be destroyed,
+/// returning those never freed.
+pub(crate) fn take_leaks(device: &Device) -> AllocationReport {
+    let report = allocation_report(device);
+    let handle = device.handle().as_raw();
+    ALLOCATIONS.lock().unwrap().retain(|(d, _), _| *d != handle);
+    report
+}
@@ -0,0 +1,401 @@
+use anyhow::Result;
+
+use cgmath::{Matrix, SquareMatrix};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    dynamic_buffer::{destroy_dynamic_buffer, update_dynamic_buffer, DynamicBuffer},
+    image::create_storage_images,
+    sampler::SamplerDesc,
+    shader::create_shader_module,
+    types::{Mat4, Vec4},
+};
+
+/// Occlusion, view depth and frames accumulated, as `ao.comp` writes them.
+const OCCLUSION_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// How the light probes' irradiance is occluded by nearby geometry.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AmbientOcclusion {
+    None,
+    /// A ray per pixel from a compute shader with ray queries, accumulated
+    /// over frames. Needs `App::ray_tracing` and `VK_KHR_ray_query`; there
+    /// is no occlusion otherwise.
+    RayQuery {
+        /// How far away geometry still occludes, in world units.
+        radius: f32,
+    },
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self::RayQuery { radius: 1.0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct OcclusionUniforms {
+    inverse_view_proj: Mat4,
+    previous_view_proj: Mat4,
+    /// With the radius in `w`.
+    camera: Vec4,
+    forward: Vec4,
+    frame: u32,
+    wide_indices: u32,
+    history_valid: u32,
+    _padding: u32,
+}
+
+/// What the scene samples ambient occlusion from, one per swapchain image.
+/// Traced at full resolution with ray queries, and otherwise a single
+/// unoccluded texel that is never read.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct OcclusionTarget {
+    image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    image_view: vk::ImageView,
+    sampler: vk::Sampler,
+    /// Null without ray queries.
+    set: vk::DescriptorSet,
+    uniforms: DynamicBuffer,
+}
+
+impl OcclusionTarget {
+    /// For binding 3 of the scene's set 0.
+    pub(crate) fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.image_view)
+            .sampler(self.sampler)
+            .build()
+    }
+
+    /// Records tracing occlusion for every pixel the camera sees through
+    /// `tlas`, blended with `data.occlusion_history` where it saw the same
+    /// surface through `previous_view_proj`, then copying the result back
+    /// into the history. To run before the scene pass samples it.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) unsafe fn record_trace(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        tlas: vk::AccelerationStructureKHR,
+        (view, proj): (Mat4, Mat4),
+        previous_view_proj: Option<Mat4>,
+        radius: f32,
+    ) {
+        let inverse_view = view.invert().unwrap();
+        let uniforms = OcclusionUniforms {
+            inverse_view_proj: (proj * view).invert().unwrap(),
+            previous_view_proj: previous_view_proj.unwrap_or(proj * view),
+            camera: inverse_view.w.truncate().extend(radius),
+            forward: -view.row(2).truncate().extend(0.0),
+            frame: data.frame_number as u32,
+            wide_indices: (data.index_type == vk::IndexType::UINT32) as u32,
+            history_valid: previous_view_proj.is_some() as u32,
+            _padding: 0,
+        };
+        update_dynamic_buffer(
+            instance,
+            device,
+            data,
+            &mut self.uniforms,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            &[uniforms],
+        )
+        .unwrap();
+
+        // The structure and the model's buffers are replaced as they change,
+        // so are written every frame while this image's set is not in use.
+        let structures = &[tlas];
+        let mut structure_info = vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+            .acceleration_structures(structures);
+        let mut structure_write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            .push_next(&mut structure_info)
+            .build();
+        structure_write.descriptor_count = 1;
+
+        let buffer_info = |buffer| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE as u64)
+                .build()]
+        };
+        let vertex_info = buffer_info(data.vertex_buffer);
+        let index_info = buffer_info(data.index_buffer);
+        let uniform_info = buffer_info(self.uniforms.buffer);
+        let buffer_write = |binding, descriptor_type, info| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(self.set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(descriptor_type)
+                .buffer_info(info)
+                .build()
+        };
+        let writes = [
+            structure_write,
+            buffer_write(1, vk::DescriptorType::STORAGE_BUFFER, &vertex_info),
+            buffer_write(2, vk::DescriptorType::STORAGE_BUFFER, &index_info),
+            buffer_write(3, vk::DescriptorType::UNIFORM_BUFFER, &uniform_info),
+        ];
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+        let history = data.occlusion_history.image;
+        barrier(
+            device,
+            command_buffer,
+            &[(self.image, vk::AccessFlags::SHADER_READ, vk::AccessFlags::SHADER_WRITE)],
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+        );
+        barrier(
+            device,
+            command_buffer,
+            &[(history, vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ)],
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+        );
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.occlusion_pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            data.occlusion_pipeline_layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        let extent = data.swapchain_extent;
+        device.cmd_dispatch(command_buffer, extent.width.div_ceil(8), extent.height.div_ceil(8), 1);
+
+        barrier(
+            device,
+            command_buffer,
+            &[
+                (self.image, vk::AccessFlags::SHADER_WRITE, vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::SHADER_READ),
+                (history, vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+            ],
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        );
+
+        let subresource = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+        let region = vk::ImageCopy::builder()
+            .src_subresource(subresource)
+            .dst_subresource(subresource)
+            .extent(vk::Extent3D { width: extent.width, height: extent.height, depth: 1 });
+        device.cmd_copy_image(
+            command_buffer,
+            self.image,
+            vk::ImageLayout::GENERAL,
+            history,
+            vk::ImageLayout::GENERAL,
+            &[region],
+        );
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_image_view(self.image_view, None);
+        device.destroy_image(self.image, None);
+        free_memory(device, self.image_memory);
+        destroy_dynamic_buffer(device, &mut self.uniforms);
+    }
+}
+
+/// Barriers between uses of images that stay in the general layout, each
+/// with its source and destination access.
+unsafe fn barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    images: &[(vk::Image, vk::AccessFlags, vk::AccessFlags)],
+    src_stage_mask: vk::PipelineStageFlags,
+    dst_stage_mask: vk::PipelineStageFlags,
+) {
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+    let barriers = images
+        .iter()
+        .map(|(image, src_access_mask, dst_access_mask)| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::GENERAL)
+                .new_layout(vk::ImageLayout::GENERAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(*image)
+                .subresource_range(subresource)
+                .src_access_mask(*src_access_mask)
+                .dst_access_mask(*dst_access_mask)
+        })
+        .collect::<Vec<_>>();
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage_mask,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &barriers,
+    );
+}
+
+/// Creates the compute pipeline that traces ambient occlusion. Does nothing
+/// without ray queries.
+pub(crate) unsafe fn create_occlusion_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.ray_query {
+        return Ok(());
+    }
+
+    let types = [
+        vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+        vk::DescriptorType::STORAGE_BUFFER,
+        vk::DescriptorType::STORAGE_BUFFER,
+        vk::DescriptorType::UNIFORM_BUFFER,
+        vk::DescriptorType::STORAGE_IMAGE,
+        vk::DescriptorType::STORAGE_IMAGE,
+    ];
+    let bindings = types
+        .iter()
+        .enumerate()
+        .map(|(binding, type_)| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding as u32)
+                .descriptor_type(*type_)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        })
+        .collect::<Vec<_>>();
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    data.occlusion_set_layout = data.set_layouts.get(device, &info).unwrap();
+
+    let set_layouts = &[data.occlusion_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    data.occlusion_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let comp = include_bytes!("../../shaders/ao_comp.spv");
+    let shader_module = create_shader_module(device, &comp[..]).unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(data.occlusion_pipeline_layout);
+
+    data.occlusion_pipeline = device
+        .create_compute_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(shader_module, None);
+    Ok(())
+}
+
+/// Creates an `OcclusionTarget` per swapchain image and the history they
+/// accumulate into, unoccluded until traced.
+pub(crate) unsafe fn create_occlusion_targets(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    data.occlusion_targets.clear();
+
+    let images = data.swapchain_images.len() as u32;
+    let mut sets = vec![vk::DescriptorSet::null(); images as usize];
+    let (width, height) = if data.ray_query {
+        let pool_sizes = [
+            (vk::DescriptorType::ACCELERATION_STRUCTURE_KHR, images),
+            (vk::DescriptorType::STORAGE_BUFFER, images * 2),
+            (vk::DescriptorType::UNIFORM_BUFFER, images),
+            (vk::DescriptorType::STORAGE_IMAGE, images * 2),
+        ]
+        .map(|(type_, descriptor_count)| {
+            vk::DescriptorPoolSize::builder()
+                .type_(type_)
+                .descriptor_count(descriptor_count)
+                .build()
+        });
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(images);
+        data.occlusion_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+        let layouts = vec![data.occlusion_set_layout; images as usize];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(data.occlusion_descriptor_pool)
+            .set_layouts(&layouts);
+        sets = device.allocate_descriptor_sets(&info).unwrap();
+
+        (data.swapchain_extent.width, data.swapchain_extent.height)
+    } else {
+        (1, 1)
+    };
+
+    let sampler = data.samplers.get(
+        device,
+        SamplerDesc {
+            wrap: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..SamplerDesc::nearest()
+        },
+    );
+
+    // The last one is the history, whose zero `w` marks it as never traced.
+    let unoccluded = vk::ClearColorValue { float32: [1.0, 0.0, 0.0, 0.0] };
+    let mut images =
+        create_storage_images(instance, device, data, sets.len() + 1, (width, height), OCCLUSION_FORMAT, unoccluded)
+            .unwrap();
+    let (image, image_memory, image_view) = images.pop().unwrap();
+    data.occlusion_history = OcclusionTarget { image, image_memory, image_view, sampler, ..Default::default() };
+
+    for (set, (image, image_memory, image_view)) in sets.into_iter().zip(images) {
+        if !set.is_null() {
+            let image_info = |image_view| {
+                [vk::DescriptorImageInfo::builder()
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .image_view(image_view)
+                    .build()]
+            };
+            let history_info = image_info(data.occlusion_history.image_view);
+            let target_info = image_info(image_view);
+            let image_write = |binding, info| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(binding)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                    .image_info(info)
+            };
+            device.update_descriptor_sets(
+                &[image_write(4, &history_info), image_write(5, &target_info)],
+                &[] as &[vk::CopyDescriptorSet],
+            );
+        }
+
+        data.occlusion_targets.push(OcclusionTarget {
+            image,
+            image_memory,
+            image_view,
+            sampler,
+            set,
+            uniforms: DynamicBuffer::default(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,266 @@
+use cgmath::{InnerSpace, VectorSpace};
+
+use crate::{
+    skinning::{JointTransform, Pose, Skeleton},
+    types::{Quat, Vec3},
+};
+
+/// How a channel moves between keyframes, as in glTF.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds each keyframe until the next.
+    Step,
+    /// Linear for translation and scale, spherical for rotation.
+    Linear,
+    /// Hermite splines. Each keyframe is stored as an in-tangent, the value
+    /// and an out-tangent.
+    CubicSpline,
+}
+
+/// The values of a channel, one per keyframe or three for cubic splines.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Keyframes {
+    Translation(Vec<Vec3>),
+    Rotation(Vec<Quat>),
+    Scale(Vec<Vec3>),
+}
+
+/// Animates one property of one joint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    /// An index into `Skeleton::joints`.
+    pub joint: usize,
+    /// Keyframe times in seconds, ascending.
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+/// Keyframed joint transforms for a skeleton.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    /// Seconds, the time of the last keyframe of any channel.
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: Option<String>, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|c| c.times.last())
+            .fold(0.0, |duration: f32, t| duration.max(*t));
+        Self { name, duration, channels }
+    }
+
+    /// Overwrites the joints and properties this clip animates with their
+    /// values at `time` seconds, clamped to the clip.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in &self.channels {
+            let Some(joint) = pose.joints.get_mut(channel.joint) else {
+                continue;
+            };
+            match &channel.keyframes {
+                Keyframes::Translation(values) => {
+                    if let Some(v) = channel.sample(time, values, lerp) {
+                        joint.translation = v;
+                    }
+                }
+                Keyframes::Rotation(values) => {
+                    if let Some(v) = channel.sample(time, values, Quat::slerp) {
+                        joint.rotation = v.normalize();
+                    }
+                }
+                Keyframes::Scale(values) => {
+                    if let Some(v) = channel.sample(time, values, lerp) {
+                        joint.scale = v;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Channel {
+    /// The value at `time`, blending neighbouring keyframes of `values` with
+    /// `mix` when linear.
+    fn sample<T>(&self, time: f32, values: &[T], mix: impl Fn(T, T, f32) -> T) -> Option<T>
+    where
+        T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f32, Output = T>,
+    {
+        let (first, last) = (*self.times.first()?, *self.times.last()?);
+        let stride = if self.interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+        let value = |k: usize| values.get(k * stride + stride / 2).copied();
+
+        if time <= first {
+            return value(0);
+        }
+        if time >= last {
+            return value(self.times.len() - 1);
+        }
+
+        let next = self.times.partition_point(|t| *t <= time);
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+        let t = if span > 0.0 { (time - self.times[previous]) / span } else { 0.0 };
+
+        match self.interpolation {
+            Interpolation::Step => value(previous),
+            Interpolation::Linear => Some(mix(value(previous)?, value(next)?, t)),
+            Interpolation::CubicSpline => {
+                let out_tangent = *values.get(previous * 3 + 2)?;
+                let in_tangent = *values.get(next * 3)?;
+                let (t2, t3) = (t * t, t * t * t);
+                Some(
+                    value(previous)? * (2.0 * t3 - 3.0 * t2 + 1.0)
+                        + out_tangent * ((t3 - 2.0 * t2 + t) * span)
+                        + value(next)? * (-2.0 * t3 + 3.0 * t2)
+                        + in_tangent * ((t3 - t2) * span),
+                )
+            }
+        }
+    }
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    a.lerp(b, t)
+}
+
+impl JointTransform {
+    /// Blends towards `other` by `t`, from 0 for `self` to 1 for `other`.
+    pub fn blend(&self, other: &JointTransform, t: f32) -> JointTransform {
+        JointTransform {
+            translation: self.translation.lerp(other.translation, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+        }
+    }
+}
+
+impl Pose {
+    /// Blends each joint towards `other` by `t`. Joints only one pose has
+    /// keep that pose's transform.
+    pub fn blend(&self, other: &Pose, t: f32) -> Pose {
+        let count = self.joints.len().max(other.joints.len());
+        let joints = (0..count)
+            .map(|i| match (self.joints.get(i), other.joints.get(i)) {
+                (Some(a), Some(b)) => a.blend(b, t),
+                (Some(j), None) | (None, Some(j)) => *j,
+                (None, None) => unreachable!(),
+            })
+            .collect();
+        Pose { joints }
+    }
+}
+
+/// A clip and how far into it playback is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Playing {
+    clip: usize,
+    time: f32,
+}
+
+/// Plays animation clips into one of `App::poses`, crossfading linearly
+/// between them. Advanced by `App` every frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationPlayer {
+    /// An index into `App::poses`, which is grown to fit.
+    pub pose: usize,
+    /// Playback rate, 1 for real time. Negative plays backwards.
+    pub speed: f32,
+    /// Whether clips wrap around at their ends, or hold the end frame.
+    pub looping: bool,
+    current: Playing,
+    /// The clip being faded out, and the fade's progress and length.
+    fading: Option<(Playing, f32, f32)>,
+}
+
+impl AnimationPlayer {
+    /// Plays clip 0 from the start, looping at normal speed.
+    pub fn new(pose: usize) -> Self {
+        Self {
+            pose,
+            speed: 1.0,
+            looping: true,
+            current: Playing { clip: 0, time: 0.0 },
+            fading: None,
+        }
+    }
+
+    /// An index into `App::clips`.
+    pub fn clip(&self) -> usize {
+        self.current.clip
+    }
+
+    /// Seconds into the current clip.
+    pub fn time(&self) -> f32 {
+        self.current.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.current.time = time;
+    }
+
+    /// Whether a crossfade is in progress.
+    pub fn fading(&self) -> bool {
+        self.fading.is_some()
+    }
+
+    /// Switches to `clip` from its start, cancelling any crossfade.
+    pub fn play(&mut self, clip: usize) {
+        self.current = Playing { clip, time: 0.0 };
+        self.fading = None;
+    }
+
+    /// Fades from the current pose to `clip` over `duration` seconds. Both
+    /// keep playing during the fade.
+    pub fn crossfade(&mut self, clip: usize, duration: f32) {
+        if duration <= 0.0 {
+            return self.play(clip);
+        }
+        self.fading = Some((self.current, 0.0, duration));
+        self.current = Playing { clip, time: 0.0 };
+    }
+
+    /// Moves playback on by `elapsed` seconds.
+    pub(crate) fn advance(&mut self, clips: &[AnimationClip], elapsed: f32) {
+        let step = elapsed * self.speed;
+        self.current.time = self.wrap(clips, self.current.clip, self.current.time + step);
+
+        if let Some((mut previous, progress, duration)) = self.fading {
+            let progress = progress + elapsed;
+            previous.time = self.wrap(clips, previous.clip, previous.time + step);
+            self.fading = (progress < duration).then_some((previous, progress, duration));
+        }
+    }
+
+    fn wrap(&self, clips: &[AnimationClip], clip: usize, time: f32) -> f32 {
+        let duration = clips.get(clip).map_or(0.0, |c| c.duration);
+        if duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        }
+    }
+
+    /// The blended pose at the current time. Joints no clip animates keep
+    /// their rest transform.
+    pub(crate) fn sample(&self, skeleton: &Skeleton, clips: &[AnimationClip]) -> Pose {
+        let sample = |playing: &Playing| {
+            let mut pose = skeleton.rest_pose();
+            if let Some(clip) = clips.get(playing.clip) {
+                clip.sample(playing.time, &mut pose);
+            }
+            pose
+        };
+
+        let pose = sample(&self.current);
+        match &self.fading {
+            Some((previous, progress, duration)) => sample(previous).blend(&pose, progress / duration),
+            None => pose,
+        }
+    }
+}
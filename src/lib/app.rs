@@ -1,45 +1,155 @@
 use anyhow::{anyhow, Result};
-use cgmath::{point3, vec3, Deg};
-use std::{mem::size_of, ptr::copy_nonoverlapping as memcpy, time::Instant};
-use winit::window::Window;
+use cgmath::{Deg, InnerSpace, SquareMatrix, Zero};
+use log::*;
+use std::{
+    ffi::CString,
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+use winit::window::{Window, WindowId};
 
 use vulkanalia::{
     loader::{LibloadingLoader, LIBRARY},
     prelude::v1_0::*,
-    vk::{ExtDebugUtilsExtension, KhrSurfaceExtension, KhrSwapchainExtension},
+    vk::{ExtDebugUtilsExtension, KhrPushDescriptorExtension, KhrSurfaceExtension, KhrSwapchainExtension},
     window as vk_window, Version,
 };
 
 use crate::{
-    command_buffer::{create_command_buffers, create_command_pools},
-    depth_object::create_depth_objects,
+    allocations::{allocation_report, free_memory, take_leaks, AllocationReport},
+    ambient_occlusion::{create_occlusion_pipeline, create_occlusion_targets, AmbientOcclusion, OcclusionTarget},
+    animation::{AnimationClip, AnimationPlayer},
+    asset_loader::{AssetCallbacks, AssetEvent, AssetLoader, LoadedAsset},
+    assets::{
+        create_mesh_asset, entity_bounds, entity_lod_count, entity_mesh, Assets, MeshHandle,
+        TextureAsset, TextureHandle,
+    },
+    barrier::{queue_submit, SemaphoreSubmit},
+    billboard::{billboard_vertices, create_billboard_pipeline, create_billboard_vertex_buffers, Billboard},
+    bindless::{create_texture_descriptor_set, create_texture_set_layout, texture_count, write_texture_table},
+    camera::{depth_terms, Camera},
+    color_grading::{create_color_lut, ColorLut, CubeLut},
+    command_buffer::{create_command_buffers, create_command_pools, resize_command_pools},
+    debug_draw::{create_debug_pipeline, create_debug_vertex_buffers, DebugDraw},
+    debug_view::DebugView,
+    device_features::{DeviceCapabilities, DeviceFeature, DeviceFeatures},
+    decal::{create_decal_descriptor_set, create_decal_pipeline, create_decal_set_layout, record_decals, Decal},
+    depth_object::{create_depth_objects, depth_planes},
+    descriptor_cache::{LayoutCache, SetCache},
     descriptor_layout::create_description_set_layout,
-    descriptor_pool::{create_descriptor_pool, create_descriptor_sets},
+    descriptor_pool::{create_descriptor_pool, create_descriptor_sets, write_texture_descriptors},
+    dynamic_buffer::{destroy_dynamic_buffer, update_dynamic_buffer, DynamicBuffer},
+    dynamic_rendering::{begin_pass, begin_secondary, end_pass, Pass},
+    entity::{demo_entities, demo_transform, demo_viewmodel_transform, Entity},
+    exposure::{create_hdr_metering_pipeline, create_metering, create_metering_pipeline, AutoExposure, Metering},
+    focus_blur::{
+        create_focus_blur_pipeline, create_focus_blur_set_layout, create_focus_blur_targets, FocusBlur,
+        FocusBlurPushConstants, FocusBlurTarget,
+    },
     framebuffer::create_framebuffers,
-    image::create_color_objects,
+    gpu_culling::{create_cull_pipeline, create_gpu_culling, create_indirect_pipeline, GpuCulling, GpuObject},
+    gpu_particles::{create_gpu_particle_pipeline, GpuParticleEmitter, GpuParticles},
+    histogram::{
+        create_histogram_buffers, create_histogram_descriptor_set, create_histogram_pipelines,
+        create_histogram_set_layout, record_histogram, ExposureReadback, HistogramPushConstants,
+    },
+    meshlet::{create_mesh_pipeline, create_meshlet_buffers, create_meshlet_set_layout, destroy_meshlet_buffers, Meshlets},
+    light_probe::LightProbes,
+    lod::{LevelOfDetail, Lod},
+    grid::{create_grid_pipeline, GridPushConstants},
+    image::{create_color_objects, create_image_view},
+    impostor::{create_impostor_texture, impostor_axes, Impostor},
     instance::create_instance,
     logical_device::create_logical_device,
+    mapped_memory::MappedMemory,
+    material::{
+        create_material_buffers, create_material_pipelines, write_material_blocks, Material, MaterialHandle,
+        MaterialInstance, MaterialParameters, Materials, Shading,
+    },
+    mipmap::create_mipmap_pipeline,
     model::load_model,
-    physical_device::pick_physical_device,
-    pipeline::create_pipeline,
-    render_pass::create_render_pass,
-    swapchain::{create_swapchain, create_swapchain_image_views},
-    sync_objects::create_sync_objects,
-    texture::{create_texture_image, create_texture_image_view, create_texture_sampler},
-    types::Mat4,
+    particles::{create_particle_pipeline, create_particle_vertex_buffers, sort_quads, ParticleEmitter},
+    paths::Directories,
+    physical_device::{pick_physical_device, GpuSelector, QueueFamilyIndices},
+    memory_budget::{get_heap_budgets, FrameStats, MEMORY_BUDGET_INTERVAL},
+    portability::PortabilitySubset,
+    reduction::create_reduction_pipelines,
+    secondary_window::SecondaryWindow,
+    self_test::{run_self_test, SelfTestReport},
+    pipeline::{create_pipeline, create_scene_pipeline, FragmentPushConstants, ScenePipelineDesc, SceneVariant},
+    oit::{create_oit_composite_pipeline, create_oit_descriptor_set, create_oit_objects, create_oit_set_layout, record_oit_composite},
+    openxr::{xr_stage, OpenXr, XrFrame},
+    pipeline_cache::{create_pipeline_cache, pipeline_cache_file, save_pipeline_cache},
+    pipeline_compiler::{AsyncPipeline, PipelineCompiler},
+    push_constants::PushConstants,
+    ray_tracing::{create_blas, create_scene_tlas, AccelerationStructure, RayTracingLimits, RayTracingPipeline, SceneTlas},
+    render_pass::{create_overlay_render_pass, create_render_pass},
+    sampler::{SamplerCache, SamplerDesc},
+    scene::{check_entity_count, Scene, SceneWatch},
+    scene_recorder::{bind_scene_descriptors, SceneRecorder},
+    settings::Settings,
+    shadows::{create_shadow_masks, create_shadow_pipeline, ShadowMask, Sun},
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    skinning::{create_joint_buffers, write_joint_matrices, Pose, Skeleton, MAX_JOINT_MATRICES},
+    sprite_batch::{
+        create_sprite_atlas, create_sprite_buffers, create_sprite_descriptor_pool,
+        create_sprite_pipeline, create_sprite_set_layout, SpriteAtlas, SpriteBatch, SpriteInstance,
+    },
+    static_batch::{create_static_batches, StaticBatches},
+    stereo::{
+        create_stereo_pipelines, create_stereo_render_pass, create_stereo_set_layout, create_stereo_target, Stereo,
+        StereoTarget,
+    },
+    streaming::{StreamedTexture, TextureStreaming},
+    swapchain::{create_swapchain, create_swapchain_image_views, SwapchainColorSpace},
+    sync_objects::{create_acquire_semaphores, create_present_semaphores, create_sync_objects, frame_fence, wait_for_frame},
+    texture::{
+        create_cubemap, create_texture_array, create_texture_image, create_texture_image_view,
+        create_texture, create_texture_sampler, load_texture, update_texture_array_layer, ColorSpace,
+        Cubemap, CubemapSource, LayeredTexture, TextureArray,
+    },
+    texture_atlas::{
+        atlas_texture, create_atlas_region_buffer, pack_textures, resolve_texture, write_atlas_regions,
+        AtlasRegion, ATLAS_PADDING, ATLAS_PAGE_SIZE, MAX_ATLAS_REGIONS,
+    },
+    tonemap::{
+        create_resolve_objects, create_tonemap_descriptor_set, create_tonemap_pipeline,
+        create_tonemap_set_layout, write_color_lut_descriptor, ResolveMode, TonemapPushConstants,
+    },
+    types::{Mat4, Vec2, Vec4},
     uniform_buffer::{create_uniform_buffers, UniformBufferObject},
     vertex::Vertex,
     vertex_buffer::{create_index_buffer, create_vertex_buffer},
+    viewmodel::{CameraLayer, Viewmodel, ViewmodelDepth},
+    viewport::{record_viewports, uniform_stride, viewports, Viewport, MAX_VIEWPORTS},
+    visibility::{compute_visibility, Bounds, EntityVisibility, Frustum, VisibilityCallbacks},
+    window_mode::{apply_window_mode, WindowMode},
+    world::WorldConfig,
 };
 
 pub(crate) const PORTABILITY_MACOS_VERSION: Version = Version::new(1, 3, 216);
+pub(crate) const VULKAN_1_1: Version = Version::new(1, 1, 0);
+pub(crate) const VULKAN_1_2: Version = Version::new(1, 2, 0);
+pub(crate) const VULKAN_1_3: Version = Version::new(1, 3, 0);
 pub(crate) const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
 pub(crate) const VALIDATION_LAYER: vk::ExtensionName =
     vk::ExtensionName::from_bytes(b"VK_LAYER_KHRONOS_validation");
 pub(crate) const DEVICE_EXTENSIONS: &[vk::ExtensionName] = &[vk::KHR_SWAPCHAIN_EXTENSION.name];
-pub(crate) const MAX_FRAMES_IN_FLIGHT: usize = 2;
-
-#[derive(Clone, Debug)]
+pub(crate) const MAX_FRAMES_IN_FLIGHT: usize = 3;
+pub(crate) const NEAR_PLANE: f32 = 0.1;
+pub(crate) const FAR_PLANE: f32 = 10.0;
+
+/// The renderer: a Vulkan device, the main window's swapchain, and the scene
+/// drawn to it.
+///
+/// # Safety
+///
+/// Its unsafe methods record and submit Vulkan work on the device it owns.
+/// Call them from one thread at a time, never after `destroy`, and keep the
+/// windows they were given alive until they are closed or the app destroyed.
+#[derive(Debug)]
 pub struct App {
     _entry: Entry,
     instance: Instance,
@@ -49,37 +159,319 @@ pub struct App {
     pub resized: bool,
     pub start: Instant,
     pub models: usize,
+    pub debug_draw: DebugDraw,
+    pub show_grid: bool,
+    pub sprite_batch: SpriteBatch,
+    /// Physical pixels per `sprite_batch` pixel, or `None` to follow the
+    /// window's scale factor so overlays keep their size on HiDPI displays.
+    /// The scene is always rendered at the swapchain's physical resolution;
+    /// `Some(1.0)` draws overlays at it too.
+    pub ui_scale: Option<f32>,
+    /// When set, the scene's secondary command buffers are recorded once per
+    /// swapchain image and reused until the scene or swapchain changes, so
+    /// model transforms are frozen at the time they were recorded.
+    pub static_scene: bool,
+    /// Draws the scene with `polygon_mode = LINE`. Ignored if the device does
+    /// not support `fillModeNonSolid`.
+    pub wireframe: bool,
+    pub debug_view: DebugView,
+    /// Takes effect on the next frame, which rebuilds the render pass.
+    pub resolve_mode: ResolveMode,
+    /// Whether to present through an `_SRGB` swapchain format, which encodes
+    /// on write, or a UNORM one the tonemap pass encodes by hand. Both should
+    /// look the same; takes effect on the next frame.
+    pub srgb_swapchain: bool,
+    /// Maps the far plane to depth 0 and the near plane to 1, which spreads
+    /// floating point depth precision evenly over distance and stops distant
+    /// surfaces z-fighting. Takes effect on the next frame, which rebuilds
+    /// the pipelines.
+    pub reverse_z: bool,
+    /// Draws transparent render queues with weighted blended
+    /// order-independent transparency instead of blending them in draw
+    /// order, so intersecting and unsorted surfaces blend plausibly. Takes
+    /// effect on the next frame, which rebuilds the render pass.
+    pub order_independent_transparency: bool,
+    /// How many frames may be queued on the GPU at once, from 1 to
+    /// `MAX_FRAMES_IN_FLIGHT`. Fewer lowers input latency, more smooths out
+    /// uneven frame times. Takes effect on the next frame.
+    pub frames_in_flight: usize,
+    /// The fewest swapchain images to ask for: 2 for double buffering, 3 for
+    /// triple buffering, or 0 for one more than the surface needs. Clamped to
+    /// what the surface allows; takes effect on the next frame.
+    pub min_swapchain_images: u32,
+    /// The color space to present in where the display offers it. Takes
+    /// effect on the next frame, see `presented_color_space` for the one
+    /// used.
+    pub swapchain_color_space: SwapchainColorSpace,
+    /// Switches the main window between windowed, borderless and exclusive
+    /// fullscreen on the next frame, which recreates the swapchain.
+    pub window_mode: WindowMode,
+    /// The resolution to switch the monitor to in exclusive fullscreen, or
+    /// `None` to keep the current one. The closest the monitor supports is
+    /// used.
+    pub fullscreen_resolution: Option<[u32; 2]>,
+    pub directories: Directories,
+    /// The first `models` entries are drawn.
+    pub entities: Vec<Entity>,
+    /// Spins the dynamic world entities about their demo positions every
+    /// frame. Cleared by `load_scene`.
+    pub demo_animation: bool,
+    /// Ambient lighting for entities. Call `invalidate_scene` after changing
+    /// it so static batches pick it up.
+    pub light_probes: LightProbes,
+    pub auto_exposure: AutoExposure,
+    pub texture_streaming: TextureStreaming,
+    pub level_of_detail: LevelOfDetail,
+    pub focus_blur: FocusBlur,
+    /// The camera for entities in `CameraLayer::Viewmodel`.
+    pub viewmodel: Viewmodel,
+    /// Poses for skinned entities, see `Entity::pose`. Start from
+    /// `Skeleton::rest_pose`.
+    pub poses: Vec<Pose>,
+    /// Each plays clips from `clips` into its pose every frame.
+    pub animations: Vec<AnimationPlayer>,
+    /// Updated and drawn every frame.
+    pub particles: Vec<ParticleEmitter>,
+    /// Drawn every frame, after the opaque scene.
+    pub billboards: Vec<Billboard>,
+    /// Projected onto the scene every frame.
+    pub decals: Vec<Decal>,
+    /// Simulated on the GPU every frame. Takes effect on the next frame.
+    pub gpu_particles: Option<GpuParticleEmitter>,
+    /// Culls and draws dynamic, opaque world entities in a compute pass
+    /// instead of recording a draw for each. Ignored without
+    /// `VK_KHR_draw_indirect_count` and `multiDrawIndirect`, and in the debug
+    /// pipeline variants.
+    pub gpu_culling: bool,
+    /// Draws world layer entities as meshlets with task and mesh shaders,
+    /// which cull them in finer pieces. Ignored without `VK_EXT_mesh_shader`,
+    /// in the debug pipeline variants and for skinned models.
+    pub mesh_shading: bool,
+    /// Draws the depth of opaque world entities in a pass of their own before
+    /// shading them, so that each pixel is shaded once however many of them
+    /// cover it. Pays off when fragments are expensive. Entities culled on
+    /// the GPU or drawn as meshlets skip the pre-pass.
+    pub depth_prepass: bool,
+    /// Rebuilds a top level acceleration structure of the entities every
+    /// frame, for ray traced effects. Ignored unless built with the
+    /// `ray-tracing` feature on a device that supports it.
+    pub ray_tracing: bool,
+    /// Lights the world in addition to the light probes.
+    pub sun: Option<Sun>,
+    /// Also draws the world for a pair of eyes, shown side by side. Ignored
+    /// without multiview support.
+    pub stereo: Option<Stereo>,
+    /// Connects to the OpenXR runtime on the next launch, see
+    /// `Settings::openxr`.
+    pub openxr: bool,
+    /// Shadows the sun with a ray per pixel, traced every frame. Needs
+    /// `ray_tracing`; the sun is unshadowed otherwise.
+    pub ray_traced_shadows: bool,
+    /// Occludes the light probes. Takes effect on the next frame.
+    pub ambient_occlusion: AmbientOcclusion,
+    /// The `.cube` file set with `set_color_grading`.
+    color_grading: Option<PathBuf>,
+    /// The model of the last scene loaded with `load_scene`.
+    scene_model: Option<PathBuf>,
+    /// The textures of the last scene loaded with `load_scene` and the slots
+    /// they were loaded into.
+    scene_textures: Vec<(PathBuf, u32)>,
+    /// The file set with `watch_scene`.
+    scene_watch: Option<SceneWatch>,
+    /// Registered with `add_camera`. Never empty.
+    cameras: Vec<Camera>,
+    /// Index into `cameras` of the camera the window is drawn with.
+    active_camera: usize,
+    exposure: f32,
+    /// Whether the last frame adapted the exposure on the GPU, where it then
+    /// carries over to the next.
+    adapting_on_gpu: bool,
+    /// The camera the last occlusion was traced from, for reprojecting its
+    /// history. Unset when there is none to reproject.
+    occlusion_view_proj: Option<Mat4>,
+    scene_luminance: Option<f32>,
+    /// Seconds since `start` at the last metering readback.
+    metered_at: f32,
+    ui_focus: bool,
+    /// How far the focus blur has faded in, from 0 to 1.
+    focus_amount: f32,
+    /// Seconds since `start` when `focus_amount` was last advanced.
+    focus_updated_at: f32,
+    /// Seconds since `start` when `animations` were last advanced.
+    animated_at: f32,
+    /// Seconds since `start` when `particles` were last updated.
+    particles_updated_at: f32,
+    /// Seconds between the last two particle updates.
+    particle_step: f32,
+    frame_stats: FrameStats,
+    /// Seconds since `start` when the heap budgets were last queried, or
+    /// `None` before the first query.
+    budget_checked_at: Option<f32>,
+    visibility: Vec<EntityVisibility>,
+    visibility_callbacks: VisibilityCallbacks,
+    /// Where each entity's joint matrices start this frame.
+    joint_offsets: Vec<u32>,
+    /// The level of detail each entity is drawn with, kept between frames
+    /// for `LevelOfDetail::hysteresis`.
+    lod_levels: Vec<usize>,
+    /// Baked by `bake_impostor`.
+    impostor: Option<Impostor>,
+    /// Bit `i` is set if entity `i` is drawn as `impostor`.
+    impostors: u64,
+    pipeline_compiler: PipelineCompiler,
+    /// Shows the stereo target in a headset while its session runs.
+    xr: Option<OpenXr>,
+    /// The OpenXR frame being drawn, between `render` waiting for it and
+    /// submitting it.
+    xr_frame: Option<XrFrame>,
+    /// Opened with `open_window`.
+    windows: Vec<SecondaryWindow>,
+    asset_loader: AssetLoader,
+    asset_callbacks: AssetCallbacks,
 }
 
+#[allow(clippy::missing_safety_doc)] // See `App`.
 impl App {
-    pub unsafe fn create(window: &Window) -> Result<Self> {
+    /// Creates the renderer with `world` as the convention for entity
+    /// transforms and imported assets.
+    pub unsafe fn create(window: &Window, world: WorldConfig) -> Result<Self> {
         let loader = LibloadingLoader::new(LIBRARY).unwrap();
         let _entry = Entry::new(loader).map_err(|b| anyhow!("{}", b)).unwrap();
-        let mut data = AppData::default();
-        let instance = create_instance(window, &_entry, &mut data).unwrap();
+        let mut data = AppData {
+            world,
+            ..Default::default()
+        };
+        let directories = Directories::resolve().unwrap_or_else(|e| {
+            warn!("{} Falling back to the working directory.", e);
+            Directories::with_root(".")
+        });
+        if let Err(e) = directories.create_all() {
+            warn!("Failed to create app directories: {}", e);
+        }
+        let settings = Settings::load(&directories.settings_file()).unwrap_or_else(|e| {
+            warn!("Failed to load settings: {}", e);
+            Settings::default()
+        });
+        data.scale_factor = window.scale_factor();
+        data.resolve_mode = settings.resolve_mode;
+        data.srgb_swapchain = settings.srgb_swapchain;
+        data.reverse_z = settings.reverse_z;
+        data.order_independent_transparency = settings.order_independent_transparency;
+        data.frames_in_flight = settings.frames_in_flight;
+        data.min_swapchain_images = settings.min_swapchain_images;
+        data.preferred_color_space = settings.swapchain_color_space;
+        // The runtime has a say in each stage of Vulkan setup, and OpenXR is
+        // given up on at the first it cannot be satisfied in.
+        let mut xr = None;
+        if settings.openxr {
+            match OpenXr::load() {
+                Ok(runtime) => xr = Some(runtime),
+                Err(e) => warn!("Continuing without OpenXR: {}", e),
+            }
+        }
+        data.xr_instance_extensions = xr_stage(&mut xr, None, |xr| xr.instance_extensions()).unwrap_or_default();
+        let instance = create_instance(Some(window), &_entry, &mut data).unwrap();
         data.surface = vk_window::create_surface(&instance, &window, &window).unwrap();
+        data.xr_physical_device =
+            xr_stage(&mut xr, None, |xr| xr.physical_device(&instance, &data)).unwrap_or_default();
+        data.gpu_selector = GpuSelector::from_env();
         pick_physical_device(&instance, &mut data).unwrap();
-        let device = create_logical_device(&_entry, &instance, &mut data).unwrap();
+        data.xr_device_extensions = xr_stage(&mut xr, None, |xr| {
+            if !data.multiview {
+                return Err(anyhow!("The device does not support multiview."));
+            }
+            xr.device_extensions()
+        })
+        .unwrap_or_default();
+        data.samplers = SamplerCache::new(&instance, &data);
+        let device = create_logical_device(&instance, &mut data).unwrap();
+        xr_stage(&mut xr, Some(&device), |xr| xr.create_session(&instance, &device, &mut data));
+        let pipeline_cache_file = pipeline_cache_file(&instance, &data, &directories);
+        create_pipeline_cache(&instance, &device, &mut data, &pipeline_cache_file).unwrap();
         create_swapchain(window, &instance, &device, &mut data).unwrap();
         create_swapchain_image_views(&device, &mut data).unwrap();
-        create_render_pass(&instance, &device, &mut data).unwrap();
+        create_render_pass(&device, &mut data).unwrap();
+        create_overlay_render_pass(&device, &mut data).unwrap();
+        create_stereo_render_pass(&device, &mut data).unwrap();
         create_description_set_layout(&device, &mut data).unwrap();
+        create_texture_set_layout(&device, &mut data).unwrap();
+        create_meshlet_set_layout(&device, &mut data).unwrap();
+        create_sprite_set_layout(&device, &mut data).unwrap();
+        create_tonemap_set_layout(&device, &mut data).unwrap();
+        create_histogram_set_layout(&device, &mut data).unwrap();
+        create_oit_set_layout(&device, &mut data).unwrap();
+        create_decal_set_layout(&device, &mut data).unwrap();
+        create_focus_blur_set_layout(&device, &mut data).unwrap();
+        create_stereo_set_layout(&device, &mut data).unwrap();
+        xr_stage(&mut xr, Some(&device), |xr| xr.create_pipeline(&device, &data));
         create_pipeline(&device, &mut data).unwrap();
+        create_debug_pipeline(&device, &mut data).unwrap();
+        create_particle_pipeline(&device, &mut data).unwrap();
+        create_billboard_pipeline(&device, &mut data).unwrap();
+        create_grid_pipeline(&device, &mut data).unwrap();
+        create_sprite_pipeline(&device, &mut data).unwrap();
+        create_tonemap_pipeline(&device, &mut data).unwrap();
+        create_oit_composite_pipeline(&device, &mut data).unwrap();
+        create_decal_pipeline(&device, &mut data).unwrap();
+        create_focus_blur_pipeline(&device, &mut data).unwrap();
+        create_stereo_pipelines(&device, &mut data).unwrap();
+        create_reduction_pipelines(&device, &mut data).unwrap();
+        create_mipmap_pipeline(&device, &mut data).unwrap();
+        create_metering_pipeline(&device, &mut data).unwrap();
+        create_histogram_pipelines(&device, &mut data).unwrap();
+        create_hdr_metering_pipeline(&device, &mut data).unwrap();
+        create_gpu_particle_pipeline(&device, &mut data).unwrap();
+        create_cull_pipeline(&device, &mut data).unwrap();
+        create_indirect_pipeline(&device, &mut data).unwrap();
+        create_mesh_pipeline(&device, &mut data).unwrap();
+        create_shadow_pipeline(&instance, &device, &mut data).unwrap();
+        create_occlusion_pipeline(&device, &mut data).unwrap();
         create_command_pools(&instance, &device, &mut data).unwrap();
         create_color_objects(&instance, &device, &mut data).unwrap();
+        create_resolve_objects(&instance, &device, &mut data).unwrap();
         create_depth_objects(&instance, &device, &mut data).unwrap();
+        create_oit_objects(&instance, &device, &mut data).unwrap();
         create_framebuffers(&device, &mut data).unwrap();
+        data.color_lut = create_color_lut(&instance, &device, &mut data, &CubeLut::identity()).unwrap();
+        create_histogram_buffers(&instance, &device, &mut data).unwrap();
+        create_tonemap_descriptor_set(&device, &mut data).unwrap();
+        create_histogram_descriptor_set(&instance, &device, &mut data).unwrap();
+        create_oit_descriptor_set(&device, &mut data).unwrap();
+        create_decal_descriptor_set(&device, &mut data).unwrap();
         create_texture_image(&instance, &device, &mut data).unwrap();
         create_texture_image_view(&device, &mut data).unwrap();
         create_texture_sampler(&device, &mut data).unwrap();
+        create_atlas_region_buffer(&instance, &device, &mut data).unwrap();
+        create_texture_descriptor_set(&device, &mut data).unwrap();
+        create_sprite_descriptor_pool(&mut data).unwrap();
+        let (texture_image_view, texture_sampler) = (data.texture_image_view, data.texture_sampler);
+        create_sprite_atlas(&device, &mut data, texture_image_view, texture_sampler).unwrap();
         load_model(&mut data).unwrap();
         create_vertex_buffer(&instance, &device, &mut data).unwrap();
         create_index_buffer(&instance, &device, &mut data).unwrap();
+        create_meshlet_buffers(&instance, &device, &mut data).unwrap();
+        create_blas(&instance, &device, &mut data).unwrap();
         create_uniform_buffers(&instance, &device, &mut data).unwrap();
-        create_descriptor_pool(&device, &mut data).unwrap();
+        create_joint_buffers(&instance, &device, &mut data).unwrap();
+        create_material_buffers(&instance, &device, &mut data).unwrap();
+        create_debug_vertex_buffers(&mut data).unwrap();
+        create_particle_vertex_buffers(&mut data).unwrap();
+        create_billboard_vertex_buffers(&mut data).unwrap();
+        create_sprite_buffers(&mut data).unwrap();
+        create_shadow_masks(&instance, &device, &mut data).unwrap();
+        create_occlusion_targets(&instance, &device, &mut data).unwrap();
+        create_descriptor_pool(&mut data).unwrap();
         create_descriptor_sets(&device, &mut data).unwrap();
+        create_metering(&instance, &device, &mut data).unwrap();
+        create_focus_blur_targets(&instance, &device, &mut data).unwrap();
+        create_stereo_target(&instance, &device, &mut data).unwrap();
+        create_gpu_culling(&instance, &device, &mut data).unwrap();
+        create_scene_tlas(&mut data).unwrap();
         create_command_buffers(&device, &mut data).unwrap();
         create_sync_objects(&device, &mut data).unwrap();
+        create_present_semaphores(&device, &mut data).unwrap();
+        let entities = demo_entities(&data.world);
+        let pipeline_compiler = PipelineCompiler::new(&device);
         Ok(Self {
             _entry,
             instance,
@@ -88,15 +480,1475 @@ impl App {
             frame: 0,
             resized: false,
             start: Instant::now(),
-            models: 4,
+            models: settings.models.min(entities.len()),
+            debug_draw: DebugDraw::default(),
+            show_grid: settings.show_grid,
+            sprite_batch: SpriteBatch::default(),
+            ui_scale: None,
+            static_scene: settings.static_scene,
+            wireframe: settings.wireframe,
+            debug_view: settings.debug_view,
+            resolve_mode: settings.resolve_mode,
+            srgb_swapchain: settings.srgb_swapchain,
+            reverse_z: settings.reverse_z,
+            order_independent_transparency: settings.order_independent_transparency,
+            frames_in_flight: settings.frames_in_flight,
+            min_swapchain_images: settings.min_swapchain_images,
+            swapchain_color_space: settings.swapchain_color_space,
+            window_mode: settings.window_mode,
+            fullscreen_resolution: None,
+            openxr: settings.openxr,
+            directories,
+            entities,
+            demo_animation: true,
+            light_probes: LightProbes::None,
+            auto_exposure: AutoExposure::default(),
+            texture_streaming: TextureStreaming::default(),
+            level_of_detail: LevelOfDetail::default(),
+            focus_blur: FocusBlur::default(),
+            viewmodel: Viewmodel::default(),
+            poses: vec![],
+            animations: vec![],
+            particles: vec![],
+            billboards: vec![],
+            decals: vec![],
+            gpu_particles: None,
+            gpu_culling: true,
+            mesh_shading: true,
+            depth_prepass: false,
+            ray_tracing: true,
+            sun: None,
+            stereo: None,
+            xr,
+            xr_frame: None,
+            windows: vec![],
+            ray_traced_shadows: true,
+            ambient_occlusion: AmbientOcclusion::default(),
+            color_grading: None,
+            scene_model: None,
+            scene_textures: vec![],
+            scene_watch: None,
+            cameras: vec![Camera::initial(&world)],
+            active_camera: 0,
+            exposure: 1.0,
+            adapting_on_gpu: false,
+            occlusion_view_proj: None,
+            scene_luminance: None,
+            metered_at: 0.0,
+            ui_focus: false,
+            focus_amount: 0.0,
+            focus_updated_at: 0.0,
+            animated_at: 0.0,
+            particles_updated_at: 0.0,
+            particle_step: 0.0,
+            frame_stats: FrameStats::default(),
+            budget_checked_at: None,
+            visibility: vec![],
+            visibility_callbacks: VisibilityCallbacks::default(),
+            joint_offsets: vec![],
+            lod_levels: vec![],
+            impostor: None,
+            impostors: 0,
+            pipeline_compiler,
+            asset_loader: AssetLoader::new(),
+            asset_callbacks: AssetCallbacks::default(),
         })
     }
 
+    pub fn settings(&self) -> Settings {
+        Settings {
+            models: self.models,
+            show_grid: self.show_grid,
+            static_scene: self.static_scene,
+            wireframe: self.wireframe,
+            debug_view: self.debug_view,
+            resolve_mode: self.resolve_mode,
+            srgb_swapchain: self.srgb_swapchain,
+            reverse_z: self.reverse_z,
+            order_independent_transparency: self.order_independent_transparency,
+            frames_in_flight: self.frames_in_flight,
+            min_swapchain_images: self.min_swapchain_images,
+            swapchain_color_space: self.swapchain_color_space,
+            window_mode: self.window_mode,
+            openxr: self.openxr,
+        }
+    }
+
+    /// Opens another window onto the scene, which shows the main window's
+    /// final image scaled to fit. It shares the device and everything drawn,
+    /// and presents in the same submit.
+    pub unsafe fn open_window(&mut self, window: Arc<Window>) -> Result<()> {
+        let window = SecondaryWindow::create(&self.instance, &self.device, &self.data, window)?;
+        info!("Opened window {:?}.", window.id());
+        self.windows.push(window);
+        Ok(())
+    }
+
+    /// Closes a window opened with `open_window`.
+    pub unsafe fn close_window(&mut self, id: WindowId) {
+        if let Some(index) = self.windows.iter().position(|w| w.id() == id) {
+            self.device.device_wait_idle().unwrap();
+            self.windows.remove(index).destroy(&self.instance, &self.device);
+        }
+    }
+
+    /// Recreates the swapchain of a window opened with `open_window` before
+    /// its next frame, as `resized` does for the main window.
+    pub fn window_resized(&mut self, id: WindowId) {
+        if let Some(window) = self.windows.iter_mut().find(|w| w.id() == id) {
+            window.resized = true;
+        }
+    }
+
+    /// Records the main window's new scale factor, from winit's
+    /// `ScaleFactorChanged`, and recreates the swapchain for the size the
+    /// window is given with it.
+    pub fn scale_factor_changed(&mut self, scale_factor: f64) {
+        info!("Scale factor changed to {}.", scale_factor);
+        self.data.scale_factor = scale_factor;
+        self.resized = true;
+    }
+
+    /// Releases the main window's surface and everything sized to its
+    /// swapchain, for winit's `Suspended`, after which Android destroys the
+    /// native window. The device and everything loaded on it are kept, and
+    /// frames are skipped until `resume`.
+    pub unsafe fn suspend(&mut self) {
+        if self.data.suspended {
+            return;
+        }
+
+        self.device.device_wait_idle().unwrap();
+        self.destroy_swapchain();
+        self.instance.destroy_surface_khr(self.data.surface, None);
+        self.data.surface = vk::SurfaceKHR::null();
+        self.data.suspended = true;
+        info!("Suspended, surface released.");
+    }
+
+    /// Recreates the surface on `window`, the native window winit's
+    /// `Resumed` comes with, and the swapchain on it. Does nothing unless
+    /// suspended, so the `Resumed` desktop platforms send at startup is
+    /// harmless.
+    pub unsafe fn resume(&mut self, window: &Window) -> Result<()> {
+        if !self.data.suspended {
+            return Ok(());
+        }
+
+        let surface = vk_window::create_surface(&self.instance, window, window)?;
+        let indices = QueueFamilyIndices::get(&self.instance, &self.data, self.data.physical_device)?;
+        if !self
+            .instance
+            .get_physical_device_surface_support_khr(self.data.physical_device, indices.present, surface)?
+        {
+            self.instance.destroy_surface_khr(surface, None);
+            return Err(anyhow!("The present queue cannot present to the resumed window."));
+        }
+
+        self.data.surface = surface;
+        self.data.suspended = false;
+        self.resized = false;
+        info!("Resumed.");
+        self.create_swapchain_objects(window)
+    }
+
+    /// Physical pixels per logical pixel of the main window.
+    pub fn scale_factor(&self) -> f64 {
+        self.data.scale_factor
+    }
+
+    /// Physical pixels per `sprite_batch` pixel, see `ui_scale`.
+    pub fn effective_ui_scale(&self) -> f32 {
+        self.ui_scale.unwrap_or(self.data.scale_factor as f32).max(f32::EPSILON)
+    }
+
+    /// The size of the main window in `sprite_batch` pixels, for laying out
+    /// overlays.
+    pub fn ui_size(&self) -> Vec2 {
+        let extent = self.data.swapchain_extent;
+        Vec2::new(extent.width as f32, extent.height as f32) / self.effective_ui_scale()
+    }
+
+    pub fn save_settings(&self) -> Result<()> {
+        self.settings().save(&self.directories.settings_file())
+    }
+
+    /// Registers `camera` and returns its index, for `set_active_camera` and
+    /// `Viewport::camera`.
+    pub fn add_camera(&mut self, camera: Camera) -> usize {
+        self.cameras.push(camera);
+        self.cameras.len() - 1
+    }
+
+    /// The registered cameras, starting with the one the app was created
+    /// with.
+    pub fn cameras(&self) -> &[Camera] {
+        &self.cameras
+    }
+
+    pub fn camera_mut(&mut self, index: usize) -> Option<&mut Camera> {
+        self.cameras.get_mut(index)
+    }
+
+    /// The index of the camera the window is drawn with.
+    pub fn active_camera(&self) -> usize {
+        self.active_camera
+    }
+
+    /// Draws the window with camera `index` from the next frame. Culling,
+    /// shadows and the eyes follow it.
+    pub fn set_active_camera(&mut self, index: usize) -> Result<()> {
+        if index >= self.cameras.len() {
+            return Err(anyhow!("No camera {} of {}.", index, self.cameras.len()));
+        }
+        self.active_camera = index;
+        Ok(())
+    }
+
+    /// Divides the window into `viewports`, each drawing the scene, for
+    /// split-screen and multi-view layouts. At most `MAX_VIEWPORTS` are
+    /// used, and none means the whole window.
+    pub fn set_viewports(&mut self, viewports: &[Viewport]) {
+        if viewports.len() > MAX_VIEWPORTS {
+            warn!("Only the first {} of {} viewports are drawn.", MAX_VIEWPORTS, viewports.len());
+        }
+        self.data.viewports = viewports.iter().take(MAX_VIEWPORTS).copied().collect();
+        // Cached scene command buffers draw each viewport.
+        self.invalidate_scene();
+    }
+
+    /// The viewports set with `set_viewports`.
+    pub fn viewports(&self) -> &[Viewport] {
+        &self.data.viewports
+    }
+
+    /// Forces the cached scene command buffers to be re-recorded. Call this
+    /// after moving a static entity.
+    pub fn invalidate_scene(&mut self) {
+        self.data.recorded_scenes.iter_mut().for_each(|s| *s = None);
+        self.data.recorded_static_batches.iter_mut().for_each(|s| *s = None);
+    }
+
+    /// Merges the static entities that would be batched, those in the
+    /// world layer's opaque queues, into combined vertex and index buffers
+    /// with one draw per draw order, texture and opacity, replacing earlier
+    /// batches. Each draws its entities' full level of detail and is lit by
+    /// the light probes at its center. Call again after moving or changing
+    /// a baked entity; batches with an entity that is no longer static are
+    /// left out, and their entities drawn one by one. Skinned models are not
+    /// baked.
+    pub unsafe fn bake_static_batches(&mut self) -> Result<()> {
+        let candidates = if self.data.skeleton.is_some() {
+            0
+        } else {
+            self.static_mask() & !self.mesh_mask() & !self.material_mask()
+        };
+        self.device.device_wait_idle()?;
+        create_static_batches(&self.instance, &self.device, &mut self.data, &self.entities, candidates)?;
+        self.invalidate_scene();
+        Ok(())
+    }
+
+    /// The batch `entity` was merged into by `bake_static_batches`.
+    pub fn static_batch(&self, entity: usize) -> Option<usize> {
+        self.data.static_batches.batch_of(entity)
+    }
+
+    /// The entities drawn from the baked batches this frame.
+    fn batched_mask(&self, statics: u64) -> u64 {
+        let baked = &self.data.static_batches;
+        baked.current(statics).iter().fold(0, |mask, b| mask | baked.batches[*b].entities)
+    }
+
+    /// The weighted log-average luminance of the most recently metered frame,
+    /// before exposure was applied. `None` until the first readback, or if
+    /// the swapchain cannot be metered.
+    pub fn scene_luminance(&self) -> Option<f32> {
+        self.scene_luminance
+    }
+
+    /// The exposure the scene is currently rendered with. Read back a frame
+    /// or two late while it is adapted on the GPU.
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Fades the focus blur in behind modal UI, or back out.
+    pub fn set_ui_focus(&mut self, focused: bool) {
+        self.ui_focus = focused;
+    }
+
+    /// Whether modal UI has focus, even while the blur is still fading.
+    pub fn ui_focus(&self) -> bool {
+        self.ui_focus
+    }
+
+    /// This frame's culling results for the drawn entities.
+    pub fn visibility(&self) -> &[EntityVisibility] {
+        &self.visibility
+    }
+
+    /// Registers a callback that receives the culling results every frame, so
+    /// systems like audio attenuation or AI level of detail can reuse them.
+    pub fn on_visibility(&mut self, callback: impl FnMut(&[EntityVisibility]) + Send + 'static) {
+        self.visibility_callbacks.0.push(Box::new(callback));
+    }
+
+    /// Culls the drawn entities and reports the results to the callbacks.
+    fn update_visibility(&mut self) {
+        let (view, proj) = self.camera();
+        // Anything another viewport's camera sees is drawn too.
+        let others = viewports(&self.data)
+            .iter()
+            .filter(|v| self.shows_other_camera(v))
+            .map(|v| {
+                let (view, proj) = self.camera_matrices(self.viewport_camera(v));
+                (view, v.aspect_correction(self.data.swapchain_extent) * proj)
+            })
+            .collect::<Vec<_>>();
+
+        let entities = &self.entities[..self.models.min(self.entities.len())];
+        let bounds = |e: &Entity| entity_bounds(&self.data, e);
+        self.visibility = compute_visibility(view, proj, bounds, entities);
+        for (view, proj) in others {
+            let seen = compute_visibility(view, proj, bounds, entities);
+            for (visibility, seen) in self.visibility.iter_mut().zip(seen) {
+                visibility.visible |= seen.visible;
+            }
+        }
+
+        // Viewmodel entities are culled in view space against their own
+        // projection.
+        if entities.iter().any(|e| e.layer == CameraLayer::Viewmodel) {
+            let viewmodel = compute_visibility(Mat4::identity(), self.viewmodel_projection(), bounds, entities);
+            for (visibility, viewmodel) in self.visibility.iter_mut().zip(viewmodel) {
+                if entities[visibility.entity].layer == CameraLayer::Viewmodel {
+                    *visibility = viewmodel;
+                }
+            }
+        }
+        for callback in &mut self.visibility_callbacks.0 {
+            callback(&self.visibility);
+        }
+    }
+
+    /// Picks each drawn entity's level of detail from its screen size.
+    fn update_lods(&mut self) {
+        self.lod_levels.resize(self.visibility.len(), 0);
+        for visibility in &self.visibility {
+            let level = &mut self.lod_levels[visibility.entity];
+            let levels = entity_lod_count(&self.data, &self.entities[visibility.entity]);
+            *level = self.level_of_detail.select(visibility.screen_size, *level, levels);
+        }
+
+        // Static entities are recorded once and drawn whatever their size.
+        let previous = std::mem::take(&mut self.impostors);
+        if self.impostor.is_none() {
+            return;
+        }
+        let statics = self.static_mask();
+        let meshes = self.mesh_mask();
+        let materials = self.material_mask();
+        for visibility in self.visibility.iter().filter(|v| v.visible && v.entity < 64) {
+            let bit = 1 << visibility.entity;
+            if (statics | meshes | materials) & bit == 0
+                && self.entities[visibility.entity].layer == CameraLayer::World
+                && self.level_of_detail.impostor(visibility.screen_size, previous & bit != 0)
+            {
+                self.impostors |= bit;
+            }
+        }
+    }
+
+    /// Two bits per entity holding its level of detail.
+    fn lod_mask(&self) -> u128 {
+        self.lod_levels
+            .iter()
+            .take(64)
+            .enumerate()
+            .fold(0, |mask, (i, level)| mask | (*level as u128) << (2 * i))
+    }
+
+    /// The scene model's joints, if it is skinned.
+    pub fn skeleton(&self) -> Option<&Skeleton> {
+        self.data.skeleton.as_ref()
+    }
+
+    /// The scene model's animations, for `AnimationPlayer`.
+    pub fn clips(&self) -> &[AnimationClip] {
+        &self.data.clips
+    }
+
+    /// The index of the clip called `name`.
+    pub fn clip(&self, name: &str) -> Option<usize> {
+        self.data.clips.iter().position(|c| c.name.as_deref() == Some(name))
+    }
+
+    /// Advances every animation player and writes its pose.
+    fn update_animations(&mut self) {
+        let now = self.start.elapsed().as_secs_f32();
+        let elapsed = now - self.animated_at;
+        self.animated_at = now;
+        let Some(skeleton) = &self.data.skeleton else {
+            return;
+        };
+
+        for player in &mut self.animations {
+            player.advance(&self.data.clips, elapsed);
+            let pose = player.sample(skeleton, &self.data.clips);
+            if self.poses.len() <= player.pose {
+                self.poses.resize_with(player.pose + 1, || skeleton.rest_pose());
+            }
+            self.poses[player.pose] = pose;
+        }
+    }
+
+    /// Writes this frame's joint matrices. The rest pose comes first, for
+    /// entities without a pose, then each posed entity's in entity order, so
+    /// offsets only move when entities or poses are added or removed and
+    /// cached scene command buffers stay valid.
+    fn update_skinning(&mut self, image_index: usize) {
+        self.joint_offsets.clear();
+        let Some(skeleton) = &self.data.skeleton else {
+            return;
+        };
+
+        let mut matrices = Vec::with_capacity(skeleton.joints.len());
+        skeleton.skinning_matrices(&skeleton.rest_pose(), &mut matrices);
+
+        for entity in &self.entities[..self.models.min(self.entities.len())] {
+            let pose = entity.pose.and_then(|p| self.poses.get(p));
+            let offset = match pose {
+                Some(pose) if matrices.len() + skeleton.joints.len() <= MAX_JOINT_MATRICES => {
+                    let offset = matrices.len() as u32;
+                    skeleton.skinning_matrices(pose, &mut matrices);
+                    offset
+                }
+                // Out of room, or not posed: the rest pose.
+                _ => 0,
+            };
+            self.joint_offsets.push(offset);
+        }
+
+        unsafe { write_joint_matrices(&self.device, &self.data, image_index, &matrices) };
+    }
+
+    /// Bit `i` is set if entity `i` passed culling.
+    fn visible_mask(&self) -> u64 {
+        self.visibility
+            .iter()
+            .take(64)
+            .filter(|v| v.visible)
+            .fold(0, |mask, v| mask | (1 << v.entity))
+    }
+
+    /// Loads the cubemap used for skyboxes and environment lighting,
+    /// replacing the previous one. `.hdr` and `.exr` sources keep their full
+    /// range as half floats.
+    pub unsafe fn load_environment_map(&mut self, source: &CubemapSource) -> Result<()> {
+        let mut cubemap = create_cubemap(&self.instance, &self.device, &self.data, source)?;
+        cubemap.sampler = self.data.samplers.get(&self.device, SamplerDesc::clamped());
+        info!("Loaded {0}x{0} environment map ({1:?}).", cubemap.width, cubemap.format);
+
+        self.device.device_wait_idle().unwrap();
+        if let Some(previous) = self.data.environment_map.replace(cubemap) {
+            previous.destroy(&self.device);
+        }
+        Ok(())
+    }
+
+    /// Loads one texture per layer into a 2D texture array and returns its
+    /// index. Arrays are sampled with `sampler2DArray` through one descriptor.
+    /// Pass `ColorSpace::Linear` for normal maps and other data textures.
+    /// Samplers are shared between arrays that describe the same sampling.
+    pub unsafe fn load_texture_array(
+        &mut self,
+        paths: &[PathBuf],
+        color_space: ColorSpace,
+        sampler: SamplerDesc,
+    ) -> Result<usize> {
+        let layers = paths
+            .iter()
+            .map(|p| Ok(load_texture(p)?.with_color_space(color_space)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut array = create_texture_array(&self.instance, &self.device, &self.data, layers)?;
+        array.sampler = self.data.samplers.get(&self.device, sampler);
+        info!("Loaded {}x{}x{} texture array ({:?}).", array.width, array.height, array.layers, array.format);
+
+        self.data.texture_arrays.push(array);
+        Ok(self.data.texture_arrays.len() - 1)
+    }
+
+    /// Loads a 2D texture into the texture table and returns its slot, for
+    /// `Entity::texture`. Slot 0 is the scene texture.
+    pub unsafe fn load_texture(&mut self, path: &Path, color_space: ColorSpace, sampler: SamplerDesc) -> Result<u32> {
+        let slot = self.data.assets.free_texture_slots.last().copied().unwrap_or(texture_count(&self.data));
+        if slot >= self.data.texture_capacity {
+            return Err(anyhow!("The texture table is full ({} slots).", self.data.texture_capacity));
+        }
+
+        let texture = load_texture(path)?.with_color_space(color_space);
+        let mut texture = create_texture(&self.instance, &self.device, &self.data, texture)?;
+        texture.sampler = self.data.samplers.get(&self.device, sampler);
+        info!("Loaded {}x{} texture into slot {} ({:?}).", texture.width, texture.height, slot, texture.format);
+
+        self.device.device_wait_idle().unwrap();
+        if self.data.assets.free_texture_slots.pop().is_some() {
+            self.data.textures[slot as usize - 1] = Some(texture);
+        } else {
+            self.data.textures.push(Some(texture));
+        }
+        write_texture_table(&self.device, &self.data);
+        self.invalidate_scene();
+        Ok(slot)
+    }
+
+    /// Loads the model at `path` with its levels of detail into the buffers
+    /// shared by every registered mesh, for `Entity::mesh`, or adds a reference to it if it is
+    /// already loaded. Skinned models are drawn in their bind pose.
+    pub unsafe fn acquire_mesh(&mut self, path: &Path) -> Result<MeshHandle> {
+        if let Some(handle) = self.data.assets.find_mesh(path) {
+            self.data.assets.meshes[handle.0 as usize].as_mut().unwrap().references += 1;
+            return Ok(handle);
+        }
+
+        let (mesh, grown) = create_mesh_asset(&self.instance, &self.device, &mut self.data, path)?;
+        if grown {
+            self.invalidate_scene();
+        }
+        self.data.assets.meshes.push(Some(mesh));
+        Ok(MeshHandle(self.data.assets.meshes.len() as u32 - 1))
+    }
+
+    /// Drops a reference from `acquire_mesh`, unloading the mesh with the
+    /// last one. Entities still drawing it fall back to the scene model.
+    pub unsafe fn release_mesh(&mut self, handle: MeshHandle) {
+        let Some(mesh) = self.data.assets.meshes.get_mut(handle.0 as usize).and_then(|m| m.as_mut()) else {
+            warn!("Released mesh {:?} more often than it was acquired.", handle);
+            return;
+        };
+        mesh.references -= 1;
+        if mesh.references > 0 {
+            return;
+        }
+
+        let mesh = self.data.assets.meshes[handle.0 as usize].take().unwrap();
+        info!("Unloaded mesh `{}`.", mesh.path.display());
+        // Frames in flight may still draw from its ranges.
+        self.device.device_wait_idle().unwrap();
+        self.data.assets.pool.remove(mesh.allocation);
+        self.invalidate_scene();
+    }
+
+    /// Like `load_texture`, but each path is loaded once and shares its slot
+    /// between callers until the last releases it with `release_texture`.
+    pub unsafe fn acquire_texture(
+        &mut self,
+        path: &Path,
+        color_space: ColorSpace,
+        sampler: SamplerDesc,
+    ) -> Result<TextureHandle> {
+        if let Some(asset) = self.data.assets.textures.iter_mut().find(|t| t.path == path) {
+            asset.references += 1;
+            return Ok(TextureHandle(asset.slot));
+        }
+
+        let slot = self.load_texture(path, color_space, sampler)?;
+        self.data.assets.textures.push(TextureAsset { path: path.to_path_buf(), references: 1, slot });
+        Ok(TextureHandle(slot))
+    }
+
+    /// Drops a reference from `acquire_texture`, unloading the texture with
+    /// the last one. Its slot draws the scene texture until another texture
+    /// is loaded into it.
+    pub unsafe fn release_texture(&mut self, handle: TextureHandle) {
+        let Some(index) = self.data.assets.textures.iter().position(|t| t.slot == handle.0) else {
+            warn!("Released texture {:?} more often than it was acquired.", handle);
+            return;
+        };
+        let asset = &mut self.data.assets.textures[index];
+        asset.references -= 1;
+        if asset.references > 0 {
+            return;
+        }
+
+        let asset = self.data.assets.textures.remove(index);
+        info!("Unloaded texture `{}` from slot {}.", asset.path.display(), asset.slot);
+        self.free_texture_slot(asset.slot);
+    }
+
+    /// Destroys the texture in `slot` and gives the slot to the next
+    /// texture loaded. A texture still loading into it is destroyed when it
+    /// arrives, see `update_assets`.
+    unsafe fn free_texture_slot(&mut self, slot: u32) {
+        self.device.device_wait_idle().unwrap();
+        if let Some(texture) = self.data.textures[slot as usize - 1].take() {
+            texture.destroy(&self.device);
+        }
+        self.data.assets.free_texture_slots.push(slot);
+        write_texture_table(&self.device, &self.data);
+        self.invalidate_scene();
+    }
+
+    /// Adds a material for `create_material_instance`, compiling the
+    /// pipelines of its shading unless an earlier material shades the same
+    /// way. Materials live until shutdown.
+    pub unsafe fn create_material(&mut self, material: Material) -> Result<MaterialHandle> {
+        let handle = self.data.materials.add(material);
+        create_material_pipelines(&self.device, &mut self.data)?;
+        Ok(handle)
+    }
+
+    /// The material `handle` was created with.
+    pub fn material(&self, handle: MaterialHandle) -> Option<&Material> {
+        self.data.materials.material(handle)
+    }
+
+    /// Adds parameters for `material`, starting from its own, for
+    /// `Entity::material`.
+    pub fn create_material_instance(&mut self, material: MaterialHandle) -> Result<MaterialInstance> {
+        self.data.materials.instantiate(material)
+    }
+
+    /// The parameters `instance` is drawn with, `None` once it is destroyed.
+    pub fn material_parameters(&self, instance: MaterialInstance) -> Option<MaterialParameters> {
+        self.data.materials.parameters(instance)
+    }
+
+    /// Replaces `instance`'s parameters from the next frame on. Nothing is
+    /// re-recorded, so they can change every frame.
+    pub fn set_material_parameters(&mut self, instance: MaterialInstance, parameters: MaterialParameters) {
+        if !self.data.materials.set_parameters(instance, parameters) {
+            warn!("Set the parameters of destroyed material instance {:?}.", instance);
+        }
+    }
+
+    /// Frees `instance`'s parameters for another instance to take. Entities
+    /// still drawing it are drawn as if they had no material.
+    pub fn destroy_material_instance(&mut self, instance: MaterialInstance) {
+        if !self.data.materials.remove(instance) {
+            warn!("Destroyed material instance {:?} more than once.", instance);
+            return;
+        }
+        // Recorded draws read its block, which the next instance takes.
+        self.invalidate_scene();
+    }
+
+    /// Packs the textures at `paths` onto shared atlas pages, each taking one
+    /// texture table slot and one sprite atlas, and returns where each
+    /// texture ended up, in order. The textures must be 8 bit RGBA and small
+    /// enough to share a page; entities drawing any of them can then be
+    /// baked into one static batch.
+    pub unsafe fn load_texture_atlas(
+        &mut self,
+        paths: &[PathBuf],
+        color_space: ColorSpace,
+        sampler: SamplerDesc,
+    ) -> Result<Vec<AtlasRegion>> {
+        let textures = paths
+            .iter()
+            .map(|p| Ok(load_texture(p)?.with_color_space(color_space)))
+            .collect::<Result<Vec<_>>>()?;
+        let packed = pack_textures(&textures, ATLAS_PAGE_SIZE, ATLAS_PADDING)?;
+
+        let first_slot = texture_count(&self.data);
+        if first_slot + packed.pages.len() as u32 > self.data.texture_capacity {
+            return Err(anyhow!("The texture table is full ({} slots).", self.data.texture_capacity));
+        }
+        if self.data.atlas_regions.len() + packed.regions.len() > MAX_ATLAS_REGIONS {
+            return Err(anyhow!("The atlas region table is full ({} regions).", MAX_ATLAS_REGIONS));
+        }
+
+        self.device.device_wait_idle().unwrap();
+        let mut sprite_atlases = Vec::with_capacity(packed.pages.len());
+        for page in packed.pages {
+            let mut texture = create_texture(&self.instance, &self.device, &self.data, page)?;
+            texture.sampler = self.data.samplers.get(&self.device, sampler);
+            sprite_atlases.push(create_sprite_atlas(&self.device, &mut self.data, texture.image_view, texture.sampler)?);
+            self.data.textures.push(Some(texture));
+        }
+
+        let first_region = self.data.atlas_regions.len();
+        self.data.atlas_regions.extend(packed.regions.iter().map(|(_, region)| *region));
+        info!(
+            "Packed {} textures onto {} atlas pages from slot {}.",
+            packed.regions.len(),
+            sprite_atlases.len(),
+            first_slot,
+        );
+
+        write_texture_table(&self.device, &self.data);
+        write_atlas_regions(&self.device, &self.data);
+        self.invalidate_scene();
+        Ok(packed
+            .regions
+            .iter()
+            .enumerate()
+            .map(|(i, (page, region))| AtlasRegion {
+                texture: atlas_texture(first_slot + *page as u32, first_region + i),
+                sprite_atlas: sprite_atlases[*page],
+                uv_min: Vec2::new(region.x, region.y),
+                uv_max: Vec2::new(region.x + region.z, region.y + region.w),
+            })
+            .collect())
+    }
+
+    /// Bakes `frames` views of the model from around it, textured with
+    /// `texture`, each `resolution` texels square, and draws movable world
+    /// entities smaller on screen than `LevelOfDetail::impostor_screen_size`
+    /// as billboards of them. Replaces the previous impostor, reusing its
+    /// texture slot. Bake again after loading another model.
+    pub unsafe fn bake_impostor(&mut self, frames: u32, resolution: u32, texture: u32) -> Result<Impostor> {
+        let slot = self.impostor.map_or(texture_count(&self.data), |i| i.texture);
+        if slot >= self.data.texture_capacity {
+            return Err(anyhow!("The texture table is full ({} slots).", self.data.texture_capacity));
+        }
+
+        self.device.device_wait_idle().unwrap();
+        let mut views = create_impostor_texture(&self.instance, &self.device, &self.data, frames, resolution, texture)?;
+        views.sampler = self.data.samplers.get(&self.device, SamplerDesc::clamped());
+        match self.data.textures.get_mut(slot as usize - 1) {
+            Some(previous) => {
+                if let Some(previous) = previous.replace(views) {
+                    previous.destroy(&self.device);
+                }
+            }
+            None => self.data.textures.push(Some(views)),
+        }
+        write_texture_table(&self.device, &self.data);
+        info!("Baked {} impostor views of {}x{} into slot {}.", frames, resolution, resolution, slot);
+
+        let impostor = Impostor {
+            texture: slot,
+            frames,
+            bounds: self.data.model_bounds,
+            axes: impostor_axes(&self.data),
+        };
+        self.impostor = Some(impostor);
+        self.invalidate_scene();
+        Ok(impostor)
+    }
+
+    /// Like `load_texture`, but reads and uploads the texture in the
+    /// background. The slot draws the scene texture until the texture is
+    /// ready, see `on_asset_loaded`.
+    pub unsafe fn load_texture_async(&mut self, path: &Path, color_space: ColorSpace, sampler: SamplerDesc) -> Result<u32> {
+        let slot = self.data.assets.free_texture_slots.last().copied().unwrap_or(texture_count(&self.data));
+        if slot >= self.data.texture_capacity {
+            return Err(anyhow!("The texture table is full ({} slots).", self.data.texture_capacity));
+        }
+
+        let sampler = self.data.samplers.get(&self.device, sampler);
+        self.asset_loader.load_texture(slot, path, color_space, sampler);
+        if self.data.assets.free_texture_slots.pop().is_none() {
+            self.data.textures.push(None);
+        }
+        Ok(slot)
+    }
+
+    /// The drawn entities, the sun and the active camera, for `Scene::save`.
+    /// The model and textures are those of the last scene loaded with
+    /// `load_scene`; entities drawing textures or atlas regions loaded any
+    /// other way are saved with the scene texture, entities drawing
+    /// registered meshes with the scene model, and materials are left out.
+    pub fn scene(&self) -> Scene {
+        let entities = self.entities[..self.models.min(self.entities.len())]
+            .iter()
+            .map(|entity| Entity {
+                texture: self
+                    .scene_textures
+                    .iter()
+                    .position(|(_, slot)| *slot == entity.texture)
+                    .map_or(0, |i| i as u32 + 1),
+                mesh: None,
+                material: None,
+                ..*entity
+            })
+            .collect();
+        Scene {
+            model: self.scene_model.clone(),
+            textures: self.scene_textures.iter().map(|(path, _)| path.clone()).collect(),
+            entities,
+            sun: self.sun,
+            camera: self.cameras[self.active_camera],
+        }
+    }
+
+    /// Replaces the entities, the sun and the active camera with `scene`'s
+    /// and stops the demo animation. Its textures and model are loaded in
+    /// the background, see `load_texture_async` and `load_model_async`.
+    /// Textures an earlier scene loaded are kept if `scene` names them too
+    /// and unloaded otherwise. Scenes of more than 64 entities are rejected.
+    pub unsafe fn load_scene(&mut self, scene: &Scene) -> Result<()> {
+        check_entity_count(&scene.entities)?;
+
+        let mut slots = vec![0];
+        for path in &scene.textures {
+            let loaded = self.scene_textures.iter().find(|(p, _)| p == path).map(|(_, slot)| *slot);
+            match loaded {
+                Some(slot) => slots.push(slot),
+                None => slots.push(self.load_texture_async(path, ColorSpace::Srgb, SamplerDesc::default())?),
+            }
+        }
+        let previous = std::mem::take(&mut self.scene_textures);
+        self.scene_textures = scene.textures.iter().cloned().zip(slots[1..].iter().copied()).collect();
+        for (path, slot) in previous {
+            if !slots.contains(&slot) {
+                info!("Unloaded texture `{}` from slot {}.", path.display(), slot);
+                self.free_texture_slot(slot);
+            }
+        }
+
+        self.entities = scene
+            .entities
+            .iter()
+            .map(|entity| Entity {
+                texture: slots.get(entity.texture as usize).copied().unwrap_or(0),
+                ..*entity
+            })
+            .collect();
+        self.models = self.entities.len();
+        self.sun = scene.sun;
+        self.cameras[self.active_camera] = scene.camera;
+        self.demo_animation = false;
+
+        if let Some(model) = &scene.model {
+            if self.scene_model.as_ref() != Some(model) {
+                self.load_model_async(model);
+            }
+        }
+        self.scene_model = scene.model.clone();
+        self.invalidate_scene();
+        Ok(())
+    }
+
+    /// Loads the scene at `path` and reloads it whenever the file changes,
+    /// until another is watched or `path` is `None`. Reloads go through
+    /// `load_scene`, so textures and the model are only loaded again if the
+    /// file names new ones, and the camera and sun are only replaced if the
+    /// edit changed them.
+    pub unsafe fn watch_scene(&mut self, path: Option<&Path>) -> Result<()> {
+        self.scene_watch = None;
+        let Some(path) = path else {
+            return Ok(());
+        };
+        let watch = SceneWatch::new(path, self.start.elapsed().as_secs_f32())?;
+        self.load_scene(&watch.scene)?;
+        info!("Watching `{}`.", path.display());
+        self.scene_watch = Some(watch);
+        Ok(())
+    }
+
+    /// The file set with `watch_scene`.
+    pub fn watched_scene(&self) -> Option<&Path> {
+        self.scene_watch.as_ref().map(|w| w.path.as_path())
+    }
+
+    /// Applies the watched scene file's changes, if it has any.
+    unsafe fn update_scene_watch(&mut self) {
+        let now = self.start.elapsed().as_secs_f32();
+        let Some(scene) = self.scene_watch.as_mut().and_then(|w| w.poll(now)) else {
+            return;
+        };
+        let watch = self.scene_watch.take().unwrap();
+        let previous = &watch.scene;
+
+        let changed = (0..scene.entities.len().max(previous.entities.len()))
+            .filter(|i| scene.entities.get(*i) != previous.entities.get(*i))
+            .count();
+        info!(
+            "Reloading `{}`: {} of {} entities changed{}{}{}.",
+            watch.path.display(),
+            changed,
+            scene.entities.len(),
+            if scene.sun != previous.sun { ", the sun changed" } else { "" },
+            if scene.camera != previous.camera { ", the camera changed" } else { "" },
+            if scene.model != previous.model || scene.textures != previous.textures { ", new assets" } else { "" },
+        );
+
+        // Keep what the app changed since unless the file changed it too.
+        let (camera, sun) = (self.cameras[self.active_camera], self.sun);
+        if let Err(e) = self.load_scene(&scene) {
+            warn!("Failed to reload the scene: {}", e);
+        }
+        if scene.camera == previous.camera {
+            self.cameras[self.active_camera] = camera;
+        }
+        if scene.sun == previous.sun {
+            self.sun = sun;
+        }
+        self.scene_watch = Some(SceneWatch { scene, ..watch });
+    }
+
+    /// Reads and uploads an OBJ or glTF model in the background, then
+    /// replaces the scene model with it. The current model is drawn until
+    /// then. A glTF model's skin and animations replace `skeleton` and
+    /// `clips`.
+    pub fn load_model_async(&mut self, path: &Path) {
+        self.asset_loader.load_model(path, self.data.world);
+    }
+
+    /// Whether any background loads have yet to finish.
+    pub fn assets_loading(&self) -> bool {
+        self.asset_loader.is_busy()
+    }
+
+    /// Registers a callback that is told, on the render thread, when each
+    /// background load finishes or fails.
+    pub fn on_asset_loaded(&mut self, callback: impl FnMut(&AssetEvent) + Send + 'static) {
+        self.asset_callbacks.0.push(Box::new(callback));
+    }
+
+    /// Swaps in background loads whose uploads have finished. Like texture
+    /// streaming this waits for the device, but only on frames where
+    /// something finished.
+    unsafe fn update_assets(&mut self) {
+        let finished = self.asset_loader.poll(&self.instance, &self.device, &self.data);
+        if finished.is_empty() {
+            return;
+        }
+
+        let loaded = finished.iter().filter_map(|f| f.as_ref().ok()).collect::<Vec<_>>();
+        if !loaded.is_empty() {
+            // Takes ownership from the transfer queue family, if it differs.
+            let command_buffer = begin_single_time_commands(&self.device, &self.data).unwrap();
+            loaded
+                .iter()
+                .for_each(|a| a.acquire(&self.instance, &self.device, &self.data, command_buffer));
+            end_single_time_commands(&self.device, &self.data, command_buffer).unwrap();
+            self.device.device_wait_idle().unwrap();
+        }
+
+        let mut events = vec![];
+        for result in finished {
+            match result {
+                Ok(LoadedAsset::Texture { slot, path, mut texture }) => {
+                    texture.image_view = create_image_view(
+                        &self.device,
+                        texture.image,
+                        texture.format,
+                        vk::ImageAspectFlags::COLOR,
+                        texture.mip_levels,
+                    )
+                    .unwrap();
+                    if self.data.assets.free_texture_slots.contains(&slot) {
+                        // The scene that asked for it was replaced while it loaded.
+                        texture.destroy(&self.device);
+                        continue;
+                    }
+                    info!("Loaded {}x{} texture into slot {} ({:?}).", texture.width, texture.height, slot, texture.format);
+                    if let Some(previous) = self.data.textures[slot as usize - 1].replace(texture) {
+                        previous.destroy(&self.device);
+                    }
+                    events.push(AssetEvent::TextureLoaded { slot, path });
+                }
+                Ok(LoadedAsset::Model {
+                    path,
+                    mesh,
+                    vertex_buffer,
+                    vertex_buffer_memory,
+                    index_buffer,
+                    index_buffer_memory,
+                    index_type,
+                }) => {
+                    self.device.destroy_buffer(self.data.vertex_buffer, None);
+                    free_memory(&self.device, self.data.vertex_buffer_memory);
+                    self.device.destroy_buffer(self.data.index_buffer, None);
+                    free_memory(&self.device, self.data.index_buffer_memory);
+                    self.data.vertex_buffer = vertex_buffer;
+                    self.data.vertex_buffer_memory = vertex_buffer_memory;
+                    self.data.index_buffer = index_buffer;
+                    self.data.index_buffer_memory = index_buffer_memory;
+                    self.data.index_type = index_type;
+                    info!("Loaded `{}` ({} vertices).", path.display(), mesh.vertices.len());
+                    self.data.vertices = mesh.vertices;
+                    self.data.indices = mesh.indices;
+                    self.data.model_bounds = mesh.bounds;
+                    self.data.skeleton = mesh.skeleton;
+                    self.data.clips = mesh.clips;
+                    self.data.meshlets = mesh.meshlets;
+                    create_meshlet_buffers(&self.instance, &self.device, &mut self.data).unwrap();
+                    create_blas(&self.instance, &self.device, &mut self.data).unwrap();
+                    if !self.data.static_batches.batches.is_empty() {
+                        self.bake_static_batches().unwrap();
+                    }
+                    events.push(AssetEvent::ModelLoaded { path });
+                }
+                Err((path, e)) => {
+                    warn!("Failed to load `{}`: {}", path.display(), e);
+                    events.push(AssetEvent::Failed { path, error: e.to_string() });
+                }
+            }
+        }
+
+        write_texture_table(&self.device, &self.data);
+        self.invalidate_scene();
+
+        for event in &events {
+            for callback in &mut self.asset_callbacks.0 {
+                callback(event);
+            }
+        }
+    }
+
+    /// Grades the tonemapped image through the 3D LUT in a `.cube` file, as
+    /// exported by most grading tools, or stops grading with `None`. Debug
+    /// views are never graded.
+    pub unsafe fn set_color_grading(&mut self, path: Option<&Path>) -> Result<()> {
+        let lut = match path {
+            Some(path) => CubeLut::load(path)?,
+            None => CubeLut::identity(),
+        };
+        let color_lut = create_color_lut(&self.instance, &self.device, &mut self.data, &lut)?;
+        if let Some(path) = path {
+            info!("Loaded {}³ color grading LUT `{}`.", lut.size, path.display());
+        }
+
+        self.device.device_wait_idle().unwrap();
+        self.data.color_lut.destroy(&self.device);
+        self.data.color_lut = color_lut;
+        write_color_lut_descriptor(&self.device, &self.data);
+        self.color_grading = path.map(Path::to_path_buf);
+        Ok(())
+    }
+
+    /// The `.cube` file the image is graded with, if any.
+    pub fn color_grading(&self) -> Option<&Path> {
+        self.color_grading.as_deref()
+    }
+
+    /// Re-uploads one layer of a texture array from `path`.
+    pub unsafe fn update_texture_array_layer(&mut self, array: usize, layer: u32, path: &Path) -> Result<()> {
+        let array = *self
+            .data
+            .texture_arrays
+            .get(array)
+            .ok_or_else(|| anyhow!("No texture array {}.", array))?;
+        let texture = load_texture(path)?;
+
+        self.device.device_wait_idle().unwrap();
+        update_texture_array_layer(&self.instance, &self.device, &self.data, &array, layer, texture)
+    }
+
+    /// Reads back the luminance metered the last time `image_index` was
+    /// rendered, which must have finished, and adapts the exposure to it.
+    unsafe fn update_exposure(&mut self, image_index: usize) {
+        if let Some(readback) = self.data.exposure_readbacks.get_mut(image_index) {
+            if readback.pending {
+                readback.pending = false;
+                let (exposure, luminance) = readback.read(&self.device);
+                self.exposure = exposure;
+                if luminance > 0.0 {
+                    self.scene_luminance = Some(luminance);
+                }
+            }
+        }
+
+        let Some(metering) = self.data.metering.get_mut(image_index) else {
+            return;
+        };
+        let Some(exposure) = metering.pending.take() else {
+            return;
+        };
+        let Some(luminance) = metering.read(&self.device) else {
+            return;
+        };
+
+        let luminance = luminance / exposure;
+        let now = self.start.elapsed().as_secs_f32();
+        self.exposure = self.auto_exposure.adapt(self.exposure, luminance, now - self.metered_at);
+        self.scene_luminance = Some(luminance);
+        self.metered_at = now;
+    }
+
+    /// Fades the focus blur towards `ui_focus`.
+    fn update_focus_blur(&mut self) {
+        let now = self.start.elapsed().as_secs_f32();
+        self.focus_amount = self.focus_blur.advance(self.focus_amount, self.ui_focus, now - self.focus_updated_at);
+        self.focus_updated_at = now;
+    }
+
+    /// Refreshes the heap budgets every `MEMORY_BUDGET_INTERVAL` seconds and
+    /// warns when a device local heap comes close to its budget, once each
+    /// time it does.
+    unsafe fn update_memory_budget(&mut self) {
+        self.frame_stats.frame_number = self.data.frame_number;
+        let now = self.start.elapsed().as_secs_f32();
+        if self.budget_checked_at.is_some_and(|t| now - t < MEMORY_BUDGET_INTERVAL) {
+            return;
+        }
+        self.budget_checked_at = Some(now);
+
+        let pressure = self.frame_stats.memory_pressure();
+        self.frame_stats.memory_budget = self.data.memory_budget;
+        self.frame_stats.heaps = get_heap_budgets(&self.instance, &self.data);
+        if self.frame_stats.memory_pressure() && !pressure {
+            for (i, heap) in self.frame_stats.heaps.iter().enumerate().filter(|(_, h)| h.device_local) {
+                warn!(
+                    "Memory heap {} is {:.0}% used ({} of {} MiB), dropping streamed texture levels.",
+                    i,
+                    heap.fraction() * 100.0,
+                    heap.usage >> 20,
+                    heap.budget >> 20,
+                );
+            }
+        }
+    }
+
+    /// Swaps in a finished texture upload and asks for the levels the
+    /// visible entities need.
+    ///
+    /// Swapping rewrites descriptor sets that in-flight frames may be using,
+    /// so it waits for the device. Residency only changes every few levels of
+    /// camera movement, which keeps the stall rare.
+    unsafe fn update_texture_streaming(&mut self) {
+        let Some(mut streamed) = self.data.streamed_texture.take() else {
+            return;
+        };
+
+        if streamed.upload_finished(&self.device) {
+            self.device.device_wait_idle().unwrap();
+            let resident = streamed.finish_upload(&self.device, &self.data).unwrap();
+
+            self.device.destroy_image_view(self.data.texture_image_view, None);
+            free_memory(&self.device, self.data.texture_image_memory);
+            self.device.destroy_image(self.data.texture_image, None);
+            self.data.texture_image = resident.image;
+            self.data.texture_image_memory = resident.image_memory;
+            self.data.mip_levels = resident.mip_levels;
+            create_texture_image_view(&self.device, &mut self.data).unwrap();
+            write_texture_descriptors(&self.device, &mut self.data);
+            self.invalidate_scene();
+
+            let (width, height) = streamed.resident_size();
+            debug!("Texture streamed to {}x{} ({} levels).", width, height, resident.mip_levels);
+        }
+
+        let level = streamed.wanted_level(
+            &self.texture_streaming,
+            &self.visibility,
+            self.data.swapchain_extent,
+            self.frame_stats.memory_pressure(),
+        );
+        if let Err(e) = streamed.request(&self.instance, &self.device, &self.data, level) {
+            warn!("Failed to stream texture: {}", e);
+        }
+        self.data.streamed_texture = Some(streamed);
+    }
+
+    /// Advances the demo animation. Static entities keep their transform.
+    fn update_entities(&mut self) {
+        if !self.demo_animation {
+            return;
+        }
+        let time = self.start.elapsed().as_secs_f32();
+        for (i, entity) in self.entities.iter_mut().enumerate() {
+            if !entity.is_static() && entity.layer == CameraLayer::World {
+                entity.transform = demo_transform(&self.data.world, i, time);
+            }
+        }
+    }
+
+    /// Moves every particle emitter on to now.
+    fn update_particles(&mut self) {
+        let now = self.start.elapsed().as_secs_f32();
+        self.particle_step = now - self.particles_updated_at;
+        self.particles_updated_at = now;
+        for emitter in &mut self.particles {
+            emitter.update(self.particle_step);
+        }
+    }
+
+    /// Creates, resizes or destroys the GPU particle simulation to match
+    /// `gpu_particles`. Waits for the device when it does, since in-flight
+    /// frames may be using the old buffers.
+    unsafe fn update_gpu_particles(&mut self) {
+        let capacity = self.gpu_particles.map(|e| e.capacity);
+        if self.data.gpu_particles.as_ref().map(|p| p.capacity) == capacity {
+            return;
+        }
+
+        self.device.device_wait_idle().unwrap();
+        if let Some(mut particles) = self.data.gpu_particles.take() {
+            particles.destroy(&self.device);
+        }
+        if let Some(capacity) = capacity {
+            self.data.gpu_particles = Some(GpuParticles::new(&self.instance, &self.device, &self.data, capacity).unwrap());
+        }
+    }
+
+    /// Moves the first entity between the world and the viewmodel layer.
+    pub fn toggle_demo_viewmodel(&mut self) {
+        let Some(entity) = self.entities.first_mut() else {
+            return;
+        };
+        entity.layer = match entity.layer {
+            CameraLayer::World => {
+                entity.transform = demo_viewmodel_transform();
+                CameraLayer::Viewmodel
+            }
+            CameraLayer::Viewmodel => CameraLayer::World,
+        };
+    }
+
+    fn static_mask(&self) -> u64 {
+        self.entities
+            .iter()
+            .take(self.models.min(64))
+            .enumerate()
+            .filter(|(_, e)| e.is_static() && e.layer == CameraLayer::World && e.order.queue.is_opaque())
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// The drawn entities drawing a registered mesh instead of the scene
+    /// model, see `Entity::mesh`.
+    fn mesh_mask(&self) -> u64 {
+        self.entities
+            .iter()
+            .take(self.models.min(64))
+            .enumerate()
+            .filter(|(_, e)| entity_mesh(&self.data, e).is_some())
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// The drawn entities with a material, see `Entity::material`.
+    fn material_mask(&self) -> u64 {
+        self.entities
+            .iter()
+            .take(self.models.min(64))
+            .enumerate()
+            .filter(|(_, e)| e.material.is_some())
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// Whether `mesh_shading` applies this frame.
+    fn mesh_shading_active(&self) -> bool {
+        self.mesh_shading
+            && self.data.mesh_shader
+            && self.data.skeleton.is_none()
+            && self.scene_variant() == SceneVariant::Shaded
+    }
+
+    /// Whether `ray_tracing` applies this frame.
+    fn ray_tracing_active(&self) -> bool {
+        self.ray_tracing && self.data.ray_tracing
+    }
+
+    /// Whether the sun's shadows are traced this frame.
+    fn ray_traced_shadows_active(&self) -> bool {
+        self.sun.is_some() && self.ray_traced_shadows && self.ray_tracing_active()
+    }
+
+    /// The radius ambient occlusion is traced within this frame, if it is.
+    fn ray_traced_occlusion_radius(&self) -> Option<f32> {
+        match self.ambient_occlusion {
+            AmbientOcclusion::RayQuery { radius } if self.ray_tracing_active() && self.data.ray_query => Some(radius),
+            _ => None,
+        }
+    }
+
+    /// Whether the exposure is metered with a histogram this frame, see
+    /// `AutoExposure::histogram`.
+    fn histogram_active(&self) -> bool {
+        // The eyes are tonemapped without it.
+        self.auto_exposure.histogram && !self.data.exposure_readbacks.is_empty() && !self.stereo_active()
+    }
+
+    /// Whether the eyes are drawn this frame, for `stereo` or the headset.
+    fn stereo_active(&self) -> bool {
+        let xr = self.xr_frame.as_ref().is_some_and(|f| f.image_index().is_some());
+        self.data.multiview && (self.stereo.is_some() || xr)
+    }
+
+    /// The eyes' views and projections this frame: the headset's while it
+    /// is drawn to, otherwise either side of the camera.
+    fn eyes(&self, view: Mat4, proj: Mat4) -> Option<([Mat4; 2], [Mat4; 2])> {
+        if !self.stereo_active() {
+            return None;
+        }
+        if let Some((views, projs)) = self.xr_frame.as_ref().and_then(|f| {
+            let camera = &self.cameras[self.active_camera];
+            let (near, far) = depth_planes(self.data.reverse_z, camera.near, camera.projected_far());
+            f.eyes(view, near, far)
+        }) {
+            let remap = match self.viewmodel.depth {
+                ViewmodelDepth::Clear => Mat4::identity(),
+                ViewmodelDepth::Remap => Viewmodel::depth_remap(self.viewmodel.depth_range, 1.0, self.data.reverse_z),
+            };
+            return Some((views, projs.map(|p| remap * p)));
+        }
+        self.stereo.map(|s| s.eyes(view, proj))
+    }
+
+    /// The entities drawn for both eyes: those in the world layer, but not
+    /// overlays, which draw over the mono view's depth.
+    fn stereo_mask(&self) -> u64 {
+        self.entities
+            .iter()
+            .take(self.models.min(64))
+            .enumerate()
+            .filter(|(_, e)| e.layer == CameraLayer::World && !e.order.queue.clears_depth())
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// The entities culled and drawn on the GPU: dynamic ones in the world
+    /// layer's opaque queues drawing the scene model without a material. None while a viewport
+    /// shows another camera, since only the active camera's frustum is
+    /// culled against.
+    fn gpu_culled_mask(&self) -> u64 {
+        if !self.gpu_culling
+            || self.data.gpu_culling.is_empty()
+            || self.scene_variant() != SceneVariant::Shaded
+            || viewports(&self.data).iter().any(|v| self.shows_other_camera(v))
+        {
+            return 0;
+        }
+        self.entities
+            .iter()
+            .take(self.models.min(64))
+            .enumerate()
+            .filter(|(_, e)| !e.is_static() && e.layer == CameraLayer::World && e.order.queue.is_opaque())
+            .filter(|(_, e)| entity_mesh(&self.data, e).is_none() && e.material.is_none())
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// The entities the depth pre-pass draws: those the scene draws one by
+    /// one in the world layer's opaque queues, lit. None until both pre-pass
+    /// pipelines have compiled.
+    fn prepass_mask(&self, visible: u64, statics: u64, gpu_culled: u64) -> u64 {
+        if !self.depth_prepass
+            || !self.data.depth_prepass_pipeline.is_ready()
+            || !self.data.depth_equal_pipeline.is_ready()
+            || self.scene_variant() != SceneVariant::Shaded
+            || self.mesh_shading_active()
+        {
+            return 0;
+        }
+        let opaque = self
+            .entities
+            .iter()
+            .take(self.models.min(64))
+            .enumerate()
+            .filter(|(_, e)| e.layer == CameraLayer::World && e.order.queue.is_opaque())
+            .filter(|(_, e)| self.data.materials.shading(e.material) == Shading::Lit)
+            .fold(0, |mask, (i, _)| mask | (1 << i));
+        (visible | statics) & opaque & !gpu_culled & !self.batched_mask(statics)
+    }
+
+    /// Records the depth pre-pass of the entities in `mask`, which has to
+    /// come before any of their color draws.
+    unsafe fn update_depth_prepass_command_buffer(&self, image_index: usize, mask: u64) -> vk::CommandBuffer {
+        let command_buffer = self.data.depth_prepass_command_buffers[image_index];
+        let entities = (0..self.models.min(64)).filter(|i| mask & (1 << i) != 0).collect::<Vec<_>>();
+        let recorder = SceneRecorder {
+            pipeline: self.data.depth_prepass_pipeline.pipeline,
+            materials: false,
+            ..self.scene_recorder(image_index)
+        };
+        recorder.record(command_buffer, &entities, false);
+        command_buffer
+    }
+
+    /// Writes the entities in `mask` to this image's culling object buffer.
+    unsafe fn upload_gpu_culling(&mut self, image_index: usize, mask: u64) {
+        let objects = (0..self.models.min(64))
+            .filter(|i| mask & (1 << i) != 0)
+            .map(|i| {
+                let entity = &self.entities[i];
+                let bounds = self.data.model_bounds.transformed(&entity.transform);
+                GpuObject {
+                    model: entity.transform,
+                    sphere: bounds.center.extend(bounds.radius),
+                    instance: entity.layer as u32 | self.joint_offsets.get(i).copied().unwrap_or(0) << 1,
+                    texture: resolve_texture(&self.data, entity.texture),
+                    opacity: entity.opacity,
+                    lod: self.lod_levels.get(i).copied().unwrap_or(0) as u32,
+                    ambient: self.light_probes.sample(entity.transform.w.truncate()).irradiance(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut culling = self.data.gpu_culling[image_index];
+        culling.upload(&self.instance, &self.device, &self.data, &objects).unwrap();
+        self.data.gpu_culling[image_index] = culling;
+    }
+
+    /// The drawn entities in the viewmodel layer.
+    fn viewmodel_mask(&self) -> u64 {
+        self.entities
+            .iter()
+            .take(self.models.min(64))
+            .enumerate()
+            .filter(|(_, e)| e.layer == CameraLayer::Viewmodel)
+            .fold(0, |mask, (i, _)| mask | (1 << i))
+    }
+
+    /// The drawn entities in `visible`, in draw order. The viewmodel layer
+    /// is drawn after the world.
+    fn draw_order(&self, visible: u64) -> Vec<usize> {
+        let (view, _) = self.camera();
+        let mut keys = (0..self.models.min(64))
+            .filter(|i| visible & (1 << i) != 0)
+            .map(|i| {
+                let entity = &self.entities[i];
+                let depth = match entity.layer {
+                    CameraLayer::World => -(view * entity.transform.w).z,
+                    CameraLayer::Viewmodel => -entity.transform.w.z,
+                };
+                let group = self.data.materials.draw_group(entity.material);
+                ((entity.layer, entity.order.draw_key(group, depth)), i)
+            })
+            .collect::<Vec<_>>();
+
+        // Stable, so equal keys keep their entity order.
+        keys.sort_by_key(|(key, _)| *key);
+        keys.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// Draws and presents a frame. The CPU only waits on the frame timeline,
+    /// or its fences without timeline semaphores, for the frame that last
+    /// used this frame's acquire semaphore and the one that last drew to the
+    /// acquired image, so up to `frames_in_flight` frames overlap. Idle waits are left to swapchain recreation, resource
+    /// replacement and shutdown.
     pub unsafe fn render(&mut self, window: &Window) -> Result<()> {
-        let in_flight_fence = self.data.in_flight_fences[self.frame];
-        self.device
-            .wait_for_fences(&[in_flight_fence], true, u64::MAX)
-            .unwrap();
+        if self.data.suspended {
+            return Ok(());
+        }
+
+        if self.window_mode != self.data.window_mode
+            || self.fullscreen_resolution != self.data.fullscreen_resolution
+        {
+            self.data.window_mode = self.window_mode;
+            self.data.fullscreen_resolution = self.fullscreen_resolution;
+            apply_window_mode(window, self.window_mode, self.fullscreen_resolution);
+            // Swapchains are created allowing exclusive access or not.
+            return self.recreate_swapchain(window);
+        }
+
+        if self.resolve_mode != self.data.resolve_mode
+            || self.srgb_swapchain != self.data.srgb_swapchain
+            || self.reverse_z != self.data.reverse_z
+            || self.order_independent_transparency != self.data.order_independent_transparency
+            || self.min_swapchain_images != self.data.min_swapchain_images
+            || self.swapchain_color_space != self.data.preferred_color_space
+        {
+            return self.recreate_swapchain(window);
+        }
+
+        let frames_in_flight = self.frames_in_flight.clamp(1, MAX_FRAMES_IN_FLIGHT);
+        if frames_in_flight != self.data.frames_in_flight {
+            self.set_frames_in_flight(frames_in_flight);
+        }
+
+        // This frame's acquire semaphore was last used `frames_in_flight`
+        // frames ago.
+        let frame_number = self.data.frame_number + 1;
+        wait_for_frame(
+            &self.device,
+            &self.data,
+            frame_number.saturating_sub(self.data.frames_in_flight as u64),
+        )
+        .unwrap();
 
         let result = self.device.acquire_next_image_khr(
             self.data.swapchain,
@@ -111,335 +1963,1333 @@ impl App {
             Err(e) => return Err(anyhow!(e)),
         };
 
-        let image_in_flight = self.data.images_in_flight[image_index];
-        if !image_in_flight.is_null() {
-            self.device.wait_for_fences(&[image_in_flight], true, u64::MAX).unwrap();
+        wait_for_frame(&self.device, &self.data, self.data.image_frames[image_index]).unwrap();
+        self.data.image_frames[image_index] = frame_number;
+
+        for window in &mut self.windows {
+            window.acquire(&self.instance, &self.device, &self.data, self.frame, frame_number)?;
+        }
+
+        // Waits for the headset's next frame, which paces the window's.
+        if let Some(xr) = &mut self.xr {
+            self.xr_frame = xr.begin_frame()?;
+        }
+
+        self.update_exposure(image_index);
+        self.update_focus_blur();
+        self.update_scene_watch();
+        self.update_entities();
+        self.update_particles();
+        self.update_visibility();
+        self.update_lods();
+        self.update_memory_budget();
+        self.update_texture_streaming();
+        self.update_assets();
+        self.update_pipelines();
+        self.update_gpu_particles();
+        self.update_animations();
+        self.update_skinning(image_index);
+        write_material_blocks(&self.device, &self.data, image_index);
+        self.update_command_buffer(image_index).unwrap();
+        self.update_uniform_buffer(image_index).unwrap();
+
+        let mut waits = vec![SemaphoreSubmit::binary(
+            self.data.image_available_semaphore[self.frame],
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        )];
+        let mut signals = vec![SemaphoreSubmit::binary(
+            self.data.render_finished_semaphore[image_index],
+            vk::PipelineStageFlags::ALL_COMMANDS,
+        )];
+        for (acquire, present) in self.windows.iter().filter_map(|w| w.semaphores()) {
+            waits.push(SemaphoreSubmit::binary(acquire, vk::PipelineStageFlags::TRANSFER));
+            signals.push(SemaphoreSubmit::binary(present, vk::PipelineStageFlags::ALL_COMMANDS));
+        }
+        if self.data.capabilities.timeline_semaphore {
+            signals.push(SemaphoreSubmit::timeline(
+                self.data.frame_timeline,
+                frame_number,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+            ));
+        }
+
+        let fence = frame_fence(&self.device, &self.data, frame_number).unwrap();
+        queue_submit(
+            &self.device,
+            &self.data,
+            self.data.graphics_queue,
+            &[self.data.command_buffers[image_index]],
+            &waits,
+            &signals,
+            fence,
+        )
+        .unwrap();
+        self.data.frame_number = frame_number;
+
+        if let (Some(xr), Some(xr_frame)) = (&mut self.xr, self.xr_frame.take()) {
+            xr.end_frame(xr_frame, &self.data)?;
+        }
+
+        let present_semaphores = &[self.data.render_finished_semaphore[image_index]];
+        let swapchains = &[self.data.swapchain];
+        let image_indices = &[image_index as u32];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(present_semaphores)
+            .swapchains(swapchains)
+            .image_indices(image_indices);
+
+        let result = self
+            .device
+            .queue_present_khr(self.data.present_queue, &present_info);
+        let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
+            || result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+
+        for window in &mut self.windows {
+            window.present(&self.device, &self.data)?;
+        }
+
+        if self.resized || changed {
+            self.resized = false;
+            self.recreate_swapchain(window).unwrap();
+        } else if let Err(e) = result {
+            return Err(anyhow!(e));
+        }
+
+        self.debug_draw.clear();
+        self.sprite_batch.clear();
+
+        self.frame = (self.frame + 1) % self.data.frames_in_flight;
+        Ok(())
+    }
+
+    /// Replaces the acquire semaphores with `frames_in_flight` new ones.
+    unsafe fn set_frames_in_flight(&mut self, frames_in_flight: usize) {
+        self.device.device_wait_idle().unwrap();
+        self.data
+            .image_available_semaphore
+            .drain(..)
+            .for_each(|s| self.device.destroy_semaphore(s, None));
+
+        self.data.frames_in_flight = frames_in_flight;
+        create_acquire_semaphores(&self.device, &mut self.data).unwrap();
+        for window in &mut self.windows {
+            window.create_acquire_semaphores(&self.device, &self.data);
+        }
+        self.frame = 0;
+        info!("Allowing {} frames in flight.", frames_in_flight);
+    }
+
+    unsafe fn update_command_buffer(&mut self, image_index: usize) -> Result<()> {
+        // Reset
+
+        let statics = self.static_mask();
+        let visible = self.visible_mask() & !self.impostors;
+        let gpu_culled = self.gpu_culled_mask() & !self.impostors;
+        let scene_key = SceneKey {
+            models: self.models,
+            statics,
+            visible,
+            gpu_culled,
+            prepassed: self.prepass_mask(visible, statics, gpu_culled),
+            mesh_shading: self.mesh_shading_active(),
+            lods: self.lod_mask(),
+            viewmodels: self.viewmodel_mask(),
+            viewmodel_depth: self.viewmodel.depth,
+            show_grid: self.show_grid,
+            wireframe: self.wireframe,
+            debug_view: self.debug_view,
+        };
+        let cached = self.static_scene && self.data.recorded_scenes[image_index] == Some(scene_key);
+
+        if !self.static_scene {
+            let command_pools = self.data.recording_pools[image_index]
+                .iter()
+                .chain(&self.data.command_pools[image_index..image_index + 1]);
+            for command_pool in command_pools {
+                self.device
+                    .reset_command_pool(*command_pool, vk::CommandPoolResetFlags::empty())
+                    .unwrap();
+            }
+            self.data.recorded_scenes[image_index] = None;
+        }
+
+        let command_buffer = self.data.command_buffers[image_index];
+
+        // Commands
+
+        let info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+        self.device
+            .begin_command_buffer(command_buffer, &info)
+            .unwrap();
+
+        if let (Some(emitter), Some(mut particles)) = (self.gpu_particles, self.data.gpu_particles.take()) {
+            particles.record_simulation(&self.device, &self.data, command_buffer, &emitter, self.particle_step);
+            self.data.gpu_particles = Some(particles);
+        }
+
+        if self.ray_tracing_active() {
+            // Viewmodels and overlays are drawn from their own cameras, so
+            // neither cast nor receive the world's shadows.
+            let transforms = self
+                .entities
+                .iter()
+                .take(self.models)
+                .filter(|e| e.layer == CameraLayer::World && entity_mesh(&self.data, e).is_none())
+                .map(|e| e.transform)
+                .collect::<Vec<_>>();
+            let mut tlas = self.data.scene_tlas[image_index];
+            tlas.record_build(&self.instance, &self.device, &self.data, command_buffer, &transforms).unwrap();
+            self.data.scene_tlas[image_index] = tlas;
+        }
+
+        if let Some(sun) = self.sun.filter(|_| self.ray_traced_shadows_active()) {
+            self.data.shadow_masks[image_index].record_trace(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.data.scene_tlas[image_index].tlas.handle,
+                self.camera(),
+                &sun,
+            );
+        }
+
+        if let Some(radius) = self.ray_traced_occlusion_radius() {
+            let (view, proj) = self.camera();
+            let mut target = self.data.occlusion_targets[image_index];
+            target.record_trace(
+                &self.instance,
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.data.scene_tlas[image_index].tlas.handle,
+                (view, proj),
+                self.occlusion_view_proj,
+                radius,
+            );
+            self.data.occlusion_targets[image_index] = target;
+            self.occlusion_view_proj = Some(proj * view);
+        } else {
+            self.occlusion_view_proj = None;
+        }
+
+        if self.stereo_active() {
+            self.data.stereo_target.begin(&self.device, &self.data, command_buffer);
+            let recorder = SceneRecorder {
+                pipeline: self.data.stereo_pipeline,
+                transparent_pipeline: self.data.stereo_transparent_pipeline,
+                debug_view: DebugView::None,
+                mesh_shading: false,
+                materials: false,
+                ..self.scene_recorder(image_index)
+            };
+            recorder.record_draws(command_buffer, &self.draw_order(self.stereo_mask()), 0);
+            self.device.cmd_end_render_pass(command_buffer);
+
+            if let (Some(xr), Some(xr_frame)) = (&self.xr, &self.xr_frame) {
+                xr.record_copy(&self.device, &self.data, command_buffer, xr_frame);
+            }
+        }
+
+        if scene_key.gpu_culled != 0 {
+            self.upload_gpu_culling(image_index, scene_key.gpu_culled);
+            let (view, proj) = self.camera();
+            self.data.gpu_culling[image_index].record_cull(
+                &self.device,
+                &self.data,
+                command_buffer,
+                &Frustum::new(proj * view),
+            );
+        }
+
+        begin_pass(
+            &self.device,
+            &self.data,
+            command_buffer,
+            Pass::Scene,
+            image_index,
+            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+        );
+
+        let mut secondary_command_buffers = if cached {
+            self.data.scene_command_buffers[image_index].clone()
+        } else {
+            let mut scene_command_buffers = vec![];
+            if scene_key.prepassed != 0 {
+                scene_command_buffers.push(self.update_depth_prepass_command_buffer(image_index, scene_key.prepassed));
+            }
+            if scene_key.statics != 0 {
+                scene_command_buffers.push(self.update_static_command_buffer(image_index, scene_key).unwrap());
+            }
+
+            // The grid goes between the world's opaque and transparent
+            // queues. Depth is cleared by the first overlay draw of each
+            // layer, and by the first viewmodel draw unless the depth range
+            // is split instead.
+            let mut draws = vec![];
+            let mut grid_at = None;
+            let mut layer = CameraLayer::World;
+            let mut overlay = false;
+            for i in self.draw_order(scene_key.visible & !scene_key.statics & !scene_key.gpu_culled) {
+                let queue = self.entities[i].order.queue;
+                let entity_layer = self.entities[i].layer;
+                if grid_at.is_none() && (entity_layer != CameraLayer::World || !queue.is_opaque()) {
+                    grid_at = Some(draws.len());
+                }
+
+                let mut clear_depth = false;
+                if entity_layer != layer {
+                    layer = entity_layer;
+                    overlay = false;
+                    clear_depth = self.viewmodel.depth == ViewmodelDepth::Clear;
+                }
+                if queue.clears_depth() && !overlay {
+                    overlay = true;
+                    clear_depth = true;
+                }
+                draws.push((i, clear_depth));
+            }
+
+            let mut command_buffers = std::mem::take(&mut self.data.secondary_command_buffers[image_index]);
+            let recorder = SceneRecorder { prepassed: scene_key.prepassed, ..self.scene_recorder(image_index) };
+            let entity_command_buffers = recorder.record_parallel(&draws, &mut command_buffers);
+            self.data.secondary_command_buffers[image_index] = command_buffers;
+
+            let grid_at = grid_at.unwrap_or(draws.len());
+            scene_command_buffers.extend_from_slice(&entity_command_buffers[..grid_at]);
+            if self.show_grid {
+                scene_command_buffers.push(self.update_grid_command_buffer(image_index).unwrap());
+            }
+            scene_command_buffers.extend_from_slice(&entity_command_buffers[grid_at..]);
+
+            if self.static_scene {
+                debug!("Recorded scene command buffers for image {}.", image_index);
+                self.data.recorded_scenes[image_index] = Some(scene_key);
+                self.data.scene_command_buffers[image_index] = scene_command_buffers.clone();
+            }
+
+            scene_command_buffers
+        };
+
+        // Recorded every frame since the set may move when the buffers grow.
+        // Opaque world entities only, so ahead of everything else is fine,
+        // the depth pre-pass included: neither writes over the other's depth
+        // where it is nearer.
+        if scene_key.gpu_culled != 0 {
+            let command_buffer = self.data.indirect_command_buffers[image_index];
+            begin_secondary(&self.device, &self.data, command_buffer, Pass::Scene, image_index).unwrap();
+            self.data.gpu_culling[image_index].record_draw(
+                &self.device,
+                &self.data,
+                command_buffer,
+                image_index,
+                self.debug_view,
+            );
+            self.device.end_command_buffer(command_buffer).unwrap();
+            secondary_command_buffers.insert(0, command_buffer);
+        }
+
+        if !self.debug_draw.is_empty() {
+            secondary_command_buffers.push(self.update_debug_command_buffer(image_index).unwrap());
+        }
+
+        if !self.billboards.is_empty() || self.impostors != 0 {
+            secondary_command_buffers.push(self.update_billboard_command_buffer(image_index).unwrap());
+        }
+
+        if self.particles.iter().any(|e| !e.particles().is_empty()) {
+            secondary_command_buffers.push(self.update_particle_command_buffer(image_index).unwrap());
+        }
+
+        if let (Some(emitter), Some(particles)) = (&self.gpu_particles, &self.data.gpu_particles) {
+            let command_buffer = self.data.gpu_particle_command_buffers[image_index];
+            begin_secondary(&self.device, &self.data, command_buffer, Pass::Scene, image_index).unwrap();
+            particles.record_draw(&self.device, &self.data, command_buffer, image_index, emitter);
+            self.device.end_command_buffer(command_buffer).unwrap();
+            secondary_command_buffers.push(command_buffer);
+        }
+
+        self.device
+            .cmd_execute_commands(command_buffer, &secondary_command_buffers[..]);
+
+        end_pass(&self.device, &self.data, command_buffer, Pass::Scene, image_index);
+
+        begin_pass(
+            &self.device,
+            &self.data,
+            command_buffer,
+            Pass::Decals,
+            image_index,
+            vk::SubpassContents::INLINE,
+        );
+        record_decals(&self.device, &self.data, command_buffer, image_index, &self.decals);
+        end_pass(&self.device, &self.data, command_buffer, Pass::Decals, image_index);
+
+        if self.data.order_independent_transparency {
+            begin_pass(
+                &self.device,
+                &self.data,
+                command_buffer,
+                Pass::OitComposite,
+                image_index,
+                vk::SubpassContents::INLINE,
+            );
+            record_oit_composite(&self.device, &self.data, command_buffer);
+            end_pass(&self.device, &self.data, command_buffer, Pass::OitComposite, image_index);
+        }
+
+        // Carries on from the exposure the scene was last drawn with, which
+        // the histogram pass adapts for the tonemap pass.
+        if self.histogram_active() {
+            let now = self.start.elapsed().as_secs_f32();
+            let (reset, elapsed) = if self.adapting_on_gpu {
+                (None, now - self.metered_at)
+            } else {
+                (Some(self.exposure), 0.0)
+            };
+            let blend = 1.0 - (-elapsed * self.auto_exposure.speed).exp();
+            let push_constants = HistogramPushConstants::new(&self.data, &self.auto_exposure, blend);
+            record_histogram(&self.device, &self.data, command_buffer, image_index, &push_constants, reset);
+            self.data.exposure_readbacks[image_index].pending = true;
+            self.metered_at = now;
+        }
+        self.adapting_on_gpu = self.histogram_active();
+
+        // Otherwise the HDR scene is metered here and read back, unless its
+        // color never left the render pass.
+        let meter_hdr = self.data.dynamic_rendering && !self.stereo_active();
+        let metering = self.data.metering.get(image_index).filter(|_| !self.adapting_on_gpu);
+        if let Some(metering) = metering.filter(|_| meter_hdr) {
+            metering.record_hdr(&self.device, &self.data, command_buffer, &self.auto_exposure);
+            self.data.metering[image_index].pending = Some(self.exposure);
+        }
+
+        begin_pass(
+            &self.device,
+            &self.data,
+            command_buffer,
+            Pass::Tonemap,
+            image_index,
+            vk::SubpassContents::INLINE,
+        );
+        self.record_tonemap(command_buffer);
+        if self.stereo_active() {
+            self.data.stereo_target.record_mirror(&self.device, &self.data, command_buffer);
+        }
+        end_pass(&self.device, &self.data, command_buffer, Pass::Tonemap, image_index);
+
+        let metering = self.data.metering.get(image_index).filter(|_| !self.adapting_on_gpu);
+        if let Some(metering) = metering.filter(|_| !meter_hdr && self.data.metering_supported) {
+            metering.record(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.data.swapchain_images[image_index],
+                &self.auto_exposure,
+            );
+            self.data.metering[image_index].pending = Some(self.exposure);
+        }
+
+        let focus_blur = if self.focus_amount > 0.0 {
+            self.data.focus_blur_targets.get(image_index).copied()
+        } else {
+            None
+        };
+        if let Some(target) = focus_blur {
+            target.record(
+                &self.device,
+                &self.data,
+                command_buffer,
+                self.data.swapchain_images[image_index],
+            );
+        }
+
+        let mut overlay_command_buffers = vec![];
+        if let Some(target) = focus_blur {
+            overlay_command_buffers.push(self.update_focus_blur_command_buffer(image_index, target).unwrap());
+        }
+
+        if !self.sprite_batch.is_empty() {
+            overlay_command_buffers.push(self.update_sprite_command_buffer(image_index).unwrap());
+        }
+
+        if !overlay_command_buffers.is_empty() {
+            begin_pass(
+                &self.device,
+                &self.data,
+                command_buffer,
+                Pass::Overlay,
+                image_index,
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            );
+            self.device
+                .cmd_execute_commands(command_buffer, &overlay_command_buffers[..]);
+            end_pass(&self.device, &self.data, command_buffer, Pass::Overlay, image_index);
+        }
+
+        for window in &self.windows {
+            window.record(&self.device, &self.data, command_buffer, self.data.swapchain_images[image_index]);
+        }
+
+        self.device.end_command_buffer(command_buffer).unwrap();
+
+        Ok(())
+    }
+
+    /// Records every static entity into one secondary command buffer that is
+    /// kept until the static set, or anything else it depends on, changes.
+    unsafe fn update_static_command_buffer(
+        &mut self,
+        image_index: usize,
+        scene_key: SceneKey,
+    ) -> Result<vk::CommandBuffer> {
+        let command_buffer = self.data.static_command_buffers[image_index];
+        if self.data.recorded_static_batches[image_index] == Some(scene_key) {
+            return Ok(command_buffer);
+        }
+
+        // The camera moves without re-recording, so only the draw order and
+        // materials order the batch. Baked batches come first.
+        let batches = self.data.static_batches.current(scene_key.statics);
+        let batched = self.batched_mask(scene_key.statics);
+        let mut statics = (0..self.models.min(64))
+            .filter(|i| scene_key.statics & !batched & (1 << i) != 0)
+            .collect::<Vec<_>>();
+        statics.sort_by_key(|i| {
+            let entity = &self.entities[*i];
+            entity.order.draw_key(self.data.materials.draw_group(entity.material), 0.0)
+        });
+        let recorder = SceneRecorder {
+            prepassed: scene_key.prepassed,
+            batches: &batches,
+            ..self.scene_recorder(image_index)
+        };
+        recorder.record(command_buffer, &statics, false);
+
+        debug!(
+            "Recorded {} static entities and {} baked batches for image {}.",
+            statics.len(),
+            batches.len(),
+            image_index,
+        );
+        self.data.recorded_static_batches[image_index] = Some(scene_key);
+        Ok(command_buffer)
+    }
+
+    /// Borrows what recording scene entities for `image_index` needs.
+    fn scene_recorder(&self, image_index: usize) -> SceneRecorder<'_> {
+        let pipeline = match self.scene_variant() {
+            SceneVariant::Shaded => self.data.pipeline,
+            SceneVariant::Overdraw => self.data.overdraw_pipeline.get(self.data.pipeline),
+            SceneVariant::Wireframe => self.data.wireframe_pipeline.get(self.data.pipeline),
+            SceneVariant::Transparent | SceneVariant::DepthPrepass | SceneVariant::DepthEqual => unreachable!(),
+        };
+        // The debug variants draw every queue the same way.
+        let transparent_pipeline = match self.scene_variant() {
+            SceneVariant::Shaded => self.data.transparent_pipeline,
+            _ => pipeline,
+        };
+
+        let (view, _) = self.camera();
+        SceneRecorder {
+            device: &self.device,
+            data: &self.data,
+            entities: &self.entities,
+            light_probes: &self.light_probes,
+            pipeline,
+            transparent_pipeline,
+            prepassed: 0,
+            debug_view: self.debug_view,
+            inverse_view: view.invert().unwrap(),
+            joint_offsets: &self.joint_offsets,
+            lods: &self.lod_levels,
+            batches: &[],
+            mesh_shading: self.mesh_shading_active(),
+            materials: self.scene_variant() == SceneVariant::Shaded,
+            image_index,
         }
+    }
 
-        self.data.images_in_flight[image_index] = in_flight_fence;
+    /// Draws every live particle, sorted back to front.
+    unsafe fn update_particle_command_buffer(&mut self, image_index: usize) -> Result<vk::CommandBuffer> {
+        let mut vertices = vec![];
+        self.particles.iter().for_each(|e| e.vertices(&mut vertices));
+        sort_quads(&mut vertices, self.camera().0, |v| v.pos);
 
-        self.update_command_buffer(image_index).unwrap();
-        self.update_uniform_buffer(image_index).unwrap();
+        let mut vertex_buffer = self.data.particle_vertex_buffers[image_index];
+        update_dynamic_buffer(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut vertex_buffer,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &vertices,
+        )
+        .unwrap();
+        self.data.particle_vertex_buffers[image_index] = vertex_buffer;
 
-        let wait_semaphores = &[self.data.image_available_semaphore[self.frame]];
-        let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let command_buffers = &[self.data.command_buffers[image_index]];
-        let signal_semaphores = &[self.data.render_finished_semaphore[self.frame]];
-        let submit_info = vk::SubmitInfo::builder()
-            .wait_semaphores(wait_semaphores)
-            .wait_dst_stage_mask(wait_stages)
-            .command_buffers(command_buffers)
-            .signal_semaphores(signal_semaphores);
+        let command_buffer = self.data.particle_command_buffers[image_index];
 
-        self.device
-            .reset_fences(&[in_flight_fence])
-            .unwrap();
+        begin_secondary(&self.device, &self.data, command_buffer, Pass::Scene, image_index).unwrap();
 
-        self.device
-            .queue_submit(
-                self.data.graphics_queue,
-                &[submit_info],
-                in_flight_fence
-            )
-            .unwrap();
+        self.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.particle_pipeline,
+        );
+        self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+        record_viewports(&self.device, &self.data, command_buffer, |viewport| {
+            bind_scene_descriptors(&self.device, &self.data, command_buffer, image_index, viewport);
+            self.device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+        });
 
-        let swapchains = &[self.data.swapchain];
-        let image_indices = &[image_index as u32];
-        let present_info = vk::PresentInfoKHR::builder()
-            .wait_semaphores(signal_semaphores)
-            .swapchains(swapchains)
-            .image_indices(image_indices);
+        self.device.end_command_buffer(command_buffer).unwrap();
 
-        let result = self
-            .device
-            .queue_present_khr(self.data.present_queue, &present_info);
-        let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
-            || result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+        Ok(command_buffer)
+    }
 
-        if self.resized || changed {
-            self.resized = false;
-            self.recreate_swapchain(window).unwrap();
-        } else if let Err(e) = result {
-            return Err(anyhow!(e));
+    /// Draws `billboards` and the entities drawn as the impostor, sorted
+    /// back to front.
+    unsafe fn update_billboard_command_buffer(&mut self, image_index: usize) -> Result<vk::CommandBuffer> {
+        let (view, _) = self.camera();
+        let mut billboards = self.billboards.clone();
+        if let Some(impostor) = &self.impostor {
+            let eye = view.invert().unwrap().w.truncate();
+            billboards.extend(
+                (0..self.models.min(64))
+                    .filter(|i| self.impostors & (1 << i) != 0)
+                    .map(|i| impostor.billboard(self.entities[i].transform, eye)),
+            );
+        }
+        let mut vertices = vec![];
+        billboard_vertices(&self.data, &billboards, &mut vertices);
+        if !self.data.order_independent_transparency {
+            sort_quads(&mut vertices, view, |v| v.pos);
         }
-        self.device
-            .queue_wait_idle(self.data.present_queue)
-            .unwrap();
 
-        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
-        Ok(())
-    }
+        let mut vertex_buffer = self.data.billboard_vertex_buffers[image_index];
+        update_dynamic_buffer(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut vertex_buffer,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &vertices,
+        )
+        .unwrap();
+        self.data.billboard_vertex_buffers[image_index] = vertex_buffer;
 
-    unsafe fn update_command_buffer(&mut self, image_index: usize) -> Result<()> {
-        // Reset
+        let command_buffer = self.data.billboard_command_buffers[image_index];
 
-        let command_pool = self.data.command_pools[image_index];
-        self.device
-            .reset_command_pool(command_pool, vk::CommandPoolResetFlags::empty())
-            .unwrap();
+        begin_secondary(&self.device, &self.data, command_buffer, Pass::Scene, image_index).unwrap();
 
-        let command_buffer = self.data.command_buffers[image_index];
+        self.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.billboard_pipeline,
+        );
+        self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+        self.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.pipeline_layout,
+            1,
+            &[self.data.texture_descriptor_set],
+            &[],
+        );
+        record_viewports(&self.device, &self.data, command_buffer, |viewport| {
+            bind_scene_descriptors(&self.device, &self.data, command_buffer, image_index, viewport);
+            self.device.cmd_draw(command_buffer, vertices.len() as u32, 1, 0, 0);
+        });
 
-        // Commands
+        self.device.end_command_buffer(command_buffer).unwrap();
 
-        let info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        Ok(command_buffer)
+    }
 
-        self.device
-            .begin_command_buffer(command_buffer, &info)
-            .unwrap();
+    unsafe fn update_debug_command_buffer(&mut self, image_index: usize) -> Result<vk::CommandBuffer> {
+        let mut vertex_buffer = self.data.debug_vertex_buffers[image_index];
+        update_dynamic_buffer(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut vertex_buffer,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &self.debug_draw.vertices,
+        )
+        .unwrap();
+        self.data.debug_vertex_buffers[image_index] = vertex_buffer;
 
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(self.data.swapchain_extent);
+        let command_buffer = self.data.debug_command_buffers[image_index];
 
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
-            },
-        };
+        begin_secondary(&self.device, &self.data, command_buffer, Pass::Scene, image_index).unwrap();
 
-        let depth_clear_value = vk::ClearValue {
-            depth_stencil: vk::ClearDepthStencilValue {
-                depth: 1.0,
-                stencil: 0,
-            },
-        };
+        self.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.debug_pipeline,
+        );
+        self.device.cmd_bind_vertex_buffers(
+            command_buffer,
+            0,
+            &[vertex_buffer.buffer],
+            &[0],
+        );
+        record_viewports(&self.device, &self.data, command_buffer, |viewport| {
+            bind_scene_descriptors(&self.device, &self.data, command_buffer, image_index, viewport);
+            self.device
+                .cmd_draw(command_buffer, self.debug_draw.vertices.len() as u32, 1, 0, 0);
+        });
+
+        self.device.end_command_buffer(command_buffer).unwrap();
+
+        Ok(command_buffer)
+    }
 
-        let clear_values = &[color_clear_value, depth_clear_value];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.data.render_pass)
-            .framebuffer(self.data.framebuffers[image_index])
-            .render_area(render_area)
-            .clear_values(clear_values);
+    unsafe fn update_sprite_command_buffer(&mut self, image_index: usize) -> Result<vk::CommandBuffer> {
+        let ui_scale = self.effective_ui_scale();
+        let (instances, draws) = self.sprite_batch.build(self.data.swapchain_extent, ui_scale);
+        let commands = draws.iter().map(|d| d.command).collect::<Vec<_>>();
+
+        let mut instance_buffer = self.data.sprite_instance_buffers[image_index];
+        update_dynamic_buffer(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut instance_buffer,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            &instances,
+        )
+        .unwrap();
+        self.data.sprite_instance_buffers[image_index] = instance_buffer;
+
+        let mut indirect_buffer = self.data.sprite_indirect_buffers[image_index];
+        update_dynamic_buffer(
+            &self.instance,
+            &self.device,
+            &self.data,
+            &mut indirect_buffer,
+            vk::BufferUsageFlags::INDIRECT_BUFFER,
+            &commands,
+        )
+        .unwrap();
+        self.data.sprite_indirect_buffers[image_index] = indirect_buffer;
+
+        let command_buffer = self.data.sprite_command_buffers[image_index];
+
+        begin_secondary(&self.device, &self.data, command_buffer, Pass::Overlay, image_index).unwrap();
 
-        self.device.cmd_begin_render_pass(
+        self.device.cmd_bind_pipeline(
             command_buffer,
-            &info,
-            vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.sprite_pipeline,
         );
 
-        let secondary_command_buffers = (0..self.models)
-            .map(|i| self.update_secondary_command_buffer(image_index, i))
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
-        self.device
-            .cmd_execute_commands(command_buffer, &secondary_command_buffers[..]);
+        let screen_size = self.ui_size();
+        let screen_size_bytes =
+            std::slice::from_raw_parts(&screen_size as *const Vec2 as *const u8, size_of::<Vec2>());
+        self.device.cmd_push_constants(
+            command_buffer,
+            self.data.sprite_pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            screen_size_bytes,
+        );
 
-        self.device.cmd_end_render_pass(command_buffer);
+        let stride = size_of::<SpriteInstance>() as u64;
+        let command_size = size_of::<vk::DrawIndirectCommand>() as u64;
+        for (index, draw) in draws.iter().enumerate() {
+            if draw.scissor.extent.width == 0 || draw.scissor.extent.height == 0 {
+                continue;
+            }
+
+            self.device.cmd_set_scissor(command_buffer, 0, &[draw.scissor]);
+            self.bind_sprite_atlas(command_buffer, draw.atlas);
+            self.device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[instance_buffer.buffer],
+                &[draw.first as u64 * stride],
+            );
+            self.device.cmd_draw_indirect(
+                command_buffer,
+                indirect_buffer.buffer,
+                index as u64 * command_size,
+                1,
+                command_size as u32,
+            );
+        }
 
         self.device.end_command_buffer(command_buffer).unwrap();
 
-        Ok(())
+        Ok(command_buffer)
     }
 
-    unsafe fn update_secondary_command_buffer(
+    /// Blends the blurred viewport over the presented one, under the UI.
+    unsafe fn update_focus_blur_command_buffer(
         &mut self,
         image_index: usize,
-        model_index: usize,
+        target: FocusBlurTarget,
     ) -> Result<vk::CommandBuffer> {
-        // Allocate
+        let command_buffer = self.data.focus_blur_command_buffers[image_index];
 
-        let command_buffers = &mut self.data.secondary_command_buffers[image_index];
-        while model_index >= command_buffers.len() {
-            let allocate_info = vk::CommandBufferAllocateInfo::builder()
-                .command_pool(self.data.command_pools[image_index])
-                .level(vk::CommandBufferLevel::SECONDARY)
-                .command_buffer_count(1);
+        begin_secondary(&self.device, &self.data, command_buffer, Pass::Overlay, image_index).unwrap();
 
-            let command_buffer = self
-                .device
-                .allocate_command_buffers(&allocate_info)
-                .unwrap()[0];
-            command_buffers.push(command_buffer);
-        }
+        self.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.focus_blur_pipeline,
+        );
+        self.device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.focus_blur_pipeline_layout,
+            0,
+            &[target.set],
+            &[],
+        );
 
-        let command_buffer = command_buffers[model_index];
+        let amount = FocusBlur::ease(self.focus_amount);
+        let extent = self.data.swapchain_extent;
+        let push_constants = FocusBlurPushConstants {
+            inverse_extent: Vec2::new(1.0 / extent.width as f32, 1.0 / extent.height as f32),
+            lod: (self.focus_blur.strength * amount).clamp(0.0, (target.mip_levels - 1) as f32),
+            dim: self.focus_blur.dim.clamp(0.0, 1.0),
+            amount,
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const FocusBlurPushConstants as *const u8,
+            size_of::<FocusBlurPushConstants>(),
+        );
+        self.device.cmd_push_constants(
+            command_buffer,
+            self.data.focus_blur_pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            push_constants_bytes,
+        );
+        self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
 
-        // Model
+        self.device.end_command_buffer(command_buffer).unwrap();
 
-        let y = (((model_index % 2) as f32) * 2.5) - 1.25;
-        let z = (((model_index / 2) as f32) * -2.0) + 1.0;
+        Ok(command_buffer)
+    }
 
-        let time = self.start.elapsed().as_secs_f32();
+    /// Binds set 0 of the scene pipeline layout, the uniform buffer for
+    /// `image_index`.
+    unsafe fn bind_sprite_atlas(&self, command_buffer: vk::CommandBuffer, atlas: SpriteAtlas) {
+        if !self.data.push_descriptors {
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.data.sprite_pipeline_layout,
+                0,
+                &[self.data.sprite_atlas_sets[atlas.0 as usize]],
+                &[],
+            );
+            return;
+        }
 
-        let model = Mat4::from_translation(vec3(0.0, y, z))
-            * Mat4::from_axis_angle(vec3(0.0, 0.0, 1.0), Deg(90.0) * time);
+        let image_info = &[self.data.sprite_atlas_images[atlas.0 as usize]];
+        let sampler_write = vk::WriteDescriptorSet::builder()
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_info);
 
-        let model_bytes =
-            std::slice::from_raw_parts(&model as *const Mat4 as *const u8, size_of::<Mat4>());
+        self.device.cmd_push_descriptor_set_khr(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.sprite_pipeline_layout,
+            0,
+            &[sampler_write],
+        );
+    }
 
-        let opacity = (model_index + 1) as f32 * 0.25;
-        let opacity_bytes = &opacity.to_ne_bytes()[..];
+    unsafe fn update_grid_command_buffer(&mut self, image_index: usize) -> Result<vk::CommandBuffer> {
+        let command_buffer = self.data.grid_command_buffers[image_index];
 
-        // Commands
+        begin_secondary(&self.device, &self.data, command_buffer, Pass::Scene, image_index).unwrap();
 
-        let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
-            .render_pass(self.data.render_pass)
-            .subpass(0)
-            .framebuffer(self.data.framebuffers[image_index]);
+        self.device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.data.grid_pipeline,
+        );
+        let push_constants = GridPushConstants {
+            up_axis: self.data.world.up_index(),
+            meters_per_unit: self.data.world.meters_per_unit,
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const GridPushConstants as *const u8,
+            size_of::<GridPushConstants>(),
+        );
+        self.device.cmd_push_constants(
+            command_buffer,
+            self.data.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            64,
+            push_constants_bytes,
+        );
+        record_viewports(&self.device, &self.data, command_buffer, |viewport| {
+            bind_scene_descriptors(&self.device, &self.data, command_buffer, image_index, viewport);
+            self.device.cmd_draw(command_buffer, 6, 1, 0, 0);
+        });
 
-        let info = vk::CommandBufferBeginInfo::builder()
-            .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
-            .inheritance_info(&inheritance_info);
+        self.device.end_command_buffer(command_buffer).unwrap();
 
-        self.device
-            .begin_command_buffer(command_buffer, &info)
-            .unwrap();
+        Ok(command_buffer)
+    }
 
+    /// Draws the HDR scene into the swapchain image in the last subpass.
+    unsafe fn record_tonemap(&self, command_buffer: vk::CommandBuffer) {
         self.device.cmd_bind_pipeline(
             command_buffer,
             vk::PipelineBindPoint::GRAPHICS,
-            self.data.pipeline,
-        );
-        self.device
-            .cmd_bind_vertex_buffers(command_buffer, 0, &[self.data.vertex_buffer], &[0]);
-        self.device.cmd_bind_index_buffer(
-            command_buffer,
-            self.data.index_buffer,
-            0,
-            vk::IndexType::UINT32,
+            self.data.tonemap_pipeline,
         );
         self.device.cmd_bind_descriptor_sets(
             command_buffer,
             vk::PipelineBindPoint::GRAPHICS,
-            self.data.pipeline_layout,
+            self.data.tonemap_pipeline_layout,
             0,
-            &[self.data.descriptor_sets[image_index]],
+            &[self.data.tonemap_descriptor_set],
             &[],
         );
-        self.device.cmd_push_constants(
-            command_buffer,
-            self.data.pipeline_layout,
-            vk::ShaderStageFlags::VERTEX,
-            0,
-            model_bytes,
+
+        let push_constants = TonemapPushConstants {
+            passthrough: (self.debug_view != DebugView::None) as u32,
+            adapted_exposure: self.adapting_on_gpu as u32,
+            _padding: [0; 2],
+            lut_scale: self.data.color_lut.scale,
+            lut_offset: self.data.color_lut.offset,
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const TonemapPushConstants as *const u8,
+            size_of::<TonemapPushConstants>(),
         );
         self.device.cmd_push_constants(
             command_buffer,
-            self.data.pipeline_layout,
+            self.data.tonemap_pipeline_layout,
             vk::ShaderStageFlags::FRAGMENT,
-            64,
-            opacity_bytes,
+            0,
+            push_constants_bytes,
         );
-        self.device
-            .cmd_draw_indexed(command_buffer, self.data.indices.len() as u32, 1, 0, 0, 0);
+        self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    }
 
-        self.device.end_command_buffer(command_buffer).unwrap();
+    /// The active camera's view and (Vulkan clip space) projection matrices.
+    fn camera(&self) -> (Mat4, Mat4) {
+        self.camera_matrices(&self.cameras[self.active_camera])
+    }
 
-        Ok(command_buffer)
+    /// Whether `viewport` shows a camera other than the active one.
+    fn shows_other_camera(&self, viewport: &Viewport) -> bool {
+        viewport.camera.is_some_and(|i| i != self.active_camera && i < self.cameras.len())
     }
 
-    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
-        let view = Mat4::look_at_rh(
-            point3::<f32>(6.0, 0.0, 2.0),
-            point3::<f32>(0.0, 0.0, 0.0),
-            vec3(0.0, 0.0, 1.0),
-        );
+    /// The camera `viewport` shows, the active one unless it names another.
+    fn viewport_camera(&self, viewport: &Viewport) -> &Camera {
+        viewport
+            .camera
+            .and_then(|i| self.cameras.get(i))
+            .unwrap_or(&self.cameras[self.active_camera])
+    }
+
+    /// `camera`'s view and (Vulkan clip space) projection matrices.
+    fn camera_matrices(&self, camera: &Camera) -> (Mat4, Mat4) {
+        let view = camera.view(&self.data.world);
+
+        let proj = self.perspective(camera.fov, camera.near, camera.projected_far());
+
+        let proj = match self.viewmodel.depth {
+            ViewmodelDepth::Clear => proj,
+            ViewmodelDepth::Remap => Viewmodel::depth_remap(self.viewmodel.depth_range, 1.0, self.data.reverse_z) * proj,
+        };
+
+        (view, proj)
+    }
+
+    /// The (Vulkan clip space) projection of the viewmodel layer.
+    fn viewmodel_projection(&self) -> Mat4 {
+        let proj = self.perspective(self.viewmodel.fov, self.viewmodel.near, self.viewmodel.far);
+
+        match self.viewmodel.depth {
+            ViewmodelDepth::Clear => proj,
+            ViewmodelDepth::Remap => Viewmodel::depth_remap(0.0, self.viewmodel.depth_range, self.data.reverse_z) * proj,
+        }
+    }
 
-        let correction = Mat4::new(
-            1.0,
+    /// A (Vulkan clip space) perspective projection filling the swapchain,
+    /// with Y down and depth in [0, 1]. `far` may be infinite. With
+    /// `reverse_z` the near and far planes swap depths, see `far_depth`.
+    fn perspective(&self, fovy: Deg<f32>, near: f32, far: f32) -> Mat4 {
+        let (near, far) = depth_planes(self.data.reverse_z, near, far);
+        let (depth_scale, depth_offset) = depth_terms(near, far);
+        let focal = 1.0 / (fovy.0.to_radians() / 2.0).tan();
+        let aspect = self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32;
+
+        Mat4::new(
+            focal / aspect,
             0.0,
             0.0,
             0.0,
             0.0,
-            -1.0,
+            -focal,
             0.0,
             0.0,
             0.0,
             0.0,
-            1.0 / 2.0,
+            depth_scale,
+            -1.0,
             0.0,
             0.0,
+            depth_offset,
             0.0,
-            1.0 / 2.0,
-            1.0,
-        );
+        )
+    }
 
-        let proj = correction
-            * cgmath::perspective(
-                Deg(45.0),
-                self.data.swapchain_extent.width as f32 / self.data.swapchain_extent.height as f32,
-                0.1,
-                10.0,
-            );
+    unsafe fn update_uniform_buffer(&self, image_index: usize) -> Result<()> {
+        let (view, proj) = self.camera();
+        let (eye_views, eye_projs) = self.eyes(view, proj).unwrap_or(([view; 2], [proj; 2]));
+        let ubo = UniformBufferObject {
+            view,
+            proj,
+            viewmodel_proj: self.viewmodel_projection(),
+            exposure: if self.histogram_active() { 1.0 } else { self.exposure },
+            near_plane: self.cameras[self.active_camera].near,
+            far_plane: self.cameras[self.active_camera].far,
+            _padding: 0.0,
+            sun_direction: self.sun.map_or(Vec4::zero(), |sun| {
+                sun.direction.normalize().extend(if self.ray_traced_shadows_active() { 1.0 } else { 0.0 })
+            }),
+            sun_illuminance: self.sun.map_or(Vec4::zero(), |sun| sun.illuminance.extend(0.0)),
+            occlusion_traced: if self.ray_traced_occlusion_radius().is_some() { 1.0 } else { 0.0 },
+            _padding_1: [0.0; 3],
+            eye_views,
+            eye_projs,
+        };
 
-        let ubo = UniformBufferObject { view, proj };
+        let stride = uniform_stride(&self.data);
+        // Each viewport's slot gets its camera and a projection for its
+        // aspect ratio.
+        let extent = self.data.swapchain_extent;
+        for (index, viewport) in viewports(&self.data).iter().enumerate() {
+            let camera = self.viewport_camera(viewport);
+            let (view, proj) = self.camera_matrices(camera);
+            let correction = viewport.aspect_correction(extent);
+            let ubo = UniformBufferObject {
+                view,
+                proj: correction * proj,
+                viewmodel_proj: correction * ubo.viewmodel_proj,
+                near_plane: camera.near,
+                far_plane: camera.far,
+                ..ubo
+            };
+            self.data.uniform_buffers_memory[image_index].write(&self.device, index as u64 * stride, &[ubo]);
+        }
 
-        let memory = self
-            .device
-            .map_memory(
-                self.data.uniform_buffers_memory[image_index],
-                0,
-                size_of::<UniformBufferObject>() as u64,
-                vk::MemoryMapFlags::empty(),
-            )
-            .unwrap();
+        Ok(())
+    }
 
-        memcpy(&ubo, memory.cast(), 1);
+    /// The scene pipeline the current debug view and wireframe setting want.
+    fn scene_variant(&self) -> SceneVariant {
+        if self.debug_view == DebugView::Overdraw {
+            SceneVariant::Overdraw
+        } else if self.wireframe && self.data.wireframe_supported {
+            SceneVariant::Wireframe
+        } else {
+            SceneVariant::Shaded
+        }
+    }
 
-        self.device
-            .unmap_memory(self.data.uniform_buffers_memory[image_index]);
+    /// Takes the pipelines that finished compiling and queues the scene
+    /// variant the current settings want, if it is not ready. The shaded
+    /// pipeline is drawn with until it is.
+    unsafe fn update_pipelines(&mut self) {
+        for (ticket, result) in self.pipeline_compiler.finished() {
+            self.accept_pipeline(ticket, result);
+        }
 
-        Ok(())
+        let mut variants = vec![self.scene_variant()];
+        if self.depth_prepass {
+            variants.extend([SceneVariant::DepthPrepass, SceneVariant::DepthEqual]);
+        }
+
+        let desc = ScenePipelineDesc::new(&self.data);
+        for variant in variants {
+            let slot = match variant {
+                SceneVariant::Shaded | SceneVariant::Transparent => continue,
+                SceneVariant::Overdraw => &mut self.data.overdraw_pipeline,
+                SceneVariant::Wireframe => &mut self.data.wireframe_pipeline,
+                SceneVariant::DepthPrepass => &mut self.data.depth_prepass_pipeline,
+                SceneVariant::DepthEqual => &mut self.data.depth_equal_pipeline,
+            };
+            if slot.is_ready() || slot.pending.is_some() {
+                continue;
+            }
+
+            debug!("Compiling the {:?} scene pipeline.", variant);
+            slot.pending = Some(self.pipeline_compiler.compile(Box::new(move |device| unsafe {
+                create_scene_pipeline(device, desc, variant)
+            })));
+        }
+    }
+
+    /// Hands a compiled pipeline to the slot waiting for it, or destroys it
+    /// if the slot has since been recreated.
+    unsafe fn accept_pipeline(&mut self, ticket: u64, result: Result<vk::Pipeline>) {
+        let pipeline = match result {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                // The slot keeps waiting, so the fallback stays in use.
+                warn!("Failed to compile a pipeline: {}", e);
+                return;
+            }
+        };
+
+        let slots = [
+            &mut self.data.overdraw_pipeline,
+            &mut self.data.wireframe_pipeline,
+            &mut self.data.depth_prepass_pipeline,
+            &mut self.data.depth_equal_pipeline,
+        ];
+        if slots.into_iter().any(|slot| slot.accept(ticket, pipeline)) {
+            // Cached scene recordings still bind the fallback.
+            self.invalidate_scene();
+        } else {
+            self.device.destroy_pipeline(pipeline, None);
+        }
     }
 
     unsafe fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
         self.device.device_wait_idle().unwrap();
         self.destroy_swapchain();
+        self.create_swapchain_objects(window)
+    }
+
+    /// Creates the swapchain and everything sized to it, with the settings
+    /// it depends on applied.
+    unsafe fn create_swapchain_objects(&mut self, window: &Window) -> Result<()> {
+        self.data.resolve_mode = self.resolve_mode;
+        self.data.srgb_swapchain = self.srgb_swapchain;
+        self.data.reverse_z = self.reverse_z;
+        self.data.order_independent_transparency = self.order_independent_transparency;
+        self.data.min_swapchain_images = self.min_swapchain_images;
+        self.data.preferred_color_space = self.swapchain_color_space;
+        // Their formats and whether they can be blitted to follow the main
+        // window's.
+        self.windows.iter_mut().for_each(|w| w.resized = true);
         create_swapchain(window, &self.instance, &self.device, &mut self.data).unwrap();
         create_swapchain_image_views(&self.device, &mut self.data).unwrap();
-        create_render_pass(&self.instance, &self.device, &mut self.data).unwrap();
+        info!(
+            "Presenting {} {:?} images in {:?} with {:?}.",
+            self.data.swapchain_images.len(),
+            self.data.swapchain_format,
+            self.data.swapchain_color_space,
+            self.data.resolve_mode,
+        );
+        create_render_pass(&self.device, &mut self.data).unwrap();
+        create_overlay_render_pass(&self.device, &mut self.data).unwrap();
+        create_stereo_render_pass(&self.device, &mut self.data).unwrap();
         create_pipeline(&self.device, &mut self.data).unwrap();
+        create_indirect_pipeline(&self.device, &mut self.data).unwrap();
+        create_mesh_pipeline(&self.device, &mut self.data).unwrap();
+        create_debug_pipeline(&self.device, &mut self.data).unwrap();
+        create_particle_pipeline(&self.device, &mut self.data).unwrap();
+        create_billboard_pipeline(&self.device, &mut self.data).unwrap();
+        create_grid_pipeline(&self.device, &mut self.data).unwrap();
+        create_sprite_pipeline(&self.device, &mut self.data).unwrap();
+        create_tonemap_pipeline(&self.device, &mut self.data).unwrap();
+        create_oit_composite_pipeline(&self.device, &mut self.data).unwrap();
+        create_decal_pipeline(&self.device, &mut self.data).unwrap();
+        create_focus_blur_pipeline(&self.device, &mut self.data).unwrap();
+        create_stereo_pipelines(&self.device, &mut self.data).unwrap();
+        create_histogram_pipelines(&self.device, &mut self.data).unwrap();
+        create_hdr_metering_pipeline(&self.device, &mut self.data).unwrap();
         create_color_objects(&self.instance, &self.device, &mut self.data).unwrap();
+        create_resolve_objects(&self.instance, &self.device, &mut self.data).unwrap();
         create_depth_objects(&self.instance, &self.device, &mut self.data).unwrap();
+        create_oit_objects(&self.instance, &self.device, &mut self.data).unwrap();
         create_framebuffers(&self.device, &mut self.data).unwrap();
+        create_tonemap_descriptor_set(&self.device, &mut self.data).unwrap();
+        create_histogram_descriptor_set(&self.instance, &self.device, &mut self.data).unwrap();
+        create_oit_descriptor_set(&self.device, &mut self.data).unwrap();
+        create_decal_descriptor_set(&self.device, &mut self.data).unwrap();
         create_uniform_buffers(&self.instance, &self.device, &mut self.data).unwrap();
-        create_descriptor_pool(&self.device, &mut self.data).unwrap();
+        create_joint_buffers(&self.instance, &self.device, &mut self.data).unwrap();
+        create_material_buffers(&self.instance, &self.device, &mut self.data).unwrap();
+        create_debug_vertex_buffers(&mut self.data).unwrap();
+        create_particle_vertex_buffers(&mut self.data).unwrap();
+        create_billboard_vertex_buffers(&mut self.data).unwrap();
+        create_sprite_buffers(&mut self.data).unwrap();
+        create_shadow_masks(&self.instance, &self.device, &mut self.data).unwrap();
+        create_occlusion_targets(&self.instance, &self.device, &mut self.data).unwrap();
+        self.occlusion_view_proj = None;
         create_descriptor_sets(&self.device, &mut self.data).unwrap();
+        create_metering(&self.instance, &self.device, &mut self.data).unwrap();
+        create_focus_blur_targets(&self.instance, &self.device, &mut self.data).unwrap();
+        create_stereo_target(&self.instance, &self.device, &mut self.data).unwrap();
+        create_gpu_culling(&self.instance, &self.device, &mut self.data).unwrap();
+        create_scene_tlas(&mut self.data).unwrap();
+        resize_command_pools(&self.instance, &self.device, &mut self.data).unwrap();
         create_command_buffers(&self.device, &mut self.data).unwrap();
-        self.data
-            .images_in_flight
-            .resize(self.data.swapchain_images.len(), vk::Fence::null());
+        create_present_semaphores(&self.device, &mut self.data).unwrap();
         Ok(())
     }
 
+    /// Whether the device was created with `feature`. Optional features are
+    /// enabled wherever the device supports them.
+    pub fn device_feature(&self, feature: DeviceFeature) -> bool {
+        self.data.device_features.contains(feature)
+    }
+
+    /// The color space the swapchain presents in, which is `Srgb` when the
+    /// display does not offer `swapchain_color_space`.
+    pub fn presented_color_space(&self) -> SwapchainColorSpace {
+        self.data.swapchain_color_space
+    }
+
+    /// The Vulkan version and the core features of Vulkan 1.1 to 1.3 the
+    /// device supports. The ones the renderer uses are enabled.
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        self.data.capabilities
+    }
+
+    /// Every device memory allocation still live, with where it was made.
+    /// Also printed at shutdown for whatever was not freed.
+    pub fn allocations(&self) -> AllocationReport {
+        allocation_report(&self.device)
+    }
+
+    /// The frame count and device memory heap budgets, see `FrameStats`.
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    /// What the device leaves out of Vulkan if it is layered over another
+    /// API, as MoltenVK is.
+    pub fn portability(&self) -> PortabilitySubset {
+        self.data.portability
+    }
+
+    /// Exercises buffer upload, readback, compute dispatch, render to texture
+    /// and texture sampling on the device, off screen, and reports which
+    /// work. Meant for triaging driver problems; waits for the device first.
+    pub unsafe fn self_test(&mut self) -> SelfTestReport {
+        self.device.device_wait_idle().unwrap();
+        run_self_test(&self.instance, &self.device, &mut self.data)
+    }
+
     pub unsafe fn destroy(&mut self) {
         self.device.device_wait_idle().unwrap();
 
-        self.destroy_swapchain();
+        let pipeline_cache_file = pipeline_cache_file(&self.instance, &self.data, &self.directories);
+        if let Err(e) = save_pipeline_cache(&self.device, &self.data, &pipeline_cache_file) {
+            warn!("Failed to save pipeline cache: {}", e);
+        }
+
+        if !self.data.suspended {
+            self.destroy_swapchain();
+        }
+
+        if let Some(xr) = &mut self.xr {
+            xr.destroy(&self.device);
+        }
+        self.windows
+            .drain(..)
+            .for_each(|mut w| w.destroy(&self.instance, &self.device));
 
+        self.device.destroy_semaphore(self.data.frame_timeline, None);
         self.data
-            .in_flight_fences
+            .frame_fences
             .iter()
             .for_each(|f| self.device.destroy_fence(*f, None));
-        self.data
-            .render_finished_semaphore
-            .iter()
-            .for_each(|s| self.device.destroy_semaphore(*s, None));
         self.data
             .image_available_semaphore
             .iter()
             .for_each(|s| self.device.destroy_semaphore(*s, None));
+        self.asset_loader.destroy(&self.device, &self.data);
+        self.device.destroy_command_pool(self.data.transfer_command_pool, None);
         self.data
             .command_pools
             .iter()
             .for_each(|p| self.device.destroy_command_pool(*p, None));
-        self.device.free_memory(self.data.index_buffer_memory, None);
+        self.data
+            .recording_pools
+            .iter()
+            .flatten()
+            .for_each(|p| self.device.destroy_command_pool(*p, None));
+        self.data.blas.destroy(&self.device);
+        self.data.static_batches.destroy(&self.device);
+        self.data.assets.destroy(&self.device);
+        free_memory(&self.device, self.data.index_buffer_memory);
         self.device.destroy_buffer(self.data.index_buffer, None);
-        self.device
-            .free_memory(self.data.vertex_buffer_memory, None);
+        free_memory(&self.device, self.data.vertex_buffer_memory);
         self.device.destroy_buffer(self.data.vertex_buffer, None);
-        self.device.destroy_sampler(self.data.texture_sampler, None);
+        self.data.texture_arrays.iter().for_each(|a| a.destroy(&self.device));
+        self.data.textures.iter().flatten().for_each(|t| t.destroy(&self.device));
+        self.device.destroy_buffer(self.data.atlas_region_buffer, None);
+        free_memory(&self.device, self.data.atlas_region_buffer_memory);
+        if let Some(mut streamed) = self.data.streamed_texture.take() {
+            streamed.destroy(&self.device, &self.data);
+        }
+        if let Some(environment_map) = self.data.environment_map.take() {
+            environment_map.destroy(&self.device);
+        }
+        self.data.samplers.destroy(&self.device);
         self.device
             .destroy_image_view(self.data.texture_image_view, None);
-        self.device
-            .free_memory(self.data.texture_image_memory, None);
+        free_memory(&self.device, self.data.texture_image_memory);
         self.device.destroy_image(self.data.texture_image, None);
         self.device
             .destroy_command_pool(self.data.command_pool, None);
+        if let Some(mut particles) = self.data.gpu_particles.take() {
+            particles.destroy(&self.device);
+        }
+        self.data.shadow_pipeline.destroy(&self.device);
+        self.device
+            .destroy_pipeline_layout(self.data.shadow_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.occlusion_pipeline, None);
         self.device
-            .destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
+            .destroy_pipeline_layout(self.data.occlusion_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.cull_pipeline, None);
+        self.device
+            .destroy_pipeline_layout(self.data.cull_pipeline_layout, None);
+        destroy_meshlet_buffers(&self.device, &mut self.data);
+        self.device
+            .destroy_descriptor_pool(self.data.meshlet_descriptor_pool, None);
+        self.device.destroy_pipeline(self.data.gpu_particle_pipeline, None);
+        self.device
+            .destroy_pipeline_layout(self.data.gpu_particle_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.metering_pipeline, None);
+        self.device
+            .destroy_pipeline_layout(self.data.metering_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.mipmap_pipeline, None);
+        self.device
+            .destroy_pipeline_layout(self.data.mipmap_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.scan_add_pipeline, None);
+        self.device.destroy_pipeline(self.data.scan_pipeline, None);
+        self.device.destroy_pipeline(self.data.reduce_pipeline, None);
+        self.device
+            .destroy_pipeline_layout(self.data.reduction_pipeline_layout, None);
+        self.data.sprite_sets.destroy(&self.device);
+        self.data.scene_sets.destroy(&self.device);
+        self.data.color_lut.destroy(&self.device);
+        self.device.destroy_buffer(self.data.histogram_bins, None);
+        free_memory(&self.device, self.data.histogram_bins_memory);
+        self.device.destroy_buffer(self.data.exposure_state, None);
+        free_memory(&self.device, self.data.exposure_state_memory);
+        self.device
+            .destroy_descriptor_pool(self.data.texture_descriptor_pool, None);
+        self.data.set_layouts.destroy(&self.device);
+        self.pipeline_compiler.destroy(&self.device);
+        self.device.destroy_pipeline_cache(self.data.pipeline_cache, None);
+        let leaks = take_leaks(&self.device);
+        if !leaks.is_empty() {
+            warn!("Device memory was not freed, {}", leaks);
+        }
         self.device.destroy_device(None);
         self.instance.destroy_surface_khr(self.data.surface, None);
 
@@ -452,69 +3302,512 @@ impl App {
     }
 
     unsafe fn destroy_swapchain(&mut self) {
-        self.device.destroy_descriptor_pool(self.data.descriptor_pool, None);
-        self.data.uniform_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
+        // Jobs in flight use the render pass and pipeline layout.
+        for (ticket, result) in self.pipeline_compiler.wait_idle() {
+            self.accept_pipeline(ticket, result);
+        }
+
+        self.data
+            .render_finished_semaphore
+            .drain(..)
+            .for_each(|s| self.device.destroy_semaphore(s, None));
+        self.device
+            .free_command_buffers(self.data.command_pool, &self.data.static_command_buffers);
+        self.data.scene_sets.clear(&self.device);
+        self.device.destroy_descriptor_pool(self.data.tonemap_descriptor_pool, None);
+        self.device.destroy_descriptor_pool(self.data.oit_descriptor_pool, None);
+        self.device.destroy_descriptor_pool(self.data.decal_descriptor_pool, None);
+        self.data.metering.iter_mut().for_each(|m| m.destroy(&self.device));
+        self.data.metering.clear();
+        self.device.destroy_descriptor_pool(self.data.metering_descriptor_pool, None);
+        self.data.metering_descriptor_pool = vk::DescriptorPool::null();
+        self.data.focus_blur_targets.iter().for_each(|t| t.destroy(&self.device));
+        self.data.focus_blur_targets.clear();
+        self.device.destroy_descriptor_pool(self.data.focus_blur_descriptor_pool, None);
+        self.data.focus_blur_descriptor_pool = vk::DescriptorPool::null();
+        self.data.stereo_target.destroy(&self.device);
+        self.data.stereo_target = StereoTarget::default();
+        self.device.destroy_descriptor_pool(self.data.stereo_descriptor_pool, None);
+        self.data.stereo_descriptor_pool = vk::DescriptorPool::null();
+        self.data.gpu_culling.iter_mut().for_each(|c| c.destroy(&self.device));
+        self.data.gpu_culling.clear();
+        self.device.destroy_descriptor_pool(self.data.gpu_culling_descriptor_pool, None);
+        self.data.gpu_culling_descriptor_pool = vk::DescriptorPool::null();
+        self.data.scene_tlas.iter_mut().for_each(|t| t.destroy(&self.device));
+        self.data.scene_tlas.clear();
+        self.data.shadow_masks.iter().for_each(|m| m.destroy(&self.device));
+        self.data.shadow_masks.clear();
+        self.device.destroy_descriptor_pool(self.data.shadow_descriptor_pool, None);
+        self.data.shadow_descriptor_pool = vk::DescriptorPool::null();
+        self.data.occlusion_targets.iter_mut().for_each(|t| t.destroy(&self.device));
+        self.data.occlusion_targets.clear();
+        self.data.occlusion_history.destroy(&self.device);
+        self.device.destroy_descriptor_pool(self.data.occlusion_descriptor_pool, None);
+        self.data.occlusion_descriptor_pool = vk::DescriptorPool::null();
+        self.data.uniform_buffers_memory.iter_mut().for_each(|m| m.free(&self.device));
         self.data.uniform_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.joint_buffers_memory.iter_mut().for_each(|m| m.free(&self.device));
+        self.data.joint_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.material_buffers_memory.iter_mut().for_each(|m| m.free(&self.device));
+        self.data.material_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
         self.device.destroy_image_view(self.data.depth_image_view, None);
-        self.device.free_memory(self.data.depth_image_memory, None);
+        free_memory(&self.device, self.data.depth_image_memory);
         self.device.destroy_image(self.data.depth_image, None);
         self.device.destroy_image_view(self.data.color_image_view, None);
-        self.device.free_memory(self.data.color_image_memory, None);
+        free_memory(&self.device, self.data.color_image_memory);
         self.device.destroy_image(self.data.color_image, None);
+        self.device.destroy_image_view(self.data.resolve_image_view, None);
+        free_memory(&self.device, self.data.resolve_image_memory);
+        self.device.destroy_image(self.data.resolve_image, None);
+        self.device.destroy_image_view(self.data.oit_accum_image_view, None);
+        free_memory(&self.device, self.data.oit_accum_image_memory);
+        self.device.destroy_image(self.data.oit_accum_image, None);
+        self.device.destroy_image_view(self.data.oit_reveal_image_view, None);
+        free_memory(&self.device, self.data.oit_reveal_image_memory);
+        self.device.destroy_image(self.data.oit_reveal_image, None);
+        self.data
+            .debug_vertex_buffers
+            .iter_mut()
+            .chain(self.data.particle_vertex_buffers.iter_mut())
+            .chain(self.data.billboard_vertex_buffers.iter_mut())
+            .chain(self.data.sprite_instance_buffers.iter_mut())
+            .chain(self.data.sprite_indirect_buffers.iter_mut())
+            .for_each(|b| destroy_dynamic_buffer(&self.device, b));
         self.data.framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
+        self.data.overlay_framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
+        self.device.destroy_pipeline(self.data.focus_blur_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.focus_blur_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.stereo_pipeline, None);
+        self.device.destroy_pipeline(self.data.stereo_transparent_pipeline, None);
+        self.device.destroy_pipeline(self.data.stereo_mirror_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.stereo_mirror_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.tonemap_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.tonemap_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.histogram_pipeline, None);
+        self.device.destroy_pipeline(self.data.exposure_adapt_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.histogram_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.hdr_metering_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.hdr_metering_pipeline_layout, None);
+        self.device.destroy_descriptor_pool(self.data.histogram_descriptor_pool, None);
+        self.data.exposure_readbacks.drain(..).for_each(|r| r.destroy(&self.device));
+        self.device.destroy_pipeline(self.data.oit_composite_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.oit_composite_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.decal_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.decal_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.sprite_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.sprite_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.grid_pipeline, None);
+        self.device.destroy_pipeline(self.data.gpu_particle_draw_pipeline, None);
+        self.device.destroy_pipeline(self.data.particle_pipeline, None);
+        self.device.destroy_pipeline(self.data.billboard_pipeline, None);
+        self.device.destroy_pipeline(self.data.debug_pipeline, None);
+        self.data.overdraw_pipeline.destroy(&self.device);
+        self.data.wireframe_pipeline.destroy(&self.device);
+        self.data.depth_prepass_pipeline.destroy(&self.device);
+        self.data.depth_equal_pipeline.destroy(&self.device);
+        self.device.destroy_pipeline(self.data.mesh_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.mesh_pipeline_layout, None);
+        self.device.destroy_pipeline(self.data.indirect_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.indirect_pipeline_layout, None);
+        self.data.materials.destroy_pipelines(&self.device);
+        self.device.destroy_pipeline(self.data.transparent_pipeline, None);
         self.device.destroy_pipeline(self.data.pipeline, None);
         self.device.destroy_pipeline_layout(self.data.pipeline_layout, None);
+        self.device.destroy_render_pass(self.data.overlay_render_pass, None);
+        self.device.destroy_render_pass(self.data.stereo_render_pass, None);
         self.device.destroy_render_pass(self.data.render_pass, None);
         self.data.swapchain_image_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
         self.device.destroy_swapchain_khr(self.data.swapchain, None);
     }
 }
 
+/// The state a cached scene recording depends on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SceneKey {
+    pub(crate) models: usize,
+    pub(crate) statics: u64,
+    /// Entities that passed culling. Static batches ignore it.
+    pub(crate) visible: u64,
+    /// Entities the culling pass draws instead, see `App::gpu_culling`.
+    pub(crate) gpu_culled: u64,
+    /// Entities drawn by the depth pre-pass, see `App::depth_prepass`.
+    pub(crate) prepassed: u64,
+    pub(crate) mesh_shading: bool,
+    /// Each entity's level of detail, see `App::lod_mask`.
+    pub(crate) lods: u128,
+    pub(crate) viewmodels: u64,
+    pub(crate) viewmodel_depth: ViewmodelDepth,
+    pub(crate) show_grid: bool,
+    pub(crate) wireframe: bool,
+    pub(crate) debug_view: DebugView,
+}
+
 #[derive(Clone, Debug, Default)]
 pub(crate) struct AppData {
+    /// Set for `Headless` contexts, which have no surface or swapchain.
+    pub(crate) headless: bool,
+    pub(crate) instance_version: u32,
+    /// `VK_KHR_get_surface_capabilities2` is enabled on the instance.
+    pub(crate) surface_capabilities2: bool,
+    pub(crate) device_version: u32,
     pub(crate) surface: vk::SurfaceKHR,
     pub(crate) messenger: vk::DebugUtilsMessengerEXT,
     pub(crate) physical_device: vk::PhysicalDevice,
+    /// Read from `GPU_OVERRIDE_VAR` at startup.
+    pub(crate) gpu_selector: Option<GpuSelector>,
+    /// The requested core features the device supports, all enabled.
+    pub(crate) device_features: DeviceFeatures,
+    pub(crate) capabilities: DeviceCapabilities,
     pub(crate) msaa_samples: vk::SampleCountFlags,
+    pub(crate) subgroup_size: u32,
+    pub(crate) subgroup_arithmetic: bool,
+    /// The texture table is a partially bound, update-after-bind array.
+    pub(crate) descriptor_indexing: bool,
+    /// Slots in the texture table.
+    pub(crate) texture_capacity: u32,
+    /// `VK_KHR_push_descriptor` is enabled. The scene uniform buffer and
+    /// sprite atlases are then pushed instead of allocated from pools.
+    pub(crate) push_descriptors: bool,
+    /// Set when the device supports Vulkan 1.3's dynamic rendering. Frames
+    /// are then drawn with `cmd_begin_rendering` and the render passes and
+    /// framebuffers are left null, see `dynamic_rendering.rs`.
+    pub(crate) dynamic_rendering: bool,
+    /// Set when the device supports Vulkan 1.1's multiview, which `Stereo`
+    /// needs.
+    pub(crate) multiview: bool,
+    /// Needed by the OpenXR runtime, empty without one.
+    pub(crate) xr_instance_extensions: Vec<CString>,
+    pub(crate) xr_device_extensions: Vec<CString>,
+    /// The device the OpenXR runtime renders with, the only one picked
+    /// while it is set.
+    pub(crate) xr_physical_device: vk::PhysicalDevice,
+    /// The size of each eye in the OpenXR swapchain, zero without one.
+    pub(crate) xr_extent: vk::Extent2D,
+    pub(crate) depth_format: vk::Format,
+    /// Timeline semaphores come from `VK_KHR_timeline_semaphore` rather than
+    /// Vulkan 1.2.
+    pub(crate) timeline_semaphore_extension: bool,
+    /// `VK_EXT_full_screen_exclusive` is enabled, which is only looked for on
+    /// Windows.
+    pub(crate) full_screen_exclusive: bool,
+    /// What the window was last switched to, see `App::window_mode`.
+    pub(crate) window_mode: WindowMode,
+    /// Physical pixels per logical pixel of the main window.
+    pub(crate) scale_factor: f64,
+    pub(crate) portability: PortabilitySubset,
+    /// `VK_EXT_memory_budget` is enabled.
+    pub(crate) memory_budget: bool,
+    /// Set between `App::suspend` and `App::resume`, while there is no
+    /// surface or swapchain.
+    pub(crate) suspended: bool,
+    pub(crate) fullscreen_resolution: Option<[u32; 2]>,
+    /// `VK_EXT_load_store_op_none` is enabled.
+    pub(crate) load_store_op_none: bool,
+    /// `VK_KHR_draw_indirect_count` is enabled, which GPU culling needs.
+    pub(crate) draw_indirect_count: bool,
+    /// `VK_EXT_mesh_shader` is enabled with task shaders, and the meshlet
+    /// resources exist.
+    pub(crate) mesh_shader: bool,
+    /// The ray tracing extensions and buffer device addresses are enabled,
+    /// see `ray_tracing.rs`.
+    pub(crate) ray_tracing: bool,
+    pub(crate) ray_tracing_limits: RayTracingLimits,
+    /// `VK_KHR_ray_query` is enabled alongside `ray_tracing`, and vertex and
+    /// index buffers can be read as storage buffers.
+    pub(crate) ray_query: bool,
     pub(crate) graphics_queue: vk::Queue,
     pub(crate) present_queue: vk::Queue,
+    /// Background uploads are submitted here, see `AssetLoader`. May be the
+    /// graphics queue.
+    pub(crate) transfer_queue: vk::Queue,
     pub(crate) swapchain_format: vk::Format,
     pub(crate) swapchain_extent: vk::Extent2D,
     pub(crate) swapchain: vk::SwapchainKHR,
     pub(crate) swapchain_images: Vec<vk::Image>,
     pub(crate) swapchain_image_views: Vec<vk::ImageView>,
     pub(crate) render_pass: vk::RenderPass,
+    /// Every descriptor set layout, by what it declares.
+    pub(crate) set_layouts: LayoutCache,
     pub(crate) descriptor_set_layout: vk::DescriptorSetLayout,
     pub(crate) pipeline_layout: vk::PipelineLayout,
+    /// The scene shaders' push constants, see `create_pipeline`.
+    pub(crate) model_push_constants: PushConstants<Mat4>,
+    pub(crate) fragment_push_constants: PushConstants<FragmentPushConstants>,
     pub(crate) pipeline: vk::Pipeline,
+    /// Draws transparent render queues, see `SceneVariant::Transparent`.
+    pub(crate) transparent_pipeline: vk::Pipeline,
+    pub(crate) wireframe_supported: bool,
+    pub(crate) texture_compression_bc: bool,
+    /// Compiled in the background the first time they are wanted.
+    pub(crate) wireframe_pipeline: AsyncPipeline,
+    pub(crate) overdraw_pipeline: AsyncPipeline,
+    pub(crate) depth_prepass_pipeline: AsyncPipeline,
+    pub(crate) depth_equal_pipeline: AsyncPipeline,
+    pub(crate) debug_pipeline: vk::Pipeline,
+    pub(crate) particle_pipeline: vk::Pipeline,
+    pub(crate) billboard_pipeline: vk::Pipeline,
+    pub(crate) gpu_particle_draw_pipeline: vk::Pipeline,
+    pub(crate) gpu_particle_set_layout: vk::DescriptorSetLayout,
+    pub(crate) gpu_particle_pipeline_layout: vk::PipelineLayout,
+    /// The compute pipeline that simulates `gpu_particles`.
+    pub(crate) gpu_particle_pipeline: vk::Pipeline,
+    pub(crate) gpu_particles: Option<GpuParticles>,
+    pub(crate) cull_set_layout: vk::DescriptorSetLayout,
+    pub(crate) cull_pipeline_layout: vk::PipelineLayout,
+    pub(crate) cull_pipeline: vk::Pipeline,
+    /// The scene layout with `cull_set_layout` at set 2.
+    pub(crate) indirect_pipeline_layout: vk::PipelineLayout,
+    /// Draws what `gpu_culling` kept.
+    pub(crate) indirect_pipeline: vk::Pipeline,
+    pub(crate) gpu_culling_descriptor_pool: vk::DescriptorPool,
+    /// One per swapchain image, empty without `draw_indirect_count`.
+    pub(crate) gpu_culling: Vec<GpuCulling>,
+    /// The scene mesh's meshlets, uploaded to the buffers below.
+    pub(crate) meshlets: Meshlets,
+    pub(crate) meshlet_buffer: vk::Buffer,
+    pub(crate) meshlet_buffer_memory: vk::DeviceMemory,
+    pub(crate) meshlet_vertex_buffer: vk::Buffer,
+    pub(crate) meshlet_vertex_buffer_memory: vk::DeviceMemory,
+    pub(crate) meshlet_triangle_buffer: vk::Buffer,
+    pub(crate) meshlet_triangle_buffer_memory: vk::DeviceMemory,
+    /// The vertex buffer and the three above, for the meshlet shaders.
+    pub(crate) meshlet_set_layout: vk::DescriptorSetLayout,
+    pub(crate) meshlet_descriptor_pool: vk::DescriptorPool,
+    pub(crate) meshlet_descriptor_set: vk::DescriptorSet,
+    /// The scene layout with `meshlet_set_layout` at set 2.
+    pub(crate) mesh_pipeline_layout: vk::PipelineLayout,
+    pub(crate) mesh_pipeline: vk::Pipeline,
+    /// Of the scene mesh, null if it has no triangles.
+    pub(crate) blas: AccelerationStructure,
+    /// One per swapchain image, empty without `ray_tracing`.
+    pub(crate) scene_tlas: Vec<SceneTlas>,
+    pub(crate) shadow_set_layout: vk::DescriptorSetLayout,
+    pub(crate) shadow_pipeline_layout: vk::PipelineLayout,
+    pub(crate) shadow_pipeline: RayTracingPipeline,
+    /// For the sets tracing `shadow_masks`, null without ray tracing.
+    pub(crate) shadow_descriptor_pool: vk::DescriptorPool,
+    /// One per swapchain image, sampled at binding 2 of the scene's set 0.
+    pub(crate) shadow_masks: Vec<ShadowMask>,
+    pub(crate) occlusion_set_layout: vk::DescriptorSetLayout,
+    pub(crate) occlusion_pipeline_layout: vk::PipelineLayout,
+    pub(crate) occlusion_pipeline: vk::Pipeline,
+    /// For the sets tracing `occlusion_targets`, null without ray queries.
+    pub(crate) occlusion_descriptor_pool: vk::DescriptorPool,
+    /// One per swapchain image, sampled at binding 3 of the scene's set 0.
+    pub(crate) occlusion_targets: Vec<OcclusionTarget>,
+    /// Copied from the latest target after it is traced.
+    pub(crate) occlusion_history: OcclusionTarget,
+    pub(crate) grid_pipeline: vk::Pipeline,
+    pub(crate) sprite_set_layout: vk::DescriptorSetLayout,
+    pub(crate) sprite_pipeline_layout: vk::PipelineLayout,
+    pub(crate) sprite_pipeline: vk::Pipeline,
+    pub(crate) sprite_sets: SetCache,
+    pub(crate) sprite_atlas_sets: Vec<vk::DescriptorSet>,
+    /// What each atlas samples, for pushing or rewriting its set.
+    pub(crate) sprite_atlas_images: Vec<vk::DescriptorImageInfo>,
+    pub(crate) reduction_set_layout: vk::DescriptorSetLayout,
+    pub(crate) reduction_pipeline_layout: vk::PipelineLayout,
+    pub(crate) reduce_pipeline: vk::Pipeline,
+    pub(crate) scan_pipeline: vk::Pipeline,
+    pub(crate) scan_add_pipeline: vk::Pipeline,
+    pub(crate) mipmap_set_layout: vk::DescriptorSetLayout,
+    pub(crate) mipmap_pipeline_layout: vk::PipelineLayout,
+    pub(crate) mipmap_pipeline: vk::Pipeline,
+    /// The swapchain can be blitted from and holds sRGB color, for metering
+    /// and the focus blur.
+    pub(crate) metering_supported: bool,
+    pub(crate) metering_set_layout: vk::DescriptorSetLayout,
+    pub(crate) metering_pipeline_layout: vk::PipelineLayout,
+    pub(crate) metering_pipeline: vk::Pipeline,
+    pub(crate) hdr_metering_pipeline_layout: vk::PipelineLayout,
+    pub(crate) hdr_metering_pipeline: vk::Pipeline,
+    pub(crate) metering_descriptor_pool: vk::DescriptorPool,
+    pub(crate) metering: Vec<Metering>,
+    pub(crate) histogram_set_layout: vk::DescriptorSetLayout,
+    pub(crate) histogram_pipeline_layout: vk::PipelineLayout,
+    pub(crate) histogram_pipeline: vk::Pipeline,
+    pub(crate) exposure_adapt_pipeline: vk::Pipeline,
+    /// Null without dynamic rendering, see `create_histogram_descriptor_set`.
+    pub(crate) histogram_descriptor_pool: vk::DescriptorPool,
+    pub(crate) histogram_descriptor_set: vk::DescriptorSet,
+    pub(crate) histogram_bins: vk::Buffer,
+    pub(crate) histogram_bins_memory: vk::DeviceMemory,
+    /// The exposure adapted on the GPU, read by the tonemap pass.
+    pub(crate) exposure_state: vk::Buffer,
+    pub(crate) exposure_state_memory: vk::DeviceMemory,
+    /// One per swapchain image. Empty without dynamic rendering.
+    pub(crate) exposure_readbacks: Vec<ExposureReadback>,
+    /// The mode the render pass was built with.
+    pub(crate) resolve_mode: ResolveMode,
+    /// Whether the swapchain was created preferring an `_SRGB` format.
+    pub(crate) srgb_swapchain: bool,
+    /// What the swapchain was created with, see `App::min_swapchain_images`.
+    pub(crate) min_swapchain_images: u32,
+    /// The color space the swapchain was created preferring.
+    pub(crate) preferred_color_space: SwapchainColorSpace,
+    /// The color space it presents in.
+    pub(crate) swapchain_color_space: SwapchainColorSpace,
+    /// What the pipelines, clears and projections were built for, see
+    /// `App::reverse_z`.
+    pub(crate) reverse_z: bool,
+    pub(crate) tonemap_set_layout: vk::DescriptorSetLayout,
+    pub(crate) tonemap_pipeline_layout: vk::PipelineLayout,
+    pub(crate) tonemap_pipeline: vk::Pipeline,
+    pub(crate) tonemap_descriptor_pool: vk::DescriptorPool,
+    pub(crate) tonemap_descriptor_set: vk::DescriptorSet,
+    /// Sampled by the tonemap pass, the identity unless
+    /// `App::set_color_grading` loaded one.
+    pub(crate) color_lut: ColorLut,
+    /// What the render pass was built for, see
+    /// `App::order_independent_transparency`.
+    pub(crate) order_independent_transparency: bool,
+    pub(crate) oit_set_layout: vk::DescriptorSetLayout,
+    pub(crate) oit_composite_pipeline_layout: vk::PipelineLayout,
+    pub(crate) oit_composite_pipeline: vk::Pipeline,
+    pub(crate) oit_descriptor_pool: vk::DescriptorPool,
+    pub(crate) oit_descriptor_set: vk::DescriptorSet,
+    pub(crate) decal_set_layout: vk::DescriptorSetLayout,
+    pub(crate) decal_pipeline_layout: vk::PipelineLayout,
+    pub(crate) decal_pipeline: vk::Pipeline,
+    pub(crate) decal_descriptor_pool: vk::DescriptorPool,
+    pub(crate) decal_descriptor_set: vk::DescriptorSet,
+    /// Draws the focus blur and sprites over the presented image.
+    pub(crate) overlay_render_pass: vk::RenderPass,
+    pub(crate) focus_blur_set_layout: vk::DescriptorSetLayout,
+    pub(crate) focus_blur_pipeline_layout: vk::PipelineLayout,
+    pub(crate) focus_blur_pipeline: vk::Pipeline,
+    pub(crate) focus_blur_descriptor_pool: vk::DescriptorPool,
+    pub(crate) focus_blur_targets: Vec<FocusBlurTarget>,
+    /// Draws both eyes at once, null without multiview.
+    pub(crate) stereo_render_pass: vk::RenderPass,
+    pub(crate) stereo_set_layout: vk::DescriptorSetLayout,
+    pub(crate) stereo_pipeline: vk::Pipeline,
+    pub(crate) stereo_transparent_pipeline: vk::Pipeline,
+    pub(crate) stereo_mirror_pipeline_layout: vk::PipelineLayout,
+    pub(crate) stereo_mirror_pipeline: vk::Pipeline,
+    pub(crate) stereo_descriptor_pool: vk::DescriptorPool,
+    pub(crate) stereo_target: StereoTarget,
     pub(crate) framebuffers: Vec<vk::Framebuffer>,
+    pub(crate) overlay_framebuffers: Vec<vk::Framebuffer>,
     pub(crate) command_pool: vk::CommandPool,
+    pub(crate) transfer_command_pool: vk::CommandPool,
     pub(crate) command_pools: Vec<vk::CommandPool>,
     pub(crate) command_buffers: Vec<vk::CommandBuffer>,
-    pub(crate) secondary_command_buffers: Vec<Vec<vk::CommandBuffer>>,
+    /// Per swapchain image, one pool per recording thread, see
+    /// `SceneRecorder::record_parallel`.
+    pub(crate) recording_pools: Vec<Vec<vk::CommandPool>>,
+    /// Per swapchain image, the buffers allocated from each recording pool.
+    pub(crate) secondary_command_buffers: Vec<Vec<Vec<vk::CommandBuffer>>>,
+    pub(crate) debug_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) particle_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) billboard_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) gpu_particle_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) indirect_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) grid_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) depth_prepass_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) sprite_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) focus_blur_command_buffers: Vec<vk::CommandBuffer>,
+    pub(crate) scene_command_buffers: Vec<Vec<vk::CommandBuffer>>,
+    pub(crate) recorded_scenes: Vec<Option<SceneKey>>,
+    pub(crate) static_command_buffers: Vec<vk::CommandBuffer>,
+    /// Baked by `App::bake_static_batches`.
+    pub(crate) static_batches: StaticBatches,
+    pub(crate) recorded_static_batches: Vec<Option<SceneKey>>,
+    pub(crate) frames_in_flight: usize,
+    /// Binary, as acquire and present cannot use the timeline. Acquire
+    /// semaphores are per frame in flight and render finished ones per
+    /// swapchain image.
     pub(crate) image_available_semaphore: Vec<vk::Semaphore>,
     pub(crate) render_finished_semaphore: Vec<vk::Semaphore>,
-    pub(crate) in_flight_fences: Vec<vk::Fence>,
-    pub(crate) images_in_flight: Vec<vk::Fence>,
+    /// Signaled with each frame's number when its commands finish. Null
+    /// without timeline semaphores.
+    pub(crate) frame_timeline: vk::Semaphore,
+    /// Without timeline semaphores, the fence each frame signals instead,
+    /// indexed by its number modulo `MAX_FRAMES_IN_FLIGHT`.
+    pub(crate) frame_fences: Vec<vk::Fence>,
+    /// The number of the last frame submitted.
+    pub(crate) frame_number: u64,
+    /// The number of the last frame that drew to each swapchain image, which
+    /// must finish before the image's command buffers are reused.
+    pub(crate) image_frames: Vec<u64>,
     pub(crate) vertices: Vec<Vertex>,
+    /// Of every level of detail, see `lods`.
     pub(crate) indices: Vec<u32>,
+    /// The model's levels of detail, the full mesh first.
+    pub(crate) lods: Vec<Lod>,
+    pub(crate) model_bounds: Bounds,
     pub(crate) vertex_buffer: vk::Buffer,
     pub(crate) vertex_buffer_memory: vk::DeviceMemory,
     pub(crate) index_buffer: vk::Buffer,
     pub(crate) index_buffer_memory: vk::DeviceMemory,
+    /// `UINT16` when the model has few enough vertices, see `pack_indices`.
+    pub(crate) index_type: vk::IndexType,
+    /// Each holds a `UniformBufferObject` per viewport, `uniform_stride`
+    /// apart, see `viewport.rs`.
     pub(crate) uniform_buffers: Vec<vk::Buffer>,
-    pub(crate) uniform_buffers_memory: Vec<vk::DeviceMemory>,
-    pub(crate) descriptor_pool: vk::DescriptorPool,
+    pub(crate) uniform_buffers_memory: Vec<MappedMemory>,
+    /// `minUniformBufferOffsetAlignment`.
+    pub(crate) uniform_alignment: u64,
+    /// Empty for the whole window, see `App::set_viewports`.
+    pub(crate) viewports: Vec<Viewport>,
+    pub(crate) joint_buffers: Vec<vk::Buffer>,
+    pub(crate) joint_buffers_memory: Vec<MappedMemory>,
+    /// Each holds a `MaterialBlock` per material instance, see `material.rs`.
+    pub(crate) material_buffers: Vec<vk::Buffer>,
+    pub(crate) material_buffers_memory: Vec<MappedMemory>,
+    pub(crate) materials: Materials,
+    /// The scene model's joints, if it is skinned.
+    pub(crate) skeleton: Option<Skeleton>,
+    pub(crate) clips: Vec<AnimationClip>,
+    pub(crate) debug_vertex_buffers: Vec<DynamicBuffer>,
+    pub(crate) particle_vertex_buffers: Vec<DynamicBuffer>,
+    pub(crate) billboard_vertex_buffers: Vec<DynamicBuffer>,
+    pub(crate) sprite_instance_buffers: Vec<DynamicBuffer>,
+    pub(crate) sprite_indirect_buffers: Vec<DynamicBuffer>,
+    /// The scene sets, see `create_descriptor_pool`.
+    pub(crate) scene_sets: SetCache,
     pub(crate) descriptor_sets: Vec<vk::DescriptorSet>,
     pub(crate) mip_levels: u32,
+    pub(crate) texture_format: vk::Format,
     pub(crate) texture_image: vk::Image,
     pub(crate) texture_image_memory: vk::DeviceMemory,
     pub(crate) texture_image_view: vk::ImageView,
+    /// Set when `texture_image` holds a streamed subset of the mips.
+    pub(crate) streamed_texture: Option<StreamedTexture>,
     pub(crate) texture_sampler: vk::Sampler,
+    pub(crate) samplers: SamplerCache,
+    /// Shared by every pipeline and saved to disk between runs.
+    pub(crate) pipeline_cache: vk::PipelineCache,
+    pub(crate) environment_map: Option<Cubemap>,
+    pub(crate) texture_arrays: Vec<TextureArray>,
+    /// Texture table slots after the scene texture. Slots still loading in
+    /// the background are `None` and draw the scene texture instead.
+    pub(crate) textures: Vec<Option<LayeredTexture>>,
+    /// Meshes and textures registered by path, see `App::acquire_mesh` and
+    /// `App::acquire_texture`.
+    pub(crate) assets: Assets,
+    pub(crate) texture_set_layout: vk::DescriptorSetLayout,
+    pub(crate) world: WorldConfig,
+    pub(crate) texture_descriptor_pool: vk::DescriptorPool,
+    pub(crate) texture_descriptor_set: vk::DescriptorSet,
+    pub(crate) atlas_region_buffer: vk::Buffer,
+    pub(crate) atlas_region_buffer_memory: vk::DeviceMemory,
+    /// Offset and scale of each region packed by `App::load_texture_atlas`
+    /// in its page. Region 0 is the whole texture.
+    pub(crate) atlas_regions: Vec<Vec4>,
     pub(crate) depth_image: vk::Image,
     pub(crate) depth_image_memory: vk::DeviceMemory,
     pub(crate) depth_image_view: vk::ImageView,
     pub(crate) color_image: vk::Image,
     pub(crate) color_image_memory: vk::DeviceMemory,
     pub(crate) color_image_view: vk::ImageView,
+    pub(crate) resolve_image: vk::Image,
+    pub(crate) resolve_image_memory: vk::DeviceMemory,
+    pub(crate) resolve_image_view: vk::ImageView,
+    pub(crate) oit_accum_image: vk::Image,
+    pub(crate) oit_accum_image_memory: vk::DeviceMemory,
+    pub(crate) oit_accum_image_view: vk::ImageView,
+    pub(crate) oit_reveal_image: vk::Image,
+    pub(crate) oit_reveal_image_memory: vk::DeviceMemory,
+    pub(crate) oit_reveal_image_view: vk::ImageView,
 }
@@ -0,0 +1,602 @@
+use anyhow::Result;
+use log::*;
+use std::{
+    fmt,
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    barrier::{cmd_barriers, queue_submit, BufferBarrier, ImageTransition},
+    image::create_image,
+    model::{read_model, MeshData},
+    physical_device::QueueFamilyIndices,
+    ray_tracing::geometry_usage,
+    texture::{decode_if_unsupported, load_texture, stage_layers, ColorSpace, LayeredTexture, TextureData},
+    vertex::Vertex,
+    vertex_buffer::{create_buffer, pack_indices},
+    world::WorldConfig,
+};
+
+/// Most worker threads the loader starts. Loading is mostly waiting on the
+/// disk and decoding, so a couple is plenty.
+const MAX_WORKERS: usize = 2;
+
+/// Called on the render thread as background loads finish or fail.
+pub type AssetCallback = Box<dyn FnMut(&AssetEvent) + Send>;
+
+/// The callbacks registered with `App::on_asset_loaded`.
+#[derive(Default)]
+pub(crate) struct AssetCallbacks(pub(crate) Vec<AssetCallback>);
+
+impl fmt::Debug for AssetCallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AssetCallbacks({})", self.0.len())
+    }
+}
+
+/// A background load that has finished, see `App::load_texture_async` and
+/// `App::load_model_async`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AssetEvent {
+    /// The texture now draws in `slot` instead of the placeholder.
+    TextureLoaded { slot: u32, path: PathBuf },
+    /// The model replaced the scene model.
+    ModelLoaded { path: PathBuf },
+    /// Textures that fail keep drawing the placeholder.
+    Failed { path: PathBuf, error: String },
+}
+
+#[derive(Debug)]
+enum Job {
+    Texture {
+        slot: u32,
+        path: PathBuf,
+        color_space: ColorSpace,
+        /// Owned by the sampler cache.
+        sampler: vk::Sampler,
+    },
+    Model {
+        path: PathBuf,
+        world: WorldConfig,
+    },
+}
+
+/// What a worker hands back to the render thread to upload.
+#[derive(Debug)]
+enum Decoded {
+    Texture {
+        slot: u32,
+        path: PathBuf,
+        texture: TextureData,
+        sampler: vk::Sampler,
+    },
+    Model {
+        path: PathBuf,
+        mesh: Box<MeshData>,
+    },
+}
+
+/// An asset uploaded to device memory, ready to be swapped in once the
+/// graphics queue family owns it.
+#[derive(Debug)]
+pub(crate) enum LoadedAsset {
+    /// `texture.image_view` is left null, views are created after the
+    /// ownership transfer.
+    Texture {
+        slot: u32,
+        path: PathBuf,
+        texture: LayeredTexture,
+    },
+    Model {
+        path: PathBuf,
+        mesh: Box<MeshData>,
+        vertex_buffer: vk::Buffer,
+        vertex_buffer_memory: vk::DeviceMemory,
+        index_buffer: vk::Buffer,
+        index_buffer_memory: vk::DeviceMemory,
+        index_type: vk::IndexType,
+    },
+}
+
+impl LoadedAsset {
+    /// Records the graphics queue's half of the ownership transfer started by
+    /// `AssetLoader::poll`. Does nothing when uploads share the graphics
+    /// queue family, whose release barrier already made the asset visible.
+    pub(crate) unsafe fn acquire(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device).unwrap();
+        if indices.transfer == indices.graphics {
+            return;
+        }
+
+        let (images, buffers) = self.barriers(indices.transfer, indices.graphics);
+        let images = images
+            .into_iter()
+            .map(|t| t.src(vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()))
+            .collect::<Vec<_>>();
+        let buffers = buffers
+            .into_iter()
+            .map(|b| BufferBarrier { src_stage: vk::PipelineStageFlags::TOP_OF_PIPE, src_access: vk::AccessFlags::empty(), ..b })
+            .collect::<Vec<_>>();
+        cmd_barriers(device, data, command_buffer, &images, &buffers);
+    }
+
+    /// Barriers that leave the asset readable by shaders, moving it from
+    /// queue family `src` to `dst`.
+    fn barriers(&self, src: u32, dst: u32) -> (Vec<ImageTransition>, Vec<BufferBarrier>) {
+        let (src, dst) = if src == dst {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        } else {
+            (src, dst)
+        };
+
+        match self {
+            LoadedAsset::Texture { texture, .. } => {
+                let transition = ImageTransition::new(
+                    texture.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+                .levels(0..texture.mip_levels)
+                .layers(0..1)
+                .queue_families(src, dst);
+
+                (vec![transition], vec![])
+            }
+            LoadedAsset::Model { vertex_buffer, index_buffer, .. } => {
+                let barrier = |buffer: vk::Buffer, access: vk::AccessFlags| BufferBarrier {
+                    buffer,
+                    src_stage: vk::PipelineStageFlags::TRANSFER,
+                    src_access: vk::AccessFlags::TRANSFER_WRITE,
+                    dst_stage: vk::PipelineStageFlags::VERTEX_INPUT,
+                    dst_access: access,
+                    queue_families: (src, dst),
+                };
+
+                (vec![], vec![
+                    barrier(*vertex_buffer, vk::AccessFlags::VERTEX_ATTRIBUTE_READ),
+                    barrier(*index_buffer, vk::AccessFlags::INDEX_READ),
+                ])
+            }
+        }
+    }
+
+    /// Frees what was created for an asset that will not be swapped in.
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        match self {
+            LoadedAsset::Texture { texture, .. } => texture.destroy(device),
+            LoadedAsset::Model { vertex_buffer, vertex_buffer_memory, index_buffer, index_buffer_memory, .. } => {
+                device.destroy_buffer(*vertex_buffer, None);
+                free_memory(device, *vertex_buffer_memory);
+                device.destroy_buffer(*index_buffer, None);
+                free_memory(device, *index_buffer_memory);
+            }
+        }
+    }
+}
+
+/// A copy submitted to the transfer queue.
+#[derive(Debug)]
+struct Upload {
+    asset: LoadedAsset,
+    fence: vk::Fence,
+    command_buffer: vk::CommandBuffer,
+    staging_buffer: vk::Buffer,
+    staging_buffer_memory: vk::DeviceMemory,
+}
+
+/// Reads and decodes textures and models on worker threads, then uploads
+/// them on the transfer queue, so that loading does not stall the render
+/// loop.
+#[derive(Debug)]
+pub(crate) struct AssetLoader {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Result<Decoded, (PathBuf, anyhow::Error)>>,
+    workers: Vec<JoinHandle<()>>,
+    uploads: Vec<Upload>,
+    in_flight: usize,
+}
+
+impl AssetLoader {
+    pub(crate) fn new() -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(2))
+            .unwrap_or(1)
+            .clamp(1, MAX_WORKERS);
+
+        let (jobs, job_receiver) = channel::<Job>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads)
+            .map(|i| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                thread::Builder::new()
+                    .name(format!("asset loader {}", i))
+                    .spawn(move || loop {
+                        let job = jobs.lock().unwrap().recv();
+                        match job {
+                            Ok(job) => {
+                                let _ = results.send(decode(job));
+                            }
+                            Err(_) => break,
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect();
+
+        debug!("Started {} asset loader threads.", threads);
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+            uploads: vec![],
+            in_flight: 0,
+        }
+    }
+
+    /// Queues a texture for `slot` of the texture table.
+    pub(crate) fn load_texture(&mut self, slot: u32, path: &Path, color_space: ColorSpace, sampler: vk::Sampler) {
+        self.send(Job::Texture { slot, path: path.to_path_buf(), color_space, sampler });
+    }
+
+    /// Queues a model, converted into `world` as it is read.
+    pub(crate) fn load_model(&mut self, path: &Path, world: WorldConfig) {
+        self.send(Job::Model { path: path.to_path_buf(), world });
+    }
+
+    fn send(&mut self, job: Job) {
+        self.in_flight += 1;
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+    }
+
+    /// Starts uploading what the workers have decoded since the last call and
+    /// returns the uploads that have finished, and the loads that failed.
+    pub(crate) unsafe fn poll(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+    ) -> Vec<Result<LoadedAsset, (PathBuf, anyhow::Error)>> {
+        let mut finished = vec![];
+
+        for result in self.results.try_iter().collect::<Vec<_>>() {
+            self.in_flight -= 1;
+            match result.and_then(|decoded| {
+                let path = decoded.path().to_path_buf();
+                self.upload(instance, device, data, decoded).map_err(|e| (path, e))
+            }) {
+                Ok(upload) => self.uploads.push(upload),
+                Err(failure) => finished.push(Err(failure)),
+            }
+        }
+
+        let (done, pending) = self
+            .uploads
+            .drain(..)
+            .partition::<Vec<_>, _>(|u| device.get_fence_status(u.fence) == Ok(vk::SuccessCode::SUCCESS));
+        self.uploads = pending;
+
+        for upload in done {
+            free_upload(device, data, &upload);
+            finished.push(Ok(upload.asset));
+        }
+
+        finished
+    }
+
+    /// Whether anything is still loading or uploading.
+    pub(crate) fn is_busy(&self) -> bool {
+        self.in_flight > 0 || !self.uploads.is_empty()
+    }
+
+    unsafe fn upload(&self, instance: &Instance, device: &Device, data: &AppData, decoded: Decoded) -> Result<Upload> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device)?;
+
+        let info = vk::CommandBufferAllocateInfo::builder()
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_pool(data.transfer_command_pool)
+            .command_buffer_count(1);
+        let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+        let info = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &info)?;
+
+        let (asset, staging_buffer, staging_buffer_memory) = match decoded {
+            Decoded::Texture { slot, path, texture, sampler } => {
+                let texture = decode_if_unsupported(instance, data, texture)?;
+                let (staging_buffer, staging_buffer_memory, offsets) =
+                    stage_layers(instance, device, data, &[&texture.levels[..]])?;
+                let texture = record_texture_copy(instance, device, data, command_buffer, &texture, staging_buffer, &offsets[0])?;
+                let texture = LayeredTexture { sampler, ..texture };
+                (LoadedAsset::Texture { slot, path, texture }, staging_buffer, staging_buffer_memory)
+            }
+            Decoded::Model { path, mesh } => {
+                let vertices = std::slice::from_raw_parts(
+                    mesh.vertices.as_ptr() as *const u8,
+                    size_of::<Vertex>() * mesh.vertices.len(),
+                );
+                let (index_type, indices) = pack_indices(&mesh.indices, mesh.vertices.len());
+                let (staging_buffer, staging_buffer_memory, offsets) =
+                    stage_layers(instance, device, data, &[&[vertices.to_vec(), indices.clone()]])?;
+
+                let (vertex_buffer, vertex_buffer_memory) = create_buffer(
+                    instance,
+                    device,
+                    data,
+                    vertices.len() as u64,
+                    vk::BufferUsageFlags::TRANSFER_DST
+                        | vk::BufferUsageFlags::VERTEX_BUFFER
+                        | vk::BufferUsageFlags::STORAGE_BUFFER
+                        | geometry_usage(data),
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )?;
+                let (index_buffer, index_buffer_memory) = create_buffer(
+                    instance,
+                    device,
+                    data,
+                    indices.len() as u64,
+                    vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER | geometry_usage(data),
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )?;
+
+                let region = vk::BufferCopy::builder().src_offset(offsets[0][0]).size(vertices.len() as u64);
+                device.cmd_copy_buffer(command_buffer, staging_buffer, vertex_buffer, &[region]);
+                let region = vk::BufferCopy::builder().src_offset(offsets[0][1]).size(indices.len() as u64);
+                device.cmd_copy_buffer(command_buffer, staging_buffer, index_buffer, &[region]);
+
+                let asset = LoadedAsset::Model {
+                    path,
+                    mesh,
+                    vertex_buffer,
+                    vertex_buffer_memory,
+                    index_buffer,
+                    index_buffer_memory,
+                    index_type,
+                };
+                (asset, staging_buffer, staging_buffer_memory)
+            }
+        };
+
+        // The release half of the ownership transfer. Its destination access
+        // is ignored and made visible by `LoadedAsset::acquire` instead.
+        let (images, buffers) = asset.barriers(indices.transfer, indices.graphics);
+        let (images, buffers) = if indices.transfer == indices.graphics {
+            (images, buffers)
+        } else {
+            (
+                images
+                    .into_iter()
+                    .map(|t| t.dst(vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()))
+                    .collect(),
+                buffers
+                    .into_iter()
+                    .map(|b| BufferBarrier { dst_stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE, dst_access: vk::AccessFlags::empty(), ..b })
+                    .collect(),
+            )
+        };
+        cmd_barriers(device, data, command_buffer, &images, &buffers);
+
+        device.end_command_buffer(command_buffer)?;
+
+        let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
+        queue_submit(device, data, data.transfer_queue, &[command_buffer], &[], &[], fence)?;
+
+        Ok(Upload {
+            asset,
+            fence,
+            command_buffer,
+            staging_buffer,
+            staging_buffer_memory,
+        })
+    }
+
+    /// Waits for queued loads and uploads, throws their results away and
+    /// stops the workers.
+    pub(crate) unsafe fn destroy(&mut self, device: &Device, data: &AppData) {
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+
+        for upload in self.uploads.drain(..) {
+            device.wait_for_fences(&[upload.fence], true, u64::MAX).unwrap();
+            free_upload(device, data, &upload);
+            upload.asset.destroy(device);
+        }
+    }
+}
+
+impl Decoded {
+    fn path(&self) -> &Path {
+        match self {
+            Decoded::Texture { path, .. } | Decoded::Model { path, .. } => path,
+        }
+    }
+}
+
+/// Runs on a worker thread.
+fn decode(job: Job) -> Result<Decoded, (PathBuf, anyhow::Error)> {
+    match job {
+        Job::Texture { slot, path, color_space, sampler } => {
+            match load_texture(&path).map(|t| generate_mipmaps(t.with_color_space(color_space))) {
+                Ok(texture) => Ok(Decoded::Texture { slot, path, texture, sampler }),
+                Err(e) => Err((path, e)),
+            }
+        }
+        Job::Model { path, world } => match read_model(&path, &world) {
+            Ok(mesh) => Ok(Decoded::Model { path, mesh: Box::new(mesh) }),
+            Err(e) => Err((path, e)),
+        },
+    }
+}
+
+/// Box filters a full mip chain for 8-bit four channel textures that came
+/// with only one level, averaging sRGB textures in linear space. Blitting
+/// would need the graphics queue, which uploads here avoid.
+fn generate_mipmaps(mut texture: TextureData) -> TextureData {
+    let srgb = match texture.format {
+        vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB => true,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::B8G8R8A8_UNORM => false,
+        _ => return texture,
+    };
+    if texture.levels.len() != 1 {
+        return texture;
+    }
+
+    let to_linear = (0..256)
+        .map(|v| {
+            let v = v as f32 / 255.0;
+            if srgb {
+                if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+            } else {
+                v
+            }
+        })
+        .collect::<Vec<_>>();
+    let from_linear = |v: f32| {
+        let v = if srgb {
+            if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+        } else {
+            v
+        };
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    let (mut width, mut height) = (texture.width as usize, texture.height as usize);
+    while width > 1 || height > 1 {
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        let source = texture.levels.last().unwrap();
+        let mut level = vec![0; next_width * next_height * 4];
+
+        for y in 0..next_height {
+            for x in 0..next_width {
+                let texels = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                    .map(|(dx, dy)| ((x * 2 + dx).min(width - 1), (y * 2 + dy).min(height - 1)));
+                for c in 0..4 {
+                    let sum = texels
+                        .iter()
+                        .map(|(sx, sy)| source[(sy * width + sx) * 4 + c])
+                        .map(|v| if c == 3 { v as f32 / 255.0 } else { to_linear[v as usize] })
+                        .sum::<f32>();
+                    let average = sum / 4.0;
+                    level[(y * next_width + x) * 4 + c] = if c == 3 {
+                        (average.clamp(0.0, 1.0) * 255.0).round() as u8
+                    } else {
+                        from_linear(average)
+                    };
+                }
+            }
+        }
+
+        texture.levels.push(level);
+        (width, height) = (next_width, next_height);
+    }
+
+    texture
+}
+
+/// Creates the image for `texture` and records copying every level into it
+/// from `staging_buffer`, leaving it in `TRANSFER_DST_OPTIMAL`.
+unsafe fn record_texture_copy(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    texture: &TextureData,
+    staging_buffer: vk::Buffer,
+    offsets: &[u64],
+) -> Result<LayeredTexture> {
+    let (width, height, format) = (texture.width, texture.height, texture.format);
+    let mip_levels = texture.levels.len() as u32;
+
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        1,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        vk::ImageCreateFlags::empty(),
+    )?;
+
+    let transition = ImageTransition::new(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .levels(0..mip_levels)
+        .layers(0..1);
+    cmd_barriers(device, data, command_buffer, &[transition], &[]);
+
+    let regions = offsets
+        .iter()
+        .enumerate()
+        .map(|(level, offset)| {
+            let subresource = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(level as u32)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            vk::BufferImageCopy::builder()
+                .buffer_offset(*offset)
+                .buffer_row_length(0)
+                .buffer_image_height(0)
+                .image_subresource(subresource)
+                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_extent(vk::Extent3D {
+                    width: (width >> level).max(1),
+                    height: (height >> level).max(1),
+                    depth: 1,
+                })
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        staging_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &regions,
+    );
+
+    Ok(LayeredTexture {
+        image,
+        image_memory,
+        image_view: vk::ImageView::null(),
+        format,
+        width,
+        height,
+        layers: 1,
+        mip_levels,
+        sampler: vk::Sampler::null(),
+    })
+}
+
+unsafe fn free_upload(device: &Device, data: &AppData, upload: &Upload) {
+    device.destroy_fence(upload.fence, None);
+    device.free_command_buffers(data.transfer_command_pool, &[upload.command_buffer]);
+    device.destroy_buffer(upload.staging_buffer, None);
+    free_memory(device, upload.staging_buffer_memory);
+}
@@ -0,0 +1,163 @@
+use anyhow::Result;
+use log::*;
+use std::path::{Path, PathBuf};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    entity::Entity,
+    lod::{model_lod, Lod},
+    mesh_pool::{MeshPool, PoolAllocation},
+    model::read_model,
+    types::Vec4,
+    visibility::Bounds,
+};
+
+/// A mesh registered with `App::acquire_mesh`, for `Entity::mesh`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle(pub(crate) u32);
+
+/// A texture registered with `App::acquire_texture`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub(crate) u32);
+
+impl TextureHandle {
+    /// The texture table slot, for `Entity::texture` or `Decal::texture`.
+    pub fn texture(self) -> u32 {
+        self.0
+    }
+}
+
+/// A registered mesh, in its ranges of `Assets::pool`.
+#[derive(Clone, Debug)]
+pub(crate) struct MeshAsset {
+    pub(crate) path: PathBuf,
+    pub(crate) references: usize,
+    pub(crate) allocation: PoolAllocation,
+    /// Where each level of detail is in the pool's index buffer, the full
+    /// mesh first.
+    pub(crate) lods: Vec<Lod>,
+    pub(crate) bounds: Bounds,
+}
+
+/// A registered texture, in its own texture table slot.
+#[derive(Clone, Debug)]
+pub(crate) struct TextureAsset {
+    pub(crate) path: PathBuf,
+    pub(crate) references: usize,
+    pub(crate) slot: u32,
+}
+
+/// Meshes and textures loaded once per path and shared by handle, each
+/// unloaded when its last reference is released.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Assets {
+    /// Indexed by `MeshHandle`. Released meshes leave `None` behind, so a
+    /// stale handle never draws another mesh.
+    pub(crate) meshes: Vec<Option<MeshAsset>>,
+    /// The vertices and indices of every mesh in `meshes`.
+    pub(crate) pool: MeshPool,
+    pub(crate) textures: Vec<TextureAsset>,
+    /// Texture table slots released textures left empty, for the next
+    /// texture to take.
+    pub(crate) free_texture_slots: Vec<u32>,
+}
+
+impl Assets {
+    pub(crate) fn mesh(&self, handle: MeshHandle) -> Option<&MeshAsset> {
+        self.meshes.get(handle.0 as usize)?.as_ref()
+    }
+
+    /// The handle of the mesh already loaded from `path`.
+    pub(crate) fn find_mesh(&self, path: &Path) -> Option<MeshHandle> {
+        self.meshes
+            .iter()
+            .position(|m| m.as_ref().is_some_and(|m| m.path == path))
+            .map(|i| MeshHandle(i as u32))
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        self.meshes.clear();
+        self.pool.destroy(device);
+        self.pool = MeshPool::default();
+        self.textures.clear();
+        self.free_texture_slots.clear();
+    }
+}
+
+/// Reads the model at `path` with its levels of detail and copies it into
+/// `data.assets.pool`. Skins are dropped, so skinned models draw in their
+/// bind pose. Also returns whether the pool's buffers were replaced to make
+/// room for it.
+pub(crate) unsafe fn create_mesh_asset(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<(MeshAsset, bool)> {
+    let mut mesh = read_model(path, &data.world)?;
+    mesh.vertices.iter_mut().for_each(|v| v.weights = Vec4::new(0.0, 0.0, 0.0, 0.0));
+
+    let mut pool = std::mem::take(&mut data.assets.pool);
+    let inserted = pool.insert(instance, device, data, &mesh.vertices, &mesh.indices);
+    data.assets.pool = pool;
+    let (allocation, grown) = inserted?;
+    info!("Loaded mesh `{}` ({} vertices, {} levels).", path.display(), mesh.vertices.len(), mesh.lods.len());
+
+    let first_index = allocation.indices.start;
+    Ok((
+        MeshAsset {
+            path: path.to_path_buf(),
+            references: 1,
+            allocation,
+            lods: mesh
+                .lods
+                .iter()
+                .map(|l| Lod { first_index: first_index + l.first_index, index_count: l.index_count })
+                .collect(),
+            bounds: mesh.bounds,
+        },
+        grown,
+    ))
+}
+
+/// The registered mesh `entity` draws, or `None` for the scene model. An
+/// entity whose mesh was released draws the scene model.
+pub(crate) fn entity_mesh<'a>(data: &'a AppData, entity: &Entity) -> Option<&'a MeshAsset> {
+    data.assets.mesh(entity.mesh?)
+}
+
+/// The bounds of the mesh `entity` draws, in its model space.
+pub(crate) fn entity_bounds(data: &AppData, entity: &Entity) -> Bounds {
+    entity_mesh(data, entity).map_or(data.model_bounds, |m| m.bounds)
+}
+
+/// How many levels of detail the mesh `entity` draws has.
+pub(crate) fn entity_lod_count(data: &AppData, entity: &Entity) -> usize {
+    entity_mesh(data, entity).map_or(data.lods.len(), |m| m.lods.len())
+}
+
+/// The index range to draw `entity` at `level` with, or its mesh's coarsest
+/// level if it has fewer.
+pub(crate) fn entity_lod(data: &AppData, entity: &Entity, level: usize) -> Lod {
+    match entity_mesh(data, entity) {
+        Some(mesh) => mesh.lods.get(level).or(mesh.lods.last()).copied().unwrap_or_default(),
+        None => model_lod(data, level),
+    }
+}
+
+/// The buffers to draw `entity` from. Every registered mesh shares those of
+/// the pool.
+pub(crate) fn entity_buffers(data: &AppData, entity: &Entity) -> (vk::Buffer, vk::Buffer, vk::IndexType) {
+    match entity_mesh(data, entity) {
+        Some(_) => (data.assets.pool.vertex_buffer, data.assets.pool.index_buffer, vk::IndexType::UINT32),
+        None => (data.vertex_buffer, data.index_buffer, data.index_type),
+    }
+}
+
+/// The `vertex_offset` to draw `entity` with, where its mesh's vertices
+/// start in the pool.
+pub(crate) fn entity_vertex_offset(data: &AppData, entity: &Entity) -> i32 {
+    entity_mesh(data, entity).map_or(0, |m| m.allocation.vertices.start as i32)
+}
@@ -0,0 +1,299 @@
+use std::ops::Range;
+
+use vulkanalia::{prelude::v1_0::*, vk::DeviceV1_3};
+
+use crate::app::AppData;
+
+/// An image layout transition and the work on either side of it that it
+/// orders. `new` fills in the stages and accesses usual for each layout;
+/// override them with `src` and `dst` where the image is used differently.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ImageTransition {
+    pub(crate) image: vk::Image,
+    pub(crate) old_layout: vk::ImageLayout,
+    pub(crate) new_layout: vk::ImageLayout,
+    pub(crate) src_stage: vk::PipelineStageFlags,
+    pub(crate) src_access: vk::AccessFlags,
+    pub(crate) dst_stage: vk::PipelineStageFlags,
+    pub(crate) dst_access: vk::AccessFlags,
+    pub(crate) range: vk::ImageSubresourceRange,
+    /// The queue families ownership moves between, or
+    /// `QUEUE_FAMILY_IGNORED` for both.
+    pub(crate) queue_families: (u32, u32),
+}
+
+impl ImageTransition {
+    /// Moves every level and layer of a color image from `old_layout` to
+    /// `new_layout`.
+    pub(crate) fn new(image: vk::Image, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> Self {
+        let (src_stage, src_access) = layout_usage(old_layout);
+        let (dst_stage, dst_access) = layout_usage(new_layout);
+        Self {
+            image,
+            old_layout,
+            new_layout,
+            src_stage,
+            src_access,
+            dst_stage,
+            dst_access,
+            range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: vk::REMAINING_MIP_LEVELS,
+                base_array_layer: 0,
+                layer_count: vk::REMAINING_ARRAY_LAYERS,
+            },
+            queue_families: (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+        }
+    }
+
+    pub(crate) fn levels(mut self, levels: Range<u32>) -> Self {
+        self.range.base_mip_level = levels.start;
+        self.range.level_count = levels.len() as u32;
+        self
+    }
+
+    pub(crate) fn layers(mut self, layers: Range<u32>) -> Self {
+        self.range.base_array_layer = layers.start;
+        self.range.layer_count = layers.len() as u32;
+        self
+    }
+
+    /// The work before the transition that it waits for.
+    pub(crate) fn src(mut self, stage: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        self.src_stage = stage;
+        self.src_access = access;
+        self
+    }
+
+    /// The work after the transition that waits for it.
+    pub(crate) fn dst(mut self, stage: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        self.dst_stage = stage;
+        self.dst_access = access;
+        self
+    }
+
+    pub(crate) fn queue_families(mut self, src: u32, dst: u32) -> Self {
+        self.queue_families = (src, dst);
+        self
+    }
+}
+
+/// A barrier over a whole buffer, optionally moving it between queue
+/// families.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct BufferBarrier {
+    pub(crate) buffer: vk::Buffer,
+    pub(crate) src_stage: vk::PipelineStageFlags,
+    pub(crate) src_access: vk::AccessFlags,
+    pub(crate) dst_stage: vk::PipelineStageFlags,
+    pub(crate) dst_access: vk::AccessFlags,
+    pub(crate) queue_families: (u32, u32),
+}
+
+/// The stage and access an image in `layout` is usually last or next used
+/// with. Layouts without a usual use wait on everything.
+fn layout_usage(layout: vk::ImageLayout) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+    match layout {
+        vk::ImageLayout::UNDEFINED => (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL | vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => {
+            (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ)
+        }
+        vk::ImageLayout::GENERAL => (
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+        _ => (
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        ),
+    }
+}
+
+// The classic flags are the low bits of their synchronization2 versions.
+
+fn stage2(stage: vk::PipelineStageFlags) -> vk::PipelineStageFlags2 {
+    vk::PipelineStageFlags2::from_bits_truncate(stage.bits() as u64)
+}
+
+fn access2(access: vk::AccessFlags) -> vk::AccessFlags2 {
+    vk::AccessFlags2::from_bits_truncate(access.bits() as u64)
+}
+
+/// Records `images` and `buffers` as one `vkCmdPipelineBarrier2` when the
+/// device has synchronization2. Otherwise they become one classic barrier,
+/// which waits on the source stages of all of them before any of their
+/// destination stages.
+pub(crate) unsafe fn cmd_barriers(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    images: &[ImageTransition],
+    buffers: &[BufferBarrier],
+) {
+    if images.is_empty() && buffers.is_empty() {
+        return;
+    }
+
+    if data.capabilities.synchronization2 {
+        let images = images
+            .iter()
+            .map(|t| {
+                vk::ImageMemoryBarrier2::builder()
+                    .src_stage_mask(stage2(t.src_stage))
+                    .src_access_mask(access2(t.src_access))
+                    .dst_stage_mask(stage2(t.dst_stage))
+                    .dst_access_mask(access2(t.dst_access))
+                    .old_layout(t.old_layout)
+                    .new_layout(t.new_layout)
+                    .src_queue_family_index(t.queue_families.0)
+                    .dst_queue_family_index(t.queue_families.1)
+                    .image(t.image)
+                    .subresource_range(t.range)
+            })
+            .collect::<Vec<_>>();
+        let buffers = buffers
+            .iter()
+            .map(|b| {
+                vk::BufferMemoryBarrier2::builder()
+                    .src_stage_mask(stage2(b.src_stage))
+                    .src_access_mask(access2(b.src_access))
+                    .dst_stage_mask(stage2(b.dst_stage))
+                    .dst_access_mask(access2(b.dst_access))
+                    .src_queue_family_index(b.queue_families.0)
+                    .dst_queue_family_index(b.queue_families.1)
+                    .buffer(b.buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE as u64)
+            })
+            .collect::<Vec<_>>();
+        let info = vk::DependencyInfo::builder()
+            .image_memory_barriers(&images)
+            .buffer_memory_barriers(&buffers);
+        device.cmd_pipeline_barrier2(command_buffer, &info);
+        return;
+    }
+
+    let src_stage = images.iter().map(|t| t.src_stage).chain(buffers.iter().map(|b| b.src_stage));
+    let dst_stage = images.iter().map(|t| t.dst_stage).chain(buffers.iter().map(|b| b.dst_stage));
+    let src_stage = src_stage.fold(vk::PipelineStageFlags::empty(), |a, b| a | b);
+    let dst_stage = dst_stage.fold(vk::PipelineStageFlags::empty(), |a, b| a | b);
+    let images = images
+        .iter()
+        .map(|t| {
+            vk::ImageMemoryBarrier::builder()
+                .src_access_mask(t.src_access)
+                .dst_access_mask(t.dst_access)
+                .old_layout(t.old_layout)
+                .new_layout(t.new_layout)
+                .src_queue_family_index(t.queue_families.0)
+                .dst_queue_family_index(t.queue_families.1)
+                .image(t.image)
+                .subresource_range(t.range)
+        })
+        .collect::<Vec<_>>();
+    let buffers = buffers
+        .iter()
+        .map(|b| {
+            vk::BufferMemoryBarrier::builder()
+                .src_access_mask(b.src_access)
+                .dst_access_mask(b.dst_access)
+                .src_queue_family_index(b.queue_families.0)
+                .dst_queue_family_index(b.queue_families.1)
+                .buffer(b.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE as u64)
+        })
+        .collect::<Vec<_>>();
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &buffers,
+        &images,
+    );
+}
+
+/// A semaphore a submission waits on before `stage` or signals once done.
+/// `value` is ignored for binary semaphores.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct SemaphoreSubmit {
+    pub(crate) semaphore: vk::Semaphore,
+    pub(crate) value: u64,
+    pub(crate) stage: vk::PipelineStageFlags,
+}
+
+impl SemaphoreSubmit {
+    pub(crate) fn binary(semaphore: vk::Semaphore, stage: vk::PipelineStageFlags) -> Self {
+        Self { semaphore, value: 0, stage }
+    }
+
+    pub(crate) fn timeline(semaphore: vk::Semaphore, value: u64, stage: vk::PipelineStageFlags) -> Self {
+        Self { semaphore, value, stage }
+    }
+}
+
+/// Submits `command_buffers` to `queue` with `vkQueueSubmit2` when the device
+/// has synchronization2, or else with a classic submission whose timeline
+/// values are chained in if the device has timeline semaphores. Classic
+/// submissions signal once every stage is done, whatever the signal stages.
+pub(crate) unsafe fn queue_submit(
+    device: &Device,
+    data: &AppData,
+    queue: vk::Queue,
+    command_buffers: &[vk::CommandBuffer],
+    waits: &[SemaphoreSubmit],
+    signals: &[SemaphoreSubmit],
+    fence: vk::Fence,
+) -> VkResult<()> {
+    if data.capabilities.synchronization2 {
+        let semaphore = |s: &SemaphoreSubmit| {
+            vk::SemaphoreSubmitInfo::builder()
+                .semaphore(s.semaphore)
+                .value(s.value)
+                .stage_mask(stage2(s.stage))
+        };
+        let waits = waits.iter().map(semaphore).collect::<Vec<_>>();
+        let signals = signals.iter().map(semaphore).collect::<Vec<_>>();
+        let command_buffers = command_buffers
+            .iter()
+            .map(|c| vk::CommandBufferSubmitInfo::builder().command_buffer(*c))
+            .collect::<Vec<_>>();
+        let info = vk::SubmitInfo2::builder()
+            .wait_semaphore_infos(&waits)
+            .command_buffer_infos(&command_buffers)
+            .signal_semaphore_infos(&signals);
+        return device.queue_submit2(queue, &[info], fence);
+    }
+
+    let wait_semaphores = waits.iter().map(|s| s.semaphore).collect::<Vec<_>>();
+    let wait_values = waits.iter().map(|s| s.value).collect::<Vec<_>>();
+    let wait_stages = waits.iter().map(|s| s.stage).collect::<Vec<_>>();
+    let signal_semaphores = signals.iter().map(|s| s.semaphore).collect::<Vec<_>>();
+    let signal_values = signals.iter().map(|s| s.value).collect::<Vec<_>>();
+    let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::builder()
+        .wait_semaphore_values(&wait_values)
+        .signal_semaphore_values(&signal_values);
+    let mut info = vk::SubmitInfo::builder()
+        .wait_semaphores(&wait_semaphores)
+        .wait_dst_stage_mask(&wait_stages)
+        .command_buffers(command_buffers)
+        .signal_semaphores(&signal_semaphores);
+    if data.capabilities.timeline_semaphore {
+        info = info.push_next(&mut timeline_info);
+    }
+    device.queue_submit(queue, &[info], fence)
+}
@@ -0,0 +1,994 @@
+use anyhow::{anyhow, Result};
+
+/// A UASTC block mode: its prefix code, the hint bits the transcoder to other
+/// formats uses (skipped here), and the ASTC block it stands for.
+struct UastcMode {
+    code: u32,
+    code_bits: u32,
+    hint_bits: u32,
+    weight_bits: u32,
+    endpoint_range: usize,
+    subsets: usize,
+    planes: usize,
+    cem: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn mode(
+    code: u32,
+    code_bits: u32,
+    hint_bits: u32,
+    weight_bits: u32,
+    endpoint_range: usize,
+    subsets: usize,
+    planes: usize,
+    cem: u32,
+) -> UastcMode {
+    UastcMode { code, code_bits, hint_bits, weight_bits, endpoint_range, subsets, planes, cem }
+}
+
+/// Mode 8 is a solid color; the rest are LDR ASTC 4x4 blocks with a single
+/// endpoint mode (8 is RGB, 12 RGBA, 4 luminance-alpha). The 7-bit code
+/// 0x45 is reserved.
+const UASTC_MODES: [UastcMode; 19] = [
+    mode(0x1, 4, 15, 4, 19, 1, 1, 8),
+    mode(0x35, 6, 15, 2, 20, 1, 1, 8),
+    mode(0x1D, 5, 15, 3, 8, 2, 1, 8),
+    mode(0x3, 5, 15, 2, 7, 3, 1, 8),
+    mode(0x13, 5, 15, 2, 12, 2, 1, 8),
+    mode(0xB, 5, 15, 3, 20, 1, 1, 8),
+    mode(0x1B, 5, 15, 2, 18, 1, 2, 8),
+    mode(0x7, 5, 15, 2, 12, 2, 1, 8),
+    mode(0x17, 5, 0, 0, 0, 0, 1, 0),
+    mode(0xF, 5, 23, 2, 8, 2, 1, 12),
+    mode(0x2, 3, 17, 4, 13, 1, 1, 12),
+    mode(0x0, 2, 17, 2, 13, 1, 2, 12),
+    mode(0x6, 3, 17, 3, 19, 1, 1, 12),
+    mode(0x1F, 5, 23, 1, 20, 1, 2, 12),
+    mode(0xD, 5, 23, 2, 20, 1, 1, 12),
+    mode(0x5, 7, 23, 4, 20, 1, 1, 4),
+    mode(0x15, 6, 23, 2, 20, 2, 1, 4),
+    mode(0x25, 6, 23, 2, 20, 1, 2, 4),
+    mode(0x9, 4, 15, 5, 11, 1, 1, 8),
+];
+
+const UASTC_SOLID_MODE: usize = 8;
+
+/// ASTC integer sequence ranges as (bits, trits, quints), by range index.
+const ASTC_RANGES: [(u32, bool, bool); 21] = [
+    (1, false, false),
+    (0, true, false),
+    (2, false, false),
+    (0, false, true),
+    (1, true, false),
+    (3, false, false),
+    (1, false, true),
+    (2, true, false),
+    (4, false, false),
+    (2, false, true),
+    (3, true, false),
+    (5, false, false),
+    (3, false, true),
+    (4, true, false),
+    (6, false, false),
+    (4, false, true),
+    (5, true, false),
+    (7, false, false),
+    (5, false, true),
+    (6, true, false),
+    (8, false, false),
+];
+
+/// ASTC partition seeds of the 2-subset patterns UASTC shares with BC7.
+const PARTITIONS2: [u32; 30] = [
+    28, 20, 16, 29, 91, 9, 107, 72, 149, 204, 50, 114, 496, 17, 78, 39, 252, 828, 43, 156, 116, 210,
+    476, 273, 684, 359, 246, 195, 694, 524,
+];
+
+/// ASTC partition seeds of the 3-subset patterns UASTC shares with BC7.
+const PARTITIONS3: [u32; 11] = [260, 74, 32, 156, 183, 15, 745, 0, 335, 902, 254];
+
+/// ASTC 2-subset seeds of mode 7, which BC7 covers with its 3-subset patterns.
+const MODE7_PARTITIONS: [u32; 19] = [
+    36, 48, 61, 137, 161, 183, 226, 281, 302, 307, 479, 495, 593, 594, 605, 799, 812, 988, 993,
+];
+
+/// Bytes in a `width` by `height` level of UASTC blocks.
+pub(crate) fn uastc_size(width: u32, height: u32) -> Option<usize> {
+    (width.div_ceil(4) as usize).checked_mul(height.div_ceil(4) as usize)?.checked_mul(16)
+}
+
+/// Transcodes a level of UASTC blocks to RGBA8. `srgb` selects the ASTC
+/// decode mode, which rounds sRGB color channels differently.
+pub(crate) fn transcode_uastc(blocks: &[u8], width: u32, height: u32, srgb: bool) -> Result<Vec<u8>> {
+    let mut pixels = vec![0; width as usize * height as usize * 4];
+    let mut texels = [[0u8; 4]; 16];
+    for (i, block) in blocks.chunks_exact(16).enumerate() {
+        decode_uastc_block(block.try_into().unwrap(), srgb, &mut texels)?;
+        write_block(&mut pixels, width, i, &texels);
+    }
+    Ok(pixels)
+}
+
+/// Copies the 4x4 texels of block `i` into a level, clipping at its edges.
+fn write_block(pixels: &mut [u8], width: u32, i: usize, texels: &[[u8; 4]; 16]) {
+    let width = width as usize;
+    let height = pixels.len() / 4 / width;
+    let blocks_x = width.div_ceil(4);
+    let (bx, by) = ((i % blocks_x) * 4, (i / blocks_x) * 4);
+    for (t, texel) in texels.iter().enumerate() {
+        let (x, y) = (bx + t % 4, by + t / 4);
+        if x < width && y < height {
+            let offset = (y * width + x) * 4;
+            pixels[offset..offset + 4].copy_from_slice(texel);
+        }
+    }
+}
+
+/// The bits of a UASTC block, read from the least significant up.
+struct BlockBits(u128);
+
+impl BlockBits {
+    fn take(&mut self, count: u32) -> u32 {
+        let value = (self.0 & ((1 << count) - 1)) as u32;
+        self.0 >>= count;
+        value
+    }
+}
+
+fn decode_uastc_block(block: &[u8; 16], srgb: bool, texels: &mut [[u8; 4]; 16]) -> Result<()> {
+    let mut bits = BlockBits(u128::from_le_bytes(*block));
+    let index = UASTC_MODES
+        .iter()
+        .position(|m| (bits.0 as u32) & ((1 << m.code_bits) - 1) == m.code)
+        .ok_or_else(|| anyhow!("Invalid UASTC block."))?;
+    let mode = &UASTC_MODES[index];
+    bits.take(mode.code_bits);
+
+    if index == UASTC_SOLID_MODE {
+        let color = [bits.take(8) as u8, bits.take(8) as u8, bits.take(8) as u8, bits.take(8) as u8];
+        texels.fill(color);
+        return Ok(());
+    }
+
+    bits.take(mode.hint_bits);
+    let seed = match (index, mode.subsets) {
+        (7, _) => *MODE7_PARTITIONS.get(bits.take(5) as usize).ok_or_else(|| anyhow!("Invalid UASTC block."))?,
+        (_, 2) => *PARTITIONS2.get(bits.take(5) as usize).ok_or_else(|| anyhow!("Invalid UASTC block."))?,
+        (_, 3) => *PARTITIONS3.get(bits.take(4) as usize).ok_or_else(|| anyhow!("Invalid UASTC block."))?,
+        _ => 0,
+    };
+    let plane_component = match (index, mode.planes) {
+        (17, _) => 3,
+        (_, 2) => bits.take(2) as usize,
+        _ => 4,
+    };
+
+    // Trits and quints come first, packed as base 3 and base 5 numbers, then
+    // the bits of each value.
+    let values_per_subset = match mode.cem {
+        4 => 4,
+        8 => 6,
+        _ => 8,
+    };
+    let count = mode.subsets * values_per_subset;
+    let (value_bits, trits, quints) = ASTC_RANGES[mode.endpoint_range];
+    let mut digits = [0u32; 18];
+    if trits || quints {
+        let (base, per_bundle, bundle_bits): (u32, usize, &[u32]) =
+            if trits { (3, 5, &[2, 4, 5, 7, 8]) } else { (5, 3, &[3, 5, 7]) };
+        for start in (0..count).step_by(per_bundle) {
+            let n = per_bundle.min(count - start);
+            let mut bundle = bits.take(bundle_bits[n - 1]);
+            for digit in &mut digits[start..start + n] {
+                *digit = bundle % base;
+                bundle /= base;
+            }
+        }
+    }
+    let mut values = [0u8; 18];
+    for (value, digit) in values[..count].iter_mut().zip(digits) {
+        *value = unquantize_endpoint(mode.endpoint_range, bits.take(value_bits), digit);
+    }
+
+    let partitions: [usize; 16] = std::array::from_fn(|t| partition(seed, mode.subsets as u32, t));
+    let mut endpoints = [[[0u8; 4]; 2]; 3];
+    for (subset, pair) in endpoints[..mode.subsets].iter_mut().enumerate() {
+        *pair = decode_endpoints(mode.cem, &values[subset * values_per_subset..]);
+    }
+
+    // The first texel of each subset has one bit fewer: its top bit is zero.
+    let mut weights = [[0u32; 16]; 2];
+    for t in 0..16 {
+        let anchor = partitions[..t].iter().all(|&p| p != partitions[t]);
+        for plane in &mut weights[..mode.planes] {
+            let value = bits.take(mode.weight_bits - anchor as u32);
+            plane[t] = unquantize_weight(value, mode.weight_bits);
+        }
+    }
+
+    for (t, texel) in texels.iter_mut().enumerate() {
+        let [e0, e1] = endpoints[partitions[t]];
+        for c in 0..4 {
+            let weight = weights[(c == plane_component) as usize][t];
+            let expand = |e: u8| {
+                let e = e as u32;
+                if srgb && c < 3 { (e << 8) | 0x80 } else { (e << 8) | e }
+            };
+            let value = (expand(e0[c]) * (64 - weight) + expand(e1[c]) * weight + 32) >> 6;
+            texel[c] = (value >> 8) as u8;
+        }
+    }
+    Ok(())
+}
+
+/// ASTC endpoint unquantization of a value split into its low bits and its
+/// trit or quint.
+fn unquantize_endpoint(range: usize, bits: u32, digit: u32) -> u8 {
+    let (count, trits, quints) = ASTC_RANGES[range];
+    if !trits && !quints {
+        let mut value = bits << (8 - count);
+        let mut filled = count;
+        while filled < 8 {
+            value |= value >> filled;
+            filled *= 2;
+        }
+        return value as u8;
+    }
+
+    let a = if bits & 1 != 0 { 0x1FF } else { 0 };
+    let x = bits >> 1;
+    let (b, c) = match (trits, count) {
+        (true, 1) => (0, 204),
+        (true, 2) => (x * 0x116, 93),
+        (true, 3) => ((x << 7) | (x << 2) | x, 44),
+        (true, 4) => ((x << 6) | x, 22),
+        (true, 5) => ((x << 5) | (x >> 2), 11),
+        (true, 6) => ((x << 4) | (x >> 4), 5),
+        (false, 1) => (0, 113),
+        (false, 2) => (x * 0x10C, 54),
+        (false, 3) => ((x << 7) | (x << 1) | (x >> 1), 26),
+        (false, 4) => ((x << 6) | (x >> 1), 13),
+        (false, 5) => ((x << 5) | (x >> 3), 6),
+        _ => unreachable!(),
+    };
+    let t = (digit * c + b) ^ a;
+    ((a & 0x80) | (t >> 2)) as u8
+}
+
+/// Expands a weight to 0-64 by bit replication.
+fn unquantize_weight(value: u32, bits: u32) -> u32 {
+    let mut weight = value << (6 - bits);
+    let mut filled = bits;
+    while filled < 6 {
+        weight |= weight >> filled;
+        filled *= 2;
+    }
+    if weight > 32 { weight + 1 } else { weight }
+}
+
+fn decode_endpoints(cem: u32, v: &[u8]) -> [[u8; 4]; 2] {
+    let blue_contract = |r: u8, g: u8, b: u8, a: u8| {
+        [((r as u32 + b as u32) >> 1) as u8, ((g as u32 + b as u32) >> 1) as u8, b, a]
+    };
+    match cem {
+        4 => [[v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]],
+        _ => {
+            let (a0, a1) = if cem == 12 { (v[6], v[7]) } else { (255, 255) };
+            let s0 = v[0] as u32 + v[2] as u32 + v[4] as u32;
+            let s1 = v[1] as u32 + v[3] as u32 + v[5] as u32;
+            if s1 >= s0 {
+                [[v[0], v[2], v[4], a0], [v[1], v[3], v[5], a1]]
+            } else {
+                [blue_contract(v[1], v[3], v[5], a1), blue_contract(v[0], v[2], v[4], a0)]
+            }
+        }
+    }
+}
+
+fn hash52(mut p: u32) -> u32 {
+    p ^= p >> 15;
+    p = p.wrapping_sub(p << 17);
+    p = p.wrapping_add(p << 7);
+    p = p.wrapping_add(p << 4);
+    p ^= p >> 5;
+    p = p.wrapping_add(p << 16);
+    p ^= p >> 7;
+    p ^= p >> 3;
+    p ^= p << 6;
+    p ^= p >> 17;
+    p
+}
+
+/// The ASTC partition of texel `t` of a 4x4 block.
+fn partition(seed: u32, subsets: u32, t: usize) -> usize {
+    if subsets < 2 {
+        return 0;
+    }
+
+    let seed = seed + (subsets - 1) * 1024;
+    let r = hash52(seed);
+    let (x, y) = ((t as u32 & 3) << 1, (t as u32 >> 2) << 1);
+    let (sh1, sh2) = if seed & 1 != 0 {
+        (if seed & 2 != 0 { 4 } else { 5 }, if subsets == 3 { 6 } else { 5 })
+    } else {
+        (if subsets == 3 { 6 } else { 5 }, if seed & 2 != 0 { 4 } else { 5 })
+    };
+    let s: [u32; 6] = std::array::from_fn(|i| {
+        let n = (r >> (i * 4)) & 0xF;
+        (n * n) >> if i % 2 == 0 { sh1 } else { sh2 }
+    });
+
+    let a = (s[0] * x + s[1] * y + (r >> 14)) & 0x3F;
+    let b = (s[2] * x + s[3] * y + (r >> 10)) & 0x3F;
+    let c = if subsets == 3 { (s[4] * x + s[5] * y + (r >> 6)) & 0x3F } else { 0 };
+    if a >= b && a >= c {
+        0
+    } else if b >= c {
+        1
+    } else {
+        2
+    }
+}
+
+/// LSB-first reader of ETC1S slice and codebook bits.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.bytes.get(self.position / 8).ok_or_else(|| anyhow!("Truncated ETC1S data."))?;
+            value |= ((*byte as u32 >> (self.position % 8)) & 1) << i;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    /// A variable-length count in chunks of `chunk_bits`, each followed by a
+    /// bit saying whether another chunk follows.
+    fn vlc(&mut self, chunk_bits: u32) -> Result<u32> {
+        let mut value = 0;
+        for shift in (0..32).step_by(chunk_bits as usize) {
+            let chunk = self.bits(chunk_bits + 1)?;
+            value |= (chunk & ((1 << chunk_bits) - 1)) << shift;
+            if chunk >> chunk_bits == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    }
+}
+
+/// A canonical Huffman code, its codes read a bit at a time from the top.
+struct Huffman {
+    counts: [u16; 17],
+    symbols: Vec<u16>,
+}
+
+/// The order code length code sizes are stored in.
+const CODE_LENGTH_ORDER: [usize; 21] = [17, 18, 19, 20, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15, 16];
+
+impl Huffman {
+    fn new(sizes: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; 17];
+        for &size in sizes {
+            counts[size as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(anyhow!("Invalid ETC1S Huffman table."));
+            }
+        }
+
+        let mut symbols = (0..sizes.len() as u16).filter(|&s| sizes[s as usize] != 0).collect::<Vec<_>>();
+        symbols.sort_by_key(|&s| sizes[s as usize]);
+        Ok(Self { counts, symbols })
+    }
+
+    fn read(reader: &mut BitReader) -> Result<Self> {
+        let total = reader.bits(14)? as usize;
+        if total == 0 {
+            return Self::new(&[]);
+        }
+
+        let mut code_length_sizes = [0u8; 21];
+        let stored = reader.bits(5)? as usize;
+        if !(1..=21).contains(&stored) {
+            return Err(anyhow!("Invalid ETC1S Huffman table."));
+        }
+        for &symbol in &CODE_LENGTH_ORDER[..stored] {
+            code_length_sizes[symbol] = reader.bits(3)? as u8;
+        }
+        let code_lengths = Self::new(&code_length_sizes)?;
+
+        let mut sizes = Vec::with_capacity(total);
+        while sizes.len() < total {
+            let (value, run) = match code_lengths.decode(reader)? {
+                size @ 0..=16 => (size as u8, 1),
+                17 => (0, reader.bits(3)? + 3),
+                18 => (0, reader.bits(7)? + 11),
+                symbol => {
+                    let run = if symbol == 19 { reader.bits(2)? + 3 } else { reader.bits(7)? + 7 };
+                    match sizes.last() {
+                        Some(&size) if size != 0 => (size, run),
+                        _ => return Err(anyhow!("Invalid ETC1S Huffman table.")),
+                    }
+                }
+            };
+            if sizes.len() + run as usize > total {
+                return Err(anyhow!("Invalid ETC1S Huffman table."));
+            }
+            sizes.resize(sizes.len() + run as usize, value);
+        }
+        Self::new(&sizes)
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u32> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as u32);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(anyhow!("Invalid ETC1S Huffman code."))
+    }
+}
+
+const ETC1_INTENSITIES: [[i32; 2]; 8] = [[8, 2], [17, 5], [29, 9], [42, 13], [60, 18], [80, 24], [106, 33], [183, 47]];
+
+/// Endpoint delta models are picked by the previous value.
+const COLOR5_MODEL0_MAX: u8 = 9;
+const COLOR5_MODEL1_MAX: u8 = 21;
+
+/// A run of this many or more identical endpoint predictions is coded as a
+/// repeat, and the selector run symbol below it as a count.
+const ENDPOINT_PRED_REPEAT: u32 = 256;
+const SELECTOR_RUN_LONG: u32 = 63;
+
+/// `imageFlags` of a P-frame, which predicts from the previous image.
+const ETC1S_P_FRAME: u32 = 0x2;
+
+#[derive(Copy, Clone)]
+struct Etc1sEndpoint {
+    color: [u8; 3],
+    intensity: u8,
+}
+
+#[derive(Copy, Clone)]
+struct Etc1sImage {
+    flags: u32,
+    rgb: (usize, usize),
+    alpha: (usize, usize),
+}
+
+/// The codebooks and Huffman tables a KTX2 file's ETC1S images share, from
+/// its supercompression global data.
+pub(crate) struct Etc1s {
+    endpoints: Vec<Etc1sEndpoint>,
+    selectors: Vec<[u8; 16]>,
+    endpoint_pred: Huffman,
+    endpoint_delta: Huffman,
+    selector: Huffman,
+    selector_run: Huffman,
+    history_size: usize,
+    images: Vec<Etc1sImage>,
+}
+
+impl Etc1s {
+    pub(crate) fn new(global_data: &[u8], image_count: usize) -> Result<Self> {
+        let truncated = || anyhow!("Truncated ETC1S global data.");
+        let read_u32 =
+            |offset: usize| global_data.get(offset..offset + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()));
+
+        let counts = read_u32(0).ok_or_else(truncated)?;
+        let (endpoint_count, selector_count) = ((counts & 0xFFFF) as usize, (counts >> 16) as usize);
+        let lengths = (0..4)
+            .map(|i| read_u32(4 + i * 4).map(|length| length as usize))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(truncated)?;
+        if endpoint_count == 0 || selector_count == 0 {
+            return Err(anyhow!("ETC1S global data without codebooks."));
+        }
+
+        let images = (0..image_count)
+            .map(|i| {
+                let [flags, rgb_offset, rgb_length, alpha_offset, alpha_length] =
+                    std::array::from_fn(|j| read_u32(20 + i * 20 + j * 4));
+                Some(Etc1sImage {
+                    flags: flags?,
+                    rgb: (rgb_offset? as usize, rgb_length? as usize),
+                    alpha: (alpha_offset? as usize, alpha_length? as usize),
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(truncated)?;
+
+        let mut offset = 20 + image_count * 20;
+        let mut sections = lengths.iter().map(|&length| {
+            let section = global_data.get(offset..offset.checked_add(length)?);
+            offset += length;
+            section
+        });
+        let mut section = || sections.next().flatten().ok_or_else(truncated);
+        let (endpoint_data, selector_data, table_data) = (section()?, section()?, section()?);
+
+        let endpoints = decode_endpoint_codebook(endpoint_data, endpoint_count)?;
+        let selectors = decode_selector_codebook(selector_data, selector_count)?;
+
+        let mut reader = BitReader::new(table_data);
+        let endpoint_pred = Huffman::read(&mut reader)?;
+        let endpoint_delta = Huffman::read(&mut reader)?;
+        let selector = Huffman::read(&mut reader)?;
+        let selector_run = Huffman::read(&mut reader)?;
+        let history_size = reader.bits(13)? as usize;
+        if history_size == 0 {
+            return Err(anyhow!("ETC1S global data without a selector history."));
+        }
+
+        Ok(Self { endpoints, selectors, endpoint_pred, endpoint_delta, selector, selector_run, history_size, images })
+    }
+
+    /// Transcodes image `index` to RGBA8. Its slices are at offsets into
+    /// `level`, the level's data in the file.
+    pub(crate) fn transcode(&self, index: usize, level: &[u8], width: u32, height: u32) -> Result<Vec<u8>> {
+        let image = self.images[index];
+        if image.flags & ETC1S_P_FRAME != 0 {
+            return Err(anyhow!("ETC1S video frames are not supported."));
+        }
+        let slice = |(offset, length): (usize, usize)| {
+            offset
+                .checked_add(length)
+                .and_then(|end| level.get(offset..end))
+                .ok_or_else(|| anyhow!("ETC1S slice of image {} is out of bounds.", index))
+        };
+
+        let blocks_x = width.div_ceil(4) as usize;
+        let blocks_y = height.div_ceil(4) as usize;
+        let rgb = self.decode_slice(slice(image.rgb)?, blocks_x, blocks_y)?;
+        let alpha = match image.alpha.1 {
+            0 => None,
+            _ => Some(self.decode_slice(slice(image.alpha)?, blocks_x, blocks_y)?),
+        };
+
+        let mut pixels = vec![0; width as usize * height as usize * 4];
+        let mut texels = [[0u8; 4]; 16];
+        for i in 0..blocks_x * blocks_y {
+            let (colors, selector) = self.block(rgb[i]);
+            for (texel, &s) in texels.iter_mut().zip(selector) {
+                *texel = [colors[s as usize][0], colors[s as usize][1], colors[s as usize][2], 255];
+            }
+            if let Some(alpha) = &alpha {
+                // Alpha is in the green channel of its slice.
+                let (colors, selector) = self.block(alpha[i]);
+                for (texel, &s) in texels.iter_mut().zip(selector) {
+                    texel[3] = colors[s as usize][1];
+                }
+            }
+            write_block(&mut pixels, width, i, &texels);
+        }
+        Ok(pixels)
+    }
+
+    /// The four colors and the selectors of a block's codebook entries.
+    fn block(&self, (endpoint, selector): (usize, usize)) -> ([[u8; 3]; 4], &[u8; 16]) {
+        let Etc1sEndpoint { color, intensity } = self.endpoints[endpoint];
+        let [large, small] = ETC1_INTENSITIES[intensity as usize];
+        let colors = [-large, -small, small, large].map(|delta| {
+            color.map(|c| {
+                let base = ((c << 3) | (c >> 2)) as i32;
+                (base + delta).clamp(0, 255) as u8
+            })
+        });
+        (colors, &self.selectors[selector])
+    }
+
+    /// Decodes the endpoint and selector indices of each block of a slice.
+    fn decode_slice(&self, slice: &[u8], blocks_x: usize, blocks_y: usize) -> Result<Vec<(usize, usize)>> {
+        let invalid = || anyhow!("Invalid ETC1S slice.");
+        let mut reader = BitReader::new(slice);
+        let mut blocks = Vec::with_capacity(blocks_x * blocks_y);
+
+        // Each prediction symbol covers a 2x2 group of blocks; the bottom
+        // row's half is kept for the next row of blocks.
+        let mut next_row_preds = vec![0u32; blocks_x];
+        let mut pred_bits = 0;
+        let mut previous_pred = 0;
+        let mut pred_repeats = 0;
+        let mut previous_endpoint = 0;
+
+        // Recently used selectors, kept roughly most recent first.
+        let mut history = vec![0usize; self.history_size];
+        let mut rover = self.history_size / 2;
+        let mut selector_run = 0;
+
+        let endpoint_count = self.endpoints.len();
+        let selector_count = self.selectors.len() as u32;
+        for y in 0..blocks_y {
+            for x in 0..blocks_x {
+                if x % 2 == 0 {
+                    if y % 2 == 0 {
+                        if pred_repeats > 0 {
+                            pred_repeats -= 1;
+                            pred_bits = previous_pred;
+                        } else {
+                            pred_bits = self.endpoint_pred.decode(&mut reader)?;
+                            if pred_bits == ENDPOINT_PRED_REPEAT {
+                                pred_repeats = reader.vlc(4)? + 2;
+                                pred_bits = previous_pred;
+                            } else {
+                                previous_pred = pred_bits;
+                            }
+                        }
+                        next_row_preds[x] = pred_bits >> 4;
+                    } else {
+                        pred_bits = next_row_preds[x];
+                    }
+                }
+
+                let above = |dx: usize| {
+                    (y > 0 && x >= dx).then(|| blocks[(y - 1) * blocks_x + x - dx]).map(|(e, _)| e).ok_or_else(invalid)
+                };
+                let endpoint = match pred_bits & 3 {
+                    0 if x > 0 => previous_endpoint,
+                    0 => return Err(invalid()),
+                    1 => above(0)?,
+                    2 => above(1)?,
+                    _ => {
+                        let endpoint = previous_endpoint + self.endpoint_delta.decode(&mut reader)? as usize;
+                        let endpoint = if endpoint >= endpoint_count { endpoint - endpoint_count } else { endpoint };
+                        if endpoint >= endpoint_count {
+                            return Err(invalid());
+                        }
+                        endpoint
+                    }
+                };
+                pred_bits >>= 2;
+                previous_endpoint = endpoint;
+
+                let symbol = if selector_run > 0 {
+                    selector_run -= 1;
+                    selector_count
+                } else {
+                    let symbol = self.selector.decode(&mut reader)?;
+                    if symbol == selector_count + self.history_size as u32 {
+                        let run = match self.selector_run.decode(&mut reader)? {
+                            SELECTOR_RUN_LONG => reader.vlc(7)? + 3,
+                            run => run + 3,
+                        };
+                        if run as usize > blocks_x * blocks_y {
+                            return Err(invalid());
+                        }
+                        selector_run = run - 1;
+                        selector_count
+                    } else {
+                        symbol
+                    }
+                };
+                let selector = if symbol >= selector_count {
+                    let i = (symbol - selector_count) as usize;
+                    let selector = *history.get(i).ok_or_else(invalid)?;
+                    history.swap(i / 2, i);
+                    selector
+                } else {
+                    history[rover] = symbol as usize;
+                    rover += 1;
+                    if rover == self.history_size {
+                        rover = self.history_size / 2;
+                    }
+                    symbol as usize
+                };
+
+                blocks.push((endpoint, selector));
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+/// Endpoints are deltas from the previous one, coded with one of three
+/// tables by the previous value, and an intensity delta.
+fn decode_endpoint_codebook(data: &[u8], count: usize) -> Result<Vec<Etc1sEndpoint>> {
+    let mut reader = BitReader::new(data);
+    let color_models = [Huffman::read(&mut reader)?, Huffman::read(&mut reader)?, Huffman::read(&mut reader)?];
+    let intensity_model = Huffman::read(&mut reader)?;
+    let grayscale = reader.bits(1)? != 0;
+
+    let mut previous = Etc1sEndpoint { color: [16; 3], intensity: 0 };
+    let mut endpoints = Vec::with_capacity(count);
+    for _ in 0..count {
+        let intensity = ((intensity_model.decode(&mut reader)? + previous.intensity as u32) & 7) as u8;
+        let mut color = previous.color;
+        for c in &mut color[..if grayscale { 1 } else { 3 }] {
+            let model = if *c <= COLOR5_MODEL0_MAX {
+                &color_models[0]
+            } else if *c <= COLOR5_MODEL1_MAX {
+                &color_models[1]
+            } else {
+                &color_models[2]
+            };
+            *c = ((*c as u32 + model.decode(&mut reader)?) & 31) as u8;
+        }
+        if grayscale {
+            color = [color[0]; 3];
+        }
+        previous = Etc1sEndpoint { color, intensity };
+        endpoints.push(previous);
+    }
+    Ok(endpoints)
+}
+
+/// Selectors are a byte per row of 2-bit values, stored raw or as the XOR
+/// with the previous selector's rows.
+fn decode_selector_codebook(data: &[u8], count: usize) -> Result<Vec<[u8; 16]>> {
+    let mut reader = BitReader::new(data);
+    if reader.bits(1)? != 0 || reader.bits(1)? != 0 {
+        return Err(anyhow!("ETC1S global selector codebooks are not supported."));
+    }
+    let raw = reader.bits(1)? != 0;
+    let delta_model = if raw { None } else { Some(Huffman::read(&mut reader)?) };
+
+    let mut rows = [0u32; 4];
+    let mut selectors = Vec::with_capacity(count);
+    for i in 0..count {
+        let mut selector = [0u8; 16];
+        for (y, row) in rows.iter_mut().enumerate() {
+            *row = match &delta_model {
+                Some(model) if i > 0 => model.decode(&mut reader)? ^ *row,
+                _ => reader.bits(8)?,
+            };
+            for x in 0..4 {
+                selector[y * 4 + x] = ((*row >> (x * 2)) & 3) as u8;
+            }
+        }
+        selectors.push(selector);
+    }
+    Ok(selectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LSB-first bit writer for building test data.
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        position: usize,
+    }
+
+    impl BitWriter {
+        fn bits(&mut self, value: u32, count: u32) {
+            for i in 0..count {
+                if self.position.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                *self.bytes.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (self.position % 8);
+                self.position += 1;
+            }
+        }
+
+        /// A code of `count` bits, written from its top bit.
+        fn code(&mut self, code: u32, count: u32) {
+            for i in (0..count).rev() {
+                self.bits(code >> i, 1);
+            }
+        }
+
+        /// A Huffman table giving each of `symbols` a code of `size` bits,
+        /// the code length codes themselves all 5 bits long.
+        fn table(&mut self, symbols: usize, size: u32) {
+            self.bits(symbols as u32, 14);
+            self.bits(21, 5);
+            for _ in CODE_LENGTH_ORDER {
+                self.bits(5, 3);
+            }
+            for _ in 0..symbols {
+                self.code(size, 5);
+            }
+        }
+    }
+
+    #[test]
+    fn uastc_mode_codes_are_a_prefix_code() {
+        let kraft: f64 = UASTC_MODES.iter().map(|m| 0.5f64.powi(m.code_bits as i32)).sum();
+        assert_eq!(kraft + 0.5f64.powi(7), 1.0);
+        assert!(UASTC_MODES.iter().all(|m| 0x45 & ((1 << m.code_bits) - 1) != m.code));
+        for (i, a) in UASTC_MODES.iter().enumerate() {
+            for b in &UASTC_MODES[i + 1..] {
+                let bits = a.code_bits.min(b.code_bits);
+                assert_ne!(a.code & ((1 << bits) - 1), b.code & ((1 << bits) - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn two_subset_partitions_match_bc7() {
+        // (BC7 partition, its subset mask, whether ASTC swaps the subsets)
+        let bc7 = [
+            (0, 0xCCCC, false),
+            (1, 0x8888, false),
+            (2, 0xEEEE, true),
+            (3, 0xECC8, false),
+            (4, 0xC880, true),
+            (5, 0xFEEC, false),
+            (6, 0xFEC8, true),
+            (7, 0xEC80, true),
+            (8, 0xC800, false),
+            (9, 0xFFEC, true),
+            (10, 0xFE80, false),
+            (11, 0xE800, true),
+            (12, 0xFFE8, true),
+            (13, 0xFF00, true),
+            (14, 0xFFF0, false),
+            (15, 0xF000, true),
+            (17, 0x008E, true),
+            (18, 0x7100, true),
+            (19, 0x08CE, false),
+            (20, 0x008C, false),
+            (21, 0x7310, false),
+            (22, 0x3100, true),
+            (23, 0x8CCE, true),
+            (24, 0x088C, false),
+            (25, 0x3110, true),
+            (26, 0x6666, false),
+            (29, 0x0FF0, true),
+            (32, 0xAAAA, true),
+            (33, 0xF0F0, true),
+            (52, 0xC936, true),
+        ];
+        for (&seed, (partition_index, mask, swapped)) in PARTITIONS2.iter().zip(bc7) {
+            let astc = (0..16).fold(0, |m, t| m | (partition(seed, 2, t) << t));
+            let expected = if swapped { !mask & 0xFFFF } else { mask };
+            assert_eq!(astc, expected, "BC7 partition {}", partition_index);
+        }
+    }
+
+    #[test]
+    fn solid_uastc_blocks_are_decoded() {
+        let mut block = BitWriter::default();
+        block.bits(0x17, 5);
+        for c in [10, 20, 30, 40] {
+            block.bits(c, 8);
+        }
+        block.bytes.resize(16, 0);
+
+        let pixels = transcode_uastc(&block.bytes, 3, 2, false).unwrap();
+        assert_eq!(pixels, [10, 20, 30, 40].repeat(6));
+    }
+
+    #[test]
+    fn uastc_weights_interpolate_the_endpoints() {
+        // Mode 5: one RGB subset, 8-bit endpoints and 3-bit weights.
+        let mut block = BitWriter::default();
+        block.bits(0xB, 5);
+        block.bits(0, 15);
+        for value in [0, 255, 64, 64, 255, 0] {
+            block.bits(value, 8);
+        }
+        for t in 0..16 {
+            block.bits(t % 8, if t == 0 { 2 } else { 3 });
+        }
+        block.bytes.resize(16, 0);
+
+        let pixels = transcode_uastc(&block.bytes, 4, 4, false).unwrap();
+        let weights = [0, 9, 18, 27, 37, 46, 55, 64];
+        for (t, texel) in pixels.chunks_exact(4).enumerate() {
+            let w = weights[t % 8];
+            let red = ((0xFFFF * w + 32) >> 6) >> 8;
+            let blue = ((0xFFFF * (64 - w) + 32) >> 6) >> 8;
+            assert_eq!(texel, [red as u8, 64, blue as u8, 255], "texel {}", t);
+        }
+    }
+
+    #[test]
+    fn astc_endpoint_unquantization_spans_the_range() {
+        for range in [7, 12, 13, 18, 19] {
+            let (bits, trits, _) = ASTC_RANGES[range];
+            let levels = if trits { 3 } else { 5 };
+            let mut values = (0..levels)
+                .flat_map(|digit| (0..1 << bits).map(move |b| unquantize_endpoint(range, b, digit)))
+                .collect::<Vec<_>>();
+            values.sort();
+            assert_eq!((values[0], values[values.len() - 1]), (0, 255), "range {}", range);
+            values.dedup();
+            assert_eq!(values.len(), (levels as usize) << bits, "range {}", range);
+        }
+    }
+
+    /// Global data for two endpoints and two selectors, and one 8x4 image
+    /// whose blocks use one each.
+    fn etc1s_global_data() -> Vec<u8> {
+        // Endpoints (4, 8, 12) intensity 1, then (6, 8, 12) intensity 3.
+        let mut endpoints = BitWriter::default();
+        for _ in 0..3 {
+            endpoints.table(32, 5);
+        }
+        endpoints.table(8, 5);
+        endpoints.bits(0, 1);
+        for (intensity, deltas) in [(1, [20, 24, 28]), (2, [2, 0, 0])] {
+            endpoints.code(intensity, 5);
+            for delta in deltas {
+                endpoints.code(delta, 5);
+            }
+        }
+
+        // Raw selectors: all 3s, then a row of each value.
+        let mut selectors = BitWriter::default();
+        selectors.bits(0b100, 3);
+        for row in [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x55, 0xAA, 0xFF] {
+            selectors.bits(row, 8);
+        }
+
+        let mut tables = BitWriter::default();
+        tables.table(257, 9);
+        tables.table(2, 5);
+        tables.table(2 + 8 + 1, 5);
+        tables.table(64, 6);
+        tables.bits(8, 13);
+
+        // Both blocks predict their endpoint as a delta: symbol 0b1111.
+        let mut slice = BitWriter::default();
+        slice.code(0xF, 9);
+        slice.code(0, 5);
+        slice.code(0, 5);
+        slice.code(1, 5);
+        slice.code(1, 5);
+
+        let mut data = Vec::new();
+        data.extend(2u16.to_le_bytes());
+        data.extend(2u16.to_le_bytes());
+        for length in [endpoints.bytes.len(), selectors.bytes.len(), tables.bytes.len(), 0] {
+            data.extend((length as u32).to_le_bytes());
+        }
+        for value in [0, 0, slice.bytes.len() as u32, 0, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(endpoints.bytes);
+        data.extend(selectors.bytes);
+        data.extend(tables.bytes);
+        data.extend(slice.bytes);
+        data
+    }
+
+    #[test]
+    fn etc1s_slices_are_decoded() {
+        let data = etc1s_global_data();
+        let etc1s = Etc1s::new(&data, 1).unwrap();
+        let slice_start = data.len() - 4;
+        let pixels = etc1s.transcode(0, &data[slice_start..], 8, 4).unwrap();
+
+        // (4, 8, 12) expands to (33, 66, 99), (6, 8, 12) to (49, 66, 99).
+        let texel = |x: usize, y: usize| &pixels[(y * 8 + x) * 4..(y * 8 + x) * 4 + 4];
+        assert_eq!(texel(0, 0), [50, 83, 116, 255]);
+        assert_eq!(texel(3, 3), [50, 83, 116, 255]);
+        assert_eq!(texel(4, 0), [7, 24, 57, 255]);
+        assert_eq!(texel(5, 1), [36, 53, 86, 255]);
+        assert_eq!(texel(6, 2), [62, 79, 112, 255]);
+        assert_eq!(texel(7, 3), [91, 108, 141, 255]);
+    }
+
+    #[test]
+    fn etc1s_video_frames_are_rejected() {
+        let mut data = etc1s_global_data();
+        data[20] = ETC1S_P_FRAME as u8;
+        let etc1s = Etc1s::new(&data, 1).unwrap();
+        assert!(etc1s.transcode(0, &data[data.len() - 4..], 8, 4).is_err());
+    }
+}
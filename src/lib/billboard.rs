@@ -0,0 +1,163 @@
+use anyhow::Result;
+use cgmath::{vec2, Zero};
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    dynamic_buffer::DynamicBuffer,
+    oit::OitWrites,
+    particles::create_quad_pipeline,
+    texture_atlas::{resolve_texture, texture_region, texture_slot},
+    types::{Vec2, Vec3, Vec4},
+};
+
+/// The corners of a billboard's quad, two triangles, from -1 to 1.
+const CORNERS: [[f32; 2]; 6] = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+
+/// How a billboard turns to face the camera.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BillboardFacing {
+    /// Always square on to the view, like particles.
+    #[default]
+    Spherical,
+    /// Kept upright along the world's up axis and only turned about it,
+    /// for trees, grass and impostors.
+    Cylindrical,
+}
+
+/// A textured quad that always faces the camera. Billboards are drawn after
+/// the opaque scene, blended back to front, or accumulated with transparent
+/// entities when `App::order_independent_transparency` is on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Billboard {
+    pub position: Vec3,
+    /// Half the quad's width and height.
+    pub size: Vec2,
+    pub facing: BillboardFacing,
+    /// As `Entity::texture`.
+    pub texture: u32,
+    /// The part of the texture drawn, from the top-left corner to the
+    /// bottom-right.
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+    /// Multiplies the texture. Alpha is opacity.
+    pub color: Vec4,
+}
+
+impl Billboard {
+    /// A spherical billboard showing the whole of `texture`.
+    pub fn new(position: Vec3, size: Vec2, texture: u32) -> Self {
+        Self {
+            position,
+            size,
+            facing: BillboardFacing::Spherical,
+            texture,
+            uv_min: vec2(0.0, 0.0),
+            uv_max: vec2(1.0, 1.0),
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BillboardVertex {
+    pub(crate) pos: Vec3,
+    pub(crate) corner: Vec2,
+    pub(crate) size: Vec2,
+    pub(crate) tex_coords: Vec2,
+    pub(crate) color: Vec4,
+    /// Zero for spherical billboards.
+    pub(crate) axis: Vec3,
+    pub(crate) texture: u32,
+}
+
+impl BillboardVertex {
+    pub(crate) fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<BillboardVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub(crate) fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 7] {
+        let (vec2_size, vec3_size) = (size_of::<Vec2>() as u32, size_of::<Vec3>() as u32);
+        let attribute = |location: u32, format: vk::Format, offset: u32| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(location)
+                .format(format)
+                .offset(offset)
+                .build()
+        };
+
+        [
+            attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32_SFLOAT, vec3_size),
+            attribute(2, vk::Format::R32G32_SFLOAT, vec3_size + vec2_size),
+            attribute(3, vk::Format::R32G32_SFLOAT, vec3_size + vec2_size * 2),
+            attribute(4, vk::Format::R32G32B32A32_SFLOAT, vec3_size + vec2_size * 3),
+            attribute(5, vk::Format::R32G32B32_SFLOAT, vec3_size + vec2_size * 3 + size_of::<Vec4>() as u32),
+            attribute(6, vk::Format::R32_UINT, vec3_size * 2 + vec2_size * 3 + size_of::<Vec4>() as u32),
+        ]
+    }
+}
+
+/// Appends a quad for each billboard. Atlas regions are resolved here, so
+/// the shader only sees texture table slots.
+pub(crate) fn billboard_vertices(data: &AppData, billboards: &[Billboard], vertices: &mut Vec<BillboardVertex>) {
+    for billboard in billboards {
+        let texture = resolve_texture(data, billboard.texture);
+        let region = data.atlas_regions[texture_region(texture) as usize];
+        let axis = match billboard.facing {
+            BillboardFacing::Spherical => Vec3::zero(),
+            BillboardFacing::Cylindrical => data.world.up_vector(),
+        };
+        vertices.extend(CORNERS.iter().map(|c| {
+            // Corners run bottom to top, texture coordinates top to bottom.
+            let (s, t) = ((c[0] + 1.0) / 2.0, (1.0 - c[1]) / 2.0);
+            let uv = vec2(
+                billboard.uv_min.x + s * (billboard.uv_max.x - billboard.uv_min.x),
+                billboard.uv_min.y + t * (billboard.uv_max.y - billboard.uv_min.y),
+            );
+            BillboardVertex {
+                pos: billboard.position,
+                corner: vec2(c[0], c[1]),
+                size: billboard.size,
+                tex_coords: vec2(region.x + uv.x * region.z, region.y + uv.y * region.w),
+                color: billboard.color,
+                axis,
+                texture: texture_slot(texture),
+            }
+        }));
+    }
+}
+
+pub(crate) unsafe fn create_billboard_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let vert = include_bytes!("../../shaders/billboard_vert.spv");
+    let (frag, oit_writes) = if data.order_independent_transparency {
+        (&include_bytes!("../../shaders/billboard_frag_oit.spv")[..], OitWrites::Accumulate)
+    } else {
+        (&include_bytes!("../../shaders/billboard_frag.spv")[..], OitWrites::Masked)
+    };
+    data.billboard_pipeline = create_quad_pipeline(
+        device,
+        data,
+        &vert[..],
+        frag,
+        &[BillboardVertex::binding_description()],
+        &BillboardVertex::attribute_descriptions(),
+        oit_writes,
+    )
+    .unwrap();
+
+    Ok(())
+}
+
+pub(crate) unsafe fn create_billboard_vertex_buffers(data: &mut AppData) -> Result<()> {
+    data.billboard_vertex_buffers = vec![DynamicBuffer::default(); data.swapchain_images.len()];
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use anyhow::Result;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+
+/// Upper bound on the texture table when descriptor indexing is available.
+pub(crate) const BINDLESS_TEXTURE_CAPACITY: u32 = 4096;
+
+/// Without descriptor indexing every slot must be written, so the table is
+/// kept to the smallest sampler limit Vulkan guarantees.
+pub(crate) const FALLBACK_TEXTURE_CAPACITY: u32 = 16;
+
+/// One array of every 2D texture the scene samples, bound once as set 1 and
+/// indexed per draw. Slot 0 is the scene texture. The atlas region table
+/// comes first, since the variable sized array has to be the last binding.
+pub(crate) unsafe fn create_texture_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let regions_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(data.texture_capacity)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    // Slots are filled as textures load, while earlier frames still use the
+    // set.
+    let binding_flags = &[
+        vk::DescriptorBindingFlags::empty(),
+        vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+    ];
+    let mut flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::builder().binding_flags(binding_flags);
+
+    let bindings = &[regions_binding, binding];
+    let mut info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    if data.descriptor_indexing {
+        info = info
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .push_next(&mut flags_info);
+    }
+
+    data.texture_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+pub(crate) unsafe fn create_texture_descriptor_set(device: &Device, data: &mut AppData) -> Result<()> {
+    let regions_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(data.texture_capacity);
+
+    let pool_sizes = &[regions_size, size];
+    let mut info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    if data.descriptor_indexing {
+        info = info.flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND);
+    }
+    data.texture_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let counts = &[data.texture_capacity];
+    let mut count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::builder().descriptor_counts(counts);
+
+    let layouts = &[data.texture_set_layout];
+    let mut info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.texture_descriptor_pool)
+        .set_layouts(layouts);
+    if data.descriptor_indexing {
+        info = info.push_next(&mut count_info);
+    }
+    data.texture_descriptor_set = device.allocate_descriptor_sets(&info).unwrap()[0];
+
+    let info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.atlas_region_buffer)
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64);
+
+    let buffer_info = &[info];
+    let regions_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.texture_descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(buffer_info);
+
+    device.update_descriptor_sets(&[regions_write], &[] as &[vk::CopyDescriptorSet]);
+
+    write_texture_table(device, data);
+    Ok(())
+}
+
+/// The number of slots in use, counting the scene texture.
+pub(crate) fn texture_count(data: &AppData) -> u32 {
+    data.textures.len() as u32 + 1
+}
+
+/// Writes every slot in use. Without descriptor indexing the unused slots
+/// must be valid too, so they repeat the scene texture, as do slots whose
+/// texture is still loading.
+pub(crate) unsafe fn write_texture_table(device: &Device, data: &AppData) {
+    let scene = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.texture_image_view)
+        .sampler(data.texture_sampler)
+        .build();
+
+    let textures = data.textures.iter().map(|t| match t {
+        Some(t) => vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(t.image_view)
+            .sampler(t.sampler)
+            .build(),
+        None => scene,
+    });
+
+    let slots = if data.descriptor_indexing {
+        texture_count(data)
+    } else {
+        data.texture_capacity
+    };
+    let image_info = std::iter::once(scene)
+        .chain(textures)
+        .chain(std::iter::repeat(scene))
+        .take(slots as usize)
+        .collect::<Vec<_>>();
+
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.texture_descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&image_info);
+
+    device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+}
@@ -0,0 +1,519 @@
+use anyhow::{anyhow, Result};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::texture::TextureData;
+
+type BlockDecoder = fn(&[u8], &mut [[u8; 4]; 16]);
+
+/// Bytes per 4x4 block for block-compressed formats.
+pub(crate) fn block_size(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => Some(8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => Some(16),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_block_compressed(format: vk::Format) -> bool {
+    block_size(format).is_some()
+}
+
+/// Decodes a BC1-BC5 or BC7 texture to RGBA8 on the CPU, for devices that
+/// cannot sample the compressed format. BC4 and BC5 expand to red and
+/// red-green. BC6H holds HDR color and has no decoder here.
+pub(crate) fn decode_block_compressed(texture: &TextureData) -> Result<TextureData> {
+    let (format, decode): (_, BlockDecoder) = match texture.format {
+        vk::Format::BC1_RGBA_UNORM_BLOCK => (vk::Format::R8G8B8A8_UNORM, decode_bc1),
+        vk::Format::BC1_RGBA_SRGB_BLOCK => (vk::Format::R8G8B8A8_SRGB, decode_bc1),
+        vk::Format::BC2_UNORM_BLOCK => (vk::Format::R8G8B8A8_UNORM, decode_bc2),
+        vk::Format::BC2_SRGB_BLOCK => (vk::Format::R8G8B8A8_SRGB, decode_bc2),
+        vk::Format::BC3_UNORM_BLOCK => (vk::Format::R8G8B8A8_UNORM, decode_bc3),
+        vk::Format::BC3_SRGB_BLOCK => (vk::Format::R8G8B8A8_SRGB, decode_bc3),
+        vk::Format::BC4_UNORM_BLOCK => (vk::Format::R8G8B8A8_UNORM, decode_bc4),
+        vk::Format::BC5_UNORM_BLOCK => (vk::Format::R8G8B8A8_UNORM, decode_bc5),
+        vk::Format::BC7_UNORM_BLOCK => (vk::Format::R8G8B8A8_UNORM, decode_bc7),
+        vk::Format::BC7_SRGB_BLOCK => (vk::Format::R8G8B8A8_SRGB, decode_bc7),
+        format => return Err(anyhow!("No CPU decoder for {:?}.", format)),
+    };
+    let block = block_size(texture.format).unwrap();
+
+    let levels = texture
+        .levels
+        .iter()
+        .enumerate()
+        .map(|(level, compressed)| {
+            let width = (texture.width >> level).max(1) as usize;
+            let height = (texture.height >> level).max(1) as usize;
+            let blocks_x = width.div_ceil(4);
+
+            let mut pixels = vec![0; width * height * 4];
+            let mut texels = [[0u8; 4]; 16];
+            for (i, bytes) in compressed.chunks_exact(block).enumerate() {
+                decode(bytes, &mut texels);
+                let (bx, by) = ((i % blocks_x) * 4, (i / blocks_x) * 4);
+                for (t, texel) in texels.iter().enumerate() {
+                    let (x, y) = (bx + t % 4, by + t / 4);
+                    if x < width && y < height {
+                        let offset = (y * width + x) * 4;
+                        pixels[offset..offset + 4].copy_from_slice(texel);
+                    }
+                }
+            }
+            pixels
+        })
+        .collect();
+
+    Ok(TextureData {
+        width: texture.width,
+        height: texture.height,
+        format,
+        levels,
+    })
+}
+
+fn decode_bc1(bytes: &[u8], texels: &mut [[u8; 4]; 16]) {
+    decode_color(bytes, texels, true);
+}
+
+fn decode_bc2(bytes: &[u8], texels: &mut [[u8; 4]; 16]) {
+    decode_color(&bytes[8..], texels, false);
+    let alpha = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = ((alpha >> (i * 4)) & 0xF) as u8 * 17;
+    }
+}
+
+fn decode_bc3(bytes: &[u8], texels: &mut [[u8; 4]; 16]) {
+    decode_color(&bytes[8..], texels, false);
+    let alpha = decode_channel(&bytes[..8]);
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+}
+
+fn decode_bc4(bytes: &[u8], texels: &mut [[u8; 4]; 16]) {
+    let red = decode_channel(bytes);
+    for (texel, red) in texels.iter_mut().zip(red) {
+        *texel = [red, 0, 0, 255];
+    }
+}
+
+fn decode_bc5(bytes: &[u8], texels: &mut [[u8; 4]; 16]) {
+    let red = decode_channel(&bytes[..8]);
+    let green = decode_channel(&bytes[8..]);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = [red[i], green[i], 0, 255];
+    }
+}
+
+/// The 8-byte RGB565 color block shared by BC1-BC3. Only BC1 uses the
+/// three-color mode with transparent black.
+fn decode_color(bytes: &[u8], texels: &mut [[u8; 4]; 16], allow_transparent: bool) {
+    let c0 = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let c1 = u16::from_le_bytes([bytes[2], bytes[3]]);
+    let indices = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+
+    let expand = |c: u16| {
+        let r = ((c >> 11) & 0x1F) as u32;
+        let g = ((c >> 5) & 0x3F) as u32;
+        let b = (c & 0x1F) as u32;
+        [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+    };
+    let (a, b) = (expand(c0), expand(c1));
+    let mix = |wa: u32, wb: u32| {
+        let total = wa + wb;
+        [0, 1, 2].map(|i| ((a[i] * wa + b[i] * wb) / total) as u8)
+    };
+
+    let palette = if c0 > c1 || !allow_transparent {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)].map(|[r, g, b]| [r, g, b, 255])
+    } else {
+        let [r2, g2, b2] = mix(1, 1);
+        [
+            [a[0] as u8, a[1] as u8, a[2] as u8, 255],
+            [b[0] as u8, b[1] as u8, b[2] as u8, 255],
+            [r2, g2, b2, 255],
+            [0, 0, 0, 0],
+        ]
+    };
+
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (i * 2)) & 0x3) as usize];
+    }
+}
+
+/// The 8-byte single channel block used for BC3 alpha and BC4/BC5.
+fn decode_channel(bytes: &[u8]) -> [u8; 16] {
+    let (e0, e1) = (bytes[0] as u32, bytes[1] as u32);
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&bytes[2..8]);
+    let indices = u64::from_le_bytes(bits);
+
+    let mut palette = [0u8; 8];
+    palette[0] = e0 as u8;
+    palette[1] = e1 as u8;
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = ((e0 * (7 - i as u32) + e1 * i as u32) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((e0 * (5 - i as u32) + e1 * i as u32) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((indices >> (i * 3)) & 0x7) as usize];
+    }
+    values
+}
+
+/// The layout of a BC7 block mode.
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// A P-bit per endpoint, or one shared by both endpoints of a subset.
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    /// Modes 4 and 5 index color and alpha separately.
+    secondary_index_bits: u32,
+}
+
+const fn bc7_mode(
+    subsets: usize,
+    [partition_bits, rotation_bits, selection_bits, color_bits, alpha_bits]: [u32; 5],
+    [endpoint_p_bits, shared_p_bits]: [bool; 2],
+    [index_bits, secondary_index_bits]: [u32; 2],
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        selection_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_p_bits,
+        shared_p_bits,
+        index_bits,
+        secondary_index_bits,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, [4, 0, 0, 4, 0], [true, false], [3, 0]),
+    bc7_mode(2, [6, 0, 0, 6, 0], [false, true], [3, 0]),
+    bc7_mode(3, [6, 0, 0, 5, 0], [false, false], [2, 0]),
+    bc7_mode(2, [6, 0, 0, 7, 0], [true, false], [2, 0]),
+    bc7_mode(1, [0, 2, 1, 5, 6], [false, false], [2, 3]),
+    bc7_mode(1, [0, 2, 0, 7, 8], [false, false], [2, 2]),
+    bc7_mode(1, [0, 0, 0, 7, 7], [true, false], [4, 0]),
+    bc7_mode(2, [6, 0, 0, 5, 5], [true, false], [2, 0]),
+];
+
+/// The subset of each texel in the 2-subset partitions, a bit per texel.
+const BC7_PARTITIONS2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80,
+    0xC800, 0xFFEC, 0xFE80, 0xE800, 0xFFE8, 0xFF00, 0xFFF0, 0xF000,
+    0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C,
+    0xAAAA, 0xF0F0, 0x5A5A, 0x33CC, 0x3C3C, 0x55AA, 0x9696, 0xA55A,
+    0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C,
+    0x9336, 0x9CC6, 0x817E, 0xE718, 0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+const BC7_PARTITIONS3: [[u8; 16]; 64] = [
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 2, 0, 0, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 1, 0, 1, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1],
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2],
+    [0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2, 0, 1, 1, 2],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0, 2, 2, 2, 0],
+    [0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 2, 1, 1, 2, 2],
+    [0, 1, 1, 1, 0, 0, 1, 1, 2, 0, 0, 1, 2, 2, 0, 0],
+    [0, 0, 0, 0, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 2, 2, 0, 0, 2, 2, 1, 1, 1, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2, 0, 2, 2, 2],
+    [0, 0, 0, 1, 0, 0, 0, 1, 2, 2, 2, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2],
+    [0, 0, 0, 0, 1, 1, 0, 0, 2, 2, 1, 0, 2, 2, 1, 0],
+    [0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1, 0, 0, 0, 0],
+    [0, 0, 1, 2, 0, 0, 1, 2, 1, 1, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1, 0, 1, 1, 0],
+    [0, 0, 0, 0, 0, 1, 1, 0, 1, 2, 2, 1, 1, 2, 2, 1],
+    [0, 0, 2, 2, 1, 1, 0, 2, 1, 1, 0, 2, 0, 0, 2, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 0, 0, 2, 2, 2, 2, 2],
+    [0, 0, 1, 1, 0, 1, 2, 2, 0, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 0, 0, 2, 0, 0, 0, 2, 2, 1, 1, 2, 2, 2, 1],
+    [0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 2, 2, 2],
+    [0, 2, 2, 2, 0, 0, 2, 2, 0, 0, 1, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 0, 0, 1, 2, 0, 0, 2, 2, 0, 2, 2, 2],
+    [0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0, 0, 1, 2, 0],
+    [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0],
+    [0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0],
+    [0, 1, 2, 0, 2, 0, 1, 2, 1, 2, 0, 1, 0, 1, 2, 0],
+    [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 0, 0, 1, 1],
+    [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 0, 0, 1, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 0, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2, 1, 1, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 2, 2, 0, 0, 1, 1],
+    [0, 2, 2, 0, 1, 2, 2, 1, 0, 2, 2, 0, 1, 2, 2, 1],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 0, 1, 0, 1],
+    [0, 0, 0, 0, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1],
+    [0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 2, 2, 2, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 2, 2, 2, 0, 1, 1, 1],
+    [0, 0, 0, 2, 1, 1, 1, 2, 0, 0, 0, 2, 1, 1, 1, 2],
+    [0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 2, 2, 2, 0, 1, 1, 1, 0, 1, 1, 1, 0, 2, 2, 2],
+    [0, 0, 0, 2, 1, 1, 1, 2, 1, 1, 1, 2, 0, 0, 0, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2, 2, 1, 1, 2],
+    [0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 0, 2, 2, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 2, 2],
+    [0, 0, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 0, 0, 2, 2],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 2],
+    [0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1],
+    [0, 2, 2, 2, 1, 2, 2, 2, 0, 2, 2, 2, 1, 2, 2, 2],
+    [0, 1, 0, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2],
+    [0, 1, 1, 1, 2, 0, 1, 1, 2, 2, 0, 1, 2, 2, 2, 0],
+];
+
+/// The texel of each partition's second subset, and of the 3-subset
+/// partitions' second and third, whose index has an implicit top bit of 0.
+const BC7_ANCHORS2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15, 2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+const BC7_ANCHORS3: [[usize; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3,
+        3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15,
+        3, 15, 5, 5, 5, 8, 5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8,
+        15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8,
+        15, 3, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+fn bc7_weight(index: u32, bits: u32) -> u32 {
+    const WEIGHTS2: [u32; 4] = [0, 21, 43, 64];
+    const WEIGHTS3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+    const WEIGHTS4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+    match bits {
+        2 => WEIGHTS2[index as usize],
+        3 => WEIGHTS3[index as usize],
+        _ => WEIGHTS4[index as usize],
+    }
+}
+
+/// Reserved modes decode to transparent black.
+fn decode_bc7(bytes: &[u8], texels: &mut [[u8; 4]; 16]) {
+    let mut bits = u128::from_le_bytes(bytes.try_into().unwrap());
+    let mut take = |count: u32| {
+        let value = (bits & ((1 << count) - 1)) as u32;
+        bits >>= count;
+        value
+    };
+
+    let index = bytes[0].trailing_zeros();
+    let Some(mode) = BC7_MODES.get(index as usize) else {
+        *texels = [[0; 4]; 16];
+        return;
+    };
+    take(index + 1);
+    let partition = take(mode.partition_bits) as usize;
+    let rotation = take(mode.rotation_bits) as usize;
+    let selection = take(mode.selection_bits);
+
+    let mut endpoints = [[255u32; 4]; 6];
+    let endpoints = &mut endpoints[..mode.subsets * 2];
+    for c in 0..3 {
+        for endpoint in endpoints.iter_mut() {
+            endpoint[c] = take(mode.color_bits);
+        }
+    }
+    if mode.alpha_bits > 0 {
+        for endpoint in endpoints.iter_mut() {
+            endpoint[3] = take(mode.alpha_bits);
+        }
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let count = if mode.endpoint_p_bits { endpoints.len() } else { mode.subsets };
+        let p_bits: [u32; 6] = std::array::from_fn(|i| if i < count { take(1) } else { 0 });
+        for (i, endpoint) in endpoints.iter_mut().enumerate() {
+            let p = if mode.endpoint_p_bits { p_bits[i] } else { p_bits[i / 2] };
+            let channels = if mode.alpha_bits > 0 { 4 } else { 3 };
+            for value in &mut endpoint[..channels] {
+                *value = (*value << 1) | p;
+            }
+        }
+        color_bits += 1;
+        alpha_bits += (mode.alpha_bits > 0) as u32;
+    }
+    let expand = |value: u32, bits: u32| (value << (8 - bits)) | (value >> (2 * bits - 8));
+    for endpoint in endpoints.iter_mut() {
+        for value in &mut endpoint[..3] {
+            *value = expand(*value, color_bits);
+        }
+        if mode.alpha_bits > 0 {
+            endpoint[3] = expand(endpoint[3], alpha_bits);
+        }
+    }
+
+    let subset = |t: usize| match mode.subsets {
+        1 => 0,
+        2 => ((BC7_PARTITIONS2[partition] >> t) & 1) as usize,
+        _ => BC7_PARTITIONS3[partition][t] as usize,
+    };
+    let anchor = |t: usize| match mode.subsets {
+        1 => t == 0,
+        2 => t == 0 || t == BC7_ANCHORS2[partition],
+        _ => t == 0 || t == BC7_ANCHORS3[0][partition] || t == BC7_ANCHORS3[1][partition],
+    };
+    let indices: [u32; 16] = std::array::from_fn(|t| take(mode.index_bits - anchor(t) as u32));
+    let secondary: [u32; 16] = match mode.secondary_index_bits {
+        0 => indices,
+        bits => std::array::from_fn(|t| take(bits - (t == 0) as u32)),
+    };
+
+    // Color takes the primary indices and alpha the secondary, unless the
+    // index selection bit swaps them.
+    let (mut color_index, mut alpha_index) = ((indices, mode.index_bits), (secondary, mode.secondary_index_bits));
+    if mode.secondary_index_bits == 0 {
+        alpha_index = color_index;
+    } else if selection == 1 {
+        std::mem::swap(&mut color_index, &mut alpha_index);
+    }
+
+    for (t, texel) in texels.iter_mut().enumerate() {
+        let s = subset(t);
+        let (e0, e1) = (endpoints[s * 2], endpoints[s * 2 + 1]);
+        let color_weight = bc7_weight(color_index.0[t], color_index.1);
+        let alpha_weight = bc7_weight(alpha_index.0[t], alpha_index.1);
+        for c in 0..4 {
+            let weight = if c == 3 { alpha_weight } else { color_weight };
+            texel[c] = ((e0[c] * (64 - weight) + e1[c] * weight + 32) >> 6) as u8;
+        }
+        if rotation > 0 {
+            texel.swap(3, rotation - 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A block of `(value, bits)` fields, written from the lowest bit up.
+    fn block(fields: &[(u32, u32)]) -> [u8; 16] {
+        let (bits, count) = fields.iter().fold((0u128, 0), |(bits, count), &(value, width)| {
+            (bits | ((value as u128) << count), count + width)
+        });
+        assert_eq!(count, 128);
+        bits.to_le_bytes()
+    }
+
+    fn decode(bytes: [u8; 16]) -> [[u8; 4]; 16] {
+        let mut texels = [[0; 4]; 16];
+        decode_bc7(&bytes, &mut texels);
+        texels
+    }
+
+    #[test]
+    fn bc7_indices_interpolate_the_endpoints() {
+        // Mode 6 with the P-bits of 0 and 1 as the bottom bit of every
+        // channel: from (0, 128, 0, 254) to (255, 129, 1, 255).
+        let mut fields = vec![(1 << 6, 7)];
+        fields.extend([0, 127, 64, 64, 0, 0, 127, 127].map(|value| (value, 7)));
+        fields.extend([(0, 1), (1, 1), (0, 3)]);
+        fields.extend((1..16).map(|t| (t, 4)));
+        let texels = decode(block(&fields));
+
+        let weights = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+        for (texel, w) in texels.iter().zip(weights) {
+            let expected =
+                [(0, 255), (128, 129), (0, 1), (254, 255)].map(|(e0, e1)| ((e0 * (64 - w) + e1 * w + 32) >> 6) as u8);
+            assert_eq!(*texel, expected);
+        }
+    }
+
+    #[test]
+    fn bc7_partitions_split_the_block() {
+        // Mode 1, partition 13: the top two rows black, the bottom two white.
+        let mut fields = vec![(0b10, 2), (13, 6)];
+        for _ in 0..3 {
+            fields.extend([(0, 6), (0, 6), (63, 6), (63, 6)]);
+        }
+        fields.extend([(0, 1), (1, 1), (0, 46)]);
+        let texels = decode(block(&fields));
+
+        for (t, texel) in texels.iter().enumerate() {
+            let value = if t < 8 { 0 } else { 255 };
+            assert_eq!(*texel, [value, value, value, 255], "texel {}", t);
+        }
+    }
+
+    #[test]
+    fn bc7_rotation_swaps_alpha_with_a_channel() {
+        // Mode 5 with rotation 1: black and opaque, with red and alpha swapped.
+        let mut fields = vec![(1 << 5, 6), (1, 2)];
+        fields.extend([(0, 7); 6]);
+        fields.extend([(255, 8), (255, 8), (0, 31), (0, 31)]);
+        assert_eq!(decode(block(&fields)), [[255, 0, 0, 0]; 16]);
+    }
+
+    #[test]
+    fn bc7_anchors_are_in_their_subsets() {
+        for p in 0..64 {
+            assert_eq!((BC7_PARTITIONS2[p] >> BC7_ANCHORS2[p]) & 1, 1, "partition {}", p);
+            assert_eq!(BC7_PARTITIONS3[p][BC7_ANCHORS3[0][p]], 1, "partition {}", p);
+            assert_eq!(BC7_PARTITIONS3[p][BC7_ANCHORS3[1][p]], 2, "partition {}", p);
+        }
+    }
+
+    #[test]
+    fn reserved_bc7_mode_is_transparent_black() {
+        assert_eq!(decode([0; 16]), [[0; 4]; 16]);
+    }
+}
@@ -0,0 +1,84 @@
+use cgmath::{vec3, Deg, EuclideanSpace, InnerSpace, Point3, Transform};
+
+use crate::{
+    app::{FAR_PLANE, NEAR_PLANE},
+    types::{Mat4, Vec3},
+    world::WorldConfig,
+};
+
+/// A point of view the scene can be drawn from, registered with
+/// `App::add_camera`. The active camera draws the window, and a `Viewport`
+/// can show any other.
+///
+/// Like entity transforms, positions and directions are in world space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    /// The point looked at.
+    pub target: Vec3,
+    pub up: Vec3,
+    /// Vertical field of view.
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+    /// Projects without a far plane, so nothing is clipped however distant.
+    /// `far` then only bounds the depth debug view and the grid. Costs some
+    /// depth precision, which `App::reverse_z` wins back.
+    pub infinite_far: bool,
+}
+
+impl Camera {
+    /// Looks from `position` at `target` with the default lens.
+    pub fn look_at(position: Vec3, target: Vec3, up: Vec3) -> Self {
+        Self {
+            position,
+            target,
+            up,
+            fov: Deg(45.0),
+            near: NEAR_PLANE,
+            far: FAR_PLANE,
+            infinite_far: false,
+        }
+    }
+
+    /// The camera the app starts with: 6 m out and 2 m up from the origin,
+    /// looking at it, in `world`'s axes.
+    pub fn initial(world: &WorldConfig) -> Self {
+        let inverse = world.inverse_render_transform();
+        Self::look_at(
+            inverse.transform_point(Point3::new(6.0, 0.0, 2.0)).to_vec(),
+            Vec3::new(0.0, 0.0, 0.0),
+            inverse.transform_vector(vec3(0.0, 0.0, 1.0)).normalize(),
+        )
+    }
+
+    /// The far plane projections are built with, infinite with
+    /// `infinite_far`.
+    pub(crate) fn projected_far(&self) -> f32 {
+        if self.infinite_far { f32::INFINITY } else { self.far }
+    }
+
+    /// Maps points in `world` space to view space. The look-at is built in
+    /// render space, which is always right-handed.
+    pub(crate) fn view(&self, world: &WorldConfig) -> Mat4 {
+        let render = world.render_transform();
+        Mat4::look_at_rh(
+            render.transform_point(Point3::from_vec(self.position)),
+            render.transform_point(Point3::from_vec(self.target)),
+            render.transform_vector(self.up).normalize(),
+        ) * render
+    }
+}
+
+/// How a [0, 1] depth perspective projection maps view space z to clip space
+/// z, as `(scale, offset)`, with `near` at depth 0 and `far` at 1. Either may
+/// be infinite, which is `near` when they are swapped for reversed depth.
+pub(crate) fn depth_terms(near: f32, far: f32) -> (f32, f32) {
+    if far.is_infinite() {
+        return (-1.0, -near);
+    }
+    if near.is_infinite() {
+        return (0.0, far);
+    }
+    (-far / (far - near), -(far * near) / (far - near))
+}
@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Result};
+use cgmath::{vec3, vec4};
+use exr::prelude::f16;
+use std::{fs, path::Path};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::{allocate_memory, free_memory, AllocationKind},
+    app::AppData,
+    hdr::HDR_TEXTURE_FORMAT,
+    image::transition_image_layout,
+    sampler::SamplerDesc,
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    texture::stage_layers,
+    types::{Vec3, Vec4},
+    vertex_buffer::get_memory_type_index,
+};
+
+/// The largest LUT accepted, well past the usual 33 and 65.
+const MAX_LUT_SIZE: u32 = 256;
+
+/// A 3D color lookup table as read from a `.cube` file: `size` cubed RGB
+/// entries with red changing fastest, then green, then blue.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CubeLut {
+    pub(crate) size: u32,
+    pub(crate) domain_min: Vec3,
+    pub(crate) domain_max: Vec3,
+    pub(crate) table: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+    /// Maps every color to itself.
+    pub(crate) fn identity() -> Self {
+        let table = (0..8).map(|i| [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32]).collect();
+        Self {
+            size: 2,
+            domain_min: vec3(0.0, 0.0, 0.0),
+            domain_max: vec3(1.0, 1.0, 1.0),
+            table,
+        }
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Parses the Resolve `.cube` format: keyword lines, then one line of
+    /// three numbers per entry. Comments start with `#`.
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        let mut size = None;
+        let mut domain_min = vec3(0.0, 0.0, 0.0);
+        let mut domain_max = vec3(1.0, 1.0, 1.0);
+        let mut table = vec![];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap();
+            let expected = || anyhow!("Line {}: Expected three numbers.", number + 1);
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => return Err(anyhow!("Only 3D LUTs are supported.")),
+                "LUT_3D_SIZE" => {
+                    let value = words
+                        .next()
+                        .and_then(|w| w.parse::<u32>().ok())
+                        .filter(|s| (2..=MAX_LUT_SIZE).contains(s))
+                        .ok_or_else(|| anyhow!("Line {}: Invalid LUT size.", number + 1))?;
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => domain_min = triple(words).map(Vec3::from).ok_or_else(expected)?,
+                "DOMAIN_MAX" => domain_max = triple(words).map(Vec3::from).ok_or_else(expected)?,
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(anyhow!("Line {}: Unknown keyword `{}`.", number + 1, keyword));
+                }
+                _ => table.push(triple(line.split_whitespace()).ok_or_else(expected)?),
+            }
+        }
+
+        let size = size.ok_or_else(|| anyhow!("Missing LUT_3D_SIZE."))?;
+        if table.len() != size.pow(3) as usize {
+            return Err(anyhow!("Expected {} entries, found {}.", size.pow(3), table.len()));
+        }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            return Err(anyhow!("DOMAIN_MAX must be above DOMAIN_MIN."));
+        }
+
+        Ok(Self { size, domain_min, domain_max, table })
+    }
+}
+
+/// Exactly three numbers.
+fn triple<'a>(words: impl Iterator<Item = &'a str>) -> Option<[f32; 3]> {
+    let numbers = words.map(|w| w.parse::<f32>().ok()).collect::<Option<Vec<_>>>()?;
+    numbers.try_into().ok()
+}
+
+/// A `CubeLut` uploaded as a 3D texture for the tonemap pass, which grades
+/// the tonemapped color through it.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ColorLut {
+    pub(crate) image: vk::Image,
+    pub(crate) memory: vk::DeviceMemory,
+    pub(crate) view: vk::ImageView,
+    pub(crate) sampler: vk::Sampler,
+    /// Maps sRGB encoded color to texture coordinates, which land on texel
+    /// centers at the ends of the domain: `color * scale + offset`.
+    pub(crate) scale: Vec4,
+    pub(crate) offset: Vec4,
+}
+
+impl Default for ColorLut {
+    fn default() -> Self {
+        Self {
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            sampler: vk::Sampler::null(),
+            scale: vec4(1.0, 1.0, 1.0, 0.0),
+            offset: vec4(0.0, 0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl ColorLut {
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        free_memory(device, self.memory);
+    }
+}
+
+pub(crate) unsafe fn create_color_lut(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    lut: &CubeLut,
+) -> Result<ColorLut> {
+    let sampler = data.samplers.get(device, SamplerDesc::clamped());
+    let data = &*data;
+
+    let format = HDR_TEXTURE_FORMAT;
+    let pixels = lut
+        .table
+        .iter()
+        .flat_map(|[r, g, b]| [*r, *g, *b, 1.0])
+        .flat_map(|c| f16::from_f32(c).to_le_bytes())
+        .collect::<Vec<_>>();
+    let (staging_buffer, staging_buffer_memory, _) =
+        stage_layers(instance, device, data, &[&[pixels]]).unwrap();
+
+    // `create_image` only makes 2D images.
+    let extent = vk::Extent3D { width: lut.size, height: lut.size, depth: lut.size };
+    let info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::_3D)
+        .extent(extent)
+        .mip_levels(1)
+        .array_layers(1)
+        .format(format)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .samples(vk::SampleCountFlags::_1)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let image = device.create_image(&info, None).unwrap();
+
+    let requirements = device.get_image_memory_requirements(image);
+    let info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(requirements.size)
+        .memory_type_index(
+            get_memory_type_index(instance, data, vk::MemoryPropertyFlags::DEVICE_LOCAL, requirements).unwrap(),
+        );
+    let memory = allocate_memory(device, &info, AllocationKind::Image).unwrap();
+    device.bind_image_memory(image, memory, 0).unwrap();
+
+    transition_image_layout(
+        device,
+        data,
+        image,
+        format,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        1,
+        0..1,
+    )
+    .unwrap();
+
+    let command_buffer = begin_single_time_commands(device, data).unwrap();
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(subresource)
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(extent);
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        staging_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+    );
+    end_single_time_commands(device, data, command_buffer).unwrap();
+
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+
+    transition_image_layout(
+        device,
+        data,
+        image,
+        format,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        1,
+        0..1,
+    )
+    .unwrap();
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+    let info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::_3D)
+        .format(format)
+        .subresource_range(subresource_range);
+    let view = device.create_image_view(&info, None).unwrap();
+
+    // Entries sit at texel centers, half a texel in from each face.
+    let size = lut.size as f32;
+    let range = lut.domain_max - lut.domain_min;
+    let scale = vec3((size - 1.0) / size / range.x, (size - 1.0) / size / range.y, (size - 1.0) / size / range.z);
+    let offset = vec3(
+        0.5 / size - lut.domain_min.x * scale.x,
+        0.5 / size - lut.domain_min.y * scale.y,
+        0.5 / size - lut.domain_min.z * scale.z,
+    );
+
+    Ok(ColorLut {
+        image,
+        memory,
+        view,
+        sampler,
+        scale: vec4(scale.x, scale.y, scale.z, 0.0),
+        offset: vec4(offset.x, offset.y, offset.z, 0.0),
+    })
+}
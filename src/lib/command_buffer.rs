@@ -1,10 +1,12 @@
 use anyhow::Result;
+use std::thread;
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
   app::AppData,
-  physical_device::QueueFamilyIndices
+  physical_device::QueueFamilyIndices,
+  scene_recorder::MAX_RECORDING_THREADS,
 };
 
 pub(crate) unsafe fn create_command_pools(
@@ -13,13 +15,50 @@ pub(crate) unsafe fn create_command_pools(
   data: &mut AppData,
 ) -> Result<()> {
   data.command_pool = create_command_pool(instance, device, data).unwrap();
+
+  let indices = QueueFamilyIndices::get(instance, data, data.physical_device).unwrap();
+  let info = vk::CommandPoolCreateInfo::builder()
+      .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+      .queue_family_index(indices.transfer);
+  data.transfer_command_pool = device.create_command_pool(&info, None).unwrap();
   
+  resize_command_pools(instance, device, data).unwrap();
+
+  Ok(())
+}
+
+/// Gives every swapchain image its command pools, creating or destroying
+/// them when the swapchain was recreated with a different image count.
+pub(crate) unsafe fn resize_command_pools(
+  instance: &Instance,
+  device: &Device,
+  data: &mut AppData,
+) -> Result<()> {
   let num_images = data.swapchain_images.len();
-  for _ in 0..num_images {
+  for command_pool in data.command_pools.drain(num_images.min(data.command_pools.len())..) {
+      device.destroy_command_pool(command_pool, None);
+  }
+  for command_pool in data.recording_pools.drain(num_images.min(data.recording_pools.len())..).flatten() {
+      device.destroy_command_pool(command_pool, None);
+  }
+
+  while data.command_pools.len() < num_images {
       let command_pool = create_command_pool(instance, device, data).unwrap();
       data.command_pools.push(command_pool);
   }
 
+  let threads = thread::available_parallelism()
+      .map(|n| n.get())
+      .unwrap_or(1)
+      .clamp(1, MAX_RECORDING_THREADS);
+  while data.recording_pools.len() < num_images {
+      let command_pools = (0..threads)
+          .map(|_| create_command_pool(instance, device, data))
+          .collect::<Result<Vec<_>>>()
+          .unwrap();
+      data.recording_pools.push(command_pools);
+  }
+
   Ok(())
 }
 
@@ -31,7 +70,10 @@ pub(crate) unsafe fn create_command_pool(
   let indices = QueueFamilyIndices::get(instance, data, data.physical_device).unwrap();
 
   let info = vk::CommandPoolCreateInfo::builder()
-      .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+      .flags(
+          vk::CommandPoolCreateFlags::TRANSIENT
+              | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+      )
       .queue_family_index(indices.graphics);
 
   Ok(device.create_command_pool(&info, None).unwrap())
@@ -49,7 +91,49 @@ pub(crate) unsafe fn create_command_buffers(device: &Device, data: &mut AppData)
       data.command_buffers.push(command_buffer);
   }
 
-  data.secondary_command_buffers = vec![vec![]; data.swapchain_images.len()];
+  data.secondary_command_buffers = data
+      .recording_pools
+      .iter()
+      .map(|pools| vec![vec![]; pools.len()])
+      .collect();
+  data.scene_command_buffers = vec![vec![]; data.swapchain_images.len()];
+  data.recorded_scenes = vec![None; data.swapchain_images.len()];
+
+  data.debug_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.particle_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.billboard_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.gpu_particle_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.indirect_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.grid_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.depth_prepass_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.sprite_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+  data.focus_blur_command_buffers = allocate_secondary_command_buffers(device, data).unwrap();
+
+  // Allocated from the shared pool, which is never reset, so static batches
+  // survive the per-image pool resets.
+  let allocate_info = vk::CommandBufferAllocateInfo::builder()
+      .command_pool(data.command_pool)
+      .level(vk::CommandBufferLevel::SECONDARY)
+      .command_buffer_count(num_images as u32);
+
+  data.static_command_buffers = device.allocate_command_buffers(&allocate_info).unwrap();
+  data.recorded_static_batches = vec![None; num_images];
 
   Ok(())
 }
+
+pub(crate) unsafe fn allocate_secondary_command_buffers(
+  device: &Device,
+  data: &AppData,
+) -> Result<Vec<vk::CommandBuffer>> {
+  (0..data.swapchain_images.len())
+      .map(|image_index| {
+          let allocate_info = vk::CommandBufferAllocateInfo::builder()
+              .command_pool(data.command_pools[image_index])
+              .level(vk::CommandBufferLevel::SECONDARY)
+              .command_buffer_count(1);
+
+          Ok(device.allocate_command_buffers(&allocate_info)?[0])
+      })
+      .collect()
+}
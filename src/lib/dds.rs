@@ -0,0 +1,182 @@
+use anyhow::{anyhow, Result};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::texture::{check_texture_size, level_size, TextureData};
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 128;
+const DX10_HEADER_SIZE: usize = 20;
+
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// Parses a DDS file holding a single 2D texture, either with a legacy
+/// FourCC/bitmask pixel format or a DX10 header with a DXGI format.
+pub(crate) fn load_dds(bytes: &[u8]) -> Result<TextureData> {
+    if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC || read_u32(bytes, 4) != 124 {
+        return Err(anyhow!("Not a DDS file."));
+    }
+
+    let height = read_u32(bytes, 12);
+    let width = read_u32(bytes, 16);
+    let mip_levels = read_u32(bytes, 28).max(1);
+    let pixel_flags = read_u32(bytes, 80);
+    let four_cc = &bytes[84..88];
+    let caps2 = read_u32(bytes, 112);
+
+    if caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+        return Err(anyhow!("Only 2D DDS textures are supported."));
+    }
+    check_texture_size(width, height, mip_levels)?;
+
+    let (format, mut offset) = if pixel_flags & DDPF_FOURCC != 0 && four_cc == b"DX10" {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(anyhow!("Truncated DDS DX10 header."));
+        }
+        if read_u32(bytes, HEADER_SIZE + 8) & DDS_RESOURCE_MISC_TEXTURECUBE != 0 {
+            return Err(anyhow!("Only 2D DDS textures are supported."));
+        }
+        if read_u32(bytes, HEADER_SIZE + 12) > 1 {
+            return Err(anyhow!("DDS texture arrays are not supported."));
+        }
+        let dxgi = read_u32(bytes, HEADER_SIZE);
+        let format = dxgi_to_vk_format(dxgi)
+            .ok_or_else(|| anyhow!("Unsupported DXGI format {}.", dxgi))?;
+        (format, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else if pixel_flags & DDPF_FOURCC != 0 {
+        let format = four_cc_to_vk_format(four_cc).ok_or_else(|| {
+            anyhow!("Unsupported DDS FourCC `{}`.", String::from_utf8_lossy(four_cc))
+        })?;
+        (format, HEADER_SIZE)
+    } else if pixel_flags & DDPF_RGB != 0 {
+        let format = masks_to_vk_format(
+            read_u32(bytes, 88),
+            [read_u32(bytes, 92), read_u32(bytes, 96), read_u32(bytes, 100), read_u32(bytes, 104)],
+        )
+        .ok_or_else(|| anyhow!("Unsupported DDS pixel masks."))?;
+        (format, HEADER_SIZE)
+    } else {
+        return Err(anyhow!("Unsupported DDS pixel format."));
+    };
+
+    let mut levels = Vec::with_capacity(mip_levels as usize);
+    for level in 0..mip_levels {
+        let size = level_size(format, (width >> level).max(1), (height >> level).max(1))
+            .ok_or_else(|| anyhow!("Unsupported DDS format {:?}.", format))?;
+        let pixels = offset
+            .checked_add(size)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| anyhow!("DDS level {} is out of bounds.", level))?;
+        levels.push(pixels.to_vec());
+        offset += size;
+    }
+
+    Ok(TextureData {
+        width,
+        height,
+        format,
+        levels,
+    })
+}
+
+pub(crate) fn dxgi_to_vk_format(dxgi: u32) -> Option<vk::Format> {
+    Some(match dxgi {
+        2 => vk::Format::R32G32B32A32_SFLOAT,
+        10 => vk::Format::R16G16B16A16_SFLOAT,
+        28 => vk::Format::R8G8B8A8_UNORM,
+        29 => vk::Format::R8G8B8A8_SRGB,
+        49 => vk::Format::R8G8_UNORM,
+        61 => vk::Format::R8_UNORM,
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        87 => vk::Format::B8G8R8A8_UNORM,
+        91 => vk::Format::B8G8R8A8_SRGB,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    })
+}
+
+fn four_cc_to_vk_format(four_cc: &[u8]) -> Option<vk::Format> {
+    Some(match four_cc {
+        b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+        b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+        b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+        b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+        b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+        b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+        _ => return None,
+    })
+}
+
+fn masks_to_vk_format(bits: u32, [r, g, b, a]: [u32; 4]) -> Option<vk::Format> {
+    match (bits, r, g, b, a) {
+        (32, 0xFF, 0xFF00, 0xFF0000, _) => Some(vk::Format::R8G8B8A8_UNORM),
+        (32, 0xFF0000, 0xFF00, 0xFF, _) => Some(vk::Format::B8G8R8A8_UNORM),
+        _ => None,
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x8 DX10 DDS file of `R8G8B8A8_UNORM` texels, all zero.
+    fn dds(width: u32, height: u32, mip_levels: u32, misc_flags: u32) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE + DX10_HEADER_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        for (offset, value) in [
+            (4, 124),
+            (12, height),
+            (16, width),
+            (28, mip_levels),
+            (80, DDPF_FOURCC),
+            (HEADER_SIZE, 28),
+            (HEADER_SIZE + 8, misc_flags),
+            (HEADER_SIZE + 12, 1),
+        ] {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        bytes[84..88].copy_from_slice(b"DX10");
+        bytes.resize(bytes.len() + 8 * 8 * 4, 0);
+        bytes
+    }
+
+    #[test]
+    fn dx10_textures_are_loaded() {
+        let texture = load_dds(&dds(8, 8, 1, 0)).unwrap();
+        assert_eq!((texture.width, texture.height, texture.format), (8, 8, vk::Format::R8G8B8A8_UNORM));
+        assert_eq!(texture.levels, vec![vec![0; 8 * 8 * 4]]);
+    }
+
+    #[test]
+    fn impossible_sizes_and_mip_counts_are_rejected() {
+        assert!(load_dds(&dds(0, 8, 1, 0)).is_err());
+        assert!(load_dds(&dds(8, 8, 5, 0)).is_err());
+        assert!(load_dds(&dds(8, 8, u32::MAX, 0)).is_err());
+    }
+
+    #[test]
+    fn dx10_cubemaps_are_rejected() {
+        assert!(load_dds(&dds(8, 8, 1, DDS_RESOURCE_MISC_TEXTURECUBE)).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::Result;
+use cgmath::{vec3, vec4, InnerSpace, SquareMatrix};
+use std::{f32::consts::PI, mem::size_of};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    depth_object::depth_compare_op,
+    dynamic_buffer::DynamicBuffer,
+    dynamic_rendering::Pass,
+    oit::{scene_blend_attachments, OitWrites},
+    shader::create_shader_module,
+    types::{Mat4, Vec3},
+};
+
+const SPHERE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DebugVertex {
+    pub(crate) pos: Vec3,
+    pub(crate) color: Vec3,
+}
+
+impl DebugVertex {
+    pub(crate) fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<DebugVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub(crate) fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        let pos = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(size_of::<Vec3>() as u32)
+            .build();
+
+        [pos, color]
+    }
+}
+
+/// Immediate-mode line drawing. Everything queued here is drawn with the next
+/// frame and then cleared.
+#[derive(Clone, Debug, Default)]
+pub struct DebugDraw {
+    pub(crate) vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec3) {
+        self.vertices.push(DebugVertex { pos: start, color });
+        self.vertices.push(DebugVertex { pos: end, color });
+    }
+
+    pub fn ray(&mut self, origin: Vec3, direction: Vec3, length: f32, color: Vec3) {
+        self.line(origin, origin + direction.normalize() * length, color);
+    }
+
+    pub fn aabb(&mut self, min: Vec3, max: Vec3, color: Vec3) {
+        let corners = [
+            vec3(min.x, min.y, min.z),
+            vec3(max.x, min.y, min.z),
+            vec3(max.x, max.y, min.z),
+            vec3(min.x, max.y, min.z),
+            vec3(min.x, min.y, max.z),
+            vec3(max.x, min.y, max.z),
+            vec3(max.x, max.y, max.z),
+            vec3(min.x, max.y, max.z),
+        ];
+        self.box_edges(&corners, color);
+    }
+
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec3) {
+        let point = |axis: usize, angle: f32| {
+            let (s, c) = angle.sin_cos();
+            let offset = match axis {
+                0 => vec3(0.0, c, s),
+                1 => vec3(c, 0.0, s),
+                _ => vec3(c, s, 0.0),
+            };
+            center + offset * radius
+        };
+
+        for axis in 0..3 {
+            for i in 0..SPHERE_SEGMENTS {
+                let a = (i as f32 / SPHERE_SEGMENTS as f32) * 2.0 * PI;
+                let b = ((i + 1) as f32 / SPHERE_SEGMENTS as f32) * 2.0 * PI;
+                self.line(point(axis, a), point(axis, b), color);
+            }
+        }
+    }
+
+    /// Draws the X, Y and Z axes of `transform` in red, green and blue.
+    pub fn axis_gizmo(&mut self, transform: Mat4, size: f32) {
+        let origin = transform.w.truncate();
+        let axes = [
+            (transform.x.truncate(), vec3(1.0, 0.0, 0.0)),
+            (transform.y.truncate(), vec3(0.0, 1.0, 0.0)),
+            (transform.z.truncate(), vec3(0.0, 0.0, 1.0)),
+        ];
+        for (axis, color) in axes {
+            self.ray(origin, axis, size, color);
+        }
+    }
+
+    /// Draws the frustum of a camera given its combined projection and view
+    /// matrix (in the Vulkan clip space the renderer uses).
+    pub fn frustum(&mut self, view_proj: Mat4, color: Vec3) {
+        let inverse = match view_proj.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        let mut corners = [vec3(0.0, 0.0, 0.0); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let z = if i & 4 == 0 { 0.0 } else { 1.0 };
+            let point = inverse * vec4(x, y, z, 1.0);
+            *corner = point.truncate() / point.w;
+        }
+
+        corners.swap(2, 3);
+        corners.swap(6, 7);
+        self.box_edges(&corners, color);
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    fn box_edges(&mut self, corners: &[Vec3; 8], color: Vec3) {
+        for i in 0..4 {
+            self.line(corners[i], corners[(i + 1) % 4], color);
+            self.line(corners[i + 4], corners[(i + 1) % 4 + 4], color);
+            self.line(corners[i], corners[i + 4], color);
+        }
+    }
+}
+
+pub(crate) unsafe fn create_debug_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let vert = include_bytes!("../../shaders/debug_vert.spv");
+    let frag = include_bytes!("../../shaders/debug_frag.spv");
+
+    let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+    let frag_shader_module = create_shader_module(device, &frag[..]).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    let binding_descriptions = &[DebugVertex::binding_description()];
+    let attribute_descriptions = DebugVertex::attribute_descriptions();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::LINE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(depth_compare_op(data.reverse_z, vk::CompareOp::LESS_OR_EQUAL))
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = scene_blend_attachments(data.order_independent_transparency, attachment.build(), OitWrites::Masked);
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(&attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    // Set per viewport, see `record_viewports`.
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    let formats = Pass::Scene.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(0);
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.debug_pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(())
+}
+
+pub(crate) unsafe fn create_debug_vertex_buffers(data: &mut AppData) -> Result<()> {
+    data.debug_vertex_buffers = vec![DynamicBuffer::default(); data.swapchain_images.len()];
+    Ok(())
+}
@@ -0,0 +1,32 @@
+/// Replaces the scene's shading with a visualization of one of its inputs.
+/// The value is passed to the fragment shader as a push constant.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    #[default]
+    None = 0,
+    /// Linear view depth between the near and far planes.
+    Depth = 1,
+    /// World-space normals (faceted, reconstructed from screen derivatives).
+    Normals = 2,
+    Uvs = 3,
+    /// The mip level the texture is sampled at, blue (0) through red (5+).
+    MipLevel = 4,
+    /// Additive heatmap of how many fragments land on each pixel.
+    Overdraw = 5,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 6] = [
+        DebugView::None,
+        DebugView::Depth,
+        DebugView::Normals,
+        DebugView::Uvs,
+        DebugView::MipLevel,
+        DebugView::Overdraw,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::Result;
+use cgmath::{vec4, SquareMatrix};
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    dynamic_rendering::Pass,
+    sampler::SamplerDesc,
+    scene_recorder::bind_scene_descriptors_with,
+    shader::{create_shader_module, SpecializationConstants},
+    texture_atlas::resolve_texture,
+    types::{Mat4, Vec4},
+    viewport::record_viewports,
+};
+
+/// A texture projected onto whatever lies inside a box, such as scorch
+/// marks, dirt or signage that follow the shape of the surfaces beneath.
+///
+/// The box is the unit cube centered on the origin, placed by `transform`.
+/// The texture is projected down its Y axis, with U along X and V along Z.
+/// Decals are drawn after the scene from its depth buffer, multiplying the
+/// color of the surfaces they land on, so they tint and darken them under
+/// their own lighting. They land on the nearest surface at each pixel,
+/// including viewmodels, and are drawn over transparent entities unless
+/// `App::order_independent_transparency` is on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Decal {
+    pub transform: Mat4,
+    /// Texture table slot from `App::load_texture`, or
+    /// `AtlasRegion::texture` from `App::load_texture_atlas`. Slot 0, the
+    /// scene texture, is used for slots that were never loaded.
+    pub texture: u32,
+    /// Multiplies the texture. Its alpha fades the decal out.
+    pub color: Vec4,
+}
+
+impl Default for Decal {
+    fn default() -> Self {
+        Self {
+            transform: Mat4::identity(),
+            texture: 0,
+            color: vec4(1.0, 1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Pushed for both stages at offset 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DecalPushConstants {
+    model: Mat4,
+    color: Vec4,
+    texture: u32,
+    _padding: [u32; 3],
+}
+
+/// How the decal pass reads the depth: as an input attachment of the render
+/// pass, or with a nearest sampler under dynamic rendering.
+fn depth_descriptor_type(data: &AppData) -> vk::DescriptorType {
+    if data.dynamic_rendering {
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+    } else {
+        vk::DescriptorType::INPUT_ATTACHMENT
+    }
+}
+
+/// Set 2 of the decal pipeline, the scene depth. Sets 0 and 1 are the
+/// scene's.
+pub(crate) unsafe fn create_decal_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(depth_descriptor_type(data))
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.decal_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// Points the decal pass at the depth, which is shared by every
+/// framebuffer, so one set is enough.
+pub(crate) unsafe fn create_decal_descriptor_set(device: &Device, data: &mut AppData) -> Result<()> {
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(depth_descriptor_type(data))
+        .descriptor_count(1);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    data.decal_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = &[data.decal_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.decal_descriptor_pool)
+        .set_layouts(layouts);
+    data.decal_descriptor_set = device.allocate_descriptor_sets(&info).unwrap()[0];
+
+    let sampler = if data.dynamic_rendering {
+        data.samplers.get(device, SamplerDesc::nearest())
+    } else {
+        vk::Sampler::null()
+    };
+
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+        .image_view(data.depth_image_view)
+        .sampler(sampler);
+
+    let image_info = &[info];
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.decal_descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(depth_descriptor_type(data))
+        .image_info(image_info);
+
+    device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+    Ok(())
+}
+
+/// Draws the inside faces of each decal's box without depth testing, so
+/// that it still covers the screen with the camera inside it, and projects
+/// the texture onto the depth behind each pixel.
+pub(crate) unsafe fn create_decal_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+    let vert = include_bytes!("../../shaders/decal_vert.spv");
+    let frag = match (multisampled, data.dynamic_rendering) {
+        (true, true) => &include_bytes!("../../shaders/decal_ms_sampled_frag.spv")[..],
+        (true, false) => &include_bytes!("../../shaders/decal_ms_frag.spv")[..],
+        (false, true) => &include_bytes!("../../shaders/decal_sampled_frag.spv")[..],
+        (false, false) => &include_bytes!("../../shaders/decal_frag.spv")[..],
+    };
+
+    let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+    let frag_shader_module = create_shader_module(device, frag).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // Sized like the scene's texture table.
+    let constants = SpecializationConstants::new().u32(0, data.texture_capacity);
+    let specialization_info = constants.info();
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    // The box is wound like the scene's meshes, so culling its front faces
+    // draws each pixel it covers once.
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::FRONT)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Multiplies the color and keeps its alpha.
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::DST_COLOR)
+        .dst_color_blend_factor(vk::BlendFactor::ZERO)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    // Set per viewport, see `record_viewports`.
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<DecalPushConstants>() as u32);
+
+    let set_layouts = &[data.descriptor_set_layout, data.texture_set_layout, data.decal_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    data.decal_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let formats = Pass::Decals.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.decal_pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(Pass::Decals.subpass(data));
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.decal_pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(())
+}
+
+/// Draws `decals` into the current decal pass, in each viewport.
+pub(crate) unsafe fn record_decals(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    decals: &[Decal],
+) {
+    if decals.is_empty() {
+        return;
+    }
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.decal_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.decal_pipeline_layout,
+        1,
+        &[data.texture_descriptor_set, data.decal_descriptor_set],
+        &[],
+    );
+
+    record_viewports(device, data, command_buffer, |viewport| {
+        bind_scene_descriptors_with(device, data, data.decal_pipeline_layout, command_buffer, image_index, viewport);
+        for decal in decals {
+            let push_constants = DecalPushConstants {
+                model: decal.transform,
+                color: decal.color,
+                texture: resolve_texture(data, decal.texture),
+                _padding: [0; 3],
+            };
+            let push_constants_bytes = std::slice::from_raw_parts(
+                &push_constants as *const DecalPushConstants as *const u8,
+                size_of::<DecalPushConstants>(),
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                data.decal_pipeline_layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push_constants_bytes,
+            );
+            device.cmd_draw(command_buffer, 36, 1, 0, 0);
+        }
+    });
+}
@@ -12,7 +12,16 @@ pub(crate) unsafe fn create_depth_objects(
   device: &Device,
   data: &mut AppData,
 ) -> Result<()> {
-  let format = get_depth_format(instance, data).unwrap();
+  // Read by the decal pass, as an input attachment of the render pass or
+  // sampled under dynamic rendering, which has to store it.
+  let format = data.depth_format;
+  let usage = if data.dynamic_rendering {
+    vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+  } else {
+    vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+        | vk::ImageUsageFlags::INPUT_ATTACHMENT
+        | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+  };
   let (depth_image, depth_image_memory) = create_image(
       instance,
       device,
@@ -20,11 +29,13 @@ pub(crate) unsafe fn create_depth_objects(
       data.swapchain_extent.width,
       data.swapchain_extent.height,
       1,
+      1,
       data.msaa_samples,
       format,
       vk::ImageTiling::OPTIMAL,
-      vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+      usage,
       vk::MemoryPropertyFlags::DEVICE_LOCAL,
+      vk::ImageCreateFlags::empty(),
   )
   .unwrap();
 
@@ -42,6 +53,31 @@ pub(crate) unsafe fn create_depth_objects(
   Ok(())
 }
 
+/// The depth the depth buffer is cleared to, the far plane's: 0 with
+/// reversed depth, which maps the near plane to 1 and the far plane to 0.
+/// Floating point depth is most precise near 0, where the far plane needs
+/// it most.
+pub(crate) fn far_depth(reverse_z: bool) -> f32 {
+  if reverse_z { 0.0 } else { 1.0 }
+}
+
+/// `near` and `far` in the order projections are built with: swapped for
+/// reversed depth, since swapping them is what maps the far plane to 0.
+pub(crate) fn depth_planes(reverse_z: bool, near: f32, far: f32) -> (f32, f32) {
+  if reverse_z { (far, near) } else { (near, far) }
+}
+
+/// `op`, written for standard depth, flipped to compare reversed depth.
+pub(crate) fn depth_compare_op(reverse_z: bool, op: vk::CompareOp) -> vk::CompareOp {
+  match (reverse_z, op) {
+    (true, vk::CompareOp::LESS) => vk::CompareOp::GREATER,
+    (true, vk::CompareOp::LESS_OR_EQUAL) => vk::CompareOp::GREATER_OR_EQUAL,
+    (true, vk::CompareOp::GREATER) => vk::CompareOp::LESS,
+    (true, vk::CompareOp::GREATER_OR_EQUAL) => vk::CompareOp::LESS_OR_EQUAL,
+    _ => op,
+  }
+}
+
 pub(crate) unsafe fn get_depth_format(instance: &Instance, data: &AppData) -> Result<vk::Format> {
   let candidates = &[
       vk::Format::D32_SFLOAT,
@@ -0,0 +1,92 @@
+use anyhow::Result;
+
+use vulkanalia::prelude::v1_0::*;
+
+/// Most sets one pool is created for, however many came before it.
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// Hands out descriptor sets of one kind from as many pools as it takes.
+/// When a pool runs out another is created with room for twice as many
+/// sets, so sets can be added for as long as the device has memory instead
+/// of up to a fixed count. `reset` frees every set at once and keeps the
+/// pools for the sets allocated next.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DescriptorAllocator {
+    /// Descriptors of each type one set holds, which pools are sized by.
+    set_sizes: Vec<(vk::DescriptorType, u32)>,
+    /// Sets the next pool is created for.
+    sets_per_pool: u32,
+    /// Pools that may still have room, the one allocated from last at the
+    /// end.
+    ready: Vec<vk::DescriptorPool>,
+    /// Pools that ran out, until the next reset.
+    full: Vec<vk::DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    pub(crate) fn new(set_sizes: &[(vk::DescriptorType, u32)], initial_sets: u32) -> Self {
+        Self {
+            set_sizes: set_sizes.to_vec(),
+            sets_per_pool: initial_sets.clamp(1, MAX_SETS_PER_POOL),
+            ready: vec![],
+            full: vec![],
+        }
+    }
+
+    pub(crate) unsafe fn allocate(&mut self, device: &Device, layout: vk::DescriptorSetLayout) -> Result<vk::DescriptorSet> {
+        let mut fresh = false;
+        loop {
+            let pool = match self.ready.last() {
+                Some(pool) => *pool,
+                None => {
+                    let pool = self.create_pool(device)?;
+                    self.ready.push(pool);
+                    fresh = true;
+                    pool
+                }
+            };
+
+            let layouts = &[layout];
+            let info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(pool)
+                .set_layouts(layouts);
+            match device.allocate_descriptor_sets(&info) {
+                Ok(sets) => return Ok(sets[0]),
+                // A set that does not fit in an empty pool never will.
+                Err(vk::ErrorCode::OUT_OF_POOL_MEMORY | vk::ErrorCode::FRAGMENTED_POOL) if !fresh => {
+                    self.full.push(self.ready.pop().unwrap());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    unsafe fn create_pool(&mut self, device: &Device) -> Result<vk::DescriptorPool> {
+        let sets = self.sets_per_pool;
+        self.sets_per_pool = (sets * 2).min(MAX_SETS_PER_POOL);
+
+        let pool_sizes = self
+            .set_sizes
+            .iter()
+            .map(|(type_, count)| vk::DescriptorPoolSize::builder().type_(*type_).descriptor_count(count * sets))
+            .collect::<Vec<_>>();
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(sets);
+        Ok(device.create_descriptor_pool(&info, None)?)
+    }
+
+    /// Frees every set handed out, which no frame may still be using.
+    pub(crate) unsafe fn reset(&mut self, device: &Device) {
+        self.ready.append(&mut self.full);
+        for pool in &self.ready {
+            device.reset_descriptor_pool(*pool, vk::DescriptorPoolResetFlags::empty()).unwrap();
+        }
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        for pool in self.ready.drain(..).chain(self.full.drain(..)) {
+            device.destroy_descriptor_pool(pool, None);
+        }
+    }
+}
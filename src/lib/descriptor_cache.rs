@@ -0,0 +1,177 @@
+use anyhow::Result;
+use std::{collections::HashMap, slice};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::descriptor_allocator::DescriptorAllocator;
+
+/// A descriptor set layout as created, down to its binding flags.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct LayoutKey {
+    flags: vk::DescriptorSetLayoutCreateFlags,
+    /// Binding, type, count, stages and immutable samplers.
+    bindings: Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags, Vec<vk::Sampler>)>,
+    binding_flags: Vec<vk::DescriptorBindingFlags>,
+}
+
+/// Every descriptor set layout the renderer created, each created once
+/// however many passes declare it. Layouts live until `destroy`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LayoutCache {
+    layouts: HashMap<LayoutKey, vk::DescriptorSetLayout>,
+}
+
+impl LayoutCache {
+    /// The layout `info` describes, created the first time it is asked for.
+    /// Of the structures chained to `info` only binding flags are known,
+    /// which is all the renderer chains.
+    pub(crate) unsafe fn get(
+        &mut self,
+        device: &Device,
+        info: &vk::DescriptorSetLayoutCreateInfo,
+    ) -> Result<vk::DescriptorSetLayout> {
+        let key = layout_key(info);
+        if let Some(layout) = self.layouts.get(&key) {
+            return Ok(*layout);
+        }
+
+        let layout = device.create_descriptor_set_layout(info, None)?;
+        self.layouts.insert(key, layout);
+        Ok(layout)
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        for (_, layout) in self.layouts.drain() {
+            device.destroy_descriptor_set_layout(layout, None);
+        }
+    }
+}
+
+unsafe fn layout_key(info: &vk::DescriptorSetLayoutCreateInfo) -> LayoutKey {
+    let bindings = parts(info.bindings, info.binding_count)
+        .iter()
+        .map(|b: &vk::DescriptorSetLayoutBinding| {
+            let samplers = parts(b.immutable_samplers, b.descriptor_count);
+            (b.binding, b.descriptor_type, b.descriptor_count, b.stage_flags, samplers.to_vec())
+        })
+        .collect();
+
+    let mut binding_flags = vec![];
+    let mut next = info.next as *const vk::BaseInStructure;
+    while !next.is_null() {
+        if (*next).s_type == vk::StructureType::DESCRIPTOR_SET_LAYOUT_BINDING_FLAGS_CREATE_INFO {
+            let flags = &*(next as *const vk::DescriptorSetLayoutBindingFlagsCreateInfo);
+            binding_flags = parts(flags.binding_flags, flags.binding_count).to_vec();
+        }
+        next = (*next).next;
+    }
+
+    LayoutKey { flags: info.flags, bindings, binding_flags }
+}
+
+/// The `count` elements at `pointer`, which may be null with none.
+unsafe fn parts<'a, T>(pointer: *const T, count: u32) -> &'a [T] {
+    if pointer.is_null() || count == 0 { &[] } else { slice::from_raw_parts(pointer, count as usize) }
+}
+
+/// What one binding of a cached set points at.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum BoundResource {
+    Buffer { buffer: vk::Buffer, offset: vk::DeviceSize, range: vk::DeviceSize },
+    Image { sampler: vk::Sampler, image_view: vk::ImageView, image_layout: vk::ImageLayout },
+}
+
+impl From<vk::DescriptorBufferInfo> for BoundResource {
+    fn from(info: vk::DescriptorBufferInfo) -> Self {
+        Self::Buffer { buffer: info.buffer, offset: info.offset, range: info.range }
+    }
+}
+
+impl From<vk::DescriptorImageInfo> for BoundResource {
+    fn from(info: vk::DescriptorImageInfo) -> Self {
+        Self::Image { sampler: info.sampler, image_view: info.image_view, image_layout: info.image_layout }
+    }
+}
+
+/// Descriptor sets by layout and what they are bound to, so that asking for
+/// the same resources again returns the set already written rather than
+/// allocating and writing another. Sets are never rewritten, so one may be
+/// in use by any number of frames. `clear` frees them all, for when the
+/// resources they point at are destroyed.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SetCache {
+    allocator: DescriptorAllocator,
+    sets: HashMap<(vk::DescriptorSetLayout, Vec<(vk::DescriptorType, BoundResource)>), vk::DescriptorSet>,
+}
+
+impl SetCache {
+    /// A cache allocating from pools sized for sets of `set_sizes`.
+    pub(crate) fn new(set_sizes: &[(vk::DescriptorType, u32)], initial_sets: u32) -> Self {
+        Self { allocator: DescriptorAllocator::new(set_sizes, initial_sets), sets: HashMap::new() }
+    }
+
+    /// A set of `layout` with each of `bindings` at the binding of its index.
+    pub(crate) unsafe fn get(
+        &mut self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+        bindings: &[(vk::DescriptorType, BoundResource)],
+    ) -> Result<vk::DescriptorSet> {
+        let key = (layout, bindings.to_vec());
+        if let Some(set) = self.sets.get(&key) {
+            return Ok(*set);
+        }
+
+        let set = self.allocator.allocate(device, layout)?;
+        let buffer_infos = bindings
+            .iter()
+            .map(|(_, resource)| match *resource {
+                BoundResource::Buffer { buffer, offset, range } => {
+                    vk::DescriptorBufferInfo::builder().buffer(buffer).offset(offset).range(range).build()
+                }
+                BoundResource::Image { .. } => vk::DescriptorBufferInfo::default(),
+            })
+            .collect::<Vec<_>>();
+        let image_infos = bindings
+            .iter()
+            .map(|(_, resource)| match *resource {
+                BoundResource::Image { sampler, image_view, image_layout } => vk::DescriptorImageInfo::builder()
+                    .sampler(sampler)
+                    .image_view(image_view)
+                    .image_layout(image_layout)
+                    .build(),
+                BoundResource::Buffer { .. } => vk::DescriptorImageInfo::default(),
+            })
+            .collect::<Vec<_>>();
+        let writes = bindings
+            .iter()
+            .enumerate()
+            .map(|(i, (descriptor_type, resource))| {
+                let write = vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(i as u32)
+                    .dst_array_element(0)
+                    .descriptor_type(*descriptor_type);
+                match resource {
+                    BoundResource::Buffer { .. } => write.buffer_info(&buffer_infos[i..i + 1]),
+                    BoundResource::Image { .. } => write.image_info(&image_infos[i..i + 1]),
+                }
+            })
+            .collect::<Vec<_>>();
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+        self.sets.insert(key, set);
+        Ok(set)
+    }
+
+    /// Frees every set, which no frame may still be using.
+    pub(crate) unsafe fn clear(&mut self, device: &Device) {
+        self.sets.clear();
+        self.allocator.reset(device);
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        self.sets.clear();
+        self.allocator.destroy(device);
+    }
+}
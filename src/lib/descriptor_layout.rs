@@ -2,24 +2,31 @@ use anyhow::Result;
 
 use vulkanalia::prelude::v1_0::*;
 
-use crate::app::AppData;
+use crate::{app::AppData, pipeline::scene_reflection};
 
+/// Set 0 of the scene pipeline, as the scene shaders declare it.
 pub(crate) unsafe fn create_description_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
-  let ubo_binding = vk::DescriptorSetLayoutBinding::builder()
-      .binding(0)
-      .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-      .descriptor_count(1)
-      .stage_flags(vk::ShaderStageFlags::VERTEX);
+  // Textures are bound separately through the texture table.
+  let mut bindings = scene_reflection().set_layout_bindings(0);
+  // Also read by the meshlet shaders, which cannot be reflected.
+  if data.mesh_shader {
+    bindings
+        .iter_mut()
+        .for_each(|b| b.stage_flags |= vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT);
+  }
+  // Each viewport's uniform buffer slot is picked with a dynamic offset.
+  // Pushed descriptors cannot be dynamic, they are pushed with the offset.
+  if !data.push_descriptors {
+    bindings
+        .iter_mut()
+        .filter(|b| b.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER)
+        .for_each(|b| b.descriptor_type = vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC);
+  }
+  let mut info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+  if data.push_descriptors {
+    info = info.flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR);
+  }
 
-  let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
-      .binding(1)
-      .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-      .descriptor_count(1)
-      .stage_flags(vk::ShaderStageFlags::FRAGMENT);
-
-  let bindings = &[ubo_binding, sampler_binding];
-  let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
-
-  data.descriptor_set_layout = device.create_descriptor_set_layout(&info, None).unwrap();
+  data.descriptor_set_layout = data.set_layouts.get(device, &info).unwrap();
   Ok(())
 }
\ No newline at end of file
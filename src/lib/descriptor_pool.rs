@@ -5,63 +5,85 @@ use vulkanalia::prelude::v1_0::*;
 
 use crate::{
   app::AppData,
+  bindless::write_texture_table,
+  descriptor_cache::SetCache,
   uniform_buffer::UniformBufferObject
 };
 
-pub(crate) unsafe fn create_descriptor_pool(device: &Device, data: &mut AppData) -> Result<()> {
-  let ubo_size = vk::DescriptorPoolSize::builder()
-      .type_(vk::DescriptorType::UNIFORM_BUFFER)
-      .descriptor_count(data.swapchain_images.len() as u32);
+/// Descriptors of each type one scene set holds.
+const SCENE_SET_SIZES: &[(vk::DescriptorType, u32)] = &[
+  (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1),
+  (vk::DescriptorType::STORAGE_BUFFER, 2),
+  (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2),
+];
 
-  let sampler_size = vk::DescriptorPoolSize::builder()
-      .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-      .descriptor_count(data.swapchain_images.len() as u32);
-
-  let pool_sizes = &[ubo_size, sampler_size];
-  let info = vk::DescriptorPoolCreateInfo::builder()
-      .pool_sizes(pool_sizes)
-      .max_sets(data.swapchain_images.len() as u32);
-
-  data.descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+/// The cache the scene sets come from, created once and cleared when the
+/// swapchain is rebuilt rather than recreated at its new image count. With
+/// push descriptors the scene set is pushed while recording, so it never
+/// creates a pool.
+pub(crate) fn create_descriptor_pool(data: &mut AppData) -> Result<()> {
+  data.scene_sets = SetCache::new(SCENE_SET_SIZES, data.swapchain_images.len() as u32);
   Ok(())
 }
 
 pub(crate) unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<()> {
-  let layouts = vec![data.descriptor_set_layout; data.swapchain_images.len()];
-  let info = vk::DescriptorSetAllocateInfo::builder()
-      .descriptor_pool(data.descriptor_pool)
-      .set_layouts(&layouts);
-
-  data.descriptor_sets = device.allocate_descriptor_sets(&info).unwrap();
+  data.descriptor_sets.clear();
+  if data.push_descriptors {
+    return Ok(());
+  }
 
   for i in 0..data.swapchain_images.len() {
-      let info = vk::DescriptorBufferInfo::builder()
+      let ubo_info = vk::DescriptorBufferInfo::builder()
           .buffer(data.uniform_buffers[i])
           .offset(0)
-          .range(size_of::<UniformBufferObject>() as u64);
+          .range(size_of::<UniformBufferObject>() as u64)
+          .build();
 
-      let buffer_info = &[info];
-      let ubo_write = vk::WriteDescriptorSet::builder()
-          .dst_set(data.descriptor_sets[i])
-          .dst_binding(0)
-          .dst_array_element(0)
-          .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-          .buffer_info(buffer_info);
-
-      let info = vk::DescriptorImageInfo::builder()
-          .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-          .image_view(data.texture_image_view)
-          .sampler(data.texture_sampler);
+      let joint_info = vk::DescriptorBufferInfo::builder()
+          .buffer(data.joint_buffers[i])
+          .offset(0)
+          .range(vk::WHOLE_SIZE as u64)
+          .build();
 
-      let image_info = &[info];
-      let sampler_write = vk::WriteDescriptorSet::builder()
-          .dst_set(data.descriptor_sets[i])
-          .dst_binding(1)
-          .dst_array_element(0)
-          .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-          .image_info(image_info);
+      let material_info = vk::DescriptorBufferInfo::builder()
+          .buffer(data.material_buffers[i])
+          .offset(0)
+          .range(vk::WHOLE_SIZE as u64)
+          .build();
 
-      device.update_descriptor_sets(&[ubo_write, sampler_write], &[] as &[vk::CopyDescriptorSet]);
+      let bindings = [
+          (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, ubo_info.into()),
+          (vk::DescriptorType::STORAGE_BUFFER, joint_info.into()),
+          (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, data.shadow_masks[i].descriptor_info().into()),
+          (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, data.occlusion_targets[i].descriptor_info().into()),
+          (vk::DescriptorType::STORAGE_BUFFER, material_info.into()),
+      ];
+      let set = data.scene_sets.get(device, data.descriptor_set_layout, &bindings)?;
+      data.descriptor_sets.push(set);
   }
   Ok(())
-}
\ No newline at end of file
+}
+
+/// Points every set that samples the scene texture at the current
+/// `texture_image_view`, after it has been replaced.
+pub(crate) unsafe fn write_texture_descriptors(device: &Device, data: &mut AppData) {
+  let info = vk::DescriptorImageInfo::builder()
+      .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+      .image_view(data.texture_image_view)
+      .sampler(data.texture_sampler)
+      .build();
+
+  write_texture_table(device, data);
+
+  // The default sprite atlas is the scene texture.
+  if let Some(atlas) = data.sprite_atlas_images.first_mut() {
+    *atlas = info;
+  }
+
+  // Cached sets are never rewritten, so the atlas moves to the set of the
+  // new texture.
+  if !data.sprite_atlas_sets.is_empty() {
+    let bindings = [(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, info.into())];
+    data.sprite_atlas_sets[0] = data.sprite_sets.get(device, data.sprite_set_layout, &bindings).unwrap();
+  }
+}
@@ -0,0 +1,218 @@
+use anyhow::{anyhow, Result};
+use log::*;
+
+use vulkanalia::{prelude::v1_0::*, vk::InstanceV1_1, Version};
+
+use crate::{
+    app::{AppData, VULKAN_1_1, VULKAN_1_2, VULKAN_1_3},
+    physical_device::supports_device_extension,
+};
+
+/// A core device feature the renderer asks for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceFeature {
+    SamplerAnisotropy,
+    /// Wireframe drawing, see `App::wireframe`.
+    FillModeNonSolid,
+    /// Lines wider than one pixel.
+    WideLines,
+    /// Indirect draws of more than one command, which GPU culling needs.
+    MultiDrawIndirect,
+    /// Shading every sample of a multisampled pixel.
+    SampleRateShading,
+    /// BC compressed textures, which are decoded on the CPU without it.
+    TextureCompressionBc,
+}
+
+/// Whether the renderer cannot run without a feature or works around its
+/// absence.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Requirement {
+    Required,
+    Optional,
+}
+
+/// The features asked of every device. Devices missing a required one are
+/// skipped; the optional ones are enabled where supported.
+pub(crate) const FEATURE_REQUESTS: &[(DeviceFeature, Requirement)] = &[
+    (DeviceFeature::SamplerAnisotropy, Requirement::Required),
+    (DeviceFeature::FillModeNonSolid, Requirement::Optional),
+    (DeviceFeature::WideLines, Requirement::Optional),
+    (DeviceFeature::MultiDrawIndirect, Requirement::Optional),
+    (DeviceFeature::SampleRateShading, Requirement::Optional),
+    (DeviceFeature::TextureCompressionBc, Requirement::Optional),
+];
+
+impl DeviceFeature {
+    fn supported_by(self, features: &vk::PhysicalDeviceFeatures) -> bool {
+        let supported = match self {
+            Self::SamplerAnisotropy => features.sampler_anisotropy,
+            Self::FillModeNonSolid => features.fill_mode_non_solid,
+            Self::WideLines => features.wide_lines,
+            Self::MultiDrawIndirect => features.multi_draw_indirect,
+            Self::SampleRateShading => features.sample_rate_shading,
+            Self::TextureCompressionBc => features.texture_compression_bc,
+        };
+        supported == vk::TRUE
+    }
+
+    fn enable(self, features: &mut vk::PhysicalDeviceFeatures) {
+        let feature = match self {
+            Self::SamplerAnisotropy => &mut features.sampler_anisotropy,
+            Self::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            Self::WideLines => &mut features.wide_lines,
+            Self::MultiDrawIndirect => &mut features.multi_draw_indirect,
+            Self::SampleRateShading => &mut features.sample_rate_shading,
+            Self::TextureCompressionBc => &mut features.texture_compression_bc,
+        };
+        *feature = vk::TRUE;
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// The features enabled on the device.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct DeviceFeatures(u32);
+
+impl DeviceFeatures {
+    pub(crate) fn contains(self, feature: DeviceFeature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    pub(crate) fn iter(self) -> impl Iterator<Item = DeviceFeature> {
+        FEATURE_REQUESTS.iter().map(|(f, _)| *f).filter(move |f| self.contains(*f))
+    }
+
+    /// The features to create the device with.
+    pub(crate) fn to_vk(self) -> vk::PhysicalDeviceFeatures {
+        let mut features = vk::PhysicalDeviceFeatures::default();
+        self.iter().for_each(|f| f.enable(&mut features));
+        features
+    }
+}
+
+/// Fails with the first required feature `features` lacks.
+pub(crate) fn check_required_features(features: &vk::PhysicalDeviceFeatures) -> Result<()> {
+    match FEATURE_REQUESTS
+        .iter()
+        .find(|(f, r)| *r == Requirement::Required && !f.supported_by(features))
+    {
+        Some((feature, _)) => Err(anyhow!("Missing the required {:?} feature.", feature)),
+        None => Ok(()),
+    }
+}
+
+/// The requested features `features` supports, logging which were enabled
+/// and which optional ones were not.
+pub(crate) fn negotiate_features(features: &vk::PhysicalDeviceFeatures) -> DeviceFeatures {
+    let (supported, missing): (Vec<_>, Vec<_>) = FEATURE_REQUESTS.iter().partition(|(f, _)| f.supported_by(features));
+    let enabled = DeviceFeatures(supported.iter().fold(0, |bits, (f, _)| bits | f.bit()));
+    info!("Enabled device features: {:?}.", enabled.iter().collect::<Vec<_>>());
+    if !missing.is_empty() {
+        info!("Unsupported optional device features: {:?}.", missing.iter().map(|(f, _)| f).collect::<Vec<_>>());
+    }
+    enabled
+}
+
+/// What the device supports of the Vulkan 1.1 to 1.3 core features the
+/// renderer builds on, see `App::capabilities`. Devices on Vulkan 1.2 or
+/// later report them all through `PhysicalDeviceVulkan11Features` to
+/// `PhysicalDeviceVulkan13Features` and are created with those; older ones
+/// report the extensions that provide them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceCapabilities {
+    /// The newest Vulkan version both the instance and the device support.
+    pub api_version: Version,
+    pub multiview: bool,
+    /// Partially bound, variable count, update-after-bind sampled image
+    /// arrays, which the texture table needs.
+    pub descriptor_indexing: bool,
+    pub timeline_semaphore: bool,
+    pub buffer_device_address: bool,
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+}
+
+impl Default for DeviceCapabilities {
+    fn default() -> Self {
+        Self {
+            api_version: Version::new(1, 0, 0),
+            multiview: false,
+            descriptor_indexing: false,
+            timeline_semaphore: false,
+            buffer_device_address: false,
+            dynamic_rendering: false,
+            synchronization2: false,
+        }
+    }
+}
+
+impl DeviceCapabilities {
+    /// Whether the device is created with the per-version feature structs,
+    /// which must not be chained with the structs of the features they
+    /// absorbed.
+    pub(crate) fn core_feature_chain(&self) -> bool {
+        self.api_version >= VULKAN_1_2
+    }
+}
+
+/// Queries the core features `physical_device` supports. Needs Vulkan 1.1 on
+/// the instance and the device for anything but the version.
+pub(crate) unsafe fn get_device_capabilities(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> DeviceCapabilities {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let api_version = Version::from(data.instance_version.min(properties.api_version));
+    let mut capabilities = DeviceCapabilities { api_version, ..Default::default() };
+    if api_version < VULKAN_1_1 {
+        return capabilities;
+    }
+
+    if capabilities.core_feature_chain() {
+        let mut vulkan_11 = vk::PhysicalDeviceVulkan11Features::default();
+        let mut vulkan_12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut vulkan_13 = vk::PhysicalDeviceVulkan13Features::default();
+        let mut features = vk::PhysicalDeviceFeatures2::builder()
+            .push_next(&mut vulkan_11)
+            .push_next(&mut vulkan_12);
+        if api_version >= VULKAN_1_3 {
+            features = features.push_next(&mut vulkan_13);
+        }
+        instance.get_physical_device_features2(physical_device, &mut features);
+
+        capabilities.multiview = vulkan_11.multiview == vk::TRUE;
+        capabilities.descriptor_indexing = vulkan_12.descriptor_binding_partially_bound == vk::TRUE
+            && vulkan_12.descriptor_binding_variable_descriptor_count == vk::TRUE
+            && vulkan_12.descriptor_binding_sampled_image_update_after_bind == vk::TRUE;
+        capabilities.timeline_semaphore = vulkan_12.timeline_semaphore == vk::TRUE;
+        capabilities.buffer_device_address = vulkan_12.buffer_device_address == vk::TRUE;
+        capabilities.dynamic_rendering = vulkan_13.dynamic_rendering == vk::TRUE;
+        capabilities.synchronization2 = vulkan_13.synchronization2 == vk::TRUE;
+        return capabilities;
+    }
+
+    let mut multiview = vk::PhysicalDeviceMultiviewFeatures::default();
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    let mut timeline = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut multiview);
+    let extension = |name| supports_device_extension(instance, physical_device, name);
+    if extension(vk::EXT_DESCRIPTOR_INDEXING_EXTENSION.name) {
+        features = features.push_next(&mut indexing);
+    }
+    if extension(vk::KHR_TIMELINE_SEMAPHORE_EXTENSION.name) {
+        features = features.push_next(&mut timeline);
+    }
+    instance.get_physical_device_features2(physical_device, &mut features);
+
+    capabilities.multiview = multiview.multiview == vk::TRUE;
+    capabilities.descriptor_indexing = indexing.descriptor_binding_partially_bound == vk::TRUE
+        && indexing.descriptor_binding_variable_descriptor_count == vk::TRUE
+        && indexing.descriptor_binding_sampled_image_update_after_bind == vk::TRUE;
+    capabilities.timeline_semaphore = timeline.timeline_semaphore == vk::TRUE;
+    capabilities
+}
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use std::mem::size_of_val;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    mapped_memory::{create_mapped_buffer, MappedMemory},
+};
+
+/// A host-visible buffer that is rewritten every frame and grows to fit
+/// whatever is uploaded into it. Stays mapped until it grows or is
+/// destroyed.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct DynamicBuffer {
+    pub(crate) buffer: vk::Buffer,
+    pub(crate) memory: MappedMemory,
+    pub(crate) capacity: vk::DeviceSize,
+}
+
+pub(crate) unsafe fn update_dynamic_buffer<T>(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    buffer: &mut DynamicBuffer,
+    usage: vk::BufferUsageFlags,
+    items: &[T],
+) -> Result<()> {
+    let size = size_of_val(items) as u64;
+    if size == 0 {
+        return Ok(());
+    }
+
+    if size > buffer.capacity {
+        destroy_dynamic_buffer(device, buffer);
+
+        let capacity = size.next_power_of_two();
+        let (new_buffer, new_memory) = create_mapped_buffer(instance, device, data, capacity, usage).unwrap();
+
+        buffer.buffer = new_buffer;
+        buffer.memory = new_memory;
+        buffer.capacity = capacity;
+    }
+
+    buffer.memory.write(device, 0, items);
+
+    Ok(())
+}
+
+pub(crate) unsafe fn destroy_dynamic_buffer(device: &Device, buffer: &mut DynamicBuffer) {
+    if buffer.capacity == 0 {
+        return;
+    }
+
+    device.destroy_buffer(buffer.buffer, None);
+    buffer.memory.free(device);
+    *buffer = DynamicBuffer::default();
+}
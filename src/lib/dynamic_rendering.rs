@@ -0,0 +1,515 @@
+use anyhow::Result;
+use vulkanalia::{prelude::v1_0::*, vk::DeviceV1_3};
+
+use crate::{
+    app::AppData,
+    depth_object::far_depth,
+    oit::{ACCUM_FORMAT, REVEAL_FORMAT},
+    render_pass::discard_store_op,
+    tonemap::{uses_resolve_attachment, HDR_FORMAT},
+};
+
+/// The passes a frame is drawn in.
+///
+/// With render pass objects the scene, decal and tonemap passes are
+/// subpasses of `data.render_pass`, with the OIT composite before the tonemap
+/// when it is drawn, and the overlay is `data.overlay_render_pass`. With
+/// dynamic rendering each is its own `cmd_begin_rendering`, with the layout
+/// transitions between them recorded by `begin_pass` and `end_pass`, and the
+/// passes after the scene sample its targets instead of loading them as
+/// input attachments.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Pass {
+    Scene,
+    /// Projects decals onto the scene by its depth, see `decal.rs`.
+    Decals,
+    /// Blends the OIT targets over the scene color, see `oit.rs`. Only
+    /// begun with `order_independent_transparency`.
+    OitComposite,
+    Tonemap,
+    Overlay,
+}
+
+impl Pass {
+    fn render_pass(self, data: &AppData) -> vk::RenderPass {
+        match self {
+            Self::Scene | Self::Decals | Self::OitComposite | Self::Tonemap => data.render_pass,
+            Self::Overlay => data.overlay_render_pass,
+        }
+    }
+
+    pub(crate) fn subpass(self, data: &AppData) -> u32 {
+        match self {
+            Self::Decals => 1,
+            Self::OitComposite => 2,
+            Self::Tonemap if data.order_independent_transparency => 3,
+            Self::Tonemap => 2,
+            _ => 0,
+        }
+    }
+
+    /// Whether this is the last pass to draw into the scene color, which
+    /// resolves it if it is resolved and hands it to the tonemap pass.
+    pub(crate) fn finishes_color(self, data: &AppData) -> bool {
+        match self {
+            Self::Decals => !data.order_independent_transparency,
+            Self::OitComposite => true,
+            _ => false,
+        }
+    }
+
+    fn framebuffer(self, data: &AppData, image_index: usize) -> vk::Framebuffer {
+        match self {
+            Self::Scene | Self::Decals | Self::OitComposite | Self::Tonemap => data.framebuffers[image_index],
+            Self::Overlay => data.overlay_framebuffers[image_index],
+        }
+    }
+
+    /// The attachments pipelines in this pass are created against.
+    pub(crate) fn formats(self, data: &AppData) -> PassFormats {
+        match self {
+            Self::Scene if data.order_independent_transparency => PassFormats {
+                colors: [HDR_FORMAT, ACCUM_FORMAT, REVEAL_FORMAT],
+                depth: data.depth_format,
+                samples: data.msaa_samples,
+            },
+            Self::Scene => PassFormats {
+                colors: [HDR_FORMAT, vk::Format::UNDEFINED, vk::Format::UNDEFINED],
+                depth: data.depth_format,
+                samples: data.msaa_samples,
+            },
+            Self::Decals | Self::OitComposite => PassFormats {
+                colors: [HDR_FORMAT, vk::Format::UNDEFINED, vk::Format::UNDEFINED],
+                depth: vk::Format::UNDEFINED,
+                samples: data.msaa_samples,
+            },
+            Self::Tonemap | Self::Overlay => PassFormats {
+                colors: [data.swapchain_format, vk::Format::UNDEFINED, vk::Format::UNDEFINED],
+                depth: vk::Format::UNDEFINED,
+                samples: vk::SampleCountFlags::_1,
+            },
+        }
+    }
+}
+
+/// The attachment formats of a pass, which stand in for the render pass
+/// when creating pipelines and secondary command buffers for dynamic
+/// rendering.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct PassFormats {
+    /// The color attachments, up to the first `UNDEFINED`.
+    pub(crate) colors: [vk::Format; 3],
+    /// `UNDEFINED` when the pass has no depth attachment.
+    pub(crate) depth: vk::Format,
+    pub(crate) samples: vk::SampleCountFlags,
+}
+
+impl PassFormats {
+    pub(crate) fn color_formats(&self) -> &[vk::Format] {
+        let count = self.colors.iter().take_while(|f| **f != vk::Format::UNDEFINED).count();
+        &self.colors[..count]
+    }
+
+    /// Chained into `vk::GraphicsPipelineCreateInfo` in place of a render pass.
+    pub(crate) fn pipeline_info(&self) -> vk::PipelineRenderingCreateInfoBuilder<'_> {
+        vk::PipelineRenderingCreateInfo::builder()
+            .color_attachment_formats(self.color_formats())
+            .depth_attachment_format(self.depth)
+    }
+}
+
+/// Begins a secondary command buffer that continues `pass`.
+pub(crate) unsafe fn begin_secondary(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    pass: Pass,
+    image_index: usize,
+) -> Result<()> {
+    let formats = pass.formats(data);
+    let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
+        .color_attachment_formats(formats.color_formats())
+        .depth_attachment_format(formats.depth)
+        .rasterization_samples(formats.samples);
+
+    let inheritance_info = if data.dynamic_rendering {
+        vk::CommandBufferInheritanceInfo::builder().push_next(&mut rendering_info)
+    } else {
+        vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(pass.render_pass(data))
+            .subpass(pass.subpass(data))
+            .framebuffer(pass.framebuffer(data, image_index))
+    };
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+        .inheritance_info(&inheritance_info);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+    Ok(())
+}
+
+/// Begins `pass` in a primary command buffer. The tonemap pass must follow
+/// the scene pass.
+pub(crate) unsafe fn begin_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    pass: Pass,
+    image_index: usize,
+    contents: vk::SubpassContents,
+) {
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(data.swapchain_extent);
+
+    let color_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 1.0],
+        },
+    };
+
+    let depth_clear_value = vk::ClearValue {
+        depth_stencil: vk::ClearDepthStencilValue {
+            depth: far_depth(data.reverse_z),
+            stencil: 0,
+        },
+    };
+
+    // Nothing has been accumulated and everything is revealed.
+    let accum_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 0.0],
+        },
+    };
+
+    let reveal_clear_value = vk::ClearValue {
+        color: vk::ClearColorValue {
+            float32: [1.0, 0.0, 0.0, 0.0],
+        },
+    };
+
+    let oit = data.order_independent_transparency;
+    if !data.dynamic_rendering {
+        match pass {
+            Pass::Scene | Pass::Overlay => {
+                let mut clear_values = vec![color_clear_value, depth_clear_value];
+                if pass == Pass::Scene && oit {
+                    clear_values.extend([accum_clear_value, reveal_clear_value]);
+                }
+                let info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(pass.render_pass(data))
+                    .framebuffer(pass.framebuffer(data, image_index))
+                    .render_area(render_area)
+                    .clear_values(&clear_values);
+                device.cmd_begin_render_pass(command_buffer, &info, contents);
+            }
+            Pass::Decals | Pass::OitComposite | Pass::Tonemap => device.cmd_next_subpass(command_buffer, contents),
+        }
+        return;
+    }
+
+    let flags = if contents == vk::SubpassContents::SECONDARY_COMMAND_BUFFERS {
+        vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+    } else {
+        vk::RenderingFlags::empty()
+    };
+
+    match pass {
+        Pass::Scene => {
+            let resolve = uses_resolve_attachment(data);
+            let mut barriers = vec![
+                image_barrier(data.color_image, vk::ImageAspectFlags::COLOR)
+                    .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+                image_barrier(data.depth_image, depth_aspect_mask(data.depth_format))
+                    .new_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+            ];
+            if oit {
+                for image in [data.oit_accum_image, data.oit_reveal_image] {
+                    barriers.push(
+                        image_barrier(image, vk::ImageAspectFlags::COLOR)
+                            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+                    );
+                }
+            }
+            if resolve {
+                barriers.push(
+                    image_barrier(data.resolve_image, vk::ImageAspectFlags::COLOR)
+                        .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+                );
+            }
+
+            // The images are shared between frames in flight, so this waits
+            // for the last frame's scene pass to write them and its tonemap
+            // pass to read them.
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[] as &[vk::MemoryBarrier],
+                &[] as &[vk::BufferMemoryBarrier],
+                &barriers,
+            );
+
+            // A later pass resolves the color. The depth is stored for the
+            // decal pass.
+            let color_attachment = scene_color_attachment(data, false)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .clear_value(color_clear_value);
+
+            let depth_attachment = vk::RenderingAttachmentInfo::builder()
+                .image_view(data.depth_image_view)
+                .image_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .clear_value(depth_clear_value);
+
+            let mut color_attachments = vec![color_attachment];
+            if oit {
+                for (view, clear_value) in [
+                    (data.oit_accum_image_view, accum_clear_value),
+                    (data.oit_reveal_image_view, reveal_clear_value),
+                ] {
+                    color_attachments.push(
+                        vk::RenderingAttachmentInfo::builder()
+                            .image_view(view)
+                            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                            .load_op(vk::AttachmentLoadOp::CLEAR)
+                            .store_op(vk::AttachmentStoreOp::STORE)
+                            .clear_value(clear_value),
+                    );
+                }
+            }
+
+            let info = vk::RenderingInfo::builder()
+                .flags(flags)
+                .render_area(render_area)
+                .layer_count(1)
+                .color_attachments(&color_attachments)
+                .depth_attachment(&depth_attachment);
+            device.cmd_begin_rendering(command_buffer, &info);
+        }
+        Pass::Decals | Pass::OitComposite => {
+            // `end_pass` has already made the previous pass's writes visible.
+            let resolve = uses_resolve_attachment(data) && pass.finishes_color(data);
+            let color_attachment = scene_color_attachment(data, resolve).load_op(vk::AttachmentLoadOp::LOAD);
+
+            let color_attachments = &[color_attachment];
+            let info = vk::RenderingInfo::builder()
+                .flags(flags)
+                .render_area(render_area)
+                .layer_count(1)
+                .color_attachments(color_attachments);
+            device.cmd_begin_rendering(command_buffer, &info);
+        }
+        Pass::Tonemap | Pass::Overlay => {
+            // The tonemap pass writes every pixel; the overlay draws over
+            // the finished frame, after any blits from it.
+            let (old_layout, src_stage_mask, src_access_mask, load_op) = if pass == Pass::Tonemap {
+                (
+                    vk::ImageLayout::UNDEFINED,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    vk::AccessFlags::empty(),
+                    vk::AttachmentLoadOp::DONT_CARE,
+                )
+            } else {
+                (
+                    vk::ImageLayout::PRESENT_SRC_KHR,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    vk::AttachmentLoadOp::LOAD,
+                )
+            };
+
+            let barrier = image_barrier(data.swapchain_images[image_index], vk::ImageAspectFlags::COLOR)
+                .old_layout(old_layout)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage_mask,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[] as &[vk::MemoryBarrier],
+                &[] as &[vk::BufferMemoryBarrier],
+                &[barrier],
+            );
+
+            let color_attachment = vk::RenderingAttachmentInfo::builder()
+                .image_view(data.swapchain_image_views[image_index])
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(load_op)
+                .store_op(vk::AttachmentStoreOp::STORE);
+
+            let color_attachments = &[color_attachment];
+            let info = vk::RenderingInfo::builder()
+                .flags(flags)
+                .render_area(render_area)
+                .layer_count(1)
+                .color_attachments(color_attachments);
+            device.cmd_begin_rendering(command_buffer, &info);
+        }
+    }
+}
+
+/// Ends `pass` in a primary command buffer, leaving the swapchain image in
+/// `PRESENT_SRC_KHR` after the tonemap and overlay passes, as the render
+/// passes do.
+pub(crate) unsafe fn end_pass(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    pass: Pass,
+    image_index: usize,
+) {
+    if !data.dynamic_rendering {
+        // The subpasses before the tonemap are ended by the next one.
+        if matches!(pass, Pass::Tonemap | Pass::Overlay) {
+            device.cmd_end_render_pass(command_buffer);
+        }
+        return;
+    }
+
+    device.cmd_end_rendering(command_buffer);
+
+    // The next pass draws over the color. After the scene, the decal pass
+    // samples its depth and the composite its OIT targets.
+    if matches!(pass, Pass::Scene | Pass::Decals) && !pass.finishes_color(data) {
+        let mut barriers = vec![image_barrier(data.color_image, vk::ImageAspectFlags::COLOR)
+            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)];
+        if pass == Pass::Scene {
+            barriers.push(
+                image_barrier(data.depth_image, depth_aspect_mask(data.depth_format))
+                    .old_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ),
+            );
+        }
+        if pass == Pass::Scene && data.order_independent_transparency {
+            barriers.extend([data.oit_accum_image, data.oit_reveal_image].map(|image| {
+                image_barrier(image, vk::ImageAspectFlags::COLOR)
+                    .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            }));
+        }
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &barriers,
+        );
+        return;
+    }
+
+    let (image, dst_stage_mask, dst_access_mask, new_layout) = match pass {
+        Pass::Scene | Pass::Decals | Pass::OitComposite => {
+            let image = if uses_resolve_attachment(data) {
+                data.resolve_image
+            } else {
+                data.color_image
+            };
+            // The exposure histogram may read it before the tonemap pass.
+            (
+                image,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+        }
+        // Metering and the focus blur may blit from the image next.
+        Pass::Tonemap => (
+            data.swapchain_images[image_index],
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::COLOR_ATTACHMENT_READ,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        ),
+        Pass::Overlay => (
+            data.swapchain_images[image_index],
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::AccessFlags::empty(),
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        ),
+    };
+
+    let barrier = image_barrier(image, vk::ImageAspectFlags::COLOR)
+        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .new_layout(new_layout)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(dst_access_mask);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[barrier],
+    );
+}
+
+/// The scene color as an attachment, resolved into `resolve_image` with
+/// `resolve`. As in the render pass, the color is only stored when the next
+/// pass reads it directly.
+fn scene_color_attachment(data: &AppData, resolve: bool) -> vk::RenderingAttachmentInfoBuilder {
+    let store_op = if resolve { discard_store_op(data) } else { vk::AttachmentStoreOp::STORE };
+    let mut attachment = vk::RenderingAttachmentInfo::builder()
+        .image_view(data.color_image_view)
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .store_op(store_op);
+    if resolve {
+        attachment = attachment
+            .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+            .resolve_image_view(data.resolve_image_view)
+            .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    }
+    attachment
+}
+
+/// Layout transitions of combined depth/stencil images must name both
+/// aspects, even though only depth is attached.
+fn depth_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D32_SFLOAT_S8_UINT | vk::Format::D24_UNORM_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+/// A barrier over the whole of a single-level image, from `UNDEFINED`.
+fn image_barrier(image: vk::Image, aspect_mask: vk::ImageAspectFlags) -> vk::ImageMemoryBarrierBuilder<'static> {
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect_mask)
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+}
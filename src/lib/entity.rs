@@ -0,0 +1,118 @@
+use cgmath::{vec3, Deg, SquareMatrix};
+
+use crate::{
+    assets::MeshHandle,
+    material::MaterialInstance,
+    render_queue::{DrawOrder, RenderQueue},
+    types::{Mat4, Vec3},
+    viewmodel::CameraLayer,
+    world::WorldConfig,
+};
+
+/// Whether an entity's transform may change after it is placed.
+///
+/// Static entities skip per-frame transform updates. Those in opaque render
+/// queues are drawn from a batched secondary command buffer that is only
+/// re-recorded when the set of static entities changes, and their geometry
+/// can be merged by `App::bake_static_batches`. Systems that can precompute per-entity data
+/// (shadow caching, acceleration structures) should key off this as well.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Mobility {
+    Static,
+    #[default]
+    Dynamic,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Entity {
+    pub transform: Mat4,
+    pub mobility: Mobility,
+    /// Blends the entity over what is behind it in transparent render
+    /// queues. Other queues draw it opaque.
+    pub opacity: f32,
+    /// Texture table slot from `App::load_texture`, or
+    /// `AtlasRegion::texture` from `App::load_texture_atlas`. Slot 0, the
+    /// scene texture, is used for slots that were never loaded.
+    pub texture: u32,
+    /// Where the entity falls in the draw order.
+    pub order: DrawOrder,
+    /// Shades the entity with a material from `App::create_material`
+    /// instead of the scene shader, with the instance's parameters. Such
+    /// entities are never culled on the GPU, drawn as an impostor or baked
+    /// into a static batch; those not `Shading::Lit` are also left out of
+    /// meshlets and the depth pre-pass, and the eyes draw them lit.
+    pub material: Option<MaterialInstance>,
+    pub layer: CameraLayer,
+    /// Index into `App::poses` for skinned models. Without one a skinned
+    /// model is drawn in its rest pose.
+    pub pose: Option<usize>,
+    /// A mesh from `App::acquire_mesh` to draw instead of the scene model.
+    /// Such entities are always drawn one by one with the CPU's culling:
+    /// never culled on the GPU, drawn as meshlets or an impostor, baked
+    /// into a static batch or added to the acceleration structure.
+    pub mesh: Option<MeshHandle>,
+}
+
+impl Default for Entity {
+    fn default() -> Self {
+        Self {
+            transform: Mat4::identity(),
+            mobility: Mobility::Dynamic,
+            opacity: 1.0,
+            texture: 0,
+            order: DrawOrder::default(),
+            material: None,
+            layer: CameraLayer::World,
+            pose: None,
+            mesh: None,
+        }
+    }
+}
+
+impl Entity {
+    pub fn is_static(&self) -> bool {
+        self.mobility == Mobility::Static
+    }
+}
+
+/// Where the demo scene places the `index`th model.
+pub(crate) fn demo_position(index: usize) -> Vec3 {
+    let y = (((index % 2) as f32) * 2.5) - 1.25;
+    let z = (((index / 2) as f32) * -2.0) + 1.0;
+    vec3(0.0, y, z)
+}
+
+/// The demo scene's four models, spinning about Z at 90 degrees per second.
+/// The layout is written in render space and converted to `world`.
+pub(crate) fn demo_entities(world: &WorldConfig) -> Vec<Entity> {
+    (0..4)
+        .map(|i| Entity {
+            transform: world.convert_render_transform(Mat4::from_translation(demo_position(i))),
+            mobility: Mobility::Dynamic,
+            opacity: (i + 1) as f32 * 0.25,
+            texture: 0,
+            // All but the last are see-through.
+            order: DrawOrder {
+                queue: if i < 3 { RenderQueue::TRANSPARENT } else { RenderQueue::OPAQUE },
+                ..DrawOrder::default()
+            },
+            material: None,
+            layer: CameraLayer::World,
+            pose: None,
+            mesh: None,
+        })
+        .collect()
+}
+
+/// A small model held at the lower right of the view, for the viewmodel
+/// layer.
+pub(crate) fn demo_viewmodel_transform() -> Mat4 {
+    Mat4::from_translation(vec3(0.25, -0.2, -0.6)) * Mat4::from_scale(0.15)
+}
+
+pub(crate) fn demo_transform(world: &WorldConfig, index: usize, time: f32) -> Mat4 {
+    world.convert_render_transform(
+        Mat4::from_translation(demo_position(index))
+            * Mat4::from_axis_angle(vec3(0.0, 0.0, 1.0), Deg(90.0) * time),
+    )
+}
@@ -0,0 +1,618 @@
+use anyhow::Result;
+use cgmath::vec2;
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    image::{create_image, create_image_view},
+    reduction::{create_reduction, Reduction},
+    shader::{create_shader_module, SpecializationConstants},
+    texture::ColorSpace,
+    tonemap::resolves_in_shader,
+    types::Vec2,
+    vertex_buffer::create_buffer,
+};
+
+/// The resolved frame is blitted down to a square of this size before it is
+/// metered. The aspect ratio is restored when weighting texels.
+const METERING_SIZE: u32 = 64;
+const METERING_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// How texels are weighted when averaging the frame's luminance.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MeteringMode {
+    /// Every texel counts equally.
+    #[default]
+    Average = 0,
+    /// Gaussian falloff around `AutoExposure::center`.
+    CenterWeighted = 1,
+    /// Only texels within `AutoExposure::spot_radius` of the center count.
+    Spot = 2,
+}
+
+impl MeteringMode {
+    pub const ALL: [MeteringMode; 3] = [
+        MeteringMode::Average,
+        MeteringMode::CenterWeighted,
+        MeteringMode::Spot,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+/// Scales the scene so the metered luminance lands on `key`.
+///
+/// With `histogram` the HDR scene is metered and the exposure adapted on the
+/// GPU before tonemapping, in the same frame. Otherwise the metered HDR scene
+/// is read back, so results lag a frame or two behind. Without dynamic
+/// rendering and while stereo is drawn the scene color never leaves the
+/// render pass, so the presented image is metered instead and saturated
+/// highlights are under-counted.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AutoExposure {
+    /// When unset the exposure stays at 1, but the frame is still metered.
+    pub enabled: bool,
+    pub metering: MeteringMode,
+    /// Where the center-weighted and spot modes are centered, from `(0, 0)`
+    /// at the top left of the screen to `(1, 1)` at the bottom right.
+    pub center: Vec2,
+    /// In screen heights.
+    pub spot_radius: f32,
+    pub key: f32,
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+    /// How quickly the exposure approaches its target, per second.
+    pub speed: f32,
+    /// Meters a histogram of the HDR scene's luminance instead of the
+    /// presented image. Ignored without dynamic rendering and while stereo
+    /// is drawn.
+    pub histogram: bool,
+    /// The percent of the histogram's weight, from the darkest end, that is
+    /// ignored when averaging it.
+    pub low_percentile: f32,
+    /// The percent of the histogram's weight above which the brightest end is
+    /// ignored, so that small highlights do not darken the frame.
+    pub high_percentile: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            metering: MeteringMode::Average,
+            center: vec2(0.5, 0.5),
+            spot_radius: 0.1,
+            key: 0.18,
+            min_exposure: 0.25,
+            max_exposure: 4.0,
+            speed: 2.0,
+            histogram: true,
+            low_percentile: 50.0,
+            high_percentile: 95.0,
+        }
+    }
+}
+
+impl AutoExposure {
+    /// Moves `exposure` towards the value that maps `luminance` to `key`.
+    pub(crate) fn adapt(&self, exposure: f32, luminance: f32, elapsed: f32) -> f32 {
+        if !self.enabled {
+            return 1.0;
+        }
+
+        let target = (self.key / luminance.max(1e-4)).clamp(self.min_exposure, self.max_exposure);
+        let blend = 1.0 - (-elapsed * self.speed).exp();
+        (exposure.ln() + (target.ln() - exposure.ln()) * blend).exp()
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct MeteringPushConstants {
+    mode: u32,
+    spot_radius: f32,
+    center: Vec2,
+    aspect: f32,
+    /// Set when the swapchain is UNORM, since blits only decode `_SRGB`
+    /// sources.
+    decode_srgb: u32,
+}
+
+/// Per swapchain image metering resources.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Metering {
+    pub(crate) image: vk::Image,
+    pub(crate) image_memory: vk::DeviceMemory,
+    pub(crate) image_view: vk::ImageView,
+    pub(crate) luminance: vk::Buffer,
+    pub(crate) luminance_memory: vk::DeviceMemory,
+    pub(crate) weights: vk::Buffer,
+    pub(crate) weights_memory: vk::DeviceMemory,
+    pub(crate) luminance_sum: Reduction,
+    pub(crate) weight_sum: Reduction,
+    /// Host visible; receives the two sums.
+    pub(crate) readback: vk::Buffer,
+    pub(crate) readback_memory: vk::DeviceMemory,
+    pub(crate) set: vk::DescriptorSet,
+    /// The exposure the metered frame was rendered with, if one was recorded
+    /// and not yet read back.
+    pub(crate) pending: Option<f32>,
+}
+
+impl Metering {
+    /// Records the blit, metering and reduction of `swapchain_image`, which
+    /// must be in `PRESENT_SRC_KHR` and is left there.
+    pub(crate) unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        swapchain_image: vk::Image,
+        settings: &AutoExposure,
+    ) {
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let source_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(swapchain_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+        let destination_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[source_barrier, destination_barrier],
+        );
+
+        let layers = vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(0)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let extent = data.swapchain_extent;
+        let blit = vk::ImageBlit::builder()
+            .src_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: extent.width as i32,
+                    y: extent.height as i32,
+                    z: 1,
+                },
+            ])
+            .src_subresource(layers)
+            .dst_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: METERING_SIZE as i32,
+                    y: METERING_SIZE as i32,
+                    z: 1,
+                },
+            ])
+            .dst_subresource(layers);
+
+        device.cmd_blit_image(
+            command_buffer,
+            swapchain_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+
+        let source_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(swapchain_image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::empty());
+
+        let destination_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[source_barrier, destination_barrier],
+        );
+
+        let decode_srgb = ColorSpace::of(data.swapchain_format) == ColorSpace::Linear;
+        let (pipeline, layout) = (data.metering_pipeline, data.metering_pipeline_layout);
+        self.record_metering(device, data, command_buffer, settings, pipeline, layout, &[self.set], decode_srgb);
+    }
+
+    /// Records the metering and reduction of the HDR scene color, which must
+    /// be in `SHADER_READ_ONLY_OPTIMAL`. Only under dynamic rendering, see
+    /// `create_histogram_descriptor_set`.
+    pub(crate) unsafe fn record_hdr(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        settings: &AutoExposure,
+    ) {
+        let (pipeline, layout) = (data.hdr_metering_pipeline, data.hdr_metering_pipeline_layout);
+        let sets = [data.histogram_descriptor_set, self.set];
+        self.record_metering(device, data, command_buffer, settings, pipeline, layout, &sets, false);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn record_metering(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        settings: &AutoExposure,
+        pipeline: vk::Pipeline,
+        layout: vk::PipelineLayout,
+        sets: &[vk::DescriptorSet],
+        decode_srgb: bool,
+    ) {
+        let extent = data.swapchain_extent;
+        let push_constants = MeteringPushConstants {
+            mode: settings.metering as u32,
+            spot_radius: settings.spot_radius,
+            center: settings.center,
+            aspect: extent.width as f32 / extent.height as f32,
+            decode_srgb: decode_srgb as u32,
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const MeteringPushConstants as *const u8,
+            size_of::<MeteringPushConstants>(),
+        );
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+        device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, layout, 0, sets, &[]);
+        device.cmd_push_constants(command_buffer, layout, vk::ShaderStageFlags::COMPUTE, 0, push_constants_bytes);
+        device.cmd_dispatch(command_buffer, METERING_SIZE / 8, METERING_SIZE / 8, 1);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        self.luminance_sum.record_sum(device, data, command_buffer);
+        self.weight_sum.record_sum(device, data, command_buffer);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        for (i, total) in [self.luminance_sum.total(), self.weight_sum.total()].into_iter().enumerate() {
+            let region = vk::BufferCopy::builder()
+                .src_offset(0)
+                .dst_offset(i as u64 * 4)
+                .size(4);
+            device.cmd_copy_buffer(command_buffer, total, self.readback, &[region]);
+        }
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+    }
+
+    /// The weighted log-average luminance of the last recorded frame, or
+    /// `None` if no texel had any weight. The frame must have finished.
+    pub(crate) unsafe fn read(&self, device: &Device) -> Option<f32> {
+        let memory = device
+            .map_memory(self.readback_memory, 0, 8, vk::MemoryMapFlags::empty())
+            .unwrap();
+        let [luminance, weight] = *memory.cast::<[f32; 2]>();
+        device.unmap_memory(self.readback_memory);
+
+        if weight > 0.0 {
+            Some((luminance / weight).exp())
+        } else {
+            None
+        }
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        self.luminance_sum.destroy(device);
+        self.weight_sum.destroy(device);
+        device.destroy_buffer(self.readback, None);
+        free_memory(device, self.readback_memory);
+        device.destroy_buffer(self.weights, None);
+        free_memory(device, self.weights_memory);
+        device.destroy_buffer(self.luminance, None);
+        free_memory(device, self.luminance_memory);
+        device.destroy_image_view(self.image_view, None);
+        free_memory(device, self.image_memory);
+        device.destroy_image(self.image, None);
+    }
+}
+
+pub(crate) unsafe fn create_metering_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let buffer_bindings = [1, 2].map(|binding| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+    });
+
+    let bindings = &[image_binding, buffer_bindings[0], buffer_bindings[1]];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.metering_set_layout = data.set_layouts.get(device, &info).unwrap();
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<MeteringPushConstants>() as u32);
+
+    let set_layouts = &[data.metering_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.metering_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let comp = include_bytes!("../../shaders/metering_comp.spv");
+    let shader_module = create_shader_module(device, &comp[..]).unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(data.metering_pipeline_layout);
+
+    data.metering_pipeline = device
+        .create_compute_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(shader_module, None);
+    Ok(())
+}
+
+/// The pipeline `Metering::record_hdr` meters the HDR scene color with. Like
+/// the histogram pass it reads every sample when the tonemap pass resolves
+/// them, so it is recreated with the swapchain.
+pub(crate) unsafe fn create_hdr_metering_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<MeteringPushConstants>() as u32);
+
+    let set_layouts = &[data.histogram_set_layout, data.metering_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.hdr_metering_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let comp = if resolves_in_shader(data) {
+        &include_bytes!("../../shaders/metering_hdr_ms_comp.spv")[..]
+    } else {
+        &include_bytes!("../../shaders/metering_hdr_comp.spv")[..]
+    };
+    let shader_module = create_shader_module(device, comp).unwrap();
+
+    let constants = SpecializationConstants::new().i32(0, data.msaa_samples.bits() as i32);
+    let specialization_info = constants.info();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(data.hdr_metering_pipeline_layout);
+
+    data.hdr_metering_pipeline = device
+        .create_compute_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(shader_module, None);
+    Ok(())
+}
+
+/// Creates one `Metering` per swapchain image. Does nothing if neither the
+/// HDR scene color nor the swapchain can be metered.
+pub(crate) unsafe fn create_metering(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.metering.clear();
+    if !data.metering_supported && !data.dynamic_rendering {
+        return Ok(());
+    }
+
+    let images = data.swapchain_images.len() as u32;
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(images);
+    let buffer_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(images * 2);
+
+    let pool_sizes = &[image_size, buffer_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(images);
+    data.metering_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = vec![data.metering_set_layout; images as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.metering_descriptor_pool)
+        .set_layouts(&layouts);
+    let sets = device.allocate_descriptor_sets(&info).unwrap();
+
+    let count = METERING_SIZE * METERING_SIZE;
+    for set in sets {
+        let (image, image_memory) = create_image(
+            instance,
+            device,
+            data,
+            METERING_SIZE,
+            METERING_SIZE,
+            1,
+            1,
+            vk::SampleCountFlags::_1,
+            METERING_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::STORAGE,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::empty(),
+        )
+        .unwrap();
+        let image_view = create_image_view(device, image, METERING_FORMAT, vk::ImageAspectFlags::COLOR, 1).unwrap();
+
+        let [(luminance, luminance_memory), (weights, weights_memory)] = [(); 2].map(|_| {
+            create_buffer(
+                instance,
+                device,
+                data,
+                count as u64 * 4,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap()
+        });
+
+        let (readback, readback_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            8,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )
+        .unwrap();
+
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(image_view);
+        let image_infos = &[image_info];
+        let image_write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+            .image_info(image_infos);
+
+        let buffer_infos = [luminance, weights].map(|buffer| {
+            [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE as u64)
+                .build()]
+        });
+        let buffer_writes = [1, 2].map(|binding| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(binding)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_infos[binding as usize - 1])
+        });
+
+        device.update_descriptor_sets(
+            &[image_write, buffer_writes[0], buffer_writes[1]],
+            &[] as &[vk::CopyDescriptorSet],
+        );
+
+        data.metering.push(Metering {
+            image,
+            image_memory,
+            image_view,
+            luminance,
+            luminance_memory,
+            weights,
+            weights_memory,
+            luminance_sum: create_reduction(instance, device, data, luminance, count).unwrap(),
+            weight_sum: create_reduction(instance, device, data, weights, count).unwrap(),
+            readback,
+            readback_memory,
+            set,
+            pending: None,
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,477 @@
+use anyhow::Result;
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    dynamic_rendering::Pass,
+    image::{create_image, create_image_view},
+    sampler::SamplerDesc,
+    shader::create_shader_module,
+    tonemap::HDR_FORMAT,
+    types::Vec2,
+};
+
+/// Blurs and dims the viewport behind modal UI such as a pause menu or a
+/// console. Toggled with `App::set_ui_focus`, which fades it in and out.
+///
+/// The presented image is blitted into a half-resolution mip chain and one
+/// level is drawn back over the viewport before the UI, so the cost does not
+/// depend on the blur radius. Needs a swapchain that can be blitted from,
+/// like auto-exposure; otherwise the viewport is left as is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FocusBlur {
+    /// The mip level sampled when fully faded in. Each level doubles the
+    /// blur radius.
+    pub strength: f32,
+    /// How much darker the viewport gets, from 0 to 1.
+    pub dim: f32,
+    /// Seconds to fade in or out.
+    pub duration: f32,
+}
+
+impl Default for FocusBlur {
+    fn default() -> Self {
+        Self {
+            strength: 4.0,
+            dim: 0.4,
+            duration: 0.25,
+        }
+    }
+}
+
+impl FocusBlur {
+    /// Moves `amount` towards 1 when `focused` and 0 otherwise.
+    pub(crate) fn advance(&self, amount: f32, focused: bool, elapsed: f32) -> f32 {
+        let step = if self.duration > 0.0 {
+            elapsed / self.duration
+        } else {
+            1.0
+        };
+
+        if focused {
+            (amount + step).min(1.0)
+        } else {
+            (amount - step).max(0.0)
+        }
+    }
+
+    /// The fade curve applied to `amount`.
+    pub(crate) fn ease(amount: f32) -> f32 {
+        amount * amount * (3.0 - 2.0 * amount)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct FocusBlurPushConstants {
+    /// One over the swapchain extent.
+    pub(crate) inverse_extent: Vec2,
+    pub(crate) lod: f32,
+    pub(crate) dim: f32,
+    /// Blend weight of the blurred viewport.
+    pub(crate) amount: f32,
+}
+
+/// A half-resolution copy of one swapchain image and its mips.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct FocusBlurTarget {
+    pub(crate) image: vk::Image,
+    pub(crate) image_memory: vk::DeviceMemory,
+    pub(crate) image_view: vk::ImageView,
+    pub(crate) mip_levels: u32,
+    pub(crate) set: vk::DescriptorSet,
+}
+
+impl FocusBlurTarget {
+    /// Copies the presented image into the mip chain and leaves it ready to
+    /// sample. Must be recorded outside a render pass, after the swapchain
+    /// image was last written.
+    pub(crate) unsafe fn record(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        swapchain_image: vk::Image,
+    ) {
+        let level = |base_mip_level, level_count| {
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(base_mip_level)
+                .level_count(level_count)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build()
+        };
+
+        let source_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(swapchain_image)
+            .subresource_range(level(0, 1))
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+        let destination_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(level(0, self.mip_levels))
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+
+        // Metering may have blitted from the swapchain image already.
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[source_barrier, destination_barrier],
+        );
+
+        let extent = data.swapchain_extent;
+        let size = |level: u32| vk::Offset3D {
+            x: (extent.width as i32 >> (level + 1)).max(1),
+            y: (extent.height as i32 >> (level + 1)).max(1),
+            z: 1,
+        };
+        let layers = |mip_level| {
+            vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(mip_level)
+                .base_array_layer(0)
+                .layer_count(1)
+                .build()
+        };
+
+        let blit = vk::ImageBlit::builder()
+            .src_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: extent.width as i32,
+                    y: extent.height as i32,
+                    z: 1,
+                },
+            ])
+            .src_subresource(layers(0))
+            .dst_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, size(0)])
+            .dst_subresource(layers(0));
+
+        device.cmd_blit_image(
+            command_buffer,
+            swapchain_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+
+        // Each level is read once it has been written, then sampled.
+        for i in 1..self.mip_levels {
+            let barrier = vk::ImageMemoryBarrier::builder()
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.image)
+                .subresource_range(level(i - 1, 1))
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[] as &[vk::MemoryBarrier],
+                &[] as &[vk::BufferMemoryBarrier],
+                &[barrier],
+            );
+
+            let blit = vk::ImageBlit::builder()
+                .src_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, size(i - 1)])
+                .src_subresource(layers(i - 1))
+                .dst_offsets([vk::Offset3D { x: 0, y: 0, z: 0 }, size(i)])
+                .dst_subresource(layers(i));
+
+            device.cmd_blit_image(
+                command_buffer,
+                self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        let source_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(swapchain_image)
+            .subresource_range(level(0, 1))
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::empty());
+
+        let read_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(level(0, self.mip_levels - 1))
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        let last_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(level(self.mip_levels - 1, 1))
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+        let barriers = if self.mip_levels > 1 {
+            vec![source_barrier, read_barrier, last_barrier]
+        } else {
+            vec![source_barrier, last_barrier]
+        };
+
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &barriers,
+        );
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.destroy_image_view(self.image_view, None);
+        free_memory(device, self.image_memory);
+        device.destroy_image(self.image, None);
+    }
+}
+
+pub(crate) unsafe fn create_focus_blur_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.focus_blur_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// A full-screen triangle in the overlay pass that blends the blurred
+/// viewport over the presented one.
+pub(crate) unsafe fn create_focus_blur_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let vert = include_bytes!("../../shaders/tonemap_vert.spv");
+    let frag = include_bytes!("../../shaders/focus_blur_frag.spv");
+
+    let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+    let frag_shader_module = create_shader_module(device, &frag[..]).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<FocusBlurPushConstants>() as u32);
+
+    let set_layouts = &[data.focus_blur_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.focus_blur_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let formats = Pass::Overlay.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.focus_blur_pipeline_layout)
+        .render_pass(data.overlay_render_pass)
+        .subpass(0);
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.focus_blur_pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(())
+}
+
+/// Creates one `FocusBlurTarget` per swapchain image. Does nothing if the
+/// swapchain cannot be blitted from.
+pub(crate) unsafe fn create_focus_blur_targets(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.focus_blur_targets.clear();
+    if !data.metering_supported {
+        return Ok(());
+    }
+
+    let images = data.swapchain_images.len() as u32;
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(images);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(images);
+    data.focus_blur_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = vec![data.focus_blur_set_layout; images as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.focus_blur_descriptor_pool)
+        .set_layouts(&layouts);
+    let sets = device.allocate_descriptor_sets(&info).unwrap();
+
+    let sampler = data.samplers.get(device, SamplerDesc::clamped());
+    let width = (data.swapchain_extent.width / 2).max(1);
+    let height = (data.swapchain_extent.height / 2).max(1);
+    let mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
+
+    for set in sets {
+        let (image, image_memory) = create_image(
+            instance,
+            device,
+            data,
+            width,
+            height,
+            mip_levels,
+            1,
+            vk::SampleCountFlags::_1,
+            HDR_FORMAT,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::empty(),
+        )
+        .unwrap();
+        let image_view =
+            create_image_view(device, image, HDR_FORMAT, vk::ImageAspectFlags::COLOR, mip_levels).unwrap();
+
+        let image_info = vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image_view)
+            .sampler(sampler);
+        let image_infos = &[image_info];
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(image_infos);
+
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+        data.focus_blur_targets.push(FocusBlurTarget {
+            image,
+            image_memory,
+            image_view,
+            mip_levels,
+            set,
+        });
+    }
+
+    Ok(())
+}
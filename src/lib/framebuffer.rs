@@ -2,16 +2,48 @@ use anyhow::Result;
 
 use vulkanalia::prelude::v1_0::*;
 
-use crate::app::AppData;
+use crate::{app::AppData, tonemap::uses_resolve_attachment};
 
+/// Dynamic rendering names its attachments when recording instead, so no
+/// framebuffers are created for it.
 pub(crate) unsafe fn create_framebuffers(device: &Device, data: &mut AppData) -> Result<()> {
+  if data.dynamic_rendering {
+    data.framebuffers = vec![];
+    data.overlay_framebuffers = vec![];
+    return Ok(());
+  }
+
   data.framebuffers = data
       .swapchain_image_views
       .iter()
       .map(|i| {
-          let attachments = &[data.color_image_view, data.depth_image_view, *i];
+          let mut attachments = vec![data.color_image_view, data.depth_image_view];
+          if data.order_independent_transparency {
+            attachments.extend([data.oit_accum_image_view, data.oit_reveal_image_view]);
+          }
+          attachments.push(*i);
+          if uses_resolve_attachment(data) {
+            attachments.push(data.resolve_image_view);
+          }
+
           let create_info = vk::FramebufferCreateInfo::builder()
               .render_pass(data.render_pass)
+              .attachments(&attachments)
+              .width(data.swapchain_extent.width)
+              .height(data.swapchain_extent.height)
+              .layers(1);
+          device.create_framebuffer(&create_info, None)
+      })
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap();
+
+  data.overlay_framebuffers = data
+      .swapchain_image_views
+      .iter()
+      .map(|i| {
+          let attachments = &[*i];
+          let create_info = vk::FramebufferCreateInfo::builder()
+              .render_pass(data.overlay_render_pass)
               .attachments(attachments)
               .width(data.swapchain_extent.width)
               .height(data.swapchain_extent.height)
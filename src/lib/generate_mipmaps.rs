@@ -1,11 +1,14 @@
 use anyhow::{anyhow, Result};
+use std::ops::Range;
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
     app::AppData,
+    barrier::{cmd_barriers, ImageTransition},
     single_time_cmd::{begin_single_time_commands, end_single_time_commands},
 };
+#[allow(clippy::too_many_arguments)]
 pub(crate) unsafe fn generate_mipmaps(
     instance: &Instance,
     device: &Device,
@@ -15,6 +18,7 @@ pub(crate) unsafe fn generate_mipmaps(
     width: u32,
     height: u32,
     mip_levels: u32,
+    layers: Range<u32>,
 ) -> Result<()> {
     if !instance
         .get_physical_device_format_properties(data.physical_device, format)
@@ -28,49 +32,28 @@ pub(crate) unsafe fn generate_mipmaps(
 
     let command_buffer = begin_single_time_commands(device, data).unwrap();
 
-    let subresource = vk::ImageSubresourceRange::builder()
-        .aspect_mask(vk::ImageAspectFlags::COLOR)
-        .base_array_layer(0)
-        .layer_count(1)
-        .level_count(1);
-
-    let mut barrier = vk::ImageMemoryBarrier::builder()
-        .image(image)
-        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-        .subresource_range(subresource);
+    let level = |i: u32, old_layout, new_layout| {
+        ImageTransition::new(image, old_layout, new_layout).levels(i..i + 1).layers(layers.clone())
+    };
 
     let mut mip_width = width;
     let mut mip_height = height;
 
     for i in 1..mip_levels {
-        barrier.subresource_range.base_mip_level = i - 1;
-        barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-        barrier.new_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-        barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-        barrier.dst_access_mask = vk::AccessFlags::TRANSFER_READ;
-
-        device.cmd_pipeline_barrier(
-            command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[] as &[vk::MemoryBarrier],
-            &[] as &[vk::BufferMemoryBarrier],
-            &[barrier],
-        );
+        let to_src = level(i - 1, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        cmd_barriers(device, data, command_buffer, &[to_src], &[]);
 
         let src_subresource = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .mip_level(i - 1)
-            .base_array_layer(0)
-            .layer_count(1);
+            .base_array_layer(layers.start)
+            .layer_count(layers.len() as u32);
 
         let dst_subresource = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .mip_level(i)
-            .base_array_layer(0)
-            .layer_count(1);
+            .base_array_layer(layers.start)
+            .layer_count(layers.len() as u32);
 
         let blit = vk::ImageBlit::builder()
             .src_offsets([
@@ -102,20 +85,8 @@ pub(crate) unsafe fn generate_mipmaps(
             vk::Filter::LINEAR,
         );
 
-        barrier.old_layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-        barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-        barrier.src_access_mask = vk::AccessFlags::TRANSFER_READ;
-        barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-        device.cmd_pipeline_barrier(
-            command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
-            vk::DependencyFlags::empty(),
-            &[] as &[vk::MemoryBarrier],
-            &[] as &[vk::BufferMemoryBarrier],
-            &[barrier],
-        );
+        let to_read = level(i - 1, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        cmd_barriers(device, data, command_buffer, &[to_read], &[]);
 
         if mip_width > 1 {
             mip_width /= 2;
@@ -126,21 +97,8 @@ pub(crate) unsafe fn generate_mipmaps(
         }
     }
 
-    barrier.subresource_range.base_mip_level = mip_levels - 1;
-    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
-    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
-
-    device.cmd_pipeline_barrier(
-        command_buffer,
-        vk::PipelineStageFlags::TRANSFER,
-        vk::PipelineStageFlags::FRAGMENT_SHADER,
-        vk::DependencyFlags::empty(),
-        &[] as &[vk::MemoryBarrier],
-        &[] as &[vk::BufferMemoryBarrier],
-        &[barrier],
-    );
+    let to_read = level(mip_levels - 1, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    cmd_barriers(device, data, command_buffer, &[to_read], &[]);
 
     end_single_time_commands(device, data, command_buffer).unwrap();
 
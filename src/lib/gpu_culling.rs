@@ -0,0 +1,454 @@
+use anyhow::Result;
+use std::mem::size_of;
+
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::KhrDrawIndirectCountExtension,
+};
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    debug_view::DebugView,
+    device_features::DeviceFeature,
+    dynamic_buffer::{destroy_dynamic_buffer, update_dynamic_buffer, DynamicBuffer},
+    lod::MAX_LODS,
+    pipeline::{create_scene_pipeline, scene_reflection, FragmentPushConstants, SceneGeometry, ScenePipelineDesc, SceneVariant},
+    scene_recorder::bind_scene_descriptors_with,
+    shader::create_shader_module,
+    types::{Mat4, Vec4},
+    vertex_buffer::create_buffer,
+    viewport::record_viewports,
+    visibility::Frustum,
+};
+
+/// Threads per culling workgroup, as in `cull.comp`.
+const WORKGROUP_SIZE: u32 = 64;
+
+const INDIRECT_VERT: &[u8] = include_bytes!("../../shaders/vert_indirect.spv");
+const INDIRECT_FRAG: &[u8] = include_bytes!("../../shaders/frag_indirect.spv");
+const INDIRECT_FRAG_OIT: &[u8] = include_bytes!("../../shaders/frag_indirect_oit.spv");
+
+/// An entity as the culling pass and the indirect scene shaders read it:
+/// what `SceneRecorder` otherwise pushes per draw, and its bounds.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct GpuObject {
+    pub(crate) model: Mat4,
+    /// World space center and radius.
+    pub(crate) sphere: Vec4,
+    /// The camera layer in the low bit and the joint offset in the rest, as
+    /// the first instance of a CPU draw.
+    pub(crate) instance: u32,
+    pub(crate) texture: u32,
+    pub(crate) opacity: f32,
+    /// The level of detail drawn if the object passes culling.
+    pub(crate) lod: u32,
+    pub(crate) ambient: [Vec4; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct CullPushConstants {
+    planes: [Vec4; 6],
+    object_count: u32,
+    /// Where each level of detail starts in the index buffer, and where the
+    /// last one ends.
+    lod_indices: [u32; MAX_LODS + 1],
+}
+
+/// Frustum culls a buffer of `GpuObject`s in a compute shader, which writes
+/// a `vk::DrawIndexedIndirectCommand` per visible object and its count, so
+/// drawing them costs the CPU one call however many there are. One per
+/// swapchain image.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct GpuCulling {
+    objects: DynamicBuffer,
+    /// How many objects `draws` and `draw_objects` have room for.
+    capacity: u32,
+    object_count: u32,
+    draws: vk::Buffer,
+    draws_memory: vk::DeviceMemory,
+    /// The object each draw is of.
+    draw_objects: vk::Buffer,
+    draw_objects_memory: vk::DeviceMemory,
+    draw_count: vk::Buffer,
+    draw_count_memory: vk::DeviceMemory,
+    set: vk::DescriptorSet,
+}
+
+impl GpuCulling {
+    /// Uploads this frame's objects, growing the buffers to fit.
+    pub(crate) unsafe fn upload(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        objects: &[GpuObject],
+    ) -> Result<()> {
+        self.object_count = objects.len() as u32;
+        if objects.is_empty() {
+            return Ok(());
+        }
+
+        let previous = self.objects.buffer;
+        update_dynamic_buffer(
+            instance,
+            device,
+            data,
+            &mut self.objects,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            objects,
+        )
+        .unwrap();
+
+        let grown = self.object_count > self.capacity;
+        if grown {
+            self.destroy_draws(device);
+            self.capacity = self.object_count.next_power_of_two();
+            (self.draws, self.draws_memory) = create_buffer(
+                instance,
+                device,
+                data,
+                size_of::<vk::DrawIndexedIndirectCommand>() as u64 * self.capacity as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+            (self.draw_objects, self.draw_objects_memory) = create_buffer(
+                instance,
+                device,
+                data,
+                size_of::<u32>() as u64 * self.capacity as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+        }
+
+        if grown || previous != self.objects.buffer {
+            let buffers = [self.objects.buffer, self.draw_objects, self.draws, self.draw_count];
+            let infos = buffers.map(|buffer| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE as u64)
+                    .build()]
+            });
+            let writes = infos
+                .iter()
+                .enumerate()
+                .map(|(binding, info)| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(self.set)
+                        .dst_binding(binding as u32)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(info)
+                })
+                .collect::<Vec<_>>();
+            device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+        }
+
+        Ok(())
+    }
+
+    /// Records culling the uploaded objects against `frustum`, to run before
+    /// the scene pass draws the result.
+    pub(crate) unsafe fn record_cull(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        frustum: &Frustum,
+    ) {
+        if self.object_count == 0 {
+            return;
+        }
+
+        // The last draws from these buffers must be done before they are
+        // cleared and overwritten.
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::SHADER_WRITE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER,
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        device.cmd_fill_buffer(command_buffer, self.draw_count, 0, size_of::<u32>() as u64, 0);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        let push_constants = CullPushConstants {
+            planes: frustum.planes(),
+            object_count: self.object_count,
+            lod_indices: lod_indices(data),
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const CullPushConstants as *const u8,
+            size_of::<CullPushConstants>(),
+        );
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.cull_pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            data.cull_pipeline_layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            data.cull_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            push_constants_bytes,
+        );
+        device.cmd_dispatch(command_buffer, self.object_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::SHADER_READ);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+    }
+
+    /// Records drawing whatever the last cull kept, in a scene pass
+    /// secondary command buffer.
+    pub(crate) unsafe fn record_draw(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        debug_view: DebugView,
+    ) {
+        if self.object_count == 0 {
+            return;
+        }
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.indirect_pipeline);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.vertex_buffer], &[0]);
+        device.cmd_bind_index_buffer(command_buffer, data.index_buffer, 0, data.index_type);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            data.indirect_pipeline_layout,
+            1,
+            &[data.texture_descriptor_set, self.set],
+            &[],
+        );
+
+        // Only the debug view is read from the push constants, the rest
+        // comes from each draw's object.
+        let fragment_push_constants = FragmentPushConstants {
+            opacity: 1.0,
+            debug_view: debug_view as u32,
+            texture: 0,
+            material: 0,
+            ambient: [Vec4::new(0.0, 0.0, 0.0, 0.0); 3],
+        };
+        data.fragment_push_constants.push(
+            device,
+            command_buffer,
+            data.indirect_pipeline_layout,
+            &fragment_push_constants,
+        );
+
+        record_viewports(device, data, command_buffer, |viewport| {
+            // With this layout, so the sets after it stay bound.
+            bind_scene_descriptors_with(
+                device,
+                data,
+                data.indirect_pipeline_layout,
+                command_buffer,
+                image_index,
+                viewport,
+            );
+            device.cmd_draw_indexed_indirect_count_khr(
+                command_buffer,
+                self.draws,
+                0,
+                self.draw_count,
+                0,
+                self.object_count,
+                size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+            );
+        });
+    }
+
+    unsafe fn destroy_draws(&mut self, device: &Device) {
+        device.destroy_buffer(self.draws, None);
+        free_memory(device, self.draws_memory);
+        device.destroy_buffer(self.draw_objects, None);
+        free_memory(device, self.draw_objects_memory);
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        self.destroy_draws(device);
+        device.destroy_buffer(self.draw_count, None);
+        free_memory(device, self.draw_count_memory);
+        destroy_dynamic_buffer(device, &mut self.objects);
+    }
+}
+
+/// `CullPushConstants::lod_indices` of the model. Levels it does not have
+/// are empty.
+fn lod_indices(data: &AppData) -> [u32; MAX_LODS + 1] {
+    let mut indices = [0; MAX_LODS + 1];
+    for (level, index) in indices.iter_mut().enumerate() {
+        *index = match data.lods.get(level) {
+            Some(lod) => lod.first_index,
+            None => data.lods.last().map_or(0, |lod| lod.first_index + lod.index_count),
+        };
+    }
+    indices
+}
+
+/// Creates the culling compute pipeline and the descriptor set layout the
+/// indirect scene shaders share with it.
+pub(crate) unsafe fn create_cull_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let bindings = [0, 1, 2, 3].map(|binding| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::COMPUTE)
+    });
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    data.cull_set_layout = data.set_layouts.get(device, &info).unwrap();
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<CullPushConstants>() as u32);
+
+    let set_layouts = &[data.cull_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.cull_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let comp = include_bytes!("../../shaders/cull_comp.spv");
+    let shader_module = create_shader_module(device, &comp[..]).unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(data.cull_pipeline_layout);
+
+    data.cull_pipeline = device
+        .create_compute_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(shader_module, None);
+    Ok(())
+}
+
+/// Creates the scene pipeline variant that draws culled objects. Its layout
+/// is the scene layout with the culling set appended.
+pub(crate) unsafe fn create_indirect_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let reflection = scene_reflection();
+
+    let set_layouts = &[data.descriptor_set_layout, data.texture_set_layout, data.cull_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(&reflection.push_constant_ranges);
+
+    data.indirect_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let desc = ScenePipelineDesc {
+        layout: data.indirect_pipeline_layout,
+        geometry: SceneGeometry::Vertex(INDIRECT_VERT),
+        frag: if data.order_independent_transparency { INDIRECT_FRAG_OIT } else { INDIRECT_FRAG },
+        ..ScenePipelineDesc::new(data)
+    };
+    data.indirect_pipeline = create_scene_pipeline(device, desc, SceneVariant::Shaded).unwrap();
+    Ok(())
+}
+
+/// Creates one `GpuCulling` per swapchain image. Does nothing without
+/// `VK_KHR_draw_indirect_count` and `multiDrawIndirect`.
+pub(crate) unsafe fn create_gpu_culling(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.gpu_culling.clear();
+    if !data.draw_indirect_count || !data.device_features.contains(DeviceFeature::MultiDrawIndirect) {
+        return Ok(());
+    }
+
+    let images = data.swapchain_images.len() as u32;
+    let pool_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(images * 4);
+
+    let pool_sizes = &[pool_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(images);
+    data.gpu_culling_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = vec![data.cull_set_layout; images as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.gpu_culling_descriptor_pool)
+        .set_layouts(&layouts);
+    let sets = device.allocate_descriptor_sets(&info).unwrap();
+
+    for set in sets {
+        let (draw_count, draw_count_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<u32>() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .unwrap();
+
+        data.gpu_culling.push(GpuCulling {
+            draw_count,
+            draw_count_memory,
+            set,
+            ..Default::default()
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,436 @@
+use anyhow::Result;
+use cgmath::{vec3, vec4};
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    scene_recorder::bind_scene_descriptors,
+    shader::create_shader_module,
+    types::{Vec3, Vec4},
+    vertex_buffer::create_buffer,
+    viewport::record_viewports,
+};
+
+/// Threads per simulation workgroup, as in `gpu_particles.comp`.
+const WORKGROUP_SIZE: u32 = 256;
+
+/// A particle as the simulation stores it and the draw reads it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct GpuParticle {
+    pub(crate) position: Vec3,
+    pub(crate) age: f32,
+    pub(crate) velocity: Vec3,
+    pub(crate) lifetime: f32,
+}
+
+impl GpuParticle {
+    pub(crate) fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<GpuParticle>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    pub(crate) fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
+        let position = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let age = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32_SFLOAT)
+            .offset(size_of::<Vec3>() as u32)
+            .build();
+        let lifetime = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32_SFLOAT)
+            .offset((size_of::<Vec3>() * 2 + size_of::<f32>()) as u32)
+            .build();
+
+        [position, age, lifetime]
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SimulationPushConstants {
+    position: Vec3,
+    elapsed: f32,
+    velocity: Vec3,
+    lifetime: f32,
+    velocity_spread: Vec3,
+    emit: u32,
+    acceleration: Vec3,
+    seed: u32,
+    capacity: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DrawPushConstants {
+    start_color: Vec4,
+    end_color: Vec4,
+    start_size: f32,
+    end_size: f32,
+}
+
+/// A particle emitter simulated in a compute shader, for far more particles
+/// than `ParticleEmitter` can move on the CPU. Particles are not sorted, so
+/// overlapping translucent ones may blend in the wrong order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GpuParticleEmitter {
+    pub position: Vec3,
+    /// Particles spawned per second.
+    pub spawn_rate: f32,
+    /// Seconds each particle lives.
+    pub lifetime: f32,
+    pub velocity: Vec3,
+    /// How far each component of the initial velocity varies either way.
+    pub velocity_spread: Vec3,
+    pub acceleration: Vec3,
+    pub start_color: Vec4,
+    pub end_color: Vec4,
+    pub start_size: f32,
+    pub end_size: f32,
+    /// The most particles alive at once. Changing it recreates the
+    /// simulation, dropping every live particle.
+    pub capacity: u32,
+}
+
+impl Default for GpuParticleEmitter {
+    fn default() -> Self {
+        Self {
+            position: vec3(0.0, 0.0, 0.0),
+            spawn_rate: 20_000.0,
+            lifetime: 5.0,
+            velocity: vec3(0.0, 0.0, 1.0),
+            velocity_spread: vec3(0.5, 0.5, 0.5),
+            acceleration: vec3(0.0, 0.0, -0.5),
+            start_color: vec4(1.0, 0.6, 0.2, 1.0),
+            end_color: vec4(0.2, 0.2, 0.2, 0.0),
+            start_size: 0.01,
+            end_size: 0.02,
+            capacity: 262_144,
+        }
+    }
+}
+
+/// The state of a `GpuParticleEmitter`: two particle buffers, each with its
+/// indirect draw arguments, simulated from one into the other in turns.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GpuParticles {
+    pub(crate) capacity: u32,
+    particles: [vk::Buffer; 2],
+    particles_memory: [vk::DeviceMemory; 2],
+    /// A `vk::DrawIndirectCommand` per particle buffer. The instance count
+    /// is how many particles are alive and is the simulation's atomic
+    /// counter.
+    args: [vk::Buffer; 2],
+    args_memory: [vk::DeviceMemory; 2],
+    descriptor_pool: vk::DescriptorPool,
+    /// Set `i` simulates from buffer `i` into the other.
+    sets: [vk::DescriptorSet; 2],
+    /// The buffer the last recorded simulation wrote.
+    current: usize,
+    /// Whether the arguments still need clearing.
+    fresh: bool,
+    /// Fractional particles owed by the spawn rate.
+    pending: f32,
+    seed: u32,
+}
+
+impl GpuParticles {
+    pub(crate) unsafe fn new(instance: &Instance, device: &Device, data: &AppData, capacity: u32) -> Result<Self> {
+        let mut particles = Self {
+            capacity,
+            fresh: true,
+            ..Default::default()
+        };
+
+        for i in 0..2 {
+            (particles.particles[i], particles.particles_memory[i]) = create_buffer(
+                instance,
+                device,
+                data,
+                size_of::<GpuParticle>() as u64 * capacity.max(1) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+            (particles.args[i], particles.args_memory[i]) = create_buffer(
+                instance,
+                device,
+                data,
+                size_of::<vk::DrawIndirectCommand>() as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER
+                    | vk::BufferUsageFlags::INDIRECT_BUFFER
+                    | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )
+            .unwrap();
+        }
+
+        let pool_size = vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(8);
+        let pool_sizes = &[pool_size];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(2);
+        particles.descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+        let layouts = [data.gpu_particle_set_layout; 2];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(particles.descriptor_pool)
+            .set_layouts(&layouts);
+        let sets = device.allocate_descriptor_sets(&info).unwrap();
+        particles.sets = [sets[0], sets[1]];
+
+        for (i, set) in particles.sets.iter().enumerate() {
+            let buffers = [
+                particles.particles[i],
+                particles.args[i],
+                particles.particles[1 - i],
+                particles.args[1 - i],
+            ];
+            let infos = buffers.map(|buffer| {
+                [vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE as u64)
+                    .build()]
+            });
+            let writes = infos
+                .iter()
+                .enumerate()
+                .map(|(binding, info)| {
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(*set)
+                        .dst_binding(binding as u32)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(info)
+                })
+                .collect::<Vec<_>>();
+            device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+        }
+
+        Ok(particles)
+    }
+
+    /// Records one simulation step of `elapsed` seconds, to run before the
+    /// scene pass draws the result.
+    pub(crate) unsafe fn record_simulation(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        emitter: &GpuParticleEmitter,
+        elapsed: f32,
+    ) {
+        let source = self.current;
+        let destination = 1 - source;
+
+        let empty = vk::DrawIndirectCommand {
+            vertex_count: 6,
+            instance_count: 0,
+            first_vertex: 0,
+            first_instance: 0,
+        };
+        let empty_bytes = std::slice::from_raw_parts(
+            &empty as *const vk::DrawIndirectCommand as *const u8,
+            size_of::<vk::DrawIndirectCommand>(),
+        );
+
+        // The last frames' simulation and draws must be done with the buffers
+        // before they are cleared and overwritten.
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::DRAW_INDIRECT
+                | vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        if self.fresh {
+            device.cmd_update_buffer(command_buffer, self.args[source], 0, empty_bytes);
+            self.fresh = false;
+        }
+        device.cmd_update_buffer(command_buffer, self.args[destination], 0, empty_bytes);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        self.pending += emitter.spawn_rate.max(0.0) * elapsed;
+        let emit = self.pending.min(self.capacity as f32) as u32;
+        self.pending -= emit as f32;
+        self.seed = self.seed.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+
+        let push_constants = SimulationPushConstants {
+            position: emitter.position,
+            elapsed,
+            velocity: emitter.velocity,
+            lifetime: emitter.lifetime,
+            velocity_spread: emitter.velocity_spread,
+            emit,
+            acceleration: emitter.acceleration,
+            seed: self.seed,
+            capacity: self.capacity,
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const SimulationPushConstants as *const u8,
+            size_of::<SimulationPushConstants>(),
+        );
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.gpu_particle_pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            data.gpu_particle_pipeline_layout,
+            0,
+            &[self.sets[source]],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            data.gpu_particle_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            push_constants_bytes,
+        );
+        device.cmd_dispatch(command_buffer, self.capacity.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        self.current = destination;
+    }
+
+    /// Records drawing the particles the last simulation step wrote, in a
+    /// scene pass secondary command buffer.
+    pub(crate) unsafe fn record_draw(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        image_index: usize,
+        emitter: &GpuParticleEmitter,
+    ) {
+        let push_constants = DrawPushConstants {
+            start_color: emitter.start_color,
+            end_color: emitter.end_color,
+            start_size: emitter.start_size,
+            end_size: emitter.end_size,
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const DrawPushConstants as *const u8,
+            size_of::<DrawPushConstants>(),
+        );
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.gpu_particle_draw_pipeline);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.particles[self.current]], &[0]);
+        device.cmd_push_constants(
+            command_buffer,
+            data.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            push_constants_bytes,
+        );
+        record_viewports(device, data, command_buffer, |viewport| {
+            bind_scene_descriptors(device, data, command_buffer, image_index, viewport);
+            device.cmd_draw_indirect(command_buffer, self.args[self.current], 0, 1, 0);
+        });
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        for i in 0..2 {
+            device.destroy_buffer(self.args[i], None);
+            free_memory(device, self.args_memory[i]);
+            device.destroy_buffer(self.particles[i], None);
+            free_memory(device, self.particles_memory[i]);
+        }
+    }
+}
+
+pub(crate) unsafe fn create_gpu_particle_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let bindings = [0, 1, 2, 3].map(|binding| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+    });
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    data.gpu_particle_set_layout = data.set_layouts.get(device, &info).unwrap();
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<SimulationPushConstants>() as u32);
+
+    let set_layouts = &[data.gpu_particle_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.gpu_particle_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let comp = include_bytes!("../../shaders/gpu_particles_comp.spv");
+    let shader_module = create_shader_module(device, &comp[..]).unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(data.gpu_particle_pipeline_layout);
+
+    data.gpu_particle_pipeline = device
+        .create_compute_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(shader_module, None);
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use exr::prelude::{f16, read_first_rgba_layer_from_file};
+use std::path::Path;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::texture::{check_texture_size, TextureData};
+
+/// High dynamic range images are uploaded as half floats, which every device
+/// can sample, filter and blit.
+pub(crate) const HDR_TEXTURE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Parses a Radiance RGBE (`.hdr`) image stored top to bottom, with either
+/// flat or run-length encoded scanlines.
+pub(crate) fn load_radiance(bytes: &[u8]) -> Result<TextureData> {
+    if !bytes.starts_with(b"#?") {
+        return Err(anyhow!("Not a Radiance HDR file."));
+    }
+
+    let mut lines = bytes.split(|b| *b == b'\n');
+    let mut offset = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        offset += line.len() + 1;
+        Some(String::from_utf8_lossy(line).trim().to_string())
+    };
+
+    loop {
+        let line = next_line().ok_or_else(|| anyhow!("Truncated Radiance HDR header."))?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(anyhow!("Unsupported Radiance HDR format `{}`.", format));
+            }
+        }
+    }
+
+    let resolution = next_line().ok_or_else(|| anyhow!("Missing Radiance HDR resolution."))?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (height.parse::<u32>()?, width.parse::<u32>()?),
+        _ => return Err(anyhow!("Unsupported Radiance HDR orientation `{}`.", resolution)),
+    };
+
+    check_texture_size(width, height, 1)?;
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|texels| texels.checked_mul(8))
+        .ok_or_else(|| anyhow!("Unsupported Radiance HDR size {}x{}.", width, height))?;
+
+    let mut data = bytes.get(offset..).unwrap_or_default();
+    let mut pixels = Vec::with_capacity(size);
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for y in 0..height {
+        data = read_scanline(data, &mut scanline)
+            .ok_or_else(|| anyhow!("Radiance HDR scanline {} is truncated.", y))?;
+        for rgbe in &scanline {
+            let scale = if rgbe[3] == 0 {
+                0.0
+            } else {
+                2f32.powi(rgbe[3] as i32 - (128 + 8))
+            };
+            let rgba = [rgbe[0] as f32 * scale, rgbe[1] as f32 * scale, rgbe[2] as f32 * scale, 1.0];
+            pixels.extend(rgba.iter().flat_map(|c| f16::from_f32(*c).to_le_bytes()));
+        }
+    }
+
+    Ok(TextureData {
+        width,
+        height,
+        format: HDR_TEXTURE_FORMAT,
+        levels: vec![pixels],
+    })
+}
+
+/// Decodes one scanline into `scanline`, returning the remaining bytes.
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Option<&'a [u8]> {
+    let width = scanline.len();
+    let run_length_encoded = (8..0x8000).contains(&width)
+        && data.len() >= 4
+        && data[0] == 2
+        && data[1] == 2
+        && ((data[2] as usize) << 8 | data[3] as usize) == width;
+
+    if !run_length_encoded {
+        for (texel, rgbe) in scanline.iter_mut().zip(data.chunks_exact(4)) {
+            texel.copy_from_slice(rgbe);
+        }
+        return data.get(width * 4..);
+    }
+
+    // Each component is stored separately as runs and literal spans.
+    let mut data = &data[4..];
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let (&count, rest) = data.split_first()?;
+            if count > 128 {
+                let count = (count - 128) as usize;
+                let value = *rest.first()?;
+                scanline.get_mut(x..x + count)?.iter_mut().for_each(|t| t[component] = value);
+                data = &rest[1..];
+                x += count;
+            } else {
+                let count = count as usize;
+                let values = rest.get(..count)?;
+                for (texel, value) in scanline.get_mut(x..x + count)?.iter_mut().zip(values) {
+                    texel[component] = *value;
+                }
+                data = &rest[count..];
+                x += count;
+            }
+        }
+    }
+
+    Some(data)
+}
+
+/// Reads the first RGB(A) layer of an OpenEXR image. Images without alpha
+/// are opaque.
+pub(crate) fn load_exr(path: &Path) -> Result<TextureData> {
+    let image = read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| (resolution.width(), vec![0u8; resolution.area() * 8]),
+        |(width, pixels), position, (r, g, b, a): (f16, f16, f16, f16)| {
+            let offset = (position.y() * *width + position.x()) * 8;
+            for (i, channel) in [r, g, b, a].iter().enumerate() {
+                pixels[offset + i * 2..offset + i * 2 + 2].copy_from_slice(&channel.to_le_bytes());
+            }
+        },
+    )
+    .map_err(|e| anyhow!("Failed to read OpenEXR image: {}", e))?;
+
+    let size = image.layer_data.size;
+    let (_, pixels) = image.layer_data.channel_data.pixels;
+    Ok(TextureData {
+        width: size.width() as u32,
+        height: size.height() as u32,
+        format: HDR_TEXTURE_FORMAT,
+        levels: vec![pixels],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn radiance(resolution: &str, pixels: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes();
+        bytes.extend(pixels);
+        bytes
+    }
+
+    #[test]
+    fn flat_scanlines_are_decoded() {
+        // 1.0 and 0.5 in RGBE.
+        let texture = load_radiance(&radiance("-Y 1 +X 2", &[128, 128, 128, 129, 128, 128, 128, 128])).unwrap();
+        let texels = texture.levels[0]
+            .chunks_exact(2)
+            .map(|c| f16::from_le_bytes([c[0], c[1]]).to_f32())
+            .collect::<Vec<_>>();
+        assert_eq!(texels, [1.0, 1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn oversized_resolutions_are_rejected() {
+        assert!(load_radiance(&radiance("-Y 4294967295 +X 4294967295", &[])).is_err());
+        assert!(load_radiance(&radiance("-Y 0 +X 16", &[])).is_err());
+    }
+}
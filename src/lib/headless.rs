@@ -0,0 +1,209 @@
+use anyhow::{anyhow, Result};
+use log::*;
+use std::ptr::copy_nonoverlapping as memcpy;
+
+use vulkanalia::{
+    loader::{LibloadingLoader, LIBRARY},
+    prelude::v1_0::*,
+    vk::ExtDebugUtilsExtension,
+};
+
+use crate::{
+    allocations::{free_memory, take_leaks},
+    app::{AppData, VALIDATION_ENABLED},
+    command_buffer::create_command_pool,
+    instance::create_instance,
+    logical_device::create_logical_device,
+    physical_device::{pick_physical_device, GpuSelector},
+    reduction::create_reduction_pipelines,
+    sampler::SamplerCache,
+    self_test::{run_self_test, SelfTestReport},
+    shader::create_shader_module,
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    vertex_buffer::create_buffer,
+};
+
+/// A device created without a window, surface or swapchain, for running
+/// compute shaders, offline baking and `--self-test` on machines with no
+/// display. Devices are picked as for `App`, `GPU_OVERRIDE_VAR` included,
+/// except that presenting is not needed and a queue family that can compute
+/// is enough.
+///
+/// # Safety
+///
+/// As with `App`, its unsafe methods use the device it owns: call them from
+/// one thread at a time and never after `destroy`.
+pub struct Headless {
+    _entry: Entry,
+    instance: Instance,
+    device: Device,
+    data: AppData,
+}
+
+#[allow(clippy::missing_safety_doc)] // See `Headless`.
+impl Headless {
+    pub unsafe fn create() -> Result<Self> {
+        let loader = LibloadingLoader::new(LIBRARY)?;
+        let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
+        let mut data = AppData { headless: true, ..Default::default() };
+        let instance = create_instance(None, &entry, &mut data)?;
+        data.gpu_selector = GpuSelector::from_env();
+        pick_physical_device(&instance, &mut data)?;
+        data.samplers = SamplerCache::new(&instance, &data);
+        let device = create_logical_device(&instance, &mut data)?;
+        data.command_pool = create_command_pool(&instance, &device, &mut data)?;
+        create_reduction_pipelines(&device, &mut data)?;
+        Ok(Self { _entry: entry, instance, device, data })
+    }
+
+    /// The name of the device work runs on.
+    pub fn device_name(&self) -> String {
+        let properties = unsafe { self.instance.get_physical_device_properties(self.data.physical_device) };
+        properties.device_name.to_string()
+    }
+
+    /// Runs the compute shader `spirv` over `groups` workgroups and waits for
+    /// it. Each of `buffers` is bound as a storage buffer at the binding of
+    /// its index in set 0, and holds what the shader left in it afterwards.
+    pub unsafe fn dispatch(&mut self, spirv: &[u8], buffers: &mut [&mut [u8]], groups: [u32; 3]) -> Result<()> {
+        let (device, data) = (&self.device, &self.data);
+
+        let storage = buffers
+            .iter()
+            .map(|bytes| {
+                // Empty buffers cannot be created, so they get one word.
+                let size = bytes.len().max(4) as u64;
+                let (buffer, memory) = create_buffer(
+                    &self.instance,
+                    device,
+                    data,
+                    size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                )?;
+                let mapped = device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())?;
+                memcpy(bytes.as_ptr(), mapped.cast(), bytes.len());
+                device.unmap_memory(memory);
+                Ok((buffer, memory, size))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let bindings = (0..buffers.len() as u32)
+            .map(|i| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(i)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            })
+            .collect::<Vec<_>>();
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+        let set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+        let pool_sizes = [vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(buffers.len().max(1) as u32)];
+        let info = vk::DescriptorPoolCreateInfo::builder().pool_sizes(&pool_sizes).max_sets(1);
+        let descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+        let set_layouts = &[set_layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(set_layouts);
+        let set = device.allocate_descriptor_sets(&info)?[0];
+
+        let buffer_infos = storage
+            .iter()
+            .map(|(buffer, _, size)| [vk::DescriptorBufferInfo::builder().buffer(*buffer).offset(0).range(*size)])
+            .collect::<Vec<_>>();
+        let writes = buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(i, info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(set)
+                    .dst_binding(i as u32)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(info)
+            })
+            .collect::<Vec<_>>();
+        device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+        let info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+        let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+        let module = create_shader_module(device, spirv)?;
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(b"main\0");
+        let info = vk::ComputePipelineCreateInfo::builder().stage(stage).layout(pipeline_layout);
+        let pipeline = device.create_compute_pipelines(vk::PipelineCache::null(), &[info], None)?.0[0];
+
+        let command_buffer = begin_single_time_commands(device, data)?;
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline_layout,
+            0,
+            &[set],
+            &[],
+        );
+        device.cmd_dispatch(command_buffer, groups[0], groups[1], groups[2]);
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+        end_single_time_commands(device, data, command_buffer)?;
+
+        for (bytes, (buffer, memory, size)) in buffers.iter_mut().zip(&storage) {
+            let mapped = device.map_memory(*memory, 0, *size, vk::MemoryMapFlags::empty())?;
+            memcpy(mapped.cast(), bytes.as_mut_ptr(), bytes.len());
+            device.unmap_memory(*memory);
+            device.destroy_buffer(*buffer, None);
+            free_memory(device, *memory);
+        }
+        device.destroy_pipeline(pipeline, None);
+        device.destroy_shader_module(module, None);
+        device.destroy_pipeline_layout(pipeline_layout, None);
+        device.destroy_descriptor_pool(descriptor_pool, None);
+        device.destroy_descriptor_set_layout(set_layout, None);
+        Ok(())
+    }
+
+    /// See `App::self_test`.
+    pub unsafe fn self_test(&mut self) -> SelfTestReport {
+        self.device.device_wait_idle().unwrap();
+        run_self_test(&self.instance, &self.device, &mut self.data)
+    }
+
+    pub unsafe fn destroy(&mut self) {
+        self.device.device_wait_idle().unwrap();
+
+        self.device.destroy_pipeline(self.data.scan_pipeline, None);
+        self.device.destroy_pipeline(self.data.reduce_pipeline, None);
+        self.device.destroy_pipeline_layout(self.data.reduction_pipeline_layout, None);
+        self.data.set_layouts.destroy(&self.device);
+        self.data.samplers.destroy(&self.device);
+        self.device.destroy_command_pool(self.data.command_pool, None);
+        let leaks = take_leaks(&self.device);
+        if !leaks.is_empty() {
+            warn!("Device memory was not freed, {}", leaks);
+        }
+        self.device.destroy_device(None);
+
+        if VALIDATION_ENABLED {
+            self.instance.destroy_debug_utils_messenger_ext(self.data.messenger, None);
+        }
+
+        self.instance.destroy_instance(None);
+    }
+}
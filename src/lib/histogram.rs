@@ -0,0 +1,412 @@
+use anyhow::Result;
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    exposure::AutoExposure,
+    sampler::SamplerDesc,
+    shader::{create_shader_module, SpecializationConstants},
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    tonemap::{resolves_in_shader, uses_resolve_attachment},
+    vertex_buffer::create_buffer,
+};
+
+const BINS: u64 = 256;
+
+/// `exposure_adapt.comp`'s `Exposure`: the exposure the tonemap pass
+/// applies, and the luminance it was adapted to.
+const STATE_SIZE: u64 = 8;
+
+/// Shared by the histogram and adapt shaders, which each read their own
+/// members.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct HistogramPushConstants {
+    mode: u32,
+    spot_radius: f32,
+    center: [f32; 2],
+    aspect: f32,
+    enabled: u32,
+    key: f32,
+    min_exposure: f32,
+    max_exposure: f32,
+    blend: f32,
+    low_percentile: f32,
+    high_percentile: f32,
+}
+
+impl HistogramPushConstants {
+    /// Moves `blend` of the way towards the target exposure.
+    pub(crate) fn new(data: &AppData, settings: &AutoExposure, blend: f32) -> Self {
+        let extent = data.swapchain_extent;
+        Self {
+            mode: settings.metering as u32,
+            spot_radius: settings.spot_radius,
+            center: settings.center.into(),
+            aspect: extent.width as f32 / extent.height as f32,
+            enabled: settings.enabled as u32,
+            key: settings.key,
+            min_exposure: settings.min_exposure,
+            max_exposure: settings.max_exposure,
+            blend,
+            low_percentile: settings.low_percentile,
+            high_percentile: settings.high_percentile,
+        }
+    }
+}
+
+/// A swapchain image's copy of the adapted exposure, for `App::exposure`.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct ExposureReadback {
+    pub(crate) buffer: vk::Buffer,
+    pub(crate) memory: vk::DeviceMemory,
+    /// Whether the last frame rendered to the image adapted the exposure.
+    pub(crate) pending: bool,
+}
+
+impl ExposureReadback {
+    /// The adapted exposure and the luminance it was adapted to. The frame
+    /// must have finished.
+    pub(crate) unsafe fn read(&self, device: &Device) -> (f32, f32) {
+        let memory = device
+            .map_memory(self.memory, 0, STATE_SIZE, vk::MemoryMapFlags::empty())
+            .unwrap();
+        let [exposure, luminance] = *memory.cast::<[f32; 2]>();
+        device.unmap_memory(self.memory);
+        (exposure, luminance)
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.destroy_buffer(self.buffer, None);
+        free_memory(device, self.memory);
+    }
+}
+
+/// The HDR color at binding 0, the bins at 1 and the adapted exposure at 2.
+pub(crate) unsafe fn create_histogram_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let image_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let buffer_bindings = [1, 2].map(|binding| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+    });
+
+    let bindings = &[image_binding, buffer_bindings[0], buffer_bindings[1]];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.histogram_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// The bins and the adapted exposure, which carries over from frame to
+/// frame and swapchain to swapchain. The exposure starts at 1.
+pub(crate) unsafe fn create_histogram_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let (bins, bins_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        BINS * 4,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )
+    .unwrap();
+    data.histogram_bins = bins;
+    data.histogram_bins_memory = bins_memory;
+
+    let (state, state_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        STATE_SIZE,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )
+    .unwrap();
+    data.exposure_state = state;
+    data.exposure_state_memory = state_memory;
+
+    let command_buffer = begin_single_time_commands(device, data).unwrap();
+    write_exposure(device, data, command_buffer, 1.0);
+    end_single_time_commands(device, data, command_buffer).unwrap();
+    Ok(())
+}
+
+/// Sets the adapted exposure, for the next pass that reads it to pick up.
+unsafe fn write_exposure(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, exposure: f32) {
+    let bytes = [exposure, 0.0].map(f32::to_ne_bytes).concat();
+    device.cmd_update_buffer(command_buffer, data.exposure_state, 0, &bytes);
+}
+
+/// The histogram pass reads every sample of the HDR color when the tonemap
+/// pass resolves it, like the tonemap pass itself.
+pub(crate) unsafe fn create_histogram_pipelines(device: &Device, data: &mut AppData) -> Result<()> {
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<HistogramPushConstants>() as u32);
+
+    let set_layouts = &[data.histogram_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.histogram_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let histogram = if resolves_in_shader(data) {
+        &include_bytes!("../../shaders/histogram_ms_comp.spv")[..]
+    } else {
+        &include_bytes!("../../shaders/histogram_comp.spv")[..]
+    };
+    let adapt = &include_bytes!("../../shaders/exposure_adapt_comp.spv")[..];
+
+    let constants = SpecializationConstants::new().i32(0, data.msaa_samples.bits() as i32);
+    let specialization_info = constants.info();
+
+    let [histogram_pipeline, adapt_pipeline] = [histogram, adapt].map(|code| {
+        let shader_module = create_shader_module(device, code).unwrap();
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(b"main\0")
+            .specialization_info(&specialization_info);
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(data.histogram_pipeline_layout);
+
+        let pipeline = device
+            .create_compute_pipelines(data.pipeline_cache, &[info], None)
+            .unwrap()
+            .0[0];
+
+        device.destroy_shader_module(shader_module, None);
+        pipeline
+    });
+
+    data.histogram_pipeline = histogram_pipeline;
+    data.exposure_adapt_pipeline = adapt_pipeline;
+    Ok(())
+}
+
+/// Points the histogram pass at the HDR color and creates an
+/// `ExposureReadback` per swapchain image. Only dynamic rendering leaves the
+/// color where a compute pass can read it before tonemapping, so this does
+/// nothing without it.
+pub(crate) unsafe fn create_histogram_descriptor_set(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.exposure_readbacks.clear();
+    if !data.dynamic_rendering {
+        data.histogram_descriptor_pool = vk::DescriptorPool::null();
+        data.histogram_descriptor_set = vk::DescriptorSet::null();
+        return Ok(());
+    }
+
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+    let buffer_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(2);
+
+    let pool_sizes = &[image_size, buffer_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    data.histogram_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = &[data.histogram_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.histogram_descriptor_pool)
+        .set_layouts(layouts);
+    data.histogram_descriptor_set = device.allocate_descriptor_sets(&info).unwrap()[0];
+
+    let image_view = if uses_resolve_attachment(data) {
+        data.resolve_image_view
+    } else {
+        data.color_image_view
+    };
+
+    let image_info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(image_view)
+        .sampler(data.samplers.get(device, SamplerDesc::nearest()));
+    let image_infos = &[image_info];
+    let image_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.histogram_descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_infos);
+
+    let buffer_infos = [data.histogram_bins, data.exposure_state].map(|buffer| {
+        [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64)
+            .build()]
+    });
+    let buffer_writes = [1, 2].map(|binding| {
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.histogram_descriptor_set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_infos[binding as usize - 1])
+    });
+
+    device.update_descriptor_sets(
+        &[image_write, buffer_writes[0], buffer_writes[1]],
+        &[] as &[vk::CopyDescriptorSet],
+    );
+
+    for _ in 0..data.swapchain_images.len() {
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            STATE_SIZE,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )
+        .unwrap();
+        data.exposure_readbacks.push(ExposureReadback { buffer, memory, pending: false });
+    }
+
+    Ok(())
+}
+
+/// Bins the finished HDR color, which must be in `SHADER_READ_ONLY_OPTIMAL`,
+/// and adapts the exposure to it for the tonemap pass that follows. The
+/// adapted exposure is first reset to `reset` if given. Copies the result to
+/// `image_index`'s readback.
+pub(crate) unsafe fn record_histogram(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    push_constants: &HistogramPushConstants,
+    reset: Option<f32>,
+) {
+    // The last frame's passes may still be reading the bins and exposure.
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE | vk::AccessFlags::TRANSFER_READ)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    device.cmd_fill_buffer(command_buffer, data.histogram_bins, 0, BINS * 4, 0);
+    if let Some(exposure) = reset {
+        write_exposure(device, data, command_buffer, exposure);
+    }
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    let push_constants_bytes = std::slice::from_raw_parts(
+        push_constants as *const HistogramPushConstants as *const u8,
+        size_of::<HistogramPushConstants>(),
+    );
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        data.histogram_pipeline_layout,
+        0,
+        &[data.histogram_descriptor_set],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        data.histogram_pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        push_constants_bytes,
+    );
+
+    let extent = data.swapchain_extent;
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.histogram_pipeline);
+    device.cmd_dispatch(command_buffer, extent.width.div_ceil(16), extent.height.div_ceil(16), 1);
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.exposure_adapt_pipeline);
+    device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    let region = vk::BufferCopy::builder()
+        .src_offset(0)
+        .dst_offset(0)
+        .size(STATE_SIZE);
+    let readback = data.exposure_readbacks[image_index].buffer;
+    device.cmd_copy_buffer(command_buffer, data.exposure_state, readback, &[region]);
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::HOST_READ);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+}
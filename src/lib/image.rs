@@ -1,13 +1,21 @@
 use anyhow::{anyhow, Result};
+use std::ops::Range;
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
+    allocations::{allocate_memory, AllocationKind},
     app::AppData,
+    barrier::{cmd_barriers, ImageTransition},
     single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    tonemap::{uses_resolve_attachment, HDR_FORMAT},
     vertex_buffer::get_memory_type_index
 };
 
+/// Creates an image with memory of its own, tracked with the caller's
+/// location, see `allocate_memory`.
+#[track_caller]
+#[allow(clippy::too_many_arguments)]
 pub(crate) unsafe fn create_image(
   instance: &Instance,
   device: &Device,
@@ -15,13 +23,16 @@ pub(crate) unsafe fn create_image(
   width: u32,
   height: u32,
   mip_levels: u32,
+  layers: u32,
   samples: vk::SampleCountFlags,
   format: vk::Format,
   tiling: vk::ImageTiling,
   usage: vk::ImageUsageFlags,
   properties: vk::MemoryPropertyFlags,
+  flags: vk::ImageCreateFlags,
 ) -> Result<(vk::Image, vk::DeviceMemory)> {
   let info = vk::ImageCreateInfo::builder()
+      .flags(flags)
       .image_type(vk::ImageType::_2D)
       .extent(vk::Extent3D {
           width,
@@ -29,7 +40,7 @@ pub(crate) unsafe fn create_image(
           depth: 1,
       })
       .mip_levels(mip_levels)
-      .array_layers(1)
+      .array_layers(layers)
       .format(format)
       .tiling(tiling)
       .initial_layout(vk::ImageLayout::UNDEFINED)
@@ -41,19 +52,35 @@ pub(crate) unsafe fn create_image(
 
   let requirements = device.get_image_memory_requirements(image);
 
+  // Transient attachments never leave tile memory on tiled GPUs, so they
+  // only need backing memory if the driver decides to spill them.
+  let lazy = usage.contains(vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+      .then(|| {
+        let properties = properties | vk::MemoryPropertyFlags::LAZILY_ALLOCATED;
+        get_memory_type_index(instance, data, properties, requirements).ok()
+      })
+      .flatten();
+
   let info = vk::MemoryAllocateInfo::builder()
       .allocation_size(requirements.size)
-      .memory_type_index(
-          get_memory_type_index(instance, data, properties, requirements).unwrap(),
-      );
+      .memory_type_index(lazy.unwrap_or_else(|| {
+        get_memory_type_index(instance, data, properties, requirements).unwrap()
+      }));
 
-  let image_memory = device.allocate_memory(&info, None).unwrap();
+  let image_memory = match allocate_memory(device, &info, AllocationKind::Image) {
+    Ok(memory) => memory,
+    Err(e) => {
+      device.destroy_image(image, None);
+      return Err(anyhow!("Failed to allocate {} bytes for an image: {}", requirements.size, e));
+    }
+  };
 
   device.bind_image_memory(image, image_memory, 0).unwrap();
 
   Ok((image, image_memory))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) unsafe fn transition_image_layout(
   device: &Device,
   data: &AppData,
@@ -62,51 +89,20 @@ pub(crate) unsafe fn transition_image_layout(
   old_layout: vk::ImageLayout,
   new_layout: vk::ImageLayout,
   mip_levels: u32,
+  layers: Range<u32>,
 ) -> Result<()> {
-  let (src_access_mask, dst_access_mask, src_stage_mask, dst_stage_mask) =
-      match (old_layout, new_layout) {
-          (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
-              vk::AccessFlags::empty(),
-              vk::AccessFlags::TRANSFER_WRITE,
-              vk::PipelineStageFlags::TOP_OF_PIPE,
-              vk::PipelineStageFlags::TRANSFER,
-          ),
-          (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL) => (
-              vk::AccessFlags::TRANSFER_WRITE,
-              vk::AccessFlags::SHADER_READ,
-              vk::PipelineStageFlags::TRANSFER,
-              vk::PipelineStageFlags::FRAGMENT_SHADER,
-          ),
-          _ => return Err(anyhow!("Unsupported image layout transition!")),
-      };
+  match (old_layout, new_layout) {
+      (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+      | (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+      | (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => {}
+      _ => return Err(anyhow!("Unsupported image layout transition!")),
+  }
   let command_buffer = begin_single_time_commands(device, data).unwrap();
 
-  let subresource = vk::ImageSubresourceRange::builder()
-      .aspect_mask(vk::ImageAspectFlags::COLOR)
-      .base_mip_level(0)
-      .level_count(mip_levels)
-      .base_array_layer(0)
-      .layer_count(1);
-
-  let barrier = vk::ImageMemoryBarrier::builder()
-      .old_layout(old_layout)
-      .new_layout(new_layout)
-      .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-      .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-      .image(image)
-      .subresource_range(subresource)
-      .src_access_mask(src_access_mask)
-      .dst_access_mask(dst_access_mask);
-
-  device.cmd_pipeline_barrier(
-      command_buffer,
-      src_stage_mask,
-      dst_stage_mask,
-      vk::DependencyFlags::empty(),
-      &[] as &[vk::MemoryBarrier],
-      &[] as &[vk::BufferMemoryBarrier],
-      &[barrier],
-  );
+  let transition = ImageTransition::new(image, old_layout, new_layout)
+      .levels(0..mip_levels)
+      .layers(layers);
+  cmd_barriers(device, data, command_buffer, &[transition], &[]);
 
   end_single_time_commands(device, data, command_buffer).unwrap();
 
@@ -154,6 +150,74 @@ pub(crate) unsafe fn copy_buffer_to_image(
   Ok(())
 }
 
+/// Copies a mip chain packed back to back in `buffer`, with `offsets[i]`
+/// giving the start of level `i`.
+pub(crate) unsafe fn copy_buffer_to_image_levels(
+  device: &Device,
+  data: &AppData,
+  buffer: vk::Buffer,
+  image: vk::Image,
+  width: u32,
+  height: u32,
+  offsets: &[u64],
+) -> Result<()> {
+  copy_buffer_to_image_layers(device, data, buffer, image, width, height, 0, &[offsets.to_vec()])
+}
+
+/// Like `copy_buffer_to_image_levels`, with `offsets[layer][level]` giving
+/// the start of each level of array layer `base_layer + layer`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn copy_buffer_to_image_layers(
+  device: &Device,
+  data: &AppData,
+  buffer: vk::Buffer,
+  image: vk::Image,
+  width: u32,
+  height: u32,
+  base_layer: u32,
+  offsets: &[Vec<u64>],
+) -> Result<()> {
+  let command_buffer = begin_single_time_commands(device, data).unwrap();
+
+  let regions = offsets
+      .iter()
+      .enumerate()
+      .flat_map(|(layer, levels)| levels.iter().enumerate().map(move |(level, offset)| (layer, level, offset)))
+      .map(|(layer, level, offset)| {
+          let subresource = vk::ImageSubresourceLayers::builder()
+              .aspect_mask(vk::ImageAspectFlags::COLOR)
+              .mip_level(level as u32)
+              .base_array_layer(base_layer + layer as u32)
+              .layer_count(1);
+
+          vk::BufferImageCopy::builder()
+              .buffer_offset(*offset)
+              .buffer_row_length(0)
+              .buffer_image_height(0)
+              .image_subresource(subresource)
+              .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+              .image_extent(vk::Extent3D {
+                  width: (width >> level).max(1),
+                  height: (height >> level).max(1),
+                  depth: 1,
+              })
+              .build()
+      })
+      .collect::<Vec<_>>();
+
+  device.cmd_copy_buffer_to_image(
+      command_buffer,
+      buffer,
+      image,
+      vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+      &regions,
+  );
+
+  end_single_time_commands(device, data, command_buffer).unwrap();
+
+  Ok(())
+}
+
 pub(crate) unsafe fn create_image_view(
   device: &Device,
   image: vk::Image,
@@ -177,11 +241,121 @@ pub(crate) unsafe fn create_image_view(
   Ok(device.create_image_view(&info, None).unwrap())
 }
 
+/// A color view of every mip level and array layer, for cube and array views.
+pub(crate) unsafe fn create_layered_image_view(
+  device: &Device,
+  image: vk::Image,
+  format: vk::Format,
+  view_type: vk::ImageViewType,
+  mip_levels: u32,
+  layers: u32,
+) -> Result<vk::ImageView> {
+  let subresource_range = vk::ImageSubresourceRange::builder()
+      .aspect_mask(vk::ImageAspectFlags::COLOR)
+      .base_mip_level(0)
+      .level_count(mip_levels)
+      .base_array_layer(0)
+      .layer_count(layers);
+
+  let info = vk::ImageViewCreateInfo::builder()
+      .image(image)
+      .view_type(view_type)
+      .format(format)
+      .subresource_range(subresource_range);
+
+  Ok(device.create_image_view(&info, None).unwrap())
+}
+
+/// Creates `count` images for a compute or ray tracing pass to write and
+/// the scene to sample, each cleared to `clear` and left in the general
+/// layout for good.
+pub(crate) unsafe fn create_storage_images(
+  instance: &Instance,
+  device: &Device,
+  data: &AppData,
+  count: usize,
+  (width, height): (u32, u32),
+  format: vk::Format,
+  clear: vk::ClearColorValue,
+) -> Result<Vec<(vk::Image, vk::DeviceMemory, vk::ImageView)>> {
+  let images = (0..count)
+      .map(|_| {
+        let (image, image_memory) = create_image(
+            instance,
+            device,
+            data,
+            width,
+            height,
+            1,
+            1,
+            vk::SampleCountFlags::_1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::STORAGE
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::empty(),
+        )
+        .unwrap();
+        let image_view = create_image_view(device, image, format, vk::ImageAspectFlags::COLOR, 1).unwrap();
+        (image, image_memory, image_view)
+      })
+      .collect::<Vec<_>>();
+
+  let command_buffer = begin_single_time_commands(device, data).unwrap();
+  let subresource = vk::ImageSubresourceRange::builder()
+      .aspect_mask(vk::ImageAspectFlags::COLOR)
+      .base_mip_level(0)
+      .level_count(1)
+      .base_array_layer(0)
+      .layer_count(1);
+  let transitions = images
+      .iter()
+      .map(|(image, _, _)| {
+        ImageTransition::new(*image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL)
+            .dst(vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE)
+      })
+      .collect::<Vec<_>>();
+  cmd_barriers(device, data, command_buffer, &transitions, &[]);
+  for (image, _, _) in &images {
+    device.cmd_clear_color_image(command_buffer, *image, vk::ImageLayout::GENERAL, &clear, &[subresource]);
+  }
+  let barrier = vk::MemoryBarrier::builder()
+      .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+      .dst_access_mask(vk::AccessFlags::SHADER_READ);
+  device.cmd_pipeline_barrier(
+      command_buffer,
+      vk::PipelineStageFlags::TRANSFER,
+      vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+      vk::DependencyFlags::empty(),
+      &[barrier],
+      &[] as &[vk::BufferMemoryBarrier],
+      &[] as &[vk::ImageMemoryBarrier],
+  );
+  end_single_time_commands(device, data, command_buffer).unwrap();
+
+  Ok(images)
+}
+
 pub(crate) unsafe fn create_color_objects(
   instance: &Instance,
   device: &Device,
   data: &mut AppData,
 ) -> Result<()> {
+  // Under dynamic rendering the tonemap pass samples the color unless it
+  // reads the resolve image instead.
+  let usage = if !data.dynamic_rendering {
+    vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | vk::ImageUsageFlags::INPUT_ATTACHMENT
+        | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+  } else if uses_resolve_attachment(data) {
+    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+  } else {
+    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+  };
+
   let (color_image, color_image_memory) = create_image(
       instance,
       device,
@@ -189,11 +363,13 @@ pub(crate) unsafe fn create_color_objects(
       data.swapchain_extent.width,
       data.swapchain_extent.height,
       1,
+      1,
       data.msaa_samples,
-      data.swapchain_format,
+      HDR_FORMAT,
       vk::ImageTiling::OPTIMAL,
-      vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+      usage,
       vk::MemoryPropertyFlags::DEVICE_LOCAL,
+      vk::ImageCreateFlags::empty(),
   )
   .unwrap();
 
@@ -203,7 +379,7 @@ pub(crate) unsafe fn create_color_objects(
   data.color_image_view = create_image_view(
       device,
       data.color_image,
-      data.swapchain_format,
+      HDR_FORMAT,
       vk::ImageAspectFlags::COLOR,
       1,
   )
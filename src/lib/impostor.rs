@@ -0,0 +1,383 @@
+use anyhow::{anyhow, Result};
+use cgmath::{vec2, EuclideanSpace, InnerSpace, Point3, SquareMatrix, Transform};
+use std::{f32::consts::TAU, mem::size_of};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    billboard::{Billboard, BillboardFacing},
+    camera::Camera,
+    image::{create_image, create_image_view},
+    lod::model_lod,
+    shader::{create_shader_module, SpecializationConstants},
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    texture::LayeredTexture,
+    texture_atlas::resolve_texture,
+    types::{Mat4, Vec2, Vec3, Vec4},
+    vertex::Vertex,
+    visibility::Bounds,
+};
+
+const IMPOSTOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Views of the model from evenly spaced directions around its up axis,
+/// baked side by side into one texture by `App::bake_impostor`. A distant
+/// entity is then one billboard showing the view nearest its camera.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Impostor {
+    /// The views' texture table slot.
+    pub texture: u32,
+    pub frames: u32,
+    /// The model's bounds, which every view is framed on.
+    pub(crate) bounds: Bounds,
+    /// Where the first view is seen from, where the view a quarter turn
+    /// later is seen from, and up, in model space.
+    pub(crate) axes: [Vec3; 3],
+}
+
+impl Impostor {
+    /// A billboard standing in for the model drawn with `transform`, seen
+    /// from `eye`. The entity is assumed to be upright and uniformly scaled.
+    pub fn billboard(&self, transform: Mat4, eye: Vec3) -> Billboard {
+        let [forward, side, _] = self.axes;
+        let position = transform.transform_point(Point3::from_vec(self.bounds.center)).to_vec();
+        let local = transform.invert().unwrap_or(Mat4::identity()).transform_vector(eye - position);
+        let angle = local.dot(side).atan2(local.dot(forward)).rem_euclid(TAU);
+        let frame = (angle / TAU * self.frames as f32).round() as u32 % self.frames;
+
+        let size = self.bounds.radius * transform.x.truncate().magnitude();
+        let width = 1.0 / self.frames as f32;
+        Billboard {
+            facing: BillboardFacing::Cylindrical,
+            uv_min: vec2(frame as f32 * width, 0.0),
+            uv_max: vec2((frame + 1) as f32 * width, 1.0),
+            ..Billboard::new(position, vec2(size, size), self.texture)
+        }
+    }
+}
+
+/// The axes `Impostor::axes` describes, for `data`'s world.
+pub(crate) fn impostor_axes(data: &AppData) -> [Vec3; 3] {
+    let up = data.world.up_vector();
+    let forward = if up == Vec3::unit_z() { Vec3::unit_x() } else { Vec3::unit_z() };
+    [forward, up.cross(forward), up]
+}
+
+/// Draws the model's full level of detail, textured with `texture`, from
+/// `frames` directions around it into a `frames * resolution` by
+/// `resolution` texture. Each view is an orthographic projection of the
+/// model's bounding sphere, on a transparent background.
+pub(crate) unsafe fn create_impostor_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    frames: u32,
+    resolution: u32,
+    texture: u32,
+) -> Result<LayeredTexture> {
+    let bounds = data.model_bounds;
+    if bounds.radius <= 0.0 {
+        return Err(anyhow!("There is no model to bake an impostor of."));
+    }
+    let (width, height) = (frames * resolution, resolution);
+    let max_dimension = instance.get_physical_device_properties(data.physical_device).limits.max_image_dimension_2d;
+    if frames == 0 || width > max_dimension {
+        return Err(anyhow!("{frames} views of {resolution} texels do not fit a {max_dimension} texel wide texture."));
+    }
+
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        IMPOSTOR_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        vk::ImageCreateFlags::empty(),
+    )?;
+    let image_view = create_image_view(device, image, IMPOSTOR_FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+
+    let (depth_image, depth_image_memory) = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        data.depth_format,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        vk::ImageCreateFlags::empty(),
+    )?;
+    let depth_image_view = create_image_view(device, depth_image, data.depth_format, vk::ImageAspectFlags::DEPTH, 1)?;
+
+    // Cleared to transparent, so only the model shows on the billboard.
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(IMPOSTOR_FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+    let depth_attachment = vk::AttachmentDescription::builder()
+        .format(data.depth_format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let depth_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[color_attachment_ref];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments)
+        .depth_stencil_attachment(&depth_attachment_ref);
+
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+    let attachments = &[color_attachment, depth_attachment];
+    let subpasses = &[subpass];
+    let dependencies = &[dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+    let render_pass = device.create_render_pass(&info, None)?;
+
+    let framebuffer_attachments = &[image_view, depth_image_view];
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(render_pass)
+        .attachments(framebuffer_attachments)
+        .width(width)
+        .height(height)
+        .layers(1);
+    let framebuffer = device.create_framebuffer(&info, None)?;
+
+    let pipeline = create_impostor_pipeline(device, data, render_pass)?;
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    let clear_values = &[
+        vk::ClearValue { color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0] } },
+        vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+    ];
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(render_pass)
+        .framebuffer(framebuffer)
+        .render_area(vk::Rect2D::builder().extent(vk::Extent2D { width, height }))
+        .clear_values(clear_values);
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.pipeline_layout,
+        1,
+        &[data.texture_descriptor_set],
+        &[],
+    );
+    device.cmd_bind_vertex_buffers(command_buffer, 0, &[data.vertex_buffer], &[0]);
+    device.cmd_bind_index_buffer(command_buffer, data.index_buffer, 0, data.index_type);
+
+    let [forward, side, up] = impostor_axes(data);
+    let texture = resolve_texture(data, texture);
+    device.cmd_push_constants(command_buffer, data.pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 72, &texture.to_ne_bytes());
+    let up_bytes = std::slice::from_raw_parts(&up.extend(0.0) as *const Vec4 as *const u8, size_of::<Vec4>());
+    device.cmd_push_constants(command_buffer, data.pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 80, up_bytes);
+
+    // Orthographic, in render space, around the bounding sphere seen from
+    // twice its radius away.
+    let radius = bounds.radius * data.world.render_transform().transform_vector(up).magnitude();
+    let (near, far) = (radius, 3.0 * radius);
+    #[rustfmt::skip]
+    let proj = Mat4::new(
+        1.0 / radius, 0.0, 0.0, 0.0,
+        0.0, -1.0 / radius, 0.0, 0.0,
+        0.0, 0.0, -1.0 / (far - near), 0.0,
+        0.0, 0.0, -near / (far - near), 1.0,
+    );
+
+    let lod = model_lod(data, 0);
+    for frame in 0..frames {
+        let angle = frame as f32 * TAU / frames as f32;
+        let direction = forward * angle.cos() + side * angle.sin();
+        let eye = bounds.center + direction * 2.0 * bounds.radius;
+        let view = Camera::look_at(eye, bounds.center, up).view(&data.world);
+
+        let viewport = vk::Viewport::builder()
+            .x((frame * resolution) as f32)
+            .y(0.0)
+            .width(resolution as f32)
+            .height(resolution as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D { x: (frame * resolution) as i32, y: 0 })
+            .extent(vk::Extent2D { width: resolution, height: resolution });
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+
+        let view_proj = proj * view;
+        let view_proj_bytes = std::slice::from_raw_parts(&view_proj as *const Mat4 as *const u8, size_of::<Mat4>());
+        device.cmd_push_constants(command_buffer, data.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, view_proj_bytes);
+        device.cmd_draw_indexed(command_buffer, lod.index_count, 1, lod.first_index, 0, 0);
+    }
+    device.cmd_end_render_pass(command_buffer);
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+    device.destroy_pipeline(pipeline, None);
+    device.destroy_framebuffer(framebuffer, None);
+    device.destroy_render_pass(render_pass, None);
+    device.destroy_image_view(depth_image_view, None);
+    device.destroy_image(depth_image, None);
+    free_memory(device, depth_image_memory);
+
+    Ok(LayeredTexture {
+        image,
+        image_memory,
+        image_view,
+        format: IMPOSTOR_FORMAT,
+        width,
+        height,
+        layers: 1,
+        mip_levels: 1,
+        sampler: vk::Sampler::null(),
+    })
+}
+
+/// Draws the model's vertices with the scene pipeline layout, which has the
+/// texture table at set 1 and room for a matrix and the texture index.
+unsafe fn create_impostor_pipeline(device: &Device, data: &AppData, render_pass: vk::RenderPass) -> Result<vk::Pipeline> {
+    let vert = include_bytes!("../../shaders/impostor_vert.spv");
+    let frag = include_bytes!("../../shaders/impostor_frag.spv");
+
+    let vert_shader_module = create_shader_module(device, &vert[..])?;
+    let frag_shader_module = create_shader_module(device, &frag[..])?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let constants = SpecializationConstants::new().u32(0, data.texture_capacity);
+    let specialization_info = constants.info();
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    // Only the position, texture coordinates and normal.
+    let vec3_size = size_of::<Vec3>() as u32;
+    let attribute = |location: u32, format: vk::Format, offset: u32| {
+        vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(location)
+            .format(format)
+            .offset(offset)
+            .build()
+    };
+    let attribute_descriptions = &[
+        attribute(0, vk::Format::R32G32B32_SFLOAT, 0),
+        attribute(2, vk::Format::R32G32_SFLOAT, vec3_size * 2),
+        attribute(3, vk::Format::R32G32B32_SFLOAT, vec3_size * 2 + size_of::<Vec2>() as u32),
+    ];
+    let binding_descriptions = &[Vertex::binding_description()];
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    // Set per view.
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewport_count(1)
+        .scissor_count(1);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(true)
+        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let blend_attachments = &[blend_attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(blend_attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    let stages = &[vert_stage, frag_stage];
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0);
+    let pipeline = device.create_graphics_pipelines(data.pipeline_cache, &[info], None)?.0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(pipeline)
+}
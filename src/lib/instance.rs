@@ -1,6 +1,6 @@
 use anyhow::{anyhow, Result};
 use log::*;
-use std::collections::HashSet;
+use std::{collections::HashSet, ffi::CStr};
 use winit::window::Window;
 
 use vulkanalia::{
@@ -10,21 +10,37 @@ use vulkanalia::{
 };
 
 use crate::{
-    app::{AppData, PORTABILITY_MACOS_VERSION, VALIDATION_ENABLED, VALIDATION_LAYER},
+    app::{AppData, PORTABILITY_MACOS_VERSION, VALIDATION_ENABLED, VALIDATION_LAYER, VULKAN_1_1, VULKAN_1_2, VULKAN_1_3},
     debug::debug_callback,
 };
 
+/// Creates the instance with the extensions `window` needs to present, or
+/// none for `Headless` contexts.
 pub(crate) unsafe fn create_instance(
-    window: &Window,
+    window: Option<&Window>,
     entry: &Entry,
     data: &mut AppData,
 ) -> Result<Instance> {
+    // Vulkan 1.1 is needed for subgroup operations, 1.2 for core timeline
+    // semaphores and 1.3 for dynamic rendering, but older loaders reject
+    // versions they do not know.
+    let loader_version = entry.version().unwrap();
+    data.instance_version = if loader_version >= VULKAN_1_3 {
+        u32::from(VULKAN_1_3)
+    } else if loader_version >= VULKAN_1_2 {
+        u32::from(VULKAN_1_2)
+    } else if loader_version >= VULKAN_1_1 {
+        u32::from(VULKAN_1_1)
+    } else {
+        vk::make_version(1, 0, 0)
+    };
+
     let application_info = vk::ApplicationInfo::builder()
         .application_name(b"Vulkanalia Tutorial\0")
         .application_version(vk::make_version(1, 0, 0))
         .engine_name(b"No Engine\0")
         .engine_version(vk::make_version(1, 0, 0))
-        .api_version(vk::make_version(1, 0, 0));
+        .api_version(data.instance_version);
 
     let available_layers = entry
         .enumerate_instance_layer_properties()
@@ -43,7 +59,8 @@ pub(crate) unsafe fn create_instance(
         Vec::new()
     };
 
-    let mut extensions = vk_window::get_required_instance_extensions(window)
+    let mut extensions = window
+        .map_or(&[] as &[_], |w| vk_window::get_required_instance_extensions(w))
         .iter()
         .map(|e| e.as_ptr())
         .collect::<Vec<_>>();
@@ -52,20 +69,46 @@ pub(crate) unsafe fn create_instance(
         extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr());
     }
 
-    //For MacOS vulkan instances
-    let flags =
-        if cfg!(target_os = "macos") && entry.version().unwrap() >= PORTABILITY_MACOS_VERSION {
-            info!("Enabling extensions for MacOS portability.");
-            extensions.push(
-                vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION
-                    .name
-                    .as_ptr(),
-            );
-            extensions.push(vk::KHR_PORTABILITY_ENUMERATION_EXTENSION.name.as_ptr());
-            vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
-        } else {
-            vk::InstanceCreateFlags::empty()
-        };
+    // The HDR color spaces, which surfaces only offer with it enabled.
+    let available_extensions = entry
+        .enumerate_instance_extension_properties(None)
+        .unwrap()
+        .iter()
+        .map(|e| e.extension_name)
+        .collect::<HashSet<_>>();
+    if window.is_some() && available_extensions.contains(&vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name) {
+        extensions.push(vk::EXT_SWAPCHAIN_COLORSPACE_EXTENSION.name.as_ptr());
+    }
+
+    // Needed by `VK_EXT_full_screen_exclusive`, which only Windows has.
+    data.surface_capabilities2 = cfg!(windows)
+        && window.is_some()
+        && available_extensions.contains(&vk::KHR_GET_SURFACE_CAPABILITIES2_EXTENSION.name);
+    if data.surface_capabilities2 {
+        extensions.push(vk::KHR_GET_SURFACE_CAPABILITIES2_EXTENSION.name.as_ptr());
+    }
+
+    for extension in &data.xr_instance_extensions {
+        if !extensions.iter().any(|e| CStr::from_ptr(*e) == extension.as_c_str()) {
+            extensions.push(extension.as_ptr());
+        }
+    }
+
+    //For MacOS and iOS vulkan instances
+    let flags = if cfg!(any(target_os = "macos", target_os = "ios"))
+        && entry.version().unwrap() >= PORTABILITY_MACOS_VERSION
+    {
+        info!("Enabling extensions for MacOS portability.");
+        extensions.push(
+            vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION
+                .name
+                .as_ptr(),
+        );
+        extensions.push(vk::KHR_PORTABILITY_ENUMERATION_EXTENSION.name.as_ptr());
+        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    } else {
+        vk::InstanceCreateFlags::empty()
+    };
 
     let mut info = vk::InstanceCreateInfo::builder()
         .application_info(&application_info)
@@ -0,0 +1,412 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+/// A JSON value, for the few files the engine reads and writes itself.
+/// Objects keep their keys in order, so saved files diff cleanly.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser { text, position: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.position < text.len() {
+            return Err(parser.error("Expected the end of the file"));
+        }
+        Ok(value)
+    }
+
+    /// The value at `key`, if this is an object that has one.
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f32(&self) -> Option<f32> {
+        match self {
+            Json::Number(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// An array of numbers.
+    pub(crate) fn numbers(values: impl IntoIterator<Item = f32>) -> Self {
+        Json::Array(values.into_iter().map(Json::from).collect())
+    }
+
+    /// Whether every number in the value is finite, which JSON has no way to
+    /// write otherwise.
+    pub(crate) fn is_finite(&self) -> bool {
+        match self {
+            Json::Number(value) => value.is_finite(),
+            Json::Array(values) => values.iter().all(Json::is_finite),
+            Json::Object(entries) => entries.iter().all(|(_, v)| v.is_finite()),
+            _ => true,
+        }
+    }
+
+    /// Writes the value indented by `depth` levels. Arrays of plain values
+    /// stay on one line. Numbers that are not finite are written as `null`.
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let indent = |f: &mut fmt::Formatter, depth: usize| write!(f, "{:1$}", "", depth * 2);
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) if !value.is_finite() => write!(f, "null"),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) if values.iter().all(|v| !matches!(v, Json::Array(_) | Json::Object(_))) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.write(f, depth)?;
+                }
+                write!(f, "]")
+            }
+            Json::Array(values) => {
+                writeln!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    indent(f, depth + 1)?;
+                    value.write(f, depth + 1)?;
+                    writeln!(f, "{}", if i + 1 < values.len() { "," } else { "" })?;
+                }
+                indent(f, depth)?;
+                write!(f, "]")
+            }
+            Json::Object(entries) if entries.is_empty() => write!(f, "{{}}"),
+            Json::Object(entries) => {
+                writeln!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    indent(f, depth + 1)?;
+                    write_string(f, key)?;
+                    write!(f, ": ")?;
+                    value.write(f, depth + 1)?;
+                    writeln!(f, "{}", if i + 1 < entries.len() { "," } else { "" })?;
+                }
+                indent(f, depth)?;
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<f32> for Json {
+    /// Goes through the shortest decimal that reads back as the same `f32`,
+    /// so that 0.1 is written as 0.1 rather than 0.10000000149011612.
+    fn from(value: f32) -> Self {
+        Json::Number(value.to_string().parse().unwrap_or(value as f64))
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    /// In bytes.
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Json> {
+        self.whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        self.expect('{')?;
+        let mut entries = vec![];
+        self.whitespace();
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+            self.whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(entries)),
+                _ => return Err(self.error("Expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        self.expect('[')?;
+        let mut values = vec![];
+        self.whitespace();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(values)),
+                _ => return Err(self.error("Expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('/') => value.push('/'),
+                    Some('b') => value.push('\u{8}'),
+                    Some('f') => value.push('\u{c}'),
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some('u') => {
+                        let digits = self.text.get(self.position..self.position + 4).unwrap_or("");
+                        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("Expected four hex digits"))?;
+                        self.position += 4;
+                        // Surrogate pairs are not needed by anything the
+                        // engine writes.
+                        value.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                    }
+                    _ => return Err(self.error("Expected an escape sequence")),
+                },
+                Some(c) => value.push(c),
+                None => return Err(self.error("Expected the end of the string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.position;
+        while matches!(self.peek(), Some(c) if c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' || c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.text[start..self.position]
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("Expected a number"))
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json> {
+        if !self.text[self.position..].starts_with(word) {
+            return Err(self.error(&format!("Expected `{}`", word)));
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.next() != Some(c) {
+            return Err(self.error(&format!("Expected `{}`", c)));
+        }
+        Ok(())
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        let line = self.text[..self.position.min(self.text.len())].matches('\n').count() + 1;
+        anyhow!("{} on line {}.", message, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use cgmath::{Deg, Matrix4};
+
+    use super::*;
+    use crate::{
+        entity::{Entity, Mobility},
+        render_queue::{DrawOrder, RenderQueue},
+        scene::Scene,
+        shadows::Sun,
+        types::Vec3,
+        viewmodel::CameraLayer,
+    };
+
+    fn parse_error(text: &str) -> String {
+        Json::parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn scene_round_trips() {
+        let directory = Path::new("scenes");
+        let mut scene = Scene {
+            model: Some(directory.join("models/room.glb")),
+            textures: vec![directory.join("textures/brick.png"), PathBuf::from("/absolute/wood.ktx2")],
+            entities: vec![
+                Entity::default(),
+                Entity {
+                    transform: Matrix4::from_translation(Vec3::new(1.5, -2.0, 0.1)) * Matrix4::from_scale(0.3),
+                    mobility: Mobility::Static,
+                    opacity: 0.25,
+                    texture: 2,
+                    order: DrawOrder { queue: RenderQueue(3000), sort_key: 7 },
+                    layer: CameraLayer::Viewmodel,
+                    pose: Some(1),
+                    ..Entity::default()
+                },
+            ],
+            sun: Some(Sun { direction: Vec3::new(0.2, 1.0, -0.4), illuminance: Vec3::new(3.0, 2.9, 2.7) }),
+            ..Scene::default()
+        };
+        scene.camera.fov = Deg(62.5);
+        scene.camera.infinite_far = true;
+
+        let text = scene.to_json(directory).to_string();
+        let loaded = Scene::from_json(&Json::parse(&text).unwrap(), directory).unwrap();
+        assert_eq!(loaded, scene);
+    }
+
+    #[test]
+    fn strings_unescape_and_escape() {
+        let json = Json::parse(r#""quote \" backslash \\ slash \/ \b\f\n\r\t end""#).unwrap();
+        assert_eq!(json, Json::String("quote \" backslash \\ slash / \u{8}\u{c}\n\r\t end".into()));
+
+        let value = Json::String("tab\t \"quoted\" \\ \u{1} line\n".into());
+        assert_eq!(value.to_string(), r#""tab\t \"quoted\" \\ \u0001 line\n""#);
+        assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(Json::parse(r#""\u0041\u00e9\u4E2D""#).unwrap(), Json::String("A\u{e9}\u{4e2d}".into()));
+        assert!(parse_error(r#""\u00g1""#).starts_with("Expected four hex digits"));
+        assert!(parse_error(r#""\u12"#).starts_with("Expected four hex digits"));
+    }
+
+    #[test]
+    fn nested_arrays_and_objects() {
+        let text = r#"{"a": [1, [2, {"b": null}], []], "c": {"d": {"e": [true, false]}}, "f": {}}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(
+            json,
+            Json::Object(vec![
+                (
+                    "a".into(),
+                    Json::Array(vec![
+                        Json::Number(1.0),
+                        Json::Array(vec![Json::Number(2.0), Json::Object(vec![("b".into(), Json::Null)])]),
+                        Json::Array(vec![]),
+                    ]),
+                ),
+                (
+                    "c".into(),
+                    Json::Object(vec![(
+                        "d".into(),
+                        Json::Object(vec![("e".into(), Json::Array(vec![Json::Bool(true), Json::Bool(false)]))]),
+                    )]),
+                ),
+                ("f".into(), Json::Object(vec![])),
+            ])
+        );
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+    }
+
+    #[test]
+    fn trailing_garbage_is_rejected() {
+        assert_eq!(parse_error("{} x"), "Expected the end of the file on line 1.");
+        assert_eq!(parse_error("[1, 2]\n]"), "Expected the end of the file on line 2.");
+        assert!(Json::parse("  {}\n\n").is_ok());
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        assert_eq!(parse_error("{\n  \"a\": 1,\n  \"b\": }\n"), "Expected a value on line 3.");
+        assert_eq!(parse_error("[\n1\n2]"), "Expected `,` or `]` on line 3.");
+        assert_eq!(parse_error("\n\n\n\"open"), "Expected the end of the string on line 4.");
+    }
+
+    #[test]
+    fn non_finite_numbers_are_written_as_null() {
+        assert!(!Json::numbers([1.0, f32::INFINITY]).is_finite());
+        assert_eq!(Json::numbers([1.0, f32::NAN]).to_string(), "[1, null]");
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Result};
+use ruzstd::decoding::FrameDecoder;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::basis::{transcode_uastc, uastc_size, Etc1s};
+use crate::texture::{check_texture_size, level_size, TextureData};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+/// Data format descriptor color models of Basis Universal payloads.
+const KHR_DF_MODEL_ETC1S: u8 = 163;
+const KHR_DF_MODEL_UASTC: u8 = 166;
+const KHR_DF_TRANSFER_SRGB: u8 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Supercompression {
+    None,
+    BasisLz,
+    Zstandard,
+    Zlib,
+}
+
+/// Parses a KTX2 container holding a single 2D texture. Mip levels stored in
+/// the file are returned largest first; files with a single level get their
+/// mips generated at load like PNGs do.
+///
+/// Levels may be Zlib or Zstandard supercompressed. Basis Universal
+/// payloads, BasisLZ-compressed ETC1S or UASTC, are transcoded to RGBA8.
+pub(crate) fn load_ktx2(bytes: &[u8]) -> Result<TextureData> {
+    if bytes.len() < HEADER_SIZE || bytes[..12] != IDENTIFIER {
+        return Err(anyhow!("Not a KTX2 file."));
+    }
+
+    let format = vk::Format::from_raw(read_u32(bytes, 12) as i32);
+    let width = read_u32(bytes, 20);
+    let height = read_u32(bytes, 24);
+    let depth = read_u32(bytes, 28);
+    let layers = read_u32(bytes, 32);
+    let faces = read_u32(bytes, 36);
+    let level_count = read_u32(bytes, 40).max(1);
+    let supercompression = match read_u32(bytes, 44) {
+        0 => Supercompression::None,
+        1 => Supercompression::BasisLz,
+        2 => Supercompression::Zstandard,
+        3 => Supercompression::Zlib,
+        scheme => return Err(anyhow!("Unknown KTX2 supercompression scheme {}.", scheme)),
+    };
+
+    if depth > 1 || layers > 1 || faces != 1 {
+        return Err(anyhow!("Only 2D KTX2 textures are supported."));
+    }
+
+    check_texture_size(width, height, level_count)?;
+    let index_end = HEADER_SIZE + level_count as usize * LEVEL_INDEX_ENTRY_SIZE;
+    if bytes.len() < index_end {
+        return Err(anyhow!("Truncated KTX2 level index."));
+    }
+
+    // Basis Universal payloads have no Vulkan format; the data format
+    // descriptor says which of the two they are.
+    let (basis, srgb) = if format == vk::Format::UNDEFINED {
+        let dfd = read_u32(bytes, 48) as usize;
+        let (model, transfer) = bytes
+            .get(dfd + 12..dfd + 15)
+            .map(|d| (d[0], d[2]))
+            .ok_or_else(|| anyhow!("Truncated KTX2 data format descriptor."))?;
+        (Some(model), transfer == KHR_DF_TRANSFER_SRGB)
+    } else {
+        (None, false)
+    };
+    let etc1s = match (basis, supercompression) {
+        (Some(KHR_DF_MODEL_ETC1S), Supercompression::BasisLz) => {
+            let offset = read_u64(bytes, 64) as usize;
+            let length = read_u64(bytes, 72) as usize;
+            let global_data = offset
+                .checked_add(length)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| anyhow!("KTX2 supercompression global data is out of bounds."))?;
+            Some(Etc1s::new(global_data, level_count as usize)?)
+        }
+        (Some(KHR_DF_MODEL_UASTC), scheme) if scheme != Supercompression::BasisLz => None,
+        (None, Supercompression::BasisLz) | (Some(KHR_DF_MODEL_ETC1S | KHR_DF_MODEL_UASTC), _) => {
+            return Err(anyhow!("Unsupported KTX2 Basis Universal payload."));
+        }
+        (None, _) => None,
+        (Some(model), _) => return Err(anyhow!("Unknown KTX2 color model {}.", model)),
+    };
+
+    let mut levels = Vec::with_capacity(level_count as usize);
+    for level in 0..level_count as usize {
+        let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+        let offset = read_u64(bytes, entry) as usize;
+        let length = read_u64(bytes, entry + 8) as usize;
+        let uncompressed_length = read_u64(bytes, entry + 16) as usize;
+
+        let payload = offset
+            .checked_add(length)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| anyhow!("KTX2 level {} is out of bounds.", level))?;
+
+        let level_width = (width >> level).max(1);
+        let level_height = (height >> level).max(1);
+        if let Some(etc1s) = &etc1s {
+            levels.push(etc1s.transcode(level, payload, level_width, level_height)?);
+            continue;
+        }
+
+        // Decompressed into no more than the level's size, whatever the
+        // index claims.
+        let size = match basis {
+            Some(_) => uastc_size(level_width, level_height),
+            None => level_size(format, level_width, level_height),
+        }
+        .ok_or_else(|| anyhow!("Unsupported KTX2 format {:?}.", format))?;
+        if supercompression != Supercompression::None && uncompressed_length != size {
+            return Err(anyhow!("KTX2 level {} has the wrong size.", level));
+        }
+
+        let pixels = match supercompression {
+            Supercompression::None => payload.to_vec(),
+            Supercompression::Zlib => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(payload, size)
+                .map_err(|e| anyhow!("Failed to inflate KTX2 level {}: {:?}", level, e))?,
+            Supercompression::Zstandard => {
+                let mut pixels = Vec::with_capacity(size);
+                FrameDecoder::new()
+                    .decode_all_to_vec(payload, &mut pixels)
+                    .map_err(|e| anyhow!("Failed to decompress KTX2 level {}: {}", level, e))?;
+                pixels
+            }
+            Supercompression::BasisLz => unreachable!(),
+        };
+        if pixels.len() != size {
+            return Err(anyhow!("KTX2 level {} has the wrong size.", level));
+        }
+
+        levels.push(match basis {
+            Some(_) => transcode_uastc(&pixels, level_width, level_height, srgb)?,
+            None => pixels,
+        });
+    }
+
+    let format = match (basis, srgb) {
+        (None, _) => format,
+        (Some(_), true) => vk::Format::R8G8B8A8_SRGB,
+        (Some(_), false) => vk::Format::R8G8B8A8_UNORM,
+    };
+
+    Ok(TextureData {
+        width,
+        height,
+        format,
+        levels,
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x8 RGBA gradient, `(x * 32, y * 32, 128, 255)`, as compressed by
+    /// the reference `zstd -19`.
+    const GRADIENT_ZSTD: &[u8] = &[
+        0x28, 0xB5, 0x2F, 0xFD, 0x60, 0x00, 0x00, 0xAD, 0x03, 0x00, 0x06, 0x50,
+        0x1C, 0x0D, 0xE0, 0xE9, 0x68, 0xA1, 0x43, 0x87, 0x46, 0x14, 0x3A, 0x74,
+        0x68, 0x2C, 0x03, 0x17, 0x00, 0x18, 0x00, 0x16, 0x00, 0x0B, 0xB5, 0x40,
+        0x0B, 0xB3, 0xE0, 0x82, 0x2C, 0xC4, 0x02, 0x2C, 0x6C, 0x57, 0x3B, 0xDA,
+        0xCD, 0xCE, 0x9D, 0xEC, 0x62, 0x07, 0xBB, 0x05, 0x2B, 0xB5, 0x42, 0x2B,
+        0xB3, 0xE2, 0x8A, 0xAC, 0xC4, 0x0A, 0xAC, 0x6C, 0xA3, 0x36, 0x68, 0x63,
+        0x36, 0xDC, 0x90, 0x8D, 0xD8, 0x80, 0x8D, 0x05, 0x3B, 0xB5, 0x43, 0x3B,
+        0xB3, 0xE3, 0x8E, 0xEC, 0xC4, 0x0E, 0xEC, 0x6C, 0xAD, 0x95, 0xD6, 0x59,
+        0x5D, 0x65, 0x8D, 0x15, 0xD6, 0x05, 0x5B, 0xB5, 0x45, 0x5B, 0xB3, 0xE5,
+        0x96, 0x6C, 0xC5, 0x16, 0x6C, 0x6D, 0xA9, 0x96, 0x68, 0x69, 0x96, 0x5C,
+        0x92, 0xA5, 0x58, 0x82, 0xA5, 0x05, 0x00,
+    ];
+
+    fn gradient() -> Vec<u8> {
+        (0..8u8).flat_map(|y| (0..8u8).flat_map(move |x| [x * 32, y * 32, 128, 255])).collect()
+    }
+
+    /// A KTX2 file of one 8x8 level.
+    fn ktx2(format: vk::Format, supercompression: u32, payload: &[u8], uncompressed_length: usize) -> Vec<u8> {
+        let mut bytes = IDENTIFIER.to_vec();
+        for value in [format.as_raw() as u32, 1, 8, 8, 0, 0, 1, 1, supercompression] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.resize(HEADER_SIZE, 0);
+        let offset = HEADER_SIZE + LEVEL_INDEX_ENTRY_SIZE;
+        for value in [offset, payload.len(), uncompressed_length] {
+            bytes.extend((value as u64).to_le_bytes());
+        }
+        bytes.extend(payload);
+        bytes
+    }
+
+    #[test]
+    fn zstandard_levels_are_decompressed() {
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 2, GRADIENT_ZSTD, 256);
+        let texture = load_ktx2(&bytes).unwrap();
+        assert_eq!((texture.width, texture.height, texture.format), (8, 8, vk::Format::R8G8B8A8_UNORM));
+        assert_eq!(texture.levels, vec![gradient()]);
+    }
+
+    #[test]
+    fn zstandard_levels_of_the_wrong_size_are_rejected() {
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 2, GRADIENT_ZSTD, 255);
+        assert!(load_ktx2(&bytes).is_err());
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 2, &GRADIENT_ZSTD[..64], 256);
+        assert!(load_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn level_sizes_are_checked_against_the_dimensions() {
+        // The index's claim is checked before anything is allocated for it.
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 2, GRADIENT_ZSTD, usize::MAX);
+        assert!(load_ktx2(&bytes).is_err());
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 0, &gradient()[..252], 252);
+        assert!(load_ktx2(&bytes).is_err());
+        let bytes = ktx2(vk::Format::R8G8_UNORM, 0, &gradient(), 256);
+        assert!(load_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn more_levels_than_the_size_has_are_rejected() {
+        let mut bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 0, &gradient(), 256);
+        bytes[40..44].copy_from_slice(&5u32.to_le_bytes());
+        assert!(load_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn uncompressed_levels_are_copied() {
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 0, &gradient(), 256);
+        assert_eq!(load_ktx2(&bytes).unwrap().levels, vec![gradient()]);
+    }
+
+    /// A Basis Universal KTX2 file of one 8x8 level, with a data format
+    /// descriptor of `model` in sRGB.
+    fn basis_ktx2(model: u8, supercompression: u32, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = ktx2(vk::Format::UNDEFINED, supercompression, payload, payload.len());
+        let dfd = bytes.len();
+        bytes[48..52].copy_from_slice(&(dfd as u32).to_le_bytes());
+        bytes.extend([28, 0, 0, 0, 0, 0, 0, 0, 2, 0, 24, 0, model, 1, KHR_DF_TRANSFER_SRGB, 0]);
+        bytes
+    }
+
+    #[test]
+    fn uastc_levels_are_transcoded() {
+        // Four solid mode 8 blocks.
+        let block = (0x17u128 | 10 << 5 | 20 << 13 | 30 << 21 | 40 << 29).to_le_bytes();
+        let bytes = basis_ktx2(KHR_DF_MODEL_UASTC, 0, &block.repeat(4));
+        let texture = load_ktx2(&bytes).unwrap();
+        assert_eq!(texture.format, vk::Format::R8G8B8A8_SRGB);
+        assert_eq!(texture.levels, vec![[10, 20, 30, 40].repeat(64)]);
+
+        assert!(load_ktx2(&basis_ktx2(KHR_DF_MODEL_UASTC, 0, &block.repeat(3))).is_err());
+    }
+
+    #[test]
+    fn unsupported_basis_universal_payloads_are_rejected() {
+        assert!(load_ktx2(&basis_ktx2(KHR_DF_MODEL_UASTC, 1, &[0; 64])).is_err());
+        assert!(load_ktx2(&basis_ktx2(KHR_DF_MODEL_ETC1S, 0, &[0; 64])).is_err());
+        assert!(load_ktx2(&basis_ktx2(1, 0, &[0; 64])).is_err());
+        assert!(load_ktx2(&ktx2(vk::Format::R8G8B8A8_UNORM, 1, &[0; 16], 0)).is_err());
+    }
+}
@@ -1,28 +1,146 @@
+mod allocations;
+mod ambient_occlusion;
+mod animation;
 mod app;
+mod asset_loader;
+mod assets;
+mod barrier;
+mod billboard;
+mod bindless;
+mod basis;
+mod block_compression;
+mod camera;
+mod color_grading;
 mod command_buffer;
+mod dds;
 mod debug;
+mod debug_draw;
+mod debug_view;
+mod decal;
 mod depth_object;
+mod descriptor_allocator;
+mod descriptor_cache;
 mod descriptor_layout;
 mod descriptor_pool;
+mod device_features;
+mod dynamic_buffer;
+mod dynamic_rendering;
+mod entity;
+mod exposure;
+mod focus_blur;
 mod framebuffer;
 mod generate_mipmaps;
+mod gpu_culling;
+mod gpu_particles;
+mod grid;
+mod hdr;
+mod headless;
+mod histogram;
 mod image;
+mod impostor;
 mod instance;
+mod json;
+mod ktx2;
+mod light_probe;
+mod lod;
 mod logical_device;
+mod mapped_memory;
+mod material;
+mod memory_budget;
 mod mesh;
+mod mesh_pool;
+mod meshlet;
+mod mipmap;
 mod model;
 mod msaa;
+mod oit;
+mod openxr;
+mod particles;
+mod paths;
 mod physical_device;
+mod portability;
 mod pipeline;
+mod pipeline_cache;
+mod pipeline_compiler;
+mod push_constants;
+mod ray_tracing;
+mod reduction;
+mod reflect;
 mod render_pass;
+mod render_queue;
+mod render_thread;
+mod sampler;
+mod scene;
+mod scene_recorder;
+mod secondary_window;
+mod self_test;
+mod settings;
 mod shader;
+mod shadows;
 mod single_time_cmd;
+mod skinning;
+mod sprite_batch;
+mod static_batch;
+mod stereo;
+mod streaming;
 mod swapchain;
 mod sync_objects;
 mod texture;
+mod texture_atlas;
+mod tonemap;
 mod types;
 mod uniform_buffer;
 mod vertex_buffer;
 mod vertex;
+mod viewmodel;
+mod viewport;
+mod visibility;
+mod window_mode;
+mod world;
 
-pub use app::App;
\ No newline at end of file
+pub use allocations::{Allocation, AllocationKind, AllocationReport};
+pub use ambient_occlusion::AmbientOcclusion;
+pub use animation::{AnimationClip, AnimationPlayer, Channel, Interpolation, Keyframes};
+pub use app::App;
+pub use asset_loader::{AssetCallback, AssetEvent};
+pub use assets::{MeshHandle, TextureHandle};
+pub use billboard::{Billboard, BillboardFacing};
+pub use camera::Camera;
+pub use debug_draw::DebugDraw;
+pub use debug_view::DebugView;
+pub use decal::Decal;
+pub use device_features::{DeviceCapabilities, DeviceFeature};
+pub use entity::{Entity, Mobility};
+pub use exposure::{AutoExposure, MeteringMode};
+pub use focus_blur::FocusBlur;
+pub use gpu_particles::GpuParticleEmitter;
+pub use headless::Headless;
+pub use impostor::Impostor;
+pub use light_probe::{LightProbe, LightProbeGrid, LightProbes, SphericalHarmonics};
+pub use lod::LevelOfDetail;
+pub use material::{Material, MaterialHandle, MaterialInstance, MaterialParameters, Shading};
+pub use memory_budget::{FrameStats, HeapBudget};
+pub use particles::{Particle, ParticleEmitter};
+pub use paths::Directories;
+pub use physical_device::{GpuSelector, GPU_OVERRIDE_VAR};
+pub use portability::PortabilitySubset;
+pub use render_queue::{DrawOrder, RenderQueue};
+pub use render_thread::{RenderMessage, RenderThread};
+pub use sampler::SamplerDesc;
+pub use scene::Scene;
+pub use self_test::{SelfTestCheck, SelfTestReport};
+pub use settings::Settings;
+pub use shadows::Sun;
+pub use skinning::{Joint, JointTransform, Pose, Skeleton};
+pub use sprite_batch::{SpriteAtlas, SpriteBatch};
+pub use stereo::Stereo;
+pub use streaming::TextureStreaming;
+pub use swapchain::SwapchainColorSpace;
+pub use texture::{ColorSpace, CubemapSource};
+pub use texture_atlas::AtlasRegion;
+pub use tonemap::ResolveMode;
+pub use viewmodel::{CameraLayer, Viewmodel, ViewmodelDepth};
+pub use viewport::{Viewport, MAX_VIEWPORTS};
+pub use visibility::{EntityVisibility, VisibilityCallback};
+pub use window_mode::WindowMode;
+pub use world::{Handedness, UpAxis, WorldConfig};
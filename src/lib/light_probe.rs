@@ -0,0 +1,203 @@
+use cgmath::{vec3, vec4, ElementWise, InnerSpace, Zero};
+use std::f32::consts::PI;
+
+use crate::types::{Vec3, Vec4};
+
+const SH_Y0: f32 = 0.282_095;
+const SH_Y1: f32 = 0.488_603;
+
+/// Directions used to project radiance onto the SH basis when baking.
+const BAKE_SAMPLES: usize = 256;
+
+/// Order 1 (four coefficient) spherical harmonics of incoming radiance, in
+/// `[L00, L1-1 (y), L10 (z), L11 (x)]` order. L1 is enough for smooth
+/// ambient light and keeps a probe small enough to push per draw.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SphericalHarmonics {
+    pub coefficients: [Vec3; 4],
+}
+
+impl Default for SphericalHarmonics {
+    fn default() -> Self {
+        Self {
+            coefficients: [Vec3::zero(); 4],
+        }
+    }
+}
+
+impl SphericalHarmonics {
+    /// Radiance arriving equally from every direction.
+    pub fn uniform(radiance: Vec3) -> Self {
+        let mut sh = Self::default();
+        sh.coefficients[0] = radiance * (4.0 * PI * SH_Y0);
+        sh
+    }
+
+    /// Projects `radiance(direction)` onto the basis.
+    pub fn project(radiance: impl Fn(Vec3) -> Vec3) -> Self {
+        let mut sh = Self::default();
+        let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+        for i in 0..BAKE_SAMPLES {
+            let z = 1.0 - (2.0 * i as f32 + 1.0) / BAKE_SAMPLES as f32;
+            let r = (1.0 - z * z).sqrt();
+            let (s, c) = (golden_angle * i as f32).sin_cos();
+            let direction = vec3(r * c, r * s, z);
+            let sample = radiance(direction);
+            sh.coefficients[0] += sample * SH_Y0;
+            sh.coefficients[1] += sample * (SH_Y1 * direction.y);
+            sh.coefficients[2] += sample * (SH_Y1 * direction.z);
+            sh.coefficients[3] += sample * (SH_Y1 * direction.x);
+        }
+        sh.scale(4.0 * PI / BAKE_SAMPLES as f32)
+    }
+
+    pub fn scale(mut self, factor: f32) -> Self {
+        self.coefficients.iter_mut().for_each(|c| *c *= factor);
+        self
+    }
+
+    /// Returns `self + other * weight`.
+    pub fn blend(mut self, other: &Self, weight: f32) -> Self {
+        for (c, o) in self.coefficients.iter_mut().zip(&other.coefficients) {
+            *c += *o * weight;
+        }
+        self
+    }
+
+    /// Irradiance coefficients for the fragment shader, convolved with the
+    /// cosine lobe and packed one color channel per vector so that
+    /// `E(n) = dot(c, vec4(1, n.y, n.z, n.x))`.
+    pub(crate) fn irradiance(&self) -> [Vec4; 3] {
+        let bands = [PI * SH_Y0, 2.0 * PI / 3.0 * SH_Y1, 2.0 * PI / 3.0 * SH_Y1, 2.0 * PI / 3.0 * SH_Y1];
+        let c = &self.coefficients;
+        let channel = |i: usize| {
+            vec4(c[0][i], c[1][i], c[2][i], c[3][i]).mul_element_wise(Vec4::from(bands))
+        };
+        [channel(0), channel(1), channel(2)]
+    }
+}
+
+/// A probe placed by hand.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightProbe {
+    pub position: Vec3,
+    pub sh: SphericalHarmonics,
+}
+
+/// Probes on a regular grid spanning `min..max`, `counts` probes per axis,
+/// stored X fastest then Y then Z.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LightProbeGrid {
+    pub min: Vec3,
+    pub max: Vec3,
+    pub counts: [usize; 3],
+    pub probes: Vec<SphericalHarmonics>,
+}
+
+impl LightProbeGrid {
+    /// Bakes every probe from `radiance(position, direction)`.
+    pub fn bake(
+        min: Vec3,
+        max: Vec3,
+        counts: [usize; 3],
+        radiance: impl Fn(Vec3, Vec3) -> Vec3,
+    ) -> Self {
+        let counts = counts.map(|c| c.max(1));
+        let mut grid = Self {
+            min,
+            max,
+            counts,
+            probes: Vec::with_capacity(counts[0] * counts[1] * counts[2]),
+        };
+        for z in 0..counts[2] {
+            for y in 0..counts[1] {
+                for x in 0..counts[0] {
+                    let position = grid.probe_position([x, y, z]);
+                    grid.probes.push(SphericalHarmonics::project(|d| radiance(position, d)));
+                }
+            }
+        }
+        grid
+    }
+
+    pub fn probe_position(&self, [x, y, z]: [usize; 3]) -> Vec3 {
+        let t = |i: usize, axis: usize| {
+            if self.counts[axis] > 1 {
+                i as f32 / (self.counts[axis] - 1) as f32
+            } else {
+                0.5
+            }
+        };
+        let extent = self.max - self.min;
+        self.min + vec3(extent.x * t(x, 0), extent.y * t(y, 1), extent.z * t(z, 2))
+    }
+
+    /// Trilinearly interpolates the probes around `position`, clamping to the
+    /// grid bounds.
+    pub fn sample(&self, position: Vec3) -> SphericalHarmonics {
+        let extent = self.max - self.min;
+        let local = position - self.min;
+        let mut cell = [0usize; 3];
+        let mut fraction = [0.0f32; 3];
+        for axis in 0..3 {
+            let last = self.counts[axis] - 1;
+            let t = if extent[axis] > 0.0 { local[axis] / extent[axis] } else { 0.0 };
+            let f = (t.clamp(0.0, 1.0) * last as f32).min(last as f32);
+            cell[axis] = (f.floor() as usize).min(last.saturating_sub(1));
+            fraction[axis] = if last == 0 { 0.0 } else { f - cell[axis] as f32 };
+        }
+
+        let mut sh = SphericalHarmonics::default();
+        for corner in 0..8 {
+            let mut index = [0usize; 3];
+            let mut weight = 1.0;
+            for axis in 0..3 {
+                let upper = corner & (1 << axis) != 0;
+                index[axis] = (cell[axis] + upper as usize).min(self.counts[axis] - 1);
+                weight *= if upper { fraction[axis] } else { 1.0 - fraction[axis] };
+            }
+            if weight > 0.0 {
+                sh = sh.blend(self.probe(index), weight);
+            }
+        }
+        sh
+    }
+
+    fn probe(&self, [x, y, z]: [usize; 3]) -> &SphericalHarmonics {
+        &self.probes[(z * self.counts[1] + y) * self.counts[0] + x]
+    }
+}
+
+/// Ambient lighting for entities, sampled at each entity's origin.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum LightProbes {
+    /// Uniform white ambient, which leaves textures unlit.
+    #[default]
+    None,
+    Grid(LightProbeGrid),
+    /// Blended by inverse squared distance.
+    Placed(Vec<LightProbe>),
+}
+
+impl LightProbes {
+    pub fn sample(&self, position: Vec3) -> SphericalHarmonics {
+        match self {
+            LightProbes::None => SphericalHarmonics::uniform(vec3(1.0, 1.0, 1.0)),
+            LightProbes::Grid(grid) => grid.sample(position),
+            LightProbes::Placed(probes) if probes.is_empty() => {
+                SphericalHarmonics::uniform(vec3(1.0, 1.0, 1.0))
+            }
+            LightProbes::Placed(probes) => {
+                let weights = probes
+                    .iter()
+                    .map(|p| 1.0 / (p.position - position).magnitude2().max(1e-4))
+                    .collect::<Vec<_>>();
+                let total = weights.iter().sum::<f32>();
+                probes
+                    .iter()
+                    .zip(weights)
+                    .fold(SphericalHarmonics::default(), |sh, (p, w)| sh.blend(&p.sh, w / total))
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::app::AppData;
+
+/// Most levels of detail a model has, the full mesh included. `SceneKey`
+/// packs each entity's level into two bits.
+pub(crate) const MAX_LODS: usize = 4;
+
+/// How far generated levels may move the surface, relative to the mesh's
+/// extent, see `MeshData::simplified`.
+pub(crate) const LOD_TARGET_ERROR: f32 = 0.02;
+
+/// One level of detail of the model: a range of its index buffer. Every
+/// level's indices point straight into the shared vertex buffer, and each
+/// level's range starts where the one before ends.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Lod {
+    pub(crate) first_index: u32,
+    pub(crate) index_count: u32,
+}
+
+/// Draws entities with simpler versions of the model as they shrink on
+/// screen. The levels come from files next to the model or are generated
+/// when it is loaded, see `read_model`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LevelOfDetail {
+    /// When unset every entity draws the full mesh.
+    pub enabled: bool,
+    /// The `EntityVisibility::screen_size` below which each level after the
+    /// full mesh is drawn, largest first.
+    pub screen_sizes: [f32; MAX_LODS - 1],
+    /// The screen size below which movable world entities are drawn as the
+    /// impostor baked by `App::bake_impostor`, if there is one.
+    pub impostor_screen_size: f32,
+    /// How far past a threshold, as a fraction of it, the screen size has to
+    /// move before an entity switches level or impostor, so that one
+    /// hovering around a threshold does not pop back and forth.
+    pub hysteresis: f32,
+}
+
+impl Default for LevelOfDetail {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            screen_sizes: [0.5, 0.25, 0.1],
+            impostor_screen_size: 0.05,
+            hysteresis: 0.1,
+        }
+    }
+}
+
+impl LevelOfDetail {
+    /// The level to draw an entity covering `screen_size` with, given the
+    /// level it was drawn with last frame and how many the model has.
+    pub(crate) fn select(&self, screen_size: f32, current: usize, levels: usize) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+        let level = |scale: f32| {
+            self.screen_sizes
+                .iter()
+                .take(levels.saturating_sub(1))
+                .filter(|threshold| screen_size < *threshold * scale)
+                .count()
+        };
+        // Only leave the current level once well past a threshold.
+        current.clamp(level(1.0 - self.hysteresis), level(1.0 + self.hysteresis))
+    }
+
+    /// Whether an entity covering `screen_size` is drawn as an impostor,
+    /// given whether it was last frame.
+    pub(crate) fn impostor(&self, screen_size: f32, current: bool) -> bool {
+        let scale = if current { 1.0 + self.hysteresis } else { 1.0 - self.hysteresis };
+        self.enabled && screen_size < self.impostor_screen_size * scale
+    }
+}
+
+/// The range of the model's index buffer to draw `level` with, or its
+/// coarsest level if it has fewer.
+pub(crate) fn model_lod(data: &AppData, level: usize) -> Lod {
+    data.lods.get(level).or(data.lods.last()).copied().unwrap_or_default()
+}
@@ -1,15 +1,14 @@
 use anyhow::Result;
-use std::collections::HashSet;
+use std::{collections::HashSet, ffi::CStr};
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
-    app::{AppData, VALIDATION_ENABLED, VALIDATION_LAYER, PORTABILITY_MACOS_VERSION, DEVICE_EXTENSIONS},
-    physical_device::QueueFamilyIndices,
+    app::{AppData, VALIDATION_ENABLED, VALIDATION_LAYER, DEVICE_EXTENSIONS, VULKAN_1_1, VULKAN_1_3},
+    physical_device::{supports_device_extension, QueueFamilyIndices},
 };
 
 pub(crate) unsafe fn create_logical_device(
-  entry: &Entry,
   instance: &Instance,
   data: &mut AppData,
 ) -> Result<Device> {
@@ -18,6 +17,7 @@ pub(crate) unsafe fn create_logical_device(
   let mut unique_indices = HashSet::new();
   unique_indices.insert(indices.graphics);
   unique_indices.insert(indices.present);
+  unique_indices.insert(indices.transfer);
 
   let queue_priorities = &[1.0];
   let queue_infos = unique_indices
@@ -35,22 +35,151 @@ pub(crate) unsafe fn create_logical_device(
       vec![]
   };
 
-  let mut extensions = DEVICE_EXTENSIONS
+  // Headless contexts have no swapchain.
+  let mut extensions = if data.headless { &[] } else { DEVICE_EXTENSIONS }
       .iter()
       .map(|n| n.as_ptr())
       .collect::<Vec<_>>();
 
-  if cfg!(target_os = "macos") && entry.version().unwrap() >= PORTABILITY_MACOS_VERSION {
+  // Required wherever the device has it, as on MoltenVK.
+  if data.portability.enabled {
       extensions.push(vk::KHR_PORTABILITY_SUBSET_EXTENSION.name.as_ptr());
   }
 
-  let features = vk::PhysicalDeviceFeatures::builder().sampler_anisotropy(true);
+  if data.descriptor_indexing
+      && supports_device_extension(instance, data.physical_device, vk::EXT_DESCRIPTOR_INDEXING_EXTENSION.name)
+  {
+      extensions.push(vk::EXT_DESCRIPTOR_INDEXING_EXTENSION.name.as_ptr());
+  }
+
+  if data.push_descriptors {
+      extensions.push(vk::KHR_PUSH_DESCRIPTOR_EXTENSION.name.as_ptr());
+  }
+
+  if data.load_store_op_none {
+      extensions.push(vk::EXT_LOAD_STORE_OP_NONE_EXTENSION.name.as_ptr());
+  }
+
+  if data.draw_indirect_count {
+      extensions.push(vk::KHR_DRAW_INDIRECT_COUNT_EXTENSION.name.as_ptr());
+  }
+
+  if data.mesh_shader {
+      extensions.push(vk::EXT_MESH_SHADER_EXTENSION.name.as_ptr());
+  }
+
+  if data.ray_tracing {
+      extensions.push(vk::KHR_ACCELERATION_STRUCTURE_EXTENSION.name.as_ptr());
+      extensions.push(vk::KHR_RAY_TRACING_PIPELINE_EXTENSION.name.as_ptr());
+      extensions.push(vk::KHR_DEFERRED_HOST_OPERATIONS_EXTENSION.name.as_ptr());
+  }
+
+  if data.ray_query {
+      extensions.push(vk::KHR_RAY_QUERY_EXTENSION.name.as_ptr());
+  }
+
+  if data.memory_budget {
+      extensions.push(vk::EXT_MEMORY_BUDGET_EXTENSION.name.as_ptr());
+  }
+
+  if data.full_screen_exclusive {
+      extensions.push(vk::EXT_FULL_SCREEN_EXCLUSIVE_EXTENSION.name.as_ptr());
+  }
+
+  if data.timeline_semaphore_extension {
+      extensions.push(vk::KHR_TIMELINE_SEMAPHORE_EXTENSION.name.as_ptr());
+  }
+
+  for extension in &data.xr_device_extensions {
+      if !extensions.iter().any(|e| CStr::from_ptr(*e) == extension.as_c_str()) {
+          extensions.push(extension.as_ptr());
+      }
+  }
+
+  let features = data.device_features.to_vk();
+
+  // From Vulkan 1.2 the features of each core version are enabled through
+  // one struct per version, which may not be chained with the older structs
+  // of the same features.
+  let core = data.capabilities.core_feature_chain();
+
+  let mut vulkan_11_features = vk::PhysicalDeviceVulkan11Features::builder()
+      .multiview(data.multiview);
+
+  let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::builder()
+      .descriptor_binding_partially_bound(data.descriptor_indexing)
+      .descriptor_binding_variable_descriptor_count(data.descriptor_indexing)
+      .descriptor_binding_sampled_image_update_after_bind(data.descriptor_indexing)
+      .timeline_semaphore(data.capabilities.timeline_semaphore)
+      .buffer_device_address(data.ray_tracing);
+
+  let mut vulkan_13_features = vk::PhysicalDeviceVulkan13Features::builder()
+      .dynamic_rendering(data.dynamic_rendering)
+      .synchronization2(data.capabilities.synchronization2);
+
+  let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::builder()
+      .descriptor_binding_partially_bound(true)
+      .descriptor_binding_variable_descriptor_count(true)
+      .descriptor_binding_sampled_image_update_after_bind(true);
+
+  let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder()
+      .timeline_semaphore(true);
+
+  let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::builder()
+      .multiview(true);
+
+  let mut mesh_shader_features = vk::PhysicalDeviceMeshShaderFeaturesEXT::builder()
+      .task_shader(true)
+      .mesh_shader(true);
 
-  let info = vk::DeviceCreateInfo::builder()
+  let mut acceleration_structure_features = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::builder()
+      .acceleration_structure(true);
+
+  let mut ray_tracing_pipeline_features = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::builder()
+      .ray_tracing_pipeline(true);
+
+  let mut ray_query_features = vk::PhysicalDeviceRayQueryFeaturesKHR::builder()
+      .ray_query(true);
+
+  let mut portability_features = data.portability.to_vk();
+
+  let mut info = vk::DeviceCreateInfo::builder()
       .queue_create_infos(&queue_infos)
       .enabled_layer_names(&layers)
       .enabled_extension_names(&extensions)
       .enabled_features(&features);
+  if core {
+      info = info
+          .push_next(&mut vulkan_11_features)
+          .push_next(&mut vulkan_12_features);
+      if data.capabilities.api_version >= VULKAN_1_3 {
+          info = info.push_next(&mut vulkan_13_features);
+      }
+  } else {
+      if data.capabilities.timeline_semaphore {
+          info = info.push_next(&mut timeline_features);
+      }
+      if data.descriptor_indexing {
+          info = info.push_next(&mut indexing_features);
+      }
+      if data.multiview {
+          info = info.push_next(&mut multiview_features);
+      }
+  }
+  if data.mesh_shader {
+      info = info.push_next(&mut mesh_shader_features);
+  }
+  if data.ray_tracing {
+      info = info
+          .push_next(&mut acceleration_structure_features)
+          .push_next(&mut ray_tracing_pipeline_features);
+  }
+  if data.ray_query {
+      info = info.push_next(&mut ray_query_features);
+  }
+  if data.portability.enabled && data.instance_version >= u32::from(VULKAN_1_1) {
+      info = info.push_next(&mut portability_features);
+  }
 
   let device = instance
       .create_device(data.physical_device, &info, None)
@@ -58,6 +187,7 @@ pub(crate) unsafe fn create_logical_device(
 
   data.graphics_queue = device.get_device_queue(indices.graphics, 0);
   data.present_queue = device.get_device_queue(indices.present, 0);
+  data.transfer_queue = device.get_device_queue(indices.transfer, 0);
 
   Ok(device)
 }
\ No newline at end of file
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use std::{mem::size_of_val, ptr::copy_nonoverlapping as memcpy};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{allocations::free_memory, app::AppData, vertex_buffer::create_buffer};
+
+/// Host visible memory mapped from creation until `free`, for buffers the
+/// CPU rewrites every frame. Memory that is not host coherent is flushed
+/// after each write, in whole `nonCoherentAtomSize` blocks.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct MappedMemory {
+    pub(crate) memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    /// The size of the allocation, which flushed ranges may end at.
+    size: vk::DeviceSize,
+    /// Zero for host coherent memory.
+    atom_size: vk::DeviceSize,
+}
+
+// The mapping is only written through `write`, whose callers already have
+// to keep frames from reading what is written, and secondary command
+// buffers recorded on other threads never touch it.
+unsafe impl Send for MappedMemory {}
+unsafe impl Sync for MappedMemory {}
+
+impl Default for MappedMemory {
+    fn default() -> Self {
+        Self { memory: vk::DeviceMemory::null(), mapped: std::ptr::null_mut(), size: 0, atom_size: 0 }
+    }
+}
+
+impl MappedMemory {
+    /// Copies `items` to `offset` bytes in, which no frame may be reading.
+    pub(crate) unsafe fn write<T>(&self, device: &Device, offset: vk::DeviceSize, items: &[T]) {
+        memcpy(items.as_ptr(), self.mapped.add(offset as usize).cast(), items.len());
+        if self.atom_size == 0 {
+            return;
+        }
+
+        // The range has to start and end on atoms, or end with the
+        // allocation.
+        let start = offset - offset % self.atom_size;
+        let end = (offset + size_of_val(items) as u64).next_multiple_of(self.atom_size).min(self.size);
+        let range = vk::MappedMemoryRange::builder()
+            .memory(self.memory)
+            .offset(start)
+            .size(end - start);
+        device.flush_mapped_memory_ranges(&[range]).unwrap();
+    }
+
+    pub(crate) unsafe fn free(&mut self, device: &Device) {
+        if self.memory.is_null() {
+            return;
+        }
+        device.unmap_memory(self.memory);
+        free_memory(device, self.memory);
+        *self = Self::default();
+    }
+}
+
+/// Creates a buffer in host visible memory that stays mapped, host coherent
+/// where the device has any that fits.
+#[track_caller]
+pub(crate) unsafe fn create_mapped_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, MappedMemory)> {
+    let coherent = vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE;
+    let (buffer, memory, atom_size) = match create_buffer(instance, device, data, size, usage, coherent) {
+        Ok((buffer, memory)) => (buffer, memory, 0),
+        Err(_) => {
+            let (buffer, memory) =
+                create_buffer(instance, device, data, size, usage, vk::MemoryPropertyFlags::HOST_VISIBLE)?;
+            let properties = instance.get_physical_device_properties(data.physical_device);
+            (buffer, memory, properties.limits.non_coherent_atom_size.max(1))
+        }
+    };
+
+    // What `create_buffer` allocated.
+    let size = device.get_buffer_memory_requirements(buffer).size;
+    match device.map_memory(memory, 0, vk::WHOLE_SIZE as u64, vk::MemoryMapFlags::empty()) {
+        Ok(mapped) => Ok((buffer, MappedMemory { memory, mapped: mapped.cast(), size, atom_size })),
+        Err(e) => {
+            device.destroy_buffer(buffer, None);
+            free_memory(device, memory);
+            Err(e.into())
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Result};
+use log::*;
+use std::{collections::HashMap, mem::size_of};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    mapped_memory::create_mapped_buffer,
+    pipeline::{create_scene_pipeline, ScenePipelineDesc, SceneVariant},
+    texture_atlas::resolve_texture,
+    types::Vec4,
+};
+
+/// Blocks one frame's material buffer holds, counting the default block.
+pub(crate) const MAX_MATERIAL_BLOCKS: usize = 4096;
+
+/// A texture index the scene fragment shader reads as no texture.
+const NO_TEXTURE: u32 = u32::MAX;
+
+/// How a material's surfaces are shaded. Each is a specialization of the
+/// scene fragment shader, with pipelines of its own.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Shading {
+    /// By the sun and the light probes, like entities without a material.
+    #[default]
+    Lit = 0,
+    /// The texture times the base color, as if emitted, for screens and
+    /// signs that should read the same in any light.
+    Unlit = 1,
+}
+
+/// What each instance of a material can set for itself.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MaterialParameters {
+    /// Multiplies the texture, its alpha multiplying `Entity::opacity`.
+    pub base_color: Vec4,
+    /// Light added to the shaded color, before exposure. `w` is unused.
+    pub emissive: Vec4,
+}
+
+impl Default for MaterialParameters {
+    fn default() -> Self {
+        Self { base_color: Vec4::new(1.0, 1.0, 1.0, 1.0), emissive: Vec4::new(0.0, 0.0, 0.0, 0.0) }
+    }
+}
+
+/// A shading and the textures every entity drawn with it samples. Textures
+/// are texture table slots or atlas regions, like `Entity::texture`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Material {
+    pub shading: Shading,
+    /// Sampled instead of each entity's own texture.
+    pub texture: Option<u32>,
+    /// Multiplies `MaterialParameters::emissive`.
+    pub emissive_texture: Option<u32>,
+    /// What new instances start with.
+    pub parameters: MaterialParameters,
+}
+
+/// A material from `App::create_material`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialHandle(pub(crate) u32);
+
+/// Parameters for a material from `App::create_material_instance`, for
+/// `Entity::material`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MaterialInstance(pub(crate) u32);
+
+/// An instance's block of the material buffer, as the scene fragment shader
+/// reads it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct MaterialBlock {
+    pub(crate) parameters: MaterialParameters,
+    /// Resolved texture indices, or `NO_TEXTURE`.
+    pub(crate) texture: u32,
+    pub(crate) emissive_texture: u32,
+    pub(crate) _padding: [u32; 2],
+}
+
+impl Default for MaterialBlock {
+    fn default() -> Self {
+        Self {
+            parameters: MaterialParameters::default(),
+            texture: NO_TEXTURE,
+            emissive_texture: NO_TEXTURE,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// A live instance and where its parameters are written.
+#[derive(Copy, Clone, Debug)]
+struct InstanceSlot {
+    material: MaterialHandle,
+    block: u32,
+    parameters: MaterialParameters,
+}
+
+/// Every material and material instance, and the pipelines of their
+/// shadings.
+///
+/// Instances' parameters are rewritten to each frame's material buffer, so
+/// changing them never re-records a draw. Draws only push the block to
+/// read, and every shading shares the scene pipeline layout, so moving from
+/// one material to another binds at most a pipeline.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Materials {
+    /// Indexed by `MaterialHandle`.
+    materials: Vec<Material>,
+    /// Indexed by `MaterialInstance`. Destroyed instances leave `None`
+    /// behind, so a stale handle never draws another's parameters.
+    instances: Vec<Option<InstanceSlot>>,
+    /// Blocks destroyed instances left empty, for the next instance to take.
+    free_blocks: Vec<u32>,
+    /// Blocks handed out so far, after the default block 0.
+    blocks: u32,
+    /// The shaded and transparent pipelines of each shading in use but
+    /// `Shading::Lit`, which draws with the scene's.
+    pub(crate) pipelines: HashMap<Shading, (vk::Pipeline, vk::Pipeline)>,
+}
+
+impl Materials {
+    pub(crate) fn add(&mut self, material: Material) -> MaterialHandle {
+        self.materials.push(material);
+        MaterialHandle(self.materials.len() as u32 - 1)
+    }
+
+    pub(crate) fn material(&self, handle: MaterialHandle) -> Option<&Material> {
+        self.materials.get(handle.0 as usize)
+    }
+
+    pub(crate) fn instantiate(&mut self, material: MaterialHandle) -> Result<MaterialInstance> {
+        let Some(parameters) = self.material(material).map(|m| m.parameters) else {
+            return Err(anyhow!("No material {:?}.", material));
+        };
+        let block = match self.free_blocks.pop() {
+            Some(block) => block,
+            None if (self.blocks as usize) + 1 < MAX_MATERIAL_BLOCKS => {
+                self.blocks += 1;
+                self.blocks
+            }
+            None => return Err(anyhow!("The material buffer is full ({} instances).", MAX_MATERIAL_BLOCKS - 1)),
+        };
+
+        self.instances.push(Some(InstanceSlot { material, block, parameters }));
+        Ok(MaterialInstance(self.instances.len() as u32 - 1))
+    }
+
+    /// Frees an instance's block. Returns whether it was live.
+    pub(crate) fn remove(&mut self, instance: MaterialInstance) -> bool {
+        let Some(slot) = self.instances.get_mut(instance.0 as usize).and_then(|s| s.take()) else {
+            return false;
+        };
+        self.free_blocks.push(slot.block);
+        true
+    }
+
+    pub(crate) fn parameters(&self, instance: MaterialInstance) -> Option<MaterialParameters> {
+        self.slot(Some(instance)).map(|s| s.parameters)
+    }
+
+    /// Returns whether the instance is live.
+    pub(crate) fn set_parameters(&mut self, instance: MaterialInstance, parameters: MaterialParameters) -> bool {
+        match self.instances.get_mut(instance.0 as usize).and_then(|s| s.as_mut()) {
+            Some(slot) => {
+                slot.parameters = parameters;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn slot(&self, instance: Option<MaterialInstance>) -> Option<&InstanceSlot> {
+        self.instances.get(instance?.0 as usize)?.as_ref()
+    }
+
+    /// The block a draw of `instance` reads, the default block for none or
+    /// a destroyed one.
+    pub(crate) fn block(&self, instance: Option<MaterialInstance>) -> u32 {
+        self.slot(instance).map_or(0, |s| s.block)
+    }
+
+    /// How a draw of `instance` is shaded.
+    pub(crate) fn shading(&self, instance: Option<MaterialInstance>) -> Shading {
+        self.slot(instance).map_or(Shading::Lit, |s| self.materials[s.material.0 as usize].shading)
+    }
+
+    /// What draws of `instance` are grouped by, see `DrawOrder::draw_key`:
+    /// the shading, then the material, so each pipeline is bound once.
+    pub(crate) fn draw_group(&self, instance: Option<MaterialInstance>) -> u16 {
+        let group = |s: &InstanceSlot| (self.shading(instance) as u16) << 12 | ((s.material.0 as u16 + 1) & 0xFFF);
+        self.slot(instance).map_or(0, group)
+    }
+
+    /// The material buffer's contents, with textures resolved against the
+    /// texture table as it is now.
+    fn contents(&self, data: &AppData) -> Vec<MaterialBlock> {
+        let resolve = |texture: Option<u32>| texture.map_or(NO_TEXTURE, |t| resolve_texture(data, t));
+        let mut blocks = vec![MaterialBlock::default(); self.blocks as usize + 1];
+        for slot in self.instances.iter().flatten() {
+            let material = &self.materials[slot.material.0 as usize];
+            blocks[slot.block as usize] = MaterialBlock {
+                parameters: slot.parameters,
+                texture: resolve(material.texture),
+                emissive_texture: resolve(material.emissive_texture),
+                _padding: [0; 2],
+            };
+        }
+        blocks
+    }
+
+    pub(crate) unsafe fn destroy_pipelines(&mut self, device: &Device) {
+        for (_, (shaded, transparent)) in self.pipelines.drain() {
+            device.destroy_pipeline(shaded, None);
+            device.destroy_pipeline(transparent, None);
+        }
+    }
+}
+
+/// Creates the pipelines of every shading in use that has none yet.
+pub(crate) unsafe fn create_material_pipelines(device: &Device, data: &mut AppData) -> Result<()> {
+    let mut shadings = data.materials.materials.iter().map(|m| m.shading).collect::<Vec<_>>();
+    shadings.sort();
+    shadings.dedup();
+
+    for shading in shadings {
+        if shading == Shading::Lit || data.materials.pipelines.contains_key(&shading) {
+            continue;
+        }
+        let desc = ScenePipelineDesc { shading, ..ScenePipelineDesc::new(data) };
+        let shaded = create_scene_pipeline(device, desc, SceneVariant::Shaded)?;
+        let transparent = match create_scene_pipeline(device, desc, SceneVariant::Transparent) {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                device.destroy_pipeline(shaded, None);
+                return Err(e);
+            }
+        };
+        debug!("Created the {:?} material pipelines.", shading);
+        data.materials.pipelines.insert(shading, (shaded, transparent));
+    }
+    Ok(())
+}
+
+/// One storage buffer of material blocks per swapchain image, read by the
+/// scene fragment shader.
+pub(crate) unsafe fn create_material_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.material_buffers.clear();
+    data.material_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (material_buffer, material_buffer_memory) = create_mapped_buffer(
+            instance,
+            device,
+            data,
+            (size_of::<MaterialBlock>() * MAX_MATERIAL_BLOCKS) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
+        .unwrap();
+
+        data.material_buffers.push(material_buffer);
+        data.material_buffers_memory.push(material_buffer_memory);
+    }
+
+    Ok(())
+}
+
+/// Writes every instance's parameters to the material buffer for
+/// `image_index`.
+pub(crate) unsafe fn write_material_blocks(device: &Device, data: &AppData, image_index: usize) {
+    let blocks = data.materials.contents(data);
+    data.material_buffers_memory[image_index].write(device, 0, &blocks);
+}
@@ -0,0 +1,73 @@
+use vulkanalia::{prelude::v1_0::*, vk::InstanceV1_1};
+
+use crate::app::AppData;
+
+/// Seconds between queries of the heap budgets.
+pub(crate) const MEMORY_BUDGET_INTERVAL: f32 = 0.5;
+
+/// The fraction of a device local heap's budget past which the app is
+/// short of memory, see `FrameStats::memory_pressure`.
+pub(crate) const MEMORY_PRESSURE: f64 = 0.9;
+
+/// What a device memory heap holds and may hold, from every process using
+/// the device.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeapBudget {
+    pub size: u64,
+    /// Bytes the app can allocate before allocations start failing or
+    /// slowing down. The heap size without `VK_EXT_memory_budget`.
+    pub budget: u64,
+    /// Bytes the app has allocated. Zero without `VK_EXT_memory_budget`.
+    pub usage: u64,
+    pub device_local: bool,
+}
+
+impl HeapBudget {
+    /// `usage` over `budget`.
+    pub fn fraction(&self) -> f64 {
+        self.usage as f64 / self.budget.max(1) as f64
+    }
+}
+
+/// Statistics of the frames drawn so far, see `App::frame_stats`. Heap
+/// budgets are refreshed every `MEMORY_BUDGET_INTERVAL` seconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// The frame last drawn.
+    pub frame_number: u64,
+    /// The device has `VK_EXT_memory_budget`, without which heap usage is
+    /// unknown.
+    pub memory_budget: bool,
+    pub heaps: Vec<HeapBudget>,
+}
+
+impl FrameStats {
+    /// Whether a device local heap is close to its budget. Streamed
+    /// textures give up their largest levels while it is.
+    pub fn memory_pressure(&self) -> bool {
+        self.heaps.iter().any(|h| h.device_local && h.fraction() > MEMORY_PRESSURE)
+    }
+}
+
+/// The budgets of the device's memory heaps.
+pub(crate) unsafe fn get_heap_budgets(instance: &Instance, data: &AppData) -> Vec<HeapBudget> {
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let properties = if data.memory_budget {
+        let mut properties = vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+        instance.get_physical_device_memory_properties2(data.physical_device, &mut properties);
+        properties.memory_properties
+    } else {
+        instance.get_physical_device_memory_properties(data.physical_device)
+    };
+
+    properties.memory_heaps[..properties.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+        .map(|(i, heap)| HeapBudget {
+            size: heap.size,
+            budget: if data.memory_budget { budget.heap_budget[i] } else { heap.size },
+            usage: if data.memory_budget { budget.heap_usage[i] } else { 0 },
+            device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+        })
+        .collect()
+}
@@ -0,0 +1,187 @@
+use cgmath::{vec3, InnerSpace};
+use log::*;
+use meshopt::DecodePosition;
+use std::collections::HashMap;
+
+use crate::{
+    lod::{Lod, MAX_LODS},
+    meshlet::Meshlets,
+    model::MeshData,
+    types::Vec3,
+    vertex::Vertex,
+    visibility::Bounds,
+};
+
+/// How much the overdraw pass may worsen vertex cache efficiency, 1.05
+/// allowing up to 5%, to sort triangles front to back.
+const OVERDRAW_THRESHOLD: f32 = 1.05;
+
+impl DecodePosition for Vertex {
+    fn decode_position(&self) -> [f32; 3] {
+        self.pos.into()
+    }
+}
+
+impl MeshData {
+    /// Smooth normals for meshes imported without any, averaging the normals
+    /// of the triangles around each vertex weighted by their area. Vertices
+    /// split along UV seams are not smoothed across.
+    pub(crate) fn generate_normals(&mut self) {
+        let mut normals = vec![vec3(0.0, 0.0, 0.0); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].pos);
+            // Twice the area, which weights the sum.
+            let normal = (b - a).cross(c - a);
+            triangle.iter().for_each(|i| normals[*i as usize] += normal);
+        }
+
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.normal = normalize_or(normal, vec3(0.0, 0.0, 1.0));
+        }
+    }
+
+    /// Per-vertex tangents with MikkTSpace, the convention Blender and
+    /// Substance bake normal maps in, so they shade without seams. Needs
+    /// normals. Tangents are generated per triangle corner, so vertices
+    /// whose corners disagree are split.
+    pub(crate) fn generate_tangents(&mut self) {
+        let mut corners = Corners(self.indices.iter().map(|i| self.vertices[*i as usize]).collect());
+        if !bevy_mikktspace::generate_tangents(&mut corners) {
+            warn!("Failed to generate tangents for a mesh of {} triangles.", self.indices.len() / 3);
+            return;
+        }
+
+        let mut unique_vertices = HashMap::new();
+        self.vertices.clear();
+        self.indices.clear();
+        for vertex in corners.0 {
+            let index = *unique_vertices.entry(vertex).or_insert_with(|| {
+                self.vertices.push(vertex);
+                self.vertices.len() as u32 - 1
+            });
+            self.indices.push(index);
+        }
+    }
+
+    /// Reorders triangles for the post-transform vertex cache and then for
+    /// less overdraw, and vertices into the order they are first fetched.
+    /// Draws the same triangles, faster on large meshes. Then builds the
+    /// meshlets, which depend on the order.
+    pub(crate) fn optimize(&mut self) {
+        let before = meshopt::analyze_vertex_cache(&self.indices, self.vertices.len(), 16, 0, 0);
+
+        self.indices = meshopt::optimize_vertex_cache(&self.indices, self.vertices.len());
+        meshopt::optimize_overdraw_in_place_decoder(&mut self.indices, &self.vertices, OVERDRAW_THRESHOLD);
+        let count = meshopt::optimize_vertex_fetch_in_place(&mut self.indices, &mut self.vertices);
+        self.vertices.truncate(count);
+
+        let after = meshopt::analyze_vertex_cache(&self.indices, self.vertices.len(), 16, 0, 0);
+        debug!(
+            "Optimized mesh of {} triangles, ACMR {:.3} -> {:.3}.",
+            self.indices.len() / 3,
+            before.acmr,
+            after.acmr,
+        );
+
+        self.meshlets = self.build_meshlets();
+    }
+
+    /// A lower detail version for LODs with about `ratio` of the triangles,
+    /// optimized and with only the vertices it uses. Simplification stops
+    /// early rather than move the surface more than `target_error`, relative
+    /// to the mesh's extent, so the result may keep more triangles.
+    pub(crate) fn simplified(&self, ratio: f32, target_error: f32) -> MeshData {
+        let target_count = ((self.indices.len() / 3) as f32 * ratio.clamp(0.0, 1.0)) as usize * 3;
+        let mut indices = meshopt::simplify_decoder(&self.indices, &self.vertices, target_count, target_error);
+
+        let mut vertices = self.vertices.clone();
+        let count = meshopt::optimize_vertex_fetch_in_place(&mut indices, &mut vertices);
+        vertices.truncate(count);
+
+        let mut mesh = MeshData {
+            bounds: Bounds::from_points(vertices.iter().map(|v| v.pos)),
+            vertices,
+            indices,
+            skeleton: self.skeleton.clone(),
+            clips: self.clips.clone(),
+            meshlets: Meshlets::default(),
+            lods: vec![],
+        };
+        mesh.optimize();
+        mesh
+    }
+
+    /// `levels` successively simplified meshes, each with about half the
+    /// triangles of the one before. Stops early once simplification can no
+    /// longer remove triangles within `target_error`.
+    pub(crate) fn lods(&self, levels: usize, target_error: f32) -> Vec<MeshData> {
+        let mut lods: Vec<MeshData> = Vec::with_capacity(levels);
+        for _ in 0..levels {
+            let previous = lods.last().unwrap_or(self);
+            let lod = previous.simplified(0.5, target_error);
+            if lod.indices.is_empty() || lod.indices.len() >= previous.indices.len() {
+                break;
+            }
+            lods.push(lod);
+        }
+        lods
+    }
+
+    /// Appends `lods`, simpler versions of this mesh, to its vertices and
+    /// indices so that one pair of buffers holds every level, and records
+    /// where each level's indices are. The meshlets keep describing the
+    /// full mesh.
+    pub(crate) fn append_lods(&mut self, lods: Vec<MeshData>) {
+        self.lods = vec![Lod { first_index: 0, index_count: self.indices.len() as u32 }];
+        for lod in lods.into_iter().take(MAX_LODS - 1) {
+            let base = self.vertices.len() as u32;
+            self.lods.push(Lod { first_index: self.indices.len() as u32, index_count: lod.indices.len() as u32 });
+            self.indices.extend(lod.indices.iter().map(|i| base + i));
+            self.vertices.extend(lod.vertices);
+        }
+        debug!(
+            "Mesh has {} levels of detail, down to {} triangles.",
+            self.lods.len(),
+            self.lods.last().map_or(0, |l| l.index_count / 3),
+        );
+    }
+}
+
+/// Unwelded triangles, three vertices per face, as MikkTSpace sees a mesh.
+struct Corners(Vec<Vertex>);
+
+impl bevy_mikktspace::Geometry for Corners {
+    fn num_faces(&self) -> usize {
+        self.0.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.0[face * 3 + vert].pos.into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.0[face * 3 + vert].normal.into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.0[face * 3 + vert].tex_coords.into()
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.0[face * 3 + vert].tangent = tangent.into();
+    }
+}
+
+/// `v` normalized, or `fallback` normalized if `v` is too short to have a
+/// direction.
+fn normalize_or(v: Vec3, fallback: Vec3) -> Vec3 {
+    if v.magnitude2() > f32::EPSILON {
+        v.normalize()
+    } else {
+        fallback.normalize()
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::Result;
+use log::*;
+use std::{mem::size_of, ops::Range, ptr::copy_nonoverlapping as memcpy};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    vertex::Vertex,
+    vertex_buffer::create_buffer,
+};
+
+/// Vertices the pool first makes room for.
+const INITIAL_VERTICES: u32 = 1 << 16;
+
+/// Indices the pool first makes room for.
+const INITIAL_INDICES: u32 = 1 << 18;
+
+/// Free ranges of a buffer, in elements, handed out first fit.
+#[derive(Clone, Debug, Default)]
+struct RangeAllocator {
+    capacity: u32,
+    /// Sorted and never adjacent, so that freed neighbours merge.
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    fn allocate(&mut self, count: u32) -> Option<Range<u32>> {
+        let index = self.free.iter().position(|r| r.len() >= count as usize)?;
+        let start = self.free[index].start;
+        self.free[index].start += count;
+        if self.free[index].is_empty() {
+            self.free.remove(index);
+        }
+        Some(start..start + count)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let index = self.free.partition_point(|r| r.start < range.start);
+        self.free.insert(index, range);
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+
+    /// Adds the elements past the old capacity to the free ranges.
+    fn grow(&mut self, capacity: u32) {
+        let old = self.capacity;
+        self.capacity = capacity;
+        self.free(old..capacity);
+    }
+
+    /// The capacity to grow to for a range of `count` that does not fit.
+    fn grown_capacity(&self, count: u32, initial: u32) -> u32 {
+        let tail = self.free.last().filter(|r| r.end == self.capacity).map_or(0, |r| r.len() as u32);
+        (self.capacity + count - tail).max(self.capacity * 2).max(initial)
+    }
+}
+
+/// The vertices and indices of every registered mesh, suballocated from one
+/// vertex buffer and one index buffer so that loading a mesh creates no
+/// buffers and drawing any of them binds the same two. Meshes are drawn
+/// with `first_index` and `vertex_offset`, their indices staying relative
+/// to their own first vertex.
+///
+/// Indices are always 32 bit, as meshes of either width share the buffer.
+/// When a mesh does not fit, both buffers are replaced by larger ones with
+/// the old contents copied over, which needs the device idle. The scene
+/// model keeps buffers of its own, as meshlets, skinning and ray tracing
+/// read it from offset zero.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MeshPool {
+    pub(crate) vertex_buffer: vk::Buffer,
+    pub(crate) vertex_buffer_memory: vk::DeviceMemory,
+    pub(crate) index_buffer: vk::Buffer,
+    pub(crate) index_buffer_memory: vk::DeviceMemory,
+    vertices: RangeAllocator,
+    indices: RangeAllocator,
+}
+
+/// Where a mesh is in the pool, in vertices and indices.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct PoolAllocation {
+    pub(crate) vertices: Range<u32>,
+    pub(crate) indices: Range<u32>,
+}
+
+impl MeshPool {
+    /// Copies `vertices` and `indices` into the pool. Returns whether the
+    /// buffers were replaced to make room, in which case anything recorded
+    /// with the old ones has to be recorded again.
+    pub(crate) unsafe fn insert(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) -> Result<(PoolAllocation, bool)> {
+        let (vertex_count, index_count) = (vertices.len() as u32, indices.len() as u32);
+        let mut grown = false;
+        let allocation = loop {
+            match (self.vertices.allocate(vertex_count), self.indices.allocate(index_count)) {
+                (Some(vertices), Some(indices)) => break PoolAllocation { vertices, indices },
+                (vertices, indices) => {
+                    vertices.into_iter().for_each(|r| self.vertices.free(r));
+                    indices.into_iter().for_each(|r| self.indices.free(r));
+                    self.grow(instance, device, data, vertex_count, index_count)?;
+                    grown = true;
+                }
+            }
+        };
+
+        let vertex_size = size_of::<Vertex>() as u64 * vertex_count as u64;
+        let index_size = size_of::<u32>() as u64 * index_count as u64;
+        let (staging_buffer, staging_buffer_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            (vertex_size + index_size).max(1),
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+        let memory = device.map_memory(staging_buffer_memory, 0, vk::WHOLE_SIZE as u64, vk::MemoryMapFlags::empty())?;
+        memcpy(vertices.as_ptr(), memory.cast(), vertices.len());
+        memcpy(indices.as_ptr(), memory.cast::<u8>().add(vertex_size as usize).cast(), indices.len());
+        device.unmap_memory(staging_buffer_memory);
+
+        let command_buffer = begin_single_time_commands(device, data)?;
+        let vertex_region = vk::BufferCopy::builder()
+            .src_offset(0)
+            .dst_offset(size_of::<Vertex>() as u64 * allocation.vertices.start as u64)
+            .size(vertex_size);
+        let index_region = vk::BufferCopy::builder()
+            .src_offset(vertex_size)
+            .dst_offset(size_of::<u32>() as u64 * allocation.indices.start as u64)
+            .size(index_size);
+        if vertex_size > 0 {
+            device.cmd_copy_buffer(command_buffer, staging_buffer, self.vertex_buffer, &[vertex_region]);
+        }
+        if index_size > 0 {
+            device.cmd_copy_buffer(command_buffer, staging_buffer, self.index_buffer, &[index_region]);
+        }
+        end_single_time_commands(device, data, command_buffer)?;
+
+        device.destroy_buffer(staging_buffer, None);
+        free_memory(device, staging_buffer_memory);
+        Ok((allocation, grown))
+    }
+
+    /// Frees the ranges of a mesh no longer drawn.
+    pub(crate) fn remove(&mut self, allocation: PoolAllocation) {
+        self.vertices.free(allocation.vertices);
+        self.indices.free(allocation.indices);
+    }
+
+    /// Replaces the buffers with ones with room for `vertex_count` more
+    /// vertices and `index_count` more indices, at least doubling them.
+    unsafe fn grow(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        vertex_count: u32,
+        index_count: u32,
+    ) -> Result<()> {
+        let vertex_capacity = self.vertices.grown_capacity(vertex_count, INITIAL_VERTICES);
+        let index_capacity = self.indices.grown_capacity(index_count, INITIAL_INDICES);
+        let (vertex_buffer, vertex_buffer_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<Vertex>() as u64 * vertex_capacity as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let (index_buffer, index_buffer_memory) = match create_buffer(
+            instance,
+            device,
+            data,
+            size_of::<u32>() as u64 * index_capacity as u64,
+            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                device.destroy_buffer(vertex_buffer, None);
+                free_memory(device, vertex_buffer_memory);
+                return Err(e);
+            }
+        };
+
+        if self.vertices.capacity > 0 {
+            device.device_wait_idle()?;
+            let command_buffer = begin_single_time_commands(device, data)?;
+            let vertex_region = vk::BufferCopy::builder().size(size_of::<Vertex>() as u64 * self.vertices.capacity as u64);
+            device.cmd_copy_buffer(command_buffer, self.vertex_buffer, vertex_buffer, &[vertex_region]);
+            let index_region = vk::BufferCopy::builder().size(size_of::<u32>() as u64 * self.indices.capacity as u64);
+            device.cmd_copy_buffer(command_buffer, self.index_buffer, index_buffer, &[index_region]);
+            end_single_time_commands(device, data, command_buffer)?;
+        }
+        self.destroy(device);
+        info!("Mesh pool grown to {} vertices and {} indices.", vertex_capacity, index_capacity);
+
+        self.vertex_buffer = vertex_buffer;
+        self.vertex_buffer_memory = vertex_buffer_memory;
+        self.index_buffer = index_buffer;
+        self.index_buffer_memory = index_buffer_memory;
+        self.vertices.grow(vertex_capacity);
+        self.indices.grow(index_capacity);
+        Ok(())
+    }
+
+    /// Destroys the buffers, keeping the ranges handed out.
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_buffer(self.vertex_buffer, None);
+        free_memory(device, self.vertex_buffer_memory);
+        device.destroy_buffer(self.index_buffer, None);
+        free_memory(device, self.index_buffer_memory);
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::Result;
+use std::{
+    mem::size_of_val,
+    ptr::copy_nonoverlapping as memcpy,
+};
+
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::ExtMeshShaderExtension,
+};
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    model::MeshData,
+    pipeline::{create_scene_pipeline, SceneGeometry, ScenePipelineDesc, SceneVariant},
+    types::Vec3,
+    vertex_buffer::{copy_buffer, create_buffer},
+};
+
+/// The largest meshlets `meshlet.mesh` can output.
+const MAX_MESHLET_VERTICES: usize = 64;
+const MAX_MESHLET_TRIANGLES: usize = 124;
+
+/// Meshlets culled per task shader workgroup, as in `meshlet.task`.
+const TASK_WORKGROUP_SIZE: u32 = 32;
+
+const MESHLET_TASK: &[u8] = include_bytes!("../../shaders/meshlet_task.spv");
+const MESHLET_MESH: &[u8] = include_bytes!("../../shaders/meshlet_mesh.spv");
+
+/// A meshlet as the task and mesh shaders read it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Meshlet {
+    pub(crate) center: Vec3,
+    pub(crate) radius: f32,
+    /// Every triangle faces within `acos(cone_cutoff)` of this axis, so the
+    /// meshlet can be culled when all of them face away.
+    pub(crate) cone_axis: Vec3,
+    pub(crate) cone_cutoff: f32,
+    /// Where its vertices start in `Meshlets::vertices`.
+    pub(crate) vertex_offset: u32,
+    pub(crate) vertex_count: u32,
+    /// Where its triangles start in `Meshlets::triangles`.
+    pub(crate) triangle_offset: u32,
+    pub(crate) triangle_count: u32,
+}
+
+/// A mesh split into small clusters of triangles for the mesh shader path,
+/// built on import by `MeshData::optimize`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Meshlets {
+    pub(crate) meshlets: Vec<Meshlet>,
+    /// Indices into the mesh's vertices, each meshlet's back to back.
+    pub(crate) vertices: Vec<u32>,
+    /// Three indices into the meshlet's vertices per triangle, packed into
+    /// the low three bytes.
+    pub(crate) triangles: Vec<u32>,
+}
+
+impl MeshData {
+    /// Splits the triangles into meshlets, in index order, so the index
+    /// buffer should already be optimized for the vertex cache.
+    pub(crate) fn build_meshlets(&self) -> Meshlets {
+        let mut meshlets = Meshlets::default();
+        let clusters = meshopt::build_meshlets(
+            &self.indices,
+            self.vertices.len(),
+            MAX_MESHLET_VERTICES,
+            MAX_MESHLET_TRIANGLES,
+        );
+
+        for cluster in &clusters {
+            let bounds = meshopt::compute_meshlet_bounds_decoder(cluster, &self.vertices);
+            let vertex_count = cluster.vertex_count as usize;
+            let triangle_count = cluster.triangle_count as usize;
+
+            meshlets.meshlets.push(Meshlet {
+                center: bounds.center.into(),
+                radius: bounds.radius,
+                cone_axis: bounds.cone_axis.into(),
+                cone_cutoff: bounds.cone_cutoff,
+                vertex_offset: meshlets.vertices.len() as u32,
+                vertex_count: vertex_count as u32,
+                triangle_offset: meshlets.triangles.len() as u32,
+                triangle_count: triangle_count as u32,
+            });
+            meshlets.vertices.extend_from_slice(&cluster.vertices[..vertex_count]);
+            meshlets.triangles.extend(
+                cluster.indices[..triangle_count]
+                    .iter()
+                    .map(|[a, b, c]| *a as u32 | (*b as u32) << 8 | (*c as u32) << 16),
+            );
+        }
+
+        meshlets
+    }
+}
+
+/// Uploads `data.meshlets` and points the meshlet descriptor set at them and
+/// the vertex buffer, replacing the previous upload. Called again after a
+/// model is swapped in. Does nothing without `VK_EXT_mesh_shader`.
+pub(crate) unsafe fn create_meshlet_buffers(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.mesh_shader {
+        return Ok(());
+    }
+    destroy_meshlet_buffers(device, data);
+
+    if data.meshlet_descriptor_set.is_null() {
+        let pool_size = vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(4);
+        let pool_sizes = &[pool_size];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(1);
+        data.meshlet_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+        let layouts = &[data.meshlet_set_layout];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(data.meshlet_descriptor_pool)
+            .set_layouts(layouts);
+        data.meshlet_descriptor_set = device.allocate_descriptor_sets(&info).unwrap()[0];
+    }
+
+    let meshlets = std::mem::take(&mut data.meshlets);
+    (data.meshlet_buffer, data.meshlet_buffer_memory) =
+        create_storage_buffer(instance, device, data, &meshlets.meshlets).unwrap();
+    (data.meshlet_vertex_buffer, data.meshlet_vertex_buffer_memory) =
+        create_storage_buffer(instance, device, data, &meshlets.vertices).unwrap();
+    (data.meshlet_triangle_buffer, data.meshlet_triangle_buffer_memory) =
+        create_storage_buffer(instance, device, data, &meshlets.triangles).unwrap();
+    data.meshlets = meshlets;
+
+    let buffers = [
+        data.vertex_buffer,
+        data.meshlet_buffer,
+        data.meshlet_vertex_buffer,
+        data.meshlet_triangle_buffer,
+    ];
+    let infos = buffers.map(|buffer| {
+        [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64)
+            .build()]
+    });
+    let writes = infos
+        .iter()
+        .enumerate()
+        .map(|(binding, info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.meshlet_descriptor_set)
+                .dst_binding(binding as u32)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(info)
+        })
+        .collect::<Vec<_>>();
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+
+    Ok(())
+}
+
+/// A device local storage buffer holding `items`, at least 4 bytes so that
+/// empty meshes still have something to bind.
+unsafe fn create_storage_buffer<T>(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    items: &[T],
+) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+    let size = size_of_val(items).max(4) as u64;
+
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )
+    .unwrap();
+
+    let memory = device
+        .map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())
+        .unwrap();
+    memcpy(items.as_ptr(), memory.cast(), items.len());
+    device.unmap_memory(staging_buffer_memory);
+
+    let (buffer, buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )
+    .unwrap();
+
+    copy_buffer(device, data, staging_buffer, buffer, size).unwrap();
+
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+
+    Ok((buffer, buffer_memory))
+}
+
+pub(crate) unsafe fn destroy_meshlet_buffers(device: &Device, data: &mut AppData) {
+    device.destroy_buffer(data.meshlet_buffer, None);
+    free_memory(device, data.meshlet_buffer_memory);
+    device.destroy_buffer(data.meshlet_vertex_buffer, None);
+    free_memory(device, data.meshlet_vertex_buffer_memory);
+    device.destroy_buffer(data.meshlet_triangle_buffer, None);
+    free_memory(device, data.meshlet_triangle_buffer_memory);
+}
+
+/// Creates the descriptor set layout for the buffers the meshlet shaders
+/// read. Does nothing without `VK_EXT_mesh_shader`.
+pub(crate) unsafe fn create_meshlet_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.mesh_shader {
+        return Ok(());
+    }
+
+    let bindings = [0, 1, 2, 3].map(|binding| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT)
+    });
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    data.meshlet_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// Creates the scene pipeline variant that draws meshlets with task and mesh
+/// shaders. Its layout is the scene layout with the meshlet set appended and
+/// the model matrix pushed to the task and mesh stages instead.
+pub(crate) unsafe fn create_mesh_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.mesh_shader {
+        return Ok(());
+    }
+
+    let model_range = data
+        .model_push_constants
+        .with_stages(vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT)
+        .range();
+    let fragment_range = data.fragment_push_constants.range();
+
+    let set_layouts = &[data.descriptor_set_layout, data.texture_set_layout, data.meshlet_set_layout];
+    let push_constant_ranges = &[model_range, fragment_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.mesh_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let desc = ScenePipelineDesc {
+        layout: data.mesh_pipeline_layout,
+        geometry: SceneGeometry::Mesh { task: MESHLET_TASK, mesh: MESHLET_MESH },
+        ..ScenePipelineDesc::new(data)
+    };
+    data.mesh_pipeline = create_scene_pipeline(device, desc, SceneVariant::Shaded).unwrap();
+    Ok(())
+}
+
+/// Records drawing every meshlet of the scene mesh with the mesh pipeline,
+/// which must be bound along with its descriptor sets.
+pub(crate) unsafe fn record_meshlet_draw(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    let groups = (data.meshlets.meshlets.len() as u32).div_ceil(TASK_WORKGROUP_SIZE);
+    device.cmd_draw_mesh_tasks_ext(command_buffer, groups, 1, 1);
+}
@@ -0,0 +1,303 @@
+use anyhow::Result;
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::{AppData, VULKAN_1_1},
+    shader::create_shader_module,
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    vertex_buffer::create_buffer,
+};
+
+/// Must match `MAX_MIPS` in `mipmap.comp`.
+pub(crate) const MAX_COMPUTE_MIPS: u32 = 13;
+
+/// Texels along each side of the tile one workgroup reduces.
+const TILE_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct MipmapPushConstants {
+    mip_count: u32,
+    srgb: u32,
+    workgroups: u32,
+}
+
+/// Whether `generate_mipmaps_compute` can handle an image. The shader writes
+/// through `rgba8` storage views and assumes power-of-two dimensions. sRGB
+/// images need `EXTENDED_USAGE` (Vulkan 1.1) to be created with storage usage.
+pub(crate) fn compute_mipmaps_supported(
+    data: &AppData,
+    format: vk::Format,
+    width: u32,
+    height: u32,
+) -> bool {
+    let format_supported = match format {
+        vk::Format::R8G8B8A8_UNORM => true,
+        // Written through UNORM views.
+        vk::Format::R8G8B8A8_SRGB => data.instance_version >= u32::from(VULKAN_1_1)
+            && data.device_version >= u32::from(VULKAN_1_1)
+            && data.portability.image_view_format_reinterpretation,
+        _ => false,
+    };
+
+    format_supported
+        && width.is_power_of_two()
+        && height.is_power_of_two()
+        && width.max(height) < 1 << MAX_COMPUTE_MIPS
+}
+
+/// Extra create flags and usage an image needs for `generate_mipmaps_compute`.
+pub(crate) fn compute_mipmaps_image_flags(
+    format: vk::Format,
+) -> (vk::ImageCreateFlags, vk::ImageUsageFlags) {
+    let flags = if format == vk::Format::R8G8B8A8_SRGB {
+        vk::ImageCreateFlags::MUTABLE_FORMAT | vk::ImageCreateFlags::EXTENDED_USAGE
+    } else {
+        vk::ImageCreateFlags::empty()
+    };
+    (flags, vk::ImageUsageFlags::STORAGE)
+}
+
+pub(crate) unsafe fn create_mipmap_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let counter_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let mips_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(MAX_COMPUTE_MIPS)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let bindings = &[counter_binding, mips_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.mipmap_set_layout = data.set_layouts.get(device, &info).unwrap();
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(size_of::<MipmapPushConstants>() as u32);
+
+    let set_layouts = &[data.mipmap_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.mipmap_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let comp = include_bytes!("../../shaders/mipmap_comp.spv");
+    let shader_module = create_shader_module(device, &comp[..]).unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(data.mipmap_pipeline_layout);
+
+    data.mipmap_pipeline = device
+        .create_compute_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(shader_module, None);
+    Ok(())
+}
+
+/// Fills mips 1.. of `image` from mip 0 in a single dispatch. Expects every
+/// level in `TRANSFER_DST_OPTIMAL` and leaves them `SHADER_READ_ONLY_OPTIMAL`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn generate_mipmaps_compute(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    image: vk::Image,
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) -> Result<()> {
+    // Views are always UNORM since sRGB formats rarely support storage; the
+    // shader does the conversion itself.
+    let views = (0..mip_levels)
+        .map(|level| {
+            let subresource_range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(level)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            let info = vk::ImageViewCreateInfo::builder()
+                .image(image)
+                .view_type(vk::ImageViewType::_2D)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .subresource_range(subresource_range);
+
+            device.create_image_view(&info, None)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (counter, counter_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        4,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )
+    .unwrap();
+
+    let buffer_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+    let image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(MAX_COMPUTE_MIPS);
+
+    let pool_sizes = &[buffer_size, image_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    let descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = &[data.mipmap_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(layouts);
+    let descriptor_set = device.allocate_descriptor_sets(&info).unwrap()[0];
+
+    let counter_info = vk::DescriptorBufferInfo::builder()
+        .buffer(counter)
+        .offset(0)
+        .range(4);
+
+    // Every array element must be valid, so unused slots repeat the last mip.
+    let image_infos = (0..MAX_COMPUTE_MIPS)
+        .map(|level| {
+            vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(views[(level as usize).min(views.len() - 1)])
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let counter_infos = &[counter_info];
+    let counter_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(counter_infos);
+
+    let mips_write = vk::WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .image_info(&image_infos);
+
+    device.update_descriptor_sets(&[counter_write, mips_write], &[] as &[vk::CopyDescriptorSet]);
+
+    let command_buffer = begin_single_time_commands(device, data).unwrap();
+
+    let subresource = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .base_mip_level(0)
+        .level_count(mip_levels)
+        .base_array_layer(0)
+        .layer_count(1);
+
+    let to_general = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .new_layout(vk::ImageLayout::GENERAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+
+    device.cmd_fill_buffer(command_buffer, counter, 0, 4, 0);
+
+    let counter_barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::DependencyFlags::empty(),
+        &[counter_barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[to_general],
+    );
+
+    let groups_x = width.div_ceil(TILE_SIZE);
+    let groups_y = height.div_ceil(TILE_SIZE);
+    let push_constants = MipmapPushConstants {
+        mip_count: mip_levels,
+        srgb: (format == vk::Format::R8G8B8A8_SRGB) as u32,
+        workgroups: groups_x * groups_y,
+    };
+    let push_constants_bytes = std::slice::from_raw_parts(
+        &push_constants as *const MipmapPushConstants as *const u8,
+        size_of::<MipmapPushConstants>(),
+    );
+
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.mipmap_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::COMPUTE,
+        data.mipmap_pipeline_layout,
+        0,
+        &[descriptor_set],
+        &[],
+    );
+    device.cmd_push_constants(
+        command_buffer,
+        data.mipmap_pipeline_layout,
+        vk::ShaderStageFlags::COMPUTE,
+        0,
+        push_constants_bytes,
+    );
+    device.cmd_dispatch(command_buffer, groups_x, groups_y, 1);
+
+    let to_shader_read = vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::GENERAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource)
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[to_shader_read],
+    );
+
+    end_single_time_commands(device, data, command_buffer).unwrap();
+
+    device.destroy_descriptor_pool(descriptor_pool, None);
+    device.destroy_buffer(counter, None);
+    free_memory(device, counter_memory);
+    views.iter().for_each(|v| device.destroy_image_view(*v, None));
+
+    Ok(())
+}
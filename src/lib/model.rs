@@ -1,20 +1,108 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use log::*;
 use std::{
     collections::HashMap,
-    fs::File,
-    io::BufReader
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
 };
 
 use crate::{
+    animation::{AnimationClip, Channel, Interpolation, Keyframes},
     app::AppData,
-    vertex::Vertex  
+    lod::{Lod, LOD_TARGET_ERROR, MAX_LODS},
+    meshlet::Meshlets,
+    skinning::{Joint, JointTransform, Skeleton},
+    types::{Mat4, Quat},
+    vertex::Vertex,
+    visibility::Bounds,
+    world::{Handedness, UpAxis, WorldConfig},
 };
 
-use cgmath::{vec2, vec3};
+use cgmath::{point3, vec2, vec3, vec4, EuclideanSpace, InnerSpace, SquareMatrix, Transform};
+
+/// The convention the demo model was authored in.
+const MODEL_SPACE: WorldConfig = WorldConfig {
+    up: UpAxis::Z,
+    handedness: Handedness::Right,
+    meters_per_unit: 1.0,
+};
+
+/// The convention glTF requires.
+const GLTF_SPACE: WorldConfig = WorldConfig {
+    up: UpAxis::Y,
+    handedness: Handedness::Right,
+    meters_per_unit: 1.0,
+};
+
+/// A triangulated mesh in world space, ready for upload.
+#[derive(Clone, Debug)]
+pub(crate) struct MeshData {
+    pub(crate) vertices: Vec<Vertex>,
+    pub(crate) indices: Vec<u32>,
+    /// Around the bind pose for skinned meshes.
+    pub(crate) bounds: Bounds,
+    pub(crate) skeleton: Option<Skeleton>,
+    /// Animations of `skeleton`.
+    pub(crate) clips: Vec<AnimationClip>,
+    /// For the mesh shader path, see `optimize`.
+    pub(crate) meshlets: Meshlets,
+    /// Where each level of detail is in `indices`, the full mesh first.
+    /// Empty until `append_lods`.
+    pub(crate) lods: Vec<Lod>,
+}
 
 pub(crate) fn load_model(data: &mut AppData) -> Result<()> {
-    let mut reader = BufReader::new(File::open("resources/viking_room.obj").unwrap());
-  
+    let mesh = read_model(Path::new("resources/viking_room.obj"), &data.world).unwrap();
+    data.vertices = mesh.vertices;
+    data.indices = mesh.indices;
+    data.lods = mesh.lods;
+    data.model_bounds = mesh.bounds;
+    data.meshlets = mesh.meshlets;
+    Ok(())
+}
+
+/// Reads an OBJ, glTF or GLB file by its extension, with its levels of
+/// detail. These are read from files next to it with `_lod1`, `_lod2`, ...
+/// appended to the stem, in the same format and for the same skeleton, or
+/// generated by simplifying the mesh if there are none.
+pub(crate) fn read_model(path: &Path, world: &WorldConfig) -> Result<MeshData> {
+    let mut mesh = read_mesh(path, world)?;
+    let imported = (1..MAX_LODS)
+        .map(|level| lod_path(path, level))
+        .take_while(|path| path.exists())
+        .map(|path| read_mesh(&path, world))
+        .collect::<Result<Vec<_>>>()?;
+    let lods = if imported.is_empty() { mesh.lods(MAX_LODS - 1, LOD_TARGET_ERROR) } else { imported };
+    mesh.append_lods(lods);
+    Ok(mesh)
+}
+
+fn read_mesh(path: &Path, world: &WorldConfig) -> Result<MeshData> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gltf") | Some("glb") => read_gltf(path, world),
+        _ => read_obj(path, world),
+    }
+}
+
+/// Where `path`'s imported level of detail `level` would be.
+fn lod_path(path: &Path, level: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{stem}_lod{level}.{extension}"),
+        None => format!("{stem}_lod{level}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Reads an OBJ file authored in `MODEL_SPACE`, merging identical vertices
+/// and optimizing the result for drawing. Missing normals are generated;
+/// OBJ has no tangents or second UV set, so these are always generated and
+/// copied.
+pub(crate) fn read_obj(path: &Path, world: &WorldConfig) -> Result<MeshData> {
+    let import = world.import_transform(&MODEL_SPACE);
+    let mut reader = BufReader::new(File::open(path)?);
+
     let (models, _) = tobj::load_obj_buf(
         &mut reader,
         &tobj::LoadOptions {
@@ -22,37 +110,315 @@ pub(crate) fn load_model(data: &mut AppData) -> Result<()> {
             ..Default::default()
         },
         |_| Ok(Default::default()),
-    )
-    .unwrap();
-  
+    )?;
+
     let mut unique_vertices = HashMap::new();
-  
+    let mut vertices = vec![];
+    let mut indices = vec![];
+
+    let mut has_normals = true;
     for model in &models {
-        for index in &model.mesh.indices {
+        let mesh = &model.mesh;
+        if mesh.texcoords.is_empty() {
+            return Err(anyhow!("`{}` has no texture coordinates.", path.display()));
+        }
+        has_normals &= !mesh.normals.is_empty();
+
+        // Texture coordinates and normals have their own indices, unless the
+        // file shares the position indices.
+        let texcoord_indices = if mesh.texcoord_indices.is_empty() { &mesh.indices } else { &mesh.texcoord_indices };
+        let normal_indices = if mesh.normal_indices.is_empty() { &mesh.indices } else { &mesh.normal_indices };
+
+        for (i, index) in mesh.indices.iter().enumerate() {
             let pos_offset = (3 * index) as usize;
-            let tex_coord_offset = (2 * index) as usize;
-            let vertex = Vertex {
-                pos: vec3(
-                    model.mesh.positions[pos_offset],
-                    model.mesh.positions[pos_offset + 1],
-                    model.mesh.positions[pos_offset + 2],
-                ),
-                color: vec3(1.0, 1.0, 1.0),
-                tex_coords: vec2(
-                    model.mesh.texcoords[tex_coord_offset],
-                    1.0 - model.mesh.texcoords[tex_coord_offset + 1],
-                ),
+            let tex_coord_offset = (2 * texcoord_indices[i]) as usize;
+            let normal_offset = (3 * normal_indices[i]) as usize;
+
+            let normal = if mesh.normals.is_empty() {
+                vec3(0.0, 0.0, 0.0)
+            } else {
+                import
+                    .transform_vector(vec3(
+                        mesh.normals[normal_offset],
+                        mesh.normals[normal_offset + 1],
+                        mesh.normals[normal_offset + 2],
+                    ))
+                    .normalize()
             };
-  
+
+            let vertex = Vertex::new(
+                import
+                    .transform_point(point3(
+                        mesh.positions[pos_offset],
+                        mesh.positions[pos_offset + 1],
+                        mesh.positions[pos_offset + 2],
+                    ))
+                    .to_vec(),
+                normal,
+                vec3(1.0, 1.0, 1.0),
+                vec2(mesh.texcoords[tex_coord_offset], 1.0 - mesh.texcoords[tex_coord_offset + 1]),
+            );
+
             if let Some(index) = unique_vertices.get(&vertex) {
-                data.indices.push(*index as u32);
+                indices.push(*index as u32);
             } else {
-                let index = data.vertices.len();
+                let index = vertices.len();
                 unique_vertices.insert(vertex, index);
-                data.vertices.push(vertex);
-                data.indices.push(index as u32);
+                vertices.push(vertex);
+                indices.push(index as u32);
             }
         }
     }
-    Ok(())
-  }
\ No newline at end of file
+
+    if indices.is_empty() {
+        return Err(anyhow!("`{}` has no triangles.", path.display()));
+    }
+
+    let bounds = Bounds::from_points(vertices.iter().map(|v| v.pos));
+    let mut mesh = MeshData {
+        vertices,
+        indices,
+        bounds,
+        skeleton: None,
+        clips: vec![],
+        meshlets: Meshlets::default(),
+        lods: vec![],
+    };
+    if !has_normals {
+        mesh.generate_normals();
+    }
+    mesh.generate_tangents();
+    mesh.optimize();
+    Ok(mesh)
+}
+
+/// Reads every triangle mesh in a glTF file's default scene into one mesh,
+/// with the first skin found as its skeleton. Meshes without that skin have
+/// their node transforms baked in. Buffers must be in the GLB binary chunk
+/// or in files next to `path`.
+pub(crate) fn read_gltf(path: &Path, world: &WorldConfig) -> Result<MeshData> {
+    let import = world.import_transform(&GLTF_SPACE);
+    let gltf = gltf::Gltf::open(path)?;
+
+    let buffers = gltf
+        .buffers()
+        .map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .ok_or_else(|| anyhow!("`{}` has no binary chunk.", path.display())),
+            gltf::buffer::Source::Uri(uri) if !uri.starts_with("data:") => Ok(fs::read(path.with_file_name(uri))?),
+            gltf::buffer::Source::Uri(_) => Err(anyhow!("`{}` embeds a buffer as a data URI.", path.display())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let buffer = |b: gltf::Buffer| buffers.get(b.index()).map(|b| &b[..]);
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or_else(|| anyhow!("`{}` has no scenes.", path.display()))?;
+
+    // Global transforms of every node in the scene, parents first.
+    let mut nodes = vec![];
+    let mut stack = scene.nodes().map(|n| (n, Mat4::identity())).collect::<Vec<_>>();
+    while let Some((node, parent)) = stack.pop() {
+        let global = parent * Mat4::from(node.transform().matrix());
+        stack.extend(node.children().map(|c| (c, global)));
+        nodes.push((node, global));
+    }
+    let globals = nodes.iter().map(|(n, g)| (n.index(), *g)).collect::<HashMap<_, _>>();
+
+    let skin = nodes.iter().find_map(|(n, _)| n.skin());
+    let skeleton = skin.as_ref().map(|skin| read_skeleton(&gltf, skin, &globals, import, buffer));
+    let clips = skin.as_ref().map(|skin| read_clips(&gltf, skin, buffer)).unwrap_or_default();
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let (mut has_normals, mut has_tangents) = (true, true);
+
+    for (node, global) in &nodes {
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+
+        // Skinned meshes ignore their node's transform; the joints place them.
+        let skinned = skin.is_some() && node.skin().map(|s| s.index()) == skin.as_ref().map(|s| s.index());
+        let transform = if skinned { import } else { import * global };
+
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                warn!("Skipping a {:?} primitive in `{}`.", primitive.mode(), path.display());
+                continue;
+            }
+
+            let reader = primitive.reader(buffer);
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions = positions.collect::<Vec<_>>();
+            let count = positions.len();
+
+            let normals = reader.read_normals().map(|n| n.collect::<Vec<_>>());
+            let tangents = reader.read_tangents().map(|t| t.collect::<Vec<_>>());
+            let tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32().collect::<Vec<_>>());
+            let tex_coords_1 = reader.read_tex_coords(1).map(|t| t.into_f32().collect::<Vec<_>>());
+            let joints = reader.read_joints(0).map(|j| j.into_u16().collect::<Vec<_>>());
+            let weights = reader.read_weights(0).map(|w| w.into_f32().collect::<Vec<_>>());
+            has_normals &= normals.is_some();
+            has_tangents &= tangents.is_some() && normals.is_some();
+
+            let base = vertices.len() as u32;
+            for i in 0..count {
+                let normal = normals.as_ref().map_or(vec3(0.0, 0.0, 0.0), |n| {
+                    transform.transform_vector(n[i].into()).normalize()
+                });
+                let tex_coord = tex_coords.as_ref().map_or(vec2(0.0, 0.0), |t| t[i].into());
+
+                let mut vertex = Vertex::new(
+                    transform.transform_point(positions[i].into()).to_vec(),
+                    normal,
+                    vec3(1.0, 1.0, 1.0),
+                    tex_coord,
+                );
+                if let Some(tangents) = &tangents {
+                    let [x, y, z, w] = tangents[i];
+                    let tangent = transform.transform_vector(vec3(x, y, z)).normalize();
+                    vertex.tangent = vec4(tangent.x, tangent.y, tangent.z, w);
+                }
+                if let Some(tex_coords_1) = &tex_coords_1 {
+                    vertex.tex_coords_1 = tex_coords_1[i].into();
+                }
+                if let (true, Some(joints), Some(weights)) = (skinned, &joints, &weights) {
+                    vertex.joints = joints[i].map(u32::from);
+                    vertex.weights = weights[i].into();
+                }
+                vertices.push(vertex);
+            }
+
+            match reader.read_indices() {
+                Some(read) => indices.extend(read.into_u32().map(|i| base + i)),
+                None => indices.extend(base..base + count as u32),
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(anyhow!("`{}` has no triangles.", path.display()));
+    }
+
+    let bounds = Bounds::from_points(vertices.iter().map(|v| v.pos));
+    let mut mesh = MeshData {
+        vertices,
+        indices,
+        bounds,
+        skeleton,
+        clips,
+        meshlets: Meshlets::default(),
+        lods: vec![],
+    };
+    if !has_normals {
+        mesh.generate_normals();
+    }
+    if !has_tangents {
+        mesh.generate_tangents();
+    }
+    mesh.optimize();
+    Ok(mesh)
+}
+
+/// The joints of `skin`, brought into world space by `import`.
+fn read_skeleton<'a, 's>(
+    gltf: &gltf::Gltf,
+    skin: &'a gltf::Skin<'a>,
+    globals: &HashMap<usize, Mat4>,
+    import: Mat4,
+    buffer: impl Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+) -> Skeleton {
+    let joint_nodes = skin.joints().collect::<Vec<_>>();
+    let joint_index = joint_nodes.iter().enumerate().map(|(i, n)| (n.index(), i)).collect::<HashMap<_, _>>();
+
+    let mut parents = HashMap::new();
+    for node in gltf.nodes() {
+        for child in node.children() {
+            parents.insert(child.index(), node.index());
+        }
+    }
+
+    let inverse_binds = skin
+        .reader(buffer)
+        .read_inverse_bind_matrices()
+        .map(|m| m.map(Mat4::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    // Vertices were moved into world space by `import`, so undo it before
+    // the inverse bind matrices and redo it above the root joints.
+    let unimport = import.invert().unwrap_or(Mat4::identity());
+    let mut root = import;
+
+    let joints = joint_nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let parent = parents.get(&node.index()).and_then(|p| joint_index.get(p)).copied();
+            if parent.is_none() {
+                if let Some(above) = parents.get(&node.index()).and_then(|p| globals.get(p)) {
+                    root = import * above;
+                }
+            }
+
+            let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+            Joint {
+                name: node.name().map(str::to_string),
+                parent,
+                rest: JointTransform {
+                    translation: translation.into(),
+                    rotation: Quat::new(w, x, y, z),
+                    scale: scale.into(),
+                },
+                inverse_bind: inverse_binds.get(i).copied().unwrap_or(Mat4::identity()) * unimport,
+            }
+        })
+        .collect();
+
+    Skeleton::new(joints, root)
+}
+
+/// The animations of `skin`'s joints. Channels of other nodes and morph
+/// target weights are left out.
+fn read_clips<'a, 's>(
+    gltf: &'a gltf::Gltf,
+    skin: &gltf::Skin<'a>,
+    buffer: impl Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+) -> Vec<AnimationClip> {
+    use gltf::animation::util::ReadOutputs;
+
+    let joint_index = skin.joints().enumerate().map(|(i, n)| (n.index(), i)).collect::<HashMap<_, _>>();
+
+    gltf.animations()
+        .map(|animation| {
+            let channels = animation
+                .channels()
+                .filter_map(|channel| {
+                    let joint = *joint_index.get(&channel.target().node().index())?;
+                    let reader = channel.reader(buffer.clone());
+                    let times = reader.read_inputs()?.collect();
+                    let keyframes = match reader.read_outputs()? {
+                        ReadOutputs::Translations(t) => Keyframes::Translation(t.map(Into::into).collect()),
+                        ReadOutputs::Rotations(r) => {
+                            Keyframes::Rotation(r.into_f32().map(|[x, y, z, w]| Quat::new(w, x, y, z)).collect())
+                        }
+                        ReadOutputs::Scales(s) => Keyframes::Scale(s.map(Into::into).collect()),
+                        ReadOutputs::MorphTargetWeights(_) => return None,
+                    };
+                    let interpolation = match channel.sampler().interpolation() {
+                        gltf::animation::Interpolation::Step => Interpolation::Step,
+                        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                    };
+                    Some(Channel { joint, times, keyframes, interpolation })
+                })
+                .collect();
+            AnimationClip::new(animation.name().map(str::to_string), channels)
+        })
+        .collect()
+}
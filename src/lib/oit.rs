@@ -0,0 +1,359 @@
+use anyhow::Result;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    dynamic_rendering::Pass,
+    image::{create_image, create_image_view},
+    sampler::SamplerDesc,
+    shader::{create_shader_module, SpecializationConstants},
+};
+
+// Weighted blended order-independent transparency, after McGuire and Bavoil.
+//
+// With `App::order_independent_transparency` the scene pass has two more
+// color attachments. Transparent entities add their color, weighted by
+// coverage and depth, to the accumulation target and multiply their
+// coverage out of the revealage target, in whatever order they are drawn.
+// The composite pass then blends the weighted average over the scene color
+// by how much of it is still revealed.
+
+/// Premultiplied color times weight, and the sum of the weighted coverage.
+pub(crate) const ACCUM_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// The product of one minus each surface's coverage.
+pub(crate) const REVEAL_FORMAT: vk::Format = vk::Format::R8_UNORM;
+
+/// What a scene pass pipeline's fragment shader does with the OIT targets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum OitWrites {
+    /// Nothing, it does not write them.
+    Masked,
+    /// Resets them where it draws, so that what it covers does not show
+    /// through. Only `frag_oit.spv` and `frag_indirect_oit.spv` write them.
+    Reset,
+    /// Accumulates into them.
+    Accumulate,
+}
+
+/// The blend states of a scene pass pipeline's color attachments:
+/// `attachment` for the HDR color, then the OIT targets' if the pass has
+/// them.
+pub(crate) fn scene_blend_attachments(
+    oit: bool,
+    attachment: vk::PipelineColorBlendAttachmentState,
+    writes: OitWrites,
+) -> Vec<vk::PipelineColorBlendAttachmentState> {
+    let mut attachments = vec![attachment];
+    if !oit {
+        return attachments;
+    }
+
+    let target = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false)
+        .color_blend_op(vk::BlendOp::ADD)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let (accum, reveal) = match writes {
+        OitWrites::Masked => {
+            let masked = target.color_write_mask(vk::ColorComponentFlags::empty());
+            (masked, masked)
+        }
+        OitWrites::Reset => (target, target),
+        OitWrites::Accumulate => (
+            target
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ONE)
+                .dst_color_blend_factor(vk::BlendFactor::ONE)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE),
+            target
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::ZERO)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_COLOR)
+                .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+        ),
+    };
+
+    attachments.extend([accum.build(), reveal.build()]);
+    attachments
+}
+
+/// The accumulation and revealage targets, multisampled like the scene
+/// color. Left null without `order_independent_transparency`.
+pub(crate) unsafe fn create_oit_objects(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.order_independent_transparency {
+        data.oit_accum_image = vk::Image::null();
+        data.oit_accum_image_memory = vk::DeviceMemory::null();
+        data.oit_accum_image_view = vk::ImageView::null();
+        data.oit_reveal_image = vk::Image::null();
+        data.oit_reveal_image_memory = vk::DeviceMemory::null();
+        data.oit_reveal_image_view = vk::ImageView::null();
+        return Ok(());
+    }
+
+    // Dynamic rendering has no input attachments, so the composite pass
+    // samples them instead.
+    let usage = if data.dynamic_rendering {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+    } else {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::INPUT_ATTACHMENT
+            | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+    };
+
+    let targets = [ACCUM_FORMAT, REVEAL_FORMAT].map(|format| {
+        let (image, memory) = create_image(
+            instance,
+            device,
+            data,
+            data.swapchain_extent.width,
+            data.swapchain_extent.height,
+            1,
+            1,
+            data.msaa_samples,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::empty(),
+        )
+        .unwrap();
+        let view = create_image_view(device, image, format, vk::ImageAspectFlags::COLOR, 1).unwrap();
+        (image, memory, view)
+    });
+
+    let [(accum_image, accum_memory, accum_view), (reveal_image, reveal_memory, reveal_view)] = targets;
+    data.oit_accum_image = accum_image;
+    data.oit_accum_image_memory = accum_memory;
+    data.oit_accum_image_view = accum_view;
+    data.oit_reveal_image = reveal_image;
+    data.oit_reveal_image_memory = reveal_memory;
+    data.oit_reveal_image_view = reveal_view;
+    Ok(())
+}
+
+/// How the composite pass reads the targets: as input attachments of the
+/// render pass, or with a nearest sampler under dynamic rendering.
+fn target_descriptor_type(data: &AppData) -> vk::DescriptorType {
+    if data.dynamic_rendering {
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+    } else {
+        vk::DescriptorType::INPUT_ATTACHMENT
+    }
+}
+
+pub(crate) unsafe fn create_oit_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let bindings = (0..2)
+        .map(|binding| {
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(target_descriptor_type(data))
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    data.oit_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// Points the composite pass at the targets, which are shared by every
+/// framebuffer, so one set is enough. Left null without
+/// `order_independent_transparency`.
+pub(crate) unsafe fn create_oit_descriptor_set(device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.order_independent_transparency {
+        data.oit_descriptor_pool = vk::DescriptorPool::null();
+        data.oit_descriptor_set = vk::DescriptorSet::null();
+        return Ok(());
+    }
+
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(target_descriptor_type(data))
+        .descriptor_count(2);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    data.oit_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = &[data.oit_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.oit_descriptor_pool)
+        .set_layouts(layouts);
+    data.oit_descriptor_set = device.allocate_descriptor_sets(&info).unwrap()[0];
+
+    let sampler = if data.dynamic_rendering {
+        data.samplers.get(device, SamplerDesc::nearest())
+    } else {
+        vk::Sampler::null()
+    };
+
+    let image_infos = [data.oit_accum_image_view, data.oit_reveal_image_view].map(|view| {
+        [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(view)
+            .sampler(sampler)]
+    });
+
+    let writes = image_infos
+        .iter()
+        .enumerate()
+        .map(|(binding, image_info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(data.oit_descriptor_set)
+                .dst_binding(binding as u32)
+                .dst_array_element(0)
+                .descriptor_type(target_descriptor_type(data))
+                .image_info(image_info)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+    Ok(())
+}
+
+/// A full-screen triangle in the composite pass that blends the averaged
+/// transparent color over the scene color by the revealage. Left null
+/// without `order_independent_transparency`.
+pub(crate) unsafe fn create_oit_composite_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.order_independent_transparency {
+        data.oit_composite_pipeline_layout = vk::PipelineLayout::null();
+        data.oit_composite_pipeline = vk::Pipeline::null();
+        return Ok(());
+    }
+
+    let multisampled = data.msaa_samples != vk::SampleCountFlags::_1;
+    let vert = include_bytes!("../../shaders/tonemap_vert.spv");
+    let frag = match (multisampled, data.dynamic_rendering) {
+        (true, true) => &include_bytes!("../../shaders/oit_composite_ms_sampled_frag.spv")[..],
+        (true, false) => &include_bytes!("../../shaders/oit_composite_ms_frag.spv")[..],
+        (false, true) => &include_bytes!("../../shaders/oit_composite_sampled_frag.spv")[..],
+        (false, false) => &include_bytes!("../../shaders/oit_composite_frag.spv")[..],
+    };
+
+    let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+    let frag_shader_module = create_shader_module(device, frag).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // The sample count sizes the averaging loop.
+    let constants = SpecializationConstants::new().i32(0, data.msaa_samples.bits() as i32);
+    let specialization_info = constants.info();
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    // Shaded once per pixel and blended over every sample.
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let set_layouts = &[data.oit_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    data.oit_composite_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let formats = Pass::OitComposite.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.oit_composite_pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(Pass::OitComposite.subpass(data));
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.oit_composite_pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(())
+}
+
+/// Draws the composite into the current composite pass.
+pub(crate) unsafe fn record_oit_composite(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.oit_composite_pipeline);
+    device.cmd_bind_descriptor_sets(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        data.oit_composite_pipeline_layout,
+        0,
+        &[data.oit_descriptor_set],
+        &[],
+    );
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+}
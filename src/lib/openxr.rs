@@ -0,0 +1,1204 @@
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    mem, ptr,
+};
+
+use anyhow::{anyhow, Result};
+use cgmath::{Quaternion, SquareMatrix};
+use log::*;
+use vulkanalia::{
+    loader::{LibloadingLoader, Loader},
+    prelude::v1_0::*,
+};
+
+use crate::{
+    app::AppData,
+    camera::depth_terms,
+    physical_device::QueueFamilyIndices,
+    shader::{create_shader_module, SpecializationConstants},
+    texture::ColorSpace,
+    types::{Mat4, Vec3},
+};
+
+/// The OpenXR loader, which finds the active runtime.
+#[cfg(windows)]
+const LIBRARY: &str = "openxr_loader.dll";
+#[cfg(target_os = "android")]
+const LIBRARY: &str = "libopenxr_loader.so";
+#[cfg(all(unix, not(target_os = "android")))]
+const LIBRARY: &str = "libopenxr_loader.so.1";
+
+const VULKAN_ENABLE_EXTENSION: &CStr = c"XR_KHR_vulkan_enable";
+
+/// Both eyes are drawn by the copy, as view 0 and view 1.
+const VIEW_MASK: u32 = 0b11;
+
+// The subset of `openxr.h` needed to show the stereo target in a headset,
+// loaded at run time so the runtime stays optional.
+
+type XrResult = i32;
+type XrInstance = u64;
+type XrSession = u64;
+type XrSpace = u64;
+type XrSwapchain = u64;
+type XrSystemId = u64;
+type XrTime = i64;
+
+const XR_SUCCESS: XrResult = 0;
+const XR_EVENT_UNAVAILABLE: XrResult = 4;
+
+const XR_TYPE_INSTANCE_CREATE_INFO: i32 = 3;
+const XR_TYPE_SYSTEM_GET_INFO: i32 = 4;
+const XR_TYPE_VIEW_LOCATE_INFO: i32 = 6;
+const XR_TYPE_VIEW: i32 = 7;
+const XR_TYPE_SESSION_CREATE_INFO: i32 = 8;
+const XR_TYPE_SWAPCHAIN_CREATE_INFO: i32 = 9;
+const XR_TYPE_SESSION_BEGIN_INFO: i32 = 10;
+const XR_TYPE_VIEW_STATE: i32 = 11;
+const XR_TYPE_FRAME_END_INFO: i32 = 12;
+const XR_TYPE_EVENT_DATA_BUFFER: i32 = 16;
+const XR_TYPE_EVENT_DATA_SESSION_STATE_CHANGED: i32 = 18;
+const XR_TYPE_FRAME_WAIT_INFO: i32 = 33;
+const XR_TYPE_COMPOSITION_LAYER_PROJECTION: i32 = 35;
+const XR_TYPE_REFERENCE_SPACE_CREATE_INFO: i32 = 37;
+const XR_TYPE_VIEW_CONFIGURATION_VIEW: i32 = 41;
+const XR_TYPE_FRAME_STATE: i32 = 44;
+const XR_TYPE_FRAME_BEGIN_INFO: i32 = 46;
+const XR_TYPE_COMPOSITION_LAYER_PROJECTION_VIEW: i32 = 48;
+const XR_TYPE_SWAPCHAIN_IMAGE_ACQUIRE_INFO: i32 = 55;
+const XR_TYPE_SWAPCHAIN_IMAGE_WAIT_INFO: i32 = 56;
+const XR_TYPE_SWAPCHAIN_IMAGE_RELEASE_INFO: i32 = 57;
+const XR_TYPE_GRAPHICS_BINDING_VULKAN_KHR: i32 = 1000025000;
+const XR_TYPE_SWAPCHAIN_IMAGE_VULKAN_KHR: i32 = 1000025001;
+const XR_TYPE_GRAPHICS_REQUIREMENTS_VULKAN_KHR: i32 = 1000025002;
+
+const XR_FORM_FACTOR_HEAD_MOUNTED_DISPLAY: i32 = 1;
+const XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO: i32 = 2;
+const XR_REFERENCE_SPACE_TYPE_LOCAL: i32 = 2;
+const XR_ENVIRONMENT_BLEND_MODE_OPAQUE: i32 = 1;
+const XR_SWAPCHAIN_USAGE_COLOR_ATTACHMENT_BIT: u64 = 0x1;
+const XR_INFINITE_DURATION: i64 = i64::MAX;
+
+const XR_SESSION_STATE_READY: i32 = 2;
+const XR_SESSION_STATE_STOPPING: i32 = 6;
+const XR_SESSION_STATE_LOSS_PENDING: i32 = 7;
+const XR_SESSION_STATE_EXITING: i32 = 8;
+
+/// OpenXR 1.0.0.
+const XR_API_VERSION: u64 = 1 << 48;
+
+/// Any info struct with nothing past its header.
+#[repr(C)]
+struct XrInfo {
+    ty: i32,
+    next: *const c_void,
+}
+
+impl XrInfo {
+    fn new(ty: i32) -> Self {
+        Self { ty, next: ptr::null() }
+    }
+}
+
+#[repr(C)]
+struct XrApplicationInfo {
+    application_name: [c_char; 128],
+    application_version: u32,
+    engine_name: [c_char; 128],
+    engine_version: u32,
+    api_version: u64,
+}
+
+#[repr(C)]
+struct XrInstanceCreateInfo {
+    ty: i32,
+    next: *const c_void,
+    create_flags: u64,
+    application_info: XrApplicationInfo,
+    enabled_api_layer_count: u32,
+    enabled_api_layer_names: *const *const c_char,
+    enabled_extension_count: u32,
+    enabled_extension_names: *const *const c_char,
+}
+
+#[repr(C)]
+struct XrSystemGetInfo {
+    ty: i32,
+    next: *const c_void,
+    form_factor: i32,
+}
+
+#[repr(C)]
+struct XrGraphicsRequirementsVulkan {
+    ty: i32,
+    next: *mut c_void,
+    min_api_version_supported: u64,
+    max_api_version_supported: u64,
+}
+
+#[repr(C)]
+struct XrGraphicsBindingVulkan {
+    ty: i32,
+    next: *const c_void,
+    instance: vk::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: vk::Device,
+    queue_family_index: u32,
+    queue_index: u32,
+}
+
+#[repr(C)]
+struct XrSessionCreateInfo {
+    ty: i32,
+    next: *const c_void,
+    create_flags: u64,
+    system_id: XrSystemId,
+}
+
+#[repr(C)]
+struct XrSessionBeginInfo {
+    ty: i32,
+    next: *const c_void,
+    primary_view_configuration_type: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct XrPosef {
+    orientation: [f32; 4],
+    position: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct XrFovf {
+    angle_left: f32,
+    angle_right: f32,
+    angle_up: f32,
+    angle_down: f32,
+}
+
+#[repr(C)]
+struct XrReferenceSpaceCreateInfo {
+    ty: i32,
+    next: *const c_void,
+    reference_space_type: i32,
+    pose_in_reference_space: XrPosef,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct XrViewConfigurationView {
+    ty: i32,
+    next: *mut c_void,
+    recommended_image_rect_width: u32,
+    max_image_rect_width: u32,
+    recommended_image_rect_height: u32,
+    max_image_rect_height: u32,
+    recommended_swapchain_sample_count: u32,
+    max_swapchain_sample_count: u32,
+}
+
+#[repr(C)]
+struct XrSwapchainCreateInfo {
+    ty: i32,
+    next: *const c_void,
+    create_flags: u64,
+    usage_flags: u64,
+    format: i64,
+    sample_count: u32,
+    width: u32,
+    height: u32,
+    face_count: u32,
+    array_size: u32,
+    mip_count: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct XrSwapchainImageVulkan {
+    ty: i32,
+    next: *mut c_void,
+    image: vk::Image,
+}
+
+#[repr(C)]
+struct XrSwapchainImageWaitInfo {
+    ty: i32,
+    next: *const c_void,
+    timeout: i64,
+}
+
+#[repr(C)]
+struct XrEventDataBuffer {
+    ty: i32,
+    next: *const c_void,
+    varying: [u8; 4000],
+}
+
+#[repr(C)]
+struct XrEventDataSessionStateChanged {
+    ty: i32,
+    next: *const c_void,
+    session: XrSession,
+    state: i32,
+    time: XrTime,
+}
+
+#[repr(C)]
+struct XrFrameState {
+    ty: i32,
+    next: *mut c_void,
+    predicted_display_time: XrTime,
+    predicted_display_period: i64,
+    should_render: u32,
+}
+
+#[repr(C)]
+struct XrViewLocateInfo {
+    ty: i32,
+    next: *const c_void,
+    view_configuration_type: i32,
+    display_time: XrTime,
+    space: XrSpace,
+}
+
+#[repr(C)]
+struct XrViewState {
+    ty: i32,
+    next: *mut c_void,
+    view_state_flags: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct XrView {
+    ty: i32,
+    next: *mut c_void,
+    pose: XrPosef,
+    fov: XrFovf,
+}
+
+impl Default for XrView {
+    fn default() -> Self {
+        Self {
+            ty: XR_TYPE_VIEW,
+            next: ptr::null_mut(),
+            pose: XrPosef::default(),
+            fov: XrFovf::default(),
+        }
+    }
+}
+
+#[repr(C)]
+struct XrSwapchainSubImage {
+    swapchain: XrSwapchain,
+    image_rect: [i32; 4],
+    image_array_index: u32,
+}
+
+#[repr(C)]
+struct XrCompositionLayerProjectionView {
+    ty: i32,
+    next: *const c_void,
+    pose: XrPosef,
+    fov: XrFovf,
+    sub_image: XrSwapchainSubImage,
+}
+
+#[repr(C)]
+struct XrCompositionLayerProjection {
+    ty: i32,
+    next: *const c_void,
+    layer_flags: u64,
+    space: XrSpace,
+    view_count: u32,
+    views: *const XrCompositionLayerProjectionView,
+}
+
+#[repr(C)]
+struct XrFrameEndInfo {
+    ty: i32,
+    next: *const c_void,
+    display_time: XrTime,
+    environment_blend_mode: i32,
+    layer_count: u32,
+    layers: *const *const XrCompositionLayerProjection,
+}
+
+type GetInstanceProcAddr = extern "system" fn(XrInstance, *const c_char, *mut Option<extern "system" fn()>) -> XrResult;
+
+/// The runtime's functions, loaded through `xrGetInstanceProcAddr`.
+#[derive(Debug)]
+struct XrFunctions {
+    destroy_instance: extern "system" fn(XrInstance) -> XrResult,
+    get_system: extern "system" fn(XrInstance, *const XrSystemGetInfo, *mut XrSystemId) -> XrResult,
+    get_vulkan_graphics_requirements:
+        extern "system" fn(XrInstance, XrSystemId, *mut XrGraphicsRequirementsVulkan) -> XrResult,
+    get_vulkan_instance_extensions: extern "system" fn(XrInstance, XrSystemId, u32, *mut u32, *mut c_char) -> XrResult,
+    get_vulkan_device_extensions: extern "system" fn(XrInstance, XrSystemId, u32, *mut u32, *mut c_char) -> XrResult,
+    get_vulkan_graphics_device:
+        extern "system" fn(XrInstance, XrSystemId, vk::Instance, *mut vk::PhysicalDevice) -> XrResult,
+    create_session: extern "system" fn(XrInstance, *const XrSessionCreateInfo, *mut XrSession) -> XrResult,
+    destroy_session: extern "system" fn(XrSession) -> XrResult,
+    create_reference_space:
+        extern "system" fn(XrSession, *const XrReferenceSpaceCreateInfo, *mut XrSpace) -> XrResult,
+    destroy_space: extern "system" fn(XrSpace) -> XrResult,
+    enumerate_view_configuration_views:
+        extern "system" fn(XrInstance, XrSystemId, i32, u32, *mut u32, *mut XrViewConfigurationView) -> XrResult,
+    enumerate_swapchain_formats: extern "system" fn(XrSession, u32, *mut u32, *mut i64) -> XrResult,
+    create_swapchain: extern "system" fn(XrSession, *const XrSwapchainCreateInfo, *mut XrSwapchain) -> XrResult,
+    destroy_swapchain: extern "system" fn(XrSwapchain) -> XrResult,
+    enumerate_swapchain_images: extern "system" fn(XrSwapchain, u32, *mut u32, *mut XrSwapchainImageVulkan) -> XrResult,
+    acquire_swapchain_image: extern "system" fn(XrSwapchain, *const XrInfo, *mut u32) -> XrResult,
+    wait_swapchain_image: extern "system" fn(XrSwapchain, *const XrSwapchainImageWaitInfo) -> XrResult,
+    release_swapchain_image: extern "system" fn(XrSwapchain, *const XrInfo) -> XrResult,
+    poll_event: extern "system" fn(XrInstance, *mut XrEventDataBuffer) -> XrResult,
+    begin_session: extern "system" fn(XrSession, *const XrSessionBeginInfo) -> XrResult,
+    end_session: extern "system" fn(XrSession) -> XrResult,
+    wait_frame: extern "system" fn(XrSession, *const XrInfo, *mut XrFrameState) -> XrResult,
+    begin_frame: extern "system" fn(XrSession, *const XrInfo) -> XrResult,
+    end_frame: extern "system" fn(XrSession, *const XrFrameEndInfo) -> XrResult,
+    locate_views:
+        extern "system" fn(XrSession, *const XrViewLocateInfo, *mut XrViewState, u32, *mut u32, *mut XrView) -> XrResult,
+}
+
+impl XrFunctions {
+    unsafe fn load(get: GetInstanceProcAddr, instance: XrInstance) -> Result<Self> {
+        Ok(Self {
+            destroy_instance: load(get, instance, c"xrDestroyInstance")?,
+            get_system: load(get, instance, c"xrGetSystem")?,
+            get_vulkan_graphics_requirements: load(get, instance, c"xrGetVulkanGraphicsRequirementsKHR")?,
+            get_vulkan_instance_extensions: load(get, instance, c"xrGetVulkanInstanceExtensionsKHR")?,
+            get_vulkan_device_extensions: load(get, instance, c"xrGetVulkanDeviceExtensionsKHR")?,
+            get_vulkan_graphics_device: load(get, instance, c"xrGetVulkanGraphicsDeviceKHR")?,
+            create_session: load(get, instance, c"xrCreateSession")?,
+            destroy_session: load(get, instance, c"xrDestroySession")?,
+            create_reference_space: load(get, instance, c"xrCreateReferenceSpace")?,
+            destroy_space: load(get, instance, c"xrDestroySpace")?,
+            enumerate_view_configuration_views: load(get, instance, c"xrEnumerateViewConfigurationViews")?,
+            enumerate_swapchain_formats: load(get, instance, c"xrEnumerateSwapchainFormats")?,
+            create_swapchain: load(get, instance, c"xrCreateSwapchain")?,
+            destroy_swapchain: load(get, instance, c"xrDestroySwapchain")?,
+            enumerate_swapchain_images: load(get, instance, c"xrEnumerateSwapchainImages")?,
+            acquire_swapchain_image: load(get, instance, c"xrAcquireSwapchainImage")?,
+            wait_swapchain_image: load(get, instance, c"xrWaitSwapchainImage")?,
+            release_swapchain_image: load(get, instance, c"xrReleaseSwapchainImage")?,
+            poll_event: load(get, instance, c"xrPollEvent")?,
+            begin_session: load(get, instance, c"xrBeginSession")?,
+            end_session: load(get, instance, c"xrEndSession")?,
+            wait_frame: load(get, instance, c"xrWaitFrame")?,
+            begin_frame: load(get, instance, c"xrBeginFrame")?,
+            end_frame: load(get, instance, c"xrEndFrame")?,
+            locate_views: load(get, instance, c"xrLocateViews")?,
+        })
+    }
+}
+
+unsafe fn load<T: Copy>(get: GetInstanceProcAddr, instance: XrInstance, name: &CStr) -> Result<T> {
+    let mut function = None;
+    check(get(instance, name.as_ptr(), &mut function), "xrGetInstanceProcAddr")?;
+    let function = function.ok_or_else(|| anyhow!("Missing {:?}.", name))?;
+    Ok(mem::transmute_copy(&function))
+}
+
+fn check(result: XrResult, function: &str) -> Result<()> {
+    if result < XR_SUCCESS {
+        Err(anyhow!("{} failed ({}).", function, result))
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads one of the runtime's space separated extension lists.
+fn extension_list(
+    get: impl Fn(u32, *mut u32, *mut c_char) -> XrResult,
+    function: &str,
+) -> Result<Vec<CString>> {
+    let mut length = 0;
+    check(get(0, &mut length, ptr::null_mut()), function)?;
+    let mut buffer = vec![0 as c_char; length as usize];
+    check(get(length, &mut length, buffer.as_mut_ptr()), function)?;
+
+    let list = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+    Ok(list
+        .to_string_lossy()
+        .split_whitespace()
+        .map(|e| CString::new(e).unwrap())
+        .collect())
+}
+
+/// Runs a stage of setting up `xr`, if it is still there, and gives up on
+/// OpenXR if the stage fails. `device` is given once it exists.
+pub(crate) unsafe fn xr_stage<T>(
+    xr: &mut Option<OpenXr>,
+    device: Option<&Device>,
+    stage: impl FnOnce(&mut OpenXr) -> Result<T>,
+) -> Option<T> {
+    match stage(xr.as_mut()?) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Continuing without OpenXR: {}", e);
+            let mut runtime = xr.take().unwrap();
+            match device {
+                Some(device) => runtime.destroy(device),
+                None => runtime.disconnect(),
+            }
+            None
+        }
+    }
+}
+
+/// An OpenXR frame in progress, from `xrWaitFrame` until `xrEndFrame`.
+#[derive(Debug)]
+pub(crate) struct XrFrame {
+    display_time: XrTime,
+    /// The eyes and the swapchain image to draw them into, unless the
+    /// runtime asked for this frame to be skipped.
+    target: Option<([XrView; 2], u32)>,
+}
+
+impl XrFrame {
+    /// The swapchain image to draw the eyes into, if they are drawn.
+    pub(crate) fn image_index(&self) -> Option<u32> {
+        self.target.map(|(_, image_index)| image_index)
+    }
+
+    /// The eyes' views and (Vulkan clip space) projections, from the poses
+    /// the runtime predicts for the display time. The tracking space's
+    /// origin is the camera, which shares its view space axes.
+    pub(crate) fn eyes(&self, view: Mat4, near: f32, far: f32) -> Option<([Mat4; 2], [Mat4; 2])> {
+        let (views, _) = self.target?;
+        let eye = |v: &XrView| {
+            let [x, y, z, w] = v.pose.orientation;
+            let position = Vec3::from(v.pose.position);
+            let rotation = Mat4::from(Quaternion::new(w, x, y, z));
+            let pose = Mat4::from_translation(position) * rotation;
+            (pose.invert().unwrap() * view, projection(&v.fov, near, far))
+        };
+        let (left, right) = (eye(&views[0]), eye(&views[1]));
+        Some(([left.0, right.0], [left.1, right.1]))
+    }
+}
+
+/// An asymmetric perspective projection in Vulkan clip space, with Y down
+/// and depth in [0, 1] like `App::perspective`.
+fn projection(fov: &XrFovf, near: f32, far: f32) -> Mat4 {
+    let (depth_scale, depth_offset) = depth_terms(near, far);
+    let left = fov.angle_left.tan();
+    let right = fov.angle_right.tan();
+    let up = fov.angle_up.tan();
+    let down = fov.angle_down.tan();
+    let width = right - left;
+    let height = down - up;
+
+    Mat4::new(
+        2.0 / width,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        2.0 / height,
+        0.0,
+        0.0,
+        (right + left) / width,
+        (up + down) / height,
+        depth_scale,
+        -1.0,
+        0.0,
+        0.0,
+        depth_offset,
+        0.0,
+    )
+}
+
+/// A session with an OpenXR runtime, which shows the stereo target in a
+/// headset and paces frames to its display.
+///
+/// Created in stages, as Vulkan is: `load` before the instance, which then
+/// needs `instance_extensions`, `physical_device` and `device_extensions`
+/// before the device, and `create_session` and `create_pipeline` after it.
+#[derive(Debug)]
+pub(crate) struct OpenXr {
+    _loader: LibloadingLoader,
+    functions: XrFunctions,
+    instance: XrInstance,
+    system: XrSystemId,
+    /// The oldest Vulkan version the runtime supports.
+    min_api_version: u64,
+    session: XrSession,
+    space: XrSpace,
+    swapchain: XrSwapchain,
+    /// Between `xrBeginSession` and `xrEndSession`.
+    running: bool,
+    /// The runtime is done with the session, which will not start again.
+    exiting: bool,
+    format: vk::Format,
+    image_views: Vec<vk::ImageView>,
+    framebuffers: Vec<vk::Framebuffer>,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl OpenXr {
+    /// Connects to the active runtime and finds a head mounted display.
+    pub(crate) unsafe fn load() -> Result<Self> {
+        let loader = LibloadingLoader::new(LIBRARY)?;
+        let get: GetInstanceProcAddr = mem::transmute(
+            loader
+                .load(b"xrGetInstanceProcAddr\0")
+                .map_err(|e| anyhow!("{}", e))?,
+        );
+
+        let mut application_info = XrApplicationInfo {
+            application_name: [0; 128],
+            application_version: 1,
+            engine_name: [0; 128],
+            engine_version: 1,
+            api_version: XR_API_VERSION,
+        };
+        for (to, from) in application_info.application_name.iter_mut().zip(b"Ozen Athena") {
+            *to = *from as c_char;
+        }
+        for (to, from) in application_info.engine_name.iter_mut().zip(b"Ozen Athena") {
+            *to = *from as c_char;
+        }
+
+        let extensions = [VULKAN_ENABLE_EXTENSION.as_ptr()];
+        let info = XrInstanceCreateInfo {
+            ty: XR_TYPE_INSTANCE_CREATE_INFO,
+            next: ptr::null(),
+            create_flags: 0,
+            application_info,
+            enabled_api_layer_count: 0,
+            enabled_api_layer_names: ptr::null(),
+            enabled_extension_count: extensions.len() as u32,
+            enabled_extension_names: extensions.as_ptr(),
+        };
+        let create_instance: extern "system" fn(*const XrInstanceCreateInfo, *mut XrInstance) -> XrResult =
+            load(get, 0, c"xrCreateInstance")?;
+        let mut instance = 0;
+        check(create_instance(&info, &mut instance), "xrCreateInstance")?;
+
+        let functions = match XrFunctions::load(get, instance) {
+            Ok(functions) => functions,
+            Err(e) => {
+                if let Ok(destroy) = load::<extern "system" fn(XrInstance) -> XrResult>(get, instance, c"xrDestroyInstance") {
+                    destroy(instance);
+                }
+                return Err(e);
+            }
+        };
+
+        let mut xr = Self {
+            _loader: loader,
+            functions,
+            instance,
+            system: 0,
+            min_api_version: 0,
+            session: 0,
+            space: 0,
+            swapchain: 0,
+            running: false,
+            exiting: false,
+            format: vk::Format::UNDEFINED,
+            image_views: vec![],
+            framebuffers: vec![],
+            render_pass: vk::RenderPass::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        };
+
+        let info = XrSystemGetInfo {
+            ty: XR_TYPE_SYSTEM_GET_INFO,
+            next: ptr::null(),
+            form_factor: XR_FORM_FACTOR_HEAD_MOUNTED_DISPLAY,
+        };
+        if let Err(e) = check((xr.functions.get_system)(instance, &info, &mut xr.system), "xrGetSystem") {
+            (xr.functions.destroy_instance)(instance);
+            return Err(e);
+        }
+
+        // Has to be asked before a session is created.
+        let mut requirements = XrGraphicsRequirementsVulkan {
+            ty: XR_TYPE_GRAPHICS_REQUIREMENTS_VULKAN_KHR,
+            next: ptr::null_mut(),
+            min_api_version_supported: 0,
+            max_api_version_supported: 0,
+        };
+        let result = (xr.functions.get_vulkan_graphics_requirements)(instance, xr.system, &mut requirements);
+        if let Err(e) = check(result, "xrGetVulkanGraphicsRequirementsKHR") {
+            (xr.functions.destroy_instance)(instance);
+            return Err(e);
+        }
+        xr.min_api_version = requirements.min_api_version_supported;
+
+        Ok(xr)
+    }
+
+    /// The Vulkan instance extensions the runtime needs.
+    pub(crate) fn instance_extensions(&self) -> Result<Vec<CString>> {
+        let get = |capacity, length: *mut u32, buffer| {
+            (self.functions.get_vulkan_instance_extensions)(self.instance, self.system, capacity, length, buffer)
+        };
+        extension_list(get, "xrGetVulkanInstanceExtensionsKHR")
+    }
+
+    /// The Vulkan device extensions the runtime needs.
+    pub(crate) fn device_extensions(&self) -> Result<Vec<CString>> {
+        let get = |capacity, length: *mut u32, buffer| {
+            (self.functions.get_vulkan_device_extensions)(self.instance, self.system, capacity, length, buffer)
+        };
+        extension_list(get, "xrGetVulkanDeviceExtensionsKHR")
+    }
+
+    /// The physical device driving the headset, which has to be the one
+    /// rendered with.
+    pub(crate) fn physical_device(&self, instance: &Instance, data: &AppData) -> Result<vk::PhysicalDevice> {
+        // Versions are both major.minor.patch, packed differently.
+        let min = self.min_api_version;
+        let min = vk::make_version((min >> 48) as u32, ((min >> 32) & 0xFFFF) as u32, 0);
+        if data.instance_version < min {
+            return Err(anyhow!("The OpenXR runtime needs a newer Vulkan version."));
+        }
+
+        let mut physical_device = vk::PhysicalDevice::null();
+        let result = (self.functions.get_vulkan_graphics_device)(
+            self.instance,
+            self.system,
+            instance.handle(),
+            &mut physical_device,
+        );
+        check(result, "xrGetVulkanGraphicsDeviceKHR")?;
+        Ok(physical_device)
+    }
+
+    /// Starts a session on `device`, with a local reference space and a
+    /// two layer swapchain at the runtime's recommended size, which
+    /// `data.xr_extent` is set to.
+    pub(crate) unsafe fn create_session(&mut self, instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device).unwrap();
+        let binding = XrGraphicsBindingVulkan {
+            ty: XR_TYPE_GRAPHICS_BINDING_VULKAN_KHR,
+            next: ptr::null(),
+            instance: instance.handle(),
+            physical_device: data.physical_device,
+            device: device.handle(),
+            queue_family_index: indices.graphics,
+            queue_index: 0,
+        };
+        let info = XrSessionCreateInfo {
+            ty: XR_TYPE_SESSION_CREATE_INFO,
+            next: &binding as *const XrGraphicsBindingVulkan as *const c_void,
+            create_flags: 0,
+            system_id: self.system,
+        };
+        check((self.functions.create_session)(self.instance, &info, &mut self.session), "xrCreateSession")?;
+
+        // Seated, with the origin where the headset started.
+        let info = XrReferenceSpaceCreateInfo {
+            ty: XR_TYPE_REFERENCE_SPACE_CREATE_INFO,
+            next: ptr::null(),
+            reference_space_type: XR_REFERENCE_SPACE_TYPE_LOCAL,
+            pose_in_reference_space: XrPosef {
+                orientation: [0.0, 0.0, 0.0, 1.0],
+                position: [0.0; 3],
+            },
+        };
+        check(
+            (self.functions.create_reference_space)(self.session, &info, &mut self.space),
+            "xrCreateReferenceSpace",
+        )?;
+
+        let mut count = 0;
+        let result = (self.functions.enumerate_view_configuration_views)(
+            self.instance,
+            self.system,
+            XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO,
+            0,
+            &mut count,
+            ptr::null_mut(),
+        );
+        check(result, "xrEnumerateViewConfigurationViews")?;
+        let mut views = vec![
+            XrViewConfigurationView {
+                ty: XR_TYPE_VIEW_CONFIGURATION_VIEW,
+                next: ptr::null_mut(),
+                recommended_image_rect_width: 0,
+                max_image_rect_width: 0,
+                recommended_image_rect_height: 0,
+                max_image_rect_height: 0,
+                recommended_swapchain_sample_count: 0,
+                max_swapchain_sample_count: 0,
+            };
+            count as usize
+        ];
+        let result = (self.functions.enumerate_view_configuration_views)(
+            self.instance,
+            self.system,
+            XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO,
+            count,
+            &mut count,
+            views.as_mut_ptr(),
+        );
+        check(result, "xrEnumerateViewConfigurationViews")?;
+        if views.len() != 2 {
+            return Err(anyhow!("Expected 2 views, the runtime has {}.", views.len()));
+        }
+
+        // Both eyes share one size, so they can share one layered image.
+        data.xr_extent = vk::Extent2D {
+            width: views.iter().map(|v| v.recommended_image_rect_width).max().unwrap(),
+            height: views.iter().map(|v| v.recommended_image_rect_height).max().unwrap(),
+        };
+
+        // In the runtime's order of preference, which puts sRGB first.
+        let mut count = 0;
+        let result = (self.functions.enumerate_swapchain_formats)(self.session, 0, &mut count, ptr::null_mut());
+        check(result, "xrEnumerateSwapchainFormats")?;
+        let mut formats = vec![0; count as usize];
+        let result =
+            (self.functions.enumerate_swapchain_formats)(self.session, count, &mut count, formats.as_mut_ptr());
+        check(result, "xrEnumerateSwapchainFormats")?;
+        self.format = formats
+            .iter()
+            .map(|f| vk::Format::from_raw(*f as i32))
+            .find(|f| {
+                [
+                    vk::Format::R8G8B8A8_SRGB,
+                    vk::Format::B8G8R8A8_SRGB,
+                    vk::Format::R8G8B8A8_UNORM,
+                    vk::Format::B8G8R8A8_UNORM,
+                ]
+                .contains(f)
+            })
+            .ok_or_else(|| anyhow!("The OpenXR runtime has no 8 bit color swapchain format."))?;
+
+        let info = XrSwapchainCreateInfo {
+            ty: XR_TYPE_SWAPCHAIN_CREATE_INFO,
+            next: ptr::null(),
+            create_flags: 0,
+            usage_flags: XR_SWAPCHAIN_USAGE_COLOR_ATTACHMENT_BIT,
+            format: self.format.as_raw() as i64,
+            sample_count: 1,
+            width: data.xr_extent.width,
+            height: data.xr_extent.height,
+            face_count: 1,
+            array_size: 2,
+            mip_count: 1,
+        };
+        check(
+            (self.functions.create_swapchain)(self.session, &info, &mut self.swapchain),
+            "xrCreateSwapchain",
+        )?;
+
+        info!(
+            "Started an OpenXR session at {}x{} per eye.",
+            data.xr_extent.width, data.xr_extent.height
+        );
+        Ok(())
+    }
+
+    /// Creates the multiview pass that tonemaps the stereo target into the
+    /// runtime's swapchain images.
+    pub(crate) unsafe fn create_pipeline(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        let color_attachment = vk::AttachmentDescription::builder()
+            .format(self.format)
+            .samples(vk::SampleCountFlags::_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let color_attachment_ref = vk::AttachmentReference::builder()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+        let color_attachments = &[color_attachment_ref];
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(color_attachments);
+
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+        let view_masks = &[VIEW_MASK];
+        let correlation_masks = &[VIEW_MASK];
+        let mut multiview_info = vk::RenderPassMultiviewCreateInfo::builder()
+            .view_masks(view_masks)
+            .correlation_masks(correlation_masks);
+
+        let attachments = &[color_attachment];
+        let subpasses = &[subpass];
+        let dependencies = &[dependency];
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(attachments)
+            .subpasses(subpasses)
+            .dependencies(dependencies)
+            .push_next(&mut multiview_info);
+        self.render_pass = device.create_render_pass(&info, None).unwrap();
+
+        let mut count = 0;
+        let result = (self.functions.enumerate_swapchain_images)(self.swapchain, 0, &mut count, ptr::null_mut());
+        check(result, "xrEnumerateSwapchainImages")?;
+        let mut images = vec![
+            XrSwapchainImageVulkan {
+                ty: XR_TYPE_SWAPCHAIN_IMAGE_VULKAN_KHR,
+                next: ptr::null_mut(),
+                image: vk::Image::null(),
+            };
+            count as usize
+        ];
+        let result =
+            (self.functions.enumerate_swapchain_images)(self.swapchain, count, &mut count, images.as_mut_ptr());
+        check(result, "xrEnumerateSwapchainImages")?;
+
+        for image in &images {
+            let subresource_range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(2);
+            let info = vk::ImageViewCreateInfo::builder()
+                .image(image.image)
+                .view_type(vk::ImageViewType::_2D_ARRAY)
+                .format(self.format)
+                .subresource_range(subresource_range);
+            let image_view = device.create_image_view(&info, None).unwrap();
+            self.image_views.push(image_view);
+
+            let attachments = &[image_view];
+            let info = vk::FramebufferCreateInfo::builder()
+                .render_pass(self.render_pass)
+                .attachments(attachments)
+                .width(data.xr_extent.width)
+                .height(data.xr_extent.height)
+                .layers(1);
+            self.framebuffers.push(device.create_framebuffer(&info, None).unwrap());
+        }
+
+        let vert = include_bytes!("../../shaders/tonemap_vert.spv");
+        let frag = include_bytes!("../../shaders/xr_eyes_frag.spv");
+
+        let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+        let frag_shader_module = create_shader_module(device, &frag[..]).unwrap();
+
+        let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::VERTEX)
+            .module(vert_shader_module)
+            .name(b"main\0");
+
+        // sRGB is encoded when the swapchain format does not do it on write.
+        let constants = SpecializationConstants::new()
+            .bool(1, ColorSpace::of(self.format) == ColorSpace::Linear);
+        let specialization_info = constants.info();
+
+        let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::FRAGMENT)
+            .module(frag_shader_module)
+            .name(b"main\0")
+            .specialization_info(&specialization_info);
+
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+
+        let viewport = vk::Viewport::builder()
+            .x(0.0)
+            .y(0.0)
+            .width(data.xr_extent.width as f32)
+            .height(data.xr_extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        let scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D { x: 0, y: 0 })
+            .extent(data.xr_extent);
+
+        let viewports = &[viewport];
+        let scissors = &[scissor];
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewports(viewports)
+            .scissors(scissors);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_bias_enable(false);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .sample_shading_enable(false)
+            .rasterization_samples(vk::SampleCountFlags::_1);
+
+        let attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::all())
+            .blend_enable(false);
+
+        let attachments = &[attachment];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .logic_op(vk::LogicOp::COPY)
+            .attachments(attachments)
+            .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+        let set_layouts = &[data.stereo_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+        self.pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+        let stages = &[vert_stage, frag_stage];
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(stages)
+            .vertex_input_state(&vertex_input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .layout(self.pipeline_layout)
+            .render_pass(self.render_pass)
+            .subpass(0);
+
+        self.pipeline = device
+            .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+            .unwrap()
+            .0[0];
+
+        device.destroy_shader_module(vert_shader_module, None);
+        device.destroy_shader_module(frag_shader_module, None);
+        Ok(())
+    }
+
+    /// Handles session state changes, then waits for the runtime's next
+    /// frame, which paces rendering to the headset's display. Returns the
+    /// frame begun, if the session is running.
+    pub(crate) unsafe fn begin_frame(&mut self) -> Result<Option<XrFrame>> {
+        self.poll_events()?;
+        if !self.running {
+            return Ok(None);
+        }
+
+        let mut state = XrFrameState {
+            ty: XR_TYPE_FRAME_STATE,
+            next: ptr::null_mut(),
+            predicted_display_time: 0,
+            predicted_display_period: 0,
+            should_render: 0,
+        };
+        let info = XrInfo::new(XR_TYPE_FRAME_WAIT_INFO);
+        check((self.functions.wait_frame)(self.session, &info, &mut state), "xrWaitFrame")?;
+        let info = XrInfo::new(XR_TYPE_FRAME_BEGIN_INFO);
+        check((self.functions.begin_frame)(self.session, &info), "xrBeginFrame")?;
+
+        let mut frame = XrFrame {
+            display_time: state.predicted_display_time,
+            target: None,
+        };
+        if state.should_render == 0 {
+            return Ok(Some(frame));
+        }
+
+        let info = XrViewLocateInfo {
+            ty: XR_TYPE_VIEW_LOCATE_INFO,
+            next: ptr::null(),
+            view_configuration_type: XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO,
+            display_time: frame.display_time,
+            space: self.space,
+        };
+        let mut view_state = XrViewState {
+            ty: XR_TYPE_VIEW_STATE,
+            next: ptr::null_mut(),
+            view_state_flags: 0,
+        };
+        let mut views = [XrView::default(); 2];
+        let mut count = 0;
+        let result = (self.functions.locate_views)(
+            self.session,
+            &info,
+            &mut view_state,
+            views.len() as u32,
+            &mut count,
+            views.as_mut_ptr(),
+        );
+        check(result, "xrLocateViews")?;
+
+        let mut image_index = 0;
+        let info = XrInfo::new(XR_TYPE_SWAPCHAIN_IMAGE_ACQUIRE_INFO);
+        check(
+            (self.functions.acquire_swapchain_image)(self.swapchain, &info, &mut image_index),
+            "xrAcquireSwapchainImage",
+        )?;
+        let info = XrSwapchainImageWaitInfo {
+            ty: XR_TYPE_SWAPCHAIN_IMAGE_WAIT_INFO,
+            next: ptr::null(),
+            timeout: XR_INFINITE_DURATION,
+        };
+        check((self.functions.wait_swapchain_image)(self.swapchain, &info), "xrWaitSwapchainImage")?;
+
+        frame.target = Some((views, image_index));
+        Ok(Some(frame))
+    }
+
+    /// Records tonemapping the stereo target into the acquired swapchain
+    /// image. Must be recorded after the stereo pass has ended.
+    pub(crate) unsafe fn record_copy(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        frame: &XrFrame,
+    ) {
+        let Some(image_index) = frame.image_index() else {
+            return;
+        };
+
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(data.xr_extent);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[image_index as usize])
+            .render_area(render_area);
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[data.stereo_target.set],
+            &[],
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        device.cmd_end_render_pass(command_buffer);
+    }
+
+    /// Releases the swapchain image, whose commands have been submitted,
+    /// and hands the eyes to the compositor.
+    pub(crate) unsafe fn end_frame(&mut self, frame: XrFrame, data: &AppData) -> Result<()> {
+        let mut projection_views = vec![];
+        if let Some((views, _)) = frame.target {
+            let info = XrInfo::new(XR_TYPE_SWAPCHAIN_IMAGE_RELEASE_INFO);
+            check(
+                (self.functions.release_swapchain_image)(self.swapchain, &info),
+                "xrReleaseSwapchainImage",
+            )?;
+
+            let extent = data.xr_extent;
+            projection_views.extend(views.iter().enumerate().map(|(eye, view)| {
+                XrCompositionLayerProjectionView {
+                    ty: XR_TYPE_COMPOSITION_LAYER_PROJECTION_VIEW,
+                    next: ptr::null(),
+                    pose: view.pose,
+                    fov: view.fov,
+                    sub_image: XrSwapchainSubImage {
+                        swapchain: self.swapchain,
+                        image_rect: [0, 0, extent.width as i32, extent.height as i32],
+                        image_array_index: eye as u32,
+                    },
+                }
+            }));
+        }
+
+        let layer = XrCompositionLayerProjection {
+            ty: XR_TYPE_COMPOSITION_LAYER_PROJECTION,
+            next: ptr::null(),
+            layer_flags: 0,
+            space: self.space,
+            view_count: projection_views.len() as u32,
+            views: projection_views.as_ptr(),
+        };
+        let layers = [&layer as *const XrCompositionLayerProjection];
+        let info = XrFrameEndInfo {
+            ty: XR_TYPE_FRAME_END_INFO,
+            next: ptr::null(),
+            display_time: frame.display_time,
+            environment_blend_mode: XR_ENVIRONMENT_BLEND_MODE_OPAQUE,
+            layer_count: if projection_views.is_empty() { 0 } else { 1 },
+            layers: layers.as_ptr(),
+        };
+        check((self.functions.end_frame)(self.session, &info), "xrEndFrame")
+    }
+
+    /// Begins and ends the session as the runtime asks.
+    unsafe fn poll_events(&mut self) -> Result<()> {
+        loop {
+            let mut event = XrEventDataBuffer {
+                ty: XR_TYPE_EVENT_DATA_BUFFER,
+                next: ptr::null(),
+                varying: [0; 4000],
+            };
+            let result = (self.functions.poll_event)(self.instance, &mut event);
+            if result == XR_EVENT_UNAVAILABLE {
+                return Ok(());
+            }
+            check(result, "xrPollEvent")?;
+            if event.ty != XR_TYPE_EVENT_DATA_SESSION_STATE_CHANGED {
+                continue;
+            }
+
+            let event = &*(&event as *const XrEventDataBuffer as *const XrEventDataSessionStateChanged);
+            match event.state {
+                XR_SESSION_STATE_READY if !self.exiting => {
+                    let info = XrSessionBeginInfo {
+                        ty: XR_TYPE_SESSION_BEGIN_INFO,
+                        next: ptr::null(),
+                        primary_view_configuration_type: XR_VIEW_CONFIGURATION_TYPE_PRIMARY_STEREO,
+                    };
+                    check((self.functions.begin_session)(self.session, &info), "xrBeginSession")?;
+                    self.running = true;
+                    info!("OpenXR session running.");
+                }
+                XR_SESSION_STATE_STOPPING => {
+                    check((self.functions.end_session)(self.session), "xrEndSession")?;
+                    self.running = false;
+                    info!("OpenXR session stopped.");
+                }
+                XR_SESSION_STATE_EXITING | XR_SESSION_STATE_LOSS_PENDING => {
+                    self.running = false;
+                    self.exiting = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Destroys the session, which has to happen before the device is.
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        self.framebuffers.drain(..).for_each(|f| device.destroy_framebuffer(f, None));
+        self.image_views.drain(..).for_each(|v| device.destroy_image_view(v, None));
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_render_pass(self.render_pass, None);
+        self.disconnect();
+    }
+
+    /// Destroys the runtime's objects, leaving Vulkan's.
+    unsafe fn disconnect(&self) {
+        if self.swapchain != 0 {
+            (self.functions.destroy_swapchain)(self.swapchain);
+        }
+        if self.space != 0 {
+            (self.functions.destroy_space)(self.space);
+        }
+        if self.session != 0 {
+            (self.functions.destroy_session)(self.session);
+        }
+        (self.functions.destroy_instance)(self.instance);
+    }
+}
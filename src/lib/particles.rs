@@ -0,0 +1,368 @@
+use anyhow::Result;
+use cgmath::{vec2, vec3, vec4, ElementWise, VectorSpace};
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    depth_object::depth_compare_op,
+    dynamic_buffer::DynamicBuffer,
+    dynamic_rendering::Pass,
+    gpu_particles::GpuParticle,
+    oit::{scene_blend_attachments, OitWrites},
+    shader::{create_shader_module, SpecializationConstants},
+    types::{Mat4, Vec2, Vec3, Vec4},
+};
+
+const PARTICLE_FRAG: &[u8] = include_bytes!("../../shaders/particle_frag.spv");
+
+/// The corners of a particle's quad, two triangles, from -1 to 1.
+const CORNERS: [[f32; 2]; 6] = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ParticleVertex {
+    pub(crate) pos: Vec3,
+    pub(crate) corner: Vec2,
+    pub(crate) size: f32,
+    pub(crate) color: Vec4,
+}
+
+impl ParticleVertex {
+    pub(crate) fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<ParticleVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    pub(crate) fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 4] {
+        let pos = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(0)
+            .format(vk::Format::R32G32B32_SFLOAT)
+            .offset(0)
+            .build();
+        let corner = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(1)
+            .format(vk::Format::R32G32_SFLOAT)
+            .offset(size_of::<Vec3>() as u32)
+            .build();
+        let size = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(2)
+            .format(vk::Format::R32_SFLOAT)
+            .offset((size_of::<Vec3>() + size_of::<Vec2>()) as u32)
+            .build();
+        let color = vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(3)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset((size_of::<Vec3>() + size_of::<Vec2>() + size_of::<f32>()) as u32)
+            .build();
+
+        [pos, corner, size, color]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Particle {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    /// Seconds since the particle spawned.
+    pub age: f32,
+}
+
+/// Spawns particles at a point and moves them on the CPU. Each is drawn as a
+/// soft, camera-facing quad whose color and size fade over its life.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticleEmitter {
+    pub position: Vec3,
+    /// Particles spawned per second.
+    pub spawn_rate: f32,
+    /// Seconds each particle lives.
+    pub lifetime: f32,
+    /// The initial velocity.
+    pub velocity: Vec3,
+    /// How far each component of the initial velocity varies either way.
+    pub velocity_spread: Vec3,
+    /// Added to the velocity every second.
+    pub acceleration: Vec3,
+    /// The color at spawn and at death, blended linearly. Alpha is opacity.
+    pub start_color: Vec4,
+    pub end_color: Vec4,
+    /// Quad half-widths at spawn and at death.
+    pub start_size: f32,
+    pub end_size: f32,
+    /// Spawning stops while this many particles are alive.
+    pub max_particles: usize,
+    pub(crate) particles: Vec<Particle>,
+    /// Fractional particles owed by the spawn rate.
+    pending: f32,
+    seed: u32,
+}
+
+impl ParticleEmitter {
+    pub fn new(position: Vec3) -> Self {
+        Self {
+            position,
+            spawn_rate: 50.0,
+            lifetime: 2.0,
+            velocity: vec3(0.0, 0.0, 1.0),
+            velocity_spread: vec3(0.3, 0.3, 0.2),
+            acceleration: vec3(0.0, 0.0, 0.0),
+            start_color: vec4(1.0, 1.0, 1.0, 1.0),
+            end_color: vec4(1.0, 1.0, 1.0, 0.0),
+            start_size: 0.05,
+            end_size: 0.1,
+            max_particles: 1000,
+            particles: vec![],
+            pending: 0.0,
+            seed: 0x9e37_79b9,
+        }
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.pending = 0.0;
+    }
+
+    /// Ages and moves particles by `elapsed` seconds, removes dead ones and
+    /// spawns new ones.
+    pub fn update(&mut self, elapsed: f32) {
+        self.particles.retain_mut(|particle| {
+            particle.age += elapsed;
+            particle.velocity += self.acceleration * elapsed;
+            particle.position += particle.velocity * elapsed;
+            particle.age < self.lifetime
+        });
+
+        self.pending += self.spawn_rate.max(0.0) * elapsed;
+        while self.pending >= 1.0 {
+            self.pending -= 1.0;
+            if self.particles.len() >= self.max_particles {
+                continue;
+            }
+            let spread = vec3(self.random(), self.random(), self.random());
+            self.particles.push(Particle {
+                position: self.position,
+                velocity: self.velocity + self.velocity_spread.mul_element_wise(spread),
+                age: 0.0,
+            });
+        }
+    }
+
+    /// Appends a quad for each live particle.
+    pub(crate) fn vertices(&self, vertices: &mut Vec<ParticleVertex>) {
+        for particle in &self.particles {
+            let t = if self.lifetime > 0.0 { particle.age / self.lifetime } else { 1.0 };
+            let color = self.start_color.lerp(self.end_color, t);
+            let size = self.start_size + (self.end_size - self.start_size) * t;
+            vertices.extend(CORNERS.iter().map(|c| ParticleVertex {
+                pos: particle.position,
+                corner: vec2(c[0], c[1]),
+                size,
+                color,
+            }));
+        }
+    }
+
+    /// A uniform random number from -1 to 1, by xorshift.
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+}
+
+/// Sorts quads of six vertices back to front in `view` for alpha blending,
+/// by the `position` of their first vertex.
+pub(crate) fn sort_quads<V: Copy>(vertices: &mut [V], view: Mat4, position: impl Fn(&V) -> Vec3) {
+    let mut quads = vertices
+        .chunks_exact(6)
+        .map(|q| ((view * position(&q[0]).extend(1.0)).z, <[V; 6]>::try_from(q).unwrap()))
+        .collect::<Vec<_>>();
+    // Farther is more negative in view space.
+    quads.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (chunk, (_, quad)) in vertices.chunks_exact_mut(6).zip(quads) {
+        chunk.copy_from_slice(&quad);
+    }
+}
+
+/// Creates the pipelines for CPU and GPU simulated particles, which share
+/// the fragment shader and blending.
+pub(crate) unsafe fn create_particle_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let vert = include_bytes!("../../shaders/particle_vert.spv");
+    data.particle_pipeline = create_quad_pipeline(
+        device,
+        data,
+        &vert[..],
+        PARTICLE_FRAG,
+        &[ParticleVertex::binding_description()],
+        &ParticleVertex::attribute_descriptions(),
+        OitWrites::Masked,
+    )
+    .unwrap();
+
+    let vert = include_bytes!("../../shaders/gpu_particle_vert.spv");
+    data.gpu_particle_draw_pipeline = create_quad_pipeline(
+        device,
+        data,
+        &vert[..],
+        PARTICLE_FRAG,
+        &[GpuParticle::binding_description()],
+        &GpuParticle::attribute_descriptions(),
+        OitWrites::Masked,
+    )
+    .unwrap();
+
+    Ok(())
+}
+
+/// A blended pipeline for camera-facing quads in the scene pass, built from
+/// `vert` and `frag`. The texture table's capacity is specialization
+/// constant 0 of `frag`.
+pub(crate) unsafe fn create_quad_pipeline(
+    device: &Device,
+    data: &AppData,
+    vert: &[u8],
+    frag: &[u8],
+    binding_descriptions: &[vk::VertexInputBindingDescription],
+    attribute_descriptions: &[vk::VertexInputAttributeDescription],
+    oit_writes: OitWrites,
+) -> Result<vk::Pipeline> {
+    let vert_shader_module = create_shader_module(device, vert).unwrap();
+    let frag_shader_module = create_shader_module(device, frag).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let constants = SpecializationConstants::new().u32(0, data.texture_capacity);
+    let specialization_info = constants.info();
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(data.msaa_samples);
+
+    // Tested against the scene but not written, so quads do not hide each
+    // other.
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(true)
+        .depth_write_enable(false)
+        .depth_compare_op(depth_compare_op(data.reverse_z, vk::CompareOp::LESS_OR_EQUAL))
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    // Accumulated quads leave the scene color to the composite pass.
+    let color_write_mask = if oit_writes == OitWrites::Accumulate {
+        vk::ColorComponentFlags::empty()
+    } else {
+        vk::ColorComponentFlags::all()
+    };
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(color_write_mask)
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = scene_blend_attachments(data.order_independent_transparency, attachment.build(), oit_writes);
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(&attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    // Set per viewport, see `record_viewports`.
+    let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    let formats = Pass::Scene.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(0);
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    let pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(pipeline)
+}
+
+pub(crate) unsafe fn create_particle_vertex_buffers(data: &mut AppData) -> Result<()> {
+    data.particle_vertex_buffers = vec![DynamicBuffer::default(); data.swapchain_images.len()];
+    Ok(())
+}
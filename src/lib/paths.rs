@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+pub const APP_DIRECTORY: &str = "ozen-athena";
+
+/// When set, every directory is placed under this path instead of the
+/// platform locations.
+pub const DIRECTORY_OVERRIDE_VAR: &str = "OZEN_ATHENA_HOME";
+
+/// Where the engine keeps files it writes at runtime: the XDG base
+/// directories on Linux, `%APPDATA%`/`%LOCALAPPDATA%` on Windows and
+/// `~/Library` on macOS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Directories {
+    pub config: PathBuf,
+    pub cache: PathBuf,
+    pub data: PathBuf,
+}
+
+impl Directories {
+    /// Resolves the directories from `OZEN_ATHENA_HOME` or the platform
+    /// conventions.
+    pub fn resolve() -> Result<Self> {
+        match env::var_os(DIRECTORY_OVERRIDE_VAR) {
+            Some(root) if !root.is_empty() => Ok(Self::with_root(root)),
+            _ => Self::platform(),
+        }
+    }
+
+    /// Places every directory under `root`.
+    pub fn with_root(root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref();
+        Self {
+            config: root.join("config"),
+            cache: root.join("cache"),
+            data: root.join("data"),
+        }
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.config.join("settings.ini")
+    }
+
+    /// Pipeline caches are only valid for the device they were built on, so
+    /// each gets its own file.
+    pub fn pipeline_cache_file(&self, vendor_id: u32, device_id: u32) -> PathBuf {
+        self.cache.join(format!("pipeline_cache_{:04x}_{:04x}.bin", vendor_id, device_id))
+    }
+
+    /// Where the demo saves and loads its scene.
+    pub fn scene_file(&self) -> PathBuf {
+        self.data.join("scene.json")
+    }
+
+    pub fn screenshots(&self) -> PathBuf {
+        self.data.join("screenshots")
+    }
+
+    pub fn logs(&self) -> PathBuf {
+        self.data.join("logs")
+    }
+
+    /// Where the demo writes its log, replaced each run.
+    pub fn log_file(&self) -> PathBuf {
+        self.logs().join(format!("{}.log", APP_DIRECTORY))
+    }
+
+    pub fn create_all(&self) -> Result<()> {
+        for directory in [&self.config, &self.cache, &self.screenshots(), &self.logs()] {
+            fs::create_dir_all(directory)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    fn platform() -> Result<Self> {
+        let roaming = env_path("APPDATA")?;
+        let local = env_path("LOCALAPPDATA")?;
+        Ok(Self {
+            config: roaming.join(APP_DIRECTORY),
+            cache: local.join(APP_DIRECTORY).join("cache"),
+            data: local.join(APP_DIRECTORY),
+        })
+    }
+
+    #[cfg(target_os = "macos")]
+    fn platform() -> Result<Self> {
+        let library = env_path("HOME")?.join("Library");
+        let support = library.join("Application Support").join(APP_DIRECTORY);
+        Ok(Self {
+            config: support.clone(),
+            cache: library.join("Caches").join(APP_DIRECTORY),
+            data: support,
+        })
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    fn platform() -> Result<Self> {
+        let xdg = |var: &str, fallback: &str| -> Result<PathBuf> {
+            match env::var_os(var) {
+                // The spec says relative paths must be ignored.
+                Some(path) if Path::new(&path).is_absolute() => Ok(PathBuf::from(path)),
+                _ => Ok(env_path("HOME")?.join(fallback)),
+            }
+        };
+        Ok(Self {
+            config: xdg("XDG_CONFIG_HOME", ".config")?.join(APP_DIRECTORY),
+            cache: xdg("XDG_CACHE_HOME", ".cache")?.join(APP_DIRECTORY),
+            data: xdg("XDG_DATA_HOME", ".local/share")?.join(APP_DIRECTORY),
+        })
+    }
+}
+
+fn env_path(var: &str) -> Result<PathBuf> {
+    match env::var_os(var) {
+        Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => Err(anyhow!(
+            "`{}` is not set; set `{}` to choose a directory.",
+            var,
+            DIRECTORY_OVERRIDE_VAR
+        )),
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, env, fmt};
 
 use log::{info, warn};
 
@@ -8,14 +8,19 @@ use anyhow::{anyhow, Result};
 
 use vulkanalia::{
     prelude::v1_0::*,
-    Instance,
-    vk::KhrSurfaceExtension
+    Instance, Version,
+    vk::{InstanceV1_1, KhrSurfaceExtension}
 };
 
 use crate::{
-    app::{AppData, DEVICE_EXTENSIONS},
+    app::{AppData, DEVICE_EXTENSIONS, VULKAN_1_1, VULKAN_1_2},
+    bindless::{BINDLESS_TEXTURE_CAPACITY, FALLBACK_TEXTURE_CAPACITY},
+    depth_object::get_depth_format,
+    device_features::{check_required_features, get_device_capabilities, negotiate_features, DeviceFeature},
     swapchain::SwapchainSupport,
-    msaa::get_max_msaa_samples
+    msaa::get_max_msaa_samples,
+    portability::{check_vertex_strides, get_portability_subset},
+    ray_tracing::RayTracingLimits,
 };
 
 #[derive(Debug, Error)]
@@ -27,19 +32,24 @@ pub(crate) unsafe fn check_physical_device(
     data: &AppData,
     physical_device: vk::PhysicalDevice,
 ) -> Result<()> {
-    QueueFamilyIndices::get(instance, data, physical_device).unwrap();
+    if !data.xr_physical_device.is_null() && physical_device != data.xr_physical_device {
+        return Err(anyhow!("Not the device the OpenXR runtime renders with."));
+    }
+
+    QueueFamilyIndices::get(instance, data, physical_device)?;
 
-    check_physical_device_extensions(instance, physical_device).unwrap();
+    if !data.headless {
+        check_physical_device_extensions(instance, physical_device)?;
 
-    let support = SwapchainSupport::get(instance, data, physical_device).unwrap();
-    if support.formats.is_empty() || support.present_modes.is_empty() {
-        return Err(anyhow!(SutibilityError("Insufficient Swapchain Support")));
+        let support = SwapchainSupport::get(instance, data, physical_device)?;
+        if support.formats.is_empty() || support.present_modes.is_empty() {
+            return Err(anyhow!(SutibilityError("Insufficient Swapchain Support")));
+        }
     }
 
     let features = instance.get_physical_device_features(physical_device);
-    if features.sampler_anisotropy != vk::TRUE {
-        return Err(anyhow!(SutibilityError("No sampler anisotropy.")));
-    }
+    check_required_features(&features)?;
+    check_vertex_strides(&get_portability_subset(instance, data, physical_device))?;
 
     Ok(())
 }
@@ -49,8 +59,7 @@ pub(crate) unsafe fn check_physical_device_extensions(
     physical_device: vk::PhysicalDevice,
 ) -> Result<()> {
     let extensions = instance
-        .enumerate_device_extension_properties(physical_device, None)
-        .unwrap()
+        .enumerate_device_extension_properties(physical_device, None)?
         .iter()
         .map(|e| e.extension_name)
         .collect::<HashSet<_>>();
@@ -62,29 +71,438 @@ pub(crate) unsafe fn check_physical_device_extensions(
     }
 }
 
+/// Whether an optional extension can be enabled on the device.
+pub(crate) unsafe fn supports_device_extension(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    extension: vk::ExtensionName,
+) -> bool {
+    instance
+        .enumerate_device_extension_properties(physical_device, None)
+        .unwrap()
+        .iter()
+        .any(|e| e.extension_name == extension)
+}
+
+/// Overrides which device is rendered with, as `GpuSelector::parse` reads
+/// it.
+pub const GPU_OVERRIDE_VAR: &str = "OZEN_ATHENA_GPU";
+
+/// Which device to render with, instead of the best scoring suitable one,
+/// see `score_physical_device`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GpuSelector {
+    /// Position among the devices as the startup log lists them.
+    Index(usize),
+    /// Part of the device name, ignoring case.
+    Name(String),
+    /// `deviceUUID`, as the startup log prints it. Needs Vulkan 1.1.
+    Uuid([u8; 16]),
+}
+
+impl GpuSelector {
+    /// Reads a number as an index, 32 hex digits (dashes allowed) as a UUID
+    /// and anything else as part of a name.
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if let Ok(index) = text.parse() {
+            return Self::Index(index);
+        }
+        let digits = text.chars().filter(|c| *c != '-').collect::<String>();
+        if digits.len() == 32 && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut uuid = [0; 16];
+            for (i, byte) in uuid.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).unwrap();
+            }
+            return Self::Uuid(uuid);
+        }
+        Self::Name(text.to_lowercase())
+    }
+
+    /// The selector in `GPU_OVERRIDE_VAR`, if it is set.
+    pub fn from_env() -> Option<Self> {
+        env::var(GPU_OVERRIDE_VAR).ok().filter(|v| !v.trim().is_empty()).map(|v| Self::parse(&v))
+    }
+
+    fn matches(&self, device: &DeviceInfo) -> bool {
+        match self {
+            Self::Index(index) => device.index == *index,
+            Self::Name(name) => device.name.to_lowercase().contains(name),
+            Self::Uuid(uuid) => device.uuid == Some(*uuid),
+        }
+    }
+}
+
+impl fmt::Display for GpuSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "device {}", index),
+            Self::Name(name) => write!(f, "`{}`", name),
+            Self::Uuid(uuid) => write!(f, "{}", format_uuid(uuid)),
+        }
+    }
+}
+
+/// What the startup log and `GpuSelector` know a device by.
+struct DeviceInfo {
+    index: usize,
+    name: String,
+    uuid: Option<[u8; 16]>,
+    device_type: vk::PhysicalDeviceType,
+    api_version: u32,
+    /// The total size of its device local heaps.
+    local_memory: u64,
+}
+
+/// Logs every device, then picks the one `data.gpu_selector` names if it is
+/// suitable, or else the suitable device with the highest score. The first
+/// listed wins ties.
 pub(crate) unsafe fn pick_physical_device(instance: &Instance, data: &mut AppData) -> Result<()> {
-    for physical_device in instance.enumerate_physical_devices().unwrap() {
-        let properties = instance.get_physical_device_properties(physical_device);
-
-        if let Err(error) = check_physical_device(instance, data, physical_device) {
-            warn!(
-                "Skipping Physical Device (`{}`): {}",
-                properties.device_name, error
-            );
+    let devices = instance.enumerate_physical_devices()?;
+    let mut suitable = vec![];
+    for (index, physical_device) in devices.iter().enumerate() {
+        let info = log_physical_device(instance, data, index, *physical_device);
+        if let Err(error) = check_physical_device(instance, data, *physical_device) {
+            warn!("Skipping Physical Device (`{}`): {}", info.name, error);
         } else {
-            info!("Selected Physical Device (`{}`)", properties.device_name);
-            data.physical_device = physical_device;
-            data.msaa_samples = get_max_msaa_samples(instance, data);
-            return Ok(());
+            let score = score_physical_device(instance, data, *physical_device, &info);
+            info!("Physical Device {} scores {}.", index, score);
+            suitable.push((*physical_device, info, score));
+        }
+    }
+
+    let selected = data.gpu_selector.as_ref().and_then(|selector| {
+        let selected = suitable.iter().find(|(_, info, _)| selector.matches(info));
+        if selected.is_none() {
+            warn!("No suitable device matches {}.", selector);
         }
+        selected
+    });
+    // `max_by_key` keeps the last of equal scores.
+    let selected = selected.or_else(|| suitable.iter().rev().max_by_key(|(_, _, score)| *score));
+    let Some((physical_device, info, _)) = selected else {
+        return Err(anyhow!("Failed to Find Physical Device"));
+    };
+    info!("Selected Physical Device {} (`{}`)", info.index, info.name);
+
+    let physical_device = *physical_device;
+    let properties = instance.get_physical_device_properties(physical_device);
+    data.physical_device = physical_device;
+    data.device_version = properties.api_version;
+    data.capabilities = get_device_capabilities(instance, data, physical_device);
+    info!("Device capabilities: {:?}.", data.capabilities);
+    data.msaa_samples = get_max_msaa_samples(instance, data);
+    data.depth_format = get_depth_format(instance, data)?;
+    data.uniform_alignment = properties.limits.min_uniform_buffer_offset_alignment;
+    let features = instance.get_physical_device_features(physical_device);
+    data.device_features = negotiate_features(&features);
+    data.wireframe_supported = data.device_features.contains(DeviceFeature::FillModeNonSolid);
+    data.texture_compression_bc = data.device_features.contains(DeviceFeature::TextureCompressionBc);
+    (data.subgroup_size, data.subgroup_arithmetic) =
+        get_subgroup_support(instance, data, physical_device);
+    (data.descriptor_indexing, data.texture_capacity) =
+        get_descriptor_indexing_support(instance, data, physical_device);
+    data.push_descriptors = data.instance_version >= u32::from(VULKAN_1_1)
+        && supports_device_extension(instance, physical_device, vk::KHR_PUSH_DESCRIPTOR_EXTENSION.name);
+    data.load_store_op_none = supports_device_extension(
+        instance,
+        physical_device,
+        vk::EXT_LOAD_STORE_OP_NONE_EXTENSION.name,
+    );
+    data.draw_indirect_count = supports_device_extension(
+        instance,
+        physical_device,
+        vk::KHR_DRAW_INDIRECT_COUNT_EXTENSION.name,
+    );
+    data.full_screen_exclusive = data.surface_capabilities2
+        && data.instance_version >= u32::from(VULKAN_1_1)
+        && supports_device_extension(instance, physical_device, vk::EXT_FULL_SCREEN_EXCLUSIVE_EXTENSION.name);
+    data.mesh_shader = get_mesh_shader_support(instance, data, physical_device);
+    data.portability = get_portability_subset(instance, data, physical_device);
+    data.memory_budget = data.instance_version >= u32::from(VULKAN_1_1)
+        && supports_device_extension(instance, physical_device, vk::EXT_MEMORY_BUDGET_EXTENSION.name);
+    if data.portability.enabled {
+        info!("Portability subset: {:?}.", data.portability);
+    }
+    if cfg!(feature = "ray-tracing") {
+        if let Some(limits) = get_ray_tracing_support(instance, data, physical_device) {
+            data.ray_tracing = true;
+            data.ray_tracing_limits = limits;
+            data.ray_query = get_ray_query_support(instance, physical_device);
+        }
+    }
+    data.dynamic_rendering = get_dynamic_rendering_support(instance, data, physical_device);
+    data.multiview = get_multiview_support(instance, data, physical_device);
+    data.timeline_semaphore_extension =
+        get_timeline_semaphore_support(instance, data, physical_device) == Some(true);
+    Ok(())
+}
+
+/// Ranks a suitable device for when none is selected. The weights keep the
+/// criteria in order of importance: the device type, so that laptops render
+/// on the discrete GPU rather than the integrated one, then how many of the
+/// optional features the renderer uses it supports, then its device local
+/// memory up to 24 GiB, then its Vulkan version.
+unsafe fn score_physical_device(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+    info: &DeviceInfo,
+) -> u32 {
+    let device_type = match info.device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4000,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 2000,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 1000,
+        _ => 0,
+    };
+
+    let ray_tracing = cfg!(feature = "ray-tracing") && get_ray_tracing_support(instance, data, physical_device).is_some();
+    let features = [
+        get_descriptor_indexing_support(instance, data, physical_device).0,
+        get_dynamic_rendering_support(instance, data, physical_device),
+        get_multiview_support(instance, data, physical_device),
+        get_mesh_shader_support(instance, data, physical_device),
+        get_timeline_semaphore_support(instance, data, physical_device).is_some(),
+        supports_device_extension(instance, physical_device, vk::KHR_DRAW_INDIRECT_COUNT_EXTENSION.name),
+        ray_tracing,
+    ];
+    let features = features.iter().filter(|f| **f).count() as u32 * 100;
+
+    let memory = (info.local_memory / (1024 * 1024 * 1024)).min(24) as u32 * 4;
+    let version = vk::version_minor(info.api_version).min(3);
+
+    device_type + features + memory + version
+}
+
+/// Logs the device's properties, for picking one with `GpuSelector`.
+unsafe fn log_physical_device(
+    instance: &Instance,
+    data: &AppData,
+    index: usize,
+    physical_device: vk::PhysicalDevice,
+) -> DeviceInfo {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let memory = instance.get_physical_device_memory_properties(physical_device);
+    let local_memory = memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .filter(|h| h.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|h| h.size)
+        .sum::<u64>();
+
+    let vulkan_1_1 = u32::from(VULKAN_1_1);
+    let uuid = (data.instance_version >= vulkan_1_1 && properties.api_version >= vulkan_1_1).then(|| {
+        let mut id = vk::PhysicalDeviceIDProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut id);
+        instance.get_physical_device_properties2(physical_device, &mut properties);
+        id.device_uuid.0
+    });
+
+    let version = Version::from(properties.api_version);
+    info!(
+        "Physical Device {}: `{}` ({:?}), Vulkan {}, vendor {:04x}, device {:04x}, driver {}, {} MiB device local, UUID {}",
+        index,
+        properties.device_name,
+        properties.device_type,
+        version,
+        properties.vendor_id,
+        properties.device_id,
+        properties.driver_version,
+        local_memory / (1024 * 1024),
+        uuid.as_ref().map_or("unknown".into(), format_uuid),
+    );
+
+    DeviceInfo {
+        index,
+        name: properties.device_name.to_string(),
+        uuid,
+        device_type: properties.device_type,
+        api_version: properties.api_version,
+        local_memory,
     }
-    Err(anyhow!("Failed to Find Physical Device"))
+}
+
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex = uuid.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Returns the subgroup size and whether compute shaders can use subgroup
+/// arithmetic. Both need Vulkan 1.1 on the instance and the device.
+pub(crate) unsafe fn get_subgroup_support(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> (u32, bool) {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let vulkan_1_1 = u32::from(VULKAN_1_1);
+    if data.instance_version < vulkan_1_1 || properties.api_version < vulkan_1_1 {
+        return (1, false);
+    }
+
+    let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup);
+    instance.get_physical_device_properties2(physical_device, &mut properties);
+
+    let arithmetic = subgroup.supported_stages.contains(vk::ShaderStageFlags::COMPUTE)
+        && subgroup
+            .supported_operations
+            .contains(vk::SubgroupFeatureFlags::BASIC | vk::SubgroupFeatureFlags::ARITHMETIC);
+
+    (subgroup.subgroup_size, arithmetic)
+}
+
+/// Returns whether the texture table can use descriptor indexing and how
+/// many slots it gets. Descriptor indexing needs Vulkan 1.1 on the instance
+/// and either Vulkan 1.2 or `VK_EXT_descriptor_indexing` on the device.
+pub(crate) unsafe fn get_descriptor_indexing_support(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> (bool, u32) {
+    let properties = instance.get_physical_device_properties(physical_device);
+    if !get_device_capabilities(instance, data, physical_device).descriptor_indexing {
+        return (false, FALLBACK_TEXTURE_CAPACITY.min(properties.limits.max_per_stage_descriptor_samplers));
+    }
+
+    let mut limits = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut limits);
+    instance.get_physical_device_properties2(physical_device, &mut properties);
+
+    let capacity = BINDLESS_TEXTURE_CAPACITY
+        .min(limits.max_per_stage_descriptor_update_after_bind_samplers)
+        .min(limits.max_per_stage_descriptor_update_after_bind_sampled_images)
+        .min(limits.max_descriptor_set_update_after_bind_samplers)
+        .min(limits.max_descriptor_set_update_after_bind_sampled_images);
+    (true, capacity)
+}
+
+/// Whether the device has timeline semaphores, which frames are
+/// synchronized with when available, and if so whether they come from
+/// `VK_KHR_timeline_semaphore` rather than Vulkan 1.2. Querying the feature
+/// needs Vulkan 1.1 on the instance.
+pub(crate) unsafe fn get_timeline_semaphore_support(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Option<bool> {
+    let capabilities = get_device_capabilities(instance, data, physical_device);
+    capabilities.timeline_semaphore.then_some(!capabilities.core_feature_chain())
+}
+
+/// Whether render passes can draw several views at once, see `stereo.rs`.
+/// Core in Vulkan 1.1, which the instance and the device need.
+pub(crate) unsafe fn get_multiview_support(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    get_device_capabilities(instance, data, physical_device).multiview
+}
+
+/// Whether frames can be drawn without render pass objects. Needs Vulkan 1.3
+/// on the instance and the device.
+pub(crate) unsafe fn get_dynamic_rendering_support(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    get_device_capabilities(instance, data, physical_device).dynamic_rendering
+}
+
+/// Whether meshlets can be drawn with task and mesh shaders. Their SPIR-V
+/// needs Vulkan 1.2 on the instance and the device.
+pub(crate) unsafe fn get_mesh_shader_support(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> bool {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let vulkan_1_2 = u32::from(VULKAN_1_2);
+    if data.instance_version < vulkan_1_2
+        || properties.api_version < vulkan_1_2
+        || !supports_device_extension(instance, physical_device, vk::EXT_MESH_SHADER_EXTENSION.name)
+    {
+        return false;
+    }
+
+    let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut mesh_shader);
+    instance.get_physical_device_features2(physical_device, &mut features);
+    mesh_shader.task_shader == vk::TRUE && mesh_shader.mesh_shader == vk::TRUE
+}
+
+/// The limits ray tracing needs if the device can build acceleration
+/// structures and run ray tracing pipelines. Their SPIR-V needs Vulkan 1.2
+/// on the instance and the device, which also provides buffer device
+/// addresses.
+pub(crate) unsafe fn get_ray_tracing_support(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Option<RayTracingLimits> {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let vulkan_1_2 = u32::from(VULKAN_1_2);
+    if data.instance_version < vulkan_1_2 || properties.api_version < vulkan_1_2 {
+        return None;
+    }
+
+    let extensions = [
+        vk::KHR_ACCELERATION_STRUCTURE_EXTENSION.name,
+        vk::KHR_RAY_TRACING_PIPELINE_EXTENSION.name,
+        vk::KHR_DEFERRED_HOST_OPERATIONS_EXTENSION.name,
+    ];
+    if !extensions.iter().all(|e| supports_device_extension(instance, physical_device, *e)) {
+        return None;
+    }
+
+    let mut acceleration_structure = vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+    let mut ray_tracing_pipeline = vk::PhysicalDeviceRayTracingPipelineFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder()
+        .push_next(&mut acceleration_structure)
+        .push_next(&mut ray_tracing_pipeline);
+    instance.get_physical_device_features2(physical_device, &mut features);
+    if acceleration_structure.acceleration_structure != vk::TRUE
+        || ray_tracing_pipeline.ray_tracing_pipeline != vk::TRUE
+        || !get_device_capabilities(instance, data, physical_device).buffer_device_address
+    {
+        return None;
+    }
+
+    let mut pipeline = vk::PhysicalDeviceRayTracingPipelinePropertiesKHR::default();
+    let mut acceleration_structure = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder()
+        .push_next(&mut pipeline)
+        .push_next(&mut acceleration_structure);
+    instance.get_physical_device_properties2(physical_device, &mut properties);
+
+    Some(RayTracingLimits {
+        handle_size: pipeline.shader_group_handle_size,
+        handle_alignment: pipeline.shader_group_handle_alignment,
+        base_alignment: pipeline.shader_group_base_alignment,
+        scratch_alignment: acceleration_structure.min_acceleration_structure_scratch_offset_alignment,
+    })
+}
+
+/// Whether shaders outside ray tracing pipelines can trace rays, given ray
+/// tracing support.
+pub(crate) unsafe fn get_ray_query_support(instance: &Instance, physical_device: vk::PhysicalDevice) -> bool {
+    if !supports_device_extension(instance, physical_device, vk::KHR_RAY_QUERY_EXTENSION.name) {
+        return false;
+    }
+
+    let mut ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+    let mut features = vk::PhysicalDeviceFeatures2::builder().push_next(&mut ray_query);
+    instance.get_physical_device_features2(physical_device, &mut features);
+    ray_query.ray_query == vk::TRUE
 }
 
 #[derive(Copy, Clone, Debug)]
 pub(crate) struct QueueFamilyIndices {
     pub(crate) graphics: u32,
     pub(crate) present: u32,
+    /// Background uploads, see `AssetLoader`. The graphics family when there
+    /// is no dedicated one.
+    pub(crate) transfer: u32,
 }
 
 impl QueueFamilyIndices {
@@ -94,6 +512,9 @@ impl QueueFamilyIndices {
         physical_device: vk::PhysicalDevice,
     ) -> Result<Self> {
         let properties = instance.get_physical_device_queue_family_properties(physical_device);
+        if data.headless {
+            return Self::get_headless(&properties);
+        }
 
         let graphics = properties
             .iter()
@@ -115,10 +536,34 @@ impl QueueFamilyIndices {
             }
         }
 
-        if let (Some(graphics), Some(present)) = (graphics, present) {
-            Ok(Self { graphics, present })
+        // A transfer-only family is usually a copy engine that uploads
+        // without taking time from the graphics queue.
+        let transfer = properties
+            .iter()
+            .position(|p| {
+                p.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !p.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            })
+            .map(|i| i as u32)
+            .or(graphics);
+
+        if let (Some(graphics), Some(present), Some(transfer)) = (graphics, present, transfer) {
+            Ok(Self { graphics, present, transfer })
         } else {
             Err(anyhow!(SutibilityError("Missing Queue Family: Graphics")))
         }
     }
+
+    /// Without a surface there is nothing to present to, and the
+    /// "graphics" family only has to run compute work. One that can also
+    /// draw is preferred, for baking.
+    fn get_headless(properties: &[vk::QueueFamilyProperties]) -> Result<Self> {
+        let compute = |flags: vk::QueueFlags| {
+            properties.iter().position(|p| p.queue_flags.contains(flags)).map(|i| i as u32)
+        };
+        let graphics = compute(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+            .or_else(|| compute(vk::QueueFlags::COMPUTE))
+            .ok_or_else(|| anyhow!(SutibilityError("Missing Queue Family: Compute")))?;
+        Ok(Self { graphics, present: graphics, transfer: graphics })
+    }
 }
@@ -1,32 +1,180 @@
 use anyhow::Result;
+use std::mem::size_of;
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
     app::AppData,
-    shader::create_shader_module,
+    depth_object::depth_compare_op,
+    dynamic_rendering::{Pass, PassFormats},
+    material::{create_material_pipelines, Shading},
+    oit::{scene_blend_attachments, OitWrites},
+    push_constants::PushConstants,
+    reflect::ShaderReflection,
+    shader::{create_shader_module, SpecializationConstants},
+    types::{Mat4, Vec4},
     vertex::Vertex
 };
 
+const SCENE_VERT: &[u8] = include_bytes!("../../shaders/vert.spv");
+const SCENE_FRAG: &[u8] = include_bytes!("../../shaders/frag.spv");
+const SCENE_FRAG_OIT: &[u8] = include_bytes!("../../shaders/frag_oit.spv");
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct FragmentPushConstants {
+    pub(crate) opacity: f32,
+    pub(crate) debug_view: u32,
+    /// Slot in the texture table.
+    pub(crate) texture: u32,
+    /// Block in the material buffer, 0 for entities without a material.
+    pub(crate) material: u32,
+    /// Light probe irradiance, see `SphericalHarmonics::irradiance`.
+    pub(crate) ambient: [Vec4; 3],
+}
+
+/// The descriptor bindings, push constants and vertex inputs of the scene
+/// shaders.
+pub(crate) fn scene_reflection() -> ShaderReflection {
+  ShaderReflection::merge(&[
+      ShaderReflection::new(SCENE_VERT).unwrap(),
+      ShaderReflection::new(SCENE_FRAG).unwrap(),
+  ])
+}
+
+/// The scene pipelines differ only in fixed-function state.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum SceneVariant {
+  Shaded,
+  /// Additive and without depth testing, for `DebugView::Overdraw`.
+  Overdraw,
+  /// Lines instead of fills. Needs `wireframe_supported`.
+  Wireframe,
+  /// Blended by opacity over what is behind, or accumulated into the OIT
+  /// targets with `ScenePipelineDesc::oit`, and depth tested without writing
+  /// depth, for transparent render queues.
+  Transparent,
+  /// Depth only, without a fragment shader, for `App::depth_prepass`.
+  DepthPrepass,
+  /// Shades only the surfaces the depth pre-pass left nearest, testing
+  /// EQUAL without writing depth.
+  DepthEqual,
+}
+
+/// The shaders a scene pipeline makes its triangles with.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum SceneGeometry {
+  /// A vertex shader reading `Vertex` buffers.
+  Vertex(&'static [u8]),
+  /// Task and mesh shaders drawing meshlets, without vertex input. Needs
+  /// `mesh_shader`.
+  Mesh { task: &'static [u8], mesh: &'static [u8] },
+}
+
+/// Everything a scene pipeline is built from, copied out of `AppData` so
+/// that variants can be built on another thread.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ScenePipelineDesc {
+  pub(crate) layout: vk::PipelineLayout,
+  pub(crate) render_pass: vk::RenderPass,
+  pub(crate) cache: vk::PipelineCache,
+  pub(crate) extent: vk::Extent2D,
+  pub(crate) samples: vk::SampleCountFlags,
+  pub(crate) texture_capacity: u32,
+  /// Replaces `render_pass` when `dynamic_rendering` is set.
+  pub(crate) formats: PassFormats,
+  pub(crate) dynamic_rendering: bool,
+  pub(crate) reverse_z: bool,
+  /// The pass has the OIT targets, see `oit.rs`, and `frag` writes them.
+  pub(crate) oit: bool,
+  pub(crate) geometry: SceneGeometry,
+  pub(crate) frag: &'static [u8],
+  /// Specializes the scene fragment shader, see `Material::shading`.
+  pub(crate) shading: Shading,
+}
+
+impl ScenePipelineDesc {
+  pub(crate) fn new(data: &AppData) -> Self {
+    Self {
+      layout: data.pipeline_layout,
+      render_pass: data.render_pass,
+      cache: data.pipeline_cache,
+      extent: data.swapchain_extent,
+      samples: data.msaa_samples,
+      texture_capacity: data.texture_capacity,
+      formats: Pass::Scene.formats(data),
+      dynamic_rendering: data.dynamic_rendering,
+      reverse_z: data.reverse_z,
+      oit: data.order_independent_transparency,
+      geometry: SceneGeometry::Vertex(SCENE_VERT),
+      frag: if data.order_independent_transparency { SCENE_FRAG_OIT } else { SCENE_FRAG },
+      shading: Shading::Lit,
+    }
+  }
+}
+
+/// Creates the scene pipeline layout and the shaded and transparent
+/// pipelines, also of each material shading in use. The other variants are compiled in the background when wanted, see
+/// `App::update_pipelines`.
 pub(crate) unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
-  let vert = include_bytes!("../../shaders/vert.spv");
-  let frag = include_bytes!("../../shaders/frag.spv");
+  let reflection = scene_reflection();
+
+  // The model matrix for the vertex stage at 0, then
+  // `FragmentPushConstants` for the fragment stage after it.
+  data.model_push_constants = PushConstants::new(&reflection, vk::ShaderStageFlags::VERTEX, 0)?;
+  data.fragment_push_constants =
+      PushConstants::new(&reflection, vk::ShaderStageFlags::FRAGMENT, size_of::<Mat4>() as u32)?;
+  let set_layouts = &[data.descriptor_set_layout, data.texture_set_layout];
+  let layout_info = vk::PipelineLayoutCreateInfo::builder()
+      .set_layouts(set_layouts)
+      .push_constant_ranges(&reflection.push_constant_ranges);
 
-  let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
-  let frag_shader_module = create_shader_module(device, &frag[..]).unwrap();
+  data.pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+  data.pipeline = create_scene_pipeline(device, ScenePipelineDesc::new(data), SceneVariant::Shaded).unwrap();
+  data.transparent_pipeline =
+      create_scene_pipeline(device, ScenePipelineDesc::new(data), SceneVariant::Transparent).unwrap();
+  create_material_pipelines(device, data)?;
+  Ok(())
+}
+
+pub(crate) unsafe fn create_scene_pipeline(
+  device: &Device,
+  desc: ScenePipelineDesc,
+  variant: SceneVariant,
+) -> Result<vk::Pipeline> {
+  let reflection = scene_reflection();
 
-  let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
-      .stage(vk::ShaderStageFlags::VERTEX)
-      .module(vert_shader_module)
-      .name(b"main\0");
+  let geometry_stages = match desc.geometry {
+    SceneGeometry::Vertex(vert) => vec![(vk::ShaderStageFlags::VERTEX, vert)],
+    SceneGeometry::Mesh { task, mesh } => vec![
+        (vk::ShaderStageFlags::TASK_EXT, task),
+        (vk::ShaderStageFlags::MESH_EXT, mesh),
+    ],
+  };
+  let geometry_modules = geometry_stages
+      .iter()
+      .map(|(_, code)| create_shader_module(device, code))
+      .collect::<Result<Vec<_>>>()?;
+  let frag_shader_module = create_shader_module(device, desc.frag)?;
+
+  // The shader sizes the texture table with a specialization constant, and
+  // is told whether to accumulate into the OIT targets and how to shade.
+  let accumulate = desc.oit && variant == SceneVariant::Transparent;
+  let constants = SpecializationConstants::new()
+      .u32(0, desc.texture_capacity)
+      .bool(1, accumulate)
+      .u32(2, desc.shading as u32);
+  let specialization_info = constants.info();
 
   let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
       .stage(vk::ShaderStageFlags::FRAGMENT)
       .module(frag_shader_module)
-      .name(b"main\0");
+      .name(b"main\0")
+      .specialization_info(&specialization_info);
 
+  let (stride, attribute_descriptions) = reflection.vertex_attributes(0);
+  debug_assert_eq!(stride as usize, size_of::<Vertex>(), "Vertex does not match the vertex shader inputs.");
   let binding_descriptions = &[Vertex::binding_description()];
-  let attribute_descriptions = Vertex::attribute_descriptions();
   let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
       .vertex_binding_descriptions(binding_descriptions)
       .vertex_attribute_descriptions(&attribute_descriptions);
@@ -38,14 +186,14 @@ pub(crate) unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Res
   let viewport = vk::Viewport::builder()
       .x(0.0)
       .y(0.0)
-      .width(data.swapchain_extent.width as f32)
-      .height(data.swapchain_extent.height as f32)
+      .width(desc.extent.width as f32)
+      .height(desc.extent.height as f32)
       .min_depth(0.0)
       .max_depth(1.0);
 
   let scissor = vk::Rect2D::builder()
       .offset(vk::Offset2D { x: 0, y: 0 })
-      .extent(data.swapchain_extent);
+      .extent(desc.extent);
 
   let viewports = &[viewport];
   let scissors = &[scissor];
@@ -53,10 +201,14 @@ pub(crate) unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Res
       .viewports(viewports)
       .scissors(scissors);
 
+  let polygon_mode = match variant {
+    SceneVariant::Wireframe => vk::PolygonMode::LINE,
+    _ => vk::PolygonMode::FILL,
+  };
   let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
       .depth_clamp_enable(false)
       .rasterizer_discard_enable(false)
-      .polygon_mode(vk::PolygonMode::FILL)
+      .polygon_mode(polygon_mode)
       .line_width(1.0)
       .cull_mode(vk::CullModeFlags::BACK)
       .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
@@ -64,70 +216,107 @@ pub(crate) unsafe fn create_pipeline(device: &Device, data: &mut AppData) -> Res
 
   let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
       .sample_shading_enable(false)
-      .rasterization_samples(data.msaa_samples);
+      .rasterization_samples(desc.samples);
 
+  let (depth_test, depth_write, compare_op) = match variant {
+    SceneVariant::Overdraw => (false, false, vk::CompareOp::LESS),
+    SceneVariant::DepthEqual => (true, false, vk::CompareOp::EQUAL),
+    SceneVariant::Transparent => (true, false, vk::CompareOp::LESS),
+    _ => (true, true, vk::CompareOp::LESS),
+  };
   let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-      .depth_test_enable(true)
-      .depth_write_enable(true)
-      .depth_compare_op(vk::CompareOp::LESS)
+      .depth_test_enable(depth_test)
+      .depth_write_enable(depth_write)
+      .depth_compare_op(depth_compare_op(desc.reverse_z, compare_op))
       .depth_bounds_test_enable(false)
       .stencil_test_enable(false);
 
   let attachment = vk::PipelineColorBlendAttachmentState::builder()
       .color_write_mask(vk::ColorComponentFlags::all())
-      .blend_enable(true)
-      .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-      .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_COLOR)
+      .blend_enable(false)
       .color_blend_op(vk::BlendOp::ADD)
-      .src_alpha_blend_factor(vk::BlendFactor::ONE)
-      .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
       .alpha_blend_op(vk::BlendOp::ADD);
 
-  let attachments = &[attachment];
+  let attachment = match variant {
+    SceneVariant::Transparent => attachment
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+    SceneVariant::Overdraw => attachment
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::ONE)
+        .dst_color_blend_factor(vk::BlendFactor::ONE)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE),
+    SceneVariant::DepthPrepass => attachment
+        .color_write_mask(vk::ColorComponentFlags::empty())
+        .blend_enable(false),
+    _ => attachment,
+  };
+  let attachment = if accumulate {
+    attachment.color_write_mask(vk::ColorComponentFlags::empty())
+  } else {
+    attachment
+  };
+
+  let oit_writes = match variant {
+    _ if accumulate => OitWrites::Accumulate,
+    SceneVariant::DepthPrepass => OitWrites::Masked,
+    _ => OitWrites::Reset,
+  };
+  let attachments = scene_blend_attachments(desc.oit, attachment.build(), oit_writes);
   let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
       .logic_op_enable(false)
       .logic_op(vk::LogicOp::COPY)
-      .attachments(attachments)
+      .attachments(&attachments)
       .blend_constants([0.0, 0.0, 0.0, 0.0]);
 
-  let vert_push_constant_range = vk::PushConstantRange::builder()
-      .stage_flags(vk::ShaderStageFlags::VERTEX)
-      .offset(0)
-      .size(64 /* 16 * 4 byte floats */);
+  // Set per viewport, see `record_viewports`.
+  let dynamic_states = &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+  let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
 
-  let frag_push_constant_range = vk::PushConstantRange::builder()
-      .stage_flags(vk::ShaderStageFlags::FRAGMENT)
-      .offset(64)
-      .size(4);
+  let mut rendering_info = desc.formats.pipeline_info();
 
-  let set_layouts = &[data.descriptor_set_layout];
-  let push_constant_ranges = &[vert_push_constant_range, frag_push_constant_range];
-  let layout_info = vk::PipelineLayoutCreateInfo::builder()
-      .set_layouts(set_layouts)
-      .push_constant_ranges(push_constant_ranges);
+  let mut stages = geometry_stages
+      .iter()
+      .zip(&geometry_modules)
+      .map(|((stage, _), module)| {
+        vk::PipelineShaderStageCreateInfo::builder()
+            .stage(*stage)
+            .module(*module)
+            .name(b"main\0")
+            .build()
+      })
+      .collect::<Vec<_>>();
+  if variant != SceneVariant::DepthPrepass {
+    stages.push(frag_stage.build());
+  }
 
-  data.pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
-
-  let stages = &[vert_stage, frag_stage];
-  let info = vk::GraphicsPipelineCreateInfo::builder()
-      .stages(stages)
-      .vertex_input_state(&vertex_input_state)
-      .input_assembly_state(&input_assembly_state)
+  let mut info = vk::GraphicsPipelineCreateInfo::builder()
+      .stages(&stages)
       .viewport_state(&viewport_state)
       .rasterization_state(&rasterization_state)
       .multisample_state(&multisample_state)
       .depth_stencil_state(&depth_stencil_state)
       .color_blend_state(&color_blend_state)
-      .layout(data.pipeline_layout)
-      .render_pass(data.render_pass)
+      .dynamic_state(&dynamic_state)
+      .layout(desc.layout)
+      .render_pass(desc.render_pass)
       .subpass(0);
+  if let SceneGeometry::Vertex(_) = desc.geometry {
+    info = info
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state);
+  }
+  if desc.dynamic_rendering {
+    info = info.push_next(&mut rendering_info);
+  }
 
-  data.pipeline = device
-      .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)
-      .unwrap()
-      .0[0];
+  let result = device.create_graphics_pipelines(desc.cache, &[info], None);
 
-  device.destroy_shader_module(vert_shader_module, None);
+  geometry_modules.iter().for_each(|m| device.destroy_shader_module(*m, None));
   device.destroy_shader_module(frag_shader_module, None);
-  Ok(())
-}
\ No newline at end of file
+  Ok(result?.0[0])
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use log::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{app::AppData, paths::Directories};
+
+/// Size of `VkPipelineCacheHeaderVersionOne`: the header length, version,
+/// vendor and device IDs, then the cache UUID.
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// Where the selected physical device's pipeline cache is kept.
+pub(crate) unsafe fn pipeline_cache_file(instance: &Instance, data: &AppData, directories: &Directories) -> PathBuf {
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    directories.pipeline_cache_file(properties.vendor_id, properties.device_id)
+}
+
+/// Creates `data.pipeline_cache`, seeded from `path` if it holds a cache
+/// written by this driver for this device. A missing or stale file starts
+/// an empty cache.
+pub(crate) unsafe fn create_pipeline_cache(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    path: &Path,
+) -> Result<()> {
+    let initial_data = match fs::read(path) {
+        Ok(bytes) if matches_device(instance, data, &bytes) => {
+            info!("Loaded pipeline cache ({} bytes) from `{}`.", bytes.len(), path.display());
+            bytes
+        }
+        Ok(_) => {
+            info!("Ignoring pipeline cache from another driver or device at `{}`.", path.display());
+            vec![]
+        }
+        Err(e) => {
+            debug!("No pipeline cache at `{}`: {}", path.display(), e);
+            vec![]
+        }
+    };
+
+    let info = vk::PipelineCacheCreateInfo::builder().initial_data(&initial_data);
+    data.pipeline_cache = match device.create_pipeline_cache(&info, None) {
+        Ok(cache) => cache,
+        Err(e) => {
+            // Drivers may still reject data whose header matches.
+            warn!("Failed to create pipeline cache from `{}`: {}", path.display(), e);
+            let info = vk::PipelineCacheCreateInfo::builder();
+            device.create_pipeline_cache(&info, None).unwrap()
+        }
+    };
+
+    Ok(())
+}
+
+/// Writes `data.pipeline_cache` to `path` for the next run.
+pub(crate) unsafe fn save_pipeline_cache(device: &Device, data: &AppData, path: &Path) -> Result<()> {
+    let bytes = device.get_pipeline_cache_data(data.pipeline_cache)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Write then rename so a crash mid-save cannot leave a truncated cache.
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, &bytes)?;
+    fs::rename(&temporary, path)?;
+
+    info!("Saved pipeline cache ({} bytes) to `{}`.", bytes.len(), path.display());
+    Ok(())
+}
+
+/// Whether `bytes` starts with a version one header for the selected
+/// physical device and its driver's cache UUID.
+unsafe fn matches_device(instance: &Instance, data: &AppData, bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE {
+        return false;
+    }
+
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    // Unlike the rest of the cache, the header is always little-endian.
+    let word = |i: usize| u32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+
+    word(0) as usize >= HEADER_SIZE
+        && word(1) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && word(2) == properties.vendor_id
+        && word(3) == properties.device_id
+        && bytes[16..HEADER_SIZE] == properties.pipeline_cache_uuid[..]
+}
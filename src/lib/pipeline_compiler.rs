@@ -0,0 +1,159 @@
+use anyhow::Result;
+use log::*;
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+/// Most worker threads a compiler starts, leaving cores for the render and
+/// main threads.
+const MAX_WORKERS: usize = 4;
+
+/// Builds one pipeline on a worker thread. It must only capture handles that
+/// outlive the job, see `PipelineCompiler::wait_idle`.
+pub(crate) type PipelineJob = Box<dyn FnOnce(&Device) -> Result<vk::Pipeline> + Send>;
+
+/// A pipeline compiled in the background, with the one to use until it is
+/// ready.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct AsyncPipeline {
+    pub(crate) pipeline: vk::Pipeline,
+    /// The ticket of the job that will fill `pipeline`. Results for any
+    /// other ticket are stale.
+    pub(crate) pending: Option<u64>,
+}
+
+impl AsyncPipeline {
+    /// The compiled pipeline, or `fallback` while it is still compiling.
+    pub(crate) fn get(&self, fallback: vk::Pipeline) -> vk::Pipeline {
+        if self.pipeline.is_null() {
+            fallback
+        } else {
+            self.pipeline
+        }
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        !self.pipeline.is_null()
+    }
+
+    /// Takes `pipeline` if it is the result this slot is waiting for.
+    pub(crate) fn accept(&mut self, ticket: u64, pipeline: vk::Pipeline) -> bool {
+        if self.pending == Some(ticket) {
+            self.pipeline = pipeline;
+            self.pending = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Destroys the pipeline and forgets any job in flight, whose result
+    /// will then be discarded as stale.
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        *self = Self::default();
+    }
+}
+
+/// A pool of threads that create pipelines, so that drivers compiling
+/// shaders do not stall the render loop.
+#[derive(Debug)]
+pub(crate) struct PipelineCompiler {
+    jobs: Option<Sender<(u64, PipelineJob)>>,
+    results: Receiver<(u64, Result<vk::Pipeline>)>,
+    workers: Vec<JoinHandle<()>>,
+    next_ticket: u64,
+    in_flight: usize,
+}
+
+impl PipelineCompiler {
+    pub(crate) fn new(device: &Device) -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get().saturating_sub(2))
+            .unwrap_or(1)
+            .clamp(1, MAX_WORKERS);
+
+        let (jobs, job_receiver) = channel::<(u64, PipelineJob)>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads)
+            .map(|i| {
+                let device = device.clone();
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                thread::Builder::new()
+                    .name(format!("pipeline compiler {}", i))
+                    .spawn(move || loop {
+                        // The lock is only held while waiting, not while
+                        // compiling.
+                        let job = jobs.lock().unwrap().recv();
+                        match job {
+                            Ok((ticket, job)) => {
+                                let _ = results.send((ticket, job(&device)));
+                            }
+                            Err(_) => break,
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect();
+
+        debug!("Started {} pipeline compiler threads.", threads);
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+            next_ticket: 0,
+            in_flight: 0,
+        }
+    }
+
+    /// Queues `job` and returns the ticket its result is reported with.
+    pub(crate) fn compile(&mut self, job: PipelineJob) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.in_flight += 1;
+        self.jobs.as_ref().unwrap().send((ticket, job)).unwrap();
+        ticket
+    }
+
+    /// The results of jobs that have finished since the last call.
+    pub(crate) fn finished(&mut self) -> Vec<(u64, Result<vk::Pipeline>)> {
+        let results = self.results.try_iter().collect::<Vec<_>>();
+        self.in_flight -= results.len();
+        results
+    }
+
+    /// Waits for every queued job and returns their results. Call this
+    /// before destroying anything a job may use, such as the render pass.
+    pub(crate) fn wait_idle(&mut self) -> Vec<(u64, Result<vk::Pipeline>)> {
+        let mut results = self.finished();
+        while self.in_flight > 0 {
+            results.push(self.results.recv().unwrap());
+            self.in_flight -= 1;
+        }
+        results
+    }
+
+    /// Waits for the queued jobs, destroys their pipelines and stops the
+    /// workers.
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        for (_, result) in self.wait_idle() {
+            if let Ok(pipeline) = result {
+                device.destroy_pipeline(pipeline, None);
+            }
+        }
+
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
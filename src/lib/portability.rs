@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use std::mem::size_of;
+
+use vulkanalia::{prelude::v1_0::*, vk::InstanceV1_1};
+
+use crate::{
+    app::{AppData, VULKAN_1_1},
+    billboard::BillboardVertex,
+    debug_draw::DebugVertex,
+    gpu_particles::GpuParticle,
+    particles::ParticleVertex,
+    physical_device::supports_device_extension,
+    sprite_batch::SpriteInstance,
+    vertex::Vertex,
+};
+
+/// What a device layered over another API, such as MoltenVK on macOS and
+/// iOS, leaves out of Vulkan through `VK_KHR_portability_subset`, see
+/// `App::portability`. Everything is supported on other devices.
+///
+/// The renderer works around the gaps it would otherwise hit: LOD bias is
+/// dropped from samplers, sRGB textures get their mipmaps blitted rather
+/// than written through UNORM views, and devices that cannot read its
+/// vertex layouts are skipped. Nothing draws triangle fans or point
+/// polygons, uses events, constant alpha blend factors, separate stencil
+/// references or multisampled array images, so those gaps only matter to
+/// code built on top.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PortabilitySubset {
+    /// The device has `VK_KHR_portability_subset`, which is then enabled.
+    pub enabled: bool,
+    pub constant_alpha_color_blend_factors: bool,
+    pub events: bool,
+    /// Image views in a different, compatible format than their image.
+    pub image_view_format_reinterpretation: bool,
+    pub image_view_format_swizzle: bool,
+    pub multisample_array_image: bool,
+    /// Comparison samplers outside of immutable samplers.
+    pub mutable_comparison_samplers: bool,
+    pub point_polygons: bool,
+    pub sampler_mip_lod_bias: bool,
+    pub separate_stencil_mask_ref: bool,
+    pub triangle_fans: bool,
+    pub vertex_attribute_access_beyond_stride: bool,
+    /// Vertex buffer strides must be multiples of this.
+    pub min_vertex_input_binding_stride_alignment: u32,
+}
+
+impl Default for PortabilitySubset {
+    /// A full Vulkan implementation.
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            constant_alpha_color_blend_factors: true,
+            events: true,
+            image_view_format_reinterpretation: true,
+            image_view_format_swizzle: true,
+            multisample_array_image: true,
+            mutable_comparison_samplers: true,
+            point_polygons: true,
+            sampler_mip_lod_bias: true,
+            separate_stencil_mask_ref: true,
+            triangle_fans: true,
+            vertex_attribute_access_beyond_stride: true,
+            min_vertex_input_binding_stride_alignment: 1,
+        }
+    }
+}
+
+impl PortabilitySubset {
+    /// The features to enable on the device, which are all those supported.
+    pub(crate) fn to_vk(self) -> vk::PhysicalDevicePortabilitySubsetFeaturesKHR {
+        let bool32 = |supported: bool| if supported { vk::TRUE } else { vk::FALSE };
+        vk::PhysicalDevicePortabilitySubsetFeaturesKHR {
+            constant_alpha_color_blend_factors: bool32(self.constant_alpha_color_blend_factors),
+            events: bool32(self.events),
+            image_view_format_reinterpretation: bool32(self.image_view_format_reinterpretation),
+            image_view_format_swizzle: bool32(self.image_view_format_swizzle),
+            multisample_array_image: bool32(self.multisample_array_image),
+            mutable_comparison_samplers: bool32(self.mutable_comparison_samplers),
+            point_polygons: bool32(self.point_polygons),
+            sampler_mip_lod_bias: bool32(self.sampler_mip_lod_bias),
+            separate_stencil_mask_ref: bool32(self.separate_stencil_mask_ref),
+            triangle_fans: bool32(self.triangle_fans),
+            vertex_attribute_access_beyond_stride: bool32(self.vertex_attribute_access_beyond_stride),
+            ..Default::default()
+        }
+    }
+}
+
+/// Queries the portability subset of `physical_device`. Devices that have
+/// the extension on an instance too old to query it are assumed to support
+/// none of it.
+pub(crate) unsafe fn get_portability_subset(
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> PortabilitySubset {
+    if !supports_device_extension(instance, physical_device, vk::KHR_PORTABILITY_SUBSET_EXTENSION.name) {
+        return PortabilitySubset::default();
+    }
+
+    let mut features = vk::PhysicalDevicePortabilitySubsetFeaturesKHR::default();
+    let mut properties = vk::PhysicalDevicePortabilitySubsetPropertiesKHR {
+        min_vertex_input_binding_stride_alignment: 4,
+        ..Default::default()
+    };
+    if data.instance_version >= u32::from(VULKAN_1_1) {
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder().push_next(&mut features);
+        instance.get_physical_device_features2(physical_device, &mut features2);
+        let mut properties2 = vk::PhysicalDeviceProperties2::builder().push_next(&mut properties);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+
+    let supported = |feature: vk::Bool32| feature == vk::TRUE;
+    PortabilitySubset {
+        enabled: true,
+        constant_alpha_color_blend_factors: supported(features.constant_alpha_color_blend_factors),
+        events: supported(features.events),
+        image_view_format_reinterpretation: supported(features.image_view_format_reinterpretation),
+        image_view_format_swizzle: supported(features.image_view_format_swizzle),
+        multisample_array_image: supported(features.multisample_array_image),
+        mutable_comparison_samplers: supported(features.mutable_comparison_samplers),
+        point_polygons: supported(features.point_polygons),
+        sampler_mip_lod_bias: supported(features.sampler_mip_lod_bias),
+        separate_stencil_mask_ref: supported(features.separate_stencil_mask_ref),
+        triangle_fans: supported(features.triangle_fans),
+        vertex_attribute_access_beyond_stride: supported(features.vertex_attribute_access_beyond_stride),
+        min_vertex_input_binding_stride_alignment: properties.min_vertex_input_binding_stride_alignment.max(1),
+    }
+}
+
+/// Fails if the device cannot read the renderer's vertex buffers, whose
+/// strides are fixed by their layouts.
+pub(crate) fn check_vertex_strides(portability: &PortabilitySubset) -> Result<()> {
+    let strides = [
+        ("Vertex", size_of::<Vertex>()),
+        ("SpriteInstance", size_of::<SpriteInstance>()),
+        ("DebugVertex", size_of::<DebugVertex>()),
+        ("ParticleVertex", size_of::<ParticleVertex>()),
+        ("BillboardVertex", size_of::<BillboardVertex>()),
+        ("GpuParticle", size_of::<GpuParticle>()),
+    ];
+    let alignment = portability.min_vertex_input_binding_stride_alignment as usize;
+    match strides.iter().find(|(_, stride)| stride % alignment != 0) {
+        Some((name, stride)) => Err(anyhow!(
+            "The {} byte stride of `{}` is not a multiple of the required {} bytes.",
+            stride,
+            name,
+            alignment,
+        )),
+        None => Ok(()),
+    }
+}
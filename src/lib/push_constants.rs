@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+use std::{
+    any::type_name,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::reflect::ShaderReflection;
+
+/// Where a `T` is pushed, checked once against the push constant block the
+/// shaders declare, so that a shader moving or resizing its block fails
+/// pipeline creation instead of reading garbage.
+///
+/// `T` is `#[repr(C)]` and laid out as the block is in std430, where
+/// vectors are aligned to their size and matrices are column major. Rust
+/// pads `T` to its alignment and the block is not padded at the end, so the
+/// block may be shorter than `T` by less than its alignment.
+#[derive(Debug)]
+pub(crate) struct PushConstants<T> {
+    pub(crate) stages: vk::ShaderStageFlags,
+    pub(crate) offset: u32,
+    value: PhantomData<fn(&T)>,
+}
+
+impl<T> Clone for PushConstants<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PushConstants<T> {}
+
+impl<T> Default for PushConstants<T> {
+    fn default() -> Self {
+        Self { stages: vk::ShaderStageFlags::empty(), offset: 0, value: PhantomData }
+    }
+}
+
+impl<T: Copy> PushConstants<T> {
+    /// The block `reflection` declares for `stages` at `offset`.
+    pub(crate) fn new(reflection: &ShaderReflection, stages: vk::ShaderStageFlags, offset: u32) -> Result<Self> {
+        let size = size_of::<T>() as u32;
+        if !offset.is_multiple_of(4) || !size.is_multiple_of(4) {
+            return Err(anyhow!("`{}` pushed at {} is not aligned to 4 bytes.", type_name::<T>(), offset));
+        }
+
+        let range = reflection
+            .push_constant_ranges
+            .iter()
+            .find(|r| r.offset == offset && r.stage_flags.contains(stages))
+            .ok_or_else(|| anyhow!("No push constant block for {:?} at {}.", stages, offset))?;
+        if range.size > size || size - range.size >= align_of::<T>().max(4) as u32 {
+            return Err(anyhow!(
+                "`{}` is {} bytes but the shaders declare {} bytes at {}.",
+                type_name::<T>(),
+                size,
+                range.size,
+                offset,
+            ));
+        }
+
+        Ok(Self { stages, offset, value: PhantomData })
+    }
+
+    /// The same block, pushed to `stages` instead, for shaders that cannot
+    /// be reflected but declare it the same way.
+    pub(crate) fn with_stages(self, stages: vk::ShaderStageFlags) -> Self {
+        Self { stages, ..self }
+    }
+
+    /// The range to create pipeline layouts with.
+    pub(crate) fn range(&self) -> vk::PushConstantRange {
+        vk::PushConstantRange::builder()
+            .stage_flags(self.stages)
+            .offset(self.offset)
+            .size(size_of::<T>() as u32)
+            .build()
+    }
+
+    pub(crate) unsafe fn push(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        value: &T,
+    ) {
+        let bytes = std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>());
+        device.cmd_push_constants(command_buffer, layout, self.stages, self.offset, bytes);
+    }
+}
@@ -0,0 +1,545 @@
+use anyhow::Result;
+use std::mem::size_of;
+
+use vulkanalia::{
+    prelude::v1_2::*,
+    vk::{KhrAccelerationStructureExtension, KhrRayTracingPipelineExtension},
+};
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    dynamic_buffer::{destroy_dynamic_buffer, update_dynamic_buffer, DynamicBuffer},
+    lod::model_lod,
+    shader::create_shader_module,
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    types::Mat4,
+    vertex::Vertex,
+    vertex_buffer::create_buffer,
+};
+
+/// What `get_ray_tracing_support` found the device needs for building
+/// acceleration structures and laying out shader binding tables.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct RayTracingLimits {
+    pub(crate) handle_size: u32,
+    /// Of each record within a shader binding table region.
+    pub(crate) handle_alignment: u32,
+    /// Of the start of each region.
+    pub(crate) base_alignment: u32,
+    pub(crate) scratch_alignment: u32,
+}
+
+/// The extra usage vertex and index buffers need to be built into
+/// acceleration structures, if ray tracing is enabled, and to be read by
+/// shaders that find what ray queries hit.
+pub(crate) fn geometry_usage(data: &AppData) -> vk::BufferUsageFlags {
+    let mut usage = vk::BufferUsageFlags::empty();
+    if data.ray_tracing {
+        usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+            | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
+    }
+    if data.ray_query {
+        usage |= vk::BufferUsageFlags::STORAGE_BUFFER;
+    }
+    usage
+}
+
+unsafe fn buffer_address(device: &Device, buffer: vk::Buffer) -> vk::DeviceAddress {
+    let info = vk::BufferDeviceAddressInfo::builder().buffer(buffer);
+    device.get_buffer_device_address(&info)
+}
+
+/// Rounds `value` up to a multiple of `alignment`, a power of two.
+fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+/// An acceleration structure and the buffer it lives in.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct AccelerationStructure {
+    pub(crate) handle: vk::AccelerationStructureKHR,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    /// What instances refer to it by, for bottom level ones.
+    pub(crate) address: vk::DeviceAddress,
+    pub(crate) size: vk::DeviceSize,
+}
+
+impl AccelerationStructure {
+    unsafe fn new(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        type_: vk::AccelerationStructureTypeKHR,
+        size: vk::DeviceSize,
+    ) -> Result<Self> {
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .unwrap();
+
+        let info = vk::AccelerationStructureCreateInfoKHR::builder()
+            .buffer(buffer)
+            .size(size)
+            .type_(type_);
+        let handle = device.create_acceleration_structure_khr(&info, None).unwrap();
+
+        let info = vk::AccelerationStructureDeviceAddressInfoKHR::builder().acceleration_structure(handle);
+        let address = device.get_acceleration_structure_device_address_khr(&info);
+
+        Ok(Self { handle, buffer, memory, address, size })
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        if !self.handle.is_null() {
+            device.destroy_acceleration_structure_khr(self.handle, None);
+        }
+        device.destroy_buffer(self.buffer, None);
+        free_memory(device, self.memory);
+        *self = Self::default();
+    }
+}
+
+/// A device local buffer for acceleration structure builds to scratch in,
+/// at least `size` bytes from an address aligned as the device needs.
+#[derive(Copy, Clone, Debug, Default)]
+struct ScratchBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    capacity: vk::DeviceSize,
+    address: vk::DeviceAddress,
+}
+
+impl ScratchBuffer {
+    unsafe fn reserve(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        size: vk::DeviceSize,
+    ) -> Result<()> {
+        if size <= self.capacity {
+            return Ok(());
+        }
+        self.destroy(device);
+
+        let alignment = data.ray_tracing_limits.scratch_alignment.max(1) as u64;
+        let capacity = size.next_power_of_two();
+        (self.buffer, self.memory) = create_buffer(
+            instance,
+            device,
+            data,
+            capacity + alignment,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .unwrap();
+        self.capacity = capacity;
+        self.address = align_up(buffer_address(device, self.buffer), alignment);
+        Ok(())
+    }
+
+    unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_buffer(self.buffer, None);
+        free_memory(device, self.memory);
+        *self = Self::default();
+    }
+}
+
+/// Builds `data.blas` from the scene mesh, replacing the previous one.
+/// Called again after a model is swapped in. Always of the full level of
+/// detail, and skinned meshes in their bind pose. Does nothing without ray
+/// tracing.
+pub(crate) unsafe fn create_blas(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.ray_tracing {
+        return Ok(());
+    }
+    data.blas.destroy(device);
+    if data.indices.is_empty() {
+        return Ok(());
+    }
+
+    let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR::builder()
+        .vertex_format(vk::Format::R32G32B32_SFLOAT)
+        .vertex_data(vk::DeviceOrHostAddressConstKHR {
+            device_address: buffer_address(device, data.vertex_buffer),
+        })
+        .vertex_stride(size_of::<Vertex>() as u64)
+        .max_vertex(data.vertices.len().saturating_sub(1) as u32)
+        .index_type(data.index_type)
+        .index_data(vk::DeviceOrHostAddressConstKHR {
+            device_address: buffer_address(device, data.index_buffer),
+        })
+        .build();
+    let geometry = vk::AccelerationStructureGeometryKHR::builder()
+        .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+        .geometry(vk::AccelerationStructureGeometryDataKHR { triangles })
+        .flags(vk::GeometryFlagsKHR::OPAQUE);
+    let geometries = &[geometry];
+
+    let mut info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+        .type_(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+        .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+        .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+        .geometries(geometries);
+
+    let primitive_count = model_lod(data, 0).index_count / 3;
+    let sizes = device.get_acceleration_structure_build_sizes_khr(
+        vk::AccelerationStructureBuildTypeKHR::DEVICE,
+        &info,
+        &[primitive_count],
+    );
+
+    data.blas = AccelerationStructure::new(
+        instance,
+        device,
+        data,
+        vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+        sizes.acceleration_structure_size,
+    )
+    .unwrap();
+
+    let mut scratch = ScratchBuffer::default();
+    scratch.reserve(instance, device, data, sizes.build_scratch_size).unwrap();
+
+    info = info
+        .dst_acceleration_structure(data.blas.handle)
+        .scratch_data(vk::DeviceOrHostAddressKHR { device_address: scratch.address });
+    let range = vk::AccelerationStructureBuildRangeInfoKHR::builder()
+        .primitive_count(primitive_count)
+        .build();
+
+    let command_buffer = begin_single_time_commands(device, data).unwrap();
+    device.cmd_build_acceleration_structures_khr(command_buffer, &[info], &[&range]);
+    end_single_time_commands(device, data, command_buffer).unwrap();
+
+    scratch.destroy(device);
+    Ok(())
+}
+
+/// The top level acceleration structure of one swapchain image, rebuilt
+/// from the entities every frame it is used.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct SceneTlas {
+    pub(crate) tlas: AccelerationStructure,
+    instances: DynamicBuffer,
+    scratch: ScratchBuffer,
+}
+
+impl SceneTlas {
+    /// Records building the acceleration structure from `transforms`, one
+    /// instance of `data.blas` each, whose custom index is its position.
+    /// The structure is recreated, and its handle changes, when it grows.
+    /// Ray tracing and ray queries in any shader stage can read the result.
+    pub(crate) unsafe fn record_build(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        transforms: &[Mat4],
+    ) -> Result<()> {
+        let instances = if data.blas.handle.is_null() {
+            vec![]
+        } else {
+            transforms
+                .iter()
+                .enumerate()
+                .map(|(i, transform)| vk::AccelerationStructureInstanceKHR {
+                    transform: vk::TransformMatrixKHR {
+                        matrix: [0, 1, 2].map(|row| [0, 1, 2, 3].map(|column| transform[column][row])),
+                    },
+                    instance_custom_index_and_mask: vk::Bitfield24_8::new(i as u32, 0xFF),
+                    instance_shader_binding_table_record_offset_and_flags: vk::Bitfield24_8::new(
+                        0,
+                        vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.bits() as u8,
+                    ),
+                    acceleration_structure_reference: data.blas.address,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        update_dynamic_buffer(
+            instance,
+            device,
+            data,
+            &mut self.instances,
+            vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+                | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR,
+            &instances,
+        )
+        .unwrap();
+        let instance_address = if instances.is_empty() { 0 } else { buffer_address(device, self.instances.buffer) };
+
+        let instances_data = vk::AccelerationStructureGeometryInstancesDataKHR::builder()
+            .array_of_pointers(false)
+            .data(vk::DeviceOrHostAddressConstKHR { device_address: instance_address })
+            .build();
+        let geometry = vk::AccelerationStructureGeometryKHR::builder()
+            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR { instances: instances_data });
+        let geometries = &[geometry];
+
+        let mut info = vk::AccelerationStructureBuildGeometryInfoKHR::builder()
+            .type_(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+            .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_BUILD)
+            .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+            .geometries(geometries);
+
+        // Sized for the next power of two so it is not recreated every time
+        // an entity is added.
+        let instance_count = instances.len() as u32;
+        let sizes = device.get_acceleration_structure_build_sizes_khr(
+            vk::AccelerationStructureBuildTypeKHR::DEVICE,
+            &info,
+            &[instance_count.max(1).next_power_of_two()],
+        );
+
+        if sizes.acceleration_structure_size > self.tlas.size {
+            self.tlas.destroy(device);
+            self.tlas = AccelerationStructure::new(
+                instance,
+                device,
+                data,
+                vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+                sizes.acceleration_structure_size,
+            )
+            .unwrap();
+        }
+        self.scratch.reserve(instance, device, data, sizes.build_scratch_size).unwrap();
+
+        // The previous frame to read this structure is done, but the build
+        // must still wait for the instance upload.
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::HOST_WRITE)
+            .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::HOST,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        info = info
+            .dst_acceleration_structure(self.tlas.handle)
+            .scratch_data(vk::DeviceOrHostAddressKHR { device_address: self.scratch.address });
+        let range = vk::AccelerationStructureBuildRangeInfoKHR::builder()
+            .primitive_count(instance_count)
+            .build();
+        device.cmd_build_acceleration_structures_khr(command_buffer, &[info], &[&range]);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+            .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+            vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR
+                | vk::PipelineStageFlags::COMPUTE_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+
+        Ok(())
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        self.tlas.destroy(device);
+        self.scratch.destroy(device);
+        destroy_dynamic_buffer(device, &mut self.instances);
+    }
+}
+
+/// Creates a `SceneTlas` per swapchain image. Does nothing without ray
+/// tracing.
+pub(crate) unsafe fn create_scene_tlas(data: &mut AppData) -> Result<()> {
+    data.scene_tlas.clear();
+    if data.ray_tracing {
+        data.scene_tlas = vec![SceneTlas::default(); data.swapchain_images.len()];
+    }
+    Ok(())
+}
+
+/// A ray tracing pipeline and its shader binding table, with a record per
+/// shader in the order they were given to `create_ray_tracing_pipeline`.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct RayTracingPipeline {
+    pub(crate) pipeline: vk::Pipeline,
+    sbt: vk::Buffer,
+    sbt_memory: vk::DeviceMemory,
+    raygen: vk::StridedDeviceAddressRegionKHR,
+    miss: vk::StridedDeviceAddressRegionKHR,
+    hit: vk::StridedDeviceAddressRegionKHR,
+}
+
+impl RayTracingPipeline {
+    /// Records tracing a ray per texel of `extent` with the first ray
+    /// generation shader. The pipeline and its descriptor sets must be
+    /// bound.
+    pub(crate) unsafe fn record_trace(&self, device: &Device, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+        device.cmd_trace_rays_khr(
+            command_buffer,
+            &self.raygen,
+            &self.miss,
+            &self.hit,
+            &vk::StridedDeviceAddressRegionKHR::default(),
+            extent.width,
+            extent.height,
+            1,
+        );
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_buffer(self.sbt, None);
+        free_memory(device, self.sbt_memory);
+        *self = Self::default();
+    }
+}
+
+/// Creates a ray tracing pipeline from ray generation, miss and closest hit
+/// shaders, each its own group, and lays out and uploads its shader binding
+/// table. Hit groups are triangle groups with only a closest hit shader.
+pub(crate) unsafe fn create_ray_tracing_pipeline(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    layout: vk::PipelineLayout,
+    shaders: &[(vk::ShaderStageFlags, &[u8])],
+    max_recursion_depth: u32,
+) -> Result<RayTracingPipeline> {
+    let modules = shaders
+        .iter()
+        .map(|(_, bytecode)| create_shader_module(device, bytecode).unwrap())
+        .collect::<Vec<_>>();
+    let stages = shaders
+        .iter()
+        .zip(&modules)
+        .map(|((stage, _), module)| {
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(*stage)
+                .module(*module)
+                .name(b"main\0")
+        })
+        .collect::<Vec<_>>();
+    let groups = shaders
+        .iter()
+        .enumerate()
+        .map(|(i, (stage, _))| {
+            let group = vk::RayTracingShaderGroupCreateInfoKHR::builder()
+                .general_shader(vk::SHADER_UNUSED_KHR)
+                .closest_hit_shader(vk::SHADER_UNUSED_KHR)
+                .any_hit_shader(vk::SHADER_UNUSED_KHR)
+                .intersection_shader(vk::SHADER_UNUSED_KHR);
+            if *stage == vk::ShaderStageFlags::CLOSEST_HIT_KHR {
+                group
+                    .type_(vk::RayTracingShaderGroupTypeKHR::TRIANGLES_HIT_GROUP)
+                    .closest_hit_shader(i as u32)
+            } else {
+                group.type_(vk::RayTracingShaderGroupTypeKHR::GENERAL).general_shader(i as u32)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let info = vk::RayTracingPipelineCreateInfoKHR::builder()
+        .stages(&stages)
+        .groups(&groups)
+        .max_pipeline_ray_recursion_depth(max_recursion_depth)
+        .layout(layout);
+    let pipeline = device
+        .create_ray_tracing_pipelines_khr(vk::DeferredOperationKHR::null(), data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    modules.iter().for_each(|m| device.destroy_shader_module(*m, None));
+
+    // Each region holds its stage's records back to back, starting at the
+    // base alignment. The ray generation region's stride must equal its
+    // size, so only the first ray generation shader can be traced.
+    let limits = data.ray_tracing_limits;
+    let handle_size = limits.handle_size as u64;
+    let stride = align_up(handle_size, limits.handle_alignment as u64);
+    let base_alignment = limits.base_alignment as u64;
+    let order = [
+        vk::ShaderStageFlags::RAYGEN_KHR,
+        vk::ShaderStageFlags::MISS_KHR,
+        vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+    ];
+
+    let mut regions = [(0, 0); 3];
+    let mut size = 0;
+    for (region, stage) in regions.iter_mut().zip(order) {
+        let count = shaders.iter().filter(|(s, _)| *s == stage).count() as u64;
+        *region = (size, count);
+        size = align_up(size + stride * count, base_alignment);
+    }
+
+    let group_count = groups.len() as u32;
+    let mut handles = vec![0; group_count as usize * handle_size as usize];
+    device
+        .get_ray_tracing_shader_group_handles_khr(pipeline, 0, group_count, &mut handles)
+        .unwrap();
+
+    let mut table = vec![0u8; size.max(1) as usize];
+    for (region, stage) in regions.iter().zip(order) {
+        let groups = shaders.iter().enumerate().filter(|(_, (s, _))| *s == stage).map(|(i, _)| i);
+        for (record, group) in groups.enumerate() {
+            let offset = (region.0 + stride * record as u64) as usize;
+            let handle = group * handle_size as usize;
+            table[offset..offset + handle_size as usize].copy_from_slice(&handles[handle..handle + handle_size as usize]);
+        }
+    }
+
+    // Read straight from host visible memory, as it is small and written
+    // once.
+    let (sbt, sbt_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        table.len() as u64 + base_alignment,
+        vk::BufferUsageFlags::SHADER_BINDING_TABLE_KHR | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )
+    .unwrap();
+
+    let address = buffer_address(device, sbt);
+    let start = align_up(address, base_alignment);
+    let memory = device
+        .map_memory(sbt_memory, 0, vk::WHOLE_SIZE as u64, vk::MemoryMapFlags::empty())
+        .unwrap();
+    std::ptr::copy_nonoverlapping(table.as_ptr(), memory.cast::<u8>().add((start - address) as usize), table.len());
+    device.unmap_memory(sbt_memory);
+
+    let region = |(offset, count): (u64, u64), stride: u64| {
+        if count == 0 {
+            vk::StridedDeviceAddressRegionKHR::default()
+        } else {
+            vk::StridedDeviceAddressRegionKHR::builder()
+                .device_address(start + offset)
+                .stride(stride)
+                .size(stride * count)
+                .build()
+        }
+    };
+
+    Ok(RayTracingPipeline {
+        pipeline,
+        sbt,
+        sbt_memory,
+        raygen: region((regions[0].0, regions[0].1.min(1)), stride),
+        miss: region(regions[1], stride),
+        hit: region(regions[2], stride),
+    })
+}
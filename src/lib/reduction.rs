@@ -0,0 +1,273 @@
+use anyhow::Result;
+use log::*;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{allocations::free_memory, app::AppData, shader::create_shader_module, vertex_buffer::create_buffer};
+
+/// Elements handled by one workgroup of the reduction shaders.
+pub(crate) const REDUCTION_WORKGROUP_SIZE: u32 = 256;
+
+/// The subgroup shaders reduce each subgroup and then reduce the per-subgroup
+/// results within the first subgroup, so they need at least this many lanes.
+const MIN_SUBGROUP_SIZE: u32 = 16;
+
+/// A chain of scratch buffers that reduces `count` elements by a factor of
+/// `REDUCTION_WORKGROUP_SIZE` per level until a single value remains.
+///
+/// The same chain drives both the float sum (used for luminance averaging)
+/// and the exclusive `u32` prefix sum (used for light clustering and culling
+/// compaction). Callers are responsible for barriers around `record_*`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Reduction {
+    pub(crate) descriptor_pool: vk::DescriptorPool,
+    pub(crate) sets: Vec<vk::DescriptorSet>,
+    pub(crate) counts: Vec<u32>,
+    pub(crate) buffers: Vec<vk::Buffer>,
+    pub(crate) buffers_memory: Vec<vk::DeviceMemory>,
+}
+
+impl Reduction {
+    /// The single-element buffer holding the sum of every input element.
+    pub(crate) fn total(&self) -> vk::Buffer {
+        *self.buffers.last().unwrap()
+    }
+
+    /// Sums the `f32` input into `total()`.
+    pub(crate) unsafe fn record_sum(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.reduce_pipeline);
+        for (set, count) in self.sets.iter().zip(&self.counts) {
+            self.dispatch(device, data, command_buffer, *set, *count);
+        }
+    }
+
+    /// Replaces the `u32` input with its exclusive prefix sum and writes the
+    /// sum of all elements to `total()`.
+    #[allow(dead_code)]
+    pub(crate) unsafe fn record_prefix_sum(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.scan_pipeline);
+        for (set, count) in self.sets.iter().zip(&self.counts) {
+            self.dispatch(device, data, command_buffer, *set, *count);
+        }
+
+        // Each level now holds block-local offsets; push the scanned block
+        // sums back down the chain.
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, data.scan_add_pipeline);
+        for (set, count) in self.sets.iter().zip(&self.counts).rev().skip(1) {
+            self.dispatch(device, data, command_buffer, *set, *count);
+        }
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        self.buffers_memory.iter().for_each(|m| free_memory(device, *m));
+        self.buffers.iter().for_each(|b| device.destroy_buffer(*b, None));
+        *self = Self::default();
+    }
+
+    unsafe fn dispatch(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        set: vk::DescriptorSet,
+        count: u32,
+    ) {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            data.reduction_pipeline_layout,
+            0,
+            &[set],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            data.reduction_pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            &count.to_ne_bytes(),
+        );
+        device.cmd_dispatch(command_buffer, count.div_ceil(REDUCTION_WORKGROUP_SIZE), 1, 1);
+
+        let barrier = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[barrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[] as &[vk::ImageMemoryBarrier],
+        );
+    }
+}
+
+/// Builds the scratch chain for `count` 4-byte elements stored in `input`,
+/// which must have been created with `STORAGE_BUFFER` usage.
+pub(crate) unsafe fn create_reduction(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    input: vk::Buffer,
+    count: u32,
+) -> Result<Reduction> {
+    let mut reduction = Reduction::default();
+    let mut source = input;
+    let mut remaining = count.max(1);
+
+    loop {
+        let blocks = remaining.div_ceil(REDUCTION_WORKGROUP_SIZE);
+        let (buffer, buffer_memory) = create_buffer(
+            instance,
+            device,
+            data,
+            (blocks * 4) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+        .unwrap();
+
+        reduction.counts.push(remaining);
+        reduction.buffers.push(buffer);
+        reduction.buffers_memory.push(buffer_memory);
+
+        if blocks == 1 {
+            break;
+        }
+        remaining = blocks;
+    }
+
+    let levels = reduction.counts.len() as u32;
+    let storage_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(levels * 2);
+
+    let pool_sizes = &[storage_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(levels);
+
+    reduction.descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = vec![data.reduction_set_layout; levels as usize];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(reduction.descriptor_pool)
+        .set_layouts(&layouts);
+
+    reduction.sets = device.allocate_descriptor_sets(&info).unwrap();
+
+    for (set, output) in reduction.sets.iter().zip(&reduction.buffers) {
+        let source_info = vk::DescriptorBufferInfo::builder()
+            .buffer(source)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64);
+        let output_info = vk::DescriptorBufferInfo::builder()
+            .buffer(*output)
+            .offset(0)
+            .range(vk::WHOLE_SIZE as u64);
+
+        let source_infos = &[source_info];
+        let source_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(source_infos);
+
+        let output_infos = &[output_info];
+        let output_write = vk::WriteDescriptorSet::builder()
+            .dst_set(*set)
+            .dst_binding(1)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(output_infos);
+
+        device.update_descriptor_sets(&[source_write, output_write], &[] as &[vk::CopyDescriptorSet]);
+        source = *output;
+    }
+
+    Ok(reduction)
+}
+
+pub(crate) unsafe fn create_reduction_pipelines(device: &Device, data: &mut AppData) -> Result<()> {
+    let bindings = [0, 1].map(|binding| {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .build()
+    });
+
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+    data.reduction_set_layout = data.set_layouts.get(device, &info).unwrap();
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::COMPUTE)
+        .offset(0)
+        .size(4);
+
+    let set_layouts = &[data.reduction_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.reduction_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let subgroups = data.subgroup_arithmetic && data.subgroup_size >= MIN_SUBGROUP_SIZE;
+    let (reduce, scan): (&[u8], &[u8]) = if subgroups {
+        info!("Using subgroup reductions (subgroup size {}).", data.subgroup_size);
+        (
+            include_bytes!("../../shaders/reduce_subgroup_comp.spv"),
+            include_bytes!("../../shaders/scan_subgroup_comp.spv"),
+        )
+    } else {
+        info!("Subgroup arithmetic unavailable, using shared memory reductions.");
+        (
+            include_bytes!("../../shaders/reduce_comp.spv"),
+            include_bytes!("../../shaders/scan_comp.spv"),
+        )
+    };
+    let scan_add = include_bytes!("../../shaders/scan_add_comp.spv");
+
+    data.reduce_pipeline = create_compute_pipeline(device, data, reduce).unwrap();
+    data.scan_pipeline = create_compute_pipeline(device, data, scan).unwrap();
+    data.scan_add_pipeline = create_compute_pipeline(device, data, &scan_add[..]).unwrap();
+
+    Ok(())
+}
+
+unsafe fn create_compute_pipeline(device: &Device, data: &AppData, code: &[u8]) -> Result<vk::Pipeline> {
+    let shader_module = create_shader_module(device, code).unwrap();
+
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(shader_module)
+        .name(b"main\0");
+
+    let info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(data.reduction_pipeline_layout);
+
+    let pipeline = device
+        .create_compute_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(shader_module, None);
+    Ok(pipeline)
+}
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use rspirv::{
+    dr::{load_bytes, Instruction, Operand},
+    spirv::{Decoration, Dim, ExecutionModel, Op, StorageClass, Word},
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+/// A descriptor binding declared by a shader.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ReflectedBinding {
+    pub(crate) set: u32,
+    pub(crate) binding: u32,
+    pub(crate) descriptor_type: vk::DescriptorType,
+    /// Array length. Arrays sized by a specialization constant report its
+    /// default value, and runtime arrays report 0.
+    pub(crate) count: u32,
+    pub(crate) stages: vk::ShaderStageFlags,
+}
+
+/// The pipeline layout and vertex input a set of shaders declare, read from
+/// their SPIR-V so it cannot drift from the GLSL.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ShaderReflection {
+    pub(crate) bindings: Vec<ReflectedBinding>,
+    pub(crate) push_constant_ranges: Vec<vk::PushConstantRange>,
+    /// Vertex shader inputs by location.
+    pub(crate) inputs: Vec<(u32, vk::Format)>,
+}
+
+impl ShaderReflection {
+    /// Reflects one shader module with a single entry point.
+    pub(crate) fn new(spirv: &[u8]) -> Result<Self> {
+        let module = load_bytes(spirv).map_err(|e| anyhow!("Invalid SPIR-V: {}", e))?;
+        let stage = match module.entry_points.first().map(|e| e.operands[0].unwrap_execution_model()) {
+            Some(ExecutionModel::Vertex) => vk::ShaderStageFlags::VERTEX,
+            Some(ExecutionModel::Fragment) => vk::ShaderStageFlags::FRAGMENT,
+            Some(ExecutionModel::GLCompute) => vk::ShaderStageFlags::COMPUTE,
+            model => return Err(anyhow!("Unsupported shader stage {:?}.", model)),
+        };
+
+        let types = Types::new(&module.types_global_values, &module.annotations);
+        let mut reflection = Self::default();
+
+        for variable in module.types_global_values.iter().filter(|i| i.class.opcode == Op::Variable) {
+            let id = variable.result_id.unwrap();
+            let storage_class = variable.operands[0].unwrap_storage_class();
+            let pointee = types.pointee(variable.result_type.unwrap());
+
+            match storage_class {
+                StorageClass::Uniform | StorageClass::UniformConstant | StorageClass::StorageBuffer => {
+                    let (Some(set), Some(binding)) = (
+                        types.decoration(id, Decoration::DescriptorSet),
+                        types.decoration(id, Decoration::Binding),
+                    ) else {
+                        continue;
+                    };
+
+                    let (element, count) = types.strip_array(pointee);
+                    reflection.bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        descriptor_type: types.descriptor_type(element, storage_class)?,
+                        count,
+                        stages: stage,
+                    });
+                }
+                StorageClass::PushConstant => {
+                    let (offset, end) = types.member_range(pointee)?;
+                    reflection.push_constant_ranges.push(
+                        vk::PushConstantRange::builder()
+                            .stage_flags(stage)
+                            .offset(offset)
+                            .size(end - offset)
+                            .build(),
+                    );
+                }
+                StorageClass::Input if stage == vk::ShaderStageFlags::VERTEX => {
+                    if let Some(location) = types.decoration(id, Decoration::Location) {
+                        reflection.inputs.push((location, types.format(pointee)?));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        reflection.inputs.sort_by_key(|(location, _)| *location);
+        Ok(reflection)
+    }
+
+    /// Combines the stages of one pipeline. Bindings and push constant
+    /// ranges declared by several stages are visible to all of them.
+    pub(crate) fn merge(stages: &[ShaderReflection]) -> Self {
+        let mut merged = Self::default();
+        for stage in stages {
+            for binding in &stage.bindings {
+                match merged.bindings.iter_mut().find(|b| (b.set, b.binding) == (binding.set, binding.binding)) {
+                    Some(b) => {
+                        b.stages |= binding.stages;
+                        b.count = b.count.max(binding.count);
+                    }
+                    None => merged.bindings.push(*binding),
+                }
+            }
+
+            for range in &stage.push_constant_ranges {
+                match merged
+                    .push_constant_ranges
+                    .iter_mut()
+                    .find(|r| (r.offset, r.size) == (range.offset, range.size))
+                {
+                    Some(r) => r.stage_flags |= range.stage_flags,
+                    None => merged.push_constant_ranges.push(*range),
+                }
+            }
+
+            merged.inputs.extend(stage.inputs.iter().copied());
+        }
+
+        merged.bindings.sort_by_key(|b| (b.set, b.binding));
+        merged
+    }
+
+    /// The layout bindings of descriptor set `set`.
+    pub(crate) fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.bindings
+            .iter()
+            .filter(|b| b.set == set)
+            .map(|b| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(b.binding)
+                    .descriptor_type(b.descriptor_type)
+                    .descriptor_count(b.count)
+                    .stage_flags(b.stages)
+                    .build()
+            })
+            .collect()
+    }
+
+    /// Attributes for a vertex buffer at `binding` that interleaves every
+    /// input in location order with no padding, and the stride that implies.
+    pub(crate) fn vertex_attributes(&self, binding: u32) -> (u32, Vec<vk::VertexInputAttributeDescription>) {
+        let mut offset = 0;
+        let attributes = self
+            .inputs
+            .iter()
+            .map(|(location, format)| {
+                let attribute = vk::VertexInputAttributeDescription::builder()
+                    .binding(binding)
+                    .location(*location)
+                    .format(*format)
+                    .offset(offset)
+                    .build();
+                offset += format_size(*format);
+                attribute
+            })
+            .collect();
+        (offset, attributes)
+    }
+}
+
+fn format_size(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R32_SFLOAT | vk::Format::R32_SINT | vk::Format::R32_UINT => 4,
+        vk::Format::R32G32_SFLOAT | vk::Format::R32G32_SINT | vk::Format::R32G32_UINT => 8,
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_SINT | vk::Format::R32G32B32_UINT => 12,
+        _ => 16,
+    }
+}
+
+/// Lookups over a module's types, constants and decorations.
+struct Types<'a> {
+    definitions: HashMap<Word, &'a Instruction>,
+    decorations: HashMap<(Word, Decoration), u32>,
+    /// Struct member offsets by struct and member index.
+    offsets: HashMap<(Word, u32), u32>,
+}
+
+impl<'a> Types<'a> {
+    fn new(globals: &'a [Instruction], annotations: &'a [Instruction]) -> Self {
+        let definitions = globals.iter().filter_map(|i| Some((i.result_id?, i))).collect();
+
+        let mut decorations = HashMap::new();
+        let mut offsets = HashMap::new();
+        for annotation in annotations {
+            match annotation.class.opcode {
+                Op::Decorate => {
+                    let value = annotation.operands.get(2).map(literal).unwrap_or(0);
+                    decorations.insert(
+                        (annotation.operands[0].unwrap_id_ref(), annotation.operands[1].unwrap_decoration()),
+                        value,
+                    );
+                }
+                Op::MemberDecorate if annotation.operands[2].unwrap_decoration() == Decoration::Offset => {
+                    offsets.insert(
+                        (annotation.operands[0].unwrap_id_ref(), annotation.operands[1].unwrap_literal_int32()),
+                        literal(&annotation.operands[3]),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            definitions,
+            decorations,
+            offsets,
+        }
+    }
+
+    fn decoration(&self, id: Word, decoration: Decoration) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn definition(&self, id: Word) -> &'a Instruction {
+        self.definitions[&id]
+    }
+
+    fn pointee(&self, pointer: Word) -> Word {
+        self.definition(pointer).operands[1].unwrap_id_ref()
+    }
+
+    fn constant(&self, id: Word) -> u32 {
+        self.definition(id).operands.first().map(literal).unwrap_or(0)
+    }
+
+    /// The element type of an array, and its length. Other types count as
+    /// one element.
+    fn strip_array(&self, id: Word) -> (Word, u32) {
+        let definition = self.definition(id);
+        match definition.class.opcode {
+            Op::TypeArray => (
+                definition.operands[0].unwrap_id_ref(),
+                self.constant(definition.operands[1].unwrap_id_ref()),
+            ),
+            Op::TypeRuntimeArray => (definition.operands[0].unwrap_id_ref(), 0),
+            _ => (id, 1),
+        }
+    }
+
+    fn descriptor_type(&self, id: Word, storage_class: StorageClass) -> Result<vk::DescriptorType> {
+        let definition = self.definition(id);
+        Ok(match definition.class.opcode {
+            Op::TypeSampledImage => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Op::TypeSampler => vk::DescriptorType::SAMPLER,
+            Op::TypeImage => {
+                let dim = definition.operands[1].unwrap_dim();
+                let sampled = literal(&definition.operands[5]);
+                match (dim, sampled) {
+                    (Dim::DimSubpassData, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                    (Dim::DimBuffer, 1) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (Dim::DimBuffer, _) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (_, 1) => vk::DescriptorType::SAMPLED_IMAGE,
+                    _ => vk::DescriptorType::STORAGE_IMAGE,
+                }
+            }
+            Op::TypeStruct if storage_class == StorageClass::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+            Op::TypeStruct if self.decoration(id, Decoration::BufferBlock).is_some() => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            Op::TypeStruct => vk::DescriptorType::UNIFORM_BUFFER,
+            op => return Err(anyhow!("Unsupported descriptor type {:?}.", op)),
+        })
+    }
+
+    /// The first and one past the last byte a block's members occupy.
+    fn member_range(&self, id: Word) -> Result<(u32, u32)> {
+        let definition = self.definition(id);
+        let mut range = (u32::MAX, 0);
+        for (member, operand) in definition.operands.iter().enumerate() {
+            let offset = self.offsets.get(&(id, member as u32)).copied().unwrap_or(0);
+            let end = offset + self.size(operand.unwrap_id_ref())?;
+            range = (range.0.min(offset), range.1.max(end));
+        }
+
+        if range.0 > range.1 {
+            return Err(anyhow!("Empty push constant block."));
+        }
+        Ok(range)
+    }
+
+    /// The size of a type in a block, following its explicit layout.
+    fn size(&self, id: Word) -> Result<u32> {
+        let definition = self.definition(id);
+        Ok(match definition.class.opcode {
+            Op::TypeBool => 4,
+            Op::TypeInt | Op::TypeFloat => literal(&definition.operands[0]) / 8,
+            Op::TypeVector => self.size(definition.operands[0].unwrap_id_ref())? * literal(&definition.operands[1]),
+            // Column-major matrices in std140 and std430 pad columns to vec4.
+            Op::TypeMatrix => {
+                let column = self.size(definition.operands[0].unwrap_id_ref())?;
+                column.next_multiple_of(16) * literal(&definition.operands[1])
+            }
+            Op::TypeArray => {
+                let stride = match self.decoration(id, Decoration::ArrayStride) {
+                    Some(stride) => stride,
+                    None => self.size(definition.operands[0].unwrap_id_ref())?,
+                };
+                stride * self.constant(definition.operands[1].unwrap_id_ref())
+            }
+            Op::TypeStruct => self.member_range(id)?.1,
+            op => return Err(anyhow!("Unsupported block member type {:?}.", op)),
+        })
+    }
+
+    /// The vertex attribute format of an input type.
+    fn format(&self, id: Word) -> Result<vk::Format> {
+        let definition = self.definition(id);
+        let (scalar, components) = match definition.class.opcode {
+            Op::TypeVector => (
+                self.definition(definition.operands[0].unwrap_id_ref()),
+                literal(&definition.operands[1]),
+            ),
+            _ => (definition, 1),
+        };
+
+        let formats = match scalar.class.opcode {
+            Op::TypeFloat => [
+                vk::Format::R32_SFLOAT,
+                vk::Format::R32G32_SFLOAT,
+                vk::Format::R32G32B32_SFLOAT,
+                vk::Format::R32G32B32A32_SFLOAT,
+            ],
+            Op::TypeInt if literal(&scalar.operands[1]) == 1 => [
+                vk::Format::R32_SINT,
+                vk::Format::R32G32_SINT,
+                vk::Format::R32G32B32_SINT,
+                vk::Format::R32G32B32A32_SINT,
+            ],
+            Op::TypeInt => [
+                vk::Format::R32_UINT,
+                vk::Format::R32G32_UINT,
+                vk::Format::R32G32B32_UINT,
+                vk::Format::R32G32B32A32_UINT,
+            ],
+            op => return Err(anyhow!("Unsupported vertex input type {:?}.", op)),
+        };
+
+        Ok(formats[components as usize - 1])
+    }
+}
+
+fn literal(operand: &Operand) -> u32 {
+    match operand {
+        Operand::LiteralInt32(value) => *value,
+        Operand::LiteralInt64(value) => *value as u32,
+        _ => 0,
+    }
+}
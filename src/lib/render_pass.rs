@@ -1,39 +1,61 @@
 use anyhow::Result;
+use log::*;
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
     app::AppData,
-    depth_object::get_depth_format,
-
+    oit::{ACCUM_FORMAT, REVEAL_FORMAT},
+    tonemap::{uses_resolve_attachment, HDR_FORMAT},
 };
 
+/// The store op for attachments whose contents are dropped at the end of
+/// the render pass. `NONE` skips the store access entirely, so the next
+/// frame's clear does not have to wait on a write nobody reads.
+pub(crate) fn discard_store_op(data: &AppData) -> vk::AttachmentStoreOp {
+  if data.load_store_op_none {
+    vk::AttachmentStoreOp::NONE
+  } else {
+    vk::AttachmentStoreOp::DONT_CARE
+  }
+}
+
+/// Left null under dynamic rendering, see `dynamic_rendering::begin_pass`.
 pub(crate) unsafe fn create_render_pass(
-  instance: &Instance,
   device: &Device,
   data: &mut AppData,
 ) -> Result<()> {
+  if data.dynamic_rendering {
+    data.render_pass = vk::RenderPass::null();
+    return Ok(());
+  }
+
+  // The scene color and depth only live for the render pass. The color is
+  // cleared because the background is not drawn; depth is cleared for the
+  // depth test and read by the decal subpass. Neither is stored.
   let color_attachment = vk::AttachmentDescription::builder()
-      .format(data.swapchain_format)
+      .format(HDR_FORMAT)
       .samples(data.msaa_samples)
       .load_op(vk::AttachmentLoadOp::CLEAR)
-      .store_op(vk::AttachmentStoreOp::STORE)
+      .store_op(discard_store_op(data))
       .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
       .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
       .initial_layout(vk::ImageLayout::UNDEFINED)
       .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
 
   let depth_stencil_attachment = vk::AttachmentDescription::builder()
-      .format(get_depth_format(instance, data).unwrap())
+      .format(data.depth_format)
       .samples(data.msaa_samples)
       .load_op(vk::AttachmentLoadOp::CLEAR)
-      .store_op(vk::AttachmentStoreOp::DONT_CARE)
+      .store_op(discard_store_op(data))
       .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
       .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
       .initial_layout(vk::ImageLayout::UNDEFINED)
       .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
 
-  let color_resolve_attachment = vk::AttachmentDescription::builder()
+  // Every pixel of the swapchain image is written by the tonemap subpass,
+  // so its old contents are never loaded.
+  let present_attachment = vk::AttachmentDescription::builder()
       .format(data.swapchain_format)
       .samples(vk::SampleCountFlags::_1)
       .load_op(vk::AttachmentLoadOp::DONT_CARE)
@@ -43,25 +65,111 @@ pub(crate) unsafe fn create_render_pass(
       .initial_layout(vk::ImageLayout::UNDEFINED)
       .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
 
-  let color_attachment_ref = vk::AttachmentReference::builder()
-      .attachment(0)
-      .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+  // The order-independent transparency targets are accumulated into by
+  // the scene subpass and read by the composite subpass, then dropped.
+  let oit_attachment = |format| {
+    vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(data.msaa_samples)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(discard_store_op(data))
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+  };
 
-  let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
-      .attachment(1)
-      .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+  // Only present when resolving before tonemapping. The resolve overwrites
+  // it and the tonemap subpass is its only reader.
+  let hdr_resolve_attachment = vk::AttachmentDescription::builder()
+      .format(HDR_FORMAT)
+      .samples(vk::SampleCountFlags::_1)
+      .load_op(vk::AttachmentLoadOp::DONT_CARE)
+      .store_op(discard_store_op(data))
+      .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+      .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+      .initial_layout(vk::ImageLayout::UNDEFINED)
+      .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
 
-  let color_resolve_attachment_ref = vk::AttachmentReference::builder()
-      .attachment(2)
-      .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+  // The framebuffers list their views in the same order, see
+  // `create_framebuffers`.
+  let oit = data.order_independent_transparency;
+  let resolve = uses_resolve_attachment(data);
+  let mut attachments = vec![color_attachment, depth_stencil_attachment];
+  if oit {
+    attachments.extend([oit_attachment(ACCUM_FORMAT), oit_attachment(REVEAL_FORMAT)]);
+  }
+  let present_index = attachments.len() as u32;
+  attachments.push(present_attachment);
+  let resolve_index = attachments.len() as u32;
+  if resolve {
+    attachments.push(hdr_resolve_attachment);
+  }
 
-  let color_attachments = &[color_attachment_ref];
-  let resolve_attachments = &[color_resolve_attachment_ref];
-  let subpass = vk::SubpassDescription::builder()
+  let attachment_ref = |attachment, layout| {
+    vk::AttachmentReference::builder()
+        .attachment(attachment)
+        .layout(layout)
+        .build()
+  };
+
+  let mut color_attachments = vec![attachment_ref(0, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+  if oit {
+    color_attachments.extend([
+        attachment_ref(2, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+        attachment_ref(3, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+    ]);
+  }
+  let depth_stencil_attachment_ref = attachment_ref(1, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+  let resolve_attachments = &[attachment_ref(resolve_index, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+
+  // The color is resolved by whichever subpass finishes it, see
+  // `Pass::finishes_color`.
+  let scene_subpass = vk::SubpassDescription::builder()
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+      .color_attachments(&color_attachments)
+      .depth_stencil_attachment(&depth_stencil_attachment_ref);
+
+  let depth_input_attachments = &[attachment_ref(1, vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL)];
+  let mut decal_subpass = vk::SubpassDescription::builder()
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+      .input_attachments(depth_input_attachments)
+      .color_attachments(&color_attachments[..1]);
+  if resolve && !oit {
+    decal_subpass = decal_subpass.resolve_attachments(resolve_attachments);
+  }
+
+  let oit_input_attachments = &[
+      attachment_ref(2, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+      attachment_ref(3, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+  ];
+  let composite_color_attachments = &color_attachments[..1];
+  let mut composite_subpass = vk::SubpassDescription::builder()
       .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-      .color_attachments(color_attachments)
-      .depth_stencil_attachment(&depth_stencil_attachment_ref)
-      .resolve_attachments(resolve_attachments);
+      .input_attachments(oit_input_attachments)
+      .color_attachments(composite_color_attachments);
+  if resolve {
+    composite_subpass = composite_subpass.resolve_attachments(resolve_attachments);
+  }
+
+  // The tonemap subpass reads the scene's HDR color, either the resolved
+  // image or every sample of the multisampled one.
+  let input_attachments = &[attachment_ref(
+      if resolve { resolve_index } else { 0 },
+      vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+  )];
+  let present_attachments = &[attachment_ref(present_index, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+  let tonemap_subpass = vk::SubpassDescription::builder()
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+      .input_attachments(input_attachments)
+      .color_attachments(present_attachments);
+
+  let mut subpasses = vec![scene_subpass, decal_subpass];
+  if oit {
+    subpasses.push(composite_subpass);
+  }
+  subpasses.push(tonemap_subpass);
+  let tonemap = subpasses.len() as u32 - 1;
 
   let dependency = vk::SubpassDependency::builder()
       .src_subpass(vk::SUBPASS_EXTERNAL)
@@ -80,11 +188,110 @@ pub(crate) unsafe fn create_render_pass(
               | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
       );
 
-  let attachments = &[
-      color_attachment,
-      depth_stencil_attachment,
-      color_resolve_attachment,
+  // The swapchain image is first written in the tonemap subpass, so its
+  // layout transition has to wait for the acquire semaphore there too.
+  let present_dependency = vk::SubpassDependency::builder()
+      .src_subpass(vk::SUBPASS_EXTERNAL)
+      .dst_subpass(tonemap)
+      .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+      .src_access_mask(vk::AccessFlags::empty())
+      .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+      .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+  // The decals read the depth and blend over the color, and the composite
+  // reads the OIT targets and blends over the decals.
+  let over_dependency = |src_subpass, dst_subpass| {
+    vk::SubpassDependency::builder()
+        .src_subpass(src_subpass)
+        .dst_subpass(dst_subpass)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )
+        .src_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        )
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(
+            vk::AccessFlags::INPUT_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        )
+        .dependency_flags(vk::DependencyFlags::BY_REGION)
+        .build()
+  };
+
+  let tonemap_dependency = vk::SubpassDependency::builder()
+      .src_subpass(tonemap - 1)
+      .dst_subpass(tonemap)
+      .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+      .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+      .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+      .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ)
+      .dependency_flags(vk::DependencyFlags::BY_REGION);
+
+  for (i, a) in attachments.iter().enumerate() {
+    debug!("Render pass attachment {} ({:?}): {:?} / {:?}.", i, a.format, a.load_op, a.store_op);
+  }
+
+  let mut dependencies = vec![
+      dependency.build(),
+      present_dependency.build(),
+      tonemap_dependency.build(),
+      over_dependency(0, 1),
   ];
+  if oit {
+    dependencies.extend([over_dependency(0, 2), over_dependency(1, 2)]);
+  }
+  let info = vk::RenderPassCreateInfo::builder()
+      .attachments(&attachments)
+      .subpasses(&subpasses)
+      .dependencies(&dependencies);
+
+  data.render_pass = device.create_render_pass(&info, None).unwrap();
+  Ok(())
+}
+
+/// A pass over the presented image for everything drawn after tonemapping:
+/// the focus blur and the UI.
+pub(crate) unsafe fn create_overlay_render_pass(device: &Device, data: &mut AppData) -> Result<()> {
+  if data.dynamic_rendering {
+    data.overlay_render_pass = vk::RenderPass::null();
+    return Ok(());
+  }
+
+  // The tonemapped frame is drawn over, so it is loaded and stored again.
+  let present_attachment = vk::AttachmentDescription::builder()
+      .format(data.swapchain_format)
+      .samples(vk::SampleCountFlags::_1)
+      .load_op(vk::AttachmentLoadOp::LOAD)
+      .store_op(vk::AttachmentStoreOp::STORE)
+      .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+      .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+      .initial_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+      .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+
+  let present_attachment_ref = vk::AttachmentReference::builder()
+      .attachment(0)
+      .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+  let present_attachments = &[present_attachment_ref];
+  let subpass = vk::SubpassDescription::builder()
+      .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+      .color_attachments(present_attachments);
+
+  // Waits for the tonemap subpass and for any blits from the swapchain
+  // image, metering's or the focus blur's.
+  let dependency = vk::SubpassDependency::builder()
+      .src_subpass(vk::SUBPASS_EXTERNAL)
+      .dst_subpass(0)
+      .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::TRANSFER)
+      .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+      .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+      .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+  let attachments = &[present_attachment];
   let subpasses = &[subpass];
   let dependencies = &[dependency];
   let info = vk::RenderPassCreateInfo::builder()
@@ -92,6 +299,6 @@ pub(crate) unsafe fn create_render_pass(
       .subpasses(subpasses)
       .dependencies(dependencies);
 
-  data.render_pass = device.create_render_pass(&info, None).unwrap();
+  data.overlay_render_pass = device.create_render_pass(&info, None).unwrap();
   Ok(())
-}
\ No newline at end of file
+}
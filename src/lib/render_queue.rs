@@ -0,0 +1,76 @@
+/// When an entity is drawn relative to others. Lower queues draw first.
+///
+/// The named queues leave gaps so custom ones can be slotted between them,
+/// e.g. `RenderQueue(RenderQueue::TRANSPARENT.0 + 10)` for glass that should
+/// draw over other transparent surfaces.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RenderQueue(pub u16);
+
+impl RenderQueue {
+    /// Skies and backdrops.
+    pub const BACKGROUND: Self = Self(1000);
+    pub const OPAQUE: Self = Self(2000);
+    /// Alpha-tested surfaces, after opaque ones so they are rejected by
+    /// depth more often.
+    pub const CUTOUT: Self = Self(2450);
+    /// Blended surfaces, drawn back to front after the grid. They are depth
+    /// tested but do not write depth, so they never hide each other.
+    pub const TRANSPARENT: Self = Self(3000);
+    /// Drawn last over a cleared depth buffer, for viewmodels and gizmos that
+    /// must never clip into the scene.
+    pub const OVERLAY: Self = Self(4000);
+
+    /// Queues below `TRANSPARENT` are depth tested against each other, so
+    /// their order only affects performance. Static entities in them are
+    /// batched, ahead of dynamic entities in the same queues.
+    pub fn is_opaque(self) -> bool {
+        self < Self::TRANSPARENT
+    }
+
+    /// Whether the queue is sorted back to front.
+    pub fn is_transparent(self) -> bool {
+        self >= Self::TRANSPARENT && self < Self::OVERLAY
+    }
+
+    /// Whether depth is cleared before the first draw in this queue or any
+    /// later one.
+    pub fn clears_depth(self) -> bool {
+        self >= Self::OVERLAY
+    }
+}
+
+impl Default for RenderQueue {
+    fn default() -> Self {
+        Self::OPAQUE
+    }
+}
+
+/// When an entity is drawn, relative to the others.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DrawOrder {
+    pub queue: RenderQueue,
+    /// Orders draws within a queue before distance does. Lower keys draw
+    /// first.
+    pub sort_key: u16,
+}
+
+impl DrawOrder {
+    /// The key draws are sorted by: the queue, then `sort_key`, then the
+    /// view depth, nearest first or, in transparent queues, farthest first.
+    /// Queues that are not sorted back to front group draws by `material`,
+    /// see `Materials::draw_group`, before depth, which only keeps its
+    /// leading 16 bits there.
+    ///
+    /// `depth` is the distance in front of the camera; anything behind it
+    /// counts as 0.
+    pub(crate) fn draw_key(&self, material: u16, depth: f32) -> u64 {
+        // Non-negative floats order the same as their bits.
+        let depth = depth.max(0.0).to_bits();
+        let order = (self.queue.0 as u64) << 48 | (self.sort_key as u64) << 32;
+        if self.queue.is_transparent() {
+            order | !depth as u64
+        } else {
+            order | (material as u64) << 16 | (depth >> 16) as u64
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Result};
+use log::*;
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+use winit::window::Window;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{app::App, world::WorldConfig};
+
+/// Messages sent from the event loop on the main thread to the render thread.
+pub enum RenderMessage {
+    Resized { width: u32, height: u32 },
+    /// The main window moved to a display with a different DPI, or the
+    /// display's changed.
+    ScaleFactorChanged { scale_factor: f64 },
+    /// winit's `Suspended`: the window's surface is about to go away.
+    Suspended,
+    /// winit's `Resumed`: the window can be presented to again.
+    Resumed,
+    Run(Box<dyn FnOnce(&mut App) + Send>),
+    Exit,
+}
+
+/// Owns the `App` on a dedicated thread so that the main thread is free to
+/// pump window events (which block during drags and resizes on Windows).
+pub struct RenderThread {
+    sender: Sender<RenderMessage>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl RenderThread {
+    /// Spawns the render thread. `on_frame` is called before every frame.
+    pub fn spawn<F>(window: Arc<Window>, world: WorldConfig, on_frame: F) -> Self
+    where
+        F: FnMut(&mut App) + Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let handle = thread::Builder::new()
+            .name("render".into())
+            .spawn(move || unsafe { render_loop(&window, world, receiver, on_frame) })
+            .unwrap();
+
+        Self {
+            sender,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn send(&self, message: RenderMessage) {
+        if self.sender.send(message).is_err() {
+            warn!("Render thread has already exited.");
+        }
+    }
+
+    /// Asks the render thread to tear down the `App` and waits for it.
+    pub fn join(&mut self) -> Result<()> {
+        match self.handle.take() {
+            Some(handle) => {
+                self.send(RenderMessage::Exit);
+                handle
+                    .join()
+                    .map_err(|_| anyhow!("Render thread panicked."))?
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+unsafe fn render_loop<F>(
+    window: &Window,
+    world: WorldConfig,
+    receiver: Receiver<RenderMessage>,
+    mut on_frame: F,
+) -> Result<()>
+where
+    F: FnMut(&mut App),
+{
+    // Android has no native window to create the surface on until the first
+    // `Resumed`. Work sent before it runs once the app exists.
+    let mut early = vec![];
+    if cfg!(target_os = "android") {
+        loop {
+            match receiver.recv() {
+                Ok(RenderMessage::Resumed) => break,
+                Ok(RenderMessage::Run(f)) => early.push(f),
+                Ok(RenderMessage::Exit) | Err(_) => return Ok(()),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    let mut app = App::create(window, world).unwrap();
+    early.into_iter().for_each(|f| f(&mut app));
+    let mut minimized = false;
+    let mut suspended = false;
+
+    let result = loop {
+        let message = if minimized || suspended {
+            // Nothing to draw, so block until the window changes.
+            receiver.recv().map_err(|_| TryRecvError::Disconnected)
+        } else {
+            receiver.try_recv()
+        };
+
+        match message {
+            Ok(RenderMessage::Resized { width, height }) => {
+                if width == 0 || height == 0 {
+                    minimized = true;
+                } else {
+                    minimized = false;
+                    app.resized = true;
+                }
+                continue;
+            }
+            Ok(RenderMessage::ScaleFactorChanged { scale_factor }) => {
+                app.scale_factor_changed(scale_factor);
+                continue;
+            }
+            Ok(RenderMessage::Suspended) => {
+                // Android may kill suspended apps without another word.
+                if let Err(e) = app.save_settings() {
+                    warn!("Failed to save settings: {}", e);
+                }
+                app.suspend();
+                suspended = true;
+                continue;
+            }
+            Ok(RenderMessage::Resumed) => {
+                if let Err(e) = app.resume(window) {
+                    break Err(e);
+                }
+                suspended = false;
+                continue;
+            }
+            Ok(RenderMessage::Run(f)) => {
+                f(&mut app);
+                continue;
+            }
+            Ok(RenderMessage::Exit) | Err(TryRecvError::Disconnected) => break Ok(()),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        on_frame(&mut app);
+        if let Err(e) = app.render(window) {
+            break Err(e);
+        }
+    };
+
+    if let Err(e) = app.save_settings() {
+        warn!("Failed to save settings: {}", e);
+    }
+
+    app.device.device_wait_idle().unwrap();
+    app.destroy();
+
+    result
+}
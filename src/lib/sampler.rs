@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use log::*;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::app::AppData;
+
+/// How a texture is sampled. Equal descriptions share one `vk::Sampler`.
+#[derive(Copy, Clone, Debug)]
+pub struct SamplerDesc {
+    /// Magnification and minification filter.
+    pub filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Used for all three coordinates.
+    pub wrap: vk::SamplerAddressMode,
+    /// Maximum anisotropy, or `None` to disable anisotropic filtering.
+    /// Clamped to what the device supports.
+    pub anisotropy: Option<f32>,
+    /// Added to the computed mip level. Negative values sharpen. Ignored on
+    /// devices without `PortabilitySubset::sampler_mip_lod_bias`.
+    pub lod_bias: f32,
+    /// Turns the sampler into a comparison sampler for depth textures.
+    pub compare_op: Option<vk::CompareOp>,
+}
+
+impl Default for SamplerDesc {
+    /// Trilinear, repeating and fully anisotropic.
+    fn default() -> Self {
+        Self {
+            filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            wrap: vk::SamplerAddressMode::REPEAT,
+            anisotropy: Some(16.0),
+            lod_bias: 0.0,
+            compare_op: None,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Trilinear without wrapping, for cubemaps and lookup tables.
+    pub fn clamped() -> Self {
+        Self {
+            wrap: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            anisotropy: None,
+            ..Self::default()
+        }
+    }
+
+    /// Unfiltered, for pixel art and data textures.
+    pub fn nearest() -> Self {
+        Self {
+            filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            anisotropy: None,
+            ..Self::default()
+        }
+    }
+
+    /// The fields compared and hashed, with floats by their bits.
+    fn key(&self) -> (vk::Filter, vk::SamplerMipmapMode, vk::SamplerAddressMode, Option<u32>, u32, Option<vk::CompareOp>) {
+        (
+            self.filter,
+            self.mipmap_mode,
+            self.wrap,
+            self.anisotropy.map(f32::to_bits),
+            self.lod_bias.to_bits(),
+            self.compare_op,
+        )
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Creates each distinct sampler once and owns it until `destroy`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SamplerCache {
+    max_anisotropy: f32,
+    lod_bias: bool,
+    samplers: HashMap<SamplerDesc, vk::Sampler>,
+}
+
+impl SamplerCache {
+    pub(crate) unsafe fn new(instance: &Instance, data: &AppData) -> Self {
+        let properties = instance.get_physical_device_properties(data.physical_device);
+        Self {
+            max_anisotropy: properties.limits.max_sampler_anisotropy,
+            lod_bias: data.portability.sampler_mip_lod_bias,
+            samplers: HashMap::new(),
+        }
+    }
+
+    /// Returns the sampler for `desc`, creating it on first use.
+    pub(crate) unsafe fn get(&mut self, device: &Device, desc: SamplerDesc) -> vk::Sampler {
+        if let Some(sampler) = self.samplers.get(&desc) {
+            return *sampler;
+        }
+
+        let anisotropy = desc.anisotropy.map(|a| a.clamp(1.0, self.max_anisotropy));
+        let info = vk::SamplerCreateInfo::builder()
+            .mag_filter(desc.filter)
+            .min_filter(desc.filter)
+            .address_mode_u(desc.wrap)
+            .address_mode_v(desc.wrap)
+            .address_mode_w(desc.wrap)
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0))
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(desc.compare_op.is_some())
+            .compare_op(desc.compare_op.unwrap_or(vk::CompareOp::ALWAYS))
+            .mipmap_mode(desc.mipmap_mode)
+            .mip_lod_bias(if self.lod_bias { desc.lod_bias } else { 0.0 })
+            .min_lod(0.0)
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        let sampler = device.create_sampler(&info, None).unwrap();
+        debug!("Created sampler {:?}.", desc);
+        self.samplers.insert(desc, sampler);
+        sampler
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        self.samplers.drain().for_each(|(_, s)| device.destroy_sampler(s, None));
+    }
+}
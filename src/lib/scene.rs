@@ -0,0 +1,335 @@
+use anyhow::{anyhow, Result};
+use cgmath::Deg;
+use log::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    camera::Camera,
+    entity::{Entity, Mobility},
+    json::Json,
+    render_queue::{DrawOrder, RenderQueue},
+    shadows::Sun,
+    types::{Mat4, Vec3},
+    viewmodel::CameraLayer,
+    world::WorldConfig,
+};
+
+/// Entities a scene can hold, one for each bit of the masks entities are
+/// drawn by.
+pub(crate) const MAX_SCENE_ENTITIES: usize = 64;
+
+/// Seconds between checks of a watched scene file.
+pub(crate) const SCENE_WATCH_INTERVAL: f32 = 0.5;
+
+/// A scene file reloaded whenever it changes, see `App::watch_scene`.
+#[derive(Clone, Debug)]
+pub(crate) struct SceneWatch {
+    pub(crate) path: PathBuf,
+    /// The file's modification time when it was last read.
+    pub(crate) modified: Option<SystemTime>,
+    /// Seconds since `App::start` at the last check.
+    pub(crate) checked_at: f32,
+    /// The scene as last read, for telling what an edit changed.
+    pub(crate) scene: Scene,
+}
+
+impl SceneWatch {
+    /// Reads the scene at `path` to start watching it.
+    pub(crate) fn new(path: &Path, now: f32) -> Result<Self> {
+        let modified = modified(path);
+        let scene = Scene::load(path)?;
+        Ok(Self { path: path.to_path_buf(), modified, checked_at: now, scene })
+    }
+
+    /// The file's new contents if it changed since it was last read. A file
+    /// that fails to parse is reported once and then left until it changes
+    /// again, which covers editors that save in several writes.
+    pub(crate) fn poll(&mut self, now: f32) -> Option<Scene> {
+        if now - self.checked_at < SCENE_WATCH_INTERVAL {
+            return None;
+        }
+        self.checked_at = now;
+
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match Scene::load(&self.path) {
+            Ok(scene) => Some(scene),
+            Err(e) => {
+                warn!("Failed to reload the scene: {}", e);
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A scene as saved to and loaded from a JSON file: the model, the textures
+/// its entities draw, the entities, the sun and the camera. Apply one with
+/// `App::load_scene` and capture the current one with `App::scene`.
+///
+/// Transforms and the camera are in world space, so a scene should be
+/// loaded with the `WorldConfig` it was saved with. Paths in the file are
+/// relative to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    /// An OBJ or glTF model, or the one the app started with.
+    pub model: Option<PathBuf>,
+    /// Textures loaded for the scene. `Entity::texture` indexes these from
+    /// 1, with 0 the scene texture.
+    pub textures: Vec<PathBuf>,
+    /// At most 64.
+    pub entities: Vec<Entity>,
+    pub sun: Option<Sun>,
+    pub camera: Camera,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            model: None,
+            textures: vec![],
+            entities: vec![],
+            sun: None,
+            camera: Camera::initial(&WorldConfig::default()),
+        }
+    }
+}
+
+impl Scene {
+    /// Reads a scene saved with `save`. Missing fields keep their defaults;
+    /// fields of the wrong type are errors.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        Json::parse(&text)
+            .and_then(|json| Self::from_json(&json, directory))
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Fails without writing anything if a transform, the sun or the camera
+    /// is not finite, since the file could not be loaded again.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let json = self.to_json(directory);
+        if !json.is_finite() {
+            return Err(anyhow!("The scene has a NaN or infinite number, which JSON cannot hold."));
+        }
+        fs::create_dir_all(directory)?;
+        fs::write(path, format!("{}\n", json))?;
+        Ok(())
+    }
+
+    pub(crate) fn from_json(json: &Json, directory: &Path) -> Result<Self> {
+        let defaults = Self::default();
+        let path = |json: &Json| json.as_str().map(|p| directory.join(p));
+        let model = match json.get("model") {
+            None | Some(Json::Null) => None,
+            Some(model) => Some(path(model).ok_or_else(|| anyhow!("Invalid `model`."))?),
+        };
+        let textures = list(json, "textures", |t| path(t).ok_or_else(|| anyhow!("Expected a path.")))?;
+        let entities = list(json, "entities", entity_from_json)?;
+        check_entity_count(&entities)?;
+        let sun = match json.get("sun") {
+            None | Some(Json::Null) => None,
+            Some(sun) => Some(Sun {
+                direction: field(sun, "direction", Sun::default().direction, vec3)?,
+                illuminance: field(sun, "illuminance", Sun::default().illuminance, vec3)?,
+            }),
+        };
+        let camera = match json.get("camera") {
+            None => defaults.camera,
+            Some(camera) => Camera {
+                position: field(camera, "position", defaults.camera.position, vec3)?,
+                target: field(camera, "target", defaults.camera.target, vec3)?,
+                up: field(camera, "up", defaults.camera.up, vec3)?,
+                fov: Deg(field(camera, "fov", defaults.camera.fov.0, Json::as_f32)?),
+                near: field(camera, "near", defaults.camera.near, Json::as_f32)?,
+                far: field(camera, "far", defaults.camera.far, Json::as_f32)?,
+                infinite_far: field(camera, "infinite_far", defaults.camera.infinite_far, Json::as_bool)?,
+            },
+        };
+        Ok(Self { model, textures, entities, sun, camera })
+    }
+
+    pub(crate) fn to_json(&self, directory: &Path) -> Json {
+        let path = |p: &Path| Json::String(p.strip_prefix(directory).unwrap_or(p).to_string_lossy().into_owned());
+        let sun = self.sun.map_or(Json::Null, |sun| {
+            Json::Object(vec![
+                ("direction".into(), vec3_json(sun.direction)),
+                ("illuminance".into(), vec3_json(sun.illuminance)),
+            ])
+        });
+        let camera = Json::Object(vec![
+            ("position".into(), vec3_json(self.camera.position)),
+            ("target".into(), vec3_json(self.camera.target)),
+            ("up".into(), vec3_json(self.camera.up)),
+            ("fov".into(), Json::from(self.camera.fov.0)),
+            ("near".into(), Json::from(self.camera.near)),
+            ("far".into(), Json::from(self.camera.far)),
+            ("infinite_far".into(), Json::Bool(self.camera.infinite_far)),
+        ]);
+        Json::Object(vec![
+            ("model".into(), self.model.as_deref().map_or(Json::Null, path)),
+            ("textures".into(), Json::Array(self.textures.iter().map(|p| path(p)).collect())),
+            ("entities".into(), Json::Array(self.entities.iter().map(entity_to_json).collect())),
+            ("sun".into(), sun),
+            ("camera".into(), camera),
+        ])
+    }
+}
+
+fn entity_from_json(json: &Json) -> Result<Entity> {
+    let defaults = Entity::default();
+    let transform = |json: &Json| {
+        let values = json.as_array()?.iter().map(Json::as_f32).collect::<Option<Vec<_>>>()?;
+        let columns: &[f32; 16] = values.as_slice().try_into().ok()?;
+        Some(*<&Mat4>::from(columns))
+    };
+    let mobility = |json: &Json| match json.as_str()? {
+        "static" => Some(Mobility::Static),
+        "dynamic" => Some(Mobility::Dynamic),
+        _ => None,
+    };
+    let layer = |json: &Json| match json.as_str()? {
+        "world" => Some(CameraLayer::World),
+        "viewmodel" => Some(CameraLayer::Viewmodel),
+        _ => None,
+    };
+    let pose = |json: &Json| match json {
+        Json::Null => Some(None),
+        json => index(json).map(Some),
+    };
+    Ok(Entity {
+        transform: field(json, "transform", defaults.transform, transform)?,
+        mobility: field(json, "mobility", defaults.mobility, mobility)?,
+        opacity: field(json, "opacity", defaults.opacity, Json::as_f32)?,
+        texture: field(json, "texture", defaults.texture, |j| u32::try_from(index(j)?).ok())?,
+        order: DrawOrder {
+            queue: RenderQueue(field(json, "queue", defaults.order.queue.0, |j| u16::try_from(index(j)?).ok())?),
+            sort_key: field(json, "sort_key", defaults.order.sort_key, |j| u16::try_from(index(j)?).ok())?,
+        },
+        layer: field(json, "layer", defaults.layer, layer)?,
+        pose: field(json, "pose", defaults.pose, pose)?,
+        mesh: None,
+        material: None,
+    })
+}
+
+fn entity_to_json(entity: &Entity) -> Json {
+    let transform: &[f32; 16] = entity.transform.as_ref();
+    Json::Object(vec![
+        ("transform".into(), Json::numbers(transform.iter().copied())),
+        (
+            "mobility".into(),
+            Json::String(if entity.is_static() { "static" } else { "dynamic" }.into()),
+        ),
+        ("opacity".into(), Json::from(entity.opacity)),
+        ("texture".into(), Json::Number(entity.texture as f64)),
+        ("queue".into(), Json::Number(entity.order.queue.0 as f64)),
+        ("sort_key".into(), Json::Number(entity.order.sort_key as f64)),
+        (
+            "layer".into(),
+            Json::String(match entity.layer {
+                CameraLayer::World => "world".into(),
+                CameraLayer::Viewmodel => "viewmodel".into(),
+            }),
+        ),
+        ("pose".into(), entity.pose.map_or(Json::Null, |p| Json::Number(p as f64))),
+    ])
+}
+
+pub(crate) fn check_entity_count(entities: &[Entity]) -> Result<()> {
+    if entities.len() > MAX_SCENE_ENTITIES {
+        return Err(anyhow!("{} entities, more than the {} a scene can hold.", entities.len(), MAX_SCENE_ENTITIES));
+    }
+    Ok(())
+}
+
+/// The value of `key` in `json`, `default` if it is missing, or an error if
+/// `parse` rejects it.
+fn field<T>(json: &Json, key: &str, default: T, parse: impl Fn(&Json) -> Option<T>) -> Result<T> {
+    match json.get(key) {
+        Some(value) => parse(value).ok_or_else(|| anyhow!("Invalid `{}`.", key)),
+        None => Ok(default),
+    }
+}
+
+/// Each element of the array at `key`, or none if it is missing.
+fn list<T>(json: &Json, key: &str, parse: impl Fn(&Json) -> Result<T>) -> Result<Vec<T>> {
+    let Some(values) = json.get(key) else {
+        return Ok(vec![]);
+    };
+    let values = values.as_array().ok_or_else(|| anyhow!("Invalid `{}`.", key))?;
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| parse(v).map_err(|e| anyhow!("`{}[{}]`: {}", key, i, e)))
+        .collect()
+}
+
+fn index(json: &Json) -> Option<usize> {
+    match json {
+        Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+        _ => None,
+    }
+}
+
+fn vec3(json: &Json) -> Option<Vec3> {
+    match json.as_array()? {
+        [x, y, z] => Some(Vec3::new(x.as_f32()?, y.as_f32()?, z.as_f32()?)),
+        _ => None,
+    }
+}
+
+fn vec3_json(v: Vec3) -> Json {
+    Json::numbers([v.x, v.y, v.z])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_entities(count: usize) -> Result<Scene> {
+        let entities = vec!["{}"; count].join(", ");
+        let path = std::env::temp_dir().join(format!("ozen-athena-scene-{}-{}.json", std::process::id(), count));
+        fs::write(&path, format!("{{\"entities\": [{}]}}", entities)).unwrap();
+        let scene = Scene::load(&path);
+        fs::remove_file(&path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn load_rejects_more_entities_than_masks_hold() {
+        assert_eq!(load_entities(MAX_SCENE_ENTITIES).unwrap().entities.len(), MAX_SCENE_ENTITIES);
+        let error = load_entities(MAX_SCENE_ENTITIES + 1).unwrap_err().to_string();
+        assert!(error.contains("65 entities"), "{}", error);
+    }
+
+    #[test]
+    fn save_rejects_non_finite_numbers() {
+        let path = std::env::temp_dir().join(format!("ozen-athena-scene-{}-nan.json", std::process::id()));
+        let mut scene = Scene::default();
+        scene.camera.fov = Deg(f32::NAN);
+        assert!(scene.save(&path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn entity_fields_out_of_range_are_invalid() {
+        for (key, value) in [("queue", 70000.0), ("sort_key", 70000.0), ("texture", 1e10)] {
+            let json = Json::parse(&format!("{{\"{}\": {}}}", key, value)).unwrap();
+            let error = entity_from_json(&json).unwrap_err().to_string();
+            assert_eq!(error, format!("Invalid `{}`.", key));
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use cgmath::SquareMatrix;
+use std::{mem::size_of, thread};
+
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::KhrPushDescriptorExtension,
+};
+
+use crate::{
+    app::AppData,
+    assets::{entity_buffers, entity_lod, entity_mesh, entity_vertex_offset},
+    debug_view::DebugView,
+    depth_object::far_depth,
+    dynamic_rendering::{begin_secondary, Pass},
+    entity::Entity,
+    light_probe::LightProbes,
+    material::Shading,
+    meshlet::record_meshlet_draw,
+    pipeline::FragmentPushConstants,
+    texture_atlas::resolve_texture,
+    types::{Mat4, Vec3},
+    uniform_buffer::UniformBufferObject,
+    viewmodel::CameraLayer,
+    viewport::{record_viewports, uniform_stride, viewports},
+};
+
+/// Most threads secondary command buffers are recorded on, counting the
+/// render thread.
+pub(crate) const MAX_RECORDING_THREADS: usize = 4;
+
+/// Fewer draws than this are not worth waking another thread for.
+const MIN_DRAWS_PER_THREAD: usize = 8;
+
+/// What recording scene entities needs, borrowed from `App` so that their
+/// secondary command buffers can be recorded on several threads at once.
+pub(crate) struct SceneRecorder<'a> {
+    pub(crate) device: &'a Device,
+    pub(crate) data: &'a AppData,
+    pub(crate) entities: &'a [Entity],
+    pub(crate) light_probes: &'a LightProbes,
+    pub(crate) pipeline: vk::Pipeline,
+    /// Draws entities in transparent render queues.
+    pub(crate) transparent_pipeline: vk::Pipeline,
+    /// Entities whose depth the pre-pass already laid down, drawn with
+    /// `data.depth_equal_pipeline` instead of `pipeline`.
+    pub(crate) prepassed: u64,
+    pub(crate) debug_view: DebugView,
+    /// Brings viewmodel transforms into world space, so the shader only has
+    /// to swap the projection.
+    pub(crate) inverse_view: Mat4,
+    /// Where each entity's joint matrices start, see `App::update_skinning`.
+    pub(crate) joint_offsets: &'a [u32],
+    /// Each entity's level of detail, see `App::update_lods`. Meshlets are
+    /// always of the full mesh.
+    pub(crate) lods: &'a [usize],
+    /// Baked static batches drawn ahead of the entities, with `pipeline`.
+    /// Indices into `data.static_batches.batches`.
+    pub(crate) batches: &'a [usize],
+    /// Draws world layer entities as meshlets with `data.mesh_pipeline`.
+    pub(crate) mesh_shading: bool,
+    /// Draws entities with a material with the pipelines of its shading,
+    /// see `Materials::pipelines`, rather than `pipeline` and
+    /// `transparent_pipeline`. Their parameters are read either way.
+    pub(crate) materials: bool,
+    pub(crate) image_index: usize,
+}
+
+impl SceneRecorder<'_> {
+    /// Records `draws`, each an entity and whether depth is cleared before
+    /// it, into a secondary command buffer apiece and returns them in the
+    /// same order.
+    ///
+    /// Command pools cannot be used from two threads at once, so thread `t`
+    /// records draws `t`, `t + threads`, ... into buffers from
+    /// `data.recording_pools[image_index][t]`. `command_buffers` holds the
+    /// buffers allocated from each of those pools so far and grows to fit.
+    pub(crate) unsafe fn record_parallel(
+        &self,
+        draws: &[(usize, bool)],
+        command_buffers: &mut [Vec<vk::CommandBuffer>],
+    ) -> Vec<vk::CommandBuffer> {
+        let threads = draws
+            .len()
+            .div_ceil(MIN_DRAWS_PER_THREAD)
+            .clamp(1, command_buffers.len());
+
+        for (thread, buffers) in command_buffers.iter_mut().enumerate().take(threads) {
+            let needed = (draws.len() + threads - 1 - thread) / threads;
+            if buffers.len() < needed {
+                let allocate_info = vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(self.data.recording_pools[self.image_index][thread])
+                    .level(vk::CommandBufferLevel::SECONDARY)
+                    .command_buffer_count((needed - buffers.len()) as u32);
+                buffers.extend(self.device.allocate_command_buffers(&allocate_info).unwrap());
+            }
+        }
+
+        let record = |thread: usize, buffers: &[vk::CommandBuffer]| {
+            for (slot, (entity, clear_depth)) in draws.iter().skip(thread).step_by(threads).enumerate() {
+                self.record(buffers[slot], &[*entity], *clear_depth);
+            }
+        };
+
+        thread::scope(|scope| {
+            for (thread, buffers) in command_buffers.iter().enumerate().take(threads).skip(1) {
+                let record = &record;
+                scope.spawn(move || record(thread, buffers));
+            }
+            record(0, &command_buffers[0]);
+        });
+
+        (0..draws.len())
+            .map(|i| command_buffers[i % threads][i / threads])
+            .collect()
+    }
+
+    /// Records `entities`, in order, into one secondary command buffer.
+    pub(crate) unsafe fn record(&self, command_buffer: vk::CommandBuffer, entities: &[usize], clear_depth: bool) {
+        let device = self.device;
+        let data = self.data;
+        begin_secondary(device, data, command_buffer, Pass::Scene, self.image_index).unwrap();
+
+        record_viewports(device, data, command_buffer, |viewport| {
+            if clear_depth {
+                let attachment = vk::ClearAttachment::builder()
+                    .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                    .clear_value(vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue {
+                            depth: far_depth(data.reverse_z),
+                            stencil: 0,
+                        },
+                    });
+
+                // Only this viewport's, the others are drawn separately.
+                let rect = vk::ClearRect::builder()
+                    .rect(viewports(data)[viewport].rect(data.swapchain_extent))
+                    .base_array_layer(0)
+                    .layer_count(1);
+
+                device.cmd_clear_attachments(command_buffer, &[attachment], &[rect]);
+            }
+
+            if !self.batches.is_empty() {
+                self.record_batches(command_buffer, viewport);
+            }
+            self.record_draws(command_buffer, entities, viewport);
+        });
+
+        device.end_command_buffer(command_buffer).unwrap();
+    }
+
+    /// Records `entities`, in order, into a command buffer that is already
+    /// inside a pass compatible with `pipeline`, seen from `viewport`.
+    pub(crate) unsafe fn record_draws(&self, command_buffer: vk::CommandBuffer, entities: &[usize], viewport: usize) {
+        let device = self.device;
+        let data = self.data;
+
+        // The two pipelines' layouts are not compatible, so switching
+        // between them rebinds everything.
+        let bind = |mesh: bool, pipeline: vk::Pipeline, (vertex_buffer, index_buffer, index_type)| {
+            if mesh {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, data.mesh_pipeline);
+                bind_scene_descriptors_with(
+                    device,
+                    data,
+                    data.mesh_pipeline_layout,
+                    command_buffer,
+                    self.image_index,
+                    viewport,
+                );
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    data.mesh_pipeline_layout,
+                    1,
+                    &[data.texture_descriptor_set, data.meshlet_descriptor_set],
+                    &[],
+                );
+                return;
+            }
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, index_type);
+            bind_scene_descriptors(device, data, command_buffer, self.image_index, viewport);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                data.pipeline_layout,
+                1,
+                &[data.texture_descriptor_set],
+                &[],
+            );
+        };
+        let mut bound = None;
+
+        for (index, entity) in entities.iter().map(|i| (*i, &self.entities[*i])) {
+            let transparent = entity.order.queue.is_transparent();
+            let shading = data.materials.shading(entity.material);
+            // Meshlets are only built for the scene model, and only drawn
+            // lit.
+            let mesh = self.mesh_shading
+                && entity.layer == CameraLayer::World
+                && !transparent
+                && shading == Shading::Lit
+                && entity_mesh(data, entity).is_none();
+            let material_pipelines = data.materials.pipelines.get(&shading).filter(|_| self.materials);
+            let pipeline = if let Some((shaded, transparent_shaded)) = material_pipelines {
+                if transparent { *transparent_shaded } else { *shaded }
+            } else if transparent {
+                self.transparent_pipeline
+            } else if self.prepassed & (1 << index) != 0 {
+                data.depth_equal_pipeline.pipeline
+            } else {
+                self.pipeline
+            };
+            let buffers = entity_buffers(data, entity);
+            if bound != Some((mesh, pipeline, buffers)) {
+                if !mesh && bound.is_some_and(|(m, _, b)| !m && b == buffers) {
+                    // The scene pipelines share a layout, so everything but
+                    // the pipeline stays bound.
+                    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                } else {
+                    bind(mesh, pipeline, buffers);
+                }
+                bound = Some((mesh, pipeline, buffers));
+            }
+            let (layout, model_stages) = if mesh {
+                (data.mesh_pipeline_layout, vk::ShaderStageFlags::TASK_EXT | vk::ShaderStageFlags::MESH_EXT)
+            } else {
+                (data.pipeline_layout, vk::ShaderStageFlags::VERTEX)
+            };
+
+            let model = match entity.layer {
+                CameraLayer::World => entity.transform,
+                CameraLayer::Viewmodel => self.inverse_view * entity.transform,
+            };
+            self.push_constants(
+                command_buffer,
+                layout,
+                model_stages,
+                &model,
+                model.w.truncate(),
+                entity.texture,
+                data.materials.block(entity.material),
+                entity.opacity,
+            );
+            if mesh {
+                record_meshlet_draw(device, data, command_buffer);
+                continue;
+            }
+            let lod = entity_lod(data, entity, self.lods.get(index).copied().unwrap_or(0));
+            device.cmd_draw_indexed(
+                command_buffer,
+                lod.index_count,
+                1,
+                lod.first_index,
+                entity_vertex_offset(data, entity),
+                entity.layer as u32 | self.joint_offsets.get(index).copied().unwrap_or(0) << 1,
+            );
+        }
+    }
+
+    /// Records `self.batches`, one draw apiece of the baked geometry in
+    /// `data.static_batches`, seen from `viewport`.
+    unsafe fn record_batches(&self, command_buffer: vk::CommandBuffer, viewport: usize) {
+        let device = self.device;
+        let data = self.data;
+        let baked = &data.static_batches;
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[baked.vertex_buffer], &[0]);
+        device.cmd_bind_index_buffer(command_buffer, baked.index_buffer, 0, baked.index_type);
+        bind_scene_descriptors(device, data, command_buffer, self.image_index, viewport);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            data.pipeline_layout,
+            1,
+            &[data.texture_descriptor_set],
+            &[],
+        );
+
+        for batch in self.batches.iter().map(|b| &baked.batches[*b]) {
+            // Already in world space.
+            self.push_constants(
+                command_buffer,
+                data.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                &Mat4::identity(),
+                batch.bounds.center,
+                batch.texture,
+                0,
+                batch.opacity,
+            );
+            // Joint offset 0, the rest pose. Skinned models are never baked.
+            device.cmd_draw_indexed(
+                command_buffer,
+                batch.index_count,
+                1,
+                batch.first_index,
+                0,
+                CameraLayer::World as u32,
+            );
+        }
+    }
+
+    /// Pushes the model matrix and the fragment push constants of a draw,
+    /// lit by the light probes at `position` and reading `material`'s block
+    /// of the material buffer.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn push_constants(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        model_stages: vk::ShaderStageFlags,
+        model: &Mat4,
+        position: Vec3,
+        texture: u32,
+        material: u32,
+        opacity: f32,
+    ) {
+        let fragment_push_constants = FragmentPushConstants {
+            opacity,
+            debug_view: self.debug_view as u32,
+            texture: resolve_texture(self.data, texture),
+            material,
+            ambient: self.light_probes.sample(position).irradiance(),
+        };
+        let data = self.data;
+        data.model_push_constants.with_stages(model_stages).push(self.device, command_buffer, layout, model);
+        data.fragment_push_constants.push(self.device, command_buffer, layout, &fragment_push_constants);
+    }
+}
+
+/// Binds the uniform and joint buffers for `image_index` to set 0 of the
+/// scene pipeline layout, pushed when `VK_KHR_push_descriptor` is available.
+/// The uniform buffer is `viewport`'s slot, see `viewport.rs`.
+pub(crate) unsafe fn bind_scene_descriptors(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    viewport: usize,
+) {
+    bind_scene_descriptors_with(device, data, data.pipeline_layout, command_buffer, image_index, viewport);
+}
+
+/// Like `bind_scene_descriptors`, for a layout that shares set 0 with the
+/// scene layout but is not otherwise compatible with it.
+pub(crate) unsafe fn bind_scene_descriptors_with(
+    device: &Device,
+    data: &AppData,
+    layout: vk::PipelineLayout,
+    command_buffer: vk::CommandBuffer,
+    image_index: usize,
+    viewport: usize,
+) {
+    let offset = uniform_stride(data) * viewport as u64;
+    if !data.push_descriptors {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            layout,
+            0,
+            &[data.descriptor_sets[image_index]],
+            &[offset as u32],
+        );
+        return;
+    }
+
+    let info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.uniform_buffers[image_index])
+        .offset(offset)
+        .range(size_of::<UniformBufferObject>() as u64);
+
+    let buffer_info = &[info];
+    let ubo_write = vk::WriteDescriptorSet::builder()
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+        .buffer_info(buffer_info);
+
+    let info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.joint_buffers[image_index])
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64);
+
+    let joint_info = &[info];
+    let joint_write = vk::WriteDescriptorSet::builder()
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(joint_info);
+
+    let shadow_mask_info = &[data.shadow_masks[image_index].descriptor_info()];
+    let shadow_mask_write = vk::WriteDescriptorSet::builder()
+        .dst_binding(2)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(shadow_mask_info);
+
+    let occlusion_info = &[data.occlusion_targets[image_index].descriptor_info()];
+    let occlusion_write = vk::WriteDescriptorSet::builder()
+        .dst_binding(3)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(occlusion_info);
+
+    let info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.material_buffers[image_index])
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64);
+
+    let material_info = &[info];
+    let material_write = vk::WriteDescriptorSet::builder()
+        .dst_binding(4)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(material_info);
+
+    device.cmd_push_descriptor_set_khr(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        layout,
+        0,
+        &[ubo_write, joint_write, shadow_mask_write, occlusion_write, material_write],
+    );
+}
@@ -0,0 +1,404 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use log::*;
+use winit::window::{Window, WindowId};
+
+use vulkanalia::{
+    prelude::v1_0::*,
+    vk::{KhrSurfaceExtension, KhrSwapchainExtension},
+    window as vk_window,
+};
+
+use crate::{
+    app::AppData,
+    physical_device::QueueFamilyIndices,
+    swapchain::{
+        get_swapchain_extent, get_swapchain_present_mode, get_swapchain_surface_format, SwapchainColorSpace,
+        SwapchainSupport,
+    },
+    sync_objects::wait_for_frame,
+    texture::format_supports,
+};
+
+/// Another window onto the scene, for tools such as inspectors. Shares the
+/// device and everything drawn with the main window, and shows its final
+/// image scaled to fit. Only the surface, swapchain and the semaphores for
+/// presenting to it are its own.
+#[derive(Debug)]
+pub(crate) struct SecondaryWindow {
+    window: Arc<Window>,
+    surface: vk::SurfaceKHR,
+    /// Null while the window is minimized.
+    swapchain: vk::SwapchainKHR,
+    extent: vk::Extent2D,
+    images: Vec<vk::Image>,
+    /// Whether the main image can be blitted in. The window is only cleared
+    /// otherwise.
+    blit: bool,
+    /// One per frame in flight, as `AppData::image_available_semaphore`.
+    acquire_semaphores: Vec<vk::Semaphore>,
+    /// One per image, as `AppData::render_finished_semaphore`.
+    present_semaphores: Vec<vk::Semaphore>,
+    /// The frame that last drew to each image.
+    image_frames: Vec<u64>,
+    /// The image acquired for the frame being drawn, if any.
+    acquired: Option<(usize, vk::Semaphore)>,
+    pub(crate) resized: bool,
+}
+
+impl SecondaryWindow {
+    /// Creates a surface and swapchain for `window`, which has to be
+    /// presentable from the main window's present queue.
+    pub(crate) unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        window: Arc<Window>,
+    ) -> Result<Self> {
+        let surface = vk_window::create_surface(instance, &*window, &*window).unwrap();
+        let indices = QueueFamilyIndices::get(instance, data, data.physical_device).unwrap();
+        if !instance
+            .get_physical_device_surface_support_khr(data.physical_device, indices.present, surface)
+            .unwrap()
+        {
+            instance.destroy_surface_khr(surface, None);
+            return Err(anyhow!("The present queue cannot present to this window."));
+        }
+
+        let mut secondary = Self {
+            window,
+            surface,
+            swapchain: vk::SwapchainKHR::null(),
+            extent: vk::Extent2D::default(),
+            images: vec![],
+            blit: false,
+            acquire_semaphores: vec![],
+            present_semaphores: vec![],
+            image_frames: vec![],
+            acquired: None,
+            resized: false,
+        };
+        secondary.create_acquire_semaphores(device, data);
+        secondary.create_swapchain(instance, device, data).unwrap();
+        Ok(secondary)
+    }
+
+    pub(crate) fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    unsafe fn create_swapchain(&mut self, instance: &Instance, device: &Device, data: &AppData) -> Result<()> {
+        let support = SwapchainSupport::for_surface(instance, data.physical_device, self.surface).unwrap();
+        self.extent = get_swapchain_extent(&self.window, support.capabilities);
+        if self.extent.width == 0 || self.extent.height == 0 {
+            return Ok(());
+        }
+
+        let surface_format =
+            get_swapchain_surface_format(&support.formats, data.srgb_swapchain, SwapchainColorSpace::Srgb);
+        self.blit = data.metering_supported
+            && format_supports(instance, data, surface_format.format, vk::FormatFeatureFlags::BLIT_DST);
+
+        let mut image_count = support.capabilities.min_image_count + 1;
+        if support.capabilities.max_image_count != 0 && image_count > support.capabilities.max_image_count {
+            image_count = support.capabilities.max_image_count;
+        }
+
+        // Only ever drawn to and presented from the present queue's family,
+        // which `create` checked.
+        let info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(self.surface)
+            .min_image_count(image_count)
+            .image_format(surface_format.format)
+            .image_color_space(surface_format.color_space)
+            .image_extent(self.extent)
+            .image_array_layers(1)
+            .image_usage(vk::ImageUsageFlags::TRANSFER_DST)
+            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .pre_transform(support.capabilities.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(get_swapchain_present_mode(&support.present_modes))
+            .clipped(true)
+            .old_swapchain(vk::SwapchainKHR::null());
+
+        self.swapchain = device.create_swapchain_khr(&info, None).unwrap();
+        self.images = device.get_swapchain_images_khr(self.swapchain).unwrap();
+
+        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+        self.present_semaphores = self
+            .images
+            .iter()
+            .map(|_| device.create_semaphore(&semaphore_info, None))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        self.image_frames = vec![0; self.images.len()];
+        Ok(())
+    }
+
+    unsafe fn destroy_swapchain(&mut self, device: &Device) {
+        self.present_semaphores
+            .drain(..)
+            .for_each(|s| device.destroy_semaphore(s, None));
+        device.destroy_swapchain_khr(self.swapchain, None);
+        self.swapchain = vk::SwapchainKHR::null();
+        self.images.clear();
+        self.image_frames.clear();
+    }
+
+    /// Replaces the swapchain, after the device is idle.
+    pub(crate) unsafe fn recreate_swapchain(&mut self, instance: &Instance, device: &Device, data: &AppData) {
+        self.destroy_swapchain(device);
+        self.create_swapchain(instance, device, data).unwrap();
+        self.resized = false;
+    }
+
+    /// Creates an acquire semaphore for each of `data.frames_in_flight`,
+    /// replacing any there were, after the device is idle.
+    pub(crate) unsafe fn create_acquire_semaphores(&mut self, device: &Device, data: &AppData) {
+        self.acquire_semaphores
+            .drain(..)
+            .for_each(|s| device.destroy_semaphore(s, None));
+
+        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+        self.acquire_semaphores = (0..data.frames_in_flight)
+            .map(|_| device.create_semaphore(&semaphore_info, None))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+    }
+
+    /// Acquires an image for frame `frame_number`, using the acquire
+    /// semaphore of frame in flight `frame`. Skips the frame when the window
+    /// is minimized or its swapchain has to be recreated, which then waits
+    /// for the device.
+    pub(crate) unsafe fn acquire(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        frame: usize,
+        frame_number: u64,
+    ) -> Result<()> {
+        self.acquired = None;
+        if self.resized {
+            device.device_wait_idle().unwrap();
+            self.recreate_swapchain(instance, device, data);
+        }
+        if self.swapchain.is_null() {
+            return Ok(());
+        }
+
+        let semaphore = self.acquire_semaphores[frame];
+        let result = device.acquire_next_image_khr(self.swapchain, u64::MAX, semaphore, vk::Fence::null());
+        let image_index = match result {
+            Ok((image_index, _)) => image_index as usize,
+            Err(vk::ErrorCode::OUT_OF_DATE_KHR) => {
+                self.resized = true;
+                return Ok(());
+            }
+            Err(e) => return Err(anyhow!(e)),
+        };
+
+        wait_for_frame(device, data, self.image_frames[image_index]).unwrap();
+        self.image_frames[image_index] = frame_number;
+        self.acquired = Some((image_index, semaphore));
+        Ok(())
+    }
+
+    /// The acquire semaphore to wait on and the present semaphore to signal,
+    /// if an image was acquired.
+    pub(crate) fn semaphores(&self) -> Option<(vk::Semaphore, vk::Semaphore)> {
+        self.acquired
+            .map(|(image_index, acquire)| (acquire, self.present_semaphores[image_index]))
+    }
+
+    /// Records scaling `source`, the main window's final image, into the
+    /// acquired image, keeping its aspect ratio. Expects `source` ready to
+    /// present and leaves it so.
+    pub(crate) unsafe fn record(&self, device: &Device, data: &AppData, command_buffer: vk::CommandBuffer, source: vk::Image) {
+        let Some((image_index, _)) = self.acquired else {
+            return;
+        };
+        let image = self.images[image_index];
+
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let source_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(source)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+        let destination_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+
+        let barriers = if self.blit {
+            vec![source_barrier, destination_barrier]
+        } else {
+            vec![destination_barrier]
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &barriers,
+        );
+
+        // The bars either side of the fitted image.
+        let clear = vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 1.0],
+        };
+        device.cmd_clear_color_image(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &clear,
+            &[subresource],
+        );
+
+        if self.blit {
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[] as &[vk::BufferMemoryBarrier],
+                &[] as &[vk::ImageMemoryBarrier],
+            );
+
+            let layers = vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(1);
+
+            let from = data.swapchain_extent;
+            let scale = (self.extent.width as f32 / from.width as f32)
+                .min(self.extent.height as f32 / from.height as f32);
+            let width = ((from.width as f32 * scale) as i32).max(1);
+            let height = ((from.height as f32 * scale) as i32).max(1);
+            let x = (self.extent.width as i32 - width) / 2;
+            let y = (self.extent.height as i32 - height) / 2;
+
+            let blit = vk::ImageBlit::builder()
+                .src_offsets([
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: from.width as i32,
+                        y: from.height as i32,
+                        z: 1,
+                    },
+                ])
+                .src_subresource(layers)
+                .dst_offsets([
+                    vk::Offset3D { x, y, z: 0 },
+                    vk::Offset3D {
+                        x: x + width,
+                        y: y + height,
+                        z: 1,
+                    },
+                ])
+                .dst_subresource(layers);
+
+            device.cmd_blit_image(
+                command_buffer,
+                source,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+        }
+
+        let source_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(source)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .dst_access_mask(vk::AccessFlags::empty());
+
+        let destination_barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::empty());
+
+        let barriers = if self.blit {
+            vec![source_barrier, destination_barrier]
+        } else {
+            vec![destination_barrier]
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &barriers,
+        );
+    }
+
+    /// Presents the acquired image, once the frame drawing it is submitted.
+    pub(crate) unsafe fn present(&mut self, device: &Device, data: &AppData) -> Result<()> {
+        let Some((image_index, _)) = self.acquired.take() else {
+            return Ok(());
+        };
+
+        let wait_semaphores = &[self.present_semaphores[image_index]];
+        let swapchains = &[self.swapchain];
+        let image_indices = &[image_index as u32];
+        let info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(wait_semaphores)
+            .swapchains(swapchains)
+            .image_indices(image_indices);
+
+        match device.queue_present_khr(data.present_queue, &info) {
+            Ok(vk::SuccessCode::SUBOPTIMAL_KHR) | Err(vk::ErrorCode::OUT_OF_DATE_KHR) => self.resized = true,
+            Ok(_) => {}
+            Err(e) => return Err(anyhow!(e)),
+        }
+        Ok(())
+    }
+
+    /// Destroys the window's Vulkan objects, after the device is idle.
+    pub(crate) unsafe fn destroy(&mut self, instance: &Instance, device: &Device) {
+        self.destroy_swapchain(device);
+        self.acquire_semaphores
+            .drain(..)
+            .for_each(|s| device.destroy_semaphore(s, None));
+        instance.destroy_surface_khr(self.surface, None);
+        info!("Closed window {:?}.", self.window.id());
+    }
+}
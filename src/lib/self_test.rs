@@ -0,0 +1,603 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fmt,
+    mem::size_of,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr::copy_nonoverlapping as memcpy,
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    image::{copy_buffer_to_image, create_image, create_image_view, transition_image_layout},
+    reduction::create_reduction,
+    sampler::SamplerDesc,
+    shader::create_shader_module,
+    single_time_cmd::{begin_single_time_commands, end_single_time_commands},
+    vertex_buffer::{copy_buffer, create_buffer},
+};
+
+/// The format of the self-test's textures and render targets, which every
+/// device supports for sampling and color attachments.
+const FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+/// Side length of the self-test's textures and render targets.
+const SIZE: u32 = 2;
+
+/// Elements summed by the compute check, enough for two reduction levels.
+const COMPUTE_ELEMENTS: u32 = 1000;
+
+/// The outcome of one capability checked by `App::self_test`.
+#[derive(Clone, Debug)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    /// Why the check failed, if it did.
+    pub error: Option<String>,
+}
+
+/// What `App::self_test` found on the current device.
+#[derive(Clone, Debug, Default)]
+pub struct SelfTestReport {
+    pub device: String,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.error.is_none())
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Self-test on `{}`:", self.device)?;
+        for check in &self.checks {
+            match &check.error {
+                None => writeln!(f, "  PASS  {}", check.name)?,
+                Some(error) => writeln!(f, "  FAIL  {}: {}", check.name, error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+type Check = unsafe fn(&Instance, &Device, &mut AppData) -> Result<()>;
+
+/// Runs every check on the device's graphics queue, waiting for each to
+/// finish. A check that panics, as most Vulkan errors here do, fails without
+/// stopping the others.
+pub(crate) unsafe fn run_self_test(instance: &Instance, device: &Device, data: &mut AppData) -> SelfTestReport {
+    let checks: [(&'static str, Check); 5] = [
+        ("readback", check_readback),
+        ("buffer upload", check_buffer_upload),
+        ("compute dispatch", check_compute_dispatch),
+        ("render to texture", check_render_to_texture),
+        ("texture sampling", check_texture_sampling),
+    ];
+
+    let properties = instance.get_physical_device_properties(data.physical_device);
+    let mut report = SelfTestReport {
+        device: properties.device_name.to_string(),
+        checks: vec![],
+    };
+
+    for (name, check) in checks {
+        let result = catch_unwind(AssertUnwindSafe(|| check(instance, device, data)));
+        let error = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(panic) => Some(
+                panic
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or_else(|| "panicked".into()),
+            ),
+        };
+        report.checks.push(SelfTestCheck { name, error });
+    }
+
+    report
+}
+
+/// A host-visible buffer the device copies results into.
+struct Readback {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: usize,
+}
+
+impl Readback {
+    unsafe fn create(instance: &Instance, device: &Device, data: &AppData, size: usize) -> Result<Self> {
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size as u64,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        Ok(Self { buffer, memory, size })
+    }
+
+    unsafe fn read(&self, device: &Device) -> Result<Vec<u8>> {
+        let memory = device.map_memory(self.memory, 0, self.size as u64, vk::MemoryMapFlags::empty())?;
+        let mut bytes = vec![0u8; self.size];
+        memcpy(memory.cast(), bytes.as_mut_ptr(), self.size);
+        device.unmap_memory(self.memory);
+        Ok(bytes)
+    }
+
+    unsafe fn destroy(&self, device: &Device) {
+        device.destroy_buffer(self.buffer, None);
+        free_memory(device, self.memory);
+    }
+}
+
+/// A host-visible buffer holding `bytes`, for uploads.
+unsafe fn create_staging_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    bytes: &[u8],
+) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+    let (buffer, memory) = create_buffer(
+        instance,
+        device,
+        data,
+        bytes.len() as u64,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    let mapped = device.map_memory(memory, 0, bytes.len() as u64, vk::MemoryMapFlags::empty())?;
+    memcpy(bytes.as_ptr(), mapped.cast(), bytes.len());
+    device.unmap_memory(memory);
+    Ok((buffer, memory))
+}
+
+/// The device fills a buffer the host then reads.
+unsafe fn check_readback(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let readback = Readback::create(instance, device, data, 256)?;
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+    device.cmd_fill_buffer(command_buffer, readback.buffer, 0, vk::WHOLE_SIZE as u64, 0xA5A5_A5A5);
+    end_single_time_commands(device, data, command_buffer)?;
+
+    let bytes = readback.read(device)?;
+    readback.destroy(device);
+
+    match bytes.iter().position(|b| *b != 0xA5) {
+        Some(i) => Err(anyhow!("Byte {} read back as {:#04x}, expected 0xa5.", i, bytes[i])),
+        None => Ok(()),
+    }
+}
+
+/// Data round-trips through a device-local buffer.
+unsafe fn check_buffer_upload(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let expected = (0..1024u32).flat_map(|i| i.wrapping_mul(2654435761).to_ne_bytes()).collect::<Vec<_>>();
+    let size = expected.len() as u64;
+
+    let (staging_buffer, staging_buffer_memory) = create_staging_buffer(instance, device, data, &expected)?;
+    let (buffer, buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let readback = Readback::create(instance, device, data, expected.len())?;
+
+    copy_buffer(device, data, staging_buffer, buffer, size)?;
+    copy_buffer(device, data, buffer, readback.buffer, size)?;
+    let bytes = readback.read(device)?;
+
+    readback.destroy(device);
+    device.destroy_buffer(buffer, None);
+    free_memory(device, buffer_memory);
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+
+    match bytes.iter().zip(&expected).position(|(a, b)| a != b) {
+        Some(i) => Err(anyhow!("Byte {} of {} differs after the round trip.", i, size)),
+        None => Ok(()),
+    }
+}
+
+/// The reduction shaders sum a buffer of ones.
+unsafe fn check_compute_dispatch(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let ones = (0..COMPUTE_ELEMENTS).flat_map(|_| 1.0f32.to_ne_bytes()).collect::<Vec<_>>();
+    let size = ones.len() as u64;
+
+    let (staging_buffer, staging_buffer_memory) = create_staging_buffer(instance, device, data, &ones)?;
+    let (input, input_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    copy_buffer(device, data, staging_buffer, input, size)?;
+
+    let mut reduction = create_reduction(instance, device, data, input, COMPUTE_ELEMENTS)?;
+    let readback = Readback::create(instance, device, data, size_of::<f32>())?;
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+    reduction.record_sum(device, data, command_buffer);
+
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[] as &[vk::ImageMemoryBarrier],
+    );
+
+    let region = vk::BufferCopy::builder().size(size_of::<f32>() as u64);
+    device.cmd_copy_buffer(command_buffer, reduction.total(), readback.buffer, &[region]);
+    end_single_time_commands(device, data, command_buffer)?;
+
+    let bytes = readback.read(device)?;
+    let total = f32::from_ne_bytes(bytes[..4].try_into().unwrap());
+
+    readback.destroy(device);
+    reduction.destroy(device);
+    device.destroy_buffer(input, None);
+    free_memory(device, input_memory);
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+
+    if total == COMPUTE_ELEMENTS as f32 {
+        Ok(())
+    } else {
+        Err(anyhow!("Summed {} ones to {}.", COMPUTE_ELEMENTS, total))
+    }
+}
+
+/// A full-screen triangle is drawn in a solid color.
+unsafe fn check_render_to_texture(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let frag = include_bytes!("../../shaders/self_test_color_frag.spv");
+    let pixels = render(instance, device, data, &frag[..], None)?;
+    compare_pixels(&pixels, &[[255, 0, 255, 255]; (SIZE * SIZE) as usize])
+}
+
+/// A texture is sampled texel for texel into a render target.
+unsafe fn check_texture_sampling(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let texels: [[u8; 4]; 4] = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 255],
+        [255, 255, 255, 0],
+    ];
+
+    let (staging_buffer, staging_buffer_memory) = create_staging_buffer(instance, device, data, texels.as_flattened())?;
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        SIZE,
+        SIZE,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        vk::ImageCreateFlags::empty(),
+    )?;
+    transition_image_layout(
+        device,
+        data,
+        image,
+        FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        1,
+        0..1,
+    )?;
+    copy_buffer_to_image(device, data, staging_buffer, image, SIZE, SIZE)?;
+    transition_image_layout(
+        device,
+        data,
+        image,
+        FORMAT,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        1,
+        0..1,
+    )?;
+    let image_view = create_image_view(device, image, FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+    let sampler = data.samplers.get(device, SamplerDesc::nearest());
+
+    let frag = include_bytes!("../../shaders/self_test_sample_frag.spv");
+    let pixels = render(instance, device, data, &frag[..], Some((image_view, sampler)));
+
+    device.destroy_image_view(image_view, None);
+    device.destroy_image(image, None);
+    free_memory(device, image_memory);
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+
+    compare_pixels(&pixels?, &texels)
+}
+
+fn compare_pixels(pixels: &[u8], expected: &[[u8; 4]]) -> Result<()> {
+    for (i, (pixel, expected)) in pixels.chunks_exact(4).zip(expected).enumerate() {
+        // Allow for rounding in the conversion to and from unorm.
+        if pixel.iter().zip(expected).any(|(a, b)| a.abs_diff(*b) > 1) {
+            return Err(anyhow!("Pixel {} is {:?}, expected {:?}.", i, pixel, expected));
+        }
+    }
+    Ok(())
+}
+
+/// Draws a full-screen triangle with `frag` into a `SIZE` by `SIZE` target
+/// and reads it back. `texture` is bound at set 0, binding 0.
+unsafe fn render(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    frag: &[u8],
+    texture: Option<(vk::ImageView, vk::Sampler)>,
+) -> Result<Vec<u8>> {
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        SIZE,
+        SIZE,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        vk::ImageCreateFlags::empty(),
+    )?;
+    let image_view = create_image_view(device, image, FORMAT, vk::ImageAspectFlags::COLOR, 1)?;
+
+    // Cleared to transparent black, so a missing draw shows.
+    let attachment = vk::AttachmentDescription::builder()
+        .format(FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+    let attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[attachment_ref];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments);
+
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::TRANSFER)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+
+    let attachments = &[attachment];
+    let subpasses = &[subpass];
+    let dependencies = &[dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies);
+    let render_pass = device.create_render_pass(&info, None)?;
+
+    let framebuffer_attachments = &[image_view];
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(render_pass)
+        .attachments(framebuffer_attachments)
+        .width(SIZE)
+        .height(SIZE)
+        .layers(1);
+    let framebuffer = device.create_framebuffer(&info, None)?;
+
+    // The texture's descriptor, if any.
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+    let bindings = &[binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    let set_layout = device.create_descriptor_set_layout(&info, None)?;
+
+    let pool_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+    let pool_sizes = &[pool_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    let descriptor_pool = device.create_descriptor_pool(&info, None)?;
+
+    let set_layouts = &[set_layout];
+    let set = match texture {
+        Some((image_view, sampler)) => {
+            let info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(set_layouts);
+            let set = device.allocate_descriptor_sets(&info)?[0];
+
+            let image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(image_view)
+                .sampler(sampler);
+            let image_infos = &[image_info];
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(image_infos);
+            device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+            Some(set)
+        }
+        None => None,
+    };
+
+    let layout_set_layouts: &[vk::DescriptorSetLayout] = if set.is_some() { set_layouts } else { &[] };
+    let info = vk::PipelineLayoutCreateInfo::builder().set_layouts(layout_set_layouts);
+    let pipeline_layout = device.create_pipeline_layout(&info, None)?;
+
+    let vert = include_bytes!("../../shaders/tonemap_vert.spv");
+    let vert_shader_module = create_shader_module(device, &vert[..])?;
+    let frag_shader_module = create_shader_module(device, frag)?;
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0");
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let extent = vk::Extent2D { width: SIZE, height: SIZE };
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(SIZE as f32)
+        .height(SIZE as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let blend_attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let blend_attachments = &[blend_attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(blend_attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let stages = &[vert_stage, frag_stage];
+    let info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .layout(pipeline_layout)
+        .render_pass(render_pass)
+        .subpass(0);
+    // Bypasses the pipeline cache, so a bad cache can neither fail nor hide
+    // a failure of the check.
+    let pipeline = device.create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)?.0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+
+    let readback = Readback::create(instance, device, data, (SIZE * SIZE * 4) as usize)?;
+
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    let clear_values = &[vk::ClearValue {
+        color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 0.0] },
+    }];
+    let info = vk::RenderPassBeginInfo::builder()
+        .render_pass(render_pass)
+        .framebuffer(framebuffer)
+        .render_area(vk::Rect2D::builder().extent(extent))
+        .clear_values(clear_values);
+    device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+    if let Some(set) = set {
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            0,
+            &[set],
+            &[],
+        );
+    }
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    device.cmd_end_render_pass(command_buffer);
+
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1);
+    let region = vk::BufferImageCopy::builder()
+        .image_subresource(subresource)
+        .image_extent(vk::Extent3D { width: SIZE, height: SIZE, depth: 1 });
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        readback.buffer,
+        &[region],
+    );
+
+    end_single_time_commands(device, data, command_buffer)?;
+    let pixels = readback.read(device)?;
+
+    readback.destroy(device);
+    device.destroy_pipeline(pipeline, None);
+    device.destroy_pipeline_layout(pipeline_layout, None);
+    device.destroy_descriptor_pool(descriptor_pool, None);
+    device.destroy_descriptor_set_layout(set_layout, None);
+    device.destroy_framebuffer(framebuffer, None);
+    device.destroy_render_pass(render_pass, None);
+    device.destroy_image_view(image_view, None);
+    device.destroy_image(image, None);
+    free_memory(device, image_memory);
+
+    Ok(pixels)
+}
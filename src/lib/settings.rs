@@ -0,0 +1,179 @@
+use anyhow::Result;
+use log::*;
+use std::{fs, io::ErrorKind, path::Path, str::FromStr};
+
+use crate::{
+    app::MAX_FRAMES_IN_FLIGHT, debug_view::DebugView, swapchain::SwapchainColorSpace, tonemap::ResolveMode,
+    window_mode::WindowMode,
+};
+
+/// User-facing options that survive restarts. Stored as `key = value` lines;
+/// unknown keys are ignored and missing keys keep their defaults.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub models: usize,
+    pub show_grid: bool,
+    pub static_scene: bool,
+    pub wireframe: bool,
+    pub debug_view: DebugView,
+    pub resolve_mode: ResolveMode,
+    pub srgb_swapchain: bool,
+    /// Reversed depth, see `App::reverse_z`.
+    pub reverse_z: bool,
+    /// See `App::order_independent_transparency`.
+    pub order_independent_transparency: bool,
+    /// From 1 to 3. More frames keep the GPU busier at the cost of latency.
+    pub frames_in_flight: usize,
+    /// See `App::min_swapchain_images`.
+    pub min_swapchain_images: u32,
+    pub swapchain_color_space: SwapchainColorSpace,
+    pub window_mode: WindowMode,
+    /// Shows the world in a headset through the OpenXR runtime, if there is
+    /// one. Read at startup.
+    pub openxr: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            models: 4,
+            show_grid: true,
+            static_scene: false,
+            wireframe: false,
+            debug_view: DebugView::None,
+            resolve_mode: ResolveMode::default(),
+            srgb_swapchain: true,
+            reverse_z: false,
+            order_independent_transparency: false,
+            frames_in_flight: 2,
+            min_swapchain_images: 0,
+            swapchain_color_space: SwapchainColorSpace::default(),
+            window_mode: WindowMode::default(),
+            openxr: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads `path`, falling back to the defaults if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut settings = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                warn!("{}:{}: Expected `key = value`.", path.display(), number + 1);
+                continue;
+            };
+
+            let (key, value) = (key.trim(), value.trim());
+            let parsed = match key {
+                "models" => parse(value, &mut settings.models),
+                "show_grid" => parse(value, &mut settings.show_grid),
+                "static_scene" => parse(value, &mut settings.static_scene),
+                "wireframe" => parse(value, &mut settings.wireframe),
+                "srgb_swapchain" => parse(value, &mut settings.srgb_swapchain),
+                "reverse_z" => parse(value, &mut settings.reverse_z),
+                "order_independent_transparency" => parse(value, &mut settings.order_independent_transparency),
+                "openxr" => parse(value, &mut settings.openxr),
+                "min_swapchain_images" => parse(value, &mut settings.min_swapchain_images),
+                "frames_in_flight" => {
+                    let mut frames = 0usize;
+                    parse(value, &mut frames)
+                        && (1..=MAX_FRAMES_IN_FLIGHT)
+                            .contains(&frames)
+                            .then(|| settings.frames_in_flight = frames)
+                            .is_some()
+                }
+                "debug_view" => {
+                    let mut index = 0usize;
+                    parse(value, &mut index)
+                        && DebugView::ALL
+                            .get(index)
+                            .map(|v| settings.debug_view = *v)
+                            .is_some()
+                }
+                "resolve_mode" => {
+                    let mut index = 0usize;
+                    parse(value, &mut index)
+                        && ResolveMode::ALL
+                            .get(index)
+                            .map(|m| settings.resolve_mode = *m)
+                            .is_some()
+                }
+                "swapchain_color_space" => {
+                    let mut index = 0usize;
+                    parse(value, &mut index)
+                        && SwapchainColorSpace::ALL
+                            .get(index)
+                            .map(|c| settings.swapchain_color_space = *c)
+                            .is_some()
+                }
+                "window_mode" => {
+                    let mut index = 0usize;
+                    parse(value, &mut index)
+                        && WindowMode::ALL
+                            .get(index)
+                            .map(|m| settings.window_mode = *m)
+                            .is_some()
+                }
+                _ => true,
+            };
+
+            if !parsed {
+                warn!("{}:{}: Invalid value for `{}`.", path.display(), number + 1, key);
+            }
+        }
+
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let text = format!(
+            "models = {}\nshow_grid = {}\nstatic_scene = {}\nwireframe = {}\ndebug_view = {}\nresolve_mode = {}\nsrgb_swapchain = {}\nreverse_z = {}\norder_independent_transparency = {}\nframes_in_flight = {}\nmin_swapchain_images = {}\nswapchain_color_space = {}\nwindow_mode = {}\nopenxr = {}\n",
+            self.models,
+            self.show_grid,
+            self.static_scene,
+            self.wireframe,
+            self.debug_view as u32,
+            self.resolve_mode as u32,
+            self.srgb_swapchain,
+            self.reverse_z,
+            self.order_independent_transparency,
+            self.frames_in_flight,
+            self.min_swapchain_images,
+            self.swapchain_color_space as u32,
+            self.window_mode as u32,
+            self.openxr,
+        );
+
+        // Write then rename so a crash mid-save cannot truncate the file.
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+fn parse<T: FromStr>(value: &str, out: &mut T) -> bool {
+    match value.parse() {
+        Ok(value) => {
+            *out = value;
+            true
+        }
+        Err(_) => false,
+    }
+}
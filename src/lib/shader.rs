@@ -9,4 +9,58 @@ pub(crate) unsafe fn create_shader_module(device: &Device, bytecode: &[u8]) -> R
       .code(bytecode.code());
 
   Ok(device.create_shader_module(&info, None).unwrap())
-}
\ No newline at end of file
+}
+
+/// Values for a shader's `layout(constant_id = N) const` declarations, fixed
+/// when the pipeline is created. Constants that are not set keep the default
+/// from the shader source.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SpecializationConstants {
+  entries: Vec<vk::SpecializationMapEntry>,
+  data: Vec<u8>,
+}
+
+impl SpecializationConstants {
+  pub(crate) fn new() -> Self {
+    Self::default()
+  }
+
+  pub(crate) fn u32(self, id: u32, value: u32) -> Self {
+    self.push(id, &value.to_ne_bytes())
+  }
+
+  pub(crate) fn i32(self, id: u32, value: i32) -> Self {
+    self.push(id, &value.to_ne_bytes())
+  }
+
+  pub(crate) fn f32(self, id: u32, value: f32) -> Self {
+    self.push(id, &value.to_ne_bytes())
+  }
+
+  /// GLSL `bool` constants are 32-bit `VkBool32`s.
+  pub(crate) fn bool(self, id: u32, value: bool) -> Self {
+    self.push(id, &(value as vk::Bool32).to_ne_bytes())
+  }
+
+  fn push(mut self, id: u32, bytes: &[u8]) -> Self {
+    debug_assert!(
+        self.entries.iter().all(|e| e.constant_id != id),
+        "Specialization constant {} is set twice.",
+        id,
+    );
+    self.entries.push(vk::SpecializationMapEntry {
+      constant_id: id,
+      offset: self.data.len() as u32,
+      size: bytes.len(),
+    });
+    self.data.extend_from_slice(bytes);
+    self
+  }
+
+  /// Borrows the constants for a `vk::PipelineShaderStageCreateInfo`.
+  pub(crate) fn info(&self) -> vk::SpecializationInfoBuilder<'_> {
+    vk::SpecializationInfo::builder()
+        .map_entries(&self.entries)
+        .data(&self.data)
+  }
+}
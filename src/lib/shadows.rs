@@ -0,0 +1,300 @@
+use anyhow::Result;
+use std::mem::size_of;
+
+use cgmath::{InnerSpace, Matrix, SquareMatrix};
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    image::create_storage_images,
+    ray_tracing::create_ray_tracing_pipeline,
+    sampler::SamplerDesc,
+    types::{Mat4, Vec3, Vec4},
+};
+
+const SHADOW_RGEN: &[u8] = include_bytes!("../../shaders/shadow_rgen.spv");
+const SHADOW_RMISS: &[u8] = include_bytes!("../../shaders/shadow_rmiss.spv");
+const SHADOW_RCHIT: &[u8] = include_bytes!("../../shaders/shadow_rchit.spv");
+
+/// Visibility, blocker distance and view depth, as `shadow.rgen` writes them.
+const SHADOW_MASK_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// A directional light on the world, added to what the light probes give.
+/// Shadowed with `App::ray_traced_shadows` where supported and unshadowed
+/// otherwise.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sun {
+    /// Towards the sun, in world space. Normalized when used.
+    pub direction: Vec3,
+    /// Illuminance on a surface facing the sun, per color channel, in the
+    /// units of the light probes.
+    pub illuminance: Vec3,
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::new(0.3, 1.0, 0.5),
+            illuminance: Vec3::new(3.0, 2.9, 2.7),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ShadowPushConstants {
+    inverse_view_proj: Mat4,
+    camera: Vec4,
+    sun_direction: Vec4,
+    forward: Vec4,
+}
+
+/// What the scene samples the sun's visibility from, one per swapchain
+/// image. Traced at full resolution with ray tracing, and otherwise a single
+/// lit texel that is never read.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ShadowMask {
+    image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    image_view: vk::ImageView,
+    sampler: vk::Sampler,
+    /// Null without ray tracing.
+    set: vk::DescriptorSet,
+}
+
+impl ShadowMask {
+    /// For binding 2 of the scene's set 0.
+    pub(crate) fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::GENERAL)
+            .image_view(self.image_view)
+            .sampler(self.sampler)
+            .build()
+    }
+
+    /// Records tracing the sun's visibility for every pixel the camera sees
+    /// through `tlas`, to run before the scene pass samples it.
+    pub(crate) unsafe fn record_trace(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        tlas: vk::AccelerationStructureKHR,
+        (view, proj): (Mat4, Mat4),
+        sun: &Sun,
+    ) {
+        // The structure is recreated when it grows, so is written every
+        // frame while this image's set is not in use.
+        let structures = &[tlas];
+        let mut structure_info = vk::WriteDescriptorSetAccelerationStructureKHR::builder()
+            .acceleration_structures(structures);
+        let mut write = vk::WriteDescriptorSet::builder()
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            .push_next(&mut structure_info)
+            .build();
+        write.descriptor_count = 1;
+        device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+        self.barrier(
+            device,
+            command_buffer,
+            (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+            (vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR, vk::AccessFlags::SHADER_WRITE),
+        );
+
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::RAY_TRACING_KHR,
+            data.shadow_pipeline.pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::RAY_TRACING_KHR,
+            data.shadow_pipeline_layout,
+            0,
+            &[self.set],
+            &[],
+        );
+
+        let inverse_view = view.invert().unwrap();
+        let push_constants = ShadowPushConstants {
+            inverse_view_proj: (proj * view).invert().unwrap(),
+            camera: inverse_view.w,
+            sun_direction: sun.direction.normalize().extend(0.0),
+            forward: -view.row(2).truncate().extend(0.0),
+        };
+        let push_constants_bytes = std::slice::from_raw_parts(
+            &push_constants as *const ShadowPushConstants as *const u8,
+            size_of::<ShadowPushConstants>(),
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            data.shadow_pipeline_layout,
+            vk::ShaderStageFlags::RAYGEN_KHR,
+            0,
+            push_constants_bytes,
+        );
+
+        data.shadow_pipeline.record_trace(device, command_buffer, data.swapchain_extent);
+
+        self.barrier(
+            device,
+            command_buffer,
+            (vk::PipelineStageFlags::RAY_TRACING_SHADER_KHR, vk::AccessFlags::SHADER_WRITE),
+            (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+        );
+    }
+
+    unsafe fn barrier(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        (src_stage_mask, src_access_mask): (vk::PipelineStageFlags, vk::AccessFlags),
+        (dst_stage_mask, dst_access_mask): (vk::PipelineStageFlags, vk::AccessFlags),
+    ) {
+        let subresource = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(self.image)
+            .subresource_range(subresource)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage_mask,
+            dst_stage_mask,
+            vk::DependencyFlags::empty(),
+            &[] as &[vk::MemoryBarrier],
+            &[] as &[vk::BufferMemoryBarrier],
+            &[barrier],
+        );
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.destroy_image_view(self.image_view, None);
+        device.destroy_image(self.image, None);
+        free_memory(device, self.image_memory);
+    }
+}
+
+/// Creates the ray tracing pipeline that traces shadow masks. Does nothing
+/// without ray tracing.
+pub(crate) unsafe fn create_shadow_pipeline(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.ray_tracing {
+        return Ok(());
+    }
+
+    let structure_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR);
+    let mask_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR);
+
+    let bindings = &[structure_binding, mask_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.shadow_set_layout = data.set_layouts.get(device, &info).unwrap();
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::RAYGEN_KHR)
+        .offset(0)
+        .size(size_of::<ShadowPushConstants>() as u32);
+
+    let set_layouts = &[data.shadow_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+    data.shadow_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let shaders = [
+        (vk::ShaderStageFlags::RAYGEN_KHR, SHADOW_RGEN),
+        (vk::ShaderStageFlags::MISS_KHR, SHADOW_RMISS),
+        (vk::ShaderStageFlags::CLOSEST_HIT_KHR, SHADOW_RCHIT),
+    ];
+    data.shadow_pipeline =
+        create_ray_tracing_pipeline(instance, device, data, data.shadow_pipeline_layout, &shaders, 1).unwrap();
+    Ok(())
+}
+
+/// Creates a `ShadowMask` per swapchain image, lit until traced. Every mask
+/// stays in the general layout, so the scene can sample it whether it was
+/// traced or not.
+pub(crate) unsafe fn create_shadow_masks(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    data.shadow_masks.clear();
+
+    let images = data.swapchain_images.len() as u32;
+    let mut sets = vec![vk::DescriptorSet::null(); images as usize];
+    let (width, height) = if data.ray_tracing {
+        let structure_size = vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::ACCELERATION_STRUCTURE_KHR)
+            .descriptor_count(images);
+        let mask_size = vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_IMAGE)
+            .descriptor_count(images);
+
+        let pool_sizes = &[structure_size, mask_size];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(pool_sizes)
+            .max_sets(images);
+        data.shadow_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+        let layouts = vec![data.shadow_set_layout; images as usize];
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(data.shadow_descriptor_pool)
+            .set_layouts(&layouts);
+        sets = device.allocate_descriptor_sets(&info).unwrap();
+
+        (data.swapchain_extent.width, data.swapchain_extent.height)
+    } else {
+        (1, 1)
+    };
+
+    let sampler = data.samplers.get(
+        device,
+        SamplerDesc {
+            wrap: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..SamplerDesc::nearest()
+        },
+    );
+
+    let lit = vk::ClearColorValue { float32: [1.0, 0.0, 0.0, 0.0] };
+    let images = create_storage_images(instance, device, data, sets.len(), (width, height), SHADOW_MASK_FORMAT, lit)
+        .unwrap();
+
+    for (set, (image, image_memory, image_view)) in sets.into_iter().zip(images) {
+        if !set.is_null() {
+            let image_info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::GENERAL)
+                .image_view(image_view);
+            let image_infos = &[image_info];
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(image_infos);
+            device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+        }
+
+        data.shadow_masks.push(ShadowMask { image, image_memory, image_view, sampler, set });
+    }
+
+    Ok(())
+}
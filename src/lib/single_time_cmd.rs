@@ -2,7 +2,7 @@ use anyhow::Result;
 
 use vulkanalia::prelude::v1_0::*;
 
-use crate::app::AppData;
+use crate::{app::AppData, barrier::queue_submit};
 
 pub(crate) unsafe fn begin_single_time_commands(device: &Device, data: &AppData) -> Result<vk::CommandBuffer> {
   let info = vk::CommandBufferAllocateInfo::builder()
@@ -27,12 +27,7 @@ pub(crate) unsafe fn end_single_time_commands(
 ) -> Result<()> {
   device.end_command_buffer(command_buffer).unwrap();
 
-  let command_buffers = &[command_buffer];
-  let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
-
-  device
-      .queue_submit(data.graphics_queue, &[info], vk::Fence::null())
-      .unwrap();
+  queue_submit(device, data, data.graphics_queue, &[command_buffer], &[], &[], vk::Fence::null()).unwrap();
 
   device.queue_wait_idle(data.graphics_queue).unwrap();
 
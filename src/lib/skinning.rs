@@ -0,0 +1,157 @@
+use anyhow::Result;
+use cgmath::{One, SquareMatrix};
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    mapped_memory::create_mapped_buffer,
+    types::{Mat4, Quat, Vec3},
+};
+
+/// Joint matrices one frame can hold across every skinned entity.
+pub(crate) const MAX_JOINT_MATRICES: usize = 4096;
+
+/// A joint's transform relative to its parent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct JointTransform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for JointTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quat::one(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl JointTransform {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.translation)
+            * Mat4::from(self.rotation)
+            * Mat4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Joint {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    /// The transform the mesh was bound in.
+    pub rest: JointTransform,
+    /// Takes world space vertices into the joint's space at rest.
+    pub(crate) inverse_bind: Mat4,
+}
+
+/// The joints of a skinned model. Vertices name up to four joints by their
+/// index in `joints`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    /// Places root joints in world space.
+    pub(crate) root: Mat4,
+    /// Joint indices with parents before their children.
+    order: Vec<usize>,
+}
+
+/// Local transforms for each joint of a skeleton, in its joint order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pose {
+    pub joints: Vec<JointTransform>,
+}
+
+impl Skeleton {
+    pub(crate) fn new(joints: Vec<Joint>, root: Mat4) -> Self {
+        let mut order = Vec::with_capacity(joints.len());
+        let mut placed = vec![false; joints.len()];
+        while order.len() < joints.len() {
+            let before = order.len();
+            for (i, joint) in joints.iter().enumerate() {
+                if !placed[i] && joint.parent.is_none_or(|p| placed[p]) {
+                    placed[i] = true;
+                    order.push(i);
+                }
+            }
+            // A cycle; treat what is left as roots.
+            if order.len() == before {
+                order.extend((0..joints.len()).filter(|i| !placed[*i]));
+                break;
+            }
+        }
+
+        Self { joints, root, order }
+    }
+
+    /// The pose the mesh was bound in, which leaves it undeformed.
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            joints: self.joints.iter().map(|j| j.rest).collect(),
+        }
+    }
+
+    /// The index of the joint called `name`.
+    pub fn joint(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name.as_deref() == Some(name))
+    }
+
+    /// Appends the matrices that move vertices from the bind pose to
+    /// `pose`, one per joint. Joints `pose` leaves out keep their rest
+    /// transform.
+    pub(crate) fn skinning_matrices(&self, pose: &Pose, matrices: &mut Vec<Mat4>) {
+        let mut globals = vec![Mat4::identity(); self.joints.len()];
+        for &i in &self.order {
+            let joint = &self.joints[i];
+            let local = pose.joints.get(i).unwrap_or(&joint.rest).matrix();
+            globals[i] = match joint.parent {
+                Some(parent) => globals[parent] * local,
+                None => self.root * local,
+            };
+        }
+
+        matrices.extend(globals.iter().zip(&self.joints).map(|(global, joint)| global * joint.inverse_bind));
+    }
+}
+
+/// One storage buffer of joint matrices per swapchain image, read by the
+/// scene vertex shader.
+pub(crate) unsafe fn create_joint_buffers(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    data.joint_buffers.clear();
+    data.joint_buffers_memory.clear();
+
+    for _ in 0..data.swapchain_images.len() {
+        let (joint_buffer, joint_buffer_memory) = create_mapped_buffer(
+            instance,
+            device,
+            data,
+            (size_of::<Mat4>() * MAX_JOINT_MATRICES) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+        )
+        .unwrap();
+
+        data.joint_buffers.push(joint_buffer);
+        data.joint_buffers_memory.push(joint_buffer_memory);
+    }
+
+    Ok(())
+}
+
+/// Writes `matrices`, at most `MAX_JOINT_MATRICES`, to the start of the
+/// joint buffer for `image_index`.
+pub(crate) unsafe fn write_joint_matrices(device: &Device, data: &AppData, image_index: usize, matrices: &[Mat4]) {
+    let count = matrices.len().min(MAX_JOINT_MATRICES);
+    if count == 0 {
+        return;
+    }
+
+    data.joint_buffers_memory[image_index].write(device, 0, &matrices[..count]);
+}
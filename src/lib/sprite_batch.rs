@@ -0,0 +1,491 @@
+use anyhow::Result;
+use cgmath::{vec2, Rad};
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    descriptor_cache::SetCache,
+    dynamic_buffer::DynamicBuffer,
+    dynamic_rendering::Pass,
+    shader::{create_shader_module, SpecializationConstants},
+    swapchain::HDR_PAPER_WHITE,
+    texture::ColorSpace,
+    texture_atlas::AtlasRegion,
+    types::{Mat3, Vec2, Vec4},
+};
+
+/// Atlases the first pool of atlas sets has room for.
+const INITIAL_SPRITE_ATLASES: u32 = 16;
+
+const SPRITE_MODE_TEXTURE: u32 = 0;
+const SPRITE_MODE_GLYPH: u32 = 1;
+
+/// A texture that sprites and glyphs are sampled from. Every atlas used in a
+/// frame costs one draw.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpriteAtlas(pub(crate) u32);
+
+impl SpriteAtlas {
+    /// The atlas backed by the scene's texture image.
+    pub const DEFAULT: SpriteAtlas = SpriteAtlas(0);
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SpriteInstance {
+    /// Where the top-left corner lands, in pixels.
+    pub(crate) origin: Vec2,
+    /// Where the top and left edges run, in pixels, from `origin` to the
+    /// other corners.
+    pub(crate) axis_x: Vec2,
+    pub(crate) axis_y: Vec2,
+    pub(crate) uv_min: Vec2,
+    pub(crate) uv_max: Vec2,
+    pub(crate) color: Vec4,
+    pub(crate) mode: u32,
+}
+
+impl SpriteInstance {
+    pub(crate) fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<SpriteInstance>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    pub(crate) fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 7] {
+        let vec2_size = size_of::<Vec2>() as u32;
+        let attribute = |location: u32, format: vk::Format, offset: u32| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(location)
+                .format(format)
+                .offset(offset)
+                .build()
+        };
+
+        [
+            attribute(0, vk::Format::R32G32_SFLOAT, 0),
+            attribute(1, vk::Format::R32G32_SFLOAT, vec2_size),
+            attribute(2, vk::Format::R32G32_SFLOAT, vec2_size * 2),
+            attribute(3, vk::Format::R32G32_SFLOAT, vec2_size * 3),
+            attribute(4, vk::Format::R32G32_SFLOAT, vec2_size * 4),
+            attribute(5, vk::Format::R32G32B32A32_SFLOAT, vec2_size * 5),
+            attribute(6, vk::Format::R32_UINT, vec2_size * 5 + size_of::<Vec4>() as u32),
+        ]
+    }
+}
+
+/// Screen-space sprites and glyphs, in pixels from the top-left corner of the
+/// window, for HUDs and other 2D content. Pixels are logical ones,
+/// `App::effective_ui_scale` physical pixels across, so overlays keep their
+/// size on HiDPI displays. Sprites may be rotated or carry any 2D affine
+/// transform. Everything queued here is drawn on top of the scene with the next
+/// frame (one instanced draw per atlas and clip rectangle) and then cleared.
+#[derive(Clone, Debug, Default)]
+pub struct SpriteBatch {
+    /// Each instance with its atlas and an index into `clips`.
+    pub(crate) instances: Vec<(SpriteAtlas, usize, SpriteInstance)>,
+    /// Clip rectangles as `(min, max)` in pixels. `None` is the whole window.
+    clips: Vec<Option<(Vec2, Vec2)>>,
+    clip_stack: Vec<usize>,
+}
+
+impl SpriteBatch {
+    pub fn sprite(
+        &mut self,
+        atlas: SpriteAtlas,
+        position: Vec2,
+        size: Vec2,
+        uv_min: Vec2,
+        uv_max: Vec2,
+        color: Vec4,
+    ) {
+        let (axis_x, axis_y) = (vec2(size.x, 0.0), vec2(0.0, size.y));
+        self.push(atlas, position, axis_x, axis_y, uv_min, uv_max, color, SPRITE_MODE_TEXTURE);
+    }
+
+    /// Queues a sprite whose unit square, from (0, 0) at its top-left corner
+    /// to (1, 1), `transform` maps to pixels. `transform` is a 2D affine
+    /// transform in homogeneous coordinates, so the sprite may be moved,
+    /// rotated, scaled and sheared. Clipping stays axis aligned.
+    pub fn transformed_sprite(
+        &mut self,
+        atlas: SpriteAtlas,
+        transform: Mat3,
+        uv_min: Vec2,
+        uv_max: Vec2,
+        color: Vec4,
+    ) {
+        let (origin, axis_x, axis_y) = (transform.z.truncate(), transform.x.truncate(), transform.y.truncate());
+        self.push(atlas, origin, axis_x, axis_y, uv_min, uv_max, color, SPRITE_MODE_TEXTURE);
+    }
+
+    /// Queues a sprite `size` pixels across, centered on `center` and turned
+    /// clockwise on screen by `angle`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn rotated_sprite(
+        &mut self,
+        atlas: SpriteAtlas,
+        center: Vec2,
+        size: Vec2,
+        angle: Rad<f32>,
+        uv_min: Vec2,
+        uv_max: Vec2,
+        color: Vec4,
+    ) {
+        let (sin, cos) = angle.0.sin_cos();
+        let axis_x = vec2(cos, sin) * size.x;
+        let axis_y = vec2(-sin, cos) * size.y;
+        let origin = center - (axis_x + axis_y) / 2.0;
+        self.push(atlas, origin, axis_x, axis_y, uv_min, uv_max, color, SPRITE_MODE_TEXTURE);
+    }
+
+    /// Queues the whole of a texture packed by `App::load_texture_atlas`.
+    pub fn atlas_sprite(&mut self, region: &AtlasRegion, position: Vec2, size: Vec2, color: Vec4) {
+        self.sprite(region.sprite_atlas, position, size, region.uv_min, region.uv_max, color);
+    }
+
+    /// Queues a glyph whose coverage is stored in the red channel of `atlas`.
+    pub fn glyph(
+        &mut self,
+        atlas: SpriteAtlas,
+        position: Vec2,
+        size: Vec2,
+        uv_min: Vec2,
+        uv_max: Vec2,
+        color: Vec4,
+    ) {
+        let (axis_x, axis_y) = (vec2(size.x, 0.0), vec2(0.0, size.y));
+        self.push(atlas, position, axis_x, axis_y, uv_min, uv_max, color, SPRITE_MODE_GLYPH);
+    }
+
+    /// Clips everything queued until the matching `pop_clip` to the
+    /// rectangle from `min` to `max`, in pixels, intersected with the
+    /// enclosing clip. Clipping uses the scissor rectangle, so it is free
+    /// but always axis aligned.
+    pub fn push_clip(&mut self, min: Vec2, max: Vec2) {
+        let (min, max) = match self.current_clip() {
+            Some((outer_min, outer_max)) => (
+                Vec2::new(min.x.max(outer_min.x), min.y.max(outer_min.y)),
+                Vec2::new(max.x.min(outer_max.x), max.y.min(outer_max.y)),
+            ),
+            None => (min, max),
+        };
+        self.clips.push(Some((min, max)));
+        self.clip_stack.push(self.clips.len() - 1);
+    }
+
+    /// Restores the clip that was active before the last `push_clip`.
+    pub fn pop_clip(&mut self) {
+        self.clip_stack.pop();
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.clips.clear();
+        self.clip_stack.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push(
+        &mut self,
+        atlas: SpriteAtlas,
+        origin: Vec2,
+        axis_x: Vec2,
+        axis_y: Vec2,
+        uv_min: Vec2,
+        uv_max: Vec2,
+        color: Vec4,
+        mode: u32,
+    ) {
+        if self.clips.is_empty() {
+            self.clips.push(None);
+        }
+        let clip = self.clip_stack.last().copied().unwrap_or(0);
+        self.instances.push((
+            atlas,
+            clip,
+            SpriteInstance {
+                origin,
+                axis_x,
+                axis_y,
+                uv_min,
+                uv_max,
+                color,
+                mode,
+            },
+        ));
+    }
+
+    fn current_clip(&self) -> Option<(Vec2, Vec2)> {
+        self.clip_stack.last().and_then(|i| self.clips[*i])
+    }
+
+    /// Sorts the queued instances by atlas and clip, returning them along
+    /// with one draw per pair. Each draw reads its instances starting at
+    /// `first` (the buffer is rebound at that offset rather than relying on
+    /// `first_instance`, which indirect draws only honour with the
+    /// `drawIndirectFirstInstance` feature).
+    pub(crate) fn build(&mut self, extent: vk::Extent2D, scale: f32) -> (Vec<SpriteInstance>, Vec<SpriteDraw>) {
+        self.instances.sort_by_key(|(atlas, clip, _)| (*atlas, *clip));
+
+        let mut draws: Vec<SpriteDraw> = vec![];
+        let mut clip = None;
+        for (index, (atlas, clip_index, _)) in self.instances.iter().enumerate() {
+            match draws.last_mut() {
+                Some(draw) if draw.atlas == *atlas && clip == Some(*clip_index) => {
+                    draw.command.instance_count += 1
+                }
+                _ => draws.push(SpriteDraw {
+                    atlas: *atlas,
+                    scissor: scissor(self.clips[*clip_index], extent, scale),
+                    first: index as u32,
+                    command: vk::DrawIndirectCommand {
+                        vertex_count: 6,
+                        instance_count: 1,
+                        first_vertex: 0,
+                        first_instance: 0,
+                    },
+                }),
+            }
+            clip = Some(*clip_index);
+        }
+
+        let instances = self.instances.iter().map(|(_, _, i)| *i).collect();
+        (instances, draws)
+    }
+}
+
+/// The scissor rectangle for `clip`, scaled to physical pixels, rounded out
+/// to whole ones and kept inside the framebuffer.
+fn scissor(clip: Option<(Vec2, Vec2)>, extent: vk::Extent2D, scale: f32) -> vk::Rect2D {
+    let size = Vec2::new(extent.width as f32, extent.height as f32);
+    let (min, max) = clip.map_or((Vec2::new(0.0, 0.0), size), |(min, max)| (min * scale, max * scale));
+    let x0 = min.x.floor().clamp(0.0, size.x) as u32;
+    let y0 = min.y.floor().clamp(0.0, size.y) as u32;
+    let x1 = max.x.ceil().clamp(0.0, size.x) as u32;
+    let y1 = max.y.ceil().clamp(0.0, size.y) as u32;
+    vk::Rect2D {
+        offset: vk::Offset2D { x: x0 as i32, y: y0 as i32 },
+        extent: vk::Extent2D {
+            width: x1.saturating_sub(x0),
+            height: y1.saturating_sub(y0),
+        },
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct SpriteDraw {
+    pub(crate) atlas: SpriteAtlas,
+    pub(crate) scissor: vk::Rect2D,
+    pub(crate) first: u32,
+    pub(crate) command: vk::DrawIndirectCommand,
+}
+
+pub(crate) unsafe fn create_sprite_buffers(data: &mut AppData) -> Result<()> {
+    data.sprite_instance_buffers = vec![DynamicBuffer::default(); data.swapchain_images.len()];
+    data.sprite_indirect_buffers = vec![DynamicBuffer::default(); data.swapchain_images.len()];
+    Ok(())
+}
+
+pub(crate) unsafe fn create_sprite_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[sampler_binding];
+    let mut info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    if data.push_descriptors {
+        info = info.flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR);
+    }
+
+    data.sprite_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// Atlases are pushed per draw when push descriptors are supported, so the
+/// cache never creates a pool. Otherwise it grows with the atlases, and
+/// atlases of the same image and sampler share a set.
+pub(crate) fn create_sprite_descriptor_pool(data: &mut AppData) -> Result<()> {
+    data.sprite_sets = SetCache::new(&[(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1)], INITIAL_SPRITE_ATLASES);
+    Ok(())
+}
+
+pub(crate) unsafe fn create_sprite_atlas(
+    device: &Device,
+    data: &mut AppData,
+    image_view: vk::ImageView,
+    sampler: vk::Sampler,
+) -> Result<SpriteAtlas> {
+    let info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(image_view)
+        .sampler(sampler)
+        .build();
+
+    data.sprite_atlas_images.push(info);
+    let atlas = SpriteAtlas(data.sprite_atlas_images.len() as u32 - 1);
+    if data.push_descriptors {
+        return Ok(atlas);
+    }
+
+    let bindings = [(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, info.into())];
+    let descriptor_set = data.sprite_sets.get(device, data.sprite_set_layout, &bindings)?;
+
+    data.sprite_atlas_sets.push(descriptor_set);
+    Ok(atlas)
+}
+
+/// Sprites are drawn in the overlay pass, after tonemapping and the focus
+/// blur, so UI colors reach the screen unchanged.
+pub(crate) unsafe fn create_sprite_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let vert = include_bytes!("../../shaders/sprite_vert.spv");
+    let frag = include_bytes!("../../shaders/sprite_frag.spv");
+
+    let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+    let frag_shader_module = create_shader_module(device, &frag[..]).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    let encode_srgb = ColorSpace::of(data.swapchain_format) == ColorSpace::Linear;
+    let constants = SpecializationConstants::new()
+        .bool(0, encode_srgb)
+        .i32(1, data.swapchain_color_space as i32)
+        .f32(2, HDR_PAPER_WHITE);
+    let specialization_info = constants.info();
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let binding_descriptions = &[SpriteInstance::binding_description()];
+    let attribute_descriptions = SpriteInstance::attribute_descriptions();
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+        .vertex_binding_descriptions(binding_descriptions)
+        .vertex_attribute_descriptions(&attribute_descriptions);
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+        .depth_test_enable(false)
+        .depth_write_enable(false)
+        .depth_compare_op(vk::CompareOp::ALWAYS)
+        .depth_bounds_test_enable(false)
+        .stencil_test_enable(false);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .alpha_blend_op(vk::BlendOp::ADD);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    // Each draw sets the scissor to its clip rectangle.
+    let dynamic_states = &[vk::DynamicState::SCISSOR];
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::VERTEX)
+        .offset(0)
+        .size(size_of::<Vec2>() as u32);
+
+    let set_layouts = &[data.sprite_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.sprite_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let formats = Pass::Overlay.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(data.sprite_pipeline_layout)
+        .render_pass(data.overlay_render_pass)
+        .subpass(0);
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.sprite_pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(())
+}
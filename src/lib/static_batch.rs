@@ -0,0 +1,236 @@
+use anyhow::Result;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix3, Point3, Transform};
+use log::*;
+use std::{mem::size_of, ptr::copy_nonoverlapping as memcpy};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    entity::Entity,
+    lod::model_lod,
+    render_queue::DrawOrder,
+    texture_atlas::{resolve_texture, texture_region, texture_slot},
+    types::Vec2,
+    vertex::Vertex,
+    vertex_buffer::{copy_buffer, create_buffer, pack_indices},
+    visibility::Bounds,
+};
+
+/// Static entities that share a draw order, texture and opacity, merged in
+/// world space into one draw.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct StaticBatch {
+    /// Bit `i` is set if entity `i` was merged into the batch.
+    pub(crate) entities: u64,
+    pub(crate) order: DrawOrder,
+    pub(crate) texture: u32,
+    pub(crate) opacity: f32,
+    pub(crate) first_index: u32,
+    pub(crate) index_count: u32,
+    /// In world space. The batch's ambient light is sampled at its center.
+    pub(crate) bounds: Bounds,
+}
+
+/// Where an entity's geometry ended up in `StaticBatches`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BatchedEntity {
+    pub(crate) entity: usize,
+    /// Index into `StaticBatches::batches`.
+    pub(crate) batch: usize,
+    pub(crate) first_index: u32,
+    pub(crate) index_count: u32,
+    pub(crate) first_vertex: u32,
+    pub(crate) vertex_count: u32,
+}
+
+/// The static entities baked by `App::bake_static_batches`, with their own
+/// vertex and index buffers.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StaticBatches {
+    pub(crate) vertex_buffer: vk::Buffer,
+    pub(crate) vertex_buffer_memory: vk::DeviceMemory,
+    pub(crate) index_buffer: vk::Buffer,
+    pub(crate) index_buffer_memory: vk::DeviceMemory,
+    pub(crate) index_type: vk::IndexType,
+    pub(crate) batches: Vec<StaticBatch>,
+    /// One entry per baked entity, in entity order.
+    pub(crate) remap: Vec<BatchedEntity>,
+}
+
+impl StaticBatches {
+    /// The batch `entity` was merged into.
+    pub(crate) fn batch_of(&self, entity: usize) -> Option<usize> {
+        self.remap
+            .binary_search_by_key(&entity, |e| e.entity)
+            .ok()
+            .map(|i| self.remap[i].batch)
+    }
+
+    /// The batches all of whose entities are still in `statics`. The rest
+    /// are left for their entities to be drawn one by one.
+    pub(crate) fn current(&self, statics: u64) -> Vec<usize> {
+        (0..self.batches.len())
+            .filter(|b| self.batches[*b].entities & !statics == 0)
+            .collect()
+    }
+
+    pub(crate) unsafe fn destroy(&mut self, device: &Device) {
+        device.destroy_buffer(self.vertex_buffer, None);
+        free_memory(device, self.vertex_buffer_memory);
+        device.destroy_buffer(self.index_buffer, None);
+        free_memory(device, self.index_buffer_memory);
+        *self = Self::default();
+    }
+}
+
+/// Merges the entities in `candidates` into `data.static_batches`,
+/// replacing the previous batches. Each entity's full level of detail is
+/// moved into world space and appended to the batch of its draw order,
+/// texture and opacity, so a batch is drawn with an identity model matrix.
+/// Like the vertex shader, normals assume uniform scale. Texture
+/// coordinates of entities drawing an atlas region are moved into its page,
+/// so every region of a page shares a batch.
+pub(crate) unsafe fn create_static_batches(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    entities: &[Entity],
+    candidates: u64,
+) -> Result<()> {
+    data.static_batches.destroy(device);
+
+    let lod = model_lod(data, 0);
+    let indices = &data.indices[lod.first_index as usize..(lod.first_index + lod.index_count) as usize];
+    let vertex_count = indices.iter().max().map_or(0, |i| *i as usize + 1);
+    let vertices = &data.vertices[..vertex_count];
+
+    // Batches in order of their first entity, and their entities in entity
+    // order within them.
+    let mut groups: Vec<(DrawOrder, u32, f32, Vec<usize>)> = vec![];
+    for (i, entity) in entities.iter().enumerate().take(64).filter(|(i, _)| candidates & (1 << i) != 0) {
+        let key = (entity.order, texture_slot(resolve_texture(data, entity.texture)), entity.opacity);
+        match groups.iter_mut().find(|g| (g.0, g.1, g.2) == key) {
+            Some(group) => group.3.push(i),
+            None => groups.push((key.0, key.1, key.2, vec![i])),
+        }
+    }
+
+    let mut batch_vertices = Vec::with_capacity(vertices.len() * candidates.count_ones() as usize);
+    let mut batch_indices = Vec::with_capacity(indices.len() * candidates.count_ones() as usize);
+    let mut batches = Vec::with_capacity(groups.len());
+    let mut remap = vec![];
+    for (order, texture, opacity, members) in groups {
+        let first_index = batch_indices.len() as u32;
+        let first_batch_vertex = batch_vertices.len();
+        for entity in &members {
+            let transform = entities[*entity].transform;
+            let region = data.atlas_regions[texture_region(resolve_texture(data, entities[*entity].texture)) as usize];
+            let normal_matrix = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+
+            let first_vertex = batch_vertices.len() as u32;
+            remap.push(BatchedEntity {
+                entity: *entity,
+                batch: batches.len(),
+                first_index: batch_indices.len() as u32,
+                index_count: indices.len() as u32,
+                first_vertex,
+                vertex_count: vertices.len() as u32,
+            });
+            batch_vertices.extend(vertices.iter().map(|v| {
+                let tangent = (normal_matrix * v.tangent.truncate()).normalize();
+                Vertex {
+                    pos: transform.transform_point(Point3::from_vec(v.pos)).to_vec(),
+                    normal: (normal_matrix * v.normal).normalize(),
+                    tangent: tangent.extend(v.tangent.w),
+                    tex_coords: Vec2::new(
+                        region.x + v.tex_coords.x * region.z,
+                        region.y + v.tex_coords.y * region.w,
+                    ),
+                    ..*v
+                }
+            }));
+            batch_indices.extend(indices.iter().map(|i| first_vertex + i));
+        }
+
+        batches.push(StaticBatch {
+            entities: members.iter().fold(0, |mask, i| mask | (1 << i)),
+            order,
+            texture,
+            opacity,
+            first_index,
+            index_count: batch_indices.len() as u32 - first_index,
+            bounds: Bounds::from_points(batch_vertices[first_batch_vertex..].iter().map(|v| v.pos)),
+        });
+    }
+    remap.sort_by_key(|e| e.entity);
+
+    info!(
+        "Baked {} static entities into {} batches of {} triangles.",
+        remap.len(),
+        batches.len(),
+        batch_indices.len() / 3,
+    );
+    if batch_indices.is_empty() {
+        return Ok(());
+    }
+
+    let vertex_bytes = std::slice::from_raw_parts(
+        batch_vertices.as_ptr() as *const u8,
+        size_of::<Vertex>() * batch_vertices.len(),
+    );
+    let (index_type, index_bytes) = pack_indices(&batch_indices, batch_vertices.len());
+    let (vertex_buffer, vertex_buffer_memory) =
+        create_device_buffer(instance, device, data, vertex_bytes, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+    let (index_buffer, index_buffer_memory) =
+        create_device_buffer(instance, device, data, &index_bytes, vk::BufferUsageFlags::INDEX_BUFFER)?;
+
+    data.static_batches = StaticBatches {
+        vertex_buffer,
+        vertex_buffer_memory,
+        index_buffer,
+        index_buffer_memory,
+        index_type,
+        batches,
+        remap,
+    };
+    Ok(())
+}
+
+/// A device local buffer holding `bytes`, uploaded through a staging
+/// buffer.
+pub(crate) unsafe fn create_device_buffer(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    bytes: &[u8],
+    usage: vk::BufferUsageFlags,
+) -> Result<(vk::Buffer, vk::DeviceMemory)> {
+    let size = bytes.len() as u64;
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+    memcpy(bytes.as_ptr(), memory.cast(), bytes.len());
+    device.unmap_memory(staging_buffer_memory);
+
+    let (buffer, buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST | usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    copy_buffer(device, data, staging_buffer, buffer, size)?;
+
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+    Ok((buffer, buffer_memory))
+}
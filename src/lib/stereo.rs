@@ -0,0 +1,481 @@
+use anyhow::Result;
+
+use cgmath::vec3;
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    depth_object::far_depth,
+    dynamic_rendering::Pass,
+    image::create_image,
+    pipeline::{create_scene_pipeline, SceneGeometry, ScenePipelineDesc, SceneVariant},
+    render_pass::discard_store_op,
+    sampler::SamplerDesc,
+    shader::{create_shader_module, SpecializationConstants},
+    swapchain::HDR_PAPER_WHITE,
+    texture::ColorSpace,
+    tonemap::HDR_FORMAT,
+    types::Mat4,
+    viewport::set_viewport,
+};
+
+const STEREO_VERT: &[u8] = include_bytes!("../../shaders/vert_multiview.spv");
+const STEREO_FRAG: &[u8] = include_bytes!("../../shaders/frag_multiview.spv");
+
+/// Both eyes' views are drawn by every draw, as view 0 and view 1.
+const VIEW_MASK: u32 = 0b11;
+
+/// Draws the world a second time for a pair of eyes, in one multiview pass
+/// into a layered target, and shows both side by side in the window. The
+/// groundwork for headset output, which would submit the layers instead.
+///
+/// Only world layer entities are drawn, and without ray traced shadows or
+/// occlusion, which are traced from the mono camera. Ignored without
+/// multiview support.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stereo {
+    /// The distance between the eyes, in world units.
+    pub eye_separation: f32,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self { eye_separation: 0.064 }
+    }
+}
+
+impl Stereo {
+    /// The left and right eyes' views and projections, either side of the
+    /// mono camera and half as wide at the same vertical field of view.
+    pub(crate) fn eyes(&self, view: Mat4, proj: Mat4) -> ([Mat4; 2], [Mat4; 2]) {
+        let half = self.eye_separation / 2.0;
+        let views = [half, -half].map(|x| Mat4::from_translation(vec3(x, 0.0, 0.0)) * view);
+        let proj = Mat4::from_nonuniform_scale(2.0, 1.0, 1.0) * proj;
+        (views, [proj; 2])
+    }
+}
+
+/// The size of each eye's layer: the OpenXR swapchain's, or half the
+/// swapchain's width without one.
+pub(crate) fn eye_extent(data: &AppData) -> vk::Extent2D {
+    if data.xr_extent.width != 0 {
+        return data.xr_extent;
+    }
+    vk::Extent2D {
+        width: (data.swapchain_extent.width / 2).max(1),
+        height: data.swapchain_extent.height,
+    }
+}
+
+/// One layer per eye of HDR color and depth, and the framebuffer drawing
+/// both. The color is left ready for the mirror to sample.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct StereoTarget {
+    color_image: vk::Image,
+    color_image_memory: vk::DeviceMemory,
+    color_image_view: vk::ImageView,
+    depth_image: vk::Image,
+    depth_image_memory: vk::DeviceMemory,
+    depth_image_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+    /// Samples the color for the mirror and the OpenXR copy.
+    pub(crate) set: vk::DescriptorSet,
+}
+
+impl StereoTarget {
+    /// Records beginning the stereo render pass, whose draws go to both
+    /// eyes.
+    pub(crate) unsafe fn begin(&self, device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(eye_extent(data));
+
+        let clear_values = &[
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: far_depth(data.reverse_z),
+                    stencil: 0,
+                },
+            },
+        ];
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(data.stereo_render_pass)
+            .framebuffer(self.framebuffer)
+            .render_area(render_area)
+            .clear_values(clear_values);
+        device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+        // The stereo pipeline is a scene pipeline, with dynamic viewports.
+        set_viewport(device, command_buffer, render_area.build());
+    }
+
+    /// Records drawing both eyes side by side. Must be recorded in the
+    /// tonemap pass, after the stereo pass has ended.
+    pub(crate) unsafe fn record_mirror(&self, device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+        device.cmd_bind_pipeline(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            data.stereo_mirror_pipeline,
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            data.stereo_mirror_pipeline_layout,
+            0,
+            &[self.set],
+            &[],
+        );
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+    }
+
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.destroy_framebuffer(self.framebuffer, None);
+        device.destroy_image_view(self.color_image_view, None);
+        device.destroy_image(self.color_image, None);
+        free_memory(device, self.color_image_memory);
+        device.destroy_image_view(self.depth_image_view, None);
+        device.destroy_image(self.depth_image, None);
+        free_memory(device, self.depth_image_memory);
+    }
+}
+
+/// A render pass that broadcasts every draw to both eyes' layers. Always a
+/// render pass object, as multiview needs no more under dynamic rendering.
+/// Left null without multiview.
+pub(crate) unsafe fn create_stereo_render_pass(device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.multiview {
+        data.stereo_render_pass = vk::RenderPass::null();
+        return Ok(());
+    }
+
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(HDR_FORMAT)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+    let depth_stencil_attachment = vk::AttachmentDescription::builder()
+        .format(data.depth_format)
+        .samples(vk::SampleCountFlags::_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(discard_store_op(data))
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+
+    let depth_stencil_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(1)
+        .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+
+    let color_attachments = &[color_attachment_ref];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments)
+        .depth_stencil_attachment(&depth_stencil_attachment_ref);
+
+    // The last frame's mirror has to be done reading the color before it is
+    // cleared.
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        )
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        )
+        .dst_access_mask(
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        );
+
+    let mirror_dependency = vk::SubpassDependency::builder()
+        .src_subpass(0)
+        .dst_subpass(vk::SUBPASS_EXTERNAL)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+    // Both views are drawn together, and are close enough to be correlated.
+    let view_masks = &[VIEW_MASK];
+    let correlation_masks = &[VIEW_MASK];
+    let mut multiview_info = vk::RenderPassMultiviewCreateInfo::builder()
+        .view_masks(view_masks)
+        .correlation_masks(correlation_masks);
+
+    let attachments = &[color_attachment, depth_stencil_attachment];
+    let subpasses = &[subpass];
+    let dependencies = &[dependency, mirror_dependency];
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses)
+        .dependencies(dependencies)
+        .push_next(&mut multiview_info);
+
+    data.stereo_render_pass = device.create_render_pass(&info, None).unwrap();
+    Ok(())
+}
+
+pub(crate) unsafe fn create_stereo_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.stereo_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// Creates the multiview variants of the shaded and transparent scene
+/// pipelines, and a
+/// full-screen triangle drawn after the tonemap that replaces the mono view
+/// with both eyes. Does nothing without multiview.
+pub(crate) unsafe fn create_stereo_pipelines(device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.multiview {
+        return Ok(());
+    }
+
+    let desc = ScenePipelineDesc {
+        render_pass: data.stereo_render_pass,
+        extent: eye_extent(data),
+        samples: vk::SampleCountFlags::_1,
+        dynamic_rendering: false,
+        oit: false,
+        geometry: SceneGeometry::Vertex(STEREO_VERT),
+        frag: STEREO_FRAG,
+        ..ScenePipelineDesc::new(data)
+    };
+    data.stereo_pipeline = create_scene_pipeline(device, desc, SceneVariant::Shaded).unwrap();
+    data.stereo_transparent_pipeline = create_scene_pipeline(device, desc, SceneVariant::Transparent).unwrap();
+
+    let vert = include_bytes!("../../shaders/tonemap_vert.spv");
+    let frag = include_bytes!("../../shaders/stereo_mirror_frag.spv");
+
+    let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+    let frag_shader_module = create_shader_module(device, &frag[..]).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // Encoded as by the tonemap pass.
+    let constants = SpecializationConstants::new()
+        .bool(1, ColorSpace::of(data.swapchain_format) == ColorSpace::Linear)
+        .i32(2, data.swapchain_color_space as i32)
+        .f32(3, HDR_PAPER_WHITE);
+    let specialization_info = constants.info();
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let set_layouts = &[data.stereo_set_layout];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(set_layouts);
+    data.stereo_mirror_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let formats = Pass::Tonemap.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.stereo_mirror_pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(Pass::Tonemap.subpass(data));
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.stereo_mirror_pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(())
+}
+
+/// Creates the `StereoTarget` every swapchain image draws its eyes into.
+/// Does nothing without multiview.
+pub(crate) unsafe fn create_stereo_target(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    if !data.multiview {
+        return Ok(());
+    }
+
+    let extent = eye_extent(data);
+    let attachment = |format, usage, aspect_mask| {
+        let (image, image_memory) = create_image(
+            instance,
+            device,
+            data,
+            extent.width,
+            extent.height,
+            1,
+            2,
+            vk::SampleCountFlags::_1,
+            format,
+            vk::ImageTiling::OPTIMAL,
+            usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::empty(),
+        )
+        .unwrap();
+
+        let subresource_range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(2);
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::_2D_ARRAY)
+            .format(format)
+            .subresource_range(subresource_range);
+        let image_view = device.create_image_view(&info, None).unwrap();
+
+        (image, image_memory, image_view)
+    };
+
+    let (color_image, color_image_memory, color_image_view) = attachment(
+        HDR_FORMAT,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::ImageAspectFlags::COLOR,
+    );
+    let (depth_image, depth_image_memory, depth_image_view) = attachment(
+        data.depth_format,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::ImageAspectFlags::DEPTH,
+    );
+
+    // Multiview framebuffers have a single layer; the views pick theirs.
+    let attachments = &[color_image_view, depth_image_view];
+    let info = vk::FramebufferCreateInfo::builder()
+        .render_pass(data.stereo_render_pass)
+        .attachments(attachments)
+        .width(extent.width)
+        .height(extent.height)
+        .layers(1);
+    let framebuffer = device.create_framebuffer(&info, None).unwrap();
+
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    data.stereo_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = &[data.stereo_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.stereo_descriptor_pool)
+        .set_layouts(layouts);
+    let set = device.allocate_descriptor_sets(&info).unwrap()[0];
+
+    let image_info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(color_image_view)
+        .sampler(data.samplers.get(device, SamplerDesc::clamped()));
+    let image_infos = &[image_info];
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_infos);
+    device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+
+    data.stereo_target = StereoTarget {
+        color_image,
+        color_image_memory,
+        color_image_view,
+        depth_image,
+        depth_image_memory,
+        depth_image_view,
+        framebuffer,
+        set,
+    };
+    Ok(())
+}
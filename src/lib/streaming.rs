@@ -0,0 +1,298 @@
+use anyhow::Result;
+use log::*;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    allocations::free_memory,
+    app::AppData,
+    barrier::{cmd_barriers, queue_submit, ImageTransition},
+    image::create_image,
+    texture::{stage_layers, TextureData},
+    visibility::EntityVisibility,
+};
+
+/// Levels at least this small are uploaded when a streamed texture is
+/// created; larger ones follow once something needs them.
+const INITIAL_SIZE: u32 = 128;
+
+/// How the scene texture's mips are kept resident.
+///
+/// Applies to textures that ship their own mip chain (KTX2 and DDS files
+/// with mips). Every level is kept in system memory but only the levels the
+/// camera needs are kept on the GPU. When the wanted set changes the texture
+/// is re-uploaded into a new image in the background and swapped in once the
+/// transfer has finished.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextureStreaming {
+    /// When unset every level is streamed in and kept.
+    pub enabled: bool,
+    /// Bytes the resident levels may use. The largest levels are dropped
+    /// until the texture fits.
+    pub memory_budget: u64,
+    /// Texels wanted per pixel the textured model covers on screen. Values
+    /// below 1 trade sharpness for memory.
+    pub texels_per_pixel: f32,
+}
+
+impl Default for TextureStreaming {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            memory_budget: 64 * 1024 * 1024,
+            texels_per_pixel: 1.0,
+        }
+    }
+}
+
+/// A texture whose GPU image holds the levels from `first_level` down.
+#[derive(Clone, Debug)]
+pub(crate) struct StreamedTexture {
+    /// Every level, largest first.
+    source: TextureData,
+    pub(crate) first_level: u32,
+    upload: Option<Upload>,
+}
+
+/// A new image being filled on the graphics queue.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Upload {
+    first_level: u32,
+    image: vk::Image,
+    image_memory: vk::DeviceMemory,
+    staging_buffer: vk::Buffer,
+    staging_buffer_memory: vk::DeviceMemory,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+/// A finished upload, ready to replace the texture image.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ResidentLevels {
+    pub(crate) image: vk::Image,
+    pub(crate) image_memory: vk::DeviceMemory,
+    pub(crate) mip_levels: u32,
+}
+
+impl StreamedTexture {
+    /// Uploads the small end of `texture`'s mip chain and waits for it.
+    pub(crate) unsafe fn create(
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        texture: TextureData,
+    ) -> Result<(Self, ResidentLevels)> {
+        let count = texture.levels.len() as u32;
+        let first_level = (0..count)
+            .find(|l| (texture.width.max(texture.height) >> l) <= INITIAL_SIZE)
+            .unwrap_or(count - 1);
+
+        let mut streamed = Self {
+            source: texture,
+            first_level,
+            upload: None,
+        };
+        streamed.upload = Some(streamed.begin_upload(instance, device, data, first_level)?);
+        let upload = streamed.upload.unwrap();
+        device.wait_for_fences(&[upload.fence], true, u64::MAX).unwrap();
+        let resident = streamed.finish_upload(device, data).unwrap();
+        Ok((streamed, resident))
+    }
+
+    pub(crate) fn format(&self) -> vk::Format {
+        self.source.format
+    }
+
+    /// Width and height of the resident image.
+    pub(crate) fn resident_size(&self) -> (u32, u32) {
+        level_size(&self.source, self.first_level)
+    }
+
+    /// The largest level the camera needs, limited by the budget. Under
+    /// `memory_pressure` the largest resident levels are given up instead.
+    pub(crate) fn wanted_level(
+        &self,
+        settings: &TextureStreaming,
+        visibility: &[EntityVisibility],
+        extent: vk::Extent2D,
+        memory_pressure: bool,
+    ) -> u32 {
+        let last = self.source.levels.len() as u32 - 1;
+        if !settings.enabled {
+            return 0;
+        }
+
+        // The texture wraps the model, so it needs about as many texels
+        // across as the model covers pixels.
+        let pixels = visibility
+            .iter()
+            .filter(|v| v.visible)
+            .map(|v| v.screen_size * extent.height as f32)
+            .fold(0.0, f32::max);
+        let texels = (pixels * settings.texels_per_pixel).max(1.0);
+        let size = self.source.width.max(self.source.height) as f32;
+        let mut level = ((size / texels).log2().floor().max(0.0) as u32).min(last);
+
+        while level < last && self.resident_bytes(level) > settings.memory_budget {
+            level += 1;
+        }
+        // Past `request`'s hysteresis, so the levels are dropped.
+        if memory_pressure {
+            level = level.max(self.first_level + 2).min(last);
+        }
+        level
+    }
+
+    /// Starts streaming towards `level` unless an upload is in flight. Extra
+    /// levels are only dropped once they are two levels past what is
+    /// wanted, so the texture does not thrash around a boundary.
+    pub(crate) unsafe fn request(
+        &mut self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        level: u32,
+    ) -> Result<()> {
+        if self.upload.is_some() || (level >= self.first_level && level <= self.first_level + 1) {
+            return Ok(());
+        }
+
+        debug!("Streaming texture levels {}.. (resident {}..).", level, self.first_level);
+        self.upload = Some(self.begin_upload(instance, device, data, level)?);
+        Ok(())
+    }
+
+    /// Whether the upload in flight has finished on the GPU.
+    pub(crate) unsafe fn upload_finished(&self, device: &Device) -> bool {
+        self.upload
+            .map(|u| device.get_fence_status(u.fence) == Ok(vk::SuccessCode::SUCCESS))
+            .unwrap_or(false)
+    }
+
+    /// Releases the finished upload's temporary resources and hands over its
+    /// image. The caller destroys the image it replaces.
+    pub(crate) unsafe fn finish_upload(&mut self, device: &Device, data: &AppData) -> Option<ResidentLevels> {
+        let upload = self.upload.take()?;
+        device.destroy_fence(upload.fence, None);
+        device.free_command_buffers(data.command_pool, &[upload.command_buffer]);
+        device.destroy_buffer(upload.staging_buffer, None);
+        free_memory(device, upload.staging_buffer_memory);
+
+        self.first_level = upload.first_level;
+        Some(ResidentLevels {
+            image: upload.image,
+            image_memory: upload.image_memory,
+            mip_levels: self.source.levels.len() as u32 - upload.first_level,
+        })
+    }
+
+    /// Destroys an unfinished upload. The device must be idle.
+    pub(crate) unsafe fn destroy(&mut self, device: &Device, data: &AppData) {
+        if let Some(resident) = self.finish_upload(device, data) {
+            free_memory(device, resident.image_memory);
+            device.destroy_image(resident.image, None);
+        }
+    }
+
+    fn resident_bytes(&self, first_level: u32) -> u64 {
+        self.source.levels[first_level as usize..].iter().map(|l| l.len() as u64).sum()
+    }
+
+    /// Creates an image for the levels from `first_level` and submits their
+    /// upload with a fence instead of waiting for it.
+    unsafe fn begin_upload(
+        &self,
+        instance: &Instance,
+        device: &Device,
+        data: &AppData,
+        first_level: u32,
+    ) -> Result<Upload> {
+        let levels = &self.source.levels[first_level as usize..];
+        let (width, height) = level_size(&self.source, first_level);
+        let (staging_buffer, staging_buffer_memory, offsets) = stage_layers(instance, device, data, &[levels])?;
+
+        let (image, image_memory) = create_image(
+            instance,
+            device,
+            data,
+            width,
+            height,
+            levels.len() as u32,
+            1,
+            vk::SampleCountFlags::_1,
+            self.source.format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::empty(),
+        )?;
+
+        let info = vk::CommandBufferAllocateInfo::builder()
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_pool(data.command_pool)
+            .command_buffer_count(1);
+        let command_buffer = device.allocate_command_buffers(&info).unwrap()[0];
+
+        let info = vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(command_buffer, &info).unwrap();
+
+        let transition = ImageTransition::new(image, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .levels(0..levels.len() as u32);
+        cmd_barriers(device, data, command_buffer, &[transition], &[]);
+
+        let regions = offsets[0]
+            .iter()
+            .enumerate()
+            .map(|(level, offset)| {
+                let subresource = vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(level as u32)
+                    .base_array_layer(0)
+                    .layer_count(1);
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(*offset)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(subresource)
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width: (width >> level).max(1),
+                        height: (height >> level).max(1),
+                        depth: 1,
+                    })
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &regions,
+        );
+
+        let transition =
+            ImageTransition::new(image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .levels(0..levels.len() as u32);
+        cmd_barriers(device, data, command_buffer, &[transition], &[]);
+
+        device.end_command_buffer(command_buffer).unwrap();
+
+        let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None).unwrap();
+        queue_submit(device, data, data.graphics_queue, &[command_buffer], &[], &[], fence).unwrap();
+
+        Ok(Upload {
+            first_level,
+            image,
+            image_memory,
+            staging_buffer,
+            staging_buffer_memory,
+            command_buffer,
+            fence,
+        })
+    }
+}
+
+fn level_size(texture: &TextureData, level: u32) -> (u32, u32) {
+    ((texture.width >> level).max(1), (texture.height >> level).max(1))
+}
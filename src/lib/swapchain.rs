@@ -1,4 +1,5 @@
 use anyhow::Result;
+use log::*;
 
 use winit::window::Window;
 
@@ -7,7 +8,10 @@ use vulkanalia::{
     vk::{KhrSurfaceExtension, KhrSwapchainExtension},
 };
 
-use crate::{app::AppData, image::create_image_view, physical_device::QueueFamilyIndices};
+use crate::{
+    app::AppData, image::create_image_view, physical_device::QueueFamilyIndices,
+    texture::{format_supports, ColorSpace},
+};
 
 #[derive(Clone, Debug)]
 pub(crate) struct SwapchainSupport {
@@ -21,31 +25,104 @@ impl SwapchainSupport {
         instance: &Instance,
         data: &AppData,
         physical_device: vk::PhysicalDevice,
+    ) -> Result<Self> {
+        Self::for_surface(instance, physical_device, data.surface)
+    }
+
+    pub(crate) unsafe fn for_surface(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        surface: vk::SurfaceKHR,
     ) -> Result<Self> {
         Ok(Self {
             capabilities: instance
-                .get_physical_device_surface_capabilities_khr(physical_device, data.surface)
-                .unwrap(),
+                .get_physical_device_surface_capabilities_khr(physical_device, surface)?,
             formats: instance
-                .get_physical_device_surface_formats_khr(physical_device, data.surface)
-                .unwrap(),
+                .get_physical_device_surface_formats_khr(physical_device, surface)?,
             present_modes: instance
-                .get_physical_device_surface_present_modes_khr(physical_device, data.surface)
-                .unwrap(),
+                .get_physical_device_surface_present_modes_khr(physical_device, surface)?,
         })
     }
 }
 
+/// Nits that `1.0` out of the tonemapper is shown at on HDR displays, the
+/// reference white of ITU-R BT.2408.
+pub(crate) const HDR_PAPER_WHITE: f32 = 203.0;
+
+/// The color space the swapchain is presented in. The HDR ones need a
+/// display and driver that offer them, and fall back to `Srgb` otherwise,
+/// see `App::presented_color_space`.
+///
+/// The tonemapped image is placed at `HDR_PAPER_WHITE` in the HDR ones
+/// rather than stretched over the display's range. Auto-exposure metering,
+/// focus blur and mirroring to secondary windows read the presented image
+/// and are only available in `Srgb`.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SwapchainColorSpace {
+    /// sRGB encoded in an 8-bit format.
+    #[default]
+    Srgb = 0,
+    /// Rec. 2020 primaries and the PQ curve in a 10-bit format.
+    Hdr10 = 1,
+    /// Linear, extended sRGB in a half float format, with 1.0 at 80 nits.
+    ScRgb = 2,
+}
+
+impl SwapchainColorSpace {
+    pub const ALL: [SwapchainColorSpace; 3] =
+        [SwapchainColorSpace::Srgb, SwapchainColorSpace::Hdr10, SwapchainColorSpace::ScRgb];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub(crate) fn of(color_space: vk::ColorSpaceKHR) -> Self {
+        match color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => Self::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => Self::ScRgb,
+            _ => Self::Srgb,
+        }
+    }
+
+    /// The surface formats that present in this color space, best first.
+    fn surface_formats(self) -> Vec<vk::SurfaceFormatKHR> {
+        let (color_space, formats) = match self {
+            Self::Srgb => return vec![],
+            Self::Hdr10 => (
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                &[vk::Format::A2B10G10R10_UNORM_PACK32, vk::Format::A2R10G10B10_UNORM_PACK32][..],
+            ),
+            Self::ScRgb => (vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT, &[vk::Format::R16G16B16A16_SFLOAT][..]),
+        };
+        formats
+            .iter()
+            .map(|&format| vk::SurfaceFormatKHR { format, color_space })
+            .collect()
+    }
+}
+
+/// Picks the first format `color_space` offers, if the surface has one.
+/// Otherwise prefers an 8-bit RGBA format in the requested encoding, then
+/// any format that is presented as sRGB. Whichever is picked, the tonemap
+/// pass encodes by hand when the format will not.
 pub(crate) fn get_swapchain_surface_format(
     formats: &[vk::SurfaceFormatKHR],
+    srgb: bool,
+    color_space: SwapchainColorSpace,
 ) -> vk::SurfaceFormatKHR {
-    formats
+    if let Some(format) = color_space.surface_formats().into_iter().find(|f| formats.contains(f)) {
+        return format;
+    }
+
+    let color_space = if srgb { ColorSpace::Srgb } else { ColorSpace::Linear };
+    let preferred = [vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM].map(|f| color_space.apply(f));
+    let nonlinear = || formats.iter().filter(|f| f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR);
+    preferred
         .iter()
+        .find_map(|p| nonlinear().find(|f| f.format == *p))
+        .or_else(|| nonlinear().next())
         .cloned()
-        .find(|f| {
-            f.format == vk::Format::B8G8R8A8_SRGB
-                && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        })
         .unwrap_or_else(|| formats[0])
 }
 
@@ -92,14 +169,36 @@ pub(crate) unsafe fn create_swapchain(
     let indices = QueueFamilyIndices::get(instance, data, data.physical_device).unwrap();
     let support = SwapchainSupport::get(instance, data, data.physical_device).unwrap();
 
-    let surface_format = get_swapchain_surface_format(&support.formats);
+    let surface_format =
+        get_swapchain_surface_format(&support.formats, data.srgb_swapchain, data.preferred_color_space);
+    if data.preferred_color_space != SwapchainColorSpace::of(surface_format.color_space) {
+        warn!("The surface does not offer {:?}, presenting in sRGB.", data.preferred_color_space);
+    }
     let present_mode = get_swapchain_present_mode(&support.present_modes);
     let extent = get_swapchain_extent(window, support.capabilities);
 
     data.swapchain_format = surface_format.format;
+    data.swapchain_color_space = SwapchainColorSpace::of(surface_format.color_space);
     data.swapchain_extent = extent;
+
+    // Without dynamic rendering auto-exposure blits the presented image down
+    // to meter it, which only works on sRGB encoded color.
+    data.metering_supported = support
+        .capabilities
+        .supported_usage_flags
+        .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        && data.swapchain_color_space == SwapchainColorSpace::Srgb
+        && format_supports(instance, data, surface_format.format, vk::FormatFeatureFlags::BLIT_SRC);
+    let image_usage = if data.metering_supported {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC
+    } else {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT
+    };
     
-    let mut image_count = support.capabilities.min_image_count + 1;
+    let mut image_count = match data.min_swapchain_images {
+        0 => support.capabilities.min_image_count + 1,
+        count => count.max(support.capabilities.min_image_count),
+    };
 
     if support.capabilities.max_image_count != 0
         && image_count > support.capabilities.max_image_count
@@ -116,14 +215,17 @@ pub(crate) unsafe fn create_swapchain(
         vk::SharingMode::EXCLUSIVE
     };
 
-    let info = vk::SwapchainCreateInfoKHR::builder()
+    let mut full_screen_exclusive =
+        vk::SurfaceFullScreenExclusiveInfoEXT::builder().full_screen_exclusive(data.window_mode.full_screen_exclusive());
+
+    let mut info = vk::SwapchainCreateInfoKHR::builder()
         .surface(data.surface)
         .min_image_count(image_count)
         .image_format(surface_format.format)
         .image_color_space(surface_format.color_space)
         .image_extent(extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(image_usage)
         .image_sharing_mode(image_sharing_mode)
         .queue_family_indices(&queue_family_indices)
         .pre_transform(support.capabilities.current_transform)
@@ -132,7 +234,10 @@ pub(crate) unsafe fn create_swapchain(
         .clipped(true)
         .old_swapchain(vk::SwapchainKHR::null());
 
-    
+    if data.full_screen_exclusive {
+        info = info.push_next(&mut full_screen_exclusive);
+    }
+
     data.swapchain = device.create_swapchain_khr(&info, None).unwrap();
     data.swapchain_images = device.get_swapchain_images_khr(data.swapchain).unwrap();
 
@@ -1,27 +1,104 @@
 use anyhow::Result;
 
-use vulkanalia::prelude::v1_0::*;
+use vulkanalia::{
+  prelude::v1_0::*,
+  vk::{DeviceV1_2, KhrTimelineSemaphoreExtension},
+};
 
 use crate::app::{AppData, MAX_FRAMES_IN_FLIGHT};
 
 pub(crate) unsafe fn create_sync_objects(device: &Device, data: &mut AppData) -> Result<()> {
-  let semaphore_info = vk::SemaphoreCreateInfo::builder();
-  let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
-
-  for _ in 0..MAX_FRAMES_IN_FLIGHT {
-      data.image_available_semaphore
-          .push(device.create_semaphore(&semaphore_info, None).unwrap());
-      data.render_finished_semaphore
-          .push(device.create_semaphore(&semaphore_info, None).unwrap());
+  create_acquire_semaphores(device, data)?;
 
-      data.in_flight_fences
-          .push(device.create_fence(&fence_info, None).unwrap());
+  if data.capabilities.timeline_semaphore {
+      let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+          .semaphore_type(vk::SemaphoreType::TIMELINE)
+          .initial_value(0);
+      let timeline_info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+      data.frame_timeline = device.create_semaphore(&timeline_info, None).unwrap();
+  } else {
+      // Signaled, so that waiting for a frame that was never submitted
+      // returns at once.
+      let fence_info = vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
+      data.frame_fences = (0..MAX_FRAMES_IN_FLIGHT)
+          .map(|_| device.create_fence(&fence_info, None))
+          .collect::<Result<Vec<_>, _>>()
+          .unwrap();
   }
+  data.frame_number = 0;
+  Ok(())
+}
 
-  data.images_in_flight = data
+/// Creates an image available semaphore for each of `data.frames_in_flight`.
+/// One is next reused that many frames later, after `App::render` has waited
+/// for the submit that waited on it.
+pub(crate) unsafe fn create_acquire_semaphores(device: &Device, data: &mut AppData) -> Result<()> {
+  let semaphore_info = vk::SemaphoreCreateInfo::builder();
+  data.image_available_semaphore = (0..data.frames_in_flight)
+      .map(|_| device.create_semaphore(&semaphore_info, None))
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap();
+  Ok(())
+}
+
+/// Creates a render finished semaphore for each swapchain image. Present
+/// gives no signal for when it has waited on its semaphore, so one per
+/// frame in flight could be signaled again while a present of another image
+/// still waits on it. Once an image is acquired again, its last present has
+/// consumed its semaphore.
+pub(crate) unsafe fn create_present_semaphores(device: &Device, data: &mut AppData) -> Result<()> {
+  let semaphore_info = vk::SemaphoreCreateInfo::builder();
+  data.render_finished_semaphore = data
       .swapchain_images
       .iter()
-      .map(|_| vk::Fence::null())
-      .collect();
+      .map(|_| device.create_semaphore(&semaphore_info, None))
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap();
+
+  // Called with the device idle, so every image is free.
+  data.image_frames = vec![0; data.swapchain_images.len()];
+  Ok(())
+}
+
+/// The fence frame `number` signals in place of the frame timeline, or null
+/// if the device has timeline semaphores. It is reset once the frame that
+/// last signaled it, `MAX_FRAMES_IN_FLIGHT` frames earlier, has finished.
+pub(crate) unsafe fn frame_fence(device: &Device, data: &AppData, number: u64) -> Result<vk::Fence> {
+  if data.capabilities.timeline_semaphore {
+      return Ok(vk::Fence::null());
+  }
+
+  let fence = data.frame_fences[number as usize % data.frame_fences.len()];
+  device.wait_for_fences(&[fence], true, u64::MAX)?;
+  device.reset_fences(&[fence])?;
+  Ok(fence)
+}
+
+/// Blocks until frame `number` has finished on the device. Frame 0 is never
+/// submitted, so waiting for it returns at once.
+pub(crate) unsafe fn wait_for_frame(device: &Device, data: &AppData, number: u64) -> Result<()> {
+  if !data.capabilities.timeline_semaphore {
+      // A frame whose fence a later frame has since reused has finished,
+      // see `frame_fence`.
+      let fences = data.frame_fences.len() as u64;
+      if number == 0 || number + fences <= data.frame_number {
+          return Ok(());
+      }
+      let fence = data.frame_fences[(number % fences) as usize];
+      device.wait_for_fences(&[fence], true, u64::MAX)?;
+      return Ok(());
+  }
+
+  let semaphores = &[data.frame_timeline];
+  let values = &[number];
+  let info = vk::SemaphoreWaitInfo::builder()
+      .semaphores(semaphores)
+      .values(values);
+
+  if data.timeline_semaphore_extension {
+      device.wait_semaphores_khr(&info, u64::MAX)?;
+  } else {
+      device.wait_semaphores(&info, u64::MAX)?;
+  }
   Ok(())
-}
\ No newline at end of file
+}
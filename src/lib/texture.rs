@@ -1,21 +1,177 @@
-use anyhow::Result;
-use std::{fs::File, ptr::copy_nonoverlapping as memcpy};
+use anyhow::{anyhow, Result};
+use cgmath::{vec3, InnerSpace};
+use exr::prelude::f16;
+use log::*;
+use std::{
+    f32::consts::PI,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    ptr::copy_nonoverlapping as memcpy,
+};
 
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
+    allocations::free_memory,
     app::AppData,
+    block_compression::{block_size, decode_block_compressed, is_block_compressed},
+    dds::load_dds,
     generate_mipmaps::generate_mipmaps,
-    image::{copy_buffer_to_image, create_image, create_image_view, transition_image_layout},
+    hdr::{load_exr, load_radiance, HDR_TEXTURE_FORMAT},
+    image::{
+        copy_buffer_to_image_layers, copy_buffer_to_image_levels, create_image, create_image_view,
+        create_layered_image_view, transition_image_layout,
+    },
+    ktx2::load_ktx2,
+    mipmap::{compute_mipmaps_image_flags, compute_mipmaps_supported, generate_mipmaps_compute},
+    sampler::SamplerDesc,
+    streaming::StreamedTexture,
+    types::Vec3,
     vertex_buffer::create_buffer,
 };
 
-pub(crate) unsafe fn create_texture_image(
-    instance: &Instance,
-    device: &Device,
-    data: &mut AppData,
-) -> Result<()> {
-    let image = File::open("resources/viking_room.png").unwrap();
+/// Looked up in order; the first file that exists is loaded.
+const TEXTURE_PATHS: &[&str] = &[
+    "resources/viking_room.ktx2",
+    "resources/viking_room.dds",
+    "resources/viking_room.png",
+];
+
+/// Formats that differ only in whether sampling decodes sRGB, as
+/// `(UNORM, SRGB)` pairs.
+const SRGB_PAIRS: &[(vk::Format, vk::Format)] = &[
+    (vk::Format::R8G8B8A8_UNORM, vk::Format::R8G8B8A8_SRGB),
+    (vk::Format::B8G8R8A8_UNORM, vk::Format::B8G8R8A8_SRGB),
+    (vk::Format::A8B8G8R8_UNORM_PACK32, vk::Format::A8B8G8R8_SRGB_PACK32),
+    (vk::Format::BC1_RGBA_UNORM_BLOCK, vk::Format::BC1_RGBA_SRGB_BLOCK),
+    (vk::Format::BC2_UNORM_BLOCK, vk::Format::BC2_SRGB_BLOCK),
+    (vk::Format::BC3_UNORM_BLOCK, vk::Format::BC3_SRGB_BLOCK),
+    (vk::Format::BC7_UNORM_BLOCK, vk::Format::BC7_SRGB_BLOCK),
+];
+
+/// How a texture's values are interpreted when sampled.
+///
+/// Colors authored in an image editor (albedo, emissive, UI) are sRGB encoded
+/// and must be decoded before lighting. Normal maps, roughness and other data
+/// are stored linearly; decoding them bends every value towards zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    /// The encoding `format` is sampled with. Formats without an sRGB variant
+    /// (single and two channel, float) are linear.
+    pub(crate) fn of(format: vk::Format) -> Self {
+        if SRGB_PAIRS.iter().any(|(_, srgb)| *srgb == format) {
+            ColorSpace::Srgb
+        } else {
+            ColorSpace::Linear
+        }
+    }
+
+    /// `format` reinterpreted in this color space. Formats without an sRGB
+    /// variant are returned unchanged.
+    pub(crate) fn apply(self, format: vk::Format) -> vk::Format {
+        SRGB_PAIRS
+            .iter()
+            .find(|(unorm, srgb)| *unorm == format || *srgb == format)
+            .map(|(unorm, srgb)| match self {
+                ColorSpace::Srgb => *srgb,
+                ColorSpace::Linear => *unorm,
+            })
+            .unwrap_or(format)
+    }
+}
+
+/// The largest width or height a texture file may declare. Devices limit 2D
+/// images to 16384 or so, and anything larger is more likely a corrupt
+/// header than a texture, which would otherwise size the buffers it is
+/// decoded into.
+pub(crate) const MAX_TEXTURE_DIMENSION: u32 = 16384;
+
+/// Checks the size and mip count a texture file declares before anything is
+/// allocated for it.
+pub(crate) fn check_texture_size(width: u32, height: u32, mip_levels: u32) -> Result<()> {
+    if width == 0 || height == 0 || width.max(height) > MAX_TEXTURE_DIMENSION {
+        return Err(anyhow!("Unsupported texture size {}x{}.", width, height));
+    }
+    let max_levels = width.max(height).ilog2() + 1;
+    if mip_levels > max_levels {
+        return Err(anyhow!(
+            "{} mip levels, more than the {} of a {}x{} texture.",
+            mip_levels,
+            max_levels,
+            width,
+            height
+        ));
+    }
+    Ok(())
+}
+
+/// Bytes in one `width` by `height` level of `format`, or `None` for formats
+/// textures are not loaded in.
+pub(crate) fn level_size(format: vk::Format, width: u32, height: u32) -> Option<usize> {
+    if let Some(block) = block_size(format) {
+        let blocks = (width.div_ceil(4) as usize).checked_mul(height.div_ceil(4) as usize)?;
+        return blocks.checked_mul(block);
+    }
+
+    let texel = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB | vk::Format::R16_SFLOAT => 2,
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::E5B9G9R9_UFLOAT_PACK32
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT => 4,
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT => 8,
+        vk::Format::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    };
+    (width as usize).checked_mul(height as usize)?.checked_mul(texel)
+}
+
+/// Decoded texture contents, one buffer per mip level (largest first).
+#[derive(Clone, Debug)]
+pub(crate) struct TextureData {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) format: vk::Format,
+    pub(crate) levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    /// Overrides the encoding the file declared. Many tools write sRGB
+    /// content with UNORM formats (and legacy DDS files cannot say either
+    /// way), so callers that know what a texture holds should say so.
+    pub(crate) fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.format = color_space.apply(self.format);
+        self
+    }
+}
+
+/// Keeps the format the file declares. PNGs cannot declare one and are
+/// loaded as sRGB; Radiance and OpenEXR images are loaded as half floats.
+pub(crate) fn load_texture(path: &Path) -> Result<TextureData> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("ktx2") => load_ktx2(&fs::read(path)?),
+        Some("dds") => load_dds(&fs::read(path)?),
+        Some("hdr") => load_radiance(&fs::read(path)?),
+        Some("exr") => load_exr(path),
+        _ => load_png(path),
+    }
+}
+
+fn load_png(path: &Path) -> Result<TextureData> {
+    let image = File::open(path)?;
 
     let decoder = png::Decoder::new(image);
     let mut reader = decoder.read_info().unwrap();
@@ -23,13 +179,65 @@ pub(crate) unsafe fn create_texture_image(
     let mut pixels = vec![0; reader.info().raw_bytes()];
     reader.next_frame(&mut pixels).unwrap();
 
-    let size = reader.info().raw_bytes() as u64;
     let (width, height) = reader.info().size();
-    data.mip_levels = (width.max(height) as f32).log2().floor() as u32 + 1;
 
-    if width != 1024 || height != 1024 || reader.info().color_type != png::ColorType::Rgba {
-        panic!("Invalid texture image.");
+    if reader.info().color_type != png::ColorType::Rgba {
+        return Err(anyhow!("Invalid texture image."));
+    }
+
+    Ok(TextureData {
+        width,
+        height,
+        format: vk::Format::R8G8B8A8_SRGB,
+        levels: vec![pixels],
+    })
+}
+
+pub(crate) unsafe fn create_texture_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    let path = TEXTURE_PATHS
+        .iter()
+        .map(Path::new)
+        .find(|p| p.exists())
+        .ok_or_else(|| anyhow!("No texture found."))?;
+    // The model's texture is albedo.
+    let texture = load_texture(path).unwrap().with_color_space(ColorSpace::Srgb);
+    info!("Loaded texture `{}` ({:?}).", path.display(), texture.format);
+    let texture = decode_if_unsupported(instance, data, texture)?;
+
+    // Assets that ship their own mips are streamed.
+    if texture.levels.len() > 1 {
+        let (streamed, resident) = StreamedTexture::create(instance, device, data, texture)?;
+        data.texture_format = streamed.format();
+        data.texture_image = resident.image;
+        data.texture_image_memory = resident.image_memory;
+        data.mip_levels = resident.mip_levels;
+        data.streamed_texture = Some(streamed);
+        return Ok(());
+    }
+
+    // Otherwise prefer the single-dispatch compute downsampler, falling back
+    // to a blit chain.
+    // Block-compressed mips can be neither blitted nor written from a shader.
+    let (width, height) = (texture.width, texture.height);
+    let generate = texture.levels.len() == 1 && !is_block_compressed(texture.format);
+    let compute = generate && compute_mipmaps_supported(data, texture.format, width, height);
+    let blit = generate && !compute && can_blit_mipmaps(instance, data, texture.format);
+    let generate = compute || blit;
+    if texture.levels.len() == 1 && !generate {
+        warn!("Cannot generate mipmaps for {:?}.", texture.format);
     }
+    data.texture_format = texture.format;
+    data.mip_levels = if generate {
+        (width.max(height) as f32).log2().floor() as u32 + 1
+    } else {
+        texture.levels.len() as u32
+    };
+
+    let size = texture.levels.iter().map(|l| l.len() as u64).sum::<u64>();
 
     let (staging_buffer, staging_buffer_memory) = create_buffer(
         instance,
@@ -42,10 +250,24 @@ pub(crate) unsafe fn create_texture_image(
 
     let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty()).unwrap();
 
-    memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
+    let mut offsets = Vec::with_capacity(texture.levels.len());
+    let mut offset = 0;
+    for level in &texture.levels {
+        memcpy(level.as_ptr(), memory.cast::<u8>().add(offset), level.len());
+        offsets.push(offset as u64);
+        offset += level.len();
+    }
 
     device.unmap_memory(staging_buffer_memory);
 
+    let (flags, extra_usage) = if compute {
+        compute_mipmaps_image_flags(data.texture_format)
+    } else if blit {
+        (vk::ImageCreateFlags::empty(), vk::ImageUsageFlags::TRANSFER_SRC)
+    } else {
+        (vk::ImageCreateFlags::empty(), vk::ImageUsageFlags::empty())
+    };
+
     let (texture_image, texture_image_memory) = create_image(
         instance,
         device,
@@ -53,13 +275,13 @@ pub(crate) unsafe fn create_texture_image(
         width,
         height,
         data.mip_levels,
+        1,
         vk::SampleCountFlags::_1,
-        vk::Format::R8G8B8A8_SRGB,
+        data.texture_format,
         vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::SAMPLED
-            | vk::ImageUsageFlags::TRANSFER_DST
-            | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST | extra_usage,
         vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        flags,
     )
     .unwrap();
 
@@ -70,61 +292,89 @@ pub(crate) unsafe fn create_texture_image(
         device,
         data,
         data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        data.texture_format,
         vk::ImageLayout::UNDEFINED,
         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
         data.mip_levels,
+        0..1,
     )
     .unwrap();
 
-    copy_buffer_to_image(
+    copy_buffer_to_image_levels(
         device,
         data,
         staging_buffer,
         data.texture_image,
         width,
         height,
+        &offsets,
     )
     .unwrap();
 
     device.destroy_buffer(staging_buffer, None);
-    device.free_memory(staging_buffer_memory, None);
+    free_memory(device, staging_buffer_memory);
 
-    generate_mipmaps(
-        instance,
-        device,
-        data,
-        data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
-        width,
-        height,
-        data.mip_levels,
-    )
-    .unwrap();
+    if compute {
+        generate_mipmaps_compute(
+            instance,
+            device,
+            data,
+            data.texture_image,
+            data.texture_format,
+            width,
+            height,
+            data.mip_levels,
+        )
+        .unwrap();
+    } else if blit {
+        generate_mipmaps(
+            instance,
+            device,
+            data,
+            data.texture_image,
+            data.texture_format,
+            width,
+            height,
+            data.mip_levels,
+            0..1,
+        )
+        .unwrap();
+    } else {
+        transition_image_layout(
+            device,
+            data,
+            data.texture_image,
+            data.texture_format,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            data.mip_levels,
+            0..1,
+        )
+        .unwrap();
+    }
 
     Ok(())
 }
 
-pub(crate) unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Result<()> {
-    let info = vk::SamplerCreateInfo::builder()
-        .mag_filter(vk::Filter::LINEAR)
-        .min_filter(vk::Filter::LINEAR)
-        .address_mode_u(vk::SamplerAddressMode::REPEAT)
-        .address_mode_v(vk::SamplerAddressMode::REPEAT)
-        .address_mode_w(vk::SamplerAddressMode::REPEAT)
-        .anisotropy_enable(true)
-        .max_anisotropy(16.0)
-        .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-        .unnormalized_coordinates(false)
-        .compare_enable(false)
-        .compare_op(vk::CompareOp::ALWAYS)
-        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-        .mip_lod_bias(0.0)
-        .min_lod(0.0)
-        .max_lod(data.mip_levels as f32);
-
-    data.texture_sampler = device.create_sampler(&info, None).unwrap();
+pub(crate) unsafe fn format_supports(
+    instance: &Instance,
+    data: &AppData,
+    format: vk::Format,
+    features: vk::FormatFeatureFlags,
+) -> bool {
+    if is_block_compressed(format) && !data.texture_compression_bc {
+        return false;
+    }
 
+    instance
+        .get_physical_device_format_properties(data.physical_device, format)
+        .optimal_tiling_features
+        .contains(features)
+}
+
+/// The scene texture is sampled trilinearly with full anisotropy.
+pub(crate) unsafe fn create_texture_sampler(device: &Device, data: &mut AppData) -> Result<()> {
+    data.texture_sampler = data.samplers.get(device, SamplerDesc::default());
     Ok(())
 }
 
@@ -132,10 +382,510 @@ pub(crate) unsafe fn create_texture_image_view(device: &Device, data: &mut AppDa
     data.texture_image_view = create_image_view(
         device,
         data.texture_image,
-        vk::Format::R8G8B8A8_SRGB,
+        data.texture_format,
         vk::ImageAspectFlags::COLOR,
         data.mip_levels,
     )
     .unwrap();
     Ok(())
 }
+
+/// Where a cubemap's faces come from. Faces follow Vulkan's cube layout, so
+/// sample the result with a Y-up direction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CubemapSource {
+    /// One square image per face, in +X, -X, +Y, -Y, +Z, -Z order.
+    Faces([PathBuf; 6]),
+    /// A horizontal (4x3 faces) or vertical (3x4 faces) cross.
+    Cross(PathBuf),
+    /// A 2:1 latitude-longitude panorama, resampled to faces a quarter of its
+    /// width across.
+    Equirectangular(PathBuf),
+}
+
+/// An image with several array layers sharing a size, format and mip count,
+/// viewed as a cube or a 2D array.
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct LayeredTexture {
+    pub(crate) image: vk::Image,
+    pub(crate) image_memory: vk::DeviceMemory,
+    pub(crate) image_view: vk::ImageView,
+    pub(crate) format: vk::Format,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) layers: u32,
+    pub(crate) mip_levels: u32,
+    /// Owned by the sampler cache.
+    pub(crate) sampler: vk::Sampler,
+}
+
+impl LayeredTexture {
+    pub(crate) unsafe fn destroy(&self, device: &Device) {
+        device.destroy_image_view(self.image_view, None);
+        free_memory(device, self.image_memory);
+        device.destroy_image(self.image, None);
+    }
+}
+
+/// Six layers with a `CUBE` view.
+pub(crate) type Cubemap = LayeredTexture;
+
+/// A `2D_ARRAY` view, sampled with `sampler2DArray`.
+pub(crate) type TextureArray = LayeredTexture;
+
+/// Loads the six faces of a cubemap, in +X, -X, +Y, -Y, +Z, -Z order.
+pub(crate) fn load_cubemap(source: &CubemapSource) -> Result<Vec<TextureData>> {
+    let faces = match source {
+        CubemapSource::Faces(paths) => paths.iter().map(|p| load_texture(p)).collect::<Result<Vec<_>>>()?,
+        CubemapSource::Cross(path) => split_cross(&load_texture(path)?)?,
+        CubemapSource::Equirectangular(path) => resample_equirectangular(&load_texture(path)?)?,
+    };
+
+    let first = &faces[0];
+    if first.width != first.height {
+        return Err(anyhow!("Cubemap faces must be square."));
+    }
+    if faces.iter().any(|f| {
+        f.width != first.width || f.height != first.height || f.format != first.format || f.levels.len() != first.levels.len()
+    }) {
+        return Err(anyhow!("Cubemap faces must share a size, format and mip count."));
+    }
+
+    Ok(faces)
+}
+
+/// Cuts the faces out of a cross. Vertical crosses store -Z upside down
+/// below -Y.
+fn split_cross(texture: &TextureData) -> Result<Vec<TextureData>> {
+    let (width, height) = (texture.width, texture.height);
+    let (size, vertical) = if width * 3 == height * 4 {
+        (width / 4, false)
+    } else if width * 4 == height * 3 {
+        (width / 3, true)
+    } else {
+        return Err(anyhow!("A cubemap cross must be 4x3 or 3x4 faces, not {}x{}.", width, height));
+    };
+    let pixels = &texture.levels[0];
+    let texel = texel_size(texture)?;
+
+    let negative_z = if vertical { (1, 3) } else { (3, 1) };
+    let cells = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), negative_z];
+    Ok(cells
+        .iter()
+        .enumerate()
+        .map(|(face, (column, row))| {
+            let rotate = vertical && face == 5;
+            let mut levels = vec![vec![0; (size * size) as usize * texel]];
+            for y in 0..size {
+                for x in 0..size {
+                    let (sx, sy) = if rotate { (size - 1 - x, size - 1 - y) } else { (x, y) };
+                    let source = ((row * size + sy) * width + column * size + sx) as usize * texel;
+                    let target = (y * size + x) as usize * texel;
+                    levels[0][target..target + texel].copy_from_slice(&pixels[source..source + texel]);
+                }
+            }
+            TextureData {
+                width: size,
+                height: size,
+                format: texture.format,
+                levels,
+            }
+        })
+        .collect())
+}
+
+/// Bilinearly resamples a panorama onto six faces. Filtering happens in the
+/// panorama's stored encoding.
+fn resample_equirectangular(texture: &TextureData) -> Result<Vec<TextureData>> {
+    let (width, height) = (texture.width as usize, texture.height as usize);
+    let pixels = &texture.levels[0];
+    let texel = texel_size(texture)?;
+    let size = (width / 4).max(1);
+
+    let fetch = |x: usize, y: usize| read_texel(texture.format, &pixels[(y * width + x) * texel..]);
+    let sample = |u: f32, v: f32| {
+        let x = u * width as f32 - 0.5;
+        let y = (v * height as f32 - 0.5).clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let wrap = |x: f32| (x as isize).rem_euclid(width as isize) as usize;
+        let (x0, x1) = (wrap(x0), wrap(x0 + 1.0));
+        let (y0, y1) = (y0 as usize, (y0 as usize + 1).min(height - 1));
+        let (a, b, c, d) = (fetch(x0, y0), fetch(x1, y0), fetch(x0, y1), fetch(x1, y1));
+        [0, 1, 2, 3].map(|i| {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            top + (bottom - top) * fy
+        })
+    };
+
+    Ok((0..6)
+        .map(|face| {
+            let mut face_pixels = vec![0; size * size * texel];
+            for y in 0..size {
+                for x in 0..size {
+                    let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let direction = cube_direction(face, s, t).normalize();
+                    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+                    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+                    let offset = (y * size + x) * texel;
+                    write_texel(texture.format, &mut face_pixels[offset..], sample(u, v));
+                }
+            }
+            TextureData {
+                width: size as u32,
+                height: size as u32,
+                format: texture.format,
+                levels: vec![face_pixels],
+            }
+        })
+        .collect())
+}
+
+/// The direction through `(s, t)` in `-1..1` on `face`, per the Vulkan cube
+/// face selection table.
+fn cube_direction(face: usize, s: f32, t: f32) -> Vec3 {
+    match face {
+        0 => vec3(1.0, -t, -s),
+        1 => vec3(-1.0, -t, s),
+        2 => vec3(s, 1.0, t),
+        3 => vec3(s, -1.0, -t),
+        4 => vec3(s, -t, 1.0),
+        _ => vec3(-s, -t, -1.0),
+    }
+}
+
+/// Bytes per texel of the formats cubemaps can be cut from.
+fn texel_size(texture: &TextureData) -> Result<usize> {
+    match texture.format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => Ok(4),
+        HDR_TEXTURE_FORMAT => Ok(8),
+        format => Err(anyhow!("Cannot split a {:?} image into cubemap faces.", format)),
+    }
+}
+
+/// The texel at the start of `bytes`, in its stored encoding (0-255 for
+/// 8-bit formats).
+fn read_texel(format: vk::Format, bytes: &[u8]) -> [f32; 4] {
+    if format == HDR_TEXTURE_FORMAT {
+        [0, 1, 2, 3].map(|c| f16::from_le_bytes([bytes[c * 2], bytes[c * 2 + 1]]).to_f32())
+    } else {
+        [0, 1, 2, 3].map(|c| bytes[c] as f32)
+    }
+}
+
+fn write_texel(format: vk::Format, bytes: &mut [u8], texel: [f32; 4]) {
+    for (c, value) in texel.iter().enumerate() {
+        if format == HDR_TEXTURE_FORMAT {
+            bytes[c * 2..c * 2 + 2].copy_from_slice(&f16::from_f32(*value).to_le_bytes());
+        } else {
+            bytes[c] = value.round() as u8;
+        }
+    }
+}
+
+pub(crate) unsafe fn create_cubemap(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    source: &CubemapSource,
+) -> Result<Cubemap> {
+    let faces = load_cubemap(source)?
+        .into_iter()
+        .map(|f| f.with_color_space(ColorSpace::Srgb))
+        .collect();
+    create_layered_texture(
+        instance,
+        device,
+        data,
+        faces,
+        vk::ImageCreateFlags::CUBE_COMPATIBLE,
+        vk::ImageViewType::CUBE,
+    )
+}
+
+/// Creates a 2D texture array with one layer per texture. Every layer must
+/// share a size, format and mip count.
+pub(crate) unsafe fn create_texture_array(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    layers: Vec<TextureData>,
+) -> Result<TextureArray> {
+    let first = layers.first().ok_or_else(|| anyhow!("A texture array needs at least one layer."))?;
+    if layers.iter().any(|l| {
+        l.width != first.width || l.height != first.height || l.format != first.format || l.levels.len() != first.levels.len()
+    }) {
+        return Err(anyhow!("Texture array layers must share a size, format and mip count."));
+    }
+
+    create_layered_texture(
+        instance,
+        device,
+        data,
+        layers,
+        vk::ImageCreateFlags::empty(),
+        vk::ImageViewType::_2D_ARRAY,
+    )
+}
+
+/// A single 2D texture for the texture table. Missing mips are generated.
+pub(crate) unsafe fn create_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    texture: TextureData,
+) -> Result<LayeredTexture> {
+    create_layered_texture(
+        instance,
+        device,
+        data,
+        vec![texture],
+        vk::ImageCreateFlags::empty(),
+        vk::ImageViewType::_2D,
+    )
+}
+
+/// Replaces one layer of `array`, keeping the others. `texture` must match
+/// the array's size and format, and is read in the array's color space;
+/// missing mips are regenerated.
+pub(crate) unsafe fn update_texture_array_layer(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    array: &TextureArray,
+    layer: u32,
+    texture: TextureData,
+) -> Result<()> {
+    if layer >= array.layers {
+        return Err(anyhow!("Layer {} is out of range for a {} layer array.", layer, array.layers));
+    }
+    let texture = texture.with_color_space(ColorSpace::of(array.format));
+    let texture = decode_if_unsupported(instance, data, texture)?;
+    if texture.width != array.width || texture.height != array.height || texture.format != array.format {
+        return Err(anyhow!("Texture array layers must share a size and format."));
+    }
+
+    let generate = texture.levels.len() < array.mip_levels as usize;
+    if generate && !can_blit_mipmaps(instance, data, array.format) {
+        return Err(anyhow!("Cannot generate mipmaps for {:?}.", array.format));
+    }
+    let levels = if generate { 1 } else { array.mip_levels as usize };
+
+    let (staging_buffer, staging_buffer_memory, offsets) =
+        stage_layers(instance, device, data, &[&texture.levels[..levels]]).unwrap();
+
+    transition_image_layout(
+        device,
+        data,
+        array.image,
+        array.format,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        array.mip_levels,
+        layer..layer + 1,
+    )
+    .unwrap();
+
+    let (width, height) = (array.width, array.height);
+    copy_buffer_to_image_layers(device, data, staging_buffer, array.image, width, height, layer, &offsets).unwrap();
+
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+
+    if generate {
+        let (format, mip_levels) = (array.format, array.mip_levels);
+        generate_mipmaps(instance, device, data, array.image, format, width, height, mip_levels, layer..layer + 1).unwrap();
+    } else {
+        transition_image_layout(
+            device,
+            data,
+            array.image,
+            array.format,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            array.mip_levels,
+            layer..layer + 1,
+        )
+        .unwrap();
+    }
+
+    Ok(())
+}
+
+fn can_blit_mipmaps(instance: &Instance, data: &AppData, format: vk::Format) -> bool {
+    !is_block_compressed(format)
+        && unsafe {
+            format_supports(
+                instance,
+                data,
+                format,
+                vk::FormatFeatureFlags::BLIT_SRC
+                    | vk::FormatFeatureFlags::BLIT_DST
+                    | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+            )
+        }
+}
+
+/// Decodes block-compressed textures the device cannot sample.
+pub(crate) unsafe fn decode_if_unsupported(instance: &Instance, data: &AppData, texture: TextureData) -> Result<TextureData> {
+    if format_supports(instance, data, texture.format, vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+        return Ok(texture);
+    }
+    if !is_block_compressed(texture.format) {
+        return Err(anyhow!("Texture format {:?} cannot be sampled.", texture.format));
+    }
+    if matches!(texture.format, vk::Format::BC6H_UFLOAT_BLOCK | vk::Format::BC6H_SFLOAT_BLOCK) {
+        return Err(anyhow!(
+            "{:?} cannot be sampled by this device and has no CPU decoder; convert it to RGBA16F.",
+            texture.format
+        ));
+    }
+    warn!("{:?} is not supported by the device, decoding on the CPU.", texture.format);
+    decode_block_compressed(&texture)
+}
+
+/// Packs every level of every layer into one staging buffer, returning the
+/// offsets as `[layer][level]`.
+pub(crate) unsafe fn stage_layers(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    layers: &[&[Vec<u8>]],
+) -> Result<(vk::Buffer, vk::DeviceMemory, Vec<Vec<u64>>)> {
+    let total = layers
+        .iter()
+        .flat_map(|l| l.iter())
+        .map(|l| l.len() as u64)
+        .sum::<u64>();
+
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        total,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )
+    .unwrap();
+
+    let memory = device.map_memory(staging_buffer_memory, 0, total, vk::MemoryMapFlags::empty()).unwrap();
+
+    let mut offsets = Vec::with_capacity(layers.len());
+    let mut offset = 0;
+    for levels in layers {
+        let mut layer_offsets = Vec::with_capacity(levels.len());
+        for level in levels.iter() {
+            memcpy(level.as_ptr(), memory.cast::<u8>().add(offset), level.len());
+            layer_offsets.push(offset as u64);
+            offset += level.len();
+        }
+        offsets.push(layer_offsets);
+    }
+
+    device.unmap_memory(staging_buffer_memory);
+
+    Ok((staging_buffer, staging_buffer_memory, offsets))
+}
+
+unsafe fn create_layered_texture(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    layers: Vec<TextureData>,
+    flags: vk::ImageCreateFlags,
+    view_type: vk::ImageViewType,
+) -> Result<LayeredTexture> {
+    let layers = layers
+        .into_iter()
+        .map(|l| decode_if_unsupported(instance, data, l))
+        .collect::<Result<Vec<_>>>()?;
+
+    let (format, width, height) = (layers[0].format, layers[0].width, layers[0].height);
+    let layer_count = layers.len() as u32;
+    let generate = layers[0].levels.len() == 1 && can_blit_mipmaps(instance, data, format);
+    let mip_levels = if generate {
+        (width.max(height) as f32).log2().floor() as u32 + 1
+    } else {
+        layers[0].levels.len() as u32
+    };
+
+    let levels = layers.iter().map(|l| &l.levels[..]).collect::<Vec<_>>();
+    let (staging_buffer, staging_buffer_memory, offsets) = stage_layers(instance, device, data, &levels).unwrap();
+
+    // Blittable arrays keep TRANSFER_SRC so single layers can regenerate mips
+    // when they are replaced.
+    let usage = if can_blit_mipmaps(instance, data, format) {
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC
+    } else {
+        vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST
+    };
+
+    let (image, image_memory) = create_image(
+        instance,
+        device,
+        data,
+        width,
+        height,
+        mip_levels,
+        layer_count,
+        vk::SampleCountFlags::_1,
+        format,
+        vk::ImageTiling::OPTIMAL,
+        usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        flags,
+    )
+    .unwrap();
+
+    transition_image_layout(
+        device,
+        data,
+        image,
+        format,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        mip_levels,
+        0..layer_count,
+    )
+    .unwrap();
+
+    copy_buffer_to_image_layers(device, data, staging_buffer, image, width, height, 0, &offsets).unwrap();
+
+    device.destroy_buffer(staging_buffer, None);
+    free_memory(device, staging_buffer_memory);
+
+    if generate {
+        generate_mipmaps(instance, device, data, image, format, width, height, mip_levels, 0..layer_count).unwrap();
+    } else {
+        transition_image_layout(
+            device,
+            data,
+            image,
+            format,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            mip_levels,
+            0..layer_count,
+        )
+        .unwrap();
+    }
+
+    let image_view = create_layered_image_view(device, image, format, view_type, mip_levels, layer_count).unwrap();
+
+    Ok(LayeredTexture {
+        image,
+        image_memory,
+        image_view,
+        format,
+        width,
+        height,
+        layers: layer_count,
+        mip_levels,
+        sampler: vk::Sampler::null(),
+    })
+}
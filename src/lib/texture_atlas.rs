@@ -0,0 +1,180 @@
+use anyhow::{anyhow, Result};
+use std::{mem::size_of, ptr::copy_nonoverlapping as memcpy};
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    bindless::texture_count,
+    sprite_batch::SpriteAtlas,
+    texture::TextureData,
+    types::{Vec2, Vec4},
+    vertex_buffer::create_buffer,
+};
+
+/// Most regions every atlas together can hold, counting the whole-texture
+/// region 0.
+pub(crate) const MAX_ATLAS_REGIONS: usize = 4096;
+
+/// The width and height of each page `App::load_texture_atlas` packs.
+pub(crate) const ATLAS_PAGE_SIZE: u32 = 2048;
+
+/// Texels of repeated edge around each packed texture.
+pub(crate) const ATLAS_PADDING: u32 = 4;
+
+/// A texture index is a texture table slot in the low bits and an atlas
+/// region in the high bits, as the scene shaders decode it.
+const SLOT_BITS: u32 = 16;
+
+/// Where a texture packed by `App::load_texture_atlas` ended up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AtlasRegion {
+    /// For `Entity::texture` or `Decal::texture`: the page's slot, with the
+    /// region the shaders remap texture coordinates into. Coordinates are
+    /// expected within 0 to 1; repeating ones bleed into the neighbors.
+    pub texture: u32,
+    /// The page, for sprites.
+    pub sprite_atlas: SpriteAtlas,
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+}
+
+impl AtlasRegion {
+    /// `uv`, in the packed texture's own coordinates, in the page's.
+    pub fn remap(&self, uv: Vec2) -> Vec2 {
+        Vec2::new(
+            self.uv_min.x + uv.x * (self.uv_max.x - self.uv_min.x),
+            self.uv_min.y + uv.y * (self.uv_max.y - self.uv_min.y),
+        )
+    }
+}
+
+/// The texture table slot of a texture index.
+pub(crate) fn texture_slot(texture: u32) -> u32 {
+    texture & ((1 << SLOT_BITS) - 1)
+}
+
+/// The atlas region of a texture index, 0 for the whole texture.
+pub(crate) fn texture_region(texture: u32) -> u32 {
+    texture >> SLOT_BITS
+}
+
+/// The texture index of region `region` of the texture in `slot`.
+pub(crate) fn atlas_texture(slot: u32, region: usize) -> u32 {
+    slot | (region as u32) << SLOT_BITS
+}
+
+/// `texture` if its slot and region exist, or the scene texture.
+pub(crate) fn resolve_texture(data: &AppData, texture: u32) -> u32 {
+    if texture_slot(texture) < texture_count(data) && (texture_region(texture) as usize) < data.atlas_regions.len() {
+        texture
+    } else {
+        0
+    }
+}
+
+/// Pages of packed textures, ready for upload.
+pub(crate) struct PackedAtlas {
+    pub(crate) pages: Vec<TextureData>,
+    /// Per texture, in order: its page, and its offset and scale in the
+    /// page's texture coordinates.
+    pub(crate) regions: Vec<(usize, Vec4)>,
+}
+
+/// Packs `textures` onto as few `page_size` square pages as a shelf packer
+/// manages, tallest first. Each is surrounded by `padding` texels repeating
+/// its edges, so filtering and the first few mip levels do not bleed in
+/// neighbors. Only 8 bit RGBA textures can be packed, and only their first
+/// level is kept; the pages' mips are regenerated.
+pub(crate) fn pack_textures(textures: &[TextureData], page_size: u32, padding: u32) -> Result<PackedAtlas> {
+    let Some(format) = textures.first().map(|t| t.format) else {
+        return Ok(PackedAtlas { pages: vec![], regions: vec![] });
+    };
+    for texture in textures {
+        if texture.format != format
+            || !matches!(texture.format, vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB)
+        {
+            return Err(anyhow!("Only 8 bit RGBA textures of one color space can be packed, not {:?}.", texture.format));
+        }
+        if texture.width + 2 * padding > page_size || texture.height + 2 * padding > page_size {
+            let (width, height) = (texture.width, texture.height);
+            return Err(anyhow!("A {width}x{height} texture does not fit a {page_size}x{page_size} atlas page."));
+        }
+    }
+
+    let mut order = (0..textures.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| std::cmp::Reverse(textures[*i].height));
+
+    // Shelves fill left to right, stacked top to bottom.
+    let mut placements = vec![(0, 0, 0); textures.len()];
+    let (mut page, mut x, mut y, mut shelf_height) = (0, 0, 0, 0);
+    for i in order {
+        let (width, height) = (textures[i].width + 2 * padding, textures[i].height + 2 * padding);
+        if x + width > page_size {
+            (x, y, shelf_height) = (0, y + shelf_height, 0);
+        }
+        if y + height > page_size {
+            (page, x, y, shelf_height) = (page + 1, 0, 0, 0);
+        }
+        placements[i] = (page, x + padding, y + padding);
+        x += width;
+        shelf_height = shelf_height.max(height);
+    }
+
+    let mut pages = (0..=page)
+        .map(|_| TextureData {
+            width: page_size,
+            height: page_size,
+            format,
+            levels: vec![vec![0; (page_size * page_size * 4) as usize]],
+        })
+        .collect::<Vec<_>>();
+    let mut regions = Vec::with_capacity(textures.len());
+    for (texture, (page, x, y)) in textures.iter().zip(placements) {
+        let pixels = &mut pages[page].levels[0];
+        let (width, height) = (texture.width as i64, texture.height as i64);
+        for row in -(padding as i64)..height + padding as i64 {
+            for column in -(padding as i64)..width + padding as i64 {
+                let source = (row.clamp(0, height - 1) * width + column.clamp(0, width - 1)) as usize * 4;
+                let target = ((y as i64 + row) * page_size as i64 + x as i64 + column) as usize * 4;
+                pixels[target..target + 4].copy_from_slice(&texture.levels[0][source..source + 4]);
+            }
+        }
+
+        let size = page_size as f32;
+        regions.push((
+            page,
+            Vec4::new(x as f32 / size, y as f32 / size, width as f32 / size, height as f32 / size),
+        ));
+    }
+
+    Ok(PackedAtlas { pages, regions })
+}
+
+/// The table of atlas regions the scene shaders read, as offset and scale.
+/// Region 0 is the whole texture.
+pub(crate) unsafe fn create_atlas_region_buffer(instance: &Instance, device: &Device, data: &mut AppData) -> Result<()> {
+    let (buffer, memory) = create_buffer(
+        instance,
+        device,
+        data,
+        (size_of::<Vec4>() * MAX_ATLAS_REGIONS) as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+    data.atlas_region_buffer = buffer;
+    data.atlas_region_buffer_memory = memory;
+    data.atlas_regions = vec![Vec4::new(0.0, 0.0, 1.0, 1.0)];
+    write_atlas_regions(device, data);
+    Ok(())
+}
+
+/// Copies `data.atlas_regions` to the buffer, which no frame may be reading.
+pub(crate) unsafe fn write_atlas_regions(device: &Device, data: &AppData) {
+    let size = (size_of::<Vec4>() * data.atlas_regions.len()) as u64;
+    let memory = device
+        .map_memory(data.atlas_region_buffer_memory, 0, size, vk::MemoryMapFlags::empty())
+        .unwrap();
+    memcpy(data.atlas_regions.as_ptr(), memory.cast(), data.atlas_regions.len());
+    device.unmap_memory(data.atlas_region_buffer_memory);
+}
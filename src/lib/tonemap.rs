@@ -0,0 +1,372 @@
+use anyhow::Result;
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{
+    app::AppData,
+    dynamic_rendering::Pass,
+    image::{create_image, create_image_view},
+    shader::{create_shader_module, SpecializationConstants},
+    swapchain::HDR_PAPER_WHITE,
+    sampler::SamplerDesc,
+    texture::ColorSpace,
+    types::Vec4,
+};
+
+/// The scene is rendered into this format and tonemapped into the swapchain
+/// in a second subpass.
+pub(crate) const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Where multisampled HDR color is resolved relative to tonemapping.
+///
+/// Averaging HDR samples lets one very bright sample dominate an edge pixel,
+/// so edges against highlights come out aliased after tonemapping. Resolving
+/// after tonemapping averages in display space instead, at the cost of
+/// tonemapping every sample.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ResolveMode {
+    /// Fixed-function resolve into a single-sample HDR image, then tonemap.
+    BeforeTonemap = 0,
+    /// Tonemap each sample in the shader, then average.
+    #[default]
+    AfterTonemap = 1,
+}
+
+impl ResolveMode {
+    pub const ALL: [ResolveMode; 2] = [ResolveMode::BeforeTonemap, ResolveMode::AfterTonemap];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct TonemapPushConstants {
+    /// Skips the curve so debug views keep their colors.
+    pub(crate) passthrough: u32,
+    /// Set when `record_histogram` adapted the exposure, which the scene
+    /// then leaves out.
+    pub(crate) adapted_exposure: u32,
+    pub(crate) _padding: [u32; 2],
+    /// `ColorLut::scale` and `ColorLut::offset` of `AppData::color_lut`.
+    pub(crate) lut_scale: Vec4,
+    pub(crate) lut_offset: Vec4,
+}
+
+/// Whether the render pass resolves into `resolve_image` before the
+/// tonemap subpass. Without multisampling there is nothing to resolve.
+pub(crate) fn uses_resolve_attachment(data: &AppData) -> bool {
+    data.resolve_mode == ResolveMode::BeforeTonemap && data.msaa_samples != vk::SampleCountFlags::_1
+}
+
+/// Whether the tonemap subpass reads the multisampled image directly.
+pub(crate) fn resolves_in_shader(data: &AppData) -> bool {
+    data.resolve_mode == ResolveMode::AfterTonemap && data.msaa_samples != vk::SampleCountFlags::_1
+}
+
+/// The single-sample HDR image the scene is resolved into when
+/// `uses_resolve_attachment` is set. Otherwise the handles are left null.
+pub(crate) unsafe fn create_resolve_objects(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+) -> Result<()> {
+    if !uses_resolve_attachment(data) {
+        data.resolve_image = vk::Image::null();
+        data.resolve_image_memory = vk::DeviceMemory::null();
+        data.resolve_image_view = vk::ImageView::null();
+        return Ok(());
+    }
+
+    // Dynamic rendering has no input attachments, so the tonemap pass
+    // samples the resolved color instead.
+    let usage = if data.dynamic_rendering {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED
+    } else {
+        vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::INPUT_ATTACHMENT
+            | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+    };
+
+    let (resolve_image, resolve_image_memory) = create_image(
+        instance,
+        device,
+        data,
+        data.swapchain_extent.width,
+        data.swapchain_extent.height,
+        1,
+        1,
+        vk::SampleCountFlags::_1,
+        HDR_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        vk::ImageCreateFlags::empty(),
+    )
+    .unwrap();
+
+    data.resolve_image = resolve_image;
+    data.resolve_image_memory = resolve_image_memory;
+    data.resolve_image_view = create_image_view(
+        device,
+        data.resolve_image,
+        HDR_FORMAT,
+        vk::ImageAspectFlags::COLOR,
+        1,
+    )
+    .unwrap();
+
+    Ok(())
+}
+
+/// How the tonemap pass reads the HDR color: as an input attachment of the
+/// render pass, or with a nearest sampler under dynamic rendering.
+fn hdr_descriptor_type(data: &AppData) -> vk::DescriptorType {
+    if data.dynamic_rendering {
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+    } else {
+        vk::DescriptorType::INPUT_ATTACHMENT
+    }
+}
+
+pub(crate) unsafe fn create_tonemap_set_layout(device: &Device, data: &mut AppData) -> Result<()> {
+    let binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(0)
+        .descriptor_type(hdr_descriptor_type(data))
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let lut_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(1)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let exposure_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(2)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+
+    let bindings = &[binding, lut_binding, exposure_binding];
+    let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
+    data.tonemap_set_layout = data.set_layouts.get(device, &info).unwrap();
+    Ok(())
+}
+
+/// Points the tonemap subpass at the HDR color, resolved or not. The
+/// attachment is shared by every framebuffer, so one set is enough.
+pub(crate) unsafe fn create_tonemap_descriptor_set(device: &Device, data: &mut AppData) -> Result<()> {
+    let size = vk::DescriptorPoolSize::builder()
+        .type_(hdr_descriptor_type(data))
+        .descriptor_count(1);
+
+    let lut_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1);
+
+    let exposure_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1);
+
+    let pool_sizes = &[size, lut_size, exposure_size];
+    let info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(pool_sizes)
+        .max_sets(1);
+    data.tonemap_descriptor_pool = device.create_descriptor_pool(&info, None).unwrap();
+
+    let layouts = &[data.tonemap_set_layout];
+    let info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(data.tonemap_descriptor_pool)
+        .set_layouts(layouts);
+    data.tonemap_descriptor_set = device.allocate_descriptor_sets(&info).unwrap()[0];
+
+    let image_view = if uses_resolve_attachment(data) {
+        data.resolve_image_view
+    } else {
+        data.color_image_view
+    };
+
+    let sampler = if data.dynamic_rendering {
+        data.samplers.get(device, SamplerDesc::nearest())
+    } else {
+        vk::Sampler::null()
+    };
+
+    let image_info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(image_view)
+        .sampler(sampler);
+
+    let image_infos = &[image_info];
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tonemap_descriptor_set)
+        .dst_binding(0)
+        .dst_array_element(0)
+        .descriptor_type(hdr_descriptor_type(data))
+        .image_info(image_infos);
+
+    let buffer_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.exposure_state)
+        .offset(0)
+        .range(vk::WHOLE_SIZE as u64);
+
+    let buffer_infos = &[buffer_info];
+    let exposure_write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tonemap_descriptor_set)
+        .dst_binding(2)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(buffer_infos);
+
+    device.update_descriptor_sets(&[write, exposure_write], &[] as &[vk::CopyDescriptorSet]);
+    write_color_lut_descriptor(device, data);
+    Ok(())
+}
+
+/// Points the tonemap set at `AppData::color_lut`. The set must not be in
+/// use.
+pub(crate) unsafe fn write_color_lut_descriptor(device: &Device, data: &AppData) {
+    let image_info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.color_lut.view)
+        .sampler(data.color_lut.sampler);
+
+    let image_infos = &[image_info];
+    let write = vk::WriteDescriptorSet::builder()
+        .dst_set(data.tonemap_descriptor_set)
+        .dst_binding(1)
+        .dst_array_element(0)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(image_infos);
+
+    device.update_descriptor_sets(&[write], &[] as &[vk::CopyDescriptorSet]);
+}
+
+/// A full-screen triangle in the second subpass that reads the HDR color as
+/// an input attachment and writes the swapchain image.
+pub(crate) unsafe fn create_tonemap_pipeline(device: &Device, data: &mut AppData) -> Result<()> {
+    let vert = include_bytes!("../../shaders/tonemap_vert.spv");
+    let frag = match (resolves_in_shader(data), data.dynamic_rendering) {
+        (true, true) => &include_bytes!("../../shaders/tonemap_ms_sampled_frag.spv")[..],
+        (true, false) => &include_bytes!("../../shaders/tonemap_ms_frag.spv")[..],
+        (false, true) => &include_bytes!("../../shaders/tonemap_sampled_frag.spv")[..],
+        (false, false) => &include_bytes!("../../shaders/tonemap_frag.spv")[..],
+    };
+
+    let vert_shader_module = create_shader_module(device, &vert[..]).unwrap();
+    let frag_shader_module = create_shader_module(device, frag).unwrap();
+
+    let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .module(vert_shader_module)
+        .name(b"main\0");
+
+    // The sample count sizes the resolve loop, sRGB is encoded when the
+    // swapchain format does not do it on write, and HDR color spaces have
+    // encodings of their own.
+    let constants = SpecializationConstants::new()
+        .i32(0, data.msaa_samples.bits() as i32)
+        .bool(1, ColorSpace::of(data.swapchain_format) == ColorSpace::Linear)
+        .i32(2, data.swapchain_color_space as i32)
+        .f32(3, HDR_PAPER_WHITE);
+    let specialization_info = constants.info();
+
+    let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .module(frag_shader_module)
+        .name(b"main\0")
+        .specialization_info(&specialization_info);
+
+    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
+
+    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+        .primitive_restart_enable(false);
+
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(data.swapchain_extent.width as f32)
+        .height(data.swapchain_extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(data.swapchain_extent);
+
+    let viewports = &[viewport];
+    let scissors = &[scissor];
+    let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+        .viewports(viewports)
+        .scissors(scissors);
+
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+        .depth_clamp_enable(false)
+        .rasterizer_discard_enable(false)
+        .polygon_mode(vk::PolygonMode::FILL)
+        .line_width(1.0)
+        .cull_mode(vk::CullModeFlags::NONE)
+        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_bias_enable(false);
+
+    let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+        .sample_shading_enable(false)
+        .rasterization_samples(vk::SampleCountFlags::_1);
+
+    let attachment = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::all())
+        .blend_enable(false);
+
+    let attachments = &[attachment];
+    let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+        .logic_op_enable(false)
+        .logic_op(vk::LogicOp::COPY)
+        .attachments(attachments)
+        .blend_constants([0.0, 0.0, 0.0, 0.0]);
+
+    let push_constant_range = vk::PushConstantRange::builder()
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+        .offset(0)
+        .size(size_of::<TonemapPushConstants>() as u32);
+
+    let set_layouts = &[data.tonemap_set_layout];
+    let push_constant_ranges = &[push_constant_range];
+    let layout_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges);
+
+    data.tonemap_pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+
+    let formats = Pass::Tonemap.formats(data);
+    let mut rendering_info = formats.pipeline_info();
+
+    let stages = &[vert_stage, frag_stage];
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
+        .stages(stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .color_blend_state(&color_blend_state)
+        .layout(data.tonemap_pipeline_layout)
+        .render_pass(data.render_pass)
+        .subpass(Pass::Tonemap.subpass(data));
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    }
+
+    data.tonemap_pipeline = device
+        .create_graphics_pipelines(data.pipeline_cache, &[info], None)
+        .unwrap()
+        .0[0];
+
+    device.destroy_shader_module(vert_shader_module, None);
+    device.destroy_shader_module(frag_shader_module, None);
+    Ok(())
+}
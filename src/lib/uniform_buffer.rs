@@ -1,15 +1,41 @@
 use anyhow::Result;
-use std::mem::size_of;
 
 use vulkanalia::prelude::v1_0::*;
 
-use crate::{app::AppData, types::Mat4, vertex_buffer::create_buffer};
+use crate::{
+    app::AppData,
+    mapped_memory::create_mapped_buffer,
+    types::{Mat4, Vec4},
+    viewport::{uniform_stride, MAX_VIEWPORTS},
+};
 
+/// The scene's uniform buffer, which shaders declare by including
+/// `shaders/include/scene.glsl`, or `scene.hlsli` from HLSL.
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub(crate) struct UniformBufferObject {
     pub(crate) view: Mat4,
     pub(crate) proj: Mat4,
+    /// Directly after `proj`, so shaders can index both as an array by
+    /// `CameraLayer`.
+    pub(crate) viewmodel_proj: Mat4,
+    pub(crate) exposure: f32,
+    pub(crate) near_plane: f32,
+    pub(crate) far_plane: f32,
+    pub(crate) _padding: f32,
+    /// Towards the sun, with `w` set to 1 when this frame's shadow mask was
+    /// traced, see `shadows.rs`.
+    pub(crate) sun_direction: Vec4,
+    /// Zero without a sun.
+    pub(crate) sun_illuminance: Vec4,
+    /// 1 when this frame's ambient occlusion was traced, see
+    /// `ambient_occlusion.rs`.
+    pub(crate) occlusion_traced: f32,
+    pub(crate) _padding_1: [f32; 3],
+    /// The left eye's, then the right's, for the stereo pass. Unused without
+    /// `App::stereo`.
+    pub(crate) eye_views: [Mat4; 2],
+    pub(crate) eye_projs: [Mat4; 2],
 }
 
 pub(crate) unsafe fn create_uniform_buffers(
@@ -21,13 +47,12 @@ pub(crate) unsafe fn create_uniform_buffers(
     data.uniform_buffers_memory.clear();
 
     for _ in 0..data.swapchain_images.len() {
-        let (uniform_buffer, uniform_buffer_memory) = create_buffer(
+        let (uniform_buffer, uniform_buffer_memory) = create_mapped_buffer(
             instance,
             device,
             data,
-            size_of::<UniformBufferObject>() as u64,
+            uniform_stride(data) * MAX_VIEWPORTS as u64,
             vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
         )
         .unwrap();
 
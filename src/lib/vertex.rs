@@ -9,22 +9,41 @@ use std::{
 
 use vulkanalia::prelude::v1_0::*;
 
-use crate::types::{Vec2, Vec3};
+use crate::types::{Vec2, Vec3, Vec4};
 
+/// Fields are in shader location order, see `shader.vert`.
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct Vertex {
     pub(crate) pos: Vec3,
     pub(crate) color: Vec3,
     pub(crate) tex_coords: Vec2,
+    pub(crate) normal: Vec3,
+    /// `xyz` points along increasing U. `w` is the sign of the bitangent,
+    /// `cross(normal, tangent.xyz) * w`, which flips for mirrored UVs.
+    pub(crate) tangent: Vec4,
+    /// A second UV set, for lightmaps and detail textures. Importers without
+    /// one copy `tex_coords`.
+    pub(crate) tex_coords_1: Vec2,
+    /// Up to four joints of the model's skeleton that move this vertex.
+    pub(crate) joints: [u32; 4],
+    /// How much each of `joints` moves the vertex, summing to one. All zero
+    /// for vertices that are not skinned.
+    pub(crate) weights: Vec4,
 }
 
 impl Vertex {
-    pub(crate) const fn new(pos: Vec3, color: Vec3, tex_coords: Vec2) -> Self {
+    /// A vertex without a tangent, see `MeshData::generate_tangents`.
+    pub(crate) const fn new(pos: Vec3, normal: Vec3, color: Vec3, tex_coords: Vec2) -> Self {
         Self {
             pos,
             color,
             tex_coords,
+            normal,
+            tangent: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            tex_coords_1: tex_coords,
+            joints: [0; 4],
+            weights: Vec4::new(0.0, 0.0, 0.0, 0.0),
         }
     }
 
@@ -35,34 +54,18 @@ impl Vertex {
             .input_rate(vk::VertexInputRate::VERTEX)
             .build()
     }
-
-    pub(crate) fn attribute_descriptions() -> [vk::VertexInputAttributeDescription; 3] {
-        let pos = vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(0)
-            .format(vk::Format::R32G32B32_SFLOAT)
-            .offset(0)
-            .build();
-        let color = vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(1)
-            .format(vk::Format::R32G32B32_SFLOAT)
-            .offset(size_of::<Vec3>() as u32)
-            .build();
-        let tex_coord = vk::VertexInputAttributeDescription::builder()
-            .binding(0)
-            .location(2)
-            .format(vk::Format::R32G32_SFLOAT)
-            .offset((size_of::<Vec3>() + size_of::<Vec3>()) as u32)
-            .build();
-
-        [pos, color, tex_coord]
-    }
 }
 
 impl PartialEq for Vertex {
     fn eq(&self, other: &Self) -> bool {
-        self.pos == other.pos && self.color == other.color && self.tex_coords == other.tex_coords
+        self.pos == other.pos
+            && self.color == other.color
+            && self.tex_coords == other.tex_coords
+            && self.normal == other.normal
+            && self.tangent == other.tangent
+            && self.tex_coords_1 == other.tex_coords_1
+            && self.joints == other.joints
+            && self.weights == other.weights
     }
 }
 
@@ -78,5 +81,19 @@ impl Hash for Vertex {
         self.color[2].to_bits().hash(state);
         self.tex_coords[0].to_bits().hash(state);
         self.tex_coords[1].to_bits().hash(state);
+        self.normal[0].to_bits().hash(state);
+        self.normal[1].to_bits().hash(state);
+        self.normal[2].to_bits().hash(state);
+        self.tangent[0].to_bits().hash(state);
+        self.tangent[1].to_bits().hash(state);
+        self.tangent[2].to_bits().hash(state);
+        self.tangent[3].to_bits().hash(state);
+        self.tex_coords_1[0].to_bits().hash(state);
+        self.tex_coords_1[1].to_bits().hash(state);
+        self.joints.hash(state);
+        self.weights[0].to_bits().hash(state);
+        self.weights[1].to_bits().hash(state);
+        self.weights[2].to_bits().hash(state);
+        self.weights[3].to_bits().hash(state);
     }
 }
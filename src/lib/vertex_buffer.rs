@@ -7,11 +7,16 @@ use std::{
 use vulkanalia::prelude::v1_0::*;
 
 use crate::{
+  allocations::{allocate_memory, free_memory, AllocationKind},
   app::AppData,
+  ray_tracing::geometry_usage,
   vertex::Vertex,
   single_time_cmd::{begin_single_time_commands, end_single_time_commands}
 };
 
+/// Creates a buffer with memory of its own, tracked with the caller's
+/// location, see `allocate_memory`.
+#[track_caller]
 pub(crate) unsafe fn create_buffer(
   instance: &Instance,
   device: &Device,
@@ -28,12 +33,30 @@ pub(crate) unsafe fn create_buffer(
   let buffer = device.create_buffer(&buffer_info, None).unwrap();
   let requirements = device.get_buffer_memory_requirements(buffer);
 
-  let memory_info = vk::MemoryAllocateInfo::builder()
+  // Acceleration structure inputs and shader binding tables are read by
+  // address, which the memory must be allocated for.
+  let mut flags_info = vk::MemoryAllocateFlagsInfo::builder()
+      .flags(vk::MemoryAllocateFlags::DEVICE_ADDRESS);
+  let memory_type_index = match get_memory_type_index(instance, data, properties, requirements) {
+      Ok(index) => index,
+      Err(e) => {
+          device.destroy_buffer(buffer, None);
+          return Err(e);
+      }
+  };
+  let mut memory_info = vk::MemoryAllocateInfo::builder()
       .allocation_size(requirements.size)
-      .memory_type_index(
-          get_memory_type_index(instance, data, properties, requirements).unwrap(),
-      );
-  let buffer_memory = device.allocate_memory(&memory_info, None).unwrap();
+      .memory_type_index(memory_type_index);
+  if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+      memory_info = memory_info.push_next(&mut flags_info);
+  }
+  let buffer_memory = match allocate_memory(device, &memory_info, AllocationKind::Buffer) {
+      Ok(memory) => memory,
+      Err(e) => {
+          device.destroy_buffer(buffer, None);
+          return Err(anyhow!("Failed to allocate {} bytes for a buffer: {}", requirements.size, e));
+      }
+  };
 
   device.bind_buffer_memory(buffer, buffer_memory, 0).unwrap();
 
@@ -70,7 +93,11 @@ pub(crate) unsafe fn create_vertex_buffer(
       device,
       data,
       size,
-      vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
+      // Also read as a storage buffer by the mesh shader path.
+      vk::BufferUsageFlags::TRANSFER_DST
+          | vk::BufferUsageFlags::VERTEX_BUFFER
+          | vk::BufferUsageFlags::STORAGE_BUFFER
+          | geometry_usage(data),
       vk::MemoryPropertyFlags::DEVICE_LOCAL,
   )
   .unwrap();
@@ -81,7 +108,7 @@ pub(crate) unsafe fn create_vertex_buffer(
   copy_buffer(device, data, staging_buffer, vertex_buffer, size).unwrap();
 
   device.destroy_buffer(staging_buffer, None);
-  device.free_memory(staging_buffer_memory, None);
+  free_memory(device, staging_buffer_memory);
 
   Ok(())
 }
@@ -91,7 +118,8 @@ pub(crate) unsafe fn create_index_buffer(
   device: &Device,
   data: &mut AppData,
 ) -> Result<()> {
-  let size = (size_of::<u32>() * data.indices.len()) as u64;
+  let (index_type, indices) = pack_indices(&data.indices, data.vertices.len());
+  let size = indices.len() as u64;
 
   let (staging_buffer, staging_buffer_memory) = create_buffer(
       instance,
@@ -107,7 +135,7 @@ pub(crate) unsafe fn create_index_buffer(
       .map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())
       .unwrap();
 
-  memcpy(data.indices.as_ptr(), memory.cast(), data.indices.len());
+  memcpy(indices.as_ptr(), memory.cast(), indices.len());
 
   device.unmap_memory(staging_buffer_memory);
 
@@ -116,22 +144,39 @@ pub(crate) unsafe fn create_index_buffer(
       device,
       data,
       size,
-      vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
+      vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER | geometry_usage(data),
       vk::MemoryPropertyFlags::DEVICE_LOCAL,
   )
   .unwrap();
 
   data.index_buffer = index_buffer;
   data.index_buffer_memory = index_buffer_memory;
+  data.index_type = index_type;
 
   copy_buffer(device, data, staging_buffer, index_buffer, size).unwrap();
 
   device.destroy_buffer(staging_buffer, None);
-  device.free_memory(staging_buffer_memory, None);
+  free_memory(device, staging_buffer_memory);
 
   Ok(())
 }
 
+/// `indices` as bytes in the narrowest type that can address
+/// `vertex_count` vertices. Most meshes fit in 16 bits, which halves index
+/// memory and bandwidth. Primitive restart is never enabled, so `0xFFFF` is
+/// an ordinary index. 16 bit indices are padded to a whole number of words,
+/// for shaders that read them as storage buffers.
+pub(crate) fn pack_indices(indices: &[u32], vertex_count: usize) -> (vk::IndexType, Vec<u8>) {
+  if vertex_count <= u16::MAX as usize + 1 {
+    let mut bytes = indices.iter().flat_map(|i| (*i as u16).to_ne_bytes()).collect::<Vec<_>>();
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+    (vk::IndexType::UINT16, bytes)
+  } else {
+    let bytes = indices.iter().flat_map(|i| i.to_ne_bytes()).collect();
+    (vk::IndexType::UINT32, bytes)
+  }
+}
+
 pub(crate) unsafe fn get_memory_type_index(
   instance: &Instance,
   data: &AppData,
@@ -0,0 +1,64 @@
+use cgmath::{vec3, Deg};
+
+use crate::types::Mat4;
+
+/// Which camera an entity is drawn with.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CameraLayer {
+    #[default]
+    World = 0,
+    /// First-person arms and weapons. Transforms are in view space: X right,
+    /// Y up, looking down -Z from the camera at the origin. Drawn after the
+    /// world, with `App::viewmodel`'s projection.
+    Viewmodel = 1,
+}
+
+/// How the viewmodel layer is kept from clipping into the world.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ViewmodelDepth {
+    /// Clears depth before the first viewmodel draw.
+    #[default]
+    Clear,
+    /// Splits the depth range instead: the viewmodel gets the front of it
+    /// and the world the rest, so nothing is cleared but the world loses a
+    /// little depth precision.
+    Remap,
+}
+
+/// The camera the viewmodel layer is drawn with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewmodel {
+    /// Vertical field of view, usually narrower than the world's so arms do
+    /// not stretch at the edges.
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+    pub depth: ViewmodelDepth,
+    /// The fraction of the depth range the viewmodel gets with
+    /// `ViewmodelDepth::Remap`.
+    pub depth_range: f32,
+}
+
+impl Default for Viewmodel {
+    fn default() -> Self {
+        Self {
+            fov: Deg(40.0),
+            near: 0.01,
+            far: 2.0,
+            depth: ViewmodelDepth::Clear,
+            depth_range: 0.1,
+        }
+    }
+}
+
+impl Viewmodel {
+    /// Maps clip space depth from [0, 1] to [`min`, `max`]. Applied to both
+    /// projections when remapping, so every pipeline sees the split without
+    /// changing its viewport. `min` and `max` count from the near plane, so
+    /// are mirrored for reversed depth.
+    pub(crate) fn depth_remap(min: f32, max: f32, reverse_z: bool) -> Mat4 {
+        let (min, max) = if reverse_z { (1.0 - max, 1.0 - min) } else { (min, max) };
+        Mat4::from_translation(vec3(0.0, 0.0, min)) * Mat4::from_nonuniform_scale(1.0, 1.0, max - min)
+    }
+}
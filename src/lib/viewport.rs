@@ -0,0 +1,132 @@
+use std::mem::size_of;
+
+use vulkanalia::prelude::v1_0::*;
+
+use crate::{app::AppData, types::Mat4, uniform_buffer::UniformBufferObject};
+
+/// Most viewports the scene is drawn into at once. The uniform buffer has a
+/// slot for each.
+pub const MAX_VIEWPORTS: usize = 4;
+
+const FULL_WINDOW: &[Viewport] = &[Viewport::FULL];
+
+/// A region of the window the scene is drawn into, for split-screen and
+/// editor-style layouts. Every scene pass draw is repeated in each viewport
+/// with the viewport and scissor set to its region.
+///
+/// Viewports share the depth buffer, so they should not overlap. The
+/// viewmodel layer, debug draws and particles stay with the active camera.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    /// The left edge, as a fraction of the window's width.
+    pub x: f32,
+    /// The top edge, as a fraction of the window's height.
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// The index of the camera shown, see `App::add_camera`. The active
+    /// camera's when unset or out of range. Its field of view is kept and
+    /// widened or narrowed to the region's aspect ratio.
+    pub camera: Option<usize>,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self::FULL
+    }
+}
+
+impl Viewport {
+    /// The whole window, seen by the active camera.
+    pub const FULL: Self = Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0, camera: None };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height, camera: None }
+    }
+
+    pub fn with_camera(self, camera: usize) -> Self {
+        Self { camera: Some(camera), ..self }
+    }
+
+    /// `columns` by `rows` equal regions, row by row from the top left.
+    pub fn grid(columns: usize, rows: usize) -> Vec<Self> {
+        let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+        (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| Self::new(column as f32 * width, row as f32 * height, width, height))
+            .collect()
+    }
+
+    /// The region in pixels of a window `extent` in size, rounded to whole
+    /// pixels so that neighbouring viewports neither overlap nor leave gaps.
+    pub(crate) fn rect(&self, extent: vk::Extent2D) -> vk::Rect2D {
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let left = (self.x * width).round().clamp(0.0, width);
+        let top = (self.y * height).round().clamp(0.0, height);
+        let right = ((self.x + self.width) * width).round().clamp(left, width);
+        let bottom = ((self.y + self.height) * height).round().clamp(top, height);
+        vk::Rect2D {
+            offset: vk::Offset2D { x: left as i32, y: top as i32 },
+            extent: vk::Extent2D { width: (right - left) as u32, height: (bottom - top) as u32 },
+        }
+    }
+
+    /// Scales a projection made for the whole window to this region's aspect
+    /// ratio.
+    pub(crate) fn aspect_correction(&self, extent: vk::Extent2D) -> Mat4 {
+        let rect = self.rect(extent);
+        if rect.extent.width == 0 || rect.extent.height == 0 {
+            return Mat4::from_scale(1.0);
+        }
+        let window = extent.width as f32 / extent.height as f32;
+        let region = rect.extent.width as f32 / rect.extent.height as f32;
+        Mat4::from_nonuniform_scale(window / region, 1.0, 1.0)
+    }
+}
+
+/// The viewports the scene is drawn into, the whole window if none are set.
+pub(crate) fn viewports(data: &AppData) -> &[Viewport] {
+    if data.viewports.is_empty() {
+        FULL_WINDOW
+    } else {
+        &data.viewports
+    }
+}
+
+/// The distance between viewports' uniform buffer slots.
+pub(crate) fn uniform_stride(data: &AppData) -> u64 {
+    let alignment = data.uniform_alignment.max(1);
+    (size_of::<UniformBufferObject>() as u64).div_ceil(alignment) * alignment
+}
+
+/// Sets the viewport and scissor to each viewport in turn and calls `record`
+/// with its index, for a scene pass secondary command buffer. `record` binds
+/// set 0 for that index, which picks the viewport's uniform buffer slot.
+pub(crate) unsafe fn record_viewports(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    mut record: impl FnMut(usize),
+) {
+    for (index, viewport) in viewports(data).iter().enumerate() {
+        let rect = viewport.rect(data.swapchain_extent);
+        if rect.extent.width == 0 || rect.extent.height == 0 {
+            continue;
+        }
+        set_viewport(device, command_buffer, rect);
+        record(index);
+    }
+}
+
+/// Sets the dynamic viewport and scissor of the scene pipelines to `rect`.
+pub(crate) unsafe fn set_viewport(device: &Device, command_buffer: vk::CommandBuffer, rect: vk::Rect2D) {
+    let viewport = vk::Viewport::builder()
+        .x(rect.offset.x as f32)
+        .y(rect.offset.y as f32)
+        .width(rect.extent.width as f32)
+        .height(rect.extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+    device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+    device.cmd_set_scissor(command_buffer, 0, &[rect]);
+}
@@ -0,0 +1,152 @@
+use cgmath::{point3, vec4, EuclideanSpace, InnerSpace, Matrix, Point3, Transform, Zero};
+use std::fmt;
+
+use crate::{
+    entity::Entity,
+    types::{Mat4, Vec3, Vec4},
+};
+
+/// A bounding sphere in model space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Bounds {
+    pub(crate) center: Vec3,
+    pub(crate) radius: f32,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self {
+            center: Vec3::zero(),
+            radius: 0.0,
+        }
+    }
+}
+
+impl Bounds {
+    /// The sphere centered on the box around `points`.
+    pub(crate) fn from_points(points: impl Iterator<Item = Vec3> + Clone) -> Self {
+        let (min, max) = points.clone().fold(
+            (Vec3::new(f32::MAX, f32::MAX, f32::MAX), Vec3::new(f32::MIN, f32::MIN, f32::MIN)),
+            |(min, max), p| {
+                (
+                    Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                    Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+                )
+            },
+        );
+        if min.x > max.x {
+            return Self::default();
+        }
+
+        let center = (min + max) * 0.5;
+        let radius = points.map(|p| (p - center).magnitude2()).fold(0.0, f32::max).sqrt();
+        Self { center, radius }
+    }
+
+    /// These bounds moved by `transform`, growing the radius by its largest
+    /// axis scale.
+    pub(crate) fn transformed(&self, transform: &Mat4) -> Self {
+        let scale = [transform.x, transform.y, transform.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+        Self {
+            center: transform.transform_point(Point3::from_vec(self.center)).to_vec(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// What the renderer's culling decided about one entity this frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EntityVisibility {
+    /// Index into `App::entities`.
+    pub entity: usize,
+    /// Whether the entity's bounds intersect the view frustum.
+    pub visible: bool,
+    /// Projected diameter of the bounds as a fraction of the viewport
+    /// height. Roughly 1 when the entity fills the screen vertically.
+    pub screen_size: f32,
+    /// Distance from the camera to the center of the bounds.
+    pub distance: f32,
+}
+
+/// Called once per frame, after culling and before the frame is recorded,
+/// with one entry per drawn entity.
+pub type VisibilityCallback = Box<dyn FnMut(&[EntityVisibility]) + Send>;
+
+/// The callbacks registered with `App::on_visibility`.
+#[derive(Default)]
+pub(crate) struct VisibilityCallbacks(pub(crate) Vec<VisibilityCallback>);
+
+impl fmt::Debug for VisibilityCallbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VisibilityCallbacks({})", self.0.len())
+    }
+}
+
+/// View frustum planes as `(normal, distance)` with normals pointing inwards.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a projection with a `0..1` depth range. The
+    /// plane at infinity of a projection without a far plane comes out with
+    /// no normal, and is replaced with one that keeps everything.
+    pub(crate) fn new(view_proj: Mat4) -> Self {
+        let row = |i: usize| view_proj.row(i);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|p| {
+            let length = p.truncate().magnitude();
+            if length > 0.0 { p / length } else { vec4(0.0, 0.0, 0.0, 1.0) }
+        });
+        Self { planes }
+    }
+
+    /// The planes as `(normal, distance)`, normals pointing inwards.
+    pub(crate) fn planes(&self) -> [Vec4; 6] {
+        self.planes
+    }
+
+    pub(crate) fn intersects(&self, bounds: &Bounds) -> bool {
+        let center = vec4(bounds.center.x, bounds.center.y, bounds.center.z, 1.0);
+        self.planes.iter().all(|p| p.dot(center) >= -bounds.radius)
+    }
+}
+
+/// Culls `entities`, with `bounds` giving the model space bounds of each
+/// one's mesh, against the camera and measures their size on screen.
+pub(crate) fn compute_visibility(
+    view: Mat4,
+    proj: Mat4,
+    bounds: impl Fn(&Entity) -> Bounds,
+    entities: &[Entity],
+) -> Vec<EntityVisibility> {
+    let frustum = Frustum::new(proj * view);
+    let focal = proj.y.y.abs();
+    let eye = view.inverse_transform().unwrap().transform_point(point3(0.0, 0.0, 0.0));
+    entities
+        .iter()
+        .enumerate()
+        .map(|(i, entity)| {
+            let world = bounds(entity).transformed(&entity.transform);
+            let center = Point3::from_vec(world.center);
+            let depth = -view.transform_point(center).z;
+            EntityVisibility {
+                entity: i,
+                visible: frustum.intersects(&world),
+                screen_size: world.radius * focal / depth.max(1e-4),
+                distance: (center - eye).magnitude(),
+            }
+        })
+        .collect()
+}
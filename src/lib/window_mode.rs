@@ -0,0 +1,77 @@
+use log::*;
+use winit::{
+    dpi::PhysicalSize,
+    monitor::VideoMode,
+    window::{Fullscreen, Window},
+};
+
+use vulkanalia::prelude::v1_0::*;
+
+/// How the main window is shown, see `App::window_mode`.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WindowMode {
+    #[default]
+    Windowed = 0,
+    /// A borderless window covering the monitor it is on, composited like
+    /// any other window.
+    Borderless = 1,
+    /// Takes over the monitor at `App::fullscreen_resolution`. On Windows
+    /// the driver is also allowed to bypass the compositor through
+    /// `VK_EXT_full_screen_exclusive`.
+    Exclusive = 2,
+}
+
+impl WindowMode {
+    pub const ALL: [WindowMode; 3] = [WindowMode::Windowed, WindowMode::Borderless, WindowMode::Exclusive];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    /// What swapchains are created allowing, where the device supports
+    /// `VK_EXT_full_screen_exclusive`. Borderless windows are kept from being
+    /// promoted to exclusive behind the app's back, which would make
+    /// switching away from them slow.
+    pub(crate) fn full_screen_exclusive(self) -> vk::FullScreenExclusiveEXT {
+        match self {
+            Self::Windowed => vk::FullScreenExclusiveEXT::DEFAULT,
+            Self::Borderless => vk::FullScreenExclusiveEXT::DISALLOWED,
+            Self::Exclusive => vk::FullScreenExclusiveEXT::ALLOWED,
+        }
+    }
+}
+
+/// Shows `window` in `mode`. Exclusive fullscreen uses the video mode of
+/// the window's monitor closest to `resolution`, or to its current
+/// resolution with none, and falls back to borderless if the monitor lists
+/// no video modes. The window is resized later, when the platform gets to it.
+pub(crate) fn apply_window_mode(window: &Window, mode: WindowMode, resolution: Option<[u32; 2]>) {
+    let fullscreen = match mode {
+        WindowMode::Windowed => None,
+        WindowMode::Borderless => Some(Fullscreen::Borderless(None)),
+        WindowMode::Exclusive => match pick_video_mode(window, resolution) {
+            Some(video_mode) => {
+                info!("Switching to {:?}.", video_mode);
+                Some(Fullscreen::Exclusive(video_mode))
+            }
+            None => {
+                warn!("The monitor lists no video modes, going borderless instead.");
+                Some(Fullscreen::Borderless(None))
+            }
+        },
+    };
+    window.set_fullscreen(fullscreen);
+}
+
+/// The video mode closest in size to `resolution`, with the most color
+/// depth and the highest refresh rate among equals.
+fn pick_video_mode(window: &Window, resolution: Option<[u32; 2]>) -> Option<VideoMode> {
+    let monitor = window.current_monitor().or_else(|| window.primary_monitor())?;
+    let target = resolution.map_or(monitor.size(), |[width, height]| PhysicalSize::new(width, height));
+    monitor.video_modes().min_by_key(|m| {
+        let size = m.size();
+        let distance = size.width.abs_diff(target.width) as u64 + size.height.abs_diff(target.height) as u64;
+        (distance, u16::MAX - m.bit_depth(), u32::MAX - m.refresh_rate_millihertz())
+    })
+}
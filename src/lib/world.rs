@@ -0,0 +1,107 @@
+use cgmath::{Matrix4, SquareMatrix};
+
+use crate::types::{Mat4, Vec3};
+
+/// Which world axis points up.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum UpAxis {
+    /// glTF, Maya and most game engines.
+    Y,
+    /// Blender, 3ds Max and CAD packages.
+    #[default]
+    Z,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+/// The coordinate convention of a space, either the engine's world space or
+/// an asset's source space.
+///
+/// Rendering happens in a fixed right-handed, Z-up space measured in meters.
+/// Everything else (entity transforms, light probes, debug draws) is in world
+/// space, and assets are converted into world space when they are imported.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WorldConfig {
+    pub up: UpAxis,
+    pub handedness: Handedness,
+    /// How many meters one unit is.
+    pub meters_per_unit: f32,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            up: UpAxis::Z,
+            handedness: Handedness::Right,
+            meters_per_unit: 1.0,
+        }
+    }
+}
+
+impl WorldConfig {
+    /// glTF 2.0: Y-up, right-handed, meters.
+    pub const GLTF: Self = Self {
+        up: UpAxis::Y,
+        handedness: Handedness::Right,
+        meters_per_unit: 1.0,
+    };
+
+    /// Index of the up axis, for shaders.
+    pub(crate) fn up_index(&self) -> u32 {
+        match self.up {
+            UpAxis::Y => 1,
+            UpAxis::Z => 2,
+        }
+    }
+
+    /// The unit vector along the up axis.
+    pub(crate) fn up_vector(&self) -> Vec3 {
+        match self.up {
+            UpAxis::Y => Vec3::unit_y(),
+            UpAxis::Z => Vec3::unit_z(),
+        }
+    }
+
+    /// Maps points in this space to render space.
+    pub fn render_transform(&self) -> Mat4 {
+        // Left-handed spaces mirror the axis that is neither up nor forward.
+        let mirror = match (self.handedness, self.up) {
+            (Handedness::Right, _) => Mat4::identity(),
+            (Handedness::Left, UpAxis::Y) => Mat4::from_nonuniform_scale(1.0, 1.0, -1.0),
+            (Handedness::Left, UpAxis::Z) => Mat4::from_nonuniform_scale(1.0, -1.0, 1.0),
+        };
+
+        #[rustfmt::skip]
+        let basis = match self.up {
+            UpAxis::Y => Matrix4::new(
+                1.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 1.0, 0.0,
+                0.0, -1.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 1.0,
+            ),
+            UpAxis::Z => Mat4::identity(),
+        };
+
+        Mat4::from_scale(self.meters_per_unit) * basis * mirror
+    }
+
+    /// Maps points in render space to this space.
+    pub fn inverse_render_transform(&self) -> Mat4 {
+        self.render_transform().invert().unwrap()
+    }
+
+    /// Maps points in an asset authored in `source` space into this space.
+    pub fn import_transform(&self, source: &WorldConfig) -> Mat4 {
+        self.inverse_render_transform() * source.render_transform()
+    }
+
+    /// Re-expresses a transform written for render space in this space.
+    pub(crate) fn convert_render_transform(&self, transform: Mat4) -> Mat4 {
+        self.inverse_render_transform() * transform * self.render_transform()
+    }
+}
@@ -2,11 +2,20 @@
     dead_code,
     unused_variables,
     unused_imports,
+    clippy::collapsible_match,
     clippy::too_many_arguments,
     clippy::unnecessary_wraps
 )]
 
 use anyhow::Result;
+use cgmath::{vec2, vec3, vec4, InnerSpace, Matrix4, SquareMatrix};
+use log::{Log, Metadata, Record};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 use vulkanalia::vk::DeviceV1_0;
 use winit::{
     dpi::LogicalSize,
@@ -15,10 +24,83 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
-use ozen_athena::App;
+use ozen_athena::{AmbientOcclusion, App, Camera, Decal, Directories, GPU_OVERRIDE_VAR, Headless, LightProbeGrid, LightProbes, Mobility, RenderMessage, RenderThread, SpriteAtlas, Stereo, Sun, Viewport, WorldConfig};
+
+/// Logs to stderr like `pretty_env_logger` and, without colors, to
+/// `Directories::log_file`.
+struct Logger {
+    stderr: Box<dyn Log>,
+    file: Mutex<File>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.stderr.log(record);
+        let mut file = self.file.lock().unwrap();
+        let _ = writeln!(file, "{:<5} {} > {}", record.level(), record.target(), record.args());
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
+
+/// Filters with `RUST_LOG` like `pretty_env_logger::init`. Only logs to
+/// stderr if the log file cannot be created.
+fn init_logging() {
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let stderr = builder.build();
+    log::set_max_level(stderr.filter());
+
+    let file = Directories::resolve().and_then(|directories| {
+        let path = directories.log_file();
+        fs::create_dir_all(directories.logs())?;
+        Ok((File::create(&path)?, path))
+    });
+    match file {
+        Ok((file, path)) => {
+            let logger = Logger { stderr: Box::new(stderr), file: Mutex::new(file) };
+            log::set_boxed_logger(Box::new(logger)).unwrap();
+            log::info!("Logging to `{}`.", path.display());
+        }
+        Err(e) => {
+            log::set_boxed_logger(Box::new(stderr)).unwrap();
+            log::warn!("Failed to create the log file: {}", e);
+        }
+    }
+}
 
 fn main() -> Result<()> {
-    pretty_env_logger::init();
+    init_logging();
+    let self_test = std::env::args().any(|a| a == "--self-test");
+    // `--gpu <index, name or UUID>` picks the device, see `GpuSelector`.
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(gpu) = args.iter().position(|a| a == "--gpu").and_then(|i| args.get(i + 1)) {
+        std::env::set_var(GPU_OVERRIDE_VAR, gpu);
+    }
+
+    // The self-test needs no window, so it also runs without a display.
+    if self_test {
+        let report = unsafe {
+            let mut headless = Headless::create()?;
+            let report = headless.self_test();
+            headless.destroy();
+            report
+        };
+        print!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
 
     // Window
     let event_loop = EventLoop::new();
@@ -28,48 +110,271 @@ fn main() -> Result<()> {
         .build(&event_loop)
         .unwrap();
 
-    // App
-    let mut app = unsafe { App::create(&window).unwrap() };
-    let mut destroying = false;
-    let mut minimized = false;
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+    // Render Thread
+    let window = Arc::new(window);
+    let mut render_thread = RenderThread::spawn(window.clone(), WorldConfig::default(), |app| {
+        app.debug_draw.axis_gizmo(Matrix4::identity(), 1.0);
+        app.sprite_batch.sprite(
+            SpriteAtlas::DEFAULT,
+            vec2(16.0, 16.0),
+            vec2(128.0, 128.0),
+            vec2(0.0, 0.0),
+            vec2(1.0, 1.0),
+            vec4(1.0, 1.0, 1.0, 1.0),
+        );
+    });
+    // Opened with N, and closed on their own.
+    let mut windows: Vec<Arc<Window>> = vec![];
+    event_loop.run(move |event, target, control_flow| {
+        *control_flow = ControlFlow::Wait;
         match event {
-            Event::MainEventsCleared if !destroying && !minimized => {
-                unsafe { app.render(&window) }.unwrap()
-            }
+            Event::WindowEvent { window_id, event } if window_id != window.id() => match event {
+                WindowEvent::CloseRequested => {
+                    windows.retain(|w| w.id() != window_id);
+                    render_thread.send(RenderMessage::Run(Box::new(move |app| unsafe {
+                        app.close_window(window_id)
+                    })));
+                }
+                WindowEvent::Resized(_) => render_thread.send(RenderMessage::Run(
+                    Box::new(move |app| app.window_resized(window_id)),
+                )),
+                _ => {}
+            },
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
-                destroying = true;
                 *control_flow = ControlFlow::Exit;
-                unsafe {
-                    app.device.device_wait_idle().unwrap();
-                }
-                unsafe {
-                    app.destroy();
-                }
+                render_thread.join().unwrap();
             }
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 ..
-            } => {
-                if size.width == 0 || size.height == 0 {
-                    minimized = true;
-                } else {
-                    minimized = false;
-                    app.resized = true;
-                }
-            }
+            } => render_thread.send(RenderMessage::Resized {
+                width: size.width,
+                height: size.height,
+            }),
+            // Android takes the native window away while suspended.
+            Event::Suspended => render_thread.send(RenderMessage::Suspended),
+            Event::Resumed => render_thread.send(RenderMessage::Resumed),
+            Event::WindowEvent {
+                event: WindowEvent::ScaleFactorChanged { scale_factor, .. },
+                ..
+            } => render_thread.send(RenderMessage::ScaleFactorChanged { scale_factor }),
             Event::WindowEvent {
                 event: WindowEvent::KeyboardInput { input, .. },
                 ..
             } => {
                 if input.state == ElementState::Pressed {
                     match input.virtual_keycode {
-                        Some(VirtualKeyCode::Left) if app.models > 1 => app.models -= 1,
-                        Some(VirtualKeyCode::Right) if app.models < 4 => app.models += 1,
+                        Some(VirtualKeyCode::Left) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                if app.models > 1 {
+                                    app.models -= 1
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::Right) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                if app.models < 4 {
+                                    app.models += 1
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::G) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.show_grid = !app.show_grid),
+                        )),
+                        Some(VirtualKeyCode::S) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.static_scene = !app.static_scene),
+                        )),
+                        Some(VirtualKeyCode::W) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.wireframe = !app.wireframe),
+                        )),
+                        Some(VirtualKeyCode::V) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.debug_view = app.debug_view.next()),
+                        )),
+                        Some(VirtualKeyCode::R) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.resolve_mode = app.resolve_mode.next()),
+                        )),
+                        Some(VirtualKeyCode::B) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.depth_prepass = !app.depth_prepass),
+                        )),
+                        Some(VirtualKeyCode::J) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                app.order_independent_transparency = !app.order_independent_transparency
+                            }),
+                        )),
+                        Some(VirtualKeyCode::X) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                // A translucent red stain across the whole demo scene.
+                                if app.decals.is_empty() {
+                                    app.decals.push(Decal {
+                                        transform: Matrix4::from_scale(4.0),
+                                        color: vec4(1.0, 0.2, 0.2, 0.5),
+                                        ..Decal::default()
+                                    });
+                                } else {
+                                    app.decals.clear();
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::Q) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| unsafe {
+                                let path = app.color_grading().is_none().then(|| Path::new("resources/grade.cube"));
+                                if let Err(e) = app.set_color_grading(path) {
+                                    log::warn!("Failed to load color grading: {}", e);
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::Z) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.reverse_z = !app.reverse_z),
+                        )),
+                        Some(VirtualKeyCode::T) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                if let Some(entity) = app.entities.first_mut() {
+                                    entity.mobility = match entity.mobility {
+                                        Mobility::Static => Mobility::Dynamic,
+                                        Mobility::Dynamic => Mobility::Static,
+                                    };
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::D) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| unsafe {
+                                if let Err(e) = app.bake_static_batches() {
+                                    log::warn!("Failed to bake static batches: {}", e);
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::L) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                app.light_probes = match app.light_probes {
+                                    LightProbes::None => LightProbes::Grid(demo_light_probes()),
+                                    _ => LightProbes::None,
+                                };
+                                app.invalidate_scene();
+                            }),
+                        )),
+                        Some(VirtualKeyCode::U) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.sun = app.sun.xor(Some(Sun::default()))),
+                        )),
+                        Some(VirtualKeyCode::O) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                app.ambient_occlusion = match app.ambient_occlusion {
+                                    AmbientOcclusion::None => AmbientOcclusion::default(),
+                                    _ => AmbientOcclusion::None,
+                                };
+                            }),
+                        )),
+                        Some(VirtualKeyCode::Y) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.stereo = app.stereo.xor(Some(Stereo::default()))),
+                        )),
+                        Some(VirtualKeyCode::H) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                // The active camera on the left, looking down from above on the right.
+                                let top = match app.cameras().len() {
+                                    1 => app.add_camera(Camera::look_at(
+                                        vec3(0.0, 0.0, 8.0),
+                                        vec3(0.0, 0.0, 0.0),
+                                        vec3(0.0, 1.0, 0.0),
+                                    )),
+                                    _ => 1,
+                                };
+                                let viewports = match app.viewports() {
+                                    [] => vec![
+                                        Viewport::new(0.0, 0.0, 0.5, 1.0),
+                                        Viewport::new(0.5, 0.0, 0.5, 1.0).with_camera(top),
+                                    ],
+                                    _ => vec![],
+                                };
+                                app.set_viewports(&viewports);
+                            }),
+                        )),
+                        Some(VirtualKeyCode::K) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                let next = (app.active_camera() + 1) % app.cameras().len();
+                                app.set_active_camera(next).unwrap();
+                            }),
+                        )),
+                        Some(VirtualKeyCode::I) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                let index = app.active_camera();
+                                if let Some(camera) = app.camera_mut(index) {
+                                    camera.infinite_far = !camera.infinite_far;
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::N) => {
+                            let secondary = Arc::new(
+                                WindowBuilder::new()
+                                    .with_title("Secondary View")
+                                    .with_inner_size(LogicalSize::new(512, 384))
+                                    .build(target)
+                                    .unwrap(),
+                            );
+                            windows.push(secondary.clone());
+                            render_thread.send(RenderMessage::Run(Box::new(move |app| unsafe {
+                                if let Err(e) = app.open_window(secondary) {
+                                    log::warn!("Failed to open window: {}", e);
+                                }
+                            })));
+                        }
+                        Some(VirtualKeyCode::P) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.set_ui_focus(!app.ui_focus())),
+                        )),
+                        Some(VirtualKeyCode::F) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.toggle_demo_viewmodel()),
+                        )),
+                        Some(VirtualKeyCode::E) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| app.auto_exposure.enabled = !app.auto_exposure.enabled),
+                        )),
+                        Some(VirtualKeyCode::A) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                app.auto_exposure.histogram = !app.auto_exposure.histogram;
+                                log::info!("Histogram metering {}.", app.auto_exposure.histogram);
+                            }),
+                        )),
+                        Some(VirtualKeyCode::C) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                app.level_of_detail.enabled = !app.level_of_detail.enabled;
+                                log::info!("Levels of detail {}.", app.level_of_detail.enabled);
+                            }),
+                        )),
+                        Some(VirtualKeyCode::F5) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                let path = app.directories.scene_file();
+                                match app.scene().save(&path) {
+                                    Ok(()) => log::info!("Saved the scene to {}.", path.display()),
+                                    Err(e) => log::warn!("Failed to save the scene: {}", e),
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::F9) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| unsafe {
+                                // Reloads it as it is edited, too.
+                                let path = app.directories.scene_file();
+                                if let Err(e) = app.watch_scene(Some(&path)) {
+                                    log::warn!("Failed to load the scene: {}", e);
+                                }
+                            }),
+                        )),
+                        Some(VirtualKeyCode::F11) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                app.window_mode = app.window_mode.next();
+                                log::info!("Window mode {:?}.", app.window_mode);
+                            }),
+                        )),
+                        Some(VirtualKeyCode::M) => render_thread.send(RenderMessage::Run(
+                            Box::new(|app| {
+                                app.auto_exposure.metering = app.auto_exposure.metering.next();
+                                log::info!(
+                                    "Metering {:?}, scene luminance {:?}, exposure {}.",
+                                    app.auto_exposure.metering,
+                                    app.scene_luminance(),
+                                    app.exposure(),
+                                );
+                            }),
+                        )),
                         _ => {}
                     }
                 }
@@ -77,4 +382,19 @@ fn main() -> Result<()> {
             _ => {}
         }
     });
-}
\ No newline at end of file
+}
+
+/// A blue sky over a brown floor with a red light off to the +Y side.
+fn demo_light_probes() -> LightProbeGrid {
+    let light = vec3(0.0, 4.0, 1.0);
+    LightProbeGrid::bake(vec3(-2.0, -3.0, -2.0), vec3(2.0, 3.0, 2.0), [2, 4, 3], |position, direction| {
+        let sky = if direction.z > 0.0 {
+            vec3(0.6, 0.75, 1.0)
+        } else {
+            vec3(0.35, 0.25, 0.2)
+        };
+        let to_light = light - position;
+        let facing = direction.dot(to_light.normalize()).max(0.0).powi(8);
+        sky + vec3(8.0, 1.0, 0.5) * facing / to_light.magnitude2()
+    })
+}